@@ -7,7 +7,7 @@
 use std::time::Instant;
 use std::{borrow::Borrow, sync::RwLock};
 
-use afire::internal::encoding::url;
+use afire::encoding::url;
 use afire::{Content, HeaderType, Method, Query, Response, Server, Status};
 
 const DATA_LIMIT: usize = 10_000;
@@ -13,11 +13,11 @@ use std::{
 };
 
 use afire::{
+    encoding::url,
     extension::date::imp_date,
-    internal::encoding::url,
     trace,
     trace::{set_log_level, Level},
-    Content, HeaderType, Method, Query, Response, Server, Status,
+    Content, Method, Query, Response, Server, Status,
 };
 
 struct App {
@@ -66,10 +66,7 @@ fn main() {
         trace!(Level::Trace, "Added new quote #{id}");
 
         app.save();
-        Response::new()
-            .status(Status::SeeOther)
-            .header(HeaderType::Location, format!("/quote/{id}"))
-            .text("Redirecting to quote page.")
+        Response::see_other(format!("/quote/{id}"))
     });
 
     server.stateful_route(Method::GET, "/quote/{id}", |app, req| {
@@ -0,0 +1,321 @@
+//! Apps group routes and middleware so they can be defined separately and mounted on a [`Server`] under a prefix.
+//!
+//! This lets large projects split features into modules (or crates) that each export an [`App`], without passing around a mutable reference to the server.
+//! Middleware added to an app only runs for requests under the prefix it is mounted at.
+//!
+//! ## Example
+//! ```rust
+//! # use afire::{App, Method, Response, Server};
+//! fn blog() -> App<()> {
+//!     let mut app = App::new();
+//!     app.route(Method::GET, "/", |_| Response::new().text("All posts"));
+//!     app.route(Method::GET, "/post/{id}", |req| {
+//!         Response::new().text(format!("Post #{}", req.param("id").unwrap()))
+//!     });
+//!     app
+//! }
+//!
+//! let mut server = Server::<()>::new("localhost", 8080);
+//! // Handles `/blog` and `/blog/post/{id}`
+//! server.mount("/blog", blog());
+//! # assert_eq!(server.routes()[1].path, "/blog/post/{id}");
+//! ```
+
+use std::{rc::Rc, sync::Arc};
+
+use crate::{
+    error::Result,
+    internal::path::{normalize_path, Path},
+    middleware::{MiddleResult, Middleware},
+    Context, Method, Request, Response, Route, Server,
+};
+
+#[cfg(feature = "tracing")]
+use crate::trace::emoji;
+
+/// A group of routes and middleware that can be mounted on a [`Server`] with [`Server::mount`].
+/// See the [module level docs](self) for an example.
+pub struct App<State: 'static + Send + Sync> {
+    /// The routes of the app, with paths relative to where the app is mounted.
+    routes: Vec<Route<State>>,
+    /// The middleware of the app, with the prefix (relative to where the app is mounted) of the nested app it came from.
+    middleware: Vec<(String, Box<dyn Middleware + Send + Sync>)>,
+}
+
+/// Runs a middleware only for requests under a prefix.
+struct Scoped {
+    /// The normalized prefix, without leading or trailing slashes.
+    prefix: String,
+    middleware: Box<dyn Middleware + Send + Sync>,
+}
+
+impl<State: 'static + Send + Sync> App<State> {
+    /// Creates a new app with no routes or middleware.
+    pub fn new() -> Self {
+        Self {
+            routes: Vec::new(),
+            middleware: Vec::new(),
+        }
+    }
+
+    /// Adds a route to the app, see [`Server::route`].
+    /// The path is relative to where the app is mounted.
+    pub fn route(
+        &mut self,
+        method: Method,
+        path: impl AsRef<str>,
+        handler: impl Fn(&Request) -> Response + Send + Sync + 'static,
+    ) -> &mut Self {
+        let path = path.as_ref().to_owned();
+        self.routes
+            .push(Route::new(method, path, Box::new(handler)));
+        self
+    }
+
+    /// Adds a stateful route to the app, see [`Server::stateful_route`].
+    /// The path is relative to where the app is mounted.
+    pub fn stateful_route(
+        &mut self,
+        method: Method,
+        path: impl AsRef<str>,
+        handler: impl Fn(Arc<State>, &Request) -> Response + Send + Sync + 'static,
+    ) -> &mut Self {
+        let path = path.as_ref().to_owned();
+        self.routes
+            .push(Route::new_stateful(method, path, Box::new(handler)));
+        self
+    }
+
+    /// Adds a context route to the app, see [`Server::context_route`].
+    /// The path is relative to where the app is mounted.
+    pub fn context_route(
+        &mut self,
+        method: Method,
+        path: impl AsRef<str>,
+        handler: impl Fn(&Context<State>) -> Response + Send + Sync + 'static,
+    ) -> &mut Self {
+        let path = path.as_ref().to_owned();
+        self.routes
+            .push(Route::new_context(method, path, Box::new(handler)));
+        self
+    }
+
+    /// Sets the priority of the route that was added last, see [`Server::route_priority`].
+    pub fn route_priority(&mut self, priority: i32) -> &mut Self {
+        if let Some(route) = self.routes.last_mut() {
+            route.priority = priority;
+        }
+        self
+    }
+
+    /// Sets the name of the route that was added last, see [`Server::route_name`].
+    pub fn route_name(&mut self, name: impl AsRef<str>) -> &mut Self {
+        if let Some(route) = self.routes.last_mut() {
+            route.name = Some(name.as_ref().to_owned());
+        }
+        self
+    }
+
+    /// Attaches metadata to the route that was added last, see [`Server::route_meta`].
+    pub fn route_meta(&mut self, key: impl AsRef<str>, value: impl AsRef<str>) -> &mut Self {
+        if let Some(route) = self.routes.last_mut() {
+            Arc::make_mut(&mut route.meta).add(key, value);
+        }
+        self
+    }
+
+    /// Adds a middleware that only runs for requests under the prefix the app is mounted at.
+    /// Unlike [`Middleware::attach`], this can't give the middleware access to the server, so middleware that overrides `attach` should be attached to the server instead.
+    /// ## Example
+    /// ```rust
+    /// # use afire::{App, Request, middleware::{Middleware, MiddleResult}};
+    /// struct Log;
+    ///
+    /// impl Middleware for Log {
+    ///     fn pre(&self, req: &mut Request) -> MiddleResult {
+    ///         println!("Admin request: {}", req.path);
+    ///         MiddleResult::Continue
+    ///     }
+    /// }
+    ///
+    /// let mut admin = App::<()>::new();
+    /// admin.middleware(Log);
+    /// ```
+    pub fn middleware(&mut self, middleware: impl Middleware + Send + Sync + 'static) -> &mut Self {
+        self.middleware.push((String::new(), Box::new(middleware)));
+        self
+    }
+
+    /// Mounts another app under a prefix of this one, so apps can be nested.
+    pub fn mount(&mut self, prefix: impl AsRef<str>, app: App<State>) -> &mut Self {
+        let prefix = normalize_path(prefix.as_ref().to_owned());
+        for mut route in app.routes {
+            route.path = Path::new(join(&prefix, &route.path.raw));
+            self.routes.push(route);
+        }
+
+        for (scope, middleware) in app.middleware {
+            self.middleware.push((join(&prefix, &scope), middleware));
+        }
+        self
+    }
+}
+
+impl<State: 'static + Send + Sync> Default for App<State> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<State: Send + Sync> Server<State> {
+    /// Mounts an [`App`] under a prefix, adding its routes and middleware to the server.
+    /// The app's routes have the prefix added to their paths, and its middleware only runs for requests under the prefix.
+    /// Mounting an app at `/` adds its routes as they are, with its middleware running for every request.
+    /// See the [`crate::app`] module for an example.
+    pub fn mount(&mut self, prefix: impl AsRef<str>, app: App<State>) -> &mut Self {
+        let prefix = normalize_path(prefix.as_ref().to_owned());
+        trace!(
+            "{}Mounting App at /{} ({} routes, {} middleware)",
+            emoji("📦"),
+            prefix,
+            app.routes.len(),
+            app.middleware.len()
+        );
+
+        self.router.take();
+        for mut route in app.routes {
+            route.path = Path::new(join(&prefix, &route.path.raw));
+            self.routes.push(route);
+        }
+
+        for (scope, middleware) in app.middleware {
+            self.middleware.push(Box::new(Scoped {
+                prefix: join(&prefix, &scope),
+                middleware,
+            }));
+        }
+        self
+    }
+}
+
+impl Scoped {
+    /// Checks if a request path is the prefix or under it.
+    fn matches(&self, path: &str) -> bool {
+        let path = path.trim_start_matches('/');
+        self.prefix.is_empty()
+            || path
+                .strip_prefix(&self.prefix)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    }
+}
+
+impl Middleware for Scoped {
+    fn pre_raw(&self, req: &mut Result<Request>) -> MiddleResult {
+        match req {
+            Ok(i) if self.matches(&i.path) => self.middleware.pre_raw(req),
+            _ => MiddleResult::Continue,
+        }
+    }
+
+    fn post_raw(&self, req: Result<Rc<Request>>, res: &mut Result<Response>) -> MiddleResult {
+        match &req {
+            Ok(i) if self.matches(&i.path) => self.middleware.post_raw(req, res),
+            _ => MiddleResult::Continue,
+        }
+    }
+
+    fn end_raw(&self, req: &Result<Request>, res: &Result<Response>) {
+        if matches!(req, Ok(i) if self.matches(&i.path)) {
+            self.middleware.end_raw(req, res);
+        }
+    }
+}
+
+/// Joins a normalized prefix and path with a slash.
+fn join(prefix: &str, path: &str) -> String {
+    match (prefix.is_empty(), path.is_empty()) {
+        (true, _) => path.to_owned(),
+        (_, true) => prefix.to_owned(),
+        _ => format!("{prefix}/{path}"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::Cursor,
+        sync::{Arc, Mutex},
+    };
+
+    use super::App;
+    use crate::{
+        internal::testing::{MemoryListener, MemoryStream},
+        middleware::MiddleResult,
+        Method, Middleware, Request, Response, Server,
+    };
+
+    #[test]
+    fn test_mount() {
+        struct Tag(&'static str);
+
+        impl Middleware for Tag {
+            fn post(&self, _req: &Request, res: &mut Response) -> MiddleResult {
+                res.headers.add("X-Tag", self.0);
+                MiddleResult::Continue
+            }
+        }
+
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let input = [
+            "/blog",
+            "/blog/post/1",
+            "/blog/comments/2",
+            "/blogs",
+            "/about",
+        ]
+        .map(|path| format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n"))
+        .concat();
+        let listener = MemoryListener(Mutex::new(vec![MemoryStream {
+            input: Cursor::new(input.into_bytes()),
+            output: output.clone(),
+        }]));
+
+        let mut comments = App::new();
+        comments.middleware(Tag("comments"));
+        comments.route(Method::GET, "/{id}", |req| {
+            Response::new().text(format!("comment {}", req.param("id").unwrap()))
+        });
+
+        let mut blog = App::new();
+        blog.middleware(Tag("blog"));
+        blog.route(Method::GET, "/", |_| Response::new().text("posts"));
+        blog.route(Method::GET, "/post/{id}", |req| {
+            Response::new().text(format!("post {}", req.param("id").unwrap()))
+        });
+        blog.mount("/comments/", comments);
+
+        let mut server = Server::<()>::new("localhost", 0);
+        server.mount("/blog", blog);
+        server.route(Method::GET, "/blogs", |_| Response::new().text("blogs"));
+        server.route(Method::GET, "/about", |_| Response::new().text("about"));
+        assert!(server.start_with_listener(listener).is_err());
+
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        let responses = output.split("HTTP/1.1 ").skip(1).collect::<Vec<_>>();
+        let expected = [
+            ("posts", &["blog"][..]),
+            ("post 1", &["blog"]),
+            ("comment 2", &["blog", "comments"]),
+            ("blogs", &[]),
+            ("about", &[]),
+        ];
+        assert_eq!(responses.len(), expected.len());
+        for (res, (body, tags)) in responses.iter().zip(expected) {
+            assert!(res.ends_with(&format!("\r\n\r\n{body}")), "{:?}", res);
+            for tag in ["blog", "comments"] {
+                let has = res.contains(&format!("X-Tag: {tag}\r\n"));
+                assert_eq!(has, tags.contains(&tag), "{:?}", res);
+            }
+        }
+    }
+}
@@ -0,0 +1,188 @@
+//! Streaming transforms of response bodies.
+//!
+//! Body filters are added with [`crate::Server::body_filter`] and wrap the body of every response in the order they were added, once post middleware and response hooks have run.
+//! Each filter is given a reader of the body with the earlier filters already applied, so things like templating post-processing, minification, compression or re-chunking can be combined without each of them reading the whole body into memory or handling every kind of body a [`crate::Response`] can have (static, streamed or a file).
+//!
+//! ## Example
+//! ```rust
+//! # use std::io::{self, Read};
+//! # use afire::{body_filter::{Body, BodyFilter}, header::Headers, HeaderType, Request, Response, Server};
+//! /// Uppercases plain text responses.
+//! struct Uppercase;
+//!
+//! struct UppercaseReader(Body);
+//!
+//! impl BodyFilter for Uppercase {
+//!     fn applies(&self, _req: &Request, res: &Response) -> bool {
+//!         res.headers
+//!             .get(HeaderType::ContentType)
+//!             .is_some_and(|i| i.starts_with("text/plain"))
+//!     }
+//!
+//!     fn filter(&self, _req: &Request, _headers: &mut Headers, body: Body) -> Body {
+//!         Box::new(UppercaseReader(body))
+//!     }
+//! }
+//!
+//! impl Read for UppercaseReader {
+//!     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+//!         let read = self.0.read(buf)?;
+//!         buf[..read].make_ascii_uppercase();
+//!         Ok(read)
+//!     }
+//! }
+//!
+//! let mut server = Server::<()>::new("localhost", 8080);
+//! server.body_filter(Uppercase);
+//! ```
+
+use std::{
+    cell::RefCell,
+    io::{self, Cursor, Read},
+    mem,
+};
+
+use crate::{
+    handle::Writeable,
+    header::Headers,
+    response::{ResponseBody, ResponseFlag},
+    HeaderType, Request, Response, Status,
+};
+
+/// A response body being passed through the body filters.
+pub type Body = Box<dyn Read + Send>;
+
+/// A streaming transform of response bodies, added to a server with [`crate::Server::body_filter`].
+/// See the [module level documentation](self) for more information.
+///
+/// Any `Fn(&Request, &mut Headers, Body) -> Body` can be used as a filter that applies to every response.
+pub trait BodyFilter: Send + Sync {
+    /// Checks if the filter should be applied to a response, by default every response with a body is filtered.
+    /// Once an earlier filter has been applied, the body of the response is empty, so this should only look at its status and headers.
+    fn applies(&self, _req: &Request, _res: &Response) -> bool {
+        true
+    }
+
+    /// Wraps the body of a response.
+    /// The response's headers can be changed here too, like to add a `Content-Encoding`.
+    ///
+    /// Filters usually change the length of the body, so the `Content-Length` header is removed before the first filter runs and the body is sent with chunked transfer encoding.
+    /// If the filter knows the new length, it can add the header back.
+    fn filter(&self, req: &Request, headers: &mut Headers, body: Body) -> Body;
+}
+
+impl<F> BodyFilter for F
+where
+    F: Fn(&Request, &mut Headers, Body) -> Body + Send + Sync,
+{
+    fn filter(&self, req: &Request, headers: &mut Headers, body: Body) -> Body {
+        self(req, headers, body)
+    }
+}
+
+/// Reads a streaming [`ResponseBody`].
+struct StreamBody(Writeable);
+
+impl Read for StreamBody {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.get_mut().read(buf)
+    }
+}
+
+/// Passes the body of a response through the filters that apply to it.
+/// Responses that can't have a body are left alone.
+pub(crate) fn apply(filters: &[Box<dyn BodyFilter>], req: &Request, res: &mut Response) {
+    if res.flag == ResponseFlag::End
+        || matches!(res.status, Status::NoContent | Status::NotModified)
+    {
+        return;
+    }
+
+    let mut body = None;
+    for filter in filters {
+        if !filter.applies(req, res) {
+            continue;
+        }
+
+        let inner = match body.take() {
+            Some(body) => body,
+            None => {
+                res.headers.remove(HeaderType::ContentLength);
+                into_body(mem::replace(&mut res.data, ResponseBody::empty()))
+            }
+        };
+        body = Some(filter.filter(req, &mut res.headers, inner));
+    }
+
+    if let Some(body) = body {
+        res.data = ResponseBody::Stream(Box::new(RefCell::new(body)));
+    }
+}
+
+/// Gets a reader of a response body.
+fn into_body(data: ResponseBody) -> Body {
+    match data {
+        ResponseBody::Static(data) => Box::new(Cursor::new(data)),
+        ResponseBody::Stream(data) => Box::new(StreamBody(data)),
+        ResponseBody::File(file) => Box::new(file),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::{Cursor, Read},
+        sync::{Arc, Mutex},
+    };
+
+    use super::{Body, BodyFilter};
+    use crate::{
+        header::Headers,
+        internal::testing::{MemoryListener, MemoryStream},
+        Method, Request, Response, Server,
+    };
+
+    #[test]
+    fn test_body_filter() {
+        struct Uppercase;
+
+        impl BodyFilter for Uppercase {
+            fn applies(&self, req: &Request, _res: &Response) -> bool {
+                req.path != "/plain"
+            }
+
+            fn filter(&self, _req: &Request, headers: &mut Headers, mut body: Body) -> Body {
+                headers.add("X-Filtered", "uppercase");
+                let mut text = String::new();
+                let _ = body.read_to_string(&mut text);
+                Box::new(Cursor::new(text.to_uppercase()))
+            }
+        }
+
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let input = ["/", "/plain"]
+            .map(|path| format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n"))
+            .concat();
+        let listener = MemoryListener(Mutex::new(vec![MemoryStream {
+            input: Cursor::new(input.into_bytes()),
+            output: output.clone(),
+        }]));
+
+        let mut server = Server::<()>::new("localhost", 0);
+        server.body_filter(Uppercase);
+        server.body_filter(|_req: &Request, _headers: &mut Headers, body: Body| {
+            Box::new(body.chain(Cursor::new(b"!"))) as Body
+        });
+        server.route(Method::GET, "/", |_| Response::new().text("hello"));
+        server.route(Method::GET, "/plain", |_| Response::new().text("hello"));
+        assert!(server.start_with_listener(listener).is_err());
+
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        let (first, second) = output.split_at(output.rfind("HTTP/1.1 ").unwrap());
+        assert!(!first.contains("Content-Length"));
+        assert!(first.contains("\r\nX-Filtered: uppercase\r\n"));
+        assert!(first.ends_with("\r\n\r\n5\r\nHELLO\r\n1\r\n!\r\n0\r\n\r\n"));
+        assert!(!second.contains("X-Filtered"));
+        assert!(second.ends_with("\r\n\r\n5\r\nhello\r\n1\r\n!\r\n0\r\n\r\n"));
+    }
+}
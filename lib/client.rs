@@ -0,0 +1,317 @@
+//! A tiny blocking HTTP/1.1 client.
+//! Only available with the `client` feature.
+//!
+//! It is built on the same primitives as the server ([`Header`], [`Method`], [`Status`], etc.) and is meant for simple things like integration tests, health checks or proxying.
+//! Only plain `http://` URLs are supported, and a new connection is opened for every request.
+//!
+//! ## Example
+//! ```rust,no_run
+//! # use afire::client;
+//! let res = client::get("http://localhost:8080/api/status")
+//!     .header("Accept", "application/json")
+//!     .send()
+//!     .unwrap();
+//!
+//! println!("{} {}", res.status.code(), res.body_str());
+//! ```
+
+use std::{
+    borrow::Cow,
+    io::{self, BufRead, BufReader, Write},
+    net::TcpStream,
+    time::Duration,
+};
+
+use crate::{
+    consts::BUFF_SIZE,
+    error::{Result, StreamError},
+    header::{headers_to_string, Headers},
+    Header, HeaderType, Method, Status,
+};
+
+/// A request that can be sent with [`ClientRequest::send`].
+/// Create one with [`request`], [`get`] or [`post`].
+#[derive(Debug, Clone)]
+pub struct ClientRequest {
+    /// The request method.
+    pub method: Method,
+    /// The URL to send the request to.
+    pub url: String,
+    /// Headers to send along with the request.
+    /// `Host`, `Content-Length` and `Connection` are added automatically.
+    pub headers: Headers,
+    /// The request body.
+    pub body: Vec<u8>,
+    /// Read and write timeout for the connection.
+    pub timeout: Option<Duration>,
+}
+
+/// A response received from a server.
+#[derive(Debug, Clone)]
+pub struct ClientResponse {
+    /// The response status code.
+    pub status: Status,
+    /// The reason phrase sent by the server.
+    pub reason: String,
+    /// The response headers.
+    pub headers: Headers,
+    /// The response body, with any chunked transfer encoding removed.
+    pub body: Vec<u8>,
+}
+
+/// Creates a new request with the given method.
+pub fn request(method: Method, url: impl AsRef<str>) -> ClientRequest {
+    ClientRequest {
+        method,
+        url: url.as_ref().to_owned(),
+        headers: Headers::default(),
+        body: Vec::new(),
+        timeout: None,
+    }
+}
+
+/// Creates a new GET request.
+pub fn get(url: impl AsRef<str>) -> ClientRequest {
+    request(Method::GET, url)
+}
+
+/// Creates a new POST request.
+pub fn post(url: impl AsRef<str>) -> ClientRequest {
+    request(Method::POST, url)
+}
+
+impl ClientRequest {
+    /// Adds a header to the request.
+    pub fn header(mut self, key: impl Into<HeaderType>, value: impl AsRef<str>) -> Self {
+        self.headers.add(key, value);
+        self
+    }
+
+    /// Sets the request body.
+    pub fn body(self, body: impl Into<Vec<u8>>) -> Self {
+        Self {
+            body: body.into(),
+            ..self
+        }
+    }
+
+    /// Sets the read and write timeout for the connection.
+    pub fn timeout(self, timeout: Duration) -> Self {
+        Self {
+            timeout: Some(timeout),
+            ..self
+        }
+    }
+
+    /// Sends the request and waits for the whole response.
+    pub fn send(self) -> Result<ClientResponse> {
+        let (host, path) = parse_url(&self.url)?;
+        let mut stream = TcpStream::connect(host.as_ref())?;
+        stream.set_read_timeout(self.timeout)?;
+        stream.set_write_timeout(self.timeout)?;
+
+        let mut headers = self.headers;
+        if !headers.has(HeaderType::Host) {
+            headers.add(HeaderType::Host, &host);
+        }
+        if !self.body.is_empty() || !matches!(self.method, Method::GET | Method::HEAD) {
+            headers.add(HeaderType::ContentLength, self.body.len().to_string());
+        }
+        headers.add(HeaderType::Connection, "close");
+
+        let head = format!(
+            "{} {} HTTP/1.1\r\n{}\r\n\r\n",
+            self.method,
+            path,
+            headers_to_string(&headers)
+        );
+        stream.write_all(head.as_bytes())?;
+        stream.write_all(&self.body)?;
+
+        read_response(BufReader::new(stream), self.method == Method::HEAD)
+    }
+}
+
+impl ClientResponse {
+    /// Gets the body of the response as a string.
+    /// Invalid UTF-8 characters are replaced with the unicode replacement character (�).
+    pub fn body_str(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.body)
+    }
+}
+
+/// Splits a URL in the form `http://host[:port][/path]` into the host (with port) and path.
+/// If no port is given, the default port (80) is added.
+fn parse_url(url: &str) -> Result<(Cow<'_, str>, &str)> {
+    let rest = url.strip_prefix("http://").ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "Only http URLs are supported")
+    })?;
+
+    let (host, path) = match rest.find('/') {
+        Some(i) => rest.split_at(i),
+        None => (rest, "/"),
+    };
+
+    if host.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "URL has no host").into());
+    }
+
+    // IPv6 addresses are wrapped in brackets, so a colon after the closing bracket is the port
+    let has_port = host.contains(':') && !host.ends_with(']');
+    let host = match has_port {
+        true => Cow::Borrowed(host),
+        false => Cow::Owned(format!("{host}:80")),
+    };
+
+    Ok((host, path))
+}
+
+/// Reads a response from a stream.
+/// If `head` is true, no body is read.
+fn read_response(mut reader: impl BufRead, head: bool) -> Result<ClientResponse> {
+    let status_line = read_line(&mut reader)?;
+    let mut parts = status_line.splitn(3, ' ');
+    let _version = parts.next();
+    let status = parts
+        .next()
+        .and_then(|i| i.parse::<u16>().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Invalid status line"))?;
+    let reason = parts.next().unwrap_or_default().to_owned();
+
+    let mut headers = Headers::default();
+    loop {
+        let line = read_line(&mut reader)?;
+        if line.is_empty() {
+            break;
+        }
+
+        headers.push(Header::from_string(line)?);
+    }
+
+    let chunked = headers
+        .get(HeaderType::TransferEncoding)
+        .map(|i| i.eq_ignore_ascii_case("chunked"))
+        .unwrap_or(false);
+    let content_length = headers
+        .get(HeaderType::ContentLength)
+        .and_then(|i| i.parse::<usize>().ok());
+
+    let mut body = Vec::new();
+    let has_body = !(head || status == 204 || status == 304);
+    if has_body && chunked {
+        body = read_chunked(&mut reader)?;
+    } else if has_body {
+        match content_length {
+            Some(len) => {
+                body.resize(len, 0);
+                reader
+                    .read_exact(&mut body)
+                    .map_err(|_| StreamError::UnexpectedEof)?;
+            }
+            None => {
+                reader.read_to_end(&mut body)?;
+            }
+        }
+    }
+
+    Ok(ClientResponse {
+        status: status.into(),
+        reason,
+        headers,
+        body,
+    })
+}
+
+/// Reads a body sent with chunked transfer encoding.
+fn read_chunked(reader: &mut impl BufRead) -> Result<Vec<u8>> {
+    let mut body = Vec::new();
+    loop {
+        let line = read_line(reader)?;
+        let size = line.split(';').next().unwrap_or_default().trim();
+        let size = usize::from_str_radix(size, 16)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Invalid chunk size"))?;
+
+        if size == 0 {
+            // Skip any trailers
+            while !read_line(reader)?.is_empty() {}
+            return Ok(body);
+        }
+
+        let start = body.len();
+        body.resize(start + size, 0);
+        reader
+            .read_exact(&mut body[start..])
+            .map_err(|_| StreamError::UnexpectedEof)?;
+        read_line(reader)?;
+    }
+}
+
+/// Reads a line, without the trailing `\r\n`.
+fn read_line(reader: &mut impl BufRead) -> Result<String> {
+    let mut line = Vec::with_capacity(BUFF_SIZE);
+    if reader.read_until(b'\n', &mut line)? == 0 {
+        return Err(StreamError::UnexpectedEof.into());
+    }
+
+    while matches!(line.last(), Some(b'\n' | b'\r')) {
+        line.pop();
+    }
+
+    Ok(String::from_utf8_lossy(&line).into_owned())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{io::Cursor, sync::mpsc, thread};
+
+    use super::{get, parse_url, post, read_response};
+    use crate::{Method, Response, Server, Status};
+
+    #[test]
+    fn test_parse_url() {
+        assert_eq!(
+            parse_url("http://localhost:8080/a/b?c").unwrap(),
+            ("localhost:8080".into(), "/a/b?c")
+        );
+        assert_eq!(
+            parse_url("http://example.com").unwrap(),
+            ("example.com:80".into(), "/")
+        );
+        assert_eq!(parse_url("http://[::1]").unwrap().0, "[::1]:80");
+        assert!(parse_url("https://example.com").is_err());
+        assert!(parse_url("http:///path").is_err());
+    }
+
+    #[test]
+    fn test_read_chunked_response() {
+        let raw = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nHello\r\n7\r\n, World\r\n0\r\n\r\n";
+        let res = read_response(Cursor::new(raw), false).unwrap();
+        assert_eq!(res.status, Status::Ok);
+        assert_eq!(res.reason, "OK");
+        assert_eq!(res.body_str(), "Hello, World");
+    }
+
+    #[test]
+    fn test_client_server() {
+        let (tx, rx) = mpsc::sync_channel(1);
+        let mut server = Server::<()>::new("localhost", 0);
+        server.on_start(move |addr| tx.send(addr).unwrap());
+        server.route(Method::GET, "/", |_| Response::new().text("Hello"));
+        server.route(Method::POST, "/echo", |req| {
+            Response::new().bytes(&req.body).header("X-Test", "1")
+        });
+        thread::spawn(move || server.start_threaded(1).unwrap());
+        let addr = rx.recv().unwrap();
+
+        let res = get(format!("http://{addr}/")).send().unwrap();
+        assert_eq!(res.status, Status::Ok);
+        assert_eq!(res.body_str(), "Hello");
+
+        let res = post(format!("http://{addr}/echo"))
+            .body("Ping")
+            .send()
+            .unwrap();
+        assert_eq!(res.headers.get("X-Test"), Some("1"));
+        assert_eq!(res.body_str(), "Ping");
+    }
+}
@@ -0,0 +1,176 @@
+//! Sources of the current time, so time based behavior can be tested deterministically.
+//!
+//! The server reads the time from its [`Clock`], set with [`crate::Server::clock`].
+//! This is used for the `Date` header, keep-alive timeouts and by extensions like the Date, Logger and RateLimiter middleware, which get it from [`crate::Request::clock`].
+//! By default the [`SystemClock`] is used, but tests (or embedded systems without a reliable system clock) can supply their own, like a [`ManualClock`].
+//!
+//! ## Example
+//! ```rust
+//! # use std::{sync::Arc, time::{Duration, UNIX_EPOCH}};
+//! # use afire::{clock::{Clock, ManualClock}, Server};
+//! let clock = Arc::new(ManualClock::new(UNIX_EPOCH + Duration::from_secs(784111777)));
+//! let server = Server::<()>::new("localhost", 8080).clock(clock.clone());
+//!
+//! // Responses now have a Date header of `Sun, 06 Nov 1994 08:49:37 GMT`, until the clock is moved forward
+//! clock.advance(Duration::from_secs(60));
+//! assert_eq!(clock.epoch().as_secs(), 784111837);
+//! ```
+
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::internal::common::ForceLock;
+
+/// A source of the current time.
+pub trait Clock: Send + Sync {
+    /// Gets the current wall clock time, used for things like dates and rate limit windows.
+    fn now(&self) -> SystemTime;
+
+    /// Gets the current monotonic time, used for timeouts and measuring durations.
+    fn instant(&self) -> Instant;
+
+    /// Gets the time since the Unix Epoch, or zero if the clock is before it.
+    fn epoch(&self) -> Duration {
+        self.now().duration_since(UNIX_EPOCH).unwrap_or_default()
+    }
+}
+
+/// The system clock, using [`SystemTime::now`] and [`Instant::now`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+/// A clock that only moves when told to, for tests.
+/// Both the wall clock and monotonic time move forward with [`ManualClock::advance`].
+#[derive(Debug)]
+pub struct ManualClock {
+    /// The monotonic time the clock was created at.
+    start: Instant,
+    /// The current wall clock time and how far the clock has been advanced.
+    state: Mutex<(SystemTime, Duration)>,
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn instant(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+impl ManualClock {
+    /// Creates a new clock, stopped at the given wall clock time.
+    pub fn new(time: SystemTime) -> Self {
+        Self {
+            start: Instant::now(),
+            state: Mutex::new((time, Duration::ZERO)),
+        }
+    }
+
+    /// Moves the clock forward.
+    pub fn advance(&self, duration: Duration) {
+        let mut state = self.state.force_lock();
+        state.0 += duration;
+        state.1 += duration;
+    }
+
+    /// Sets the wall clock time.
+    /// Monotonic time can't go backwards, so it isn't changed.
+    pub fn set(&self, time: SystemTime) {
+        self.state.force_lock().0 = time;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> SystemTime {
+        self.state.force_lock().0
+    }
+
+    fn instant(&self) -> Instant {
+        self.start + self.state.force_lock().1
+    }
+}
+
+impl<C: Clock + ?Sized> Clock for Arc<C> {
+    fn now(&self) -> SystemTime {
+        (**self).now()
+    }
+
+    fn instant(&self) -> Instant {
+        (**self).instant()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::Cursor,
+        sync::{Arc, Mutex},
+        time::{Duration, UNIX_EPOCH},
+    };
+
+    use super::{Clock, ManualClock};
+    use crate::{
+        internal::testing::{MemoryListener, MemoryStream},
+        Method, Middleware, Response, Server,
+    };
+
+    #[test]
+    fn test_manual_clock() {
+        let clock = ManualClock::new(UNIX_EPOCH + Duration::from_secs(10));
+        let start = clock.instant();
+        assert_eq!(clock.epoch(), Duration::from_secs(10));
+
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(clock.epoch(), Duration::from_secs(15));
+        assert_eq!(clock.instant() - start, Duration::from_secs(5));
+
+        clock.set(UNIX_EPOCH);
+        assert_eq!(clock.epoch(), Duration::ZERO);
+        assert_eq!(clock.instant() - start, Duration::from_secs(5));
+    }
+
+    #[test]
+    #[cfg(feature = "extensions")]
+    fn test_clock() {
+        use std::time::UNIX_EPOCH;
+
+        use crate::extension::RateLimiter;
+
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let input = ["/", "/", "/advance", "/"]
+            .map(|path| format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n"))
+            .concat();
+        let listener = MemoryListener(Mutex::new(vec![MemoryStream {
+            input: Cursor::new(input.into_bytes()),
+            output: output.clone(),
+        }]));
+
+        let clock = Arc::new(ManualClock::new(
+            UNIX_EPOCH + Duration::from_secs(784111777),
+        ));
+        let mut server = Server::<()>::new("localhost", 0).clock(clock.clone());
+        server.route(Method::GET, "/", |_| Response::new().text("Hello"));
+        server.route(Method::GET, "/advance", move |_| {
+            clock.advance(Duration::from_secs(10));
+            Response::new()
+        });
+        RateLimiter::new()
+            .limit(1)
+            .timeout(10)
+            .key_fn(|req| req.path.clone())
+            .attach(&mut server);
+        assert!(server.start_with_listener(listener).is_err());
+
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        let responses = output.split("HTTP/1.1 ").skip(1).collect::<Vec<_>>();
+        assert!(responses[0].starts_with("200 "), "{:?}", responses[0]);
+        assert!(responses[0].contains("Date: Sun, 06 Nov 1994 08:49:37 GMT\r\n"));
+        assert!(responses[1].starts_with("429 "), "{:?}", responses[1]);
+        assert!(responses[3].starts_with("200 "), "{:?}", responses[3]);
+        assert!(responses[3].contains("Date: Sun, 06 Nov 1994 08:49:47 GMT\r\n"));
+    }
+}
@@ -0,0 +1,221 @@
+//! Server configuration that can be changed without recompiling.
+//!
+//! A [`ServerConfig`] holds the address, thread count, timeouts, limits and default headers of a server.
+//! It can be built in code, or with the `config` feature, deserialized from any format supported by serde (like TOML or JSON).
+//! Timeouts are given in seconds, and any field not in the file keeps its default value.
+//!
+//! ## Example
+//! ```rust,no_run
+//! # use afire::{Server, ServerConfig};
+//! # fn load() -> ServerConfig { ServerConfig::default() }
+//! // With the `config` feature, this could be `toml::from_str(&fs::read_to_string("server.toml")?)?`
+//! let config = load();
+//! let threads = config.threads;
+//!
+//! let server = Server::<()>::from_config(config);
+//! /* Define Routes, Attach Middleware, etc. */
+//! server.start_threaded(threads).unwrap();
+//! ```
+//!
+//! The same config in TOML:
+//! ```toml
+//! host = "0.0.0.0"
+//! port = 8080
+//! threads = 8
+//! keep_alive_timeout = 5
+//! header_timeout = 2.5
+//! max_body_size = 1048576
+//!
+//! [default_headers]
+//! X-Frame-Options = "DENY"
+//! ```
+
+use std::{collections::BTreeMap, time::Duration};
+
+use crate::{Header, Server};
+
+#[cfg(feature = "tracing")]
+use crate::trace::emoji;
+
+/// The configuration of a [`Server`], see the [module level docs](self).
+/// Every field is optional when deserializing, with the same defaults as [`Server::new`].
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Deserialize),
+    serde(default, deny_unknown_fields)
+)]
+pub struct ServerConfig {
+    /// The host to listen on, like `localhost` or `0.0.0.0`.
+    pub host: String,
+    /// The port to listen on.
+    pub port: u16,
+    /// The number of worker threads, to be passed to [`Server::start_threaded`].
+    /// Defaults to the available parallelism of the system.
+    pub threads: usize,
+    /// See [`Server::max_threads`].
+    pub max_threads: Option<usize>,
+
+    /// See [`Server::keep_alive`].
+    pub keep_alive: bool,
+    /// See [`Server::keep_alive_timeout`], in seconds.
+    #[cfg_attr(feature = "config", serde(deserialize_with = "seconds::deserialize"))]
+    pub keep_alive_timeout: Option<Duration>,
+    /// See [`Server::max_keep_alive_requests`].
+    pub max_keep_alive_requests: Option<usize>,
+    /// See [`Server::socket_timeout`], in seconds.
+    #[cfg_attr(feature = "config", serde(deserialize_with = "seconds::deserialize"))]
+    pub socket_timeout: Option<Duration>,
+    /// See [`Server::request_line_timeout`], in seconds.
+    #[cfg_attr(feature = "config", serde(deserialize_with = "seconds::deserialize"))]
+    pub request_line_timeout: Option<Duration>,
+    /// See [`Server::header_timeout`], in seconds.
+    #[cfg_attr(feature = "config", serde(deserialize_with = "seconds::deserialize"))]
+    pub header_timeout: Option<Duration>,
+    /// See [`Server::body_timeout`], in seconds.
+    #[cfg_attr(feature = "config", serde(deserialize_with = "seconds::deserialize"))]
+    pub body_timeout: Option<Duration>,
+
+    /// See [`Server::max_connections`].
+    pub max_connections: Option<usize>,
+    /// See [`Server::max_connections_per_ip`].
+    pub max_connections_per_ip: Option<usize>,
+    /// See [`Server::public_url`].
+    pub public_url: Option<String>,
+    /// See [`Server::max_queue_wait`], in seconds.
+    #[cfg_attr(feature = "config", serde(deserialize_with = "seconds::deserialize"))]
+    pub max_queue_wait: Option<Duration>,
+    /// See [`Server::max_request_line`].
+    pub max_request_line: usize,
+    /// See [`Server::max_header_size`].
+    pub max_header_size: usize,
+    /// See [`Server::max_headers`].
+    pub max_headers: usize,
+    /// See [`Server::max_body_size`].
+    pub max_body_size: Option<usize>,
+
+    /// Headers added to every response, on top of the default `Server` header.
+    pub default_headers: BTreeMap<String, String>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            host: "localhost".to_owned(),
+            port: 8080,
+            threads: std::thread::available_parallelism().map_or(1, |x| x.get()),
+            max_threads: None,
+            keep_alive: true,
+            keep_alive_timeout: None,
+            max_keep_alive_requests: None,
+            socket_timeout: None,
+            request_line_timeout: None,
+            header_timeout: None,
+            body_timeout: None,
+            max_connections: None,
+            max_connections_per_ip: None,
+            public_url: None,
+            max_queue_wait: None,
+            max_request_line: 8 * 1024,
+            max_header_size: 32 * 1024,
+            max_headers: 100,
+            max_body_size: None,
+            default_headers: BTreeMap::new(),
+        }
+    }
+}
+
+impl<State: Send + Sync> Server<State> {
+    /// Creates a new server from a [`ServerConfig`].
+    /// The thread count is not part of the server, so pass [`ServerConfig::threads`] to [`Server::start_threaded`] when starting it.
+    /// ## Example
+    /// ```rust
+    /// # use afire::{Server, ServerConfig};
+    /// let config = ServerConfig {
+    ///     port: 8081,
+    ///     max_headers: 50,
+    ///     ..ServerConfig::default()
+    /// };
+    /// let server = Server::<()>::from_config(config);
+    /// assert_eq!(server.max_headers, 50);
+    /// ```
+    pub fn from_config(config: ServerConfig) -> Self {
+        trace!("{}Loading Server Config", emoji("📄"));
+        let mut server = Self::new(config.host.as_str(), config.port);
+        for (key, value) in &config.default_headers {
+            let header = Header::new(key, value);
+            server.default_headers.insert(header.name, header.value);
+        }
+        if let Some(url) = &config.public_url {
+            server = server.public_url(url);
+        }
+
+        Server {
+            max_threads: config.max_threads,
+            keep_alive: config.keep_alive,
+            keep_alive_timeout: config.keep_alive_timeout,
+            max_keep_alive_requests: config.max_keep_alive_requests,
+            socket_timeout: config.socket_timeout,
+            request_line_timeout: config.request_line_timeout,
+            header_timeout: config.header_timeout,
+            body_timeout: config.body_timeout,
+            max_connections: config.max_connections,
+            max_connections_per_ip: config.max_connections_per_ip,
+            max_queue_wait: config.max_queue_wait,
+            max_request_line: config.max_request_line,
+            max_header_size: config.max_header_size,
+            max_headers: config.max_headers,
+            max_body_size: config.max_body_size,
+            ..server
+        }
+    }
+}
+
+/// Deserializes an optional [`Duration`] from a number of seconds.
+#[cfg(feature = "config")]
+mod seconds {
+    use std::time::Duration;
+
+    use serde::{de::Error, Deserialize, Deserializer};
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Duration>, D::Error> {
+        Option::<f64>::deserialize(deserializer)?
+            .map(|secs| Duration::try_from_secs_f64(secs).map_err(D::Error::custom))
+            .transpose()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::ServerConfig;
+    use crate::Server;
+
+    #[test]
+    fn test_from_config() {
+        let config: ServerConfig = serde_json::from_str(
+            r#"{
+                "port": 8081,
+                "header_timeout": 2.5,
+                "max_body_size": 1024,
+                "default_headers": { "X-Frame-Options": "DENY" }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(config.host, "localhost");
+        assert_eq!(config.header_timeout, Some(Duration::from_millis(2500)));
+
+        let server = Server::<()>::from_config(config);
+        assert_eq!(server.port, 8081);
+        assert_eq!(server.max_body_size, Some(1024));
+        assert_eq!(server.max_headers, 100);
+        assert_eq!(server.default_headers.get("X-Frame-Options"), Some("DENY"));
+        assert!(server.default_headers.has("Server"));
+
+        assert!(serde_json::from_str::<ServerConfig>(r#"{ "prot": 80 }"#).is_err());
+        assert!(serde_json::from_str::<ServerConfig>(r#"{ "body_timeout": -1 }"#).is_err());
+    }
+}
@@ -0,0 +1,208 @@
+//! The context passed to context routes, and deferred responses.
+
+use std::{
+    cell::RefCell,
+    net::SocketAddr,
+    rc::Rc,
+    sync::{mpsc, Arc},
+};
+
+use crate::{
+    error::{HandleError, Result},
+    internal::handle::{finish_response, post_middleware, ResponseInfo},
+    thread_pool::ThreadPoolHandle,
+    Error, Request, Response, Server, ShutdownToken,
+};
+
+/// The context passed to routes defined with [`Server::context_route`].
+/// Along with the request, it gives handlers access to the app state and the server,
+/// and lets them send a response early with [`Context::send`].
+pub struct Context<'a, State: 'static + Send + Sync = ()> {
+    /// The request being handled.
+    pub req: Rc<Request>,
+
+    /// Information about the connection, used to finish responses sent with [`Context::send`].
+    info: ResponseInfo,
+
+    /// The server handling the request.
+    server: &'a Server<State>,
+
+    /// The response sent with [`Context::send`], if any.
+    sent: RefCell<Option<Response>>,
+
+    /// Receives the response if it was deferred with [`Context::defer`].
+    deferred: RefCell<Option<mpsc::Receiver<Response>>>,
+}
+
+/// A response that will be sent later, possibly from another thread.
+/// Created with [`Context::defer`].
+///
+/// The connection waits (holding on to its worker thread) until [`DeferredResponse::send`] is called.
+/// If this is dropped without sending a response, the client is sent a `500 Internal Server Error`.
+#[derive(Debug)]
+pub struct DeferredResponse(mpsc::Sender<Response>);
+
+impl<'a, State: 'static + Send + Sync> Context<'a, State> {
+    pub(crate) fn new(req: Rc<Request>, info: ResponseInfo, server: &'a Server<State>) -> Self {
+        Self {
+            req,
+            info,
+            server,
+            sent: RefCell::new(None),
+            deferred: RefCell::new(None),
+        }
+    }
+
+    /// Gets the response to send after the handler returns.
+    /// Waits for the deferred response if there is one.
+    pub(crate) fn finish(self, res: Response) -> Result<Response> {
+        if let Some(sent) = self.sent.into_inner() {
+            return Ok(sent);
+        }
+
+        match self.deferred.into_inner() {
+            Some(rx) => rx
+                .recv()
+                .map_err(|_| Error::Handle(Box::new(HandleError::DeferredDropped))),
+            None => Ok(res),
+        }
+    }
+
+    /// Gets the server's state, see [`Server::state`].
+    /// Will <u>panic</u> if the server has no state.
+    /// ## Example
+    /// ```rust
+    /// # use std::sync::atomic::{AtomicU32, Ordering};
+    /// # use afire::{Server, Response, Method};
+    /// let mut server = Server::new("localhost", 8080).state(AtomicU32::new(0));
+    /// server.context_route(Method::GET, "/", |ctx| {
+    ///     let count = ctx.app().fetch_add(1, Ordering::Relaxed);
+    ///     Response::new().text(count)
+    /// });
+    /// ```
+    pub fn app(&self) -> Arc<State> {
+        self.server.app()
+    }
+
+    /// Gets the address the server is listening on.
+    /// This is the address the server was bound to, for the address the client connected to see [`Request::local_address`].
+    pub fn server_address(&self) -> Option<SocketAddr> {
+        self.server.shutdown.local_addr()
+    }
+
+    /// Gracefully shuts down the server, see [`crate::ShutdownHandle`].
+    /// The response to this request will still be sent.
+    /// ## Example
+    /// ```rust
+    /// # use afire::{Server, Response, Method};
+    /// # let mut server = Server::<()>::new("localhost", 8080);
+    /// server.context_route(Method::POST, "/shutdown", |ctx| {
+    ///     ctx.shutdown();
+    ///     Response::new().text("Goodbye")
+    /// });
+    /// ```
+    pub fn shutdown(&self) {
+        self.server.shutdown.shutdown();
+    }
+
+    /// Gets a token for finding out when the server is shutting down, see [`ShutdownToken`].
+    pub fn shutdown_token(&self) -> ShutdownToken {
+        self.server.shutdown.token()
+    }
+
+    /// Gets a handle to monitor the server's thread pool, see [`ThreadPoolHandle`].
+    pub fn thread_pool(&self) -> ThreadPoolHandle {
+        self.server.thread_pool()
+    }
+
+    /// Gets the counters of the server's thread pool.
+    #[deprecated(note = "Renamed to Context::thread_pool")]
+    pub fn thread_pool_stats(&self) -> ThreadPoolHandle {
+        self.thread_pool()
+    }
+
+    /// Sends a response to the client right away, before the handler returns.
+    /// The post middleware is run on the response as usual, and the response returned from the handler is ignored.
+    /// This is useful for long running handlers that want to reply to the client before they finish their work.
+    /// On HTTP/2 connections (with the experimental `http2` feature) the response is only sent once the handler returns.
+    ///
+    /// Will return an error if a response has already been sent.
+    /// ## Example
+    /// ```rust
+    /// # use afire::{Server, Response, Method, Status};
+    /// # let mut server = Server::<()>::new("localhost", 8080);
+    /// server.context_route(Method::POST, "/job", |ctx| {
+    ///     ctx.send(Response::new().status(Status::Accepted).text("Job started"))
+    ///         .unwrap();
+    ///
+    ///     /* Do the long running job */
+    ///
+    ///     // Ignored, because a response has already been sent
+    ///     Response::new()
+    /// });
+    /// ```
+    pub fn send(&self, res: Response) -> Result<()> {
+        if self.is_sent() {
+            return Err(Error::Handle(Box::new(HandleError::ResponseAlreadySent)));
+        }
+
+        let (_, mut res) = post_middleware(Ok(self.req.clone()), Ok(res), self.server);
+        finish_response(&mut res, self.info, self.server);
+        let result = match self.info.http2 {
+            true => Ok(()),
+            false => res.write(self.req.socket.clone(), self.server),
+        };
+
+        res.sent = true;
+        *self.sent.borrow_mut() = Some(res);
+        result
+    }
+
+    /// Checks if a response has already been sent with [`Context::send`].
+    pub fn is_sent(&self) -> bool {
+        self.sent.borrow().is_some()
+    }
+
+    /// Defers the response, so it can be sent later from another thread.
+    /// Once the handler returns, the response it returned is ignored and the connection waits for [`DeferredResponse::send`].
+    /// The deferred response then goes through post middleware, keep-alive handling and end middleware like any other response.
+    ///
+    /// This is useful for long polling, where a request waits for an event that happens elsewhere.
+    /// Note: A response sent with [`Context::send`] takes priority over a deferred one.
+    /// ## Example
+    /// ```rust
+    /// # use std::{sync::{mpsc, Mutex}, thread};
+    /// # use afire::{Server, Response, Method};
+    /// # let mut server = Server::<()>::new("localhost", 8080);
+    /// let (tx, rx) = mpsc::channel();
+    /// let tx = Mutex::new(tx);
+    ///
+    /// // Requests to /poll wait until an event happens
+    /// server.context_route(Method::GET, "/poll", move |ctx| {
+    ///     tx.lock().unwrap().send(ctx.defer()).unwrap();
+    ///     Response::new()
+    /// });
+    ///
+    /// // Responds to all waiting requests once the event happens
+    /// thread::spawn(move || {
+    ///     for waiting in rx {
+    ///         let _ = waiting.send(Response::new().text("Event!"));
+    ///     }
+    /// });
+    /// ```
+    pub fn defer(&self) -> DeferredResponse {
+        let (tx, rx) = mpsc::channel();
+        *self.deferred.borrow_mut() = Some(rx);
+        DeferredResponse(tx)
+    }
+}
+
+impl DeferredResponse {
+    /// Sends the response to the client.
+    /// If the connection is no longer waiting for it (because the handler panicked), the response is given back as an Err.
+    // The response is given back as is, so it can be sent another way
+    #[allow(clippy::result_large_err)]
+    pub fn send(self, res: Response) -> std::result::Result<(), Response> {
+        self.0.send(res).map_err(|e| e.0)
+    }
+}
@@ -1,13 +1,16 @@
 //! Errors that can occur in the process of connecting to clients, parsing HTTP and handling requests.
 
 use std::{
+    backtrace::Backtrace,
     error,
     fmt::{self, Display, Formatter},
+    io,
     rc::Rc,
     result,
+    sync::Arc,
 };
 
-use crate::{Method, Request};
+use crate::{Method, Request, RouteInfo};
 
 /// Easy way to use a Result<T, [`crate::Error`]>
 pub type Result<T> = result::Result<T, Error>;
@@ -28,7 +31,7 @@ pub enum Error {
     Parse(ParseError),
 
     /// IO Errors
-    Io(String),
+    Io(IoError),
 
     /// Response does not exist (probably because of an error with the request)
     None,
@@ -40,11 +43,38 @@ pub enum StartupError {
     /// The IP address specified is invalid
     InvalidIp,
 
+    /// The address is already in use by another socket
+    AddressInUse,
+
     /// No state was specified, but a route requires it
     NoState,
 
     /// The socket timeout specified is invalid (must be greater than 0)
     InvalidSocketTimeout,
+
+    /// The keep-alive timeout specified is invalid (must be greater than 0)
+    InvalidKeepAliveTimeout,
+
+    /// The max keep-alive requests specified is invalid (must be greater than 0)
+    InvalidMaxKeepAliveRequests,
+
+    /// The max connections specified is invalid (must be greater than 0)
+    InvalidMaxConnections,
+
+    /// The interval of a scheduled task is invalid (must be greater than 0)
+    InvalidTaskInterval,
+
+    /// The read buffer or chunk size specified is invalid (must be greater than 0)
+    InvalidBufferSize,
+
+    /// Two routes with the same method and priority match exactly the same paths, so it is ambiguous which should handle a request.
+    /// Contains the method and the paths of both routes.
+    /// Give one of them a higher priority with [`crate::Server::route_priority`] to resolve it.
+    RouteConflict(Method, String, String),
+
+    /// The path of a route is invalid, because a path param has an invalid regex constraint.
+    /// Contains the path of the route and the error message.
+    InvalidRoute(String, String),
 }
 
 /// Errors that can arise while handling a request
@@ -54,7 +84,41 @@ pub enum HandleError {
     NotFound(Method, String),
 
     /// A route or middleware panicked while running
-    Panic(Box<Result<Rc<Request>>>, String),
+    Panic(Box<Result<Rc<Request>>>, Box<PanicInfo>),
+
+    /// A response was sent with [`crate::Context::send`] when one had already been sent
+    ResponseAlreadySent,
+
+    /// A [`crate::context::DeferredResponse`] was dropped without sending a response
+    DeferredDropped,
+}
+
+/// Information about a panic in a route or middleware, given to the [`crate::Server::error_handler`].
+/// It is also available to `end` middleware with [`crate::Response::panic`].
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct PanicInfo {
+    /// The panic message.
+    pub message: String,
+    /// The route that panicked, or None if it was a middleware.
+    pub route: Option<RouteInfo>,
+    /// Where the panic happened.
+    /// This is only captured if the `RUST_BACKTRACE` (or `RUST_LIB_BACKTRACE`) environment variable is set, or [`crate::Server::capture_backtraces`] is enabled.
+    pub backtrace: Option<Arc<Backtrace>>,
+}
+
+impl PanicInfo {
+    pub(crate) fn new(
+        message: impl Into<String>,
+        route: Option<RouteInfo>,
+        backtrace: Option<Backtrace>,
+    ) -> Self {
+        Self {
+            message: message.into(),
+            route,
+            backtrace: backtrace.map(Arc::new),
+        }
+    }
 }
 
 /// Error that can occur while parsing the HTTP of a request
@@ -83,6 +147,38 @@ pub enum ParseError {
 
     /// Invalid Header in Request HTTP
     InvalidHeader,
+
+    /// An HTTP/1.1 request without a Host header
+    NoHost,
+
+    /// More than one Host header, or an invalid host in the Host header or request target
+    InvalidHost,
+
+    /// The request line is longer than [`crate::Server::max_request_line`]
+    RequestLineTooLong,
+
+    /// The request target is longer than the [`crate::UriPolicy::max_length`]
+    UriTooLong,
+
+    /// The request's path isn't valid UTF-8 or has invalid percent encoding, and [`crate::UriPolicy::reject_invalid_utf8`] is enabled
+    InvalidPath,
+
+    /// The request headers are larger than [`crate::Server::max_header_size`] or there are more than [`crate::Server::max_headers`] of them
+    HeadersTooLarge,
+
+    /// The request body is larger than [`crate::Server::max_body_size`] or the limit of the route it was sent to
+    BodyTooLarge,
+
+    /// The request has both `Content-Length` and `Transfer-Encoding` headers, or `Content-Length` headers with different values.
+    /// Servers and proxies could disagree on where the body ends, so the rest could be read as another request.
+    AmbiguousBody,
+
+    /// The request has a `Transfer-Encoding` header, which isn't supported.
+    /// The length of its body can't be known, so it can't be told apart from the next request.
+    UnsupportedTransferEncoding,
+
+    /// The request line has an HTTP version other than 1.x, like the HTTP/2 connection preface when the `http2` feature is disabled
+    UnsupportedVersion,
 }
 
 /// Error that can occur while reading or writing to a stream
@@ -90,9 +186,44 @@ pub enum ParseError {
 pub enum StreamError {
     /// The stream ended unexpectedly
     UnexpectedEof,
+    /// The client closed or reset the connection before sending a whole request.
+    /// This is normal for keep-alive connections, and the client can't get a response anyway, so no response is sent.
+    ConnectionClosed,
+    /// The client took too long to send the request.
+    /// See [`crate::Server::socket_timeout`] and [`crate::Server::request_line_timeout`].
+    TimedOut,
+    /// The client stopped accepting the response, so writes made no progress for the [`crate::Server::socket_timeout`] (or 30 seconds if it isn't set).
+    /// The [`crate::WriteStats`] of the response have how much was written before giving up.
+    WriteTimeout,
+}
+
+/// An [`io::Error`] that can be cloned and compared, so it can be stored in an [`Error`].
+/// Use [`IoError::kind`] to tell apart timeouts, resets and other failures, or [`IoError::inner`] to get the original error.
+/// Two IoErrors are equal if they have the same kind and message.
+#[derive(Debug, Clone)]
+pub struct IoError(Arc<io::Error>);
+
+impl IoError {
+    /// Gets the kind of the underlying [`io::Error`].
+    pub fn kind(&self) -> io::ErrorKind {
+        self.0.kind()
+    }
+
+    /// Gets a reference to the underlying [`io::Error`].
+    pub fn inner(&self) -> &io::Error {
+        &self.0
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e.inner()),
+            _ => None,
+        }
+    }
 }
 
-impl error::Error for Error {}
 impl Display for Error {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match self {
@@ -100,7 +231,7 @@ impl Display for Error {
             Error::Startup(e) => fmt::Display::fmt(e, f),
             Error::Stream(e) => fmt::Display::fmt(e, f),
             Error::Parse(e) => fmt::Display::fmt(e, f),
-            Error::Io(e) => f.write_str(e),
+            Error::Io(e) => fmt::Display::fmt(e, f),
             Error::None => f.write_str("None"),
         }
     }
@@ -115,18 +246,56 @@ impl Display for HandleError {
             HandleError::Panic(_req, err) => {
                 f.write_fmt(format_args!("Route handler panicked: {err}"))
             }
+            HandleError::ResponseAlreadySent => f.write_str("Response already sent"),
+            HandleError::DeferredDropped => {
+                f.write_str("Deferred response dropped without being sent")
+            }
         }
     }
 }
 
+impl Display for PanicInfo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
 impl Display for StartupError {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if let StartupError::RouteConflict(method, a, b) = self {
+            return write!(
+                f,
+                "The routes `{method} {a}` and `{method} {b}` match the same requests (set a priority on one of them to resolve this)"
+            );
+        }
+
+        if let StartupError::InvalidRoute(path, error) = self {
+            return write!(f, "The route `{path}` is invalid: {error}");
+        }
+
         f.write_str(match self {
             StartupError::InvalidIp => "The IP address specified is invalid",
+            StartupError::AddressInUse => "The address is already in use by another socket",
             StartupError::NoState => "No state was specified, but a route requires it",
             StartupError::InvalidSocketTimeout => {
                 "The socket timeout specified is invalid (must be greater than 0)"
             }
+            StartupError::InvalidKeepAliveTimeout => {
+                "The keep-alive timeout specified is invalid (must be greater than 0)"
+            }
+            StartupError::InvalidMaxKeepAliveRequests => {
+                "The max keep-alive requests specified is invalid (must be greater than 0)"
+            }
+            StartupError::InvalidMaxConnections => {
+                "The max connections specified is invalid (must be greater than 0)"
+            }
+            StartupError::InvalidTaskInterval => {
+                "The interval of a scheduled task is invalid (must be greater than 0)"
+            }
+            StartupError::InvalidBufferSize => {
+                "The read buffer or chunk size specified is invalid (must be greater than 0)"
+            }
+            StartupError::RouteConflict(..) | StartupError::InvalidRoute(..) => unreachable!(),
         })
     }
 }
@@ -135,6 +304,9 @@ impl Display for StreamError {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         f.write_str(match self {
             StreamError::UnexpectedEof => "The stream ended unexpectedly",
+            StreamError::ConnectionClosed => "The client closed the connection",
+            StreamError::TimedOut => "The client took too long to send the request",
+            StreamError::WriteTimeout => "The client took too long to accept the response",
         })
     }
 }
@@ -152,6 +324,16 @@ impl Display for ParseError {
             ParseError::InvalidQuery => "Invalid Query in Path",
             ParseError::InvalidMethod => "Invalid Method in Request HTTP",
             ParseError::InvalidHeader => "Invalid Header in Request HTTP",
+            ParseError::NoHost => "No Host header in HTTP/1.1 request",
+            ParseError::InvalidHost => "Invalid Host in request",
+            ParseError::RequestLineTooLong => "Request Line is too long",
+            ParseError::UriTooLong => "Request URI is too long",
+            ParseError::InvalidPath => "Invalid Path in request",
+            ParseError::HeadersTooLarge => "Request Headers are too large",
+            ParseError::BodyTooLarge => "Request Body is too large",
+            ParseError::AmbiguousBody => "Ambiguous body length in request",
+            ParseError::UnsupportedTransferEncoding => "Unsupported transfer encoding in request",
+            ParseError::UnsupportedVersion => "Unsupported HTTP version in request",
         })
     }
 }
@@ -180,9 +362,36 @@ impl From<HandleError> for Error {
     }
 }
 
-impl From<std::io::Error> for Error {
-    fn from(e: std::io::Error) -> Self {
-        Error::Io(e.to_string())
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e.into())
+    }
+}
+
+impl From<io::Error> for IoError {
+    fn from(e: io::Error) -> Self {
+        IoError(Arc::new(e))
+    }
+}
+
+impl Display for IoError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl Eq for IoError {}
+impl PartialEq for IoError {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind() == other.kind() && self.0.to_string() == other.0.to_string()
+    }
+}
+
+impl Eq for PanicInfo {}
+impl PartialEq for PanicInfo {
+    /// Backtraces can't be compared, so only the message and route are.
+    fn eq(&self, other: &Self) -> bool {
+        self.message == other.message && self.route == other.route
     }
 }
 
@@ -196,3 +405,27 @@ impl PartialEq for HandleError {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::{error::Error as _, io};
+
+    use super::Error;
+
+    #[test]
+    fn test_io_error_kind() {
+        let err = Error::from(io::Error::new(io::ErrorKind::TimedOut, "too slow"));
+        let Error::Io(io_err) = &err else {
+            panic!("Expected Error::Io, got {:?}", err);
+        };
+
+        assert_eq!(io_err.kind(), io::ErrorKind::TimedOut);
+        assert_eq!(err.to_string(), "too slow");
+        assert!(err.source().is_some());
+        assert_eq!(
+            err,
+            Error::from(io::Error::new(io::ErrorKind::TimedOut, "too slow"))
+        );
+        assert_ne!(err, Error::from(io::Error::other("too slow")));
+    }
+}
@@ -0,0 +1,150 @@
+//! Answer ACME `http-01` challenges, for getting certificates from authorities like Let's Encrypt.
+//!
+//! To prove it controls a domain, an ACME client is given a token by the certificate authority, which then requests `http://<domain>/.well-known/acme-challenge/<token>` on port 80 and checks the response is the token's key authorization.
+//! Normally clients do this by writing files to a webroot or by briefly running their own server, neither of which work when afire is the only thing listening on port 80.
+//! Instead, the key authorizations can be added to [`AcmeChallenge`] while the server is running.
+//! See [RFC 8555](https://www.rfc-editor.org/rfc/rfc8555#section-8.3) for more details.
+
+use std::{
+    any::type_name,
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    header::typed::CacheControl, internal::common::ForceLock, middleware::Middleware, trace::emoji,
+    Content, Method, Response, Server, Status,
+};
+
+/// Registers a route that serves the key authorizations of pending ACME challenges on `/.well-known/acme-challenge/{token}`.
+/// Requests for unknown tokens are sent a `404 Not Found`.
+///
+/// Clones share the same challenges, so keep a clone around to add and remove them once this is attached.
+/// ## Example
+/// ```rust
+/// # use afire::{Server, Middleware, extension::AcmeChallenge};
+/// # let mut server = Server::<()>::new("localhost", 80);
+/// let acme = AcmeChallenge::new();
+/// acme.clone().attach(&mut server);
+///
+/// // Once the ACME client has been given a challenge
+/// acme.insert("LoqXcYV8q5ONbJQxbmR7SCTNo3tiAXDfowyjxAjEuX0", "LoqXcYV8q5ONbJQxbmR7SCTNo3tiAXDfowyjxAjEuX0.9jg46WB3rR_AHD-EBXdN7cBkH1WOu0tA3M9fm21mqTI");
+///
+/// // And after it has been validated
+/// acme.remove("LoqXcYV8q5ONbJQxbmR7SCTNo3tiAXDfowyjxAjEuX0");
+/// ```
+#[derive(Clone, Default)]
+pub struct AcmeChallenge {
+    /// The key authorizations of the pending challenges, by token.
+    challenges: Arc<Mutex<HashMap<String, String>>>,
+}
+
+impl AcmeChallenge {
+    /// Creates a new AcmeChallenge extension with no pending challenges.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a challenge, so requests for `token` are answered with `key_authorization`.
+    /// Replaces any existing challenge with the same token.
+    pub fn insert(&self, token: impl AsRef<str>, key_authorization: impl AsRef<str>) {
+        self.challenges.force_lock().insert(
+            token.as_ref().to_owned(),
+            key_authorization.as_ref().to_owned(),
+        );
+    }
+
+    /// Removes a challenge, returning its key authorization if it existed.
+    pub fn remove(&self, token: impl AsRef<str>) -> Option<String> {
+        self.challenges.force_lock().remove(token.as_ref())
+    }
+
+    /// Removes all the challenges.
+    pub fn clear(&self) {
+        self.challenges.force_lock().clear();
+    }
+
+    /// Gets the key authorization of a challenge.
+    pub fn get(&self, token: impl AsRef<str>) -> Option<String> {
+        self.challenges.force_lock().get(token.as_ref()).cloned()
+    }
+}
+
+impl Middleware for AcmeChallenge {
+    fn attach<State>(self, server: &mut Server<State>)
+    where
+        Self: 'static + Send + Sync + Sized,
+        State: 'static + Send + Sync,
+    {
+        trace!("{}Adding Extension {}", emoji("📦"), type_name::<Self>());
+
+        server.route(
+            Method::GET,
+            "/.well-known/acme-challenge/{token}",
+            move |req| {
+                let key_authorization = req.param("token").and_then(|i| self.get(i));
+                let Some(key_authorization) = key_authorization else {
+                    return Response::new()
+                        .status(Status::NotFound)
+                        .text("Unknown challenge token")
+                        .content(Content::TXT);
+                };
+
+                Response::new()
+                    .text(key_authorization)
+                    .content(Content::TXT)
+                    .cache(CacheControl::new().no_store())
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::Cursor,
+        sync::{Arc, Mutex},
+    };
+
+    use super::AcmeChallenge;
+    use crate::{
+        internal::testing::{MemoryListener, MemoryStream},
+        Middleware, Server,
+    };
+
+    #[test]
+    fn test_acme_challenge() {
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let input = ["abc-123", "unknown", "removed"]
+            .map(|token| {
+                format!(
+                    "GET /.well-known/acme-challenge/{token} HTTP/1.1\r\nHost: localhost\r\n\r\n"
+                )
+            })
+            .concat();
+        let listener = MemoryListener(Mutex::new(vec![MemoryStream {
+            input: Cursor::new(input.into_bytes()),
+            output: output.clone(),
+        }]));
+
+        let mut server = Server::<()>::new("localhost", 0);
+        let acme = AcmeChallenge::new();
+        acme.clone().attach(&mut server);
+        acme.insert("abc-123", "abc-123.thumbprint");
+        acme.insert("removed", "removed.thumbprint");
+        assert_eq!(
+            acme.remove("removed").as_deref(),
+            Some("removed.thumbprint")
+        );
+        assert!(server.start_with_listener(listener).is_err());
+
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        let responses = output.split("HTTP/1.1 ").skip(1).collect::<Vec<_>>();
+        assert_eq!(responses.len(), 3);
+        assert!(responses[0].starts_with("200 OK\r\n"));
+        assert!(responses[0].contains("Cache-Control: no-store\r\n"));
+        assert!(responses[0].ends_with("\r\n\r\nabc-123.thumbprint"));
+        assert!(responses[1].starts_with("404 Not Found\r\n"));
+        assert!(responses[2].starts_with("404 Not Found\r\n"));
+    }
+}
@@ -0,0 +1,241 @@
+//! Redirect requests to the canonical form of their URL.
+//!
+//! Search engines treat `/About`, `/about` and `/about/` (or `example.com` and `www.example.com`) as different pages, splitting their ranking between them.
+//! Redirecting every variant to one canonical URL avoids that.
+
+use std::net::IpAddr;
+
+use crate::{
+    middleware::{MiddleResult, Middleware},
+    HeaderType, Method, Request, Response, Status,
+};
+
+/// What to do with trailing slashes on paths.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrailingSlash {
+    /// Add a trailing slash to paths without one, like `/about` to `/about/`.
+    /// Paths where the last segment has a file extension, like `/style.css`, are left alone.
+    Add,
+    /// Remove the trailing slash from paths with one, like `/about/` to `/about`.
+    Remove,
+}
+
+/// Which form of the domain to use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Domain {
+    /// Add `www.` to hosts without it, like `example.com` to `www.example.com`.
+    Www,
+    /// Remove `www.` from hosts with it, like `www.example.com` to `example.com`.
+    Apex,
+}
+
+/// Middleware that redirects requests to the canonical form of their URL.
+/// `GET` and `HEAD` requests are redirected with `301 Moved Permanently`, other methods with `308 Permanent Redirect` so the method and body are kept.
+///
+/// By default no rules are enabled.
+/// The domain rule is not applied to IP addresses or hosts without a dot, like `localhost`.
+/// The query string is kept as it was received.
+///
+/// Middleware run in the reverse order they were attached, so attach this after other middleware to redirect before they run.
+/// ## Example
+/// ```rust
+/// # use afire::{Server, Middleware, extension::{Canonicalize, canonicalize::{Domain, TrailingSlash}}};
+/// # let mut server = Server::<()>::new("localhost", 8080);
+/// Canonicalize::new()
+///     // `/About/` -> `/about`
+///     .lowercase(true)
+///     .trailing_slash(TrailingSlash::Remove)
+///     // `www.example.com` -> `example.com`
+///     .domain(Domain::Apex)
+///     .attach(&mut server);
+/// ```
+pub struct Canonicalize {
+    /// If paths are lowercased.
+    lowercase: bool,
+    /// What to do with trailing slashes, or None to leave them alone.
+    trailing_slash: Option<TrailingSlash>,
+    /// The form of the domain to use, or None to leave it alone.
+    domain: Option<Domain>,
+}
+
+impl Canonicalize {
+    /// Creates a new Canonicalize middleware with no rules.
+    pub fn new() -> Self {
+        Self {
+            lowercase: false,
+            trailing_slash: None,
+            domain: None,
+        }
+    }
+
+    /// Sets if paths are lowercased.
+    /// Percent-encoded characters are left as they are, so `/Caf%C3%A9` becomes `/caf%C3%A9`.
+    pub fn lowercase(self, lowercase: bool) -> Self {
+        Self { lowercase, ..self }
+    }
+
+    /// Sets if trailing slashes are added to or removed from paths.
+    /// The root path (`/`) is never changed.
+    pub fn trailing_slash(self, trailing_slash: TrailingSlash) -> Self {
+        Self {
+            trailing_slash: Some(trailing_slash),
+            ..self
+        }
+    }
+
+    /// Sets if the `www.` subdomain is added to or removed from hosts.
+    pub fn domain(self, domain: Domain) -> Self {
+        Self {
+            domain: Some(domain),
+            ..self
+        }
+    }
+
+    /// Gets the canonical form of a path.
+    fn canonical_path(&self, path: &str) -> String {
+        let mut out = String::with_capacity(path.len() + 1);
+        let mut escape = 0;
+        for i in path.chars() {
+            match i {
+                '%' => escape = 2,
+                _ if escape > 0 => escape -= 1,
+                _ if self.lowercase => {
+                    out.push(i.to_ascii_lowercase());
+                    continue;
+                }
+                _ => {}
+            }
+            out.push(i);
+        }
+
+        match self.trailing_slash {
+            Some(TrailingSlash::Add) if !out.ends_with('/') => {
+                let last = out.rsplit('/').next().unwrap_or_default();
+                if !last.contains('.') {
+                    out.push('/');
+                }
+            }
+            Some(TrailingSlash::Remove) if out.len() > 1 => {
+                let trimmed = out.trim_end_matches('/').len().max(1);
+                out.truncate(trimmed);
+            }
+            _ => {}
+        }
+
+        out
+    }
+
+    /// Gets the canonical form of a host, which can include a port.
+    fn canonical_host(&self, host: &str) -> String {
+        let name = match host.rsplit_once(':') {
+            Some((name, port)) if !port.contains(']') => name,
+            _ => host,
+        };
+        let named = name.contains('.')
+            && name
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .parse::<IpAddr>()
+                .is_err();
+
+        match self.domain {
+            Some(Domain::Www) if named && !has_www(host) => format!("www.{host}"),
+            Some(Domain::Apex) if named && has_www(host) => host[4..].to_owned(),
+            _ => host.to_owned(),
+        }
+    }
+
+    /// Gets the URL to redirect a request to, or None if it's already canonical.
+    fn location(&self, req: &Request) -> Option<String> {
+        let path = self.canonical_path(&req.path);
+        let host = req.host().map(|i| (i, self.canonical_host(i)));
+        let host_changed = host.as_ref().is_some_and(|(old, new)| old != new);
+        if path == req.path && !host_changed {
+            return None;
+        }
+
+        let query = match req.raw_query() {
+            "" => String::new(),
+            query => format!("?{query}"),
+        };
+        Some(match host {
+            Some((_, host)) if host_changed => {
+                let base = req.base_url();
+                let scheme = base.split_once("://").map_or("http", |i| i.0);
+                format!("{scheme}://{host}{path}{query}")
+            }
+            _ => format!("{path}{query}"),
+        })
+    }
+}
+
+/// Checks if a host starts with the `www.` subdomain.
+fn has_www(host: &str) -> bool {
+    host.get(..4)
+        .is_some_and(|i| i.eq_ignore_ascii_case("www."))
+}
+
+impl Middleware for Canonicalize {
+    fn pre(&self, req: &mut Request) -> MiddleResult {
+        let Some(location) = self.location(req) else {
+            return MiddleResult::Continue;
+        };
+
+        let status = match req.method {
+            Method::GET | Method::HEAD => Status::MovedPermanently,
+            _ => Status::PermanentRedirect,
+        };
+        MiddleResult::Send(
+            Response::new()
+                .status(status)
+                .header(HeaderType::Location, location),
+        )
+    }
+}
+
+impl Default for Canonicalize {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Canonicalize, Domain, TrailingSlash};
+
+    #[test]
+    fn test_canonical_path() {
+        let lower = Canonicalize::new().lowercase(true);
+        assert_eq!(lower.canonical_path("/About/Us"), "/about/us");
+        assert_eq!(lower.canonical_path("/Caf%C3%A9"), "/caf%C3%A9");
+
+        let add = Canonicalize::new().trailing_slash(TrailingSlash::Add);
+        assert_eq!(add.canonical_path("/about"), "/about/");
+        assert_eq!(add.canonical_path("/about/"), "/about/");
+        assert_eq!(add.canonical_path("/static/style.css"), "/static/style.css");
+        assert_eq!(add.canonical_path("/"), "/");
+
+        let remove = Canonicalize::new().trailing_slash(TrailingSlash::Remove);
+        assert_eq!(remove.canonical_path("/about/"), "/about");
+        assert_eq!(remove.canonical_path("/about"), "/about");
+        assert_eq!(remove.canonical_path("/"), "/");
+    }
+
+    #[test]
+    fn test_canonical_host() {
+        let www = Canonicalize::new().domain(Domain::Www);
+        assert_eq!(www.canonical_host("example.com"), "www.example.com");
+        assert_eq!(
+            www.canonical_host("example.com:8080"),
+            "www.example.com:8080"
+        );
+        assert_eq!(www.canonical_host("WWW.example.com"), "WWW.example.com");
+        assert_eq!(www.canonical_host("localhost:8080"), "localhost:8080");
+        assert_eq!(www.canonical_host("127.0.0.1:8080"), "127.0.0.1:8080");
+        assert_eq!(www.canonical_host("[::1]:8080"), "[::1]:8080");
+
+        let apex = Canonicalize::new().domain(Domain::Apex);
+        assert_eq!(apex.canonical_host("www.example.com"), "example.com");
+        assert_eq!(apex.canonical_host("example.com"), "example.com");
+    }
+}
@@ -0,0 +1,118 @@
+//! Middleware to automatically add [ETag](https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/ETag) headers to responses.
+//! If a client sends back a matching `If-None-Match` header, a `304 Not Modified` response is sent instead of the body.
+
+use crate::{
+    middleware::{MiddleResult, Middleware},
+    response::ResponseBody,
+    HeaderType, Method, Request, Response, Status,
+};
+
+/// Middleware that hashes static response bodies to generate an `ETag` header.
+/// When the request's `If-None-Match` header matches, the response is converted to a `304 Not Modified` with no body.
+///
+/// Only successful (2xx) responses to GET requests with a static body are tagged.
+/// Streaming responses and responses that already have an `ETag` are left alone.
+/// The hash is not cryptographic, its only used to detect changes to the body.
+///
+/// ## Example
+/// ```rust
+/// # use afire::{Server, Middleware, extension::ETag};
+/// # let mut server = Server::<()>::new("localhost", 8080);
+/// ETag::new().attach(&mut server);
+/// ```
+#[derive(Debug, Default)]
+pub struct ETag {
+    /// If the ETag should be marked as weak (`W/"..."`).
+    weak: bool,
+}
+
+impl ETag {
+    /// Create a new ETag middleware.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Mark the generated ETags as weak (`W/"..."`).
+    /// Weak ETags should be used when the response may change in ways that don't matter, like if it gets compressed.
+    /// ## Example
+    /// ```
+    /// # use afire::extension::ETag;
+    /// let etag = ETag::new().weak(true);
+    /// ```
+    pub fn weak(self, weak: bool) -> Self {
+        Self { weak }
+    }
+}
+
+impl Middleware for ETag {
+    fn post(&self, req: &Request, res: &mut Response) -> MiddleResult {
+        if req.method != Method::GET
+            || !res.status.is_success()
+            || res.headers.has(HeaderType::ETag)
+        {
+            return MiddleResult::Continue;
+        }
+
+        let body = match &res.data {
+            ResponseBody::Static(i) => i,
+            ResponseBody::Stream(_) | ResponseBody::File(_) => return MiddleResult::Continue,
+        };
+
+        let tag = format!(
+            "{}\"{:016x}\"",
+            if self.weak { "W/" } else { "" },
+            fnv1a(body)
+        );
+
+        if let Some(i) = req.headers.get(HeaderType::IfNoneMatch) {
+            if matches(i, &tag) {
+                res.status = Status::NotModified;
+                res.data = ResponseBody::empty();
+                res.headers.retain(|i| {
+                    i.name != HeaderType::ContentLength && i.name != HeaderType::ContentType
+                });
+            }
+        }
+
+        res.headers.add(HeaderType::ETag, tag);
+        MiddleResult::Continue
+    }
+}
+
+/// Hashes data with the 64 bit [FNV-1a](https://en.wikipedia.org/wiki/Fowler%E2%80%93Noll%E2%80%93Vo_hash_function) hash.
+/// Unlike [`std::collections::hash_map::DefaultHasher`], this is stable across Rust versions, so ETags stay valid when the server is rebuilt.
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Checks if an `If-None-Match` header value matches an ETag.
+/// Uses the weak comparison, so `W/"a"` matches `"a"`.
+fn matches(if_none_match: &str, tag: &str) -> bool {
+    let tag = tag.trim_start_matches("W/");
+    if_none_match
+        .split(',')
+        .map(|i| i.trim())
+        .any(|i| i == "*" || i.trim_start_matches("W/") == tag)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{fnv1a, matches};
+
+    #[test]
+    fn test_fnv1a() {
+        assert_eq!(fnv1a(b""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a(b"a"), 0xaf63dc4c8601ec8c);
+    }
+
+    #[test]
+    fn test_matches() {
+        assert!(matches("\"abc\"", "\"abc\""));
+        assert!(matches("\"x\", W/\"abc\"", "\"abc\""));
+        assert!(matches("\"abc\"", "W/\"abc\""));
+        assert!(matches("*", "\"abc\""));
+        assert!(!matches("\"abcd\"", "\"abc\""));
+    }
+}
@@ -0,0 +1,119 @@
+//! Serve the site's icon on `/favicon.ico`.
+//!
+//! Browsers request `/favicon.ico` for every site they visit, so without a route for it every page load also logs a `404 Not Found`.
+
+use std::{any::type_name, fs, io, path::Path, sync::Arc, time::Duration};
+
+use crate::{
+    content_type::{get_type, TYPES},
+    header::typed::CacheControl,
+    middleware::Middleware,
+    trace::emoji,
+    HeaderType, Method, Response, Server,
+};
+
+/// Registers a route that serves an icon on `/favicon.ico`, with a `Cache-Control` header so browsers don't request it again for a day.
+///
+/// The icon doesn't have to be an ICO file, browsers also accept PNG, SVG, GIF and JPEG icons.
+/// The type of icons loaded with [`Favicon::file`] is found from their extension, otherwise it is found from the start of the data.
+/// ## Example
+/// ```rust
+/// # use afire::{Server, Middleware, extension::Favicon};
+/// # let mut server = Server::<()>::new("localhost", 8080);
+/// let icon = b"<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 16 16\"><circle cx=\"8\" cy=\"8\" r=\"8\"/></svg>";
+/// Favicon::new(&icon[..]).attach(&mut server);
+/// ```
+pub struct Favicon {
+    /// The icon.
+    data: Arc<[u8]>,
+    /// The MIME type of the icon.
+    content_type: &'static str,
+    /// How long browsers can cache the icon.
+    max_age: Duration,
+}
+
+impl Favicon {
+    /// Creates a new Favicon extension that serves `data` as the icon.
+    pub fn new(data: impl Into<Vec<u8>>) -> Self {
+        let data = data.into();
+        Self {
+            content_type: sniff(&data),
+            data: data.into(),
+            max_age: Duration::from_secs(60 * 60 * 24),
+        }
+    }
+
+    /// Creates a new Favicon extension that serves the icon at `path`.
+    /// The file is read once, when this is called.
+    /// ## Example
+    /// ```rust,no_run
+    /// # use afire::{Server, Middleware, extension::Favicon};
+    /// # let mut server = Server::<()>::new("localhost", 8080);
+    /// Favicon::file("assets/icon.png").unwrap().attach(&mut server);
+    /// ```
+    pub fn file(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let mut favicon = Self::new(fs::read(path)?);
+        if let Some(content_type) = path
+            .extension()
+            .and_then(|i| get_type(&i.to_string_lossy().to_ascii_lowercase(), &TYPES))
+        {
+            favicon.content_type = content_type;
+        }
+
+        Ok(favicon)
+    }
+
+    /// Sets how long browsers can cache the icon, by default one day.
+    pub fn max_age(self, max_age: Duration) -> Self {
+        Self { max_age, ..self }
+    }
+}
+
+/// Guesses the MIME type of an icon from its first bytes, assuming it's an ICO file if it isn't recognized.
+fn sniff(data: &[u8]) -> &'static str {
+    let start = String::from_utf8_lossy(&data[..data.len().min(256)]);
+    match data {
+        [0x89, b'P', b'N', b'G', ..] => "image/png",
+        [b'G', b'I', b'F', b'8', ..] => "image/gif",
+        [0xFF, 0xD8, 0xFF, ..] => "image/jpeg",
+        _ if start.trim_start().starts_with('<') && start.contains("<svg") => "image/svg+xml",
+        _ => "image/x-icon",
+    }
+}
+
+impl Middleware for Favicon {
+    fn attach<State>(self, server: &mut Server<State>)
+    where
+        Self: 'static + Send + Sync + Sized,
+        State: 'static + Send + Sync,
+    {
+        trace!("{}Adding Extension {}", emoji("📦"), type_name::<Self>());
+
+        let cache = CacheControl::new().public().max_age(self.max_age.as_secs());
+        server.route(Method::GET, "/favicon.ico", move |_| {
+            Response::new()
+                .shared(self.data.clone())
+                .header(HeaderType::ContentType, self.content_type)
+                .cache(cache.clone())
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::sniff;
+
+    #[test]
+    fn test_sniff() {
+        assert_eq!(sniff(b"\x89PNG\r\n\x1a\n"), "image/png");
+        assert_eq!(sniff(b"GIF89a"), "image/gif");
+        assert_eq!(sniff(b"\xFF\xD8\xFF\xE0"), "image/jpeg");
+        assert_eq!(
+            sniff(b"<?xml version=\"1.0\"?>\n<svg xmlns=\"http://www.w3.org/2000/svg\"/>"),
+            "image/svg+xml"
+        );
+        assert_eq!(sniff(b"\x00\x00\x01\x00"), "image/x-icon");
+        assert_eq!(sniff(b""), "image/x-icon");
+    }
+}
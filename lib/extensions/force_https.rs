@@ -0,0 +1,234 @@
+//! Redirect plain HTTP requests to HTTPS and enable [HTTP Strict Transport Security](https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Strict-Transport-Security).
+//!
+//! afire doesn't terminate TLS itself, so this is meant for servers behind a reverse proxy that does.
+//! The proxy tells the server which protocol the client used with the `X-Forwarded-Proto` or `Forwarded` headers, which are only used if the request came from a trusted proxy.
+
+use std::net::IpAddr;
+
+use crate::{
+    extension::RealIpResolver,
+    header::typed::{Forwarded, TypedHeader},
+    middleware::{MiddleResult, Middleware},
+    HeaderType, Method, Request, Response, Status,
+};
+
+/// Middleware that redirects requests that weren't made over HTTPS to the same URL with `https://`, and adds the `Strict-Transport-Security` header to the ones that were.
+/// `GET` and `HEAD` requests are redirected with `301 Moved Permanently`, other methods with `308 Permanent Redirect` so the method and body are kept.
+///
+/// A request is only considered secure if it came from a trusted proxy that set `X-Forwarded-Proto: https` (or `proto=https` in `Forwarded`).
+/// By default only proxies on localhost are trusted.
+///
+/// **Note**: If you also use [`RealIpResolver`], attach it before this middleware, as it replaces the proxy's address with the client's.
+/// ## Example
+/// ```rust
+/// # use afire::{Server, Middleware, extension::ForceHttps};
+/// # let mut server = Server::<()>::new("localhost", 8080);
+/// ForceHttps::new()
+///     // Trust the load balancer
+///     .trust([10, 0, 0, 5])
+///     // Have browsers use HTTPS for all subdomains too
+///     .include_subdomains(true)
+///     .attach(&mut server);
+/// ```
+pub struct ForceHttps {
+    /// The proxies trusted to set the forwarded protocol.
+    trusted: RealIpResolver,
+    /// The host to redirect to, instead of the request's `Host` header.
+    host: Option<String>,
+    /// The HTTPS port to redirect to.
+    port: u16,
+    /// The `max-age` of the `Strict-Transport-Security` header in seconds, or None to not send it.
+    max_age: Option<u64>,
+    /// If the `Strict-Transport-Security` header has the `includeSubDomains` directive.
+    include_subdomains: bool,
+    /// If the `Strict-Transport-Security` header has the `preload` directive.
+    preload: bool,
+}
+
+impl ForceHttps {
+    /// Creates a new ForceHttps middleware.
+    /// It trusts proxies on localhost, redirects to port 443 of the request's host and sends HSTS with a `max-age` of one year.
+    pub fn new() -> Self {
+        Self {
+            trusted: RealIpResolver::new(),
+            host: None,
+            port: 443,
+            max_age: Some(60 * 60 * 24 * 365),
+            include_subdomains: false,
+            preload: false,
+        }
+    }
+
+    /// Trusts a proxy with the given address to set the forwarded protocol.
+    pub fn trust(self, proxy: impl Into<IpAddr>) -> Self {
+        Self {
+            trusted: self.trusted.trust(proxy),
+            ..self
+        }
+    }
+
+    /// Trusts all proxies in the given CIDR range to set the forwarded protocol.
+    /// For example `trust_range([10, 0, 0, 0], 8)` trusts `10.0.0.0/8`.
+    pub fn trust_range(self, addr: impl Into<IpAddr>, prefix: u8) -> Self {
+        Self {
+            trusted: self.trusted.trust_range(addr, prefix),
+            ..self
+        }
+    }
+
+    /// Sets the host to redirect to.
+    /// By default the host from the request's `Host` header is used, requests without one get a `400 Bad Request`.
+    pub fn host(self, host: impl AsRef<str>) -> Self {
+        Self {
+            host: Some(host.as_ref().to_owned()),
+            ..self
+        }
+    }
+
+    /// Sets the port to redirect to, if HTTPS is served on a port other than 443.
+    pub fn port(self, port: u16) -> Self {
+        Self { port, ..self }
+    }
+
+    /// Sets how long browsers should only use HTTPS for the site, in seconds.
+    /// Use `None` to not send the `Strict-Transport-Security` header, and `Some(0)` to make browsers forget a previous policy.
+    pub fn max_age(self, max_age: Option<u64>) -> Self {
+        Self { max_age, ..self }
+    }
+
+    /// Sets if the HSTS policy also applies to all subdomains.
+    pub fn include_subdomains(self, include_subdomains: bool) -> Self {
+        Self {
+            include_subdomains,
+            ..self
+        }
+    }
+
+    /// Sets if the site consents to being added to the browser [HSTS preload list](https://hstspreload.org).
+    /// This requires a `max-age` of at least one year and `includeSubDomains`.
+    pub fn preload(self, preload: bool) -> Self {
+        Self { preload, ..self }
+    }
+
+    /// Checks if a request was made over HTTPS, according to a trusted proxy.
+    pub fn is_secure(&self, req: &Request) -> bool {
+        if !self.trusted.is_trusted(req.address.ip()) {
+            return false;
+        }
+
+        // Only the protocol the client used, added by the first proxy, matters
+        let proto = match req.headers.get(HeaderType::Forwarded) {
+            Some(i) => Forwarded::parse(i)
+                .and_then(|i| i.0.into_iter().next())
+                .and_then(|i| i.proto),
+            None => req
+                .headers
+                .get("X-Forwarded-Proto")
+                .and_then(|i| i.split(',').next())
+                .map(|i| i.trim().to_owned()),
+        };
+
+        proto.is_some_and(|i| i.eq_ignore_ascii_case("https"))
+    }
+
+    /// Gets the value of the `Strict-Transport-Security` header.
+    fn hsts(&self) -> Option<String> {
+        let mut out = format!("max-age={}", self.max_age?);
+        if self.include_subdomains {
+            out.push_str("; includeSubDomains");
+        }
+        if self.preload {
+            out.push_str("; preload");
+        }
+        Some(out)
+    }
+
+    /// Gets the HTTPS URL to redirect a request to, or None if the host is unknown.
+    fn location(&self, req: &Request) -> Option<String> {
+        let host = match &self.host {
+            Some(host) => host,
+            None => req.host()?,
+        };
+
+        // Remove the port from the host, keeping the brackets of IPv6 addresses
+        let host = match host.rsplit_once(':') {
+            Some((name, port)) if !port.contains(']') => name,
+            _ => host,
+        };
+        let port = match self.port {
+            443 => String::new(),
+            port => format!(":{port}"),
+        };
+
+        Some(format!("https://{}{}{}{}", host, port, req.path, req.query))
+    }
+}
+
+impl Middleware for ForceHttps {
+    fn pre(&self, req: &mut Request) -> MiddleResult {
+        if self.is_secure(req) {
+            return MiddleResult::Continue;
+        }
+
+        let location = match self.location(req) {
+            Some(i) => i,
+            None => {
+                return MiddleResult::Send(
+                    Response::new()
+                        .status(Status::BadRequest)
+                        .text("Missing Host header"),
+                )
+            }
+        };
+
+        let status = match req.method {
+            Method::GET | Method::HEAD => Status::MovedPermanently,
+            _ => Status::PermanentRedirect,
+        };
+        MiddleResult::Send(
+            Response::new()
+                .status(status)
+                .header(HeaderType::Location, location),
+        )
+    }
+
+    fn post(&self, req: &Request, res: &mut Response) -> MiddleResult {
+        // Browsers ignore the header when it's sent over HTTP
+        if let Some(hsts) = self.hsts().filter(|_| self.is_secure(req)) {
+            if !res.headers.has("Strict-Transport-Security") {
+                res.headers.add("Strict-Transport-Security", hsts);
+            }
+        }
+
+        MiddleResult::Continue
+    }
+}
+
+impl Default for ForceHttps {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ForceHttps;
+
+    #[test]
+    fn test_hsts() {
+        assert_eq!(
+            ForceHttps::new().hsts().as_deref(),
+            Some("max-age=31536000")
+        );
+        assert_eq!(
+            ForceHttps::new()
+                .max_age(Some(60))
+                .include_subdomains(true)
+                .preload(true)
+                .hsts()
+                .as_deref(),
+            Some("max-age=60; includeSubDomains; preload")
+        );
+        assert_eq!(ForceHttps::new().max_age(None).hsts(), None);
+    }
+}
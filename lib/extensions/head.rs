@@ -53,6 +53,7 @@ impl Middleware for Head {
         let len = match &mut res.data {
             _ if res.headers.has(HeaderType::ContentLength) => None,
             ResponseBody::Static(d) => Some(d.len()),
+            ResponseBody::File(f) => f.metadata().ok().map(|i| i.len() as usize),
             ResponseBody::Stream(s) if self.streaming => {
                 let mut buf = Vec::new();
                 s.get_mut().read_to_end(&mut buf).unwrap();
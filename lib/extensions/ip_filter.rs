@@ -0,0 +1,182 @@
+//! Allow or deny requests based on the client's IP address.
+//!
+//! Rules are CIDR ranges of IPv4 or IPv6 addresses, checked in the order they were added before the request is routed.
+//! This is useful for admin panels and internal-only endpoints.
+
+use std::net::IpAddr;
+
+use crate::{
+    encoding::url,
+    internal::common::in_range,
+    middleware::{MiddleResult, Middleware},
+    path::normalize_path,
+    Request, Response, Status,
+};
+
+type Rejection = Box<dyn Fn(&Request) -> Response + Send + Sync>;
+
+/// Middleware that allows or denies requests based on the client's IP address.
+///
+/// The first rule that matches the address decides if the request is allowed.
+/// Addresses that don't match any rule are denied if there are any allow rules, and allowed otherwise.
+/// Denied requests get a `403 Forbidden` response by default, which can be changed with [`IpFilter::response`].
+///
+/// The address of the connection is used, so when behind a reverse proxy attach the [`crate::extension::RealIpResolver`] after this middleware so it runs first.
+/// IPv4 addresses mapped to IPv6 (like `::ffff:10.0.0.1`) are checked as IPv4 addresses.
+/// ## Example
+/// ```rust
+/// # use afire::{Server, Middleware, extension::IpFilter};
+/// # let mut server = Server::<()>::new("localhost", 8080);
+/// // Only allow the local network to access the admin panel
+/// IpFilter::new()
+///     .path("/admin")
+///     .deny([192, 168, 1, 1], 32)
+///     .allow([192, 168, 1, 0], 24)
+///     .allow([127, 0, 0, 1], 32)
+///     .attach(&mut server);
+/// ```
+pub struct IpFilter {
+    /// The rules, as if the range is allowed, the address and the prefix length.
+    rules: Vec<(bool, IpAddr, u8)>,
+    /// The path prefix the filter applies to.
+    path: String,
+    /// Makes the response sent to denied requests.
+    response: Rejection,
+}
+
+impl IpFilter {
+    /// Creates a new IpFilter with no rules, which applies to every path.
+    pub fn new() -> Self {
+        Self {
+            rules: Vec::new(),
+            path: String::new(),
+            response: Box::new(|_| Response::new().status(Status::Forbidden).text("Forbidden")),
+        }
+    }
+
+    /// Allows addresses in the given CIDR range.
+    /// For example `allow([10, 0, 0, 0], 8)` allows `10.0.0.0/8`.
+    pub fn allow(mut self, addr: impl Into<IpAddr>, prefix: u8) -> Self {
+        self.rules.push((true, addr.into(), prefix));
+        self
+    }
+
+    /// Denies addresses in the given CIDR range.
+    /// For example `deny([0; 16], 0)` denies every IPv6 address.
+    pub fn deny(mut self, addr: impl Into<IpAddr>, prefix: u8) -> Self {
+        self.rules.push((false, addr.into(), prefix));
+        self
+    }
+
+    /// Only filters requests to paths starting with this prefix.
+    /// By default every request is filtered.
+    pub fn path(self, path: impl AsRef<str>) -> Self {
+        Self {
+            path: normalize_path(path.as_ref().to_owned()),
+            ..self
+        }
+    }
+
+    /// Sets the function that makes the response sent to denied requests.
+    /// ## Example
+    /// ```rust
+    /// # use afire::{Response, Status, extension::IpFilter};
+    /// IpFilter::new()
+    ///     .allow([10, 0, 0, 0], 8)
+    ///     .response(|_req| Response::new().status(Status::NotFound).text("Not Found"));
+    /// ```
+    pub fn response(self, response: impl Fn(&Request) -> Response + Send + Sync + 'static) -> Self {
+        Self {
+            response: Box::new(response),
+            ..self
+        }
+    }
+
+    /// Checks if an address is allowed by the rules.
+    pub fn is_allowed(&self, ip: IpAddr) -> bool {
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+            IpAddr::V4(_) => ip,
+        };
+
+        match self
+            .rules
+            .iter()
+            .find(|(_, range, prefix)| in_range(ip, *range, *prefix))
+        {
+            Some((allow, ..)) => *allow,
+            None => !self.rules.iter().any(|(allow, ..)| *allow),
+        }
+    }
+
+    /// Checks if a path is filtered, it has to be the prefix or under it.
+    /// The segments are percent decoded first, like when routing, so encoding a character can't be used to get around the filter.
+    fn applies(&self, path: &str) -> bool {
+        let path = normalize_path(path.to_owned());
+        let mut segments = path
+            .split('/')
+            .map(|i| url::decode_path(i).unwrap_or_else(|| i.to_owned()));
+        self.path
+            .split('/')
+            .filter(|i| !i.is_empty())
+            .all(|i| segments.next().as_deref() == Some(i))
+    }
+}
+
+impl Middleware for IpFilter {
+    fn pre(&self, req: &mut Request) -> MiddleResult {
+        if !self.applies(&req.path) || self.is_allowed(req.address.ip()) {
+            return MiddleResult::Continue;
+        }
+
+        MiddleResult::Send((self.response)(req))
+    }
+}
+
+impl Default for IpFilter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+    use super::IpFilter;
+
+    const LOCAL: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 20));
+    const ROUTER: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1));
+    const REMOTE: IpAddr = IpAddr::V4(Ipv4Addr::new(203, 0, 113, 7));
+
+    #[test]
+    fn test_rules() {
+        let filter = IpFilter::new()
+            .deny(ROUTER, 32)
+            .allow([192, 168, 0, 0], 16)
+            .allow(Ipv6Addr::LOCALHOST, 128);
+
+        assert!(filter.is_allowed(LOCAL));
+        assert!(!filter.is_allowed(ROUTER));
+        assert!(!filter.is_allowed(REMOTE));
+        assert!(filter.is_allowed(Ipv6Addr::LOCALHOST.into()));
+        assert!(filter.is_allowed(Ipv4Addr::new(192, 168, 1, 20).to_ipv6_mapped().into()));
+
+        // With only deny rules, everything else is allowed
+        let filter = IpFilter::new().deny([203, 0, 113, 0], 24);
+        assert!(filter.is_allowed(LOCAL));
+        assert!(!filter.is_allowed(REMOTE));
+        assert!(IpFilter::new().is_allowed(REMOTE));
+    }
+
+    #[test]
+    fn test_applies() {
+        let filter = IpFilter::new().path("/admin/");
+        assert!(filter.applies("/admin"));
+        assert!(filter.applies("/admin/users"));
+        assert!(!filter.applies("/administrator"));
+        assert!(!filter.applies("/"));
+        assert!(filter.applies("/%61dmin/users"));
+        assert!(IpFilter::new().applies("/anything"));
+    }
+}
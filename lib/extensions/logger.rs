@@ -1,12 +1,49 @@
 //! Log requests to the console or a file.
+//!
+//! By default requests are logged based on the [`Level`], but you can also use a custom format with [`Logger::format`] or [`Logger::formatter`].
+//! The [`COMMON_LOG_FORMAT`] and [`COMBINED_LOG_FORMAT`] presets can be used to output logs that existing log analyzers understand.
+//! For log aggregators like Loki or Elasticsearch, [`Logger::json`] outputs one JSON object per line.
+//!
+//! When logging to a file, it can be rotated daily or when it reaches a certain size with [`Logger::rotate`].
 
 // If file logging is enabled
-use std::fs::{File, OpenOptions};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
 use std::io::{self, prelude::*};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::Mutex;
+use std::time::{Duration, Instant, UNIX_EPOCH};
 
-use crate::{extension::RealIp, HeaderType, Middleware, Request, Response};
+use crate::{
+    extension::{
+        date::{clf_date, rfc3339_date},
+        request_id::Id,
+        RealIp,
+    },
+    internal::common::{epoch, json_string},
+    middleware::MiddleResult,
+    response::ResponseBody,
+    HeaderType, Middleware, Request, Response,
+};
+
+/// The [Apache Common Log Format](https://httpd.apache.org/docs/current/logs.html#common).
+/// Example: `127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] "GET /apache_pb.gif HTTP/1.0" 200 2326`
+pub const COMMON_LOG_FORMAT: &str =
+    "{ip} - - [{time}] \"{method} {path}{query} {version}\" {status} {bytes}";
+
+/// The [Apache Combined Log Format](https://httpd.apache.org/docs/current/logs.html#combined).
+/// This is the Common Log Format with the referer and user agent added.
+pub const COMBINED_LOG_FORMAT: &str = "{ip} - - [{time}] \"{method} {path}{query} {version}\" {status} {bytes} \"{referer}\" \"{user_agent}\"";
+
+type Formatter = Box<dyn Fn(&Request, &Response, Duration) -> String + Send + Sync>;
+
+thread_local! {
+    /// When the request currently being handled on this thread was received by each logger.
+    /// Requests are handled from start to end on a single thread, so this is set in `pre` and read in `end`.
+    static STARTED: RefCell<HashMap<usize, Instant>> = RefCell::new(HashMap::new());
+}
 
 /// Define Log Levels
 #[derive(Debug)]
@@ -22,17 +59,54 @@ pub enum Level {
     Info,
 }
 
+/// When to rotate the log file.
+/// Rotated files are renamed, and a new file is created at the original path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rotation {
+    /// Rotate the file at midnight (UTC).
+    /// Old files get the date they were written appended, like `access.log.2024-05-01`.
+    Daily,
+
+    /// Rotate the file before it would grow past this many bytes.
+    /// Old files get a number appended, with `access.log.1` being the newest.
+    Size(u64),
+}
+
+/// How log lines are formatted.
+enum Format {
+    /// Use the log [`Level`].
+    Level,
+    /// A format string with placeholders.
+    Template(String),
+    /// A function that formats the log line.
+    Custom(Formatter),
+    /// One JSON object per line.
+    Json,
+}
+
 /// Log requests to the console or a file.
 #[derive(Debug)]
 pub struct Logger {
     /// What level of logs to show
     level: Level,
 
+    /// How to format log lines
+    format: Format,
+
+    /// The header to get the request id from for JSON logs
+    request_id: HeaderType,
+
     /// What header to use to get the clients actual IP
     real_ip: Option<HeaderType>,
 
     /// Optional file to write logs to
-    file: Option<Mutex<File>>,
+    file: Option<Mutex<LogFile>>,
+
+    /// When to rotate the log file
+    rotation: Option<Rotation>,
+
+    /// How many rotated log files to keep
+    max_files: Option<usize>,
 
     /// If logs should also be printed to stdout
     console: bool,
@@ -59,8 +133,12 @@ impl Logger {
     pub fn new() -> Logger {
         Logger {
             level: Level::Info,
+            format: Format::Level,
+            request_id: HeaderType::Custom("X-Request-Id".to_owned()),
             real_ip: None,
             file: None,
+            rotation: None,
+            max_files: None,
             console: true,
         }
     }
@@ -79,6 +157,111 @@ impl Logger {
         Self { level, ..self }
     }
 
+    /// Set a format string for log lines, this overrides the log [`Level`].
+    /// The following placeholders are replaced with info about the request and response, unknown ones are left as is.
+    ///
+    /// | Placeholder    | Value                                                        |
+    /// | -------------- | ------------------------------------------------------------ |
+    /// | `{ip}`         | The client's IP address                                      |
+    /// | `{local}`      | The server address the request was received on, or `-`       |
+    /// | `{time}`       | The current time, like `10/Oct/2000:13:55:36 +0000`          |
+    /// | `{method}`     | The request method                                           |
+    /// | `{path}`       | The request path                                             |
+    /// | `{query}`      | The query string, including the `?` if not empty             |
+    /// | `{version}`    | The HTTP version, like `HTTP/1.1`                            |
+    /// | `{status}`     | The response status code                                     |
+    /// | `{bytes}`      | The size of the response body, or `-` if empty or streamed   |
+    /// | `{latency}`    | How long the request took to handle in milliseconds          |
+    /// | `{latency_us}` | How long the request took to handle in microseconds          |
+    /// | `{referer}`    | The `Referer` header, or `-`                                 |
+    /// | `{user_agent}` | The `User-Agent` header, or `-`                              |
+    /// | `{request_id}` | The request id (see [`Logger::request_id`]), or `-`          |
+    ///
+    /// ## Example
+    /// ```rust
+    /// // Import Lib
+    /// use afire::extension::logger::{self, Logger};
+    ///
+    /// // Log in the Apache Combined Log Format
+    /// let logger = Logger::new()
+    ///     .format(logger::COMBINED_LOG_FORMAT);
+    ///
+    /// // Or use a custom format
+    /// let logger = Logger::new()
+    ///     .format("{method} {path} -> {status}");
+    /// ```
+    pub fn format(self, format: impl Into<String>) -> Self {
+        Self {
+            format: Format::Template(format.into()),
+            ..self
+        }
+    }
+
+    /// Set a function to format log lines, this overrides the log [`Level`].
+    /// The function is given the request, the response and how long it took to handle the request.
+    /// ## Example
+    /// ```rust
+    /// // Import Lib
+    /// use afire::extension::logger::Logger;
+    ///
+    /// // Create a new logger with a custom formatter
+    /// let logger = Logger::new()
+    ///     .formatter(|req, res, time| format!("{} {} {} {:?}", req.method, req.path, res.status.code(), time));
+    /// ```
+    pub fn formatter(
+        self,
+        formatter: impl Fn(&Request, &Response, Duration) -> String + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            format: Format::Custom(Box::new(formatter)),
+            ..self
+        }
+    }
+
+    /// Log one JSON object per line, this overrides the log [`Level`].
+    /// Each object has the following fields:
+    /// `timestamp` (RFC 3339), `method`, `path`, `query`, `status`, `bytes` (null if streamed), `duration_ms`, `ip`, `user_agent` and `request_id` (null if missing).
+    /// If the route or a middleware panicked, a `panic` object is added with the `message`, `route` and `backtrace` (both null if unknown).
+    /// With the other formats the panic is logged on its own line after the request.
+    ///
+    /// The request id is taken from the [`crate::extension::RequestId`] middleware if it is attached, otherwise it is read from the `X-Request-Id` header by default, this can be changed with [`Logger::request_id`].
+    /// ## Example
+    /// ```rust
+    /// // Import Lib
+    /// use afire::extension::logger::Logger;
+    ///
+    /// // Create a new logger with JSON output
+    /// let logger = Logger::new()
+    ///     .json();
+    /// ```
+    pub fn json(self) -> Self {
+        Self {
+            format: Format::Json,
+            ..self
+        }
+    }
+
+    /// Set the header the request id is read from, if the [`crate::extension::RequestId`] middleware isn't attached.
+    /// This is useful when the id is set by a reverse proxy.
+    /// ## Example
+    /// ```rust
+    /// // Import Lib
+    /// use afire::{Server, Middleware, extension::logger::Logger};
+    ///
+    /// // Log the id set by the reverse proxy
+    /// let mut server = Server::<()>::new("localhost", 8080);
+    /// Logger::new()
+    ///     .json()
+    ///     .request_id("X-Amzn-Trace-Id")
+    ///     .attach(&mut server);
+    /// ```
+    pub fn request_id(self, header: impl Into<HeaderType>) -> Self {
+        Self {
+            request_id: header.into(),
+            ..self
+        }
+    }
+
     /// Uses the [`crate::extension::RealIp`] extension for log IPs.
     /// You will need to supply the header that will contain the IP address, for example the [X-Forwarded-For header](https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/X-Forwarded-For) ([`HeaderType::XForwardedFor`])
     ///
@@ -104,17 +287,42 @@ impl Logger {
     /// ```
     pub fn file(self, file: impl AsRef<Path>) -> io::Result<Self> {
         Ok(Self {
-            file: Some(Mutex::new(
-                OpenOptions::new()
-                    .create(true)
-                    .write(true)
-                    .append(true)
-                    .open(file)?,
-            )),
+            file: Some(Mutex::new(LogFile::open(file.as_ref().to_path_buf())?)),
             ..self
         })
     }
 
+    /// Set when the log file should be rotated.
+    /// By default the log file is never rotated.
+    /// ## Example
+    /// ```rust
+    /// // Import Lib
+    /// use afire::extension::logger::{Logger, Rotation};
+    ///
+    /// // Create a new logger that starts a new file every day and keeps a week of logs
+    /// # fn run() {
+    /// let logger = Logger::new()
+    ///     .rotate(Rotation::Daily)
+    ///     .max_files(7)
+    ///     .file("access.log");
+    /// # }
+    /// ```
+    pub fn rotate(self, rotation: Rotation) -> Self {
+        Self {
+            rotation: Some(rotation),
+            ..self
+        }
+    }
+
+    /// Set the maximum number of rotated log files to keep, the oldest ones are deleted.
+    /// By default all rotated files are kept.
+    pub fn max_files(self, max_files: usize) -> Self {
+        Self {
+            max_files: Some(max_files),
+            ..self
+        }
+    }
+
     /// Enable writing events to stdout
     /// ## Example
     /// ```rust
@@ -130,11 +338,86 @@ impl Logger {
     }
 
     /// Take a request and log it
-    fn log(&self, req: &Request) {
+    fn log(&self, req: &Request, res: &Response, latency: Duration) {
         let ip = match &self.real_ip {
             Some(i) => req.real_ip_header(i),
             None => req.address.ip(),
         };
+        let bytes = match &res.data {
+            ResponseBody::Static(i) if !i.is_empty() => i.len().to_string(),
+            ResponseBody::File(i) => i
+                .metadata()
+                .map(|i| i.len().to_string())
+                .unwrap_or_else(|_| "-".to_owned()),
+            _ => "-".to_owned(),
+        };
+        let ms = latency.as_secs_f64() * 1000.0;
+
+        match &self.format {
+            Format::Level => {}
+            Format::Template(template) => {
+                return self.send_log(
+                    req,
+                    render(template, |key| {
+                        Some(match key {
+                            "ip" => ip.to_string(),
+                            "local" => req
+                                .local_address
+                                .map(|i| i.to_string())
+                                .unwrap_or_else(|| "-".to_owned()),
+                            "time" => clf_date(req.clock().epoch().as_secs()),
+                            "method" => req.method.to_string(),
+                            "path" => req.path.to_owned(),
+                            "query" => req.query.to_string(),
+                            "version" => req.version.to_owned(),
+                            "status" => res.status.code().to_string(),
+                            "bytes" => bytes.to_owned(),
+                            "latency" => format!("{ms:.2}"),
+                            "latency_us" => latency.as_micros().to_string(),
+                            "referer" => req
+                                .headers
+                                .get(HeaderType::Referer)
+                                .unwrap_or("-")
+                                .to_owned(),
+                            "request_id" => self.get_request_id(req).unwrap_or("-").to_owned(),
+                            "user_agent" => req
+                                .headers
+                                .get(HeaderType::UserAgent)
+                                .unwrap_or("-")
+                                .to_owned(),
+                            _ => return None,
+                        })
+                    }),
+                )
+            }
+            Format::Custom(formatter) => return self.send_log(req, formatter(req, res, latency)),
+            Format::Json => {
+                let optional = |value: Option<&str>| value.map(json_string);
+                let panic = res.panic().map(|panic| {
+                    format!(
+                        ",\"panic\":{{\"message\":{},\"route\":{},\"backtrace\":{}}}",
+                        json_string(&panic.message),
+                        optional(panic.route.as_ref().map(|i| i.path.as_str()))
+                            .unwrap_or_else(|| "null".to_owned()),
+                        optional(panic.backtrace.as_ref().map(|i| i.to_string()).as_deref())
+                            .unwrap_or_else(|| "null".to_owned()),
+                    )
+                });
+                return self.send_log(req, format!(
+                    "{{\"timestamp\":{},\"method\":{},\"path\":{},\"query\":{},\"status\":{},\"bytes\":{},\"duration_ms\":{ms:.3},\"ip\":{},\"user_agent\":{},\"request_id\":{}{}}}",
+                    json_string(&rfc3339_date(req.clock().epoch().as_secs())),
+                    json_string(&req.method.to_string()),
+                    json_string(&req.path),
+                    json_string(&req.query.to_string()),
+                    res.status.code(),
+                    if bytes == "-" { "null" } else { &bytes },
+                    json_string(&ip.to_string()),
+                    optional(req.headers.get(HeaderType::UserAgent)).unwrap_or_else(|| "null".to_owned()),
+                    optional(self.get_request_id(req)).unwrap_or_else(|| "null".to_owned()),
+                    panic.unwrap_or_default(),
+                ));
+            }
+        }
 
         match self.level {
             // Add Headers and Body to this one
@@ -162,14 +445,18 @@ impl Logger {
                     new_path = "/".to_string();
                 }
 
-                self.send_log(format!(
-                    "[{ip}] {} {} [{}] ({}) {{{}}}",
-                    req.method,
-                    new_path,
-                    query,
-                    headers,
-                    String::from_utf8_lossy(&req.body).replace('\n', "\\n")
-                ))
+                self.send_log(
+                    req,
+                    format!(
+                        "[{ip}] {} {} [{}] ({}) {{{}}} -> {} {bytes}B {ms:.2}ms",
+                        req.method,
+                        new_path,
+                        query,
+                        headers,
+                        String::from_utf8_lossy(&req.body).replace('\n', "\\n"),
+                        res.status.code()
+                    ),
+                )
             }
 
             Level::Info => {
@@ -178,19 +465,41 @@ impl Logger {
                     new_path = "/".to_string();
                 }
 
-                self.send_log(format!("[{ip}] {} {}{}", req.method, new_path, req.query))
+                self.send_log(
+                    req,
+                    format!(
+                        "[{ip}] {} {}{} -> {} {bytes}B {ms:.2}ms",
+                        req.method,
+                        new_path,
+                        req.query,
+                        res.status.code()
+                    ),
+                )
             }
         }
     }
 
+    /// Gets the id of a request, from the [`crate::extension::RequestId`] middleware or the request id header.
+    fn get_request_id<'a>(&self, req: &'a Request) -> Option<&'a str> {
+        match req.extensions.get::<Id>() {
+            Some(Id(id)) => Some(id),
+            None => req.headers.get(&self.request_id),
+        }
+    }
+
     /// Send log data to file / stdout
-    fn send_log(&self, data: String) {
+    fn send_log(&self, req: &Request, data: String) {
         if self.console {
             println!("{data}");
         }
 
         if let Some(i) = &self.file {
-            if let Err(e) = writeln!(i.lock().unwrap(), "{data}") {
+            let now = req.clock().epoch().as_secs();
+            if let Err(e) = i
+                .lock()
+                .unwrap()
+                .write(&data, now, self.rotation, self.max_files)
+            {
                 eprintln!("[-] Erm... Error writhing to log file: {e}")
             }
         }
@@ -198,9 +507,200 @@ impl Logger {
 }
 
 impl Middleware for Logger {
-    fn end(&self, req: &Request, _res: &Response) {
-        self.log(req);
+    fn pre(&self, req: &mut Request) -> MiddleResult {
+        let id = self as *const Logger as usize;
+        let now = req.clock().instant();
+        STARTED.with(|i| i.borrow_mut().insert(id, now));
+        MiddleResult::Continue
+    }
+
+    fn end(&self, req: &Request, res: &Response) {
+        // If a middleware that ran before this one sent a response, `pre` was never called
+        let id = self as *const Logger as usize;
+        let latency = STARTED
+            .with(|i| i.borrow_mut().remove(&id))
+            .map(|i| req.clock().instant().saturating_duration_since(i))
+            .unwrap_or_default();
+        self.log(req, res, latency);
+
+        if let Some(panic) = res.panic().filter(|_| !matches!(self.format, Format::Json)) {
+            let route = panic.route.as_ref().map_or("middleware", |i| &i.path);
+            let mut log = format!(
+                "[!] {} {} panicked in {}: {}",
+                req.method, req.path, route, panic
+            );
+            if let Some(backtrace) = &panic.backtrace {
+                log.push_str(&format!("\n{backtrace}"));
+            }
+            self.send_log(req, log);
+        }
+    }
+}
+
+/// A log file that can be rotated.
+#[derive(Debug)]
+struct LogFile {
+    /// The path of the current log file.
+    path: PathBuf,
+    /// The open log file.
+    file: File,
+    /// The current size of the file in bytes.
+    size: u64,
+    /// The day (since the unix epoch) the file was last written to.
+    day: u64,
+}
+
+impl LogFile {
+    /// Opens the log file in append mode, creating it if it doesn't exist.
+    fn open(path: PathBuf) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let meta = file.metadata()?;
+        let modified = meta
+            .modified()
+            .ok()
+            .and_then(|i| i.duration_since(UNIX_EPOCH).ok())
+            .unwrap_or_else(epoch);
+
+        Ok(Self {
+            path,
+            file,
+            size: meta.len(),
+            day: modified.as_secs() / 86400,
+        })
+    }
+
+    /// Writes a line to the file, rotating it first if needed.
+    fn write(
+        &mut self,
+        line: &str,
+        now: u64,
+        rotation: Option<Rotation>,
+        max_files: Option<usize>,
+    ) -> io::Result<()> {
+        let len = line.len() as u64 + 1;
+        let day = now / 86400;
+        let rotate = match rotation {
+            Some(Rotation::Daily) => day != self.day && self.size > 0,
+            Some(Rotation::Size(max)) => self.size + len > max && self.size > 0,
+            None => false,
+        };
+
+        if let (true, Some(rotation)) = (rotate, rotation) {
+            self.rotate(rotation, max_files)?;
+        }
+
+        writeln!(self.file, "{line}")?;
+        self.size += len;
+        self.day = day;
+        Ok(())
+    }
+
+    /// Renames the current file, deletes old files past `max_files` and opens a new file.
+    fn rotate(&mut self, rotation: Rotation, max_files: Option<usize>) -> io::Result<()> {
+        match rotation {
+            Rotation::Daily => {
+                let date = &rfc3339_date(self.day * 86400)[..10];
+                fs::rename(&self.path, self.rotated(date))?;
+
+                if let Some(max) = max_files {
+                    let mut old = self.rotated_files()?;
+                    old.sort();
+                    for i in old.iter().rev().skip(max) {
+                        fs::remove_file(i)?;
+                    }
+                }
+            }
+            Rotation::Size(_) => {
+                let mut last = 0;
+                while self.rotated(&(last + 1).to_string()).exists() {
+                    last += 1;
+                }
+
+                for i in (1..=last).rev() {
+                    let from = self.rotated(&i.to_string());
+                    match max_files {
+                        Some(max) if i >= max => fs::remove_file(from)?,
+                        _ => fs::rename(from, self.rotated(&(i + 1).to_string()))?,
+                    }
+                }
+
+                match max_files {
+                    Some(0) => fs::remove_file(&self.path)?,
+                    _ => fs::rename(&self.path, self.rotated("1"))?,
+                }
+            }
+        }
+
+        *self = Self::open(self.path.clone())?;
+        Ok(())
+    }
+
+    /// Gets the path of a rotated file with the given suffix.
+    fn rotated(&self, suffix: &str) -> PathBuf {
+        let mut name = self.path.file_name().unwrap_or_default().to_owned();
+        name.push(".");
+        name.push(suffix);
+        self.path.with_file_name(name)
+    }
+
+    /// Lists all rotated files of this log file.
+    fn rotated_files(&self) -> io::Result<Vec<PathBuf>> {
+        let prefix = self.rotated("");
+        let prefix = prefix.file_name().unwrap_or_default().to_string_lossy();
+        let dir = match self.path.parent() {
+            Some(i) if !i.as_os_str().is_empty() => i,
+            _ => Path::new("."),
+        };
+
+        let mut out = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path
+                .file_name()
+                .is_some_and(|i| i.to_string_lossy().starts_with(&*prefix))
+            {
+                out.push(path);
+            }
+        }
+
+        Ok(out)
+    }
+}
+
+impl fmt::Debug for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Format::Level => f.write_str("Level"),
+            Format::Template(i) => f.debug_tuple("Template").field(i).finish(),
+            Format::Custom(_) => f.write_str("Custom"),
+            Format::Json => f.write_str("Json"),
+        }
+    }
+}
+
+/// Replaces the `{name}` placeholders in a template with the value returned by `value`.
+/// If `value` returns None, the placeholder is left as is.
+fn render(template: &str, value: impl Fn(&str) -> Option<String>) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let Some(end) = rest.find('}') else {
+            break;
+        };
+
+        match value(&rest[1..end]) {
+            Some(i) => out.push_str(&i),
+            None => out.push_str(&rest[..=end]),
+        }
+        rest = &rest[end + 1..];
     }
+
+    out.push_str(rest);
+    out
 }
 
 // Impl Default for Response
@@ -209,3 +709,71 @@ impl Default for Logger {
         Logger::new()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::{env, fs};
+
+    use super::{render, LogFile, Rotation};
+
+    #[test]
+    fn test_render() {
+        let value = |key: &str| match key {
+            "method" => Some("GET".to_owned()),
+            "path" => Some("/".to_owned()),
+            _ => None,
+        };
+
+        assert_eq!(render("{method} {path}", value), "GET /");
+        assert_eq!(render("[{unknown}] {path}", value), "[{unknown}] /");
+        assert_eq!(render("{path} {", value), "/ {");
+        assert_eq!(render("no placeholders", value), "no placeholders");
+    }
+
+    #[test]
+    fn test_rotate_size() {
+        let dir = env::temp_dir().join(format!("afire-log-size-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("access.log");
+
+        let mut file = LogFile::open(path.clone()).unwrap();
+        for i in 0..4 {
+            file.write(&format!("line {i}"), 0, Some(Rotation::Size(10)), Some(2))
+                .unwrap();
+        }
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "line 3\n");
+        assert_eq!(
+            fs::read_to_string(dir.join("access.log.1")).unwrap(),
+            "line 2\n"
+        );
+        assert_eq!(
+            fs::read_to_string(dir.join("access.log.2")).unwrap(),
+            "line 1\n"
+        );
+        assert!(!dir.join("access.log.3").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_rotate_daily() {
+        let dir = env::temp_dir().join(format!("afire-log-daily-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("access.log");
+
+        let mut file = LogFile::open(path.clone()).unwrap();
+        for day in 0..4 {
+            let now = 1714521600 + day * 86400;
+            file.write("a", now, Some(Rotation::Daily), Some(2))
+                .unwrap();
+            file.write("b", now + 60, Some(Rotation::Daily), Some(2))
+                .unwrap();
+        }
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "a\nb\n");
+        assert!(!dir.join("access.log.2024-05-01").exists());
+        assert!(dir.join("access.log.2024-05-02").exists());
+        assert!(dir.join("access.log.2024-05-03").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,174 @@
+//! Let clients that can only send `GET` and `POST` requests, like HTML forms, use other methods.
+//!
+//! The method of a `POST` request is replaced with the one from a `_method` form field or the `X-HTTP-Method-Override` header before the request is routed.
+
+use std::str::FromStr;
+
+use crate::{
+    header::Headers,
+    middleware::{MiddleResult, Middleware},
+    HeaderType, Method, Query, Request,
+};
+
+/// Middleware that overrides the method of `POST` requests.
+///
+/// The new method is taken from the `X-HTTP-Method-Override` header, or the `_method` field of `application/x-www-form-urlencoded` bodies.
+/// Only methods in the allowlist are used, by default `PUT`, `PATCH` and `DELETE`.
+/// Overriding to a safe method like `GET` isn't allowed by default, as it could bypass CSRF protection that only checks unsafe methods.
+///
+/// Middleware run in the reverse order they were attached, so attach this after other middleware for them to see the new method.
+/// ## Example
+/// ```rust
+/// # use afire::{Server, Middleware, Method, Response, extension::MethodOverride};
+/// # let mut server = Server::<()>::new("localhost", 8080);
+/// // <form method="POST" action="/posts/5">
+/// //     <input type="hidden" name="_method" value="DELETE">
+/// //     <button>Delete</button>
+/// // </form>
+/// server.route(Method::DELETE, "/posts/{id}", |req| {
+///     Response::new().text(format!("Deleted post {}", req.param("id").unwrap()))
+/// });
+///
+/// MethodOverride::new().attach(&mut server);
+/// ```
+pub struct MethodOverride {
+    /// The methods requests can be changed to.
+    allowed: Vec<Method>,
+    /// The header the method is read from.
+    header: Option<HeaderType>,
+    /// The form field the method is read from.
+    field: Option<String>,
+}
+
+impl MethodOverride {
+    /// Creates a new MethodOverride that allows `PUT`, `PATCH` and `DELETE`, from the `X-HTTP-Method-Override` header and `_method` form field.
+    pub fn new() -> Self {
+        Self {
+            allowed: vec![Method::PUT, Method::PATCH, Method::DELETE],
+            header: Some(HeaderType::Custom("X-HTTP-Method-Override".to_owned())),
+            field: Some("_method".to_owned()),
+        }
+    }
+
+    /// Sets the methods requests can be changed to, replacing the defaults.
+    /// ## Example
+    /// ```rust
+    /// # use afire::{Method, extension::MethodOverride};
+    /// MethodOverride::new().allow([Method::DELETE, Method::Custom("PURGE".to_owned())]);
+    /// ```
+    pub fn allow(self, methods: impl IntoIterator<Item = Method>) -> Self {
+        Self {
+            allowed: methods.into_iter().collect(),
+            ..self
+        }
+    }
+
+    /// Sets the header the method is read from, or None to ignore headers.
+    pub fn header(self, header: Option<impl Into<HeaderType>>) -> Self {
+        Self {
+            header: header.map(Into::into),
+            ..self
+        }
+    }
+
+    /// Sets the form field the method is read from, or None to ignore form bodies.
+    pub fn field(self, field: Option<impl AsRef<str>>) -> Self {
+        Self {
+            field: field.map(|i| i.as_ref().to_owned()),
+            ..self
+        }
+    }
+
+    /// Gets the method a request should be changed to, if it asks for an allowed one.
+    fn method(&self, method: &Method, headers: &Headers, body: &[u8]) -> Option<Method> {
+        if *method != Method::POST {
+            return None;
+        }
+
+        let header = self.header.as_ref().and_then(|i| headers.get(i));
+        let method = match header {
+            Some(i) => i.to_owned(),
+            None => {
+                let field = self.field.as_ref()?;
+                let form = headers
+                    .get(HeaderType::ContentType)
+                    .and_then(|i| i.split(';').next())
+                    .is_some_and(|i| {
+                        i.trim()
+                            .eq_ignore_ascii_case("application/x-www-form-urlencoded")
+                    });
+                if !form {
+                    return None;
+                }
+
+                Query::from_body(&String::from_utf8_lossy(body))
+                    .get(field)?
+                    .to_owned()
+            }
+        };
+
+        Method::from_str(method.trim())
+            .ok()
+            .filter(|i| self.allowed.contains(i))
+    }
+}
+
+impl Middleware for MethodOverride {
+    fn pre(&self, req: &mut Request) -> MiddleResult {
+        if let Some(method) = self.method(&req.method, &req.headers, &req.body) {
+            req.method = method;
+        }
+
+        MiddleResult::Continue
+    }
+}
+
+impl Default for MethodOverride {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::MethodOverride;
+    use crate::{header::Headers, Header, Method};
+
+    const FORM: &str = "application/x-www-form-urlencoded";
+
+    #[test]
+    fn test_override() {
+        let over = MethodOverride::new();
+        let headers = Headers(vec![Header::new("X-HTTP-Method-Override", "patch")]);
+        assert_eq!(
+            over.method(&Method::POST, &headers, b""),
+            Some(Method::PATCH)
+        );
+        assert_eq!(over.method(&Method::GET, &headers, b""), None);
+
+        let headers = Headers(vec![Header::new("Content-Type", FORM)]);
+        let body = b"title=Hi&_method=DELETE";
+        assert_eq!(
+            over.method(&Method::POST, &headers, body),
+            Some(Method::DELETE)
+        );
+        assert_eq!(over.method(&Method::POST, &Headers::default(), body), None);
+    }
+
+    #[test]
+    fn test_allowlist() {
+        let headers = Headers(vec![Header::new("X-HTTP-Method-Override", "GET")]);
+        assert_eq!(
+            MethodOverride::new().method(&Method::POST, &headers, b""),
+            None
+        );
+
+        let over = MethodOverride::new()
+            .allow([Method::GET])
+            .field(None::<&str>);
+        assert_eq!(over.method(&Method::POST, &headers, b""), Some(Method::GET));
+
+        let headers = Headers(vec![Header::new("Content-Type", FORM)]);
+        assert_eq!(over.method(&Method::POST, &headers, b"_method=GET"), None);
+    }
+}
@@ -1,8 +1,25 @@
+pub mod acme_challenge;
+pub mod canonicalize;
 pub mod date;
+pub mod etag;
+pub mod favicon;
+pub mod force_https;
 pub mod head;
+pub mod ip_filter;
 pub mod logger;
+pub mod method_override;
+pub mod openapi;
+pub mod path_rewrite;
 pub mod ratelimit;
 pub mod real_ip;
+pub mod recorder;
 pub mod request_id;
+pub mod robots_txt;
+pub mod route_debug;
 pub mod serve_static;
+pub mod throttle;
+pub mod timeout;
 pub mod trace;
+pub mod trace_context;
+pub mod upload;
+pub mod webdav;
@@ -0,0 +1,675 @@
+//! Generates an [OpenAPI 3](https://spec.openapis.org/oas/v3.0.3) spec from the server's routes.
+//!
+//! Every route with a standard method and no wildcard segments is included in the spec, with its path params.
+//! Path params are documented as strings, unless they have a type constraint like `{id:u32}`.
+//! Routes can be documented further by attaching [`RouteDocs`] with [`crate::Server::route_docs`].
+//! The `summary`, `description` and `tag` keys of [`crate::Server::route_meta`] are also used, for routes that don't set them in their docs.
+//! Types used in request and response bodies can describe themselves by implementing [`ToSchema`].
+
+use std::{any::type_name, collections::BTreeMap};
+
+use crate::{
+    internal::common::{json_string, xml_escape},
+    middleware::{MiddleResult, Middleware},
+    path::normalize_path,
+    trace::emoji,
+    Content, Method, Request, Response, RouteInfo, Server, Status,
+};
+
+/// Serves an OpenAPI spec of the server's routes on `/openapi.json`, and optionally a [Swagger UI](https://swagger.io/tools/swagger-ui) page to explore it.
+/// The spec is generated when the middleware is attached, so it should be attached after all the routes have been added.
+/// ## Example
+/// ```rust
+/// # use afire::{Server, Response, Method, Middleware};
+/// use afire::extension::openapi::{OpenApi, Param, RouteDocs, Schema, ToSchema};
+///
+/// struct User {
+///     name: String,
+///     age: u32,
+/// }
+///
+/// impl ToSchema for User {
+///     fn schema() -> Schema {
+///         Schema::object()
+///             .field("name", String::schema())
+///             .field("age", u32::schema())
+///     }
+/// }
+///
+/// let mut server = Server::<()>::new("localhost", 8080);
+/// server
+///     .route(Method::GET, "/user/{id}", |_| Response::new().text("User"))
+///     .route_name("get_user")
+///     .route_docs(
+///         RouteDocs::new()
+///             .summary("Get a user by their ID")
+///             .param(Param::path("id").schema(u64::schema()))
+///             .response(200, "The user", Some(User::schema()))
+///             .response(404, "There is no user with that ID", None),
+///     );
+///
+/// OpenApi::new("User API", "1.0.0")
+///     .swagger_ui("/docs")
+///     .attach(&mut server);
+/// ```
+pub struct OpenApi {
+    /// The title of the API.
+    title: String,
+    /// The version of the API, not of the OpenAPI spec.
+    version: String,
+    /// A description of the API.
+    description: Option<String>,
+    /// The path to serve the spec on.
+    path: String,
+    /// The path to serve the Swagger UI page on, if it is enabled.
+    swagger_ui: Option<String>,
+    /// The generated spec.
+    spec: String,
+}
+
+/// Documentation for a route, see [`crate::Server::route_docs`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RouteDocs {
+    summary: Option<String>,
+    description: Option<String>,
+    tags: Vec<String>,
+    params: Vec<Param>,
+    request: Option<Schema>,
+    /// The status code, description and body schema of each response.
+    responses: Vec<(u16, String, Option<Schema>)>,
+}
+
+/// A parameter of a route, from its path, query or headers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Param {
+    name: String,
+    location: Location,
+    description: Option<String>,
+    required: bool,
+    schema: Schema,
+}
+
+/// Where a [`Param`] comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Location {
+    Path,
+    Query,
+    Header,
+}
+
+/// A simplified [JSON Schema](https://spec.openapis.org/oas/v3.0.3#schema-object) describing a value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Schema {
+    /// A string.
+    String,
+    /// A whole number.
+    Integer,
+    /// Any number.
+    Number,
+    /// A boolean.
+    Boolean,
+    /// An array of values.
+    Array(Box<Schema>),
+    /// A value that may be missing or null.
+    /// When used as the field of an object, the field is not required.
+    Optional(Box<Schema>),
+    /// An object with named fields.
+    Object(Vec<(String, Schema)>),
+}
+
+/// A type that can describe itself with a [`Schema`].
+/// It is implemented for the primitive types, [`String`], [`Vec`] and [`Option`], and can be implemented for your own types.
+pub trait ToSchema {
+    /// Gets the schema of the type.
+    fn schema() -> Schema;
+}
+
+impl OpenApi {
+    /// Creates a new OpenAPI middleware with the title and version of the API.
+    /// The spec is served on `/openapi.json`.
+    pub fn new(title: impl AsRef<str>, version: impl AsRef<str>) -> Self {
+        Self {
+            title: title.as_ref().to_owned(),
+            version: version.as_ref().to_owned(),
+            description: None,
+            path: "openapi.json".to_owned(),
+            swagger_ui: None,
+            spec: String::new(),
+        }
+    }
+
+    /// Sets the description of the API.
+    pub fn description(self, description: impl AsRef<str>) -> Self {
+        Self {
+            description: Some(description.as_ref().to_owned()),
+            ..self
+        }
+    }
+
+    /// Sets the path to serve the spec on.
+    /// ## Example
+    /// ```rust
+    /// # use afire::extension::OpenApi;
+    /// let openapi = OpenApi::new("API", "1.0.0").path("/api/openapi.json");
+    /// ```
+    pub fn path(self, path: impl AsRef<str>) -> Self {
+        Self {
+            path: normalize_path(path.as_ref().to_owned()),
+            ..self
+        }
+    }
+
+    /// Serves a Swagger UI page for the spec on `path`.
+    /// The page loads Swagger UI from [unpkg](https://unpkg.com), so the client needs to be able to reach it.
+    /// ## Example
+    /// ```rust
+    /// # use afire::extension::OpenApi;
+    /// let openapi = OpenApi::new("API", "1.0.0").swagger_ui("/docs");
+    /// ```
+    pub fn swagger_ui(self, path: impl AsRef<str>) -> Self {
+        Self {
+            swagger_ui: Some(normalize_path(path.as_ref().to_owned())),
+            ..self
+        }
+    }
+
+    /// Generates the spec for a list of routes.
+    fn generate(&self, routes: &[RouteInfo]) -> String {
+        let mut paths = Vec::<(String, BTreeMap<String, String>)>::new();
+        for route in routes {
+            let method = match route.method {
+                Method::GET
+                | Method::POST
+                | Method::PUT
+                | Method::DELETE
+                | Method::OPTIONS
+                | Method::HEAD
+                | Method::PATCH
+                | Method::TRACE => route.method.to_string().to_lowercase(),
+                _ => continue,
+            };
+
+            let (path, path_params) = match path_params(&route.path) {
+                Some(i) => i,
+                None => continue,
+            };
+
+            let operation = operation(route, path_params);
+            match paths.iter_mut().find(|(i, _)| *i == path) {
+                Some((_, methods)) => {
+                    methods.insert(method, operation);
+                }
+                None => paths.push((path, BTreeMap::from([(method, operation)]))),
+            }
+        }
+
+        let paths = paths
+            .iter()
+            .map(|(path, methods)| {
+                let methods = methods
+                    .iter()
+                    .map(|(method, operation)| format!("{}:{}", json_string(method), operation))
+                    .collect::<Vec<_>>();
+                format!("{}:{{{}}}", json_string(path), methods.join(","))
+            })
+            .collect::<Vec<_>>();
+
+        let mut info = vec![
+            format!("\"title\":{}", json_string(&self.title)),
+            format!("\"version\":{}", json_string(&self.version)),
+        ];
+        if let Some(description) = &self.description {
+            info.push(format!("\"description\":{}", json_string(description)));
+        }
+
+        format!(
+            "{{\"openapi\":\"3.0.3\",\"info\":{{{}}},\"paths\":{{{}}}}}",
+            info.join(","),
+            paths.join(",")
+        )
+    }
+
+    fn swagger_ui_page(&self) -> String {
+        format!(
+            "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>{}</title>\
+             <link rel=\"stylesheet\" href=\"https://unpkg.com/swagger-ui-dist@5/swagger-ui.css\"></head>\
+             <body><div id=\"swagger-ui\"></div><script src=\"https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js\"></script>\
+             <script>SwaggerUIBundle({{url: {}, dom_id: \"#swagger-ui\"}});</script></body></html>",
+            xml_escape(&self.title),
+            json_string(&format!("/{}", self.path))
+        )
+    }
+}
+
+/// Converts a route path to an OpenAPI path, removing the constraints from its params (`/user/{id:u32}` becomes `/user/{id}`).
+/// Returns the path and its params, or None if the path has wildcards, which OpenAPI can't describe.
+fn path_params(path: &str) -> Option<(String, Vec<Param>)> {
+    let mut params = Vec::new();
+    let mut segments = Vec::new();
+    for segment in path.split('/') {
+        let param = match segment {
+            "*" | "**" => return None,
+            _ => match segment.strip_prefix('{').and_then(|i| i.strip_suffix('}')) {
+                Some(i) => i,
+                None => {
+                    segments.push(segment.to_owned());
+                    continue;
+                }
+            },
+        };
+
+        let (name, constraint) = param.split_once(':').unwrap_or((param, ""));
+        let schema = match constraint {
+            "u8" | "u16" | "u32" | "u64" | "u128" | "usize" | "i8" | "i16" | "i32" | "i64"
+            | "i128" | "isize" => Schema::Integer,
+            "f32" | "f64" => Schema::Number,
+            "bool" => Schema::Boolean,
+            _ => Schema::String,
+        };
+        params.push(Param::path(name).schema(schema));
+        segments.push(format!("{{{}}}", name));
+    }
+
+    Some((segments.join("/"), params))
+}
+
+/// Generates the operation object for a route.
+/// Params in `path_params` are replaced by any documented path params with the same name.
+fn operation(route: &RouteInfo, path_params: Vec<Param>) -> String {
+    let default = RouteDocs::default();
+    let docs = route.docs.as_ref().unwrap_or(&default);
+    let mut out = Vec::new();
+
+    if let Some(name) = &route.name {
+        out.push(format!("\"operationId\":{}", json_string(name)));
+    }
+    let summary = docs.summary.as_deref().or(route.meta.get("summary"));
+    if let Some(summary) = summary {
+        out.push(format!("\"summary\":{}", json_string(summary)));
+    }
+    let description = docs
+        .description
+        .as_deref()
+        .or(route.meta.get("description"));
+    if let Some(description) = description {
+        out.push(format!("\"description\":{}", json_string(description)));
+    }
+    let tags = docs
+        .tags
+        .iter()
+        .map(String::as_str)
+        .chain(route.meta.get_all("tag"))
+        .map(json_string)
+        .collect::<Vec<_>>();
+    if !tags.is_empty() {
+        out.push(format!("\"tags\":[{}]", tags.join(",")));
+    }
+
+    let mut params = path_params
+        .into_iter()
+        .map(|param| {
+            docs.params
+                .iter()
+                .find(|i| i.location == Location::Path && i.name == param.name)
+                .cloned()
+                .unwrap_or(param)
+        })
+        .collect::<Vec<_>>();
+    params.extend(
+        docs.params
+            .iter()
+            .filter(|i| i.location != Location::Path)
+            .cloned(),
+    );
+    if !params.is_empty() {
+        let params = params.iter().map(Param::json).collect::<Vec<_>>();
+        out.push(format!("\"parameters\":[{}]", params.join(",")));
+    }
+
+    if let Some(request) = &docs.request {
+        out.push(format!(
+            "\"requestBody\":{{\"required\":true,\"content\":{}}}",
+            json_content(request)
+        ));
+    }
+
+    let responses = match docs.responses.is_empty() {
+        true => vec!["\"200\":{\"description\":\"OK\"}".to_owned()],
+        false => docs
+            .responses
+            .iter()
+            .map(|(status, description, schema)| {
+                let content = schema
+                    .as_ref()
+                    .map(|i| format!(",\"content\":{}", json_content(i)))
+                    .unwrap_or_default();
+                format!(
+                    "\"{}\":{{\"description\":{}{}}}",
+                    status,
+                    json_string(description),
+                    content
+                )
+            })
+            .collect(),
+    };
+    out.push(format!("\"responses\":{{{}}}", responses.join(",")));
+
+    format!("{{{}}}", out.join(","))
+}
+
+/// Generates a content object for a JSON body with a schema.
+fn json_content(schema: &Schema) -> String {
+    format!("{{\"application/json\":{{\"schema\":{}}}}}", schema.json())
+}
+
+impl RouteDocs {
+    /// Creates empty route documentation.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets a short summary of what the route does.
+    pub fn summary(self, summary: impl AsRef<str>) -> Self {
+        Self {
+            summary: Some(summary.as_ref().to_owned()),
+            ..self
+        }
+    }
+
+    /// Sets a longer description of the route.
+    pub fn description(self, description: impl AsRef<str>) -> Self {
+        Self {
+            description: Some(description.as_ref().to_owned()),
+            ..self
+        }
+    }
+
+    /// Adds a tag to the route, used to group routes together.
+    pub fn tag(mut self, tag: impl AsRef<str>) -> Self {
+        self.tags.push(tag.as_ref().to_owned());
+        self
+    }
+
+    /// Documents a parameter of the route.
+    /// Path params don't need to be documented, they are included automatically.
+    pub fn param(mut self, param: Param) -> Self {
+        self.params.push(param);
+        self
+    }
+
+    /// Sets the schema of the JSON request body.
+    pub fn request(self, schema: Schema) -> Self {
+        Self {
+            request: Some(schema),
+            ..self
+        }
+    }
+
+    /// Documents a response the route can send, with the schema of its JSON body if it has one.
+    /// If no responses are documented, a `200 OK` response is assumed.
+    pub fn response(
+        mut self,
+        status: impl Into<Status>,
+        description: impl AsRef<str>,
+        schema: Option<Schema>,
+    ) -> Self {
+        self.responses.push((
+            status.into().code(),
+            description.as_ref().to_owned(),
+            schema,
+        ));
+        self
+    }
+}
+
+impl Param {
+    fn new(name: impl AsRef<str>, location: Location, required: bool) -> Self {
+        Self {
+            name: name.as_ref().to_owned(),
+            location,
+            description: None,
+            required,
+            schema: Schema::String,
+        }
+    }
+
+    /// Creates a path param, like the `id` in `/user/{id}`.
+    pub fn path(name: impl AsRef<str>) -> Self {
+        Self::new(name, Location::Path, true)
+    }
+
+    /// Creates an optional query param.
+    pub fn query(name: impl AsRef<str>) -> Self {
+        Self::new(name, Location::Query, false)
+    }
+
+    /// Creates an optional header param.
+    pub fn header(name: impl AsRef<str>) -> Self {
+        Self::new(name, Location::Header, false)
+    }
+
+    /// Sets the description of the param.
+    pub fn description(self, description: impl AsRef<str>) -> Self {
+        Self {
+            description: Some(description.as_ref().to_owned()),
+            ..self
+        }
+    }
+
+    /// Sets if the param is required.
+    /// Path params are always required.
+    pub fn required(self, required: bool) -> Self {
+        Self {
+            required: required || self.location == Location::Path,
+            ..self
+        }
+    }
+
+    /// Sets the schema of the param, which defaults to a string.
+    pub fn schema(self, schema: Schema) -> Self {
+        Self { schema, ..self }
+    }
+
+    fn json(&self) -> String {
+        let location = match self.location {
+            Location::Path => "path",
+            Location::Query => "query",
+            Location::Header => "header",
+        };
+
+        let description = self
+            .description
+            .as_ref()
+            .map(|i| format!(",\"description\":{}", json_string(i)))
+            .unwrap_or_default();
+        format!(
+            "{{\"name\":{},\"in\":\"{}\"{},\"required\":{},\"schema\":{}}}",
+            json_string(&self.name),
+            location,
+            description,
+            self.required,
+            self.schema.json()
+        )
+    }
+}
+
+impl Schema {
+    /// Creates an object schema with no fields, add fields with [`Schema::field`].
+    pub fn object() -> Self {
+        Schema::Object(Vec::new())
+    }
+
+    /// Adds a field to an object schema.
+    /// Does nothing if the schema is not an object.
+    pub fn field(mut self, name: impl AsRef<str>, schema: Schema) -> Self {
+        if let Schema::Object(fields) = &mut self {
+            fields.push((name.as_ref().to_owned(), schema));
+        }
+        self
+    }
+
+    /// Generates the JSON for the schema.
+    fn json(&self) -> String {
+        match self {
+            Schema::String => "{\"type\":\"string\"}".to_owned(),
+            Schema::Integer => "{\"type\":\"integer\"}".to_owned(),
+            Schema::Number => "{\"type\":\"number\"}".to_owned(),
+            Schema::Boolean => "{\"type\":\"boolean\"}".to_owned(),
+            Schema::Array(items) => format!("{{\"type\":\"array\",\"items\":{}}}", items.json()),
+            Schema::Optional(inner) => {
+                let mut out = inner.json();
+                out.insert_str(out.len() - 1, ",\"nullable\":true");
+                out
+            }
+            Schema::Object(fields) => {
+                let properties = fields
+                    .iter()
+                    .map(|(name, schema)| format!("{}:{}", json_string(name), schema.json()))
+                    .collect::<Vec<_>>();
+                let required = fields
+                    .iter()
+                    .filter(|(_, schema)| !matches!(schema, Schema::Optional(_)))
+                    .map(|(name, _)| json_string(name))
+                    .collect::<Vec<_>>();
+
+                let mut out = format!(
+                    "{{\"type\":\"object\",\"properties\":{{{}}}",
+                    properties.join(",")
+                );
+                if !required.is_empty() {
+                    out.push_str(&format!(",\"required\":[{}]", required.join(",")));
+                }
+                out.push('}');
+                out
+            }
+        }
+    }
+}
+
+macro_rules! impl_schema {
+    ($schema: expr => $($type: ty),*) => {
+        $(
+            impl ToSchema for $type {
+                fn schema() -> Schema {
+                    $schema
+                }
+            }
+        )*
+    };
+}
+
+impl_schema!(Schema::String => String, str, char);
+impl_schema!(Schema::Integer => u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize);
+impl_schema!(Schema::Number => f32, f64);
+impl_schema!(Schema::Boolean => bool);
+
+impl<T: ToSchema> ToSchema for Vec<T> {
+    fn schema() -> Schema {
+        Schema::Array(Box::new(T::schema()))
+    }
+}
+
+impl<T: ToSchema> ToSchema for Option<T> {
+    fn schema() -> Schema {
+        Schema::Optional(Box::new(T::schema()))
+    }
+}
+
+impl Middleware for OpenApi {
+    fn pre(&self, req: &mut Request) -> MiddleResult {
+        if req.method != Method::GET {
+            return MiddleResult::Continue;
+        }
+
+        let path = normalize_path(req.path.to_owned());
+        if path == self.path {
+            return MiddleResult::Send(
+                Response::new()
+                    .text(&self.spec)
+                    .content(Content::JSON)
+                    .header("Access-Control-Allow-Origin", "*"),
+            );
+        }
+
+        if self.swagger_ui.as_ref() == Some(&path) {
+            return MiddleResult::Send(
+                Response::new()
+                    .text(self.swagger_ui_page())
+                    .content(Content::HTML),
+            );
+        }
+
+        MiddleResult::Continue
+    }
+
+    fn attach<State>(mut self, server: &mut Server<State>)
+    where
+        Self: 'static + Send + Sync + Sized,
+        State: 'static + Send + Sync,
+    {
+        trace!("{}Adding Middleware {}", emoji("📦"), type_name::<Self>());
+
+        self.spec = self.generate(&server.routes());
+        server.middleware.push(Box::new(self));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{OpenApi, Param, RouteDocs, Schema, ToSchema};
+    use crate::{Method, RouteInfo, RouteMeta};
+
+    fn route(method: Method, path: &str, docs: Option<RouteDocs>) -> RouteInfo {
+        RouteInfo {
+            method,
+            path: path.to_owned(),
+            name: None,
+            priority: 0,
+            meta: RouteMeta::default(),
+            docs,
+        }
+    }
+
+    #[test]
+    fn test_schema_json() {
+        let schema = Schema::object()
+            .field("name", String::schema())
+            .field("tags", Vec::<String>::schema())
+            .field("age", Option::<u32>::schema());
+
+        assert_eq!(
+            schema.json(),
+            r#"{"type":"object","properties":{"name":{"type":"string"},"tags":{"type":"array","items":{"type":"string"}},"age":{"type":"integer","nullable":true}},"required":["name","tags"]}"#
+        );
+    }
+
+    #[test]
+    fn test_openapi_generate() {
+        let docs = RouteDocs::new()
+            .summary("Get a user")
+            .param(Param::path("id").schema(Schema::Integer))
+            .param(Param::query("full").schema(Schema::Boolean))
+            .response(200, "The user", Some(Schema::String));
+
+        let mut delete = route(Method::DELETE, "/user/{id:u32}", None);
+        delete.meta.add("summary", "Delete a user");
+        delete.meta.add("tag", "users");
+
+        let routes = [
+            route(Method::GET, "/user/{id}", Some(docs)),
+            delete,
+            route(Method::ANY, "/any", None),
+            route(Method::GET, "/static/**", None),
+        ];
+
+        assert_eq!(
+            OpenApi::new("Test", "1.0").generate(&routes),
+            concat!(
+                r#"{"openapi":"3.0.3","info":{"title":"Test","version":"1.0"},"paths":{"/user/{id}":{"#,
+                r#""delete":{"summary":"Delete a user","tags":["users"],"parameters":[{"name":"id","in":"path","required":true,"schema":{"type":"integer"}}],"responses":{"200":{"description":"OK"}}},"#,
+                r#""get":{"summary":"Get a user","parameters":[{"name":"id","in":"path","required":true,"schema":{"type":"integer"}},{"name":"full","in":"query","required":false,"schema":{"type":"boolean"}}],"#,
+                r#""responses":{"200":{"description":"The user","content":{"application/json":{"schema":{"type":"string"}}}}}}}}}"#
+            )
+        );
+    }
+}
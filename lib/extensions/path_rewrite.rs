@@ -0,0 +1,154 @@
+//! Rewrite request paths before they are routed.
+//!
+//! This is useful when the server is behind an ingress or reverse proxy that forwards requests under a path prefix (like `/app`), so every route doesn't need to include it.
+
+use crate::{
+    encoding::url,
+    internal::path::{Path, PathPart},
+    middleware::{MiddleResult, Middleware},
+    Request,
+};
+
+/// A rewrite rule.
+enum Rule {
+    /// Remove a path prefix.
+    StripPrefix(String),
+    /// Replace paths matching a pattern.
+    Rewrite(Path, String),
+}
+
+/// Middleware that rewrites request paths before they are routed.
+///
+/// Rules are checked in the order they were added, and only the first one that matches a path is applied.
+/// Rewrite rules use the same patterns as routes, with `{name}` params and `*` / `**` wildcards.
+/// The values of params can be used in the replacement with `{name}`, and `**` in the replacement is the part of the path matched by `**`.
+/// If the replacement has a query string, it replaces the request's query.
+///
+/// Middleware run in the reverse order they were attached, so attach this after other middleware for them to see the rewritten path.
+/// ## Example
+/// ```rust
+/// # use afire::{Server, Middleware, extension::PathRewrite};
+/// # let mut server = Server::<()>::new("localhost", 8080);
+/// PathRewrite::new()
+///     // The ingress forwards requests for `example.com/app/...`
+///     .strip_prefix("/app")
+///     .rewrite("/user/{id}", "/users/{id}")
+///     .rewrite("/static/**", "/assets/**")
+///     .attach(&mut server);
+/// ```
+pub struct PathRewrite {
+    /// The rules, in the order they are checked.
+    rules: Vec<Rule>,
+}
+
+impl PathRewrite {
+    /// Creates a new PathRewrite with no rules.
+    pub fn new() -> Self {
+        Self { rules: Vec::new() }
+    }
+
+    /// Removes a prefix from paths under it.
+    /// The prefix has to match whole segments, so `/app` is removed from `/app/users` and `/app` but not `/application`.
+    pub fn strip_prefix(mut self, prefix: impl AsRef<str>) -> Self {
+        let prefix = prefix.as_ref().trim_end_matches('/').to_owned();
+        self.rules.push(Rule::StripPrefix(prefix));
+        self
+    }
+
+    /// Replaces paths matching the pattern.
+    /// For example `rewrite("/blog/{year}/{slug}", "/posts/{slug}?year={year}")`.
+    pub fn rewrite(mut self, pattern: impl AsRef<str>, replacement: impl AsRef<str>) -> Self {
+        let pattern = Path::new(pattern.as_ref().to_owned());
+        self.rules
+            .push(Rule::Rewrite(pattern, replacement.as_ref().to_owned()));
+        self
+    }
+
+    /// Gets the rewritten path, or None if no rules match.
+    fn rewrite_path(&self, path: &str) -> Option<String> {
+        self.rules.iter().find_map(|rule| match rule {
+            Rule::StripPrefix(prefix) => match path.strip_prefix(prefix.as_str())? {
+                "" => Some("/".to_owned()),
+                rest if rest.starts_with('/') => Some(rest.to_owned()),
+                _ => None,
+            },
+            Rule::Rewrite(pattern, replacement) => {
+                let params = pattern.match_path(path.to_owned())?;
+                let mut out = replacement.to_owned();
+                for (name, value) in params {
+                    out = out.replace(&format!("{{{name}}}"), &url::encode(&value));
+                }
+
+                // Get the part of the path matched by `**`
+                if let Some(i) = pattern.parts.iter().position(|i| *i == PathPart::AnyAfter) {
+                    let rest = path
+                        .trim_matches('/')
+                        .split('/')
+                        .skip(i)
+                        .collect::<Vec<_>>()
+                        .join("/");
+                    out = out.replace("**", &rest);
+                }
+
+                Some(out)
+            }
+        })
+    }
+}
+
+impl Middleware for PathRewrite {
+    fn pre(&self, req: &mut Request) -> MiddleResult {
+        if let Some(path) = self.rewrite_path(&req.path) {
+            req.set_path(path);
+        }
+
+        MiddleResult::Continue
+    }
+}
+
+impl Default for PathRewrite {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::PathRewrite;
+
+    #[test]
+    fn test_strip_prefix() {
+        let rewrite = PathRewrite::new().strip_prefix("/app/");
+        assert_eq!(
+            rewrite.rewrite_path("/app/users").as_deref(),
+            Some("/users")
+        );
+        assert_eq!(rewrite.rewrite_path("/app").as_deref(), Some("/"));
+        assert_eq!(rewrite.rewrite_path("/application"), None);
+        assert_eq!(rewrite.rewrite_path("/users"), None);
+    }
+
+    #[test]
+    fn test_rewrite() {
+        let rewrite = PathRewrite::new()
+            .rewrite("/user/{id}", "/users/{id}")
+            .rewrite("/blog/{year}/{slug}", "/posts/{slug}?year={year}")
+            .rewrite("/static/**", "/assets/**");
+
+        assert_eq!(rewrite.rewrite_path("/user/5").as_deref(), Some("/users/5"));
+        assert_eq!(
+            rewrite.rewrite_path("/user/a%20b").as_deref(),
+            Some("/users/a%20b")
+        );
+        assert_eq!(
+            rewrite.rewrite_path("/blog/2024/hello").as_deref(),
+            Some("/posts/hello?year=2024")
+        );
+        assert_eq!(
+            rewrite.rewrite_path("/static/css/main.css").as_deref(),
+            Some("/assets/css/main.css")
+        );
+        assert_eq!(rewrite.rewrite_path("/static"), None);
+        assert_eq!(rewrite.rewrite_path("/user"), None);
+    }
+}
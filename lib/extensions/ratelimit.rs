@@ -1,53 +1,142 @@
-//! An extension to limit the amount of requests sent from a single IP that will be handled by the server.
+//! An extension to limit the amount of requests sent from a single IP (or any other key) that will be handled by the server.
+//!
+//! Responses will include the `RateLimit-Limit` and `RateLimit-Remaining` headers,
+//! and once a client has no requests left, the `Retry-After` header with how many seconds until it can make another request.
+//!
+//! By default the state of each key is kept in memory with a [`MemoryStore`].
+//! To share limits between multiple processes, implement [`RateLimitStore`] for a shared store (file, database, redis, etc.) and use [`RateLimiter::store`].
 
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::fmt;
-use std::net::IpAddr;
 use std::sync::{
     atomic::{AtomicU64, Ordering},
-    RwLock,
+    Mutex,
 };
 
-use crate::internal::common::epoch;
+use crate::internal::common::ForceLock;
 use crate::Status;
 use crate::{
     middleware::{MiddleResult, Middleware},
-    Content, Request, Response,
+    Content, HeaderType, Request, Response,
 };
 
 // Handler Type
 type Handler = Box<dyn Fn(&Request) -> Option<Response> + Send + Sync>;
+type KeyFn = Box<dyn Fn(&Request) -> String + Send + Sync>;
 
 /// Limit the amount of requests handled by the server.
 pub struct RateLimiter {
     /// Requests Per Req_Timeout
     req_limit: u64,
 
-    /// Time of last reset
+    /// Time of last cleanup of stale entries (ms)
     last_reset: AtomicU64,
 
-    /// How often to reset the counters (sec)
+    /// The length of the rate limit period (sec)
     req_timeout: u64,
 
-    /// Table that maps an IP to a list of request timestamps
-    // requests: RwLock<HashMap<IpAddr, Vec<u64>>>,
-    requests: RwLock<HashMap<IpAddr, u64>>,
+    /// The algorithm used to limit requests
+    algorithm: Algorithm,
+
+    /// Store that maps a key to its rate limit state
+    store: Box<dyn RateLimitStore>,
+
+    /// Gets the key requests are counted under.
+    /// By default this is the client's IP address.
+    key_fn: KeyFn,
+
+    /// If the RateLimit headers should be added to responses.
+    headers: bool,
 
     /// Handler for when the limit is reached.
     /// If the handler returns None, the request will be processed normally.
     handler: Handler,
 }
 
+/// The algorithm used by a [`RateLimiter`] to decide if a request is over the limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    /// Counts requests in fixed windows of `timeout` seconds, starting from a key's first request.
+    /// This is the cheapest algorithm, but it allows bursts of up to twice the limit around the end of a window.
+    /// This is the default.
+    FixedWindow,
+
+    /// Keeps a log of the time of each request in the last `timeout` seconds.
+    /// This enforces the limit exactly over any window of time, but uses memory proportional to the limit for each key.
+    SlidingWindow,
+
+    /// Each key gets a bucket of `limit` tokens, which is refilled continuously so it becomes full again after `timeout` seconds.
+    /// Each request takes one token, allowing short bursts while limiting the average rate.
+    TokenBucket,
+}
+
+/// The rate limit state of a single key.
+/// All times are milliseconds since the unix epoch.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Bucket {
+    /// Used by [`Algorithm::FixedWindow`].
+    Window {
+        /// When the current window started.
+        start: u64,
+        /// How many requests have been made in the current window.
+        count: u64,
+    },
+    /// The times of requests in the current window.
+    /// Used by [`Algorithm::SlidingWindow`].
+    Log(VecDeque<u64>),
+    /// Used by [`Algorithm::TokenBucket`].
+    Tokens {
+        /// How many tokens are left.
+        tokens: f64,
+        /// When the tokens were last refilled.
+        last: u64,
+    },
+}
+
+/// Storage for the rate limit state of each key.
+///
+/// The default [`MemoryStore`] keeps everything in a HashMap, implement this trait to share the state between multiple processes.
+/// Each update must be atomic, otherwise concurrent requests may be allowed over the limit.
+pub trait RateLimitStore: Send + Sync {
+    /// Runs `update` on the state of `key` and saves the result.
+    /// If the key has no state yet, `init` should be used.
+    fn update(&self, key: &str, init: Bucket, update: &mut dyn FnMut(&mut Bucket));
+
+    /// Removes every entry where `stale` returns true.
+    /// This is called about once per rate limit period, so entries for clients that are no longer sending requests don't stick around forever.
+    fn evict(&self, stale: &dyn Fn(&Bucket) -> bool);
+}
+
+/// The default [`RateLimitStore`], keeps the state of each key in memory.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+/// The result of checking a key against the rate limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Decision {
+    /// If the request is allowed.
+    allowed: bool,
+    /// How many more requests can be made right now.
+    remaining: u64,
+    /// How long until another request will be allowed (ms), 0 if there are requests remaining.
+    retry_after: u64,
+}
+
 impl RateLimiter {
     /// Make a new RateLimiter.
     ///
-    /// Default limit is 10 and timeout is 60
+    /// Default limit is 10 and timeout is 60, using the [`Algorithm::FixedWindow`] algorithm.
     pub fn new() -> RateLimiter {
         RateLimiter {
             last_reset: AtomicU64::new(0),
             req_limit: 10,
             req_timeout: 60,
-            requests: RwLock::new(HashMap::new()),
+            algorithm: Algorithm::FixedWindow,
+            store: Box::new(MemoryStore::default()),
+            key_fn: Box::new(|req| req.address.ip().to_string()),
+            headers: true,
             handler: Box::new(|_| {
                 Some(
                     Response::new()
@@ -114,6 +203,39 @@ impl RateLimiter {
         }
     }
 
+    /// Set the algorithm used to limit requests.
+    /// See [`Algorithm`] for the options, the default is [`Algorithm::FixedWindow`].
+    /// ## Example
+    /// ```rust,no_run
+    /// // Import Lib
+    /// use afire::{Server, extension::{RateLimiter, ratelimit::Algorithm}, Middleware};
+    ///
+    /// // Create a new server
+    /// let mut server = Server::<()>::new("localhost", 1234);
+    ///
+    /// // Add a rate limiter
+    /// RateLimiter::new()
+    ///     // Allow bursts of up to 20 requests, refilling over 10 seconds
+    ///     .algorithm(Algorithm::TokenBucket)
+    ///     .limit(20)
+    ///     .timeout(10)
+    ///     // Attach it to the server
+    ///     .attach(&mut server);
+    ///
+    /// // Start Server
+    /// // This is blocking
+    /// server.start().unwrap();
+    /// ```
+    pub fn algorithm(self, algorithm: Algorithm) -> RateLimiter {
+        RateLimiter { algorithm, ..self }
+    }
+
+    /// Set if the `RateLimit-Limit`, `RateLimit-Remaining` and `Retry-After` headers should be added to responses.
+    /// This is enabled by default.
+    pub fn headers(self, headers: bool) -> RateLimiter {
+        RateLimiter { headers, ..self }
+    }
+
     /// Define a Custom Handler for when a client has exceeded the ratelimit.
     /// If the handler returns None, the request will be processed normally.
     /// ## Example
@@ -139,31 +261,191 @@ impl RateLimiter {
         RateLimiter { handler, ..self }
     }
 
-    /// Count a request.
-    fn add_request(&self, ip: IpAddr) {
-        let mut req = self.requests.write().unwrap();
-        let count = req.get(&ip).unwrap_or(&0) + 1;
-        req.insert(ip, count);
+    /// Set the store used to keep the rate limit state of each key.
+    /// By default this is a [`MemoryStore`], which is not shared between processes.
+    pub fn store(self, store: impl RateLimitStore + 'static) -> RateLimiter {
+        RateLimiter {
+            store: Box::new(store),
+            ..self
+        }
+    }
+
+    /// Set the function used to get the key requests are counted under.
+    /// By default requests are limited per client IP address, but this lets you limit requests per API token, user, route, etc.
+    /// ## Example
+    /// ```rust,no_run
+    /// // Import Lib
+    /// use afire::{Server, extension::RateLimiter, Middleware};
+    ///
+    /// // Create a new server
+    /// let mut server = Server::<()>::new("localhost", 1234);
+    ///
+    /// // Add a rate limiter
+    /// RateLimiter::new()
+    ///     // Limit requests per API token
+    ///     .key_fn(|req| req.headers.get("Authorization").unwrap_or_default().to_owned())
+    ///     // Attach it to the server
+    ///     .attach(&mut server);
+    ///
+    /// // Start Server
+    /// // This is blocking
+    /// server.start().unwrap();
+    /// ```
+    pub fn key_fn(
+        self,
+        key_fn: impl Fn(&Request) -> String + Send + Sync + 'static,
+    ) -> RateLimiter {
+        RateLimiter {
+            key_fn: Box::new(key_fn),
+            ..self
+        }
+    }
+
+    /// The length of the rate limit period in milliseconds.
+    fn period(&self) -> u64 {
+        self.req_timeout.saturating_mul(1000).max(1)
+    }
+
+    /// Checks a key against the limit.
+    /// If `take` is true and the request is allowed, it will be counted.
+    fn check(&self, req: &Request, take: bool) -> Decision {
+        let key = (self.key_fn)(req);
+        let now = req.clock().epoch().as_millis() as u64;
+        self.cleanup(now);
+
+        let mut decision = None;
+        let init = self.algorithm.bucket(self.req_limit, now);
+        self.store.update(&key, init, &mut |bucket| {
+            decision = Some(bucket.update(self.req_limit, self.period(), now, take));
+        });
+
+        decision.expect("RateLimitStore::update must call the update function")
+    }
+
+    /// Removes entries that have fully reset, so the store doesn't grow forever.
+    /// This runs at most once per period.
+    fn cleanup(&self, now: u64) {
+        let (limit, period) = (self.req_limit, self.period());
+        let last = self.last_reset.load(Ordering::Acquire);
+        if last + period > now
+            || self
+                .last_reset
+                .compare_exchange(last, now, Ordering::AcqRel, Ordering::Acquire)
+                .is_err()
+        {
+            return;
+        }
+
+        self.store
+            .evict(&|bucket| bucket.is_stale(limit, period, now));
+    }
+}
+
+impl RateLimitStore for MemoryStore {
+    fn update(&self, key: &str, init: Bucket, update: &mut dyn FnMut(&mut Bucket)) {
+        let mut buckets = self.buckets.force_lock();
+        match buckets.get_mut(key) {
+            Some(bucket) => update(bucket),
+            None => update(buckets.entry(key.to_owned()).or_insert(init)),
+        }
+    }
+
+    fn evict(&self, stale: &dyn Fn(&Bucket) -> bool) {
+        self.buckets.force_lock().retain(|_, bucket| !stale(bucket));
+    }
+}
+
+impl Algorithm {
+    /// Creates the state for a new key.
+    fn bucket(self, limit: u64, now: u64) -> Bucket {
+        match self {
+            Algorithm::FixedWindow => Bucket::Window {
+                start: now,
+                count: 0,
+            },
+            Algorithm::SlidingWindow => Bucket::Log(VecDeque::new()),
+            Algorithm::TokenBucket => Bucket::Tokens {
+                tokens: limit as f64,
+                last: now,
+            },
+        }
+    }
+}
+
+impl Bucket {
+    /// Brings the state up to date with the current time, then checks if a request is allowed.
+    /// If `take` is true and the request is allowed, it will be counted.
+    fn update(&mut self, limit: u64, period: u64, now: u64, take: bool) -> Decision {
+        match self {
+            Bucket::Window { start, count } => {
+                if now >= *start + period {
+                    *start = now;
+                    *count = 0;
+                }
+
+                let allowed = *count < limit;
+                if allowed && take {
+                    *count += 1;
+                }
+
+                let remaining = limit.saturating_sub(*count);
+                Decision::new(allowed, remaining, *start + period - now)
+            }
+            Bucket::Log(log) => {
+                while log.front().map(|i| i + period <= now).unwrap_or(false) {
+                    log.pop_front();
+                }
+
+                let allowed = (log.len() as u64) < limit;
+                if allowed && take {
+                    log.push_back(now);
+                }
+
+                let remaining = limit.saturating_sub(log.len() as u64);
+                let oldest = log.front().copied().unwrap_or(now);
+                Decision::new(allowed, remaining, oldest + period - now)
+            }
+            Bucket::Tokens { tokens, last } => {
+                let rate = limit as f64 / period as f64;
+                *tokens = (*tokens + (now - *last) as f64 * rate).min(limit as f64);
+                *last = now;
+
+                let allowed = *tokens >= 1.0;
+                if allowed && take {
+                    *tokens -= 1.0;
+                }
+
+                let wait = ((1.0 - *tokens) / rate).ceil().max(0.0) as u64;
+                Decision::new(allowed, *tokens as u64, wait)
+            }
+        }
     }
 
-    /// Check if request table needs to be cleared.
-    fn check_reset(&self) {
-        let time = epoch().as_secs();
-        if self.last_reset.load(Ordering::Acquire) + self.req_timeout <= time {
-            self.requests.write().unwrap().clear();
-            self.last_reset.store(time, Ordering::Release);
+    /// Checks if this state is the same as a new key's state, so it can be removed.
+    fn is_stale(&self, limit: u64, period: u64, now: u64) -> bool {
+        match self {
+            Bucket::Window { start, .. } => now >= start + period,
+            Bucket::Log(log) => log.back().map(|i| i + period <= now).unwrap_or(true),
+            Bucket::Tokens { tokens, last } => {
+                *tokens + (now - last) as f64 * limit as f64 / period as f64 >= limit as f64
+            }
         }
     }
+}
 
-    /// Check if the request limit has been reached for an ip.
-    fn is_over_limit(&self, ip: IpAddr) -> bool {
-        self.requests.read().unwrap().get(&ip).unwrap_or(&0) >= &self.req_limit
+impl Decision {
+    fn new(allowed: bool, remaining: u64, retry_after: u64) -> Self {
+        Self {
+            allowed,
+            remaining,
+            retry_after: if remaining > 0 { 0 } else { retry_after },
+        }
     }
 }
 
 impl Middleware for RateLimiter {
     fn pre(&self, req: &mut Request) -> MiddleResult {
-        if self.is_over_limit(req.address.ip()) {
+        if !self.check(req, true).allowed {
             if let Some(i) = (self.handler)(req) {
                 return MiddleResult::Send(i);
             }
@@ -172,9 +454,23 @@ impl Middleware for RateLimiter {
         MiddleResult::Continue
     }
 
-    fn end(&self, req: &Request, _res: &Response) {
-        self.check_reset();
-        self.add_request(req.address.ip());
+    fn post(&self, req: &Request, res: &mut Response) -> MiddleResult {
+        if !self.headers {
+            return MiddleResult::Continue;
+        }
+
+        let decision = self.check(req, false);
+        res.headers
+            .add("RateLimit-Limit", self.req_limit.to_string());
+        res.headers
+            .add("RateLimit-Remaining", decision.remaining.to_string());
+        if decision.remaining == 0 && !res.headers.has(HeaderType::RetryAfter) {
+            let seconds = decision.retry_after.div_ceil(1000);
+            res.headers
+                .add(HeaderType::RetryAfter, seconds.max(1).to_string());
+        }
+
+        MiddleResult::Continue
     }
 }
 
@@ -190,8 +486,85 @@ impl fmt::Debug for RateLimiter {
         f.debug_struct("RateLimiter")
             .field("req_limit", &self.req_limit)
             .field("req_timeout", &self.req_timeout)
+            .field("algorithm", &self.algorithm)
             .field("last_reset", &self.last_reset)
-            .field("requests", &self.requests)
             .finish()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{Algorithm, Bucket, Decision, MemoryStore, RateLimitStore};
+
+    const PERIOD: u64 = 10_000;
+
+    #[test]
+    fn test_fixed_window() {
+        let mut bucket = Algorithm::FixedWindow.bucket(2, 0);
+        assert_eq!(bucket.update(2, PERIOD, 0, true), Decision::new(true, 1, 0));
+        assert_eq!(
+            bucket.update(2, PERIOD, 1_000, true),
+            Decision::new(true, 0, 9_000)
+        );
+        assert!(!bucket.update(2, PERIOD, 9_999, true).allowed);
+        assert!(bucket.update(2, PERIOD, 10_000, true).allowed);
+        assert!(bucket.is_stale(2, PERIOD, 20_000));
+    }
+
+    #[test]
+    fn test_sliding_window() {
+        let mut bucket = Algorithm::SlidingWindow.bucket(2, 0);
+        assert!(bucket.update(2, PERIOD, 0, true).allowed);
+        assert!(bucket.update(2, PERIOD, 9_000, true).allowed);
+
+        // Unlike the fixed window, the request at 9s still counts just after the first period
+        assert_eq!(
+            bucket.update(2, PERIOD, 10_000, true),
+            Decision::new(true, 0, 9_000)
+        );
+        assert_eq!(
+            bucket.update(2, PERIOD, 11_000, true),
+            Decision::new(false, 0, 8_000)
+        );
+        assert!(!bucket.is_stale(2, PERIOD, 19_999));
+        assert!(bucket.is_stale(2, PERIOD, 20_000));
+    }
+
+    #[test]
+    fn test_token_bucket() {
+        let mut bucket = Algorithm::TokenBucket.bucket(2, 0);
+        assert!(bucket.update(2, PERIOD, 0, true).allowed);
+        assert_eq!(
+            bucket.update(2, PERIOD, 0, true),
+            Decision::new(true, 0, 5_000)
+        );
+        assert!(!bucket.update(2, PERIOD, 4_999, true).allowed);
+
+        // One token is refilled every 5 seconds
+        assert!(bucket.update(2, PERIOD, 5_000, true).allowed);
+        assert!(!bucket.is_stale(2, PERIOD, 14_999));
+        assert!(bucket.is_stale(2, PERIOD, 15_000));
+    }
+
+    #[test]
+    fn test_memory_store() {
+        let store = MemoryStore::default();
+        let init = Algorithm::FixedWindow.bucket(2, 0);
+        store.update("a", init.clone(), &mut |i| assert_eq!(*i, init));
+        let init = Algorithm::FixedWindow.bucket(2, 5_000);
+        store.update("b", init, &mut |i| {
+            i.update(2, PERIOD, 5_000, true);
+        });
+
+        store.evict(&|i| i.is_stale(2, PERIOD, 10_000));
+        let buckets = store.buckets.lock().unwrap();
+        assert_eq!(buckets.len(), 1);
+        assert_eq!(
+            buckets.get("b"),
+            Some(&Bucket::Window {
+                start: 5_000,
+                count: 1
+            })
+        );
+    }
+}
@@ -1,15 +1,27 @@
 //! Methods for getting the real IP of a client through a reverse proxy.
 //!
+//! The [`RealIp`] trait adds `req.real_ip()`, and the [`RealIpResolver`] middleware replaces [`Request::address`] with the client's real address,
+//! so other middleware like the RateLimiter and Logger don't attribute all traffic to the proxy.
+//!
+//! The `Forwarded` ([RFC 7239](https://www.rfc-editor.org/rfc/rfc7239)), `X-Forwarded-For` and `X-Real-IP` headers are supported, in that order of preference.
+//! The headers are only used if the request came from a trusted proxy, and the chain of proxies is walked from the right until an untrusted address is found.
+//!
 //! **Warning**: Make sure your reverse proxy is overwriting the specified header on the incoming requests so clients cant spoof their original Ips.
 
-use std::net::IpAddr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 
-use crate::{HeaderType, Request};
+use crate::{
+    header::typed::{Forwarded, TypedHeader},
+    internal::common::in_range,
+    middleware::{MiddleResult, Middleware},
+    HeaderType, Request,
+};
 
 /// Trait that adds methods for getting the real IP of a client through a reverse proxy.
-/// If you are using the "X-Forwarded-For" header you can use `req.real_ip()` but if you are using a different header you will have to use `req.real_ip_header(...)`.
+/// `req.real_ip()` trusts proxies on localhost, use the [`RealIpResolver`] middleware to trust other proxies.
 pub trait RealIp {
-    /// Uses [`RealIp::real_ip_header`] with the ["X-Forwarded-For"](https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/X-Forwarded-For) header.
+    /// Gets the 'real IP' of a client from the `Forwarded`, `X-Forwarded-For` or `X-Real-IP` headers.
+    /// Only proxies on localhost are trusted, if the connection is coming from anywhere else, the raw socket address will be returned.
     /// ## Example
     /// ```rust
     /// use afire::extension::RealIp;
@@ -22,31 +34,224 @@ pub trait RealIp {
     /// });
     /// # }
     /// ```
-    fn real_ip(&self) -> IpAddr {
-        self.real_ip_header(HeaderType::XForwardedFor)
-    }
+    fn real_ip(&self) -> IpAddr;
 
     /// Gets the 'real IP' of a client by parsing the value of `header` into an IpAddr.
+    /// The header can contain a comma separated list of addresses, like `X-Forwarded-For`.
     /// If the connection is not coming from localhost, the header isn't found or the header contains an invalid IP address, the raw socket address will be returned.
     ///
     /// **Warning**: Make sure your reverse proxy is overwriting the specified header on the incoming requests so clients cant spoof their original Ips.
     fn real_ip_header(&self, header: impl Into<HeaderType>) -> IpAddr;
 }
 
+/// Middleware that replaces [`Request::address`] with the real address of the client, when the request comes through a trusted reverse proxy.
+/// The port of the proxy's connection is kept, as it isn't forwarded.
+/// By default only proxies on localhost are trusted.
+///
+/// ## Example
+/// ```rust
+/// # use afire::{Server, Middleware, extension::RealIpResolver};
+/// # let mut server = Server::<()>::new("localhost", 8080);
+/// RealIpResolver::new()
+///     // Trust a proxy on another machine
+///     .trust([10, 0, 0, 5])
+///     // Trust every proxy in a private subnet
+///     .trust_range([172, 16, 0, 0], 12)
+///     .attach(&mut server);
+/// ```
+#[derive(Debug, Clone)]
+pub struct RealIpResolver {
+    /// Trusted proxy address ranges, as an address and prefix length.
+    trusted: Vec<(IpAddr, u8)>,
+}
+
+impl RealIpResolver {
+    /// Creates a new RealIpResolver that trusts proxies on localhost.
+    pub fn new() -> Self {
+        Self {
+            trusted: vec![
+                (Ipv4Addr::new(127, 0, 0, 0).into(), 8),
+                (Ipv6Addr::LOCALHOST.into(), 128),
+            ],
+        }
+    }
+
+    /// Trusts a proxy with the given address.
+    pub fn trust(self, proxy: impl Into<IpAddr>) -> Self {
+        let proxy = proxy.into();
+        let prefix = if proxy.is_ipv4() { 32 } else { 128 };
+        self.trust_range(proxy, prefix)
+    }
+
+    /// Trusts all proxies in the given CIDR range.
+    /// For example `trust_range([10, 0, 0, 0], 8)` trusts `10.0.0.0/8`.
+    pub fn trust_range(mut self, addr: impl Into<IpAddr>, prefix: u8) -> Self {
+        self.trusted.push((addr.into(), prefix));
+        self
+    }
+
+    /// Checks if an address is in one of the trusted ranges.
+    pub fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.trusted
+            .iter()
+            .any(|(range, prefix)| in_range(ip, *range, *prefix))
+    }
+
+    /// Gets the real IP of the client that sent a request.
+    pub fn resolve(&self, req: &Request) -> IpAddr {
+        let peer = req.address.ip();
+        if !self.is_trusted(peer) {
+            return peer;
+        }
+
+        let chain = if let Some(i) = req.headers.get(HeaderType::Forwarded) {
+            parse_forwarded(i)
+        } else if let Some(i) = req.headers.get(HeaderType::XForwardedFor) {
+            parse_list(i)
+        } else if let Some(i) = req.headers.get(HeaderType::XRealIp) {
+            parse_list(i)
+        } else {
+            return peer;
+        };
+
+        self.walk(peer, &chain)
+    }
+
+    /// Walks a chain of forwarded addresses from the right, returning the first untrusted address.
+    /// If an invalid address is found, the last valid one is returned.
+    fn walk(&self, peer: IpAddr, chain: &[Option<IpAddr>]) -> IpAddr {
+        let mut ip = peer;
+        for hop in chain.iter().rev() {
+            match hop {
+                Some(hop) => ip = *hop,
+                None => break,
+            }
+
+            if !self.is_trusted(ip) {
+                break;
+            }
+        }
+
+        ip
+    }
+}
+
+impl Middleware for RealIpResolver {
+    fn pre(&self, req: &mut Request) -> MiddleResult {
+        req.address = SocketAddr::new(self.resolve(req), req.address.port());
+        MiddleResult::Continue
+    }
+}
+
+impl Default for RealIpResolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl RealIp for Request {
+    fn real_ip(&self) -> IpAddr {
+        RealIpResolver::new().resolve(self)
+    }
+
     fn real_ip_header(&self, header: impl Into<HeaderType>) -> IpAddr {
+        let resolver = RealIpResolver::new();
         let ip = self.address.ip();
 
         // If the connection is not coming from localhost (likely from reverse proxy) return the raw IP
-        if !ip.is_loopback() {
+        if !resolver.is_trusted(ip) {
             return ip;
         }
 
-        // If the 'X-Forwarded-For' Header is present and its value is a valid IP, return it
-        // Otherwise return the socket address
-        self.headers
-            .get(header.into())
-            .and_then(|x| x.parse().ok())
-            .unwrap_or(ip)
+        match self.headers.get(header.into()) {
+            Some(i) => resolver.walk(ip, &parse_list(i)),
+            None => ip,
+        }
+    }
+}
+
+/// Parses a node (address with an optional port) from a forwarding header.
+/// Supports `1.2.3.4`, `1.2.3.4:80`, `::1`, `[::1]` and `[::1]:80`.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    if let Ok(ip) = node.parse() {
+        return Some(ip);
+    }
+
+    if let Some(rest) = node.strip_prefix('[') {
+        return rest.split(']').next()?.parse().ok();
+    }
+
+    node.parse::<SocketAddr>().ok().map(|i| i.ip())
+}
+
+/// Parses a comma separated list of addresses, like in the `X-Forwarded-For` header.
+fn parse_list(value: &str) -> Vec<Option<IpAddr>> {
+    value.split(',').map(parse_node).collect()
+}
+
+/// Parses the `for` parameters of a `Forwarded` header.
+/// Elements without a `for` parameter or with an obfuscated / unknown address are returned as None.
+fn parse_forwarded(value: &str) -> Vec<Option<IpAddr>> {
+    Forwarded::parse(value)
+        .map(|i| i.0)
+        .unwrap_or_default()
+        .iter()
+        .map(|i| i.forwarded_for.as_deref().and_then(parse_node))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+    use super::{in_range, parse_forwarded, parse_list, parse_node, RealIpResolver};
+
+    const CLIENT: IpAddr = IpAddr::V4(Ipv4Addr::new(203, 0, 113, 7));
+    const PROXY: IpAddr = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 5));
+
+    #[test]
+    fn test_parse_node() {
+        assert_eq!(parse_node(" 203.0.113.7"), Some(CLIENT));
+        assert_eq!(parse_node("203.0.113.7:8080"), Some(CLIENT));
+        assert_eq!(parse_node("\"[::1]:80\""), Some(Ipv6Addr::LOCALHOST.into()));
+        assert_eq!(parse_node("::1"), Some(Ipv6Addr::LOCALHOST.into()));
+        assert_eq!(parse_node("unknown"), None);
+        assert_eq!(parse_node("_hidden"), None);
+    }
+
+    #[test]
+    fn test_parse_forwarded() {
+        assert_eq!(
+            parse_forwarded("for=203.0.113.7;proto=http, For=\"[::1]:4711\", by=10.0.0.1"),
+            vec![Some(CLIENT), Some(Ipv6Addr::LOCALHOST.into()), None]
+        );
+    }
+
+    #[test]
+    fn test_in_range() {
+        assert!(in_range(PROXY, [10, 0, 0, 0].into(), 8));
+        assert!(!in_range(CLIENT, [10, 0, 0, 0].into(), 8));
+        assert!(in_range(CLIENT, [0, 0, 0, 0].into(), 0));
+        assert!(in_range(PROXY, PROXY, 32));
+        assert!(!in_range(PROXY, Ipv6Addr::LOCALHOST.into(), 0));
+    }
+
+    #[test]
+    fn test_walk() {
+        let resolver = RealIpResolver::new().trust(PROXY);
+        let localhost = Ipv4Addr::LOCALHOST.into();
+
+        // The right-most untrusted address is used, so clients cant spoof their address by adding to the list
+        let chain = parse_list("1.1.1.1, 203.0.113.7, 10.0.0.5");
+        assert_eq!(resolver.walk(localhost, &chain), CLIENT);
+
+        // An invalid address stops the walk
+        let chain = parse_list("203.0.113.7, garbage, 10.0.0.5");
+        assert_eq!(resolver.walk(localhost, &chain), PROXY);
+
+        // If every address is trusted, the left-most one is used
+        let chain = parse_list("10.0.0.5");
+        assert_eq!(resolver.walk(localhost, &chain), PROXY);
     }
 }
@@ -0,0 +1,536 @@
+//! Record requests and their responses to disk, so they can be replayed later.
+//!
+//! Every recorded exchange is saved to its own `.rec` file, which can be loaded with [`Recording::load`].
+//! With the `client` feature, a recording can be sent to a local server with [`Recording::replay`] to reproduce a bug report.
+//!
+//! ## Format
+//! Each file has a section for the request and one for the response.
+//! A section starts with a line like `request 5 full`, giving the number of body bytes and if the body is `full` or `truncated`.
+//! Next is the HTTP head (request or status line and headers, ending with a blank line), then the body and a newline.
+//! ```text
+//! request 5 full
+//! POST /api/echo?x=1 HTTP/1.1
+//! Host: localhost:8080
+//! Content-Length: 5
+//!
+//! Hello
+//! response 5 full
+//! HTTP/1.1 200 OK
+//! Content-Type: text/plain
+//!
+//! Hello
+//! ```
+//! Lines in the head end with `\r\n`, just like on the wire.
+//! Headers that usually hold secrets are redacted by default, see [`Recorder::redact`].
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    str::{self, FromStr},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::{
+    header::Headers,
+    internal::common::{epoch, random_u64},
+    middleware::Middleware,
+    path::normalize_path,
+    response::ResponseBody,
+    Header, HeaderType, Method, Request, Response, Status,
+};
+
+type FilterCallback = Box<dyn Fn(&Request) -> bool + Send + Sync>;
+
+/// The value that redacted headers are replaced with.
+const REDACTED: &str = "[redacted]";
+
+/// Middleware that saves requests and their responses to a directory.
+/// See the [module level docs](self) for the file format.
+///
+/// ## Example
+/// ```rust,no_run
+/// # use afire::{Server, Middleware, extension::Recorder};
+/// let mut server = Server::<()>::new("localhost", 8080);
+///
+/// // Record one in ten requests to the API, with up to 16 KiB of each body
+/// Recorder::new("recordings")
+///     .path("/api")
+///     .sample(0.1)
+///     .max_body(16 * 1024)
+///     .attach(&mut server);
+///
+/// server.start().unwrap();
+/// ```
+pub struct Recorder {
+    /// Directory to save recordings in.
+    dir: PathBuf,
+    /// The most bytes of each body to save.
+    max_body: usize,
+    /// The fraction of requests to record, from 0 to 1.
+    sample: f64,
+    /// Path prefixes to record, all paths are recorded if this is empty.
+    paths: Vec<String>,
+    /// Headers to redact in requests and responses.
+    redact: Vec<HeaderType>,
+    /// Decides if a request should be recorded.
+    filter: Option<FilterCallback>,
+    /// Number of recordings made, to keep file names unique.
+    count: AtomicU64,
+}
+
+/// A request and its response, as saved by a [`Recorder`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Recording {
+    /// The request method.
+    pub method: Method,
+    /// The request target, the path with the query string.
+    pub target: String,
+    /// The HTTP version of the request.
+    pub version: String,
+    /// The request headers, including cookies.
+    pub headers: Headers,
+    /// The request body, or its start if it was truncated.
+    pub body: Vec<u8>,
+    /// If the request body was cut off or not captured.
+    pub body_truncated: bool,
+
+    /// The response status.
+    pub status: Status,
+    /// The response headers.
+    /// This doesn't include the headers added as the response is written, like `Content-Length` or the server's default headers.
+    pub response_headers: Headers,
+    /// The response body, or its start if it was truncated.
+    pub response_body: Vec<u8>,
+    /// If the response body was cut off or not captured.
+    /// Streamed and file bodies are never captured.
+    pub response_body_truncated: bool,
+}
+
+impl Recorder {
+    /// Creates a new recorder that saves recordings to the given directory, which will be created if it doesn't exist.
+    /// By default every request is recorded, with up to 64 KiB of each body, and the `Authorization`, `Cookie` and `Set-Cookie` headers are redacted.
+    pub fn new(dir: impl AsRef<Path>) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+            max_body: 64 * 1024,
+            sample: 1.0,
+            paths: Vec::new(),
+            redact: vec![
+                HeaderType::Authorization,
+                HeaderType::Cookie,
+                HeaderType::SetCookie,
+            ],
+            filter: None,
+            count: AtomicU64::new(0),
+        }
+    }
+
+    /// Sets the most bytes of each request and response body to save.
+    /// Longer bodies are cut off and marked as truncated.
+    pub fn max_body(self, max_body: usize) -> Self {
+        Self { max_body, ..self }
+    }
+
+    /// Sets the fraction of requests to record, from `0.0` (none) to `1.0` (all).
+    /// Requests are picked at random.
+    pub fn sample(self, sample: f64) -> Self {
+        Self {
+            sample: sample.clamp(0.0, 1.0),
+            ..self
+        }
+    }
+
+    /// Only records requests under this path.
+    /// Can be called multiple times to record more paths.
+    /// ## Example
+    /// ```rust
+    /// # use afire::extension::Recorder;
+    /// let recorder = Recorder::new("recordings").path("/api").path("/upload");
+    /// ```
+    pub fn path(mut self, path: impl AsRef<str>) -> Self {
+        self.paths.push(normalize_path(path.as_ref().to_owned()));
+        self
+    }
+
+    /// Adds a header to redact, on top of the defaults.
+    /// Redacted headers are kept in the recording, but their value is replaced with `[redacted]`.
+    pub fn redact(mut self, header: impl Into<HeaderType>) -> Self {
+        self.redact.push(header.into());
+        self
+    }
+
+    /// Stops redacting any headers, including the defaults.
+    pub fn no_redact(self) -> Self {
+        Self {
+            redact: Vec::new(),
+            ..self
+        }
+    }
+
+    /// Sets a callback that decides if a request should be recorded.
+    /// This is checked after the path filters and before sampling.
+    pub fn filter(self, filter: impl Fn(&Request) -> bool + Send + Sync + 'static) -> Self {
+        Self {
+            filter: Some(Box::new(filter)),
+            ..self
+        }
+    }
+
+    /// Checks if a request should be recorded.
+    fn wants(&self, req: &Request) -> bool {
+        let path = req.path.trim_matches('/');
+        let path_matches = self.paths.is_empty()
+            || self.paths.iter().any(|prefix| {
+                prefix.is_empty()
+                    || path
+                        .strip_prefix(prefix.as_str())
+                        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+            });
+
+        path_matches
+            && self.filter.as_ref().is_none_or(|filter| filter(req))
+            && (self.sample >= 1.0 || (random_u64() as f64 / u64::MAX as f64) < self.sample)
+    }
+
+    /// Replaces the values of the headers that should be redacted.
+    fn redact_headers(&self, headers: &mut [Header]) {
+        for header in headers.iter_mut().filter(|x| self.redact.contains(&x.name)) {
+            header.value = REDACTED.to_owned();
+        }
+    }
+
+    /// Builds the recording of a request and its response.
+    fn record(&self, req: &Request, res: &Response) -> Recording {
+        let head = String::from_utf8_lossy(req.raw_head());
+        let mut lines = head.split("\r\n").filter(|x| !x.is_empty());
+        let mut request_line = lines.next().unwrap_or_default().splitn(3, ' ');
+        let target = request_line.nth(1).unwrap_or(&req.path).to_owned();
+
+        let mut headers = Headers(lines.filter_map(|x| Header::from_string(x).ok()).collect());
+        let mut response_headers = res.headers.clone();
+        self.redact_headers(&mut headers);
+        self.redact_headers(&mut response_headers);
+
+        // Streamed request bodies were read by the handler, so they aren't in `req.body`
+        let streamed = req.body_stream.borrow().is_some();
+        let (body, body_truncated) = self.truncate(&req.body);
+        let (response_body, response_body_truncated) = match &res.data {
+            ResponseBody::Static(data) => self.truncate(data),
+            _ => (Vec::new(), true),
+        };
+
+        Recording {
+            method: req.method.clone(),
+            target,
+            version: req.version.clone(),
+            headers,
+            body,
+            body_truncated: body_truncated || streamed,
+            status: res.status,
+            response_headers,
+            response_body,
+            response_body_truncated,
+        }
+    }
+
+    /// Cuts a body down to the max body size.
+    fn truncate(&self, body: &[u8]) -> (Vec<u8>, bool) {
+        let len = body.len().min(self.max_body);
+        (body[..len].to_vec(), len < body.len())
+    }
+
+    /// Saves a recording to a new file in the recording directory.
+    fn save(&self, recording: &Recording) -> io::Result<PathBuf> {
+        fs::create_dir_all(&self.dir)?;
+        let count = self.count.fetch_add(1, Ordering::Relaxed);
+        let path = self
+            .dir
+            .join(format!("{}-{}.rec", epoch().as_millis(), count));
+        fs::write(&path, recording.to_bytes())?;
+        Ok(path)
+    }
+}
+
+impl Middleware for Recorder {
+    fn end(&self, req: &Request, res: &Response) {
+        if !self.wants(req) {
+            return;
+        }
+
+        let recording = self.record(req, res);
+        if let Err(e) = self.save(&recording) {
+            trace!(Level::Error, "Error saving recording: {}", e);
+        }
+    }
+}
+
+impl Recording {
+    /// Loads a recording from a file.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+
+    /// Parses a recording from the contents of a `.rec` file.
+    /// Returns an [`io::ErrorKind::InvalidData`] error if it is malformed.
+    pub fn from_bytes(data: &[u8]) -> io::Result<Self> {
+        let (request, rest) = read_section(data, "request")?;
+        let (response, _) = read_section(rest, "response")?;
+
+        let mut request_line = request.start.splitn(3, ' ');
+        let (method, target, version) = match (
+            request_line.next().map(Method::from_str),
+            request_line.next(),
+            request_line.next(),
+        ) {
+            (Some(Ok(method)), Some(target), Some(version)) => (method, target, version),
+            _ => return Err(invalid("invalid request line")),
+        };
+
+        let status = response
+            .start
+            .split(' ')
+            .nth(1)
+            .and_then(|x| x.parse::<u16>().ok())
+            .ok_or_else(|| invalid("invalid status line"))?;
+
+        Ok(Self {
+            method,
+            target: target.to_owned(),
+            version: version.to_owned(),
+            headers: request.headers,
+            body: request.body.to_vec(),
+            body_truncated: request.truncated,
+            status: Status::from(status),
+            response_headers: response.headers,
+            response_body: response.body.to_vec(),
+            response_body_truncated: response.truncated,
+        })
+    }
+
+    /// Serializes the recording in the `.rec` format, see the [module level docs](self).
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        let request_line = format!("{} {} {}", self.method, self.target, self.version);
+        write_section(
+            &mut out,
+            "request",
+            &request_line,
+            &self.headers,
+            &self.body,
+            self.body_truncated,
+        );
+
+        let status_line = format!(
+            "HTTP/1.1 {} {}",
+            self.status.code(),
+            self.status.reason_phrase()
+        );
+        write_section(
+            &mut out,
+            "response",
+            &status_line,
+            &self.response_headers,
+            &self.response_body,
+            self.response_body_truncated,
+        );
+        out
+    }
+
+    /// Sends the recorded request to a server with the [`crate::client`].
+    /// The `base` is the scheme and host to send it to, like `http://localhost:8080`, and the recorded target is added to it.
+    /// The `Host`, `Content-Length`, `Connection` and `Transfer-Encoding` headers are set by the client instead of copied from the recording.
+    ///
+    /// The recorded response is not checked, so compare it with the returned response if needed.
+    /// ## Example
+    /// ```rust,no_run
+    /// # use afire::extension::recorder::Recording;
+    /// let recording = Recording::load("recordings/1700000000000-0.rec").unwrap();
+    /// let res = recording.replay("http://localhost:8080").unwrap();
+    /// assert_eq!(res.status, recording.status);
+    /// ```
+    #[cfg(feature = "client")]
+    pub fn replay(
+        &self,
+        base: impl AsRef<str>,
+    ) -> crate::error::Result<crate::client::ClientResponse> {
+        self.request(base).send()
+    }
+
+    /// Creates a [`crate::client::ClientRequest`] for the recorded request, without sending it.
+    /// See [`Recording::replay`].
+    #[cfg(feature = "client")]
+    pub fn request(&self, base: impl AsRef<str>) -> crate::client::ClientRequest {
+        let url = format!("{}{}", base.as_ref().trim_end_matches('/'), self.target);
+        let mut request = crate::client::request(self.method.clone(), url).body(self.body.clone());
+        for header in self.headers.iter().filter(|x| {
+            !matches!(
+                x.name,
+                HeaderType::Host
+                    | HeaderType::ContentLength
+                    | HeaderType::Connection
+                    | HeaderType::TransferEncoding
+            )
+        }) {
+            request.headers.push(header.clone());
+        }
+        request
+    }
+}
+
+/// One half of a recording, see [`read_section`].
+struct Section<'a> {
+    /// The request or status line.
+    start: String,
+    headers: Headers,
+    body: &'a [u8],
+    truncated: bool,
+}
+
+/// Writes the request or response section of a recording.
+fn write_section(
+    out: &mut Vec<u8>,
+    kind: &str,
+    start: &str,
+    headers: &[Header],
+    body: &[u8],
+    truncated: bool,
+) {
+    let state = if truncated { "truncated" } else { "full" };
+    out.extend_from_slice(format!("{kind} {} {state}\n{start}\r\n", body.len()).as_bytes());
+    for header in headers {
+        out.extend_from_slice(format!("{header}\r\n").as_bytes());
+    }
+    out.extend_from_slice(b"\r\n");
+    out.extend_from_slice(body);
+    out.push(b'\n');
+}
+
+/// Reads a section of a recording, returning it and the rest of the data.
+fn read_section<'a>(data: &'a [u8], kind: &str) -> io::Result<(Section<'a>, &'a [u8])> {
+    let line_end = data
+        .iter()
+        .position(|&x| x == b'\n')
+        .ok_or_else(|| invalid("missing section line"))?;
+    let line = str::from_utf8(&data[..line_end]).map_err(|_| invalid("invalid section line"))?;
+    let (len, truncated) = match line.split(' ').collect::<Vec<_>>().as_slice() {
+        [name, len, state] if *name == kind => (
+            len.parse::<usize>()
+                .map_err(|_| invalid("invalid body length"))?,
+            match *state {
+                "full" => false,
+                "truncated" => true,
+                _ => return Err(invalid("invalid body state")),
+            },
+        ),
+        _ => return Err(invalid("invalid section line")),
+    };
+
+    let data = &data[line_end + 1..];
+    let head_end = data
+        .windows(4)
+        .position(|x| x == b"\r\n\r\n")
+        .ok_or_else(|| invalid("missing end of head"))?;
+    let head = str::from_utf8(&data[..head_end]).map_err(|_| invalid("invalid head"))?;
+    let mut lines = head.split("\r\n");
+    let start = lines.next().unwrap_or_default().to_owned();
+    let headers = lines
+        .map(|x| Header::from_string(x).map_err(|_| invalid("invalid header")))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    let data = &data[head_end + 4..];
+    if data.len() <= len || data[len] != b'\n' {
+        return Err(invalid("invalid body"));
+    }
+
+    let section = Section {
+        start,
+        headers: Headers(headers),
+        body: &data[..len],
+        truncated,
+    };
+    Ok((section, &data[len + 1..]))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::Cursor,
+        sync::{Arc, Mutex},
+    };
+
+    use super::{Recorder, Recording};
+    use crate::{
+        header::Headers,
+        internal::testing::{MemoryListener, MemoryStream},
+        Header, Method, Middleware, Response, Server, Status,
+    };
+
+    #[test]
+    fn test_format() {
+        let recording = Recording {
+            method: Method::PUT,
+            target: "/file?a=b".to_owned(),
+            version: "HTTP/1.1".to_owned(),
+            headers: Headers(vec![Header::new("Host", "localhost")]),
+            body: b"line\r\n\r\nnext".to_vec(),
+            body_truncated: false,
+            status: Status::Created,
+            response_headers: Headers::default(),
+            response_body: Vec::new(),
+            response_body_truncated: true,
+        };
+
+        let bytes = recording.to_bytes();
+        assert!(bytes
+            .starts_with(b"request 12 full\nPUT /file?a=b HTTP/1.1\r\nHost: localhost\r\n\r\n"));
+        assert_eq!(Recording::from_bytes(&bytes).unwrap(), recording);
+
+        assert!(Recording::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(Recording::from_bytes(b"request 1 full\nGET / HTTP/1.1\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn test_recorder() {
+        let dir = std::env::temp_dir().join(format!("afire-recorder-{}", std::process::id()));
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let input = [
+            "POST /api/echo?x=1 HTTP/1.1\r\nHost: localhost\r\nCookie: id=1\r\nContent-Length: 11\r\n\r\nHello World",
+            "GET /other HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        ]
+        .concat();
+        let listener = MemoryListener(Mutex::new(vec![MemoryStream {
+            input: Cursor::new(input.into_bytes()),
+            output: output.clone(),
+        }]));
+
+        let mut server = Server::<()>::new("localhost", 0);
+        server.route(Method::POST, "/api/echo", |req| {
+            Response::new().bytes(&req.body).header("X-Test", "1")
+        });
+        server.route(Method::GET, "/other", |_| Response::new().text("other"));
+        Recorder::new(&dir)
+            .path("/api")
+            .max_body(5)
+            .attach(&mut server);
+        assert!(server.start_with_listener(listener).is_err());
+
+        let files = std::fs::read_dir(&dir).unwrap().collect::<Vec<_>>();
+        assert_eq!(files.len(), 1);
+        let recording = Recording::load(files[0].as_ref().unwrap().path()).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(recording.method, Method::POST);
+        assert_eq!(recording.target, "/api/echo?x=1");
+        assert_eq!(recording.headers.get("Cookie"), Some("[redacted]"));
+        assert_eq!(recording.body, b"Hello");
+        assert!(recording.body_truncated);
+        assert_eq!(recording.status, Status::Ok);
+        assert_eq!(recording.response_headers.get("X-Test"), Some("1"));
+        assert_eq!(recording.response_body, b"Hello");
+        assert!(recording.response_body_truncated);
+    }
+}
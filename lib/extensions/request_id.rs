@@ -1,21 +1,58 @@
 //! Add an ID to every incoming Request in the form of a header.
-//! The ID is just incremented on each request to not have to worry about collisions.
+//! By default the ID is just incremented on each request to not have to worry about collisions,
+//! but it can come from any generator, like [`uuid_v7`] or a [`Snowflake`].
+//!
+//! The ID is also added to the response headers and to the request's extensions as an [`Id`], which the [`crate::extension::Logger`] uses for its request id.
 
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Mutex,
+};
 
 use crate::{
+    internal::common::{epoch, random_u64},
     middleware::{MiddleResult, Middleware},
-    HeaderType, Request,
+    HeaderType, Request, Response,
 };
 
+type Generator = Box<dyn Fn() -> String + Send + Sync>;
+
 /// Add an id to every incoming Request
 ///
-/// The ID is just incremented on each request to not have to worry about collisions
+/// By default the ID is just incremented on each request to not have to worry about collisions
 pub struct RequestId {
     id_header: HeaderType,
-    id: AtomicUsize,
+    generator: Generator,
+    trust_incoming: bool,
+    echo: bool,
 }
 
+/// The id of a request, added to the request's extensions by the [`RequestId`] middleware.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Id(pub String);
+
+/// Generates [Snowflake IDs](https://en.wikipedia.org/wiki/Snowflake_ID), which are sortable by time and unique across workers.
+/// The ids are made of a 41 bit timestamp in milliseconds since 2020-01-01, a 10 bit worker id and a 12 bit sequence number.
+/// ## Example
+/// ```rust
+/// # use afire::{Server, Middleware};
+/// use afire::extension::request_id::{RequestId, Snowflake};
+///
+/// # let mut server = Server::<()>::new("localhost", 8080);
+/// let snowflake = Snowflake::new(1);
+/// RequestId::new("X-Request-Id")
+///     .generator(move || snowflake.next().to_string())
+///     .attach(&mut server);
+/// ```
+pub struct Snowflake {
+    worker: u64,
+    /// The timestamp and sequence number of the last id.
+    last: Mutex<(u64, u64)>,
+}
+
+/// The Snowflake epoch, 2020-01-01 in milliseconds since the unix epoch.
+const SNOWFLAKE_EPOCH: u64 = 1_577_836_800_000;
+
 impl RequestId {
     /// Create a new RequestId Middleware
     /// ## Example
@@ -31,20 +68,163 @@ impl RequestId {
     /// server.start().unwrap();
     ///```
     pub fn new(header: impl Into<HeaderType>) -> Self {
+        let id = AtomicUsize::new(0);
         Self {
-            id: AtomicUsize::new(0),
             id_header: header.into(),
+            generator: Box::new(move || id.fetch_add(1, Ordering::Relaxed).to_string()),
+            trust_incoming: false,
+            echo: true,
+        }
+    }
+
+    /// Sets the function used to generate ids.
+    /// ## Example
+    /// ```rust
+    /// # use afire::{Server, Middleware};
+    /// use afire::extension::request_id::{self, RequestId};
+    ///
+    /// # let mut server = Server::<()>::new("localhost", 8080);
+    /// RequestId::new("X-Request-Id")
+    ///     .generator(request_id::uuid_v7)
+    ///     .attach(&mut server);
+    /// ```
+    pub fn generator(self, generator: impl Fn() -> String + Send + Sync + 'static) -> Self {
+        Self {
+            generator: Box::new(generator),
+            ..self
         }
     }
+
+    /// Sets if an id already in the request's header is used instead of generating a new one.
+    /// Ids longer than 128 bytes or with characters other than visible ASCII are always replaced.
+    /// By default incoming ids are replaced.
+    ///
+    /// **Warning**: Only enable this if the server is behind a reverse proxy or gateway that sets the header, as clients could otherwise pick their own ids.
+    pub fn trust_incoming(self, trust_incoming: bool) -> Self {
+        Self {
+            trust_incoming,
+            ..self
+        }
+    }
+
+    /// Sets if the id is added to the response headers, so clients can reference it when reporting problems.
+    /// This is enabled by default.
+    pub fn echo(self, echo: bool) -> Self {
+        Self { echo, ..self }
+    }
+}
+
+impl Snowflake {
+    /// Creates a new Snowflake generator.
+    /// Each server generating ids should have its own worker id, only the lower 10 bits are used.
+    pub fn new(worker: u16) -> Self {
+        Self {
+            worker: worker as u64 & 0x3ff,
+            last: Mutex::new((0, 0)),
+        }
+    }
+
+    /// Generates a new id.
+    /// If more than 4096 ids are generated in one millisecond, this waits for the next millisecond.
+    pub fn next(&self) -> u64 {
+        let mut last = self.last.lock().unwrap();
+        let mut time = (epoch().as_millis() as u64).saturating_sub(SNOWFLAKE_EPOCH);
+        loop {
+            if time > last.0 {
+                *last = (time, 0);
+                break;
+            }
+
+            // Don't go backwards if the clock does
+            if last.1 < 0xfff {
+                last.1 += 1;
+                break;
+            }
+
+            std::thread::yield_now();
+            time = (epoch().as_millis() as u64).saturating_sub(SNOWFLAKE_EPOCH);
+        }
+
+        (last.0 & 0x1ff_ffff_ffff) << 22 | self.worker << 12 | last.1
+    }
+}
+
+/// Generates a [UUIDv7](https://www.rfc-editor.org/rfc/rfc9562#name-uuid-version-7), which is random but sortable by time.
+/// The randomness is not cryptographically secure, so don't use these ids as secrets.
+pub fn uuid_v7() -> String {
+    let time = epoch().as_millis() as u64 & 0xffff_ffff_ffff;
+    let (a, b) = (random_u64(), random_u64());
+    let high = time << 16 | 0x7000 | (a & 0xfff);
+    let low = (b & 0x3fff_ffff_ffff_ffff) | 0x8000_0000_0000_0000;
+
+    format!(
+        "{:08x}-{:04x}-{:04x}-{:04x}-{:012x}",
+        high >> 32,
+        (high >> 16) & 0xffff,
+        high & 0xffff,
+        low >> 48,
+        low & 0xffff_ffff_ffff
+    )
+}
+
+/// Checks if an incoming id is safe to use.
+fn valid_id(id: &str) -> bool {
+    !id.is_empty() && id.len() <= 128 && id.bytes().all(|i| i.is_ascii_graphic())
 }
 
 impl Middleware for RequestId {
     fn pre(&self, req: &mut Request) -> MiddleResult {
-        req.headers.add(
-            &self.id_header,
-            self.id.fetch_add(1, Ordering::Relaxed).to_string(),
-        );
+        let id = match req.headers.get(&self.id_header) {
+            Some(id) if self.trust_incoming && valid_id(id) => id.to_owned(),
+            _ => (self.generator)(),
+        };
 
+        req.headers.insert(&self.id_header, &id);
+        req.extensions.insert(Id(id));
         MiddleResult::Continue
     }
+
+    fn post(&self, req: &Request, res: &mut Response) -> MiddleResult {
+        if let Some(Id(id)) = req.extensions.get::<Id>() {
+            if self.echo && !res.headers.has(&self.id_header) {
+                res.headers.add(&self.id_header, id);
+            }
+        }
+
+        MiddleResult::Continue
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{uuid_v7, valid_id, Snowflake};
+
+    #[test]
+    fn test_uuid_v7() {
+        let (a, b) = (uuid_v7(), uuid_v7());
+        assert_ne!(a, b);
+        assert_eq!(a.len(), 36);
+        assert_eq!(&a[14..15], "7");
+        assert!(matches!(&a[19..20], "8" | "9" | "a" | "b"));
+        assert_eq!(
+            a.split('-').map(str::len).collect::<Vec<_>>(),
+            [8, 4, 4, 4, 12]
+        );
+    }
+
+    #[test]
+    fn test_snowflake() {
+        let snowflake = Snowflake::new(5);
+        let ids = (0..10_000).map(|_| snowflake.next()).collect::<Vec<_>>();
+        assert!(ids.windows(2).all(|i| i[0] < i[1]));
+        assert!(ids.iter().all(|i| i >> 12 & 0x3ff == 5));
+    }
+
+    #[test]
+    fn test_valid_id() {
+        assert!(valid_id("0af7651916cd43dd"));
+        assert!(!valid_id(""));
+        assert!(!valid_id("has space"));
+        assert!(!valid_id(&"a".repeat(129)));
+    }
 }
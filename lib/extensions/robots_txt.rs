@@ -0,0 +1,194 @@
+//! Serve a `robots.txt` telling crawlers which paths they can visit.
+//!
+//! The rules are built with [`RobotsTxt`] and rendered once, when it is attached.
+//! See [RFC 9309](https://www.rfc-editor.org/rfc/rfc9309) for how crawlers read them.
+
+use std::{any::type_name, fmt::Write as _, time::Duration};
+
+use crate::{
+    header::typed::CacheControl, middleware::Middleware, trace::emoji, Content, Method, Response,
+    Server,
+};
+
+/// Registers a route that serves a `robots.txt` on `/robots.txt`, with a `Cache-Control` header so crawlers don't request it again for a day.
+///
+/// Rules are grouped by the user agents they apply to.
+/// [`RobotsTxt::user_agent`] starts a new group, or adds another user agent to the current group if it doesn't have any rules yet.
+/// Rules added before any user agent apply to all crawlers (`*`).
+/// Without any rules, every crawler is allowed everywhere.
+/// ## Example
+/// ```rust
+/// # use afire::{Server, Middleware, extension::RobotsTxt};
+/// # let mut server = Server::<()>::new("localhost", 8080);
+/// RobotsTxt::new()
+///     .disallow("/admin/")
+///     .disallow("/api/")
+///     // Keep this crawler out completely
+///     .user_agent("BadBot")
+///     .disallow("/")
+///     .sitemap("https://example.com/sitemap.xml")
+///     .attach(&mut server);
+/// ```
+pub struct RobotsTxt {
+    /// The groups of rules, in order.
+    groups: Vec<Group>,
+    /// The URLs of the site's sitemaps.
+    sitemaps: Vec<String>,
+    /// How long crawlers can cache the file.
+    max_age: Duration,
+}
+
+/// Rules that apply to some user agents.
+struct Group {
+    user_agents: Vec<String>,
+    /// The rules, with true for `Allow` and false for `Disallow`.
+    rules: Vec<(bool, String)>,
+}
+
+impl RobotsTxt {
+    /// Creates a new RobotsTxt extension with no rules.
+    pub fn new() -> Self {
+        Self {
+            groups: Vec::new(),
+            sitemaps: Vec::new(),
+            max_age: Duration::from_secs(60 * 60 * 24),
+        }
+    }
+
+    /// Starts a group of rules for a user agent, like `Googlebot` or `*` for all crawlers.
+    pub fn user_agent(mut self, user_agent: impl AsRef<str>) -> Self {
+        let user_agent = user_agent.as_ref().to_owned();
+        match self.groups.last_mut() {
+            Some(group) if group.rules.is_empty() => group.user_agents.push(user_agent),
+            _ => self.groups.push(Group {
+                user_agents: vec![user_agent],
+                rules: Vec::new(),
+            }),
+        }
+        self
+    }
+
+    /// Allows crawling paths starting with `path` in the current group.
+    /// Allow rules are used to make exceptions to broader disallow rules, as everything is allowed by default.
+    pub fn allow(self, path: impl AsRef<str>) -> Self {
+        self.rule(true, path.as_ref())
+    }
+
+    /// Disallows crawling paths starting with `path` in the current group.
+    /// Use `/` to disallow everything.
+    pub fn disallow(self, path: impl AsRef<str>) -> Self {
+        self.rule(false, path.as_ref())
+    }
+
+    /// Adds the URL of a sitemap, which must be absolute.
+    /// Sitemaps aren't part of any group.
+    pub fn sitemap(mut self, url: impl AsRef<str>) -> Self {
+        self.sitemaps.push(url.as_ref().to_owned());
+        self
+    }
+
+    /// Sets how long crawlers can cache the file, by default one day.
+    pub fn max_age(self, max_age: Duration) -> Self {
+        Self { max_age, ..self }
+    }
+
+    /// Adds a rule to the current group, starting a group for all crawlers if there isn't one.
+    fn rule(mut self, allow: bool, path: &str) -> Self {
+        if self.groups.is_empty() {
+            self = self.user_agent("*");
+        }
+
+        let group = self.groups.last_mut().expect("A group was just added");
+        group.rules.push((allow, path.to_owned()));
+        self
+    }
+
+    /// Renders the rules into a `robots.txt` file.
+    fn render(&self) -> String {
+        let mut out = String::new();
+        for group in &self.groups {
+            if !out.is_empty() {
+                out.push('\n');
+            }
+
+            for user_agent in &group.user_agents {
+                let _ = writeln!(out, "User-agent: {user_agent}");
+            }
+
+            // A group without rules allows everything
+            if group.rules.is_empty() {
+                out.push_str("Disallow:\n");
+            }
+
+            for (allow, path) in &group.rules {
+                let name = if *allow { "Allow" } else { "Disallow" };
+                let _ = writeln!(out, "{name}: {path}");
+            }
+        }
+
+        if self.groups.is_empty() {
+            out.push_str("User-agent: *\nDisallow:\n");
+        }
+
+        if !self.sitemaps.is_empty() {
+            out.push('\n');
+        }
+
+        for sitemap in &self.sitemaps {
+            let _ = writeln!(out, "Sitemap: {sitemap}");
+        }
+
+        out
+    }
+}
+
+impl Middleware for RobotsTxt {
+    fn attach<State>(self, server: &mut Server<State>)
+    where
+        Self: 'static + Send + Sync + Sized,
+        State: 'static + Send + Sync,
+    {
+        trace!("{}Adding Extension {}", emoji("📦"), type_name::<Self>());
+
+        let body = self.render();
+        let cache = CacheControl::new().public().max_age(self.max_age.as_secs());
+        server.route(Method::GET, "/robots.txt", move |_| {
+            Response::new()
+                .text(&body)
+                .content(Content::TXT)
+                .cache(cache.clone())
+        });
+    }
+}
+
+impl Default for RobotsTxt {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::RobotsTxt;
+
+    #[test]
+    fn test_render() {
+        assert_eq!(RobotsTxt::new().render(), "User-agent: *\nDisallow:\n");
+
+        let robots = RobotsTxt::new()
+            .disallow("/admin/")
+            .allow("/admin/public/")
+            .user_agent("BadBot")
+            .user_agent("WorseBot")
+            .disallow("/")
+            .user_agent("Googlebot")
+            .sitemap("https://example.com/sitemap.xml");
+        assert_eq!(
+            robots.render(),
+            "User-agent: *\nDisallow: /admin/\nAllow: /admin/public/\n\n\
+             User-agent: BadBot\nUser-agent: WorseBot\nDisallow: /\n\n\
+             User-agent: Googlebot\nDisallow:\n\n\
+             Sitemap: https://example.com/sitemap.xml\n"
+        );
+    }
+}
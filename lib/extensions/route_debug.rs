@@ -0,0 +1,188 @@
+//! Serves a list of the server's routes, for checking what an app actually registered.
+
+use std::{any::type_name, fmt::Write as _};
+
+use crate::{
+    internal::common::{json_string, xml_escape},
+    middleware::{MiddleResult, Middleware},
+    path::normalize_path,
+    trace::emoji,
+    Content, Method, Request, Response, RouteInfo, Server,
+};
+
+/// Serves a list of the server's routes as an HTML page, or as JSON if the client prefers it (with `Accept: application/json`).
+/// The list is taken when the middleware is attached, so it should be attached after all the routes have been added.
+///
+/// By default it is only enabled in debug builds, so it doesn't expose the app's routes in production.
+/// ## Example
+/// ```rust
+/// # use afire::{Server, Response, Method, Middleware, extension::RouteDebug};
+/// let mut server = Server::<()>::new("localhost", 8080);
+/// server.route(Method::GET, "/", |_| Response::new().text("Hello"));
+///
+/// // Lists the routes on `/_routes`
+/// RouteDebug::new().attach(&mut server);
+/// ```
+pub struct RouteDebug {
+    /// The path to serve the route list on.
+    path: String,
+    /// If the route list is served.
+    enabled: bool,
+    /// The routes of the server, taken when the middleware is attached.
+    routes: Vec<RouteInfo>,
+}
+
+impl RouteDebug {
+    /// Create a new instance of the middleware.
+    /// The route list is served on `/_routes`, and only in debug builds.
+    pub fn new() -> Self {
+        Self {
+            path: "_routes".to_owned(),
+            enabled: cfg!(debug_assertions),
+            routes: Vec::new(),
+        }
+    }
+
+    /// Set the path to serve the route list on.
+    /// ## Example
+    /// ```rust
+    /// # use afire::extension::RouteDebug;
+    /// let route_debug = RouteDebug::new().path("/debug/routes");
+    /// ```
+    pub fn path(self, path: impl AsRef<str>) -> Self {
+        Self {
+            path: normalize_path(path.as_ref().to_owned()),
+            ..self
+        }
+    }
+
+    /// Set if the route list is served, overriding the default of only serving it in debug builds.
+    /// ## Example
+    /// ```rust
+    /// # use afire::extension::RouteDebug;
+    /// let route_debug = RouteDebug::new().enabled(std::env::var("DEBUG_ROUTES").is_ok());
+    /// ```
+    pub fn enabled(self, enabled: bool) -> Self {
+        Self { enabled, ..self }
+    }
+
+    fn json(&self) -> String {
+        let routes = self
+            .routes
+            .iter()
+            .map(|i| {
+                let meta = i
+                    .meta
+                    .iter()
+                    .map(|(k, v)| format!("[{},{}]", json_string(k), json_string(v)))
+                    .collect::<Vec<_>>();
+                format!(
+                    "{{\"method\":{},\"path\":{},\"name\":{},\"priority\":{},\"meta\":[{}]}}",
+                    json_string(&i.method.to_string()),
+                    json_string(&i.path),
+                    i.name.as_deref().map_or("null".to_owned(), json_string),
+                    i.priority,
+                    meta.join(",")
+                )
+            })
+            .collect::<Vec<_>>();
+        format!("[{}]", routes.join(","))
+    }
+
+    fn html(&self) -> String {
+        let mut rows = String::new();
+        for i in &self.routes {
+            let meta = i
+                .meta
+                .iter()
+                .map(|(k, v)| format!("{k}={v}"))
+                .collect::<Vec<_>>();
+            let _ = write!(
+                rows,
+                "<tr><td>{}</td><td><code>{}</code></td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                xml_escape(&i.method.to_string()),
+                xml_escape(&i.path),
+                xml_escape(i.name.as_deref().unwrap_or("")),
+                i.priority,
+                xml_escape(&meta.join(", "))
+            );
+        }
+
+        format!(
+            "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>Routes</title></head><body>\
+             <h1>Routes ({})</h1><table><tr><th>Method</th><th>Path</th><th>Name</th><th>Priority</th><th>Meta</th></tr>{}</table>\
+             </body></html>",
+            self.routes.len(),
+            rows
+        )
+    }
+}
+
+impl Middleware for RouteDebug {
+    fn pre(&self, req: &mut Request) -> MiddleResult {
+        if !self.enabled
+            || req.method != Method::GET
+            || normalize_path(req.path.to_owned()) != self.path
+        {
+            return MiddleResult::Continue;
+        }
+
+        let res = match req.accepts(&[Content::HTML, Content::JSON]) {
+            Some(Content::JSON) => Response::new().text(self.json()).content(Content::JSON),
+            _ => Response::new().text(self.html()).content(Content::HTML),
+        };
+        MiddleResult::Send(res)
+    }
+
+    fn attach<State>(mut self, server: &mut Server<State>)
+    where
+        Self: 'static + Send + Sync + Sized,
+        State: 'static + Send + Sync,
+    {
+        trace!("{}Adding Middleware {}", emoji("📦"), type_name::<Self>());
+
+        self.routes = server.routes();
+        server.middleware.push(Box::new(self));
+    }
+}
+
+impl Default for RouteDebug {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::RouteDebug;
+    use crate::{Method, RouteInfo, RouteMeta};
+
+    #[test]
+    fn test_route_debug_json() {
+        let mut route_debug = RouteDebug::new();
+        route_debug.routes = vec![
+            RouteInfo {
+                method: Method::GET,
+                path: "/user/{id}".to_owned(),
+                name: Some("get_user".to_owned()),
+                priority: 0,
+                meta: RouteMeta::default(),
+                docs: None,
+            },
+            RouteInfo {
+                method: Method::ANY,
+                path: "/**".to_owned(),
+                name: None,
+                priority: -1,
+                meta: RouteMeta::default(),
+                docs: None,
+            },
+        ];
+        route_debug.routes[0].meta.add("auth", "user");
+
+        assert_eq!(
+            route_debug.json(),
+            r#"[{"method":"GET","path":"/user/{id}","name":"get_user","priority":0,"meta":[["auth","user"]]},{"method":"ANY","path":"/**","name":null,"priority":-1,"meta":[]}]"#
+        );
+    }
+}
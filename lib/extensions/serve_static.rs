@@ -1,15 +1,33 @@
 //! Serve Static Content from the file system.
-
-use std::{borrow::Cow, fs::File, rc::Rc};
+//!
+//! Files are sent with a `Last-Modified` header, and a `304 Not Modified` response is sent if they haven't changed since the request's `If-Modified-Since` date.
+//! Range requests are supported with [`crate::Download`], so downloads can be resumed and media can be seeked.
+
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fs::{self, File, Metadata},
+    io::{self, Cursor, Read, Seek},
+    path::PathBuf,
+    rc::Rc,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 use crate::{
+    encoding::url,
     error::{HandleError, Result},
+    internal::common::ForceLock,
+    internal::date::{imp_date, parse_date},
     middleware::{MiddleResult, Middleware},
     path::normalize_path,
-    Error, HeaderType, Request, Response, Status,
+    Download, Error, HeaderType, Request, Response, Status,
 };
 
+pub use crate::content_type::{get_type, MIME, TYPES};
+
 type SSMiddleware = Box<dyn Fn(Rc<Request>, &mut Response, &mut bool) + Send + Sync>;
+type SSPreMiddleware = Box<dyn Fn(&Request, &mut PathBuf) -> Option<Response> + Send + Sync>;
 
 /// Serve Static Content
 pub struct ServeStatic {
@@ -32,8 +50,42 @@ pub struct ServeStatic {
     /// (Request, Static Response, success [eg If file found])
     pub middleware: Vec<SSMiddleware>,
 
+    /// Middleware run before a file is opened
+    ///
+    /// (Request, File path) -> Response to send instead
+    pub pre_middleware: Vec<SSPreMiddleware>,
+
     /// MIME Types
     pub types: Vec<(String, String)>,
+
+    /// Cache of small files, see [`ServeStatic::cache`].
+    cache: Option<FileCache>,
+}
+
+/// Keeps the contents of small files in memory.
+struct FileCache {
+    /// The largest file that will be cached, in bytes.
+    max_entry: u64,
+    /// The max total size of the cached files, in bytes.
+    max_size: u64,
+    inner: Mutex<FileCacheInner>,
+}
+
+struct FileCacheInner {
+    /// Cached files, by path.
+    files: HashMap<String, CachedFile>,
+    /// The total size of the cached files.
+    size: u64,
+    /// Counts cache lookups, used to find the least recently used file.
+    tick: u64,
+}
+
+struct CachedFile {
+    data: Arc<[u8]>,
+    /// When the file was modified when it was cached.
+    modified: Option<SystemTime>,
+    /// The tick the file was last used on.
+    last_used: u64,
 }
 
 impl Middleware for ServeStatic {
@@ -55,7 +107,7 @@ impl Middleware for ServeStatic {
             _ => return MiddleResult::Continue,
         };
 
-        if !path.starts_with(&self.serve_path) {
+        if !normalize_path(path.to_owned()).starts_with(&self.serve_path) {
             return MiddleResult::Continue;
         }
 
@@ -90,6 +142,7 @@ impl ServeStatic {
             data_dir: data_path.as_ref().to_string(),
             disabled_files: Vec::new(),
             middleware: Vec::new(),
+            pre_middleware: Vec::new(),
             not_found: |req, _| {
                 Response::new()
                     .status(Status::NotFound)
@@ -97,6 +150,7 @@ impl ServeStatic {
                     .header(HeaderType::ContentType, "text/plain")
             },
             types: Vec::new(),
+            cache: None,
         }
     }
 
@@ -190,8 +244,8 @@ impl ServeStatic {
 
     /// Add a MIME type to the Static file Server
     ///
-    /// This extension comes with a lot of builtin MIME types
-    /// but if you need to add more thats what this is for
+    /// Files use the server's MIME types (see [`crate::Server::mime`]) by default,
+    /// types added here override them only for this static file server
     ///
     /// The key is the file extension
     ///
@@ -272,6 +326,91 @@ impl ServeStatic {
         Self { middleware, ..self }
     }
 
+    /// Add a middleware that runs before a file is opened.
+    /// Like [`ServeStatic::middleware`], the most recently added one runs first.
+    ///
+    /// The middleware function gets the request and the path of the file on disk (including the data dir), which it can change to serve a different file.
+    /// If it returns a response, that is sent instead of the file and no other middleware run before the normal [`ServeStatic::middleware`].
+    /// This is useful for checking that the client is allowed to access a file.
+    /// ## Example
+    /// ```rust,no_run
+    /// // Import Library
+    /// use afire::{Response, Server, extension::ServeStatic, Middleware, Status};
+    ///
+    /// // Create a server for localhost on port 8080
+    /// let mut server = Server::<()>::new("localhost", 8080);
+    ///
+    /// // Make a new static server
+    /// ServeStatic::new("data/static")
+    ///     // Only let admins see files in the admin dir
+    ///     .pre_middleware(|req, path| {
+    ///         let admin = req.headers.get("Authorization") == Some("Bearer admin");
+    ///         (path.starts_with("data/static/admin") && !admin)
+    ///             .then(|| Response::new().status(Status::Forbidden).text("Forbidden"))
+    ///     })
+    ///     // Serve `app.html` for all pages of the single page app
+    ///     .pre_middleware(|_req, path| {
+    ///         if path.starts_with("data/static/app") && !path.exists() {
+    ///             *path = "data/static/app.html".into();
+    ///         }
+    ///         None
+    ///     })
+    ///     // Attach it to the afire server
+    ///     .attach(&mut server);
+    ///
+    /// server.start().unwrap();
+    /// ```
+    pub fn pre_middleware(
+        self,
+        f: impl Fn(&Request, &mut PathBuf) -> Option<Response> + Send + Sync + 'static,
+    ) -> Self {
+        let mut pre_middleware = self.pre_middleware;
+        pre_middleware.push(Box::new(f));
+
+        Self {
+            pre_middleware,
+            ..self
+        }
+    }
+
+    /// Keep files up to `max_entry` bytes in memory, so they don't have to be read from disk for every request.
+    /// The least recently used files are removed once the cached files take up more than `max_size` bytes.
+    ///
+    /// The modified date and size of files is still checked on every request, and changed files are read again.
+    /// ## Example
+    /// ```rust,no_run
+    /// // Import Library
+    /// use afire::{Server, extension::ServeStatic, Middleware};
+    ///
+    /// // Create a server for localhost on port 8080
+    /// let mut server = Server::<()>::new("localhost", 8080);
+    ///
+    /// // Make a new static server
+    /// ServeStatic::new("data/static")
+    ///     // Cache files up to 64 KiB, using at most 16 MiB
+    ///     .cache(64 * 1024, 16 * 1024 * 1024)
+    ///     // Attach it to the afire server
+    ///     .attach(&mut server);
+    ///
+    /// server.start().unwrap();
+    /// ```
+    pub fn cache(self, max_entry: u64, max_size: u64) -> Self {
+        let cache = FileCache {
+            max_entry,
+            max_size,
+            inner: Mutex::new(FileCacheInner {
+                files: HashMap::new(),
+                size: 0,
+                tick: 0,
+            }),
+        };
+
+        Self {
+            cache: Some(cache),
+            ..self
+        }
+    }
+
     /// Set path to serve static files on
     ///
     /// Default is '/' (root)
@@ -301,11 +440,10 @@ impl ServeStatic {
 }
 
 fn process_req(req: Rc<Request>, this: &ServeStatic) -> (Response, bool) {
-    let mut path = format!(
-        "{}/{}",
-        this.data_dir,
-        safe_path(req.path.strip_prefix(&this.serve_path).unwrap())
-    );
+    let path = normalize_path(req.path.to_owned());
+    let path = path.strip_prefix(&this.serve_path).unwrap();
+    let path = url::decode_path(path).unwrap_or_else(|| path.to_owned());
+    let mut path = format!("{}/{}", this.data_dir, safe_path(&path));
 
     // Add Index.html if path ends with /
     if path.ends_with('/') {
@@ -324,23 +462,133 @@ fn process_req(req: Rc<Request>, this: &ServeStatic) -> (Response, bool) {
         return ((this.not_found)(req, true), false);
     }
 
+    let mut file_path = PathBuf::from(path);
+    for i in this.pre_middleware.iter().rev() {
+        if let Some(res) = i(&req, &mut file_path) {
+            return (res, false);
+        }
+    }
+    let path = file_path.to_string_lossy();
+
     // Try to read File
     let ext = path.rsplit('.').next().unwrap_or_default();
-    let file = match File::open(&path) {
-        Ok(i) => i,
-        Err(_) => return ((this.not_found)(req, false), false),
+    let metadata = match fs::metadata(&*path) {
+        Ok(i) if i.is_file() => i,
+        _ => return ((this.not_found)(req, false), false),
     };
 
-    let content_type = get_type(ext, &TYPES)
-        .or_else(|| this.types.iter().find(|x| x.0 == ext).map(|x| x.1.as_str()))
-        .unwrap_or("application/octet-stream");
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|i| i.duration_since(UNIX_EPOCH).ok())
+        .map(|i| i.as_secs());
+
+    if let Some(modified) = modified {
+        let since = req
+            .headers
+            .get(HeaderType::IfModifiedSince)
+            .and_then(parse_date);
+        // If-Modified-Since is ignored when If-None-Match is sent
+        if since.is_some_and(|i| modified <= i) && !req.headers.has(HeaderType::IfNoneMatch) {
+            let res = Response::new()
+                .status(Status::NotModified)
+                .header(HeaderType::LastModified, imp_date(modified));
+            return (res, true);
+        }
+    }
+
+    let res = match this.cache.as_ref().and_then(|i| i.get(&path, &metadata)) {
+        Some(data) => file_response(&req, this, Download::new(Cursor::new(data)), ext, modified),
+        None => File::open(&*path)
+            .and_then(|file| file_response(&req, this, Download::new(file), ext, modified)),
+    };
+
+    match res {
+        Ok(res) => (res, true),
+        Err(_) => ((this.not_found)(req, false), false),
+    }
+}
 
-    let mut res = Response::new();
-    if let Ok(i) = file.metadata() {
-        res.headers.add("Content-Length", i.len().to_string());
+/// Makes the response for a file, with its content type and last modified date.
+fn file_response<R: Read + Seek + Send + 'static>(
+    req: &Request,
+    this: &ServeStatic,
+    download: Download<R>,
+    ext: &str,
+    modified: Option<u64>,
+) -> io::Result<Response> {
+    // Types added to the extension take priority over the server's
+    let content_type = this
+        .types
+        .iter()
+        .find(|x| x.0.trim_start_matches('.').eq_ignore_ascii_case(ext))
+        .map(|x| x.1.as_str());
+
+    let mut download = match content_type {
+        Some(i) => download.content_type(i),
+        None => download.extension(ext),
+    };
+    if let Some(modified) = modified {
+        download = download.last_modified(modified);
     }
 
-    (res.stream(file).header("Content-Type", content_type), true)
+    download.response(req)
+}
+
+impl FileCache {
+    /// Gets the contents of a file from the cache, loading it if it isn't cached or has changed.
+    /// Returns None if the file is too large to cache or can't be read.
+    fn get(&self, path: &str, metadata: &Metadata) -> Option<Arc<[u8]>> {
+        if metadata.len() > self.max_entry {
+            return None;
+        }
+
+        let modified = metadata.modified().ok();
+        let mut inner = self.inner.force_lock();
+        inner.tick += 1;
+        let tick = inner.tick;
+
+        if let Some(file) = inner.files.get_mut(path) {
+            if file.modified == modified && file.data.len() as u64 == metadata.len() {
+                file.last_used = tick;
+                return Some(file.data.clone());
+            }
+
+            let old = inner.files.remove(path).unwrap();
+            inner.size -= old.data.len() as u64;
+        }
+        drop(inner);
+
+        // Read the file without holding the lock, so other files can be served meanwhile
+        let data = Arc::<[u8]>::from(fs::read(path).ok()?);
+        let len = data.len() as u64;
+        if len > self.max_entry || len > self.max_size {
+            return Some(data);
+        }
+
+        let mut inner = self.inner.force_lock();
+        while inner.size + len > self.max_size {
+            let oldest = inner
+                .files
+                .iter()
+                .min_by_key(|(_, file)| file.last_used)
+                .map(|(path, _)| path.to_owned())
+                .unwrap();
+            let old = inner.files.remove(&oldest).unwrap();
+            inner.size -= old.data.len() as u64;
+        }
+
+        let file = CachedFile {
+            data: data.clone(),
+            modified,
+            last_used: tick,
+        };
+        inner.size += len;
+        if let Some(old) = inner.files.insert(path.to_owned(), file) {
+            inner.size -= old.data.len() as u64;
+        }
+        Some(data)
+    }
 }
 
 /// Prevents path traversals.
@@ -364,90 +612,99 @@ pub fn safe_path(path: &str) -> Cow<'_, str> {
     Cow::Owned(out.join("/"))
 }
 
-/// Common MIME Types (sorted in alphabetical order by extension)
-///
-/// Used by ServeStatic extension
-pub const TYPES: [MIME; 56] = [
-    MIME::new("7z", "application/x-7z-compressed"),
-    MIME::new("aac", "audio/aac"),
-    MIME::new("avi", "video/x-msvideo"),
-    MIME::new("bin", "application/octet-stream"),
-    MIME::new("bmp", "image/bmp"),
-    MIME::new("bz", "application/x-bzip"),
-    MIME::new("bz2", "application/x-bzip2"),
-    MIME::new("cda", "application/x-cdf"),
-    MIME::new("css", "text/css"),
-    MIME::new("csv", "text/csv"),
-    MIME::new("epub", "application/epub+zip"),
-    MIME::new("gif", "image/gif"),
-    MIME::new("gz", "application/gzip"),
-    MIME::new("htm", "text/html"),
-    MIME::new("html", "text/html"),
-    MIME::new("ico", "image/x-icon"),
-    MIME::new("ics", "text/calendar"),
-    MIME::new("jar", "application/java-archive"),
-    MIME::new("jpeg", "image/jpeg"),
-    MIME::new("jpg", "image/jpeg"),
-    MIME::new("js", "application/javascript"),
-    MIME::new("json", "application/json"),
-    MIME::new("jsonld", "application/ld+json"),
-    MIME::new("mid", "audio/midi audio/x-midi"),
-    MIME::new("midi", "audio/midi audio/x-midi"),
-    MIME::new("mjs", "text/javascript"),
-    MIME::new("mp3", "audio/mpeg"),
-    MIME::new("mp4", "video/mp4"),
-    MIME::new("mpeg", "video/mpeg"),
-    MIME::new("oga", "audio/ogg"),
-    MIME::new("ogv", "video/ogg"),
-    MIME::new("ogx", "application/ogg"),
-    MIME::new("opus", "audio/opus"),
-    MIME::new("otf", "font/otf"),
-    MIME::new("pdf", "application/pdf"),
-    MIME::new("png", "image/png"),
-    MIME::new("rar", "application/vnd.rar"),
-    MIME::new("rtf", "application/rtf"),
-    MIME::new("sh", "application/x-sh"),
-    MIME::new("svg", "image/svg+xml"),
-    MIME::new("swf", "application/x-shockwave-flash"),
-    MIME::new("tar", "application/x-tar"),
-    MIME::new("tif", "image/tiff"),
-    MIME::new("tiff", "image/tiff"),
-    MIME::new("ts", "text/x-typescript"),
-    MIME::new("ttf", "font/ttf"),
-    MIME::new("txt", "text/plain"),
-    MIME::new("wav", "audio/wav"),
-    MIME::new("weba", "audio/webm"),
-    MIME::new("webm", "video/webm"),
-    MIME::new("webp", "image/webp"),
-    MIME::new("woff", "font/woff"),
-    MIME::new("woff2", "font/woff2"),
-    MIME::new("xhtml", "application/xhtml+xml"),
-    MIME::new("xml", "application/xml"),
-    MIME::new("zip", "application/zip"),
-];
-
-/// Struct to hold a file extension and its matching MIME type
-#[derive(Debug, Clone)]
-pub struct MIME {
-    extension: &'static str,
-    mime_type: &'static str,
-}
+#[cfg(test)]
+mod test {
+    use std::{
+        env, fs,
+        io::Cursor,
+        process,
+        sync::{Arc, Mutex},
+    };
 
-impl MIME {
-    /// Create a new MIME type
-    pub const fn new(extension: &'static str, mime_type: &'static str) -> Self {
-        Self {
-            extension,
-            mime_type,
+    use super::ServeStatic;
+    use crate::{
+        internal::testing::{MemoryListener, MemoryStream},
+        Middleware, Response, Server, Status,
+    };
+
+    #[test]
+    fn test_file_cache() {
+        let dir = env::temp_dir().join(format!("afire-cache-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = |name: &str| dir.join(name).to_string_lossy().into_owned();
+        for (name, data) in [
+            ("a", "aaaa"),
+            ("b", "bbbb"),
+            ("c", "cccc"),
+            ("big", "0123456789ab"),
+        ] {
+            fs::write(path(name), data).unwrap();
         }
+
+        let serve = ServeStatic::new("").cache(10, 8);
+        let cache = serve.cache.as_ref().unwrap();
+        let get = |name: &str| {
+            let data = cache.get(&path(name), &fs::metadata(path(name)).unwrap());
+            data.map(|i| String::from_utf8(i.to_vec()).unwrap())
+        };
+        let cached = |name: &str| cache.inner.lock().unwrap().files.contains_key(&path(name));
+
+        assert_eq!(get("big"), None);
+        assert_eq!(get("a").as_deref(), Some("aaaa"));
+        assert_eq!(get("b").as_deref(), Some("bbbb"));
+        assert_eq!(get("a").as_deref(), Some("aaaa"));
+
+        // b is the least recently used
+        assert_eq!(get("c").as_deref(), Some("cccc"));
+        assert!(cached("a") && !cached("b") && cached("c"));
+
+        fs::write(path("a"), "new").unwrap();
+        assert_eq!(get("a").as_deref(), Some("new"));
+        assert_eq!(cache.inner.lock().unwrap().size, 7);
+
+        fs::remove_dir_all(dir).unwrap();
     }
-}
 
-/// Gets the MIME type from the specified file extension using a slice of MIME types.
-/// If no type is found, None is returned.
-pub fn get_type(ext: &str, extensions: &[MIME]) -> Option<&'static str> {
-    extensions
-        .binary_search_by(|x| x.extension.cmp(ext))
-        .map(|x| TYPES[x].mime_type)
-        .ok()
+    #[test]
+    fn test_serve_static_pre_middleware() {
+        let dir = std::env::temp_dir().join(format!("afire-static-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("admin")).unwrap();
+        std::fs::write(dir.join("admin/secret.txt"), b"secret").unwrap();
+        std::fs::write(dir.join("app.html"), b"app").unwrap();
+
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let requests = [
+            "GET /admin/secret.txt HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            "GET /admin/secret.txt HTTP/1.1\r\nHost: localhost\r\nAuthorization: admin\r\n\r\n",
+            "GET /app/settings HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        ];
+        let listener = MemoryListener(Mutex::new(vec![MemoryStream {
+            input: Cursor::new(requests.concat().into_bytes()),
+            output: output.clone(),
+        }]));
+
+        let mut server = Server::<()>::new("localhost", 0);
+        let (admin, app) = (dir.join("admin"), dir.join("app"));
+        let index = dir.join("app.html");
+        ServeStatic::new(dir.to_string_lossy())
+            .pre_middleware(move |req, path| {
+                (path.starts_with(&admin) && !req.headers.has("Authorization"))
+                    .then(|| Response::new().status(Status::Forbidden))
+            })
+            .pre_middleware(move |_req, path| {
+                if path.starts_with(&app) {
+                    *path = index.clone();
+                }
+                None
+            })
+            .attach(&mut server);
+        assert!(server.start_with_listener(listener).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        assert!(output.contains("HTTP/1.1 403 Forbidden\r\n"));
+        assert!(output.contains("\r\n\r\nsecret"));
+        assert!(output.contains("\r\nContent-Type: text/html\r\n"));
+        assert!(output.contains("\r\n\r\napp"));
+    }
 }
@@ -0,0 +1,273 @@
+//! Limit the bandwidth used by responses.
+//!
+//! Response bodies are sent through a [token bucket](https://en.wikipedia.org/wiki/Token_bucket), which is refilled at the configured rate.
+//! This is useful for sharing bandwidth fairly between clients of a download server, or simulating slow networks when testing.
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    io::{self, Cursor, Read},
+    mem,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    internal::{common::ForceLock, handle::Writeable},
+    middleware::{MiddleResult, Middleware},
+    response::ResponseBody,
+    HeaderType, Method, Request, Response, Status,
+};
+
+/// What the bandwidth limit of a [`Throttle`] applies to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ThrottleScope {
+    /// Each connection gets the full rate.
+    /// This is the default.
+    #[default]
+    Connection,
+    /// All connections from the same IP address share the rate.
+    Client,
+}
+
+/// Middleware that limits how fast response bodies are sent.
+///
+/// The limit applies to every write of a response body, so responses with large or streaming bodies are slowed down while headers and small responses within the burst size are sent right away.
+/// Responses to `HEAD` requests and responses that never have a body are not throttled.
+///
+/// Middleware run in the reverse order they were attached, so attach this before other middleware that make responses (like [`crate::extension::ServeStatic`]) to throttle them too.
+///
+/// **Note**: Throttled files are streamed through the throttle, so they aren't sent with `sendfile`.
+/// ## Example
+/// ```rust
+/// # use afire::{Server, Middleware, extension::{Throttle, throttle::ThrottleScope}};
+/// # let mut server = Server::<()>::new("localhost", 8080);
+/// // Let each client download at 1 MiB/s, shared between all their connections
+/// Throttle::new(1024 * 1024)
+///     .scope(ThrottleScope::Client)
+///     .attach(&mut server);
+/// ```
+pub struct Throttle {
+    /// The bytes per second bodies are sent at.
+    rate: u64,
+    /// How many bytes can be sent at once after not sending anything.
+    burst: u64,
+    /// What the limit applies to.
+    scope: ThrottleScope,
+    /// The buckets shared by all connections from a client, used with [`ThrottleScope::Client`].
+    clients: Mutex<HashMap<IpAddr, Arc<TokenBucket>>>,
+}
+
+/// The bucket of a connection, stored in the connection's extensions.
+struct ConnectionBucket(Arc<TokenBucket>);
+
+/// Tokens are taken for each byte sent and refilled at a constant rate.
+struct TokenBucket {
+    /// Tokens added per second.
+    rate: f64,
+    /// The max number of tokens.
+    burst: f64,
+    /// The tokens available and when they were last refilled.
+    state: Mutex<(f64, Instant)>,
+}
+
+/// Reads a body, waiting for tokens before each read.
+struct ThrottledReader<R> {
+    inner: R,
+    bucket: Arc<TokenBucket>,
+    /// The bytes left to read, if the length of the body is known.
+    /// This lets the end of the body be found without waiting for more tokens.
+    remaining: Option<u64>,
+}
+
+/// A stream body taken out of a response.
+struct StreamBody(Writeable);
+
+impl Throttle {
+    /// Creates a new Throttle that limits each connection to `rate` bytes per second.
+    /// The burst size defaults to one second worth of bytes.
+    pub fn new(rate: u64) -> Self {
+        Self {
+            rate: rate.max(1),
+            burst: rate.max(1),
+            scope: ThrottleScope::Connection,
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Sets how many bytes can be sent at once after nothing has been sent for a while.
+    /// Bodies are read in pieces of at most this size.
+    pub fn burst(self, burst: u64) -> Self {
+        Self {
+            burst: burst.max(1),
+            ..self
+        }
+    }
+
+    /// Sets what the limit applies to, see [`ThrottleScope`].
+    pub fn scope(self, scope: ThrottleScope) -> Self {
+        Self { scope, ..self }
+    }
+
+    /// Gets the bucket a request's response is throttled with.
+    fn bucket(&self, req: &Request) -> Arc<TokenBucket> {
+        let new = || Arc::new(TokenBucket::new(self.rate, self.burst));
+        match self.scope {
+            ThrottleScope::Connection => {
+                let mut extensions = req.connection().extensions();
+                if let Some(i) = extensions.get::<ConnectionBucket>() {
+                    return i.0.clone();
+                }
+
+                let bucket = new();
+                extensions.insert(ConnectionBucket(bucket.clone()));
+                bucket
+            }
+            ThrottleScope::Client => {
+                let mut clients = self.clients.force_lock();
+                let ip = req.address.ip();
+                if let Some(i) = clients.get(&ip) {
+                    return i.clone();
+                }
+
+                // Forget clients that aren't being sent anything
+                clients.retain(|_, i| Arc::strong_count(i) > 1);
+                clients.entry(ip).or_insert_with(new).clone()
+            }
+        }
+    }
+}
+
+impl Middleware for Throttle {
+    fn post(&self, req: &Request, res: &mut Response) -> MiddleResult {
+        let bodiless = matches!(res.status, Status::NoContent | Status::NotModified);
+        if req.method == Method::HEAD || bodiless {
+            return MiddleResult::Continue;
+        }
+
+        let bucket = self.bucket(req);
+        let body = mem::replace(&mut res.data, ResponseBody::Static(Arc::new([])));
+        res.data = match body {
+            ResponseBody::Static(data) if data.is_empty() => ResponseBody::Static(data),
+            ResponseBody::Static(data) => {
+                let len = data.len() as u64;
+                if !res.headers.has(HeaderType::ContentLength) {
+                    res.headers.add(HeaderType::ContentLength, len.to_string());
+                }
+                ThrottledReader::body(Cursor::new(data), bucket, Some(len))
+            }
+            ResponseBody::File(file) => {
+                let len = file.metadata().ok().map(|i| i.len());
+                if let (false, Some(len)) = (res.headers.has(HeaderType::ContentLength), len) {
+                    res.headers.add(HeaderType::ContentLength, len.to_string());
+                }
+                ThrottledReader::body(file, bucket, len)
+            }
+            ResponseBody::Stream(stream) => ThrottledReader::body(StreamBody(stream), bucket, None),
+        };
+
+        MiddleResult::Continue
+    }
+}
+
+impl TokenBucket {
+    /// Creates a new full bucket.
+    fn new(rate: u64, burst: u64) -> Self {
+        Self {
+            rate: rate as f64,
+            burst: burst as f64,
+            state: Mutex::new((burst as f64, Instant::now())),
+        }
+    }
+
+    /// Waits until tokens are available, then takes up to `want` of them.
+    /// Returns how many were taken, which is at least one.
+    fn take(&self, want: u64) -> u64 {
+        let need = (want as f64).min(self.burst).max(1.0);
+        loop {
+            let mut state = self.state.force_lock();
+            let now = Instant::now();
+            state.0 =
+                (state.0 + now.duration_since(state.1).as_secs_f64() * self.rate).min(self.burst);
+            state.1 = now;
+
+            if state.0 >= need {
+                state.0 -= need;
+                return need as u64;
+            }
+
+            let wait = (need - state.0) / self.rate;
+            drop(state);
+            thread::sleep(Duration::from_secs_f64(wait));
+        }
+    }
+
+    /// Returns tokens that were taken but not used.
+    fn give_back(&self, tokens: u64) {
+        let mut state = self.state.force_lock();
+        state.0 = (state.0 + tokens as f64).min(self.burst);
+    }
+}
+
+impl<R: Read + Send + 'static> ThrottledReader<R> {
+    /// Makes a stream body that reads `inner` through the bucket.
+    fn body(inner: R, bucket: Arc<TokenBucket>, remaining: Option<u64>) -> ResponseBody {
+        let reader: Writeable = Box::new(RefCell::new(Self {
+            inner,
+            bucket,
+            remaining,
+        }));
+        ResponseBody::Stream(reader)
+    }
+}
+
+impl<R: Read> Read for ThrottledReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let want = match self.remaining {
+            Some(remaining) => remaining.min(buf.len() as u64),
+            None => buf.len() as u64,
+        };
+        if want == 0 {
+            return Ok(0);
+        }
+
+        let tokens = self.bucket.take(want);
+        let read = self.inner.read(&mut buf[..tokens as usize]);
+        let used = *read.as_ref().unwrap_or(&0) as u64;
+        self.bucket.give_back(tokens - used);
+        if let Some(remaining) = &mut self.remaining {
+            *remaining -= used;
+        }
+        read
+    }
+}
+
+impl Read for StreamBody {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.get_mut().read(buf)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use super::TokenBucket;
+
+    #[test]
+    fn test_token_bucket() {
+        let bucket = TokenBucket::new(1000, 100);
+        assert_eq!(bucket.take(500), 100);
+        assert_eq!(bucket.take(10), 10);
+
+        // The bucket has to refill 100 tokens at 1000/s
+        let start = Instant::now();
+        bucket.give_back(5);
+        assert_eq!(bucket.take(100), 100);
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(80), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(500), "{:?}", elapsed);
+    }
+}
@@ -0,0 +1,293 @@
+//! Bound how long handlers can take to respond.
+//!
+//! Rust threads can't be interrupted, so a handler that takes too long keeps running on its worker.
+//! Instead, once the deadline passes the client is sent a `504 Gateway Time-out` right away, and whatever the handler returns afterwards is thrown away and the connection is closed.
+//!
+//! Handlers can cooperate by checking the [`Deadline`] in the request's extensions, to give up on work that won't be sent anyway.
+
+use std::{
+    io::Write,
+    rc::Rc,
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        Arc, Condvar, Mutex, Once, Weak,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    error::Result,
+    internal::common::ForceLock,
+    listener::Stream,
+    middleware::{MiddleResult, Middleware},
+    Request, Response,
+};
+
+/// The longest the watcher thread sleeps, so it notices when the middleware is dropped.
+const MAX_SLEEP: Duration = Duration::from_secs(1);
+
+/// The request is still being handled.
+const PENDING: u8 = 0;
+/// The handler responded in time.
+const DONE: u8 = 1;
+/// The deadline passed and the timeout response was sent.
+const TIMED_OUT: u8 = 2;
+/// The handler cancelled the deadline.
+const CANCELLED: u8 = 3;
+
+/// Middleware that sends a `504 Gateway Time-out` to clients when handling their request takes too long.
+///
+/// The deadline starts when this middleware's pre hook runs, so attach it last to have it include the time spent in other middleware.
+/// It only applies to HTTP/1 requests, as HTTP/2 streams share their connection.
+/// ## Example
+/// ```rust
+/// # use std::time::Duration;
+/// # use afire::{Server, Middleware, Response, Method, extension::{Timeout, timeout::Deadline}};
+/// # let mut server = Server::<()>::new("localhost", 8080);
+/// Timeout::new(Duration::from_secs(30)).attach(&mut server);
+///
+/// server.route(Method::GET, "/report", |req| {
+///     let deadline = req.extensions.get::<Deadline>().unwrap();
+///     let mut rows = Vec::new();
+///     for i in 0..1000 {
+///         // Stop working once the client has been sent the timeout response
+///         if deadline.expired() {
+///             break;
+///         }
+///         rows.push(i.to_string());
+///     }
+///     Response::new().text(rows.join("\n"))
+/// });
+/// ```
+pub struct Timeout {
+    /// How long requests have to be handled.
+    timeout: Duration,
+    /// The raw response sent when the deadline passes.
+    response: Arc<[u8]>,
+    /// Sends the timeout responses.
+    watcher: Arc<Watcher>,
+}
+
+/// The deadline of a request, added to the request's extensions by [`Timeout`].
+pub struct Deadline(Arc<Shared>);
+
+/// The state of a deadline, shared with the watcher thread.
+struct Shared {
+    deadline: Instant,
+    state: AtomicU8,
+    socket: Arc<Mutex<Box<dyn Stream>>>,
+    response: Arc<[u8]>,
+}
+
+/// Waits for deadlines to pass on a background thread.
+struct Watcher {
+    pending: Mutex<Vec<Arc<Shared>>>,
+    wake: Condvar,
+    started: Once,
+}
+
+impl Timeout {
+    /// Creates a new Timeout middleware, which gives handlers `timeout` to respond.
+    pub fn new(timeout: Duration) -> Self {
+        let body = "Gateway Time-out: the request took too long to handle";
+        let response = format!(
+            "HTTP/1.1 504 Gateway Time-out\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        );
+
+        Self {
+            timeout,
+            response: response.into_bytes().into(),
+            watcher: Arc::new(Watcher {
+                pending: Mutex::new(Vec::new()),
+                wake: Condvar::new(),
+                started: Once::new(),
+            }),
+        }
+    }
+
+    /// Starts the watcher thread, if it isn't already running.
+    /// It only holds a weak reference to the watcher, so it stops once the middleware is dropped.
+    fn start_watcher(&self) {
+        let watcher = Arc::downgrade(&self.watcher);
+        self.watcher.started.call_once(|| {
+            thread::Builder::new()
+                .name("afire-timeout".to_owned())
+                .spawn(move || watch(watcher))
+                .expect("Failed to spawn timeout thread");
+        });
+    }
+}
+
+impl Deadline {
+    /// Gets when the timeout response will be sent.
+    pub fn deadline(&self) -> Instant {
+        self.0.deadline
+    }
+
+    /// Gets how long is left until the deadline, or zero if it has passed.
+    pub fn remaining(&self) -> Duration {
+        self.0.deadline.saturating_duration_since(Instant::now())
+    }
+
+    /// Checks if the deadline has passed and the client was sent the timeout response.
+    /// Anything the handler returns after this is thrown away.
+    pub fn expired(&self) -> bool {
+        self.0.state.load(Ordering::Acquire) == TIMED_OUT
+    }
+
+    /// Stops the timeout response from being sent for this request, like for a handler that takes over the socket.
+    /// Returns false if it was already sent.
+    pub fn cancel(&self) -> bool {
+        self.0.finish(CANCELLED)
+    }
+}
+
+impl Shared {
+    /// Moves a pending deadline to `state`, returning false if it was already finished or timed out.
+    fn finish(&self, state: u8) -> bool {
+        let result =
+            self.state
+                .compare_exchange(PENDING, state, Ordering::AcqRel, Ordering::Acquire);
+        result.is_ok() || self.state.load(Ordering::Acquire) != TIMED_OUT
+    }
+
+    /// Sends the timeout response, unless the handler already finished.
+    fn expire(&self) {
+        if self
+            .state
+            .compare_exchange(PENDING, TIMED_OUT, Ordering::AcqRel, Ordering::Acquire)
+            .is_err()
+        {
+            return;
+        }
+
+        trace!(Level::Debug, "Request timed out, sending timeout response");
+        let mut socket = self.socket.force_lock();
+        if let Err(e) = socket
+            .write_all(&self.response)
+            .and_then(|_| socket.flush())
+        {
+            trace!(Level::Debug, "Error sending timeout response: {}", e);
+        }
+    }
+}
+
+/// Sends the timeout responses of deadlines as they pass, until the watcher is dropped.
+fn watch(watcher: Weak<Watcher>) {
+    while let Some(watcher) = watcher.upgrade() {
+        let mut pending = watcher.pending.force_lock();
+        pending.retain(|i| i.state.load(Ordering::Acquire) == PENDING);
+
+        let now = Instant::now();
+        let (expired, waiting): (Vec<_>, Vec<_>) =
+            pending.drain(..).partition(|i| i.deadline <= now);
+        *pending = waiting;
+
+        if !expired.is_empty() {
+            drop(pending);
+            expired.iter().for_each(|i| i.expire());
+            continue;
+        }
+
+        let sleep = pending
+            .iter()
+            .map(|i| i.deadline - now)
+            .min()
+            .map_or(MAX_SLEEP, |i| i.min(MAX_SLEEP));
+        drop(watcher.wake.wait_timeout(pending, sleep));
+    }
+}
+
+impl Middleware for Timeout {
+    fn pre(&self, req: &mut Request) -> MiddleResult {
+        if !req.version.starts_with("HTTP/1") {
+            return MiddleResult::Continue;
+        }
+
+        let shared = Arc::new(Shared {
+            deadline: Instant::now() + self.timeout,
+            state: AtomicU8::new(PENDING),
+            socket: req.socket.clone(),
+            response: self.response.clone(),
+        });
+
+        self.start_watcher();
+        self.watcher.pending.force_lock().push(shared.clone());
+        self.watcher.wake.notify_one();
+        req.extensions.insert(Deadline(shared));
+        MiddleResult::Continue
+    }
+
+    fn post_raw(&self, req: Result<Rc<Request>>, res: &mut Result<Response>) -> MiddleResult {
+        let Some(deadline) = req
+            .as_ref()
+            .ok()
+            .and_then(|i| i.extensions.get::<Deadline>())
+        else {
+            return MiddleResult::Continue;
+        };
+
+        if !deadline.0.finish(DONE) {
+            *res = Ok(Response::end());
+        }
+
+        MiddleResult::Continue
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::Cursor,
+        sync::{Arc, Mutex},
+        thread,
+        time::Duration,
+    };
+
+    use super::{Deadline, Timeout};
+    use crate::{
+        internal::testing::{MemoryListener, MemoryStream},
+        Method, Middleware, Response, Server,
+    };
+
+    #[test]
+    fn test_timeout() {
+        let outputs = [(); 2].map(|_| Arc::new(Mutex::new(Vec::new())));
+        let listener = MemoryListener(Mutex::new(vec![
+            MemoryStream {
+                input: Cursor::new(b"GET /slow HTTP/1.1\r\nHost: localhost\r\n\r\n".to_vec()),
+                output: outputs[0].clone(),
+            },
+            MemoryStream {
+                input: Cursor::new(b"GET /fast HTTP/1.1\r\nHost: localhost\r\n\r\n".to_vec()),
+                output: outputs[1].clone(),
+            },
+        ]));
+
+        let mut server = Server::<()>::new("localhost", 0);
+        Timeout::new(Duration::from_millis(50)).attach(&mut server);
+        server.route(Method::GET, "/slow", |req| {
+            let deadline = req.extensions.get::<Deadline>().unwrap();
+            while !deadline.expired() {
+                thread::sleep(Duration::from_millis(5));
+            }
+            Response::new().text("Too late")
+        });
+        server.route(Method::GET, "/fast", |req| {
+            let deadline = req.extensions.get::<Deadline>().unwrap();
+            Response::new().text(deadline.remaining() > Duration::ZERO)
+        });
+        assert!(server.start_with_listener(listener).is_err());
+
+        let output = String::from_utf8(outputs[0].lock().unwrap().clone()).unwrap();
+        assert!(output.starts_with("HTTP/1.1 504 Gateway Time-out\r\n"));
+        assert!(!output.contains("Too late"));
+
+        let output = String::from_utf8(outputs[1].lock().unwrap().clone()).unwrap();
+        assert!(output.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(output.ends_with("\r\n\r\ntrue"));
+    }
+}
@@ -0,0 +1,260 @@
+//! Propagates [W3C Trace Context](https://www.w3.org/TR/trace-context/) headers, so afire services can take part in distributed tracing.
+//!
+//! The [`TraceContext`] middleware reads the `traceparent` and `tracestate` headers of incoming requests and starts a new span for each request.
+//! The span is added to the [`crate::Request::extensions`] as a [`SpanContext`], which can be used to log the trace id or to pass the trace on to other services.
+
+use std::convert::TryInto;
+
+use crate::{
+    encoding::hex,
+    internal::common::random_u64,
+    middleware::{MiddleResult, Middleware},
+    Request, Response,
+};
+
+/// Middleware that parses the `traceparent` and `tracestate` headers of incoming requests and starts a new span for each one.
+/// If a request has no (valid) `traceparent` a new trace is started.
+///
+/// The span is added to the request's extensions as a [`SpanContext`], and by default is echoed in the `traceparent` header of the response.
+/// ## Example
+/// ```rust
+/// # use afire::{Server, Response, Method, Middleware};
+/// use afire::extension::trace_context::{SpanContext, TraceContext};
+///
+/// # let mut server = Server::<()>::new("localhost", 8080);
+/// TraceContext::new().attach(&mut server);
+///
+/// server.route(Method::GET, "/", |req| {
+///     let span = req.extensions.get::<SpanContext>().unwrap();
+///     println!("Handling request in trace {}", span.trace_id());
+///
+///     // Pass `span.traceparent()` in the `traceparent` header of requests to other services
+///     Response::new().text("Hello")
+/// });
+/// ```
+pub struct TraceContext {
+    /// If the span is echoed in the response headers.
+    echo: bool,
+    /// If new traces are sampled.
+    sampled: bool,
+}
+
+/// The trace and span a request is part of, added to the request's extensions by the [`TraceContext`] middleware.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpanContext {
+    trace_id: [u8; 16],
+    parent_id: Option<[u8; 8]>,
+    span_id: [u8; 8],
+    flags: u8,
+    trace_state: Option<String>,
+}
+
+impl TraceContext {
+    /// Creates a new TraceContext middleware.
+    /// Spans are echoed in the response headers, and new traces are sampled.
+    pub fn new() -> Self {
+        Self {
+            echo: true,
+            sampled: true,
+        }
+    }
+
+    /// Sets if the span of a request is sent back in the `traceparent` and `tracestate` headers of the response.
+    pub fn echo(self, echo: bool) -> Self {
+        Self { echo, ..self }
+    }
+
+    /// Sets if traces started by this server (for requests without a `traceparent`) are marked as sampled.
+    /// Requests that are already part of a trace keep the sampled flag of their parent.
+    pub fn sampled(self, sampled: bool) -> Self {
+        Self { sampled, ..self }
+    }
+}
+
+impl SpanContext {
+    /// Starts a new span, as a child of the span in a `traceparent` header if it is valid.
+    /// Otherwise a new trace is started, and `tracestate` is ignored.
+    fn new(traceparent: Option<&str>, tracestate: Option<&str>, sampled: bool) -> Self {
+        let span_id = random_id();
+        match traceparent.and_then(parse_traceparent) {
+            Some((trace_id, parent_id, flags)) => Self {
+                trace_id,
+                parent_id: Some(parent_id),
+                span_id,
+                flags,
+                trace_state: tracestate
+                    .map(|i| i.trim().to_owned())
+                    .filter(|i| !i.is_empty()),
+            },
+            None => Self {
+                trace_id: random_id(),
+                parent_id: None,
+                span_id,
+                flags: sampled as u8,
+                trace_state: None,
+            },
+        }
+    }
+
+    /// The id of the trace, as 32 hex characters.
+    pub fn trace_id(&self) -> String {
+        hex::encode(&self.trace_id)
+    }
+
+    /// The id of this request's span, as 16 hex characters.
+    pub fn span_id(&self) -> String {
+        hex::encode(&self.span_id)
+    }
+
+    /// The id of the parent span from the request's `traceparent` header, or None if this request started the trace.
+    pub fn parent_id(&self) -> Option<String> {
+        self.parent_id.map(|i| hex::encode(&i))
+    }
+
+    /// If the trace is sampled, meaning the caller may be recording it.
+    pub fn sampled(&self) -> bool {
+        self.flags & 1 != 0
+    }
+
+    /// The vendor specific trace state from the request's `tracestate` header.
+    pub fn trace_state(&self) -> Option<&str> {
+        self.trace_state.as_deref()
+    }
+
+    /// The `traceparent` header value with this span as the parent.
+    /// Send this in requests made to other services while handling the request, so they are part of the same trace.
+    pub fn traceparent(&self) -> String {
+        format!(
+            "00-{}-{}-{:02x}",
+            self.trace_id(),
+            self.span_id(),
+            self.flags
+        )
+    }
+}
+
+/// Parses a `traceparent` header into the trace id, parent id and flags.
+/// Returns None if the header is invalid, in which case the spec says a new trace should be started.
+fn parse_traceparent(header: &str) -> Option<([u8; 16], [u8; 8], u8)> {
+    fn decode<const N: usize>(hex: &str) -> Option<[u8; N]> {
+        let valid =
+            hex.len() == N * 2 && hex.bytes().all(|i| matches!(i, b'0'..=b'9' | b'a'..=b'f'));
+        valid.then(|| hex::decode(hex)?.try_into().ok()).flatten()
+    }
+
+    let parts = header.trim().split('-').collect::<Vec<_>>();
+    let version = decode::<1>(parts.first()?)?[0];
+    // Later versions can add fields, but must keep the first four
+    if version == 0xff || (version == 0 && parts.len() != 4) || parts.len() < 4 {
+        return None;
+    }
+
+    let trace_id = decode::<16>(parts[1])?;
+    let parent_id = decode::<8>(parts[2])?;
+    let flags = decode::<1>(parts[3])?[0];
+    if trace_id == [0; 16] || parent_id == [0; 8] {
+        return None;
+    }
+
+    Some((trace_id, parent_id, flags))
+}
+
+/// Generates a random id that isn't all zeros, which is invalid.
+fn random_id<const N: usize>() -> [u8; N] {
+    let mut id = [0; N];
+    while id == [0; N] {
+        for chunk in id.chunks_mut(8) {
+            chunk.copy_from_slice(&random_u64().to_le_bytes()[..chunk.len()]);
+        }
+    }
+    id
+}
+
+impl Middleware for TraceContext {
+    fn pre(&self, req: &mut Request) -> MiddleResult {
+        let span = SpanContext::new(
+            req.headers.get("traceparent"),
+            req.headers.get("tracestate"),
+            self.sampled,
+        );
+        req.extensions.insert(span);
+        MiddleResult::Continue
+    }
+
+    fn post(&self, req: &Request, res: &mut Response) -> MiddleResult {
+        let span = match req.extensions.get::<SpanContext>() {
+            Some(span) if self.echo && !res.headers.has("traceparent") => span,
+            _ => return MiddleResult::Continue,
+        };
+
+        res.headers.add("traceparent", span.traceparent());
+        if let Some(state) = span.trace_state() {
+            res.headers.add("tracestate", state);
+        }
+
+        MiddleResult::Continue
+    }
+}
+
+impl Default for TraceContext {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_traceparent, SpanContext};
+
+    #[test]
+    fn test_parse_traceparent() {
+        let (trace_id, parent_id, flags) =
+            parse_traceparent("00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01").unwrap();
+        assert_eq!(trace_id[..2], [0x0a, 0xf7]);
+        assert_eq!(parent_id[..2], [0xb7, 0xad]);
+        assert_eq!(flags, 1);
+
+        for i in [
+            "",
+            "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331",
+            "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01-extra",
+            "ff-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01",
+            "00-0AF7651916CD43DD8448EB211C80319C-b7ad6b7169203331-01",
+            "00-00000000000000000000000000000000-b7ad6b7169203331-01",
+            "00-0af7651916cd43dd8448eb211c80319c-0000000000000000-01",
+        ] {
+            assert!(parse_traceparent(i).is_none(), "{}", i);
+        }
+
+        // Later versions can have extra fields
+        assert!(
+            parse_traceparent("01-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01-extra")
+                .is_some()
+        );
+    }
+
+    #[test]
+    fn test_span_context() {
+        let span = SpanContext::new(
+            Some("00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-00"),
+            Some("vendor=value"),
+            true,
+        );
+        assert_eq!(span.trace_id(), "0af7651916cd43dd8448eb211c80319c");
+        assert_eq!(span.parent_id().as_deref(), Some("b7ad6b7169203331"));
+        assert_ne!(span.span_id(), "b7ad6b7169203331");
+        assert!(!span.sampled());
+        assert_eq!(span.trace_state(), Some("vendor=value"));
+        assert_eq!(
+            span.traceparent(),
+            format!("00-0af7651916cd43dd8448eb211c80319c-{}-00", span.span_id())
+        );
+
+        // Without a valid parent a new trace is started and the state is dropped
+        let span = SpanContext::new(Some("invalid"), Some("vendor=value"), true);
+        assert_eq!(span.parent_id(), None);
+        assert_eq!(span.trace_id().len(), 32);
+        assert!(span.sampled());
+        assert_eq!(span.trace_state(), None);
+    }
+}
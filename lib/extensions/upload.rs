@@ -0,0 +1,390 @@
+//! Accept file uploads into a directory, the write side of [`crate::extension::ServeStatic`].
+//!
+//! Files can be uploaded with a `PUT` to `<path>/<filename>`, where the body is the file, or with a `POST` to `<path>` with a `multipart/form-data` body (like from an HTML form), where every part with a filename is saved.
+//! Filenames are sanitized with [`sanitize_filename`], so uploads can't escape the directory or create hidden files.
+//! Files are written to a temporary file first and then renamed, so partial uploads are never visible.
+
+use std::{
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    encoding::url,
+    internal::common::{json_string, random_u64},
+    middleware::{MiddleResult, Middleware},
+    multipart::MultipartReader,
+    path::normalize_path,
+    Content, HeaderType, Method, Request, Response, Status,
+};
+
+type AuthCallback = Box<dyn Fn(&Request) -> bool + Send + Sync>;
+
+/// Middleware that saves uploaded files to a directory.
+///
+/// ## Example
+/// ```rust,no_run
+/// # use afire::{Server, Middleware, extension::{ServeStatic, Upload, upload::Overwrite}};
+/// let mut server = Server::<()>::new("localhost", 8080);
+///
+/// // Accept uploads of up to 10 MB on `/files`, and serve them back
+/// Upload::new("data/files")
+///     .path("/files")
+///     .max_size(10 * 1024 * 1024)
+///     .overwrite(Overwrite::Rename)
+///     .auth(|req| req.headers.get("Authorization") == Some("Bearer secret"))
+///     .attach(&mut server);
+/// ServeStatic::new("data/files").path("/files").attach(&mut server);
+///
+/// server.start().unwrap();
+/// ```
+pub struct Upload {
+    /// Path to accept uploads on.
+    serve_path: String,
+    /// Directory to save files in.
+    data_dir: PathBuf,
+    /// The largest file that can be uploaded, in bytes.
+    max_size: u64,
+    /// What to do when a file with the same name already exists.
+    overwrite: Overwrite,
+    /// Decides if a request is allowed to upload files.
+    auth: Option<AuthCallback>,
+}
+
+/// What to do when an uploaded file has the same name as an existing file, see [`Upload::overwrite`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Overwrite {
+    /// Replace the existing file.
+    Replace,
+    /// Reject the upload with a `409 Conflict`.
+    #[default]
+    Reject,
+    /// Save the upload with a number added to its name, like `notes (1).txt`.
+    Rename,
+}
+
+impl Upload {
+    /// Creates a new upload handler that saves files to the given directory, which must already exist.
+    /// By default uploads are accepted on the root path, are limited to 100 MB and can't replace existing files.
+    pub fn new(data_dir: impl AsRef<Path>) -> Self {
+        Self {
+            serve_path: normalize_path("/".to_owned()),
+            data_dir: data_dir.as_ref().to_path_buf(),
+            max_size: 100 * 1024 * 1024,
+            overwrite: Overwrite::default(),
+            auth: None,
+        }
+    }
+
+    /// Sets the path to accept uploads on.
+    /// ## Example
+    /// ```rust
+    /// # use afire::extension::Upload;
+    /// let upload = Upload::new("data").path("/files");
+    /// ```
+    pub fn path(self, path: impl AsRef<str>) -> Self {
+        Self {
+            serve_path: normalize_path(path.as_ref().to_owned()),
+            ..self
+        }
+    }
+
+    /// Sets the largest file that can be uploaded, in bytes.
+    /// Larger files are rejected with a `413 Payload Too Large`.
+    /// The server's [`crate::Server::max_body_size`] still applies to the whole request.
+    pub fn max_size(self, max_size: u64) -> Self {
+        Self { max_size, ..self }
+    }
+
+    /// Sets what to do when a file with the same name already exists.
+    pub fn overwrite(self, overwrite: Overwrite) -> Self {
+        Self { overwrite, ..self }
+    }
+
+    /// Sets a callback that decides if a request is allowed to upload files.
+    /// Requests it returns false for are rejected with a `401 Unauthorized`.
+    pub fn auth(self, auth: impl Fn(&Request) -> bool + Send + Sync + 'static) -> Self {
+        Self {
+            auth: Some(Box::new(auth)),
+            ..self
+        }
+    }
+
+    /// Gets the path relative to the upload path, or None if the request is not for the upload path.
+    fn relative<'a>(&self, path: &'a str) -> Option<&'a str> {
+        let path = path.trim_matches('/');
+        if self.serve_path.is_empty() {
+            return Some(path);
+        }
+
+        let rest = path.strip_prefix(&self.serve_path)?;
+        match rest.strip_prefix('/') {
+            Some(rest) => Some(rest),
+            None if rest.is_empty() => Some(rest),
+            None => None,
+        }
+    }
+
+    fn handle(&self, req: &Request, rest: &str) -> Result<Response, Status> {
+        if self.auth.as_ref().is_some_and(|auth| !auth(req)) {
+            return Err(Status::Unauthorized);
+        }
+
+        if req.method == Method::PUT {
+            let name = url::decode_path(rest).ok_or(Status::BadRequest)?;
+            if name.contains(['/', '\\']) {
+                return Err(Status::NotFound);
+            }
+            let name = sanitize_filename(&name).ok_or(Status::BadRequest)?;
+
+            let (name, replaced) = self.save(&name, &mut req.body_reader())?;
+            let location = format!("/{}", url::encode(&name));
+            let location = match self.serve_path.is_empty() {
+                true => location,
+                false => format!("/{}{}", self.serve_path, location),
+            };
+            return Ok(Response::new()
+                .status(if replaced {
+                    Status::NoContent
+                } else {
+                    Status::Created
+                })
+                .header(HeaderType::Location, location)
+                .bytes(&[]));
+        }
+
+        let mut reader = MultipartReader::from_stream(req).map_err(|_| Status::BadRequest)?;
+        let mut saved = Vec::new();
+        let result = loop {
+            let mut part = match reader.next_part() {
+                Ok(Some(part)) => part,
+                Ok(None) => break Ok(()),
+                Err(_) => break Err(Status::BadRequest),
+            };
+            let name = match part.filename.as_deref().and_then(sanitize_filename) {
+                Some(name) => name,
+                None => continue,
+            };
+
+            match self.save(&name, &mut part) {
+                Ok(file) => saved.push(file),
+                Err(status) => break Err(status),
+            }
+        };
+
+        // Don't keep some of the files if the upload as a whole failed
+        if let Err(status) = result {
+            for (name, _) in saved.iter().filter(|(_, replaced)| !replaced) {
+                let _ = fs::remove_file(self.data_dir.join(name));
+            }
+            return Err(status);
+        }
+
+        if saved.is_empty() {
+            return Err(Status::BadRequest);
+        }
+
+        let files = saved
+            .iter()
+            .map(|(name, _)| json_string(name))
+            .collect::<Vec<_>>();
+        Ok(Response::new()
+            .status(Status::Created)
+            .text(format!("{{\"files\":[{}]}}", files.join(",")))
+            .content(Content::JSON))
+    }
+
+    /// Saves a file with a sanitized name, returning the name it was saved as and if it replaced an existing file.
+    fn save(&self, name: &str, data: &mut impl Read) -> Result<(String, bool), Status> {
+        let temp = self
+            .data_dir
+            .join(format!(".upload-{:016x}.part", random_u64()));
+        let written = File::create(&temp).and_then(|mut file| {
+            let copied = io::copy(&mut data.take(self.max_size), &mut file)?;
+            file.flush()?;
+            Ok(copied)
+        });
+
+        let result = match written {
+            Ok(copied)
+                if copied == self.max_size && data.read(&mut [0]).map_or(true, |i| i != 0) =>
+            {
+                Err(Status::PayloadTooLarge)
+            }
+            Ok(_) => self.rename(&temp, name),
+            Err(e) => Err(io_status(e)),
+        };
+
+        if result.is_err() {
+            let _ = fs::remove_file(&temp);
+        }
+        result
+    }
+
+    /// Moves a fully written temporary file to its final name, following the overwrite policy.
+    fn rename(&self, temp: &Path, name: &str) -> Result<(String, bool), Status> {
+        let path = self.data_dir.join(name);
+        let exists = path.exists();
+        let name = match self.overwrite {
+            Overwrite::Reject if exists => return Err(Status::Conflict),
+            Overwrite::Rename if exists => (1..)
+                .map(|i| numbered(name, i))
+                .find(|i| !self.data_dir.join(i).exists())
+                .unwrap(),
+            _ => name.to_owned(),
+        };
+
+        fs::rename(temp, self.data_dir.join(&name)).map_err(io_status)?;
+        Ok((name, exists && self.overwrite == Overwrite::Replace))
+    }
+}
+
+impl Middleware for Upload {
+    fn pre(&self, req: &mut Request) -> MiddleResult {
+        let rest = match self.relative(&req.path) {
+            Some(rest) => rest,
+            None => return MiddleResult::Continue,
+        };
+
+        let handled = match req.method {
+            Method::PUT => !rest.is_empty(),
+            Method::POST => rest.is_empty(),
+            _ => false,
+        };
+        if !handled {
+            return MiddleResult::Continue;
+        }
+
+        MiddleResult::Send(self.handle(req, rest).unwrap_or_else(|status| {
+            Response::new()
+                .status(status)
+                .text(status.reason_phrase())
+                .content(Content::TXT)
+        }))
+    }
+}
+
+/// Makes an uploaded filename safe to save, returning None if nothing usable is left.
+/// Only the last component of the name is kept, so `../../etc/passwd` becomes `passwd`.
+/// Control characters and characters that aren't allowed in Windows filenames are replaced with `_`,
+/// and leading dots and trailing dots or spaces are removed so files can't be hidden or overwrite `.` and `..`.
+/// ## Example
+/// ```rust
+/// # use afire::extension::upload::sanitize_filename;
+/// assert_eq!(sanitize_filename("C:\\Users\\me\\report?.pdf").as_deref(), Some("report_.pdf"));
+/// assert_eq!(sanitize_filename("../.."), None);
+/// ```
+pub fn sanitize_filename(name: &str) -> Option<String> {
+    let name = name.rsplit(['/', '\\']).next().unwrap_or_default();
+    let name = name
+        .chars()
+        .map(|c| match c {
+            '<' | '>' | ':' | '"' | '|' | '?' | '*' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect::<String>();
+    let mut name = name
+        .trim_start_matches(['.', ' '])
+        .trim_end_matches(['.', ' '])
+        .to_owned();
+
+    // Keep names under the 255 byte limit of most file systems
+    while name.len() > 255 {
+        name.pop();
+    }
+
+    const RESERVED: &[&str] = &["CON", "PRN", "AUX", "NUL", "COM1", "LPT1"];
+    let stem = name.split('.').next().unwrap_or_default();
+    if RESERVED.iter().any(|i| i.eq_ignore_ascii_case(stem)) {
+        name.insert(0, '_');
+    }
+
+    (!name.is_empty()).then_some(name)
+}
+
+/// Adds a number to a filename, before its extension.
+fn numbered(name: &str, n: u32) -> String {
+    match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => format!("{stem} ({n}).{ext}"),
+        _ => format!("{name} ({n})"),
+    }
+}
+
+fn io_status(err: io::Error) -> Status {
+    match err.kind() {
+        io::ErrorKind::NotFound => Status::NotFound,
+        io::ErrorKind::PermissionDenied => Status::Forbidden,
+        _ => Status::InternalServerError,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, fs, process};
+
+    use super::{numbered, sanitize_filename, Overwrite, Upload};
+    use crate::Status;
+
+    #[test]
+    fn test_sanitize_filename() {
+        assert_eq!(sanitize_filename("notes.txt").as_deref(), Some("notes.txt"));
+        assert_eq!(
+            sanitize_filename("../../etc/passwd").as_deref(),
+            Some("passwd")
+        );
+        assert_eq!(sanitize_filename("a\\b\\c.png").as_deref(), Some("c.png"));
+        assert_eq!(sanitize_filename(".htaccess").as_deref(), Some("htaccess"));
+        assert_eq!(sanitize_filename("a\r\nb. ").as_deref(), Some("a__b"));
+        assert_eq!(sanitize_filename("con.txt").as_deref(), Some("_con.txt"));
+        assert_eq!(sanitize_filename(".."), None);
+        assert_eq!(sanitize_filename("dir/"), None);
+        assert_eq!(
+            sanitize_filename(&"a".repeat(300)).map(|x| x.len()),
+            Some(255)
+        );
+    }
+
+    #[test]
+    fn test_save() {
+        let dir = env::temp_dir().join(format!("afire-upload-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let upload = Upload::new(&dir).max_size(8);
+
+        assert_eq!(
+            upload.save("a.txt", &mut &b"hello"[..]),
+            Ok(("a.txt".to_owned(), false))
+        );
+        assert_eq!(
+            upload.save("a.txt", &mut &b"again"[..]),
+            Err(Status::Conflict)
+        );
+        assert_eq!(
+            upload.save("b.txt", &mut &b"too large!"[..]),
+            Err(Status::PayloadTooLarge)
+        );
+
+        let upload = upload.overwrite(Overwrite::Rename);
+        assert_eq!(
+            upload.save("a.txt", &mut &b"again"[..]),
+            Ok(("a (1).txt".to_owned(), false))
+        );
+        let upload = upload.overwrite(Overwrite::Replace);
+        assert_eq!(
+            upload.save("a.txt", &mut &b"replaced"[..]),
+            Ok(("a.txt".to_owned(), true))
+        );
+
+        let mut files = fs::read_dir(&dir)
+            .unwrap()
+            .map(|i| i.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        files.sort();
+        assert_eq!(files, ["a (1).txt", "a.txt"]);
+        assert_eq!(fs::read(dir.join("a.txt")).unwrap(), b"replaced");
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(numbered(".env", 2), ".env (2)");
+    }
+}
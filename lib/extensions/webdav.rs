@@ -0,0 +1,410 @@
+//! A [WebDAV](https://developer.mozilla.org/en-US/docs/Glossary/WebDAV) server for a directory, so it can be browsed and edited from OS file managers.
+//!
+//! Only the WebDAV methods are handled here (`PROPFIND`, `MKCOL`, `PUT`, `DELETE`, `MOVE` and `COPY`).
+//! Files are read with plain `GET` requests, so [`crate::extension::ServeStatic`] should be attached for the same directory.
+//! Locking is not supported, so clients that require it (like the macOS Finder) will mount the directory read-only.
+
+use std::{
+    fmt::Write as _,
+    fs, io,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use crate::{
+    content_type::{get_type, TYPES},
+    encoding::url,
+    internal::{common::xml_escape, date::imp_date},
+    middleware::{MiddleResult, Middleware},
+    path::normalize_path,
+    Content, Method, Request, Response, Status,
+};
+
+/// The methods supported by the WebDAV server, sent in the `Allow` header.
+const ALLOW: &str = "OPTIONS, GET, HEAD, PUT, DELETE, PROPFIND, MKCOL, COPY, MOVE";
+
+/// Middleware that serves a directory over WebDAV.
+///
+/// ## Example
+/// ```rust,no_run
+/// # use afire::{Server, Middleware, extension::{ServeStatic, WebDav}};
+/// let mut server = Server::<()>::new("localhost", 8080);
+///
+/// // Serve the files in `data` on `/files`.
+/// // ServeStatic handles GET requests, WebDav handles everything else.
+/// ServeStatic::new("data").path("/files").attach(&mut server);
+/// WebDav::new("data").path("/files").attach(&mut server);
+///
+/// server.start().unwrap();
+/// ```
+#[derive(Debug)]
+pub struct WebDav {
+    /// Path to serve the directory on.
+    serve_path: String,
+    /// Directory to serve.
+    data_dir: PathBuf,
+    /// If methods that modify the directory should be rejected.
+    read_only: bool,
+}
+
+impl WebDav {
+    /// Create a new WebDAV server for the given directory.
+    /// By default it is served on the root path and can be modified by clients.
+    pub fn new(data_dir: impl AsRef<Path>) -> Self {
+        Self {
+            serve_path: normalize_path("/".to_owned()),
+            data_dir: data_dir.as_ref().to_path_buf(),
+            read_only: false,
+        }
+    }
+
+    /// Set the path to serve the directory on.
+    /// This should be the same as the [`crate::extension::ServeStatic::path`] for the directory.
+    /// ## Example
+    /// ```rust
+    /// # use afire::extension::WebDav;
+    /// let dav = WebDav::new("data").path("/files");
+    /// ```
+    pub fn path(self, path: impl AsRef<str>) -> Self {
+        Self {
+            serve_path: normalize_path(path.as_ref().to_owned()),
+            ..self
+        }
+    }
+
+    /// Reject requests that would modify the directory with `403 Forbidden`.
+    /// ## Example
+    /// ```rust
+    /// # use afire::extension::WebDav;
+    /// let dav = WebDav::new("data").read_only(true);
+    /// ```
+    pub fn read_only(self, read_only: bool) -> Self {
+        Self { read_only, ..self }
+    }
+
+    /// Gets the path segments of a request path relative to the served path.
+    /// Returns None if the path is not under the served path.
+    fn segments(&self, path: &str) -> Option<Vec<String>> {
+        let path = normalize_path(path.to_owned());
+        let rest = if self.serve_path.is_empty() {
+            path.as_str()
+        } else if path == self.serve_path {
+            ""
+        } else {
+            path.strip_prefix(&self.serve_path)?.strip_prefix('/')?
+        };
+
+        rest.split('/')
+            .filter(|i| !i.is_empty())
+            .map(|i| url::decode_path(i).unwrap_or_else(|| i.to_owned()))
+            .map(Some)
+            .collect()
+    }
+
+    /// Gets the file system path for a request path.
+    /// Returns Err with the status to send if the path is not under the served path or tries to escape the directory.
+    fn resolve(&self, path: &str) -> Result<(Vec<String>, PathBuf), Status> {
+        let segments = self.segments(path).ok_or(Status::BadGateway)?;
+        if segments
+            .iter()
+            .any(|i| i == ".." || i == "." || i.contains(['/', '\\']))
+        {
+            return Err(Status::Forbidden);
+        }
+
+        let file = segments
+            .iter()
+            .fold(self.data_dir.clone(), |i, j| i.join(j));
+        Ok((segments, file))
+    }
+
+    /// Gets the href of a resource, with each segment percent encoded.
+    fn href(&self, segments: &[String], collection: bool) -> String {
+        let mut out = String::new();
+        for i in Some(&self.serve_path)
+            .filter(|i| !i.is_empty())
+            .into_iter()
+            .chain(segments)
+        {
+            out.push('/');
+            out.push_str(&url::encode(i));
+        }
+
+        if collection || out.is_empty() {
+            out.push('/');
+        }
+        out
+    }
+
+    fn handle(&self, req: &Request) -> Result<Response, Status> {
+        let (segments, file) = self.resolve(&req.path)?;
+        let method = req.method.to_string();
+
+        if self.read_only && !matches!(method.as_str(), "OPTIONS" | "PROPFIND") {
+            return Err(Status::Forbidden);
+        }
+
+        match method.as_str() {
+            "OPTIONS" => Ok(empty(Status::Ok)
+                .header("DAV", "1")
+                .header("Allow", ALLOW)
+                .header("MS-Author-Via", "DAV")),
+            "PROPFIND" => self.propfind(req, &segments, &file),
+            "MKCOL" => {
+                if !req.body.is_empty() {
+                    return Err(Status::UnsupportedMediaType);
+                }
+                if file.exists() {
+                    return Err(Status::MethodNotAllowed);
+                }
+                check_parent(&file)?;
+                fs::create_dir(&file).map_err(io_status)?;
+                Ok(empty(Status::Created))
+            }
+            "PUT" => {
+                if file.is_dir() {
+                    return Err(Status::MethodNotAllowed);
+                }
+                check_parent(&file)?;
+                let existed = file.exists();
+                fs::write(&file, &*req.body).map_err(io_status)?;
+                Ok(empty(if existed {
+                    Status::NoContent
+                } else {
+                    Status::Created
+                }))
+            }
+            "DELETE" => {
+                if segments.is_empty() {
+                    return Err(Status::Forbidden);
+                }
+                remove(&file).map_err(io_status)?;
+                Ok(empty(Status::NoContent))
+            }
+            "COPY" | "MOVE" => {
+                if segments.is_empty() || !file.exists() {
+                    return Err(if segments.is_empty() {
+                        Status::Forbidden
+                    } else {
+                        Status::NotFound
+                    });
+                }
+
+                let destination = req.headers.get("Destination").ok_or(Status::BadRequest)?;
+                let (dest_segments, dest) = self.resolve(destination_path(destination))?;
+                if dest_segments.is_empty() || dest_segments.starts_with(&segments) {
+                    return Err(Status::Forbidden);
+                }
+
+                let overwrite = req.headers.get("Overwrite");
+                let existed = dest.exists();
+                if existed && overwrite.is_some_and(|i| i.eq_ignore_ascii_case("F")) {
+                    return Err(Status::PreconditionFailed);
+                }
+                check_parent(&dest)?;
+                if existed {
+                    remove(&dest).map_err(io_status)?;
+                }
+
+                match method.as_str() {
+                    "MOVE" => fs::rename(&file, &dest),
+                    _ => copy(&file, &dest),
+                }
+                .map_err(io_status)?;
+
+                Ok(empty(if existed {
+                    Status::NoContent
+                } else {
+                    Status::Created
+                }))
+            }
+            _ => Err(Status::MethodNotAllowed),
+        }
+    }
+
+    fn propfind(
+        &self,
+        req: &Request,
+        segments: &[String],
+        file: &Path,
+    ) -> Result<Response, Status> {
+        let metadata = fs::metadata(file).map_err(io_status)?;
+
+        let mut out = String::from(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">\n",
+        );
+        self.write_entry(&mut out, segments, &metadata);
+
+        // Infinite depth is treated as a depth of one, to avoid walking huge directory trees
+        let depth = req.headers.get("Depth");
+        if metadata.is_dir() && depth != Some("0") {
+            let mut entries = fs::read_dir(file)
+                .map_err(io_status)?
+                .filter_map(|i| i.ok())
+                .collect::<Vec<_>>();
+            entries.sort_by_key(|i| i.file_name());
+
+            for entry in entries {
+                let file_name = entry.file_name();
+                let (Some(name), Ok(metadata)) = (file_name.to_str(), entry.metadata()) else {
+                    continue;
+                };
+
+                let mut segments = segments.to_vec();
+                segments.push(name.to_owned());
+                self.write_entry(&mut out, &segments, &metadata);
+            }
+        }
+
+        out.push_str("</D:multistatus>\n");
+        Ok(Response::new()
+            .status(Status::MultiStatus)
+            .text(out)
+            .content(Content::XML))
+    }
+
+    /// Writes the `<D:response>` element describing a file or directory.
+    fn write_entry(&self, out: &mut String, segments: &[String], metadata: &fs::Metadata) {
+        let name = segments.last().map(|i| i.as_str()).unwrap_or("");
+        let _ = write!(
+            out,
+            "<D:response><D:href>{}</D:href><D:propstat><D:prop><D:displayname>{}</D:displayname>",
+            xml_escape(&self.href(segments, metadata.is_dir())),
+            xml_escape(name)
+        );
+
+        if metadata.is_dir() {
+            out.push_str("<D:resourcetype><D:collection/></D:resourcetype>");
+        } else {
+            let content_type = get_type(name.rsplit('.').next().unwrap_or_default(), &TYPES)
+                .unwrap_or("application/octet-stream");
+            let _ = write!(
+                out,
+                "<D:resourcetype/><D:getcontentlength>{}</D:getcontentlength><D:getcontenttype>{}</D:getcontenttype>",
+                metadata.len(),
+                content_type
+            );
+        }
+
+        if let Some(modified) = metadata
+            .modified()
+            .ok()
+            .and_then(|i| i.duration_since(UNIX_EPOCH).ok())
+        {
+            let _ = write!(
+                out,
+                "<D:getlastmodified>{}</D:getlastmodified>",
+                imp_date(modified.as_secs())
+            );
+        }
+
+        out.push_str("</D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>\n");
+    }
+}
+
+impl Middleware for WebDav {
+    fn pre(&self, req: &mut Request) -> MiddleResult {
+        let handled = matches!(req.method, Method::OPTIONS | Method::PUT | Method::DELETE)
+            || matches!(&req.method, Method::Custom(i) if ["PROPFIND", "MKCOL", "COPY", "MOVE"].contains(&i.as_str()));
+        if !handled || self.segments(&req.path).is_none() {
+            return MiddleResult::Continue;
+        }
+
+        MiddleResult::Send(self.handle(req).unwrap_or_else(|status| {
+            Response::new()
+                .status(status)
+                .text(status.reason_phrase())
+                .content(Content::TXT)
+        }))
+    }
+}
+
+/// Creates a response with no body.
+fn empty(status: Status) -> Response {
+    Response::new().status(status).bytes(&[])
+}
+
+/// Gets the path from a `Destination` header, which is usually an absolute URL.
+fn destination_path(destination: &str) -> &str {
+    let path = match destination.split_once("://") {
+        Some((_, rest)) => rest.find('/').map(|i| &rest[i..]).unwrap_or("/"),
+        None => destination,
+    };
+    path.split(['?', '#']).next().unwrap_or_default()
+}
+
+/// Returns `409 Conflict` if the parent directory of a path doesn't exist.
+fn check_parent(path: &Path) -> Result<(), Status> {
+    match path.parent() {
+        Some(i) if !i.is_dir() => Err(Status::Conflict),
+        _ => Ok(()),
+    }
+}
+
+/// Removes a file or directory (recursively).
+fn remove(path: &Path) -> io::Result<()> {
+    if path.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
+/// Copies a file or directory (recursively).
+fn copy(from: &Path, to: &Path) -> io::Result<()> {
+    if !from.is_dir() {
+        return fs::copy(from, to).map(|_| ());
+    }
+
+    fs::create_dir(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        copy(&entry.path(), &to.join(entry.file_name()))?;
+    }
+    Ok(())
+}
+
+fn io_status(err: io::Error) -> Status {
+    match err.kind() {
+        io::ErrorKind::NotFound => Status::NotFound,
+        io::ErrorKind::PermissionDenied => Status::Forbidden,
+        _ => Status::InternalServerError,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{destination_path, WebDav};
+
+    #[test]
+    fn test_destination_path() {
+        assert_eq!(destination_path("http://localhost:8080/a/b?c"), "/a/b");
+        assert_eq!(destination_path("https://example.com"), "/");
+        assert_eq!(destination_path("/a%20b"), "/a%20b");
+    }
+
+    #[test]
+    fn test_segments() {
+        let dav = WebDav::new("data").path("/files");
+        assert_eq!(dav.segments("/files"), Some(vec![]));
+        assert_eq!(
+            dav.segments("/files/my%20doc.txt"),
+            Some(vec!["my doc.txt".to_owned()])
+        );
+        assert_eq!(dav.segments("/filesystem"), None);
+        assert_eq!(dav.segments("/other"), None);
+        assert!(dav.resolve("/files/../secret").is_err());
+        assert!(dav.resolve("/files/%2e%2e/secret").is_err());
+    }
+
+    #[test]
+    fn test_href() {
+        let dav = WebDav::new("data").path("/files");
+        assert_eq!(dav.href(&[], true), "/files/");
+        assert_eq!(
+            dav.href(&["a b".to_owned(), "c.txt".to_owned()], false),
+            "/files/a%20b/c.txt"
+        );
+        assert_eq!(WebDav::new("data").href(&[], true), "/");
+    }
+}
@@ -1,7 +1,9 @@
+use std::collections::HashMap;
+
 use crate::Header;
 
 /// Common MIME types.
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Content<'a> {
     /// HTML - `text/html`
     HTML,
@@ -37,3 +39,198 @@ impl From<Content<'_>> for Header {
         Header::new("Content-Type", format!("{}; charset=utf-8", x.as_type()))
     }
 }
+
+/// Common MIME Types (sorted in alphabetical order by extension)
+///
+/// These are the defaults of [`MimeTypes`].
+pub const TYPES: [MIME; 56] = [
+    MIME::new("7z", "application/x-7z-compressed"),
+    MIME::new("aac", "audio/aac"),
+    MIME::new("avi", "video/x-msvideo"),
+    MIME::new("bin", "application/octet-stream"),
+    MIME::new("bmp", "image/bmp"),
+    MIME::new("bz", "application/x-bzip"),
+    MIME::new("bz2", "application/x-bzip2"),
+    MIME::new("cda", "application/x-cdf"),
+    MIME::new("css", "text/css"),
+    MIME::new("csv", "text/csv"),
+    MIME::new("epub", "application/epub+zip"),
+    MIME::new("gif", "image/gif"),
+    MIME::new("gz", "application/gzip"),
+    MIME::new("htm", "text/html"),
+    MIME::new("html", "text/html"),
+    MIME::new("ico", "image/x-icon"),
+    MIME::new("ics", "text/calendar"),
+    MIME::new("jar", "application/java-archive"),
+    MIME::new("jpeg", "image/jpeg"),
+    MIME::new("jpg", "image/jpeg"),
+    MIME::new("js", "application/javascript"),
+    MIME::new("json", "application/json"),
+    MIME::new("jsonld", "application/ld+json"),
+    MIME::new("mid", "audio/midi audio/x-midi"),
+    MIME::new("midi", "audio/midi audio/x-midi"),
+    MIME::new("mjs", "text/javascript"),
+    MIME::new("mp3", "audio/mpeg"),
+    MIME::new("mp4", "video/mp4"),
+    MIME::new("mpeg", "video/mpeg"),
+    MIME::new("oga", "audio/ogg"),
+    MIME::new("ogv", "video/ogg"),
+    MIME::new("ogx", "application/ogg"),
+    MIME::new("opus", "audio/opus"),
+    MIME::new("otf", "font/otf"),
+    MIME::new("pdf", "application/pdf"),
+    MIME::new("png", "image/png"),
+    MIME::new("rar", "application/vnd.rar"),
+    MIME::new("rtf", "application/rtf"),
+    MIME::new("sh", "application/x-sh"),
+    MIME::new("svg", "image/svg+xml"),
+    MIME::new("swf", "application/x-shockwave-flash"),
+    MIME::new("tar", "application/x-tar"),
+    MIME::new("tif", "image/tiff"),
+    MIME::new("tiff", "image/tiff"),
+    MIME::new("ts", "text/x-typescript"),
+    MIME::new("ttf", "font/ttf"),
+    MIME::new("txt", "text/plain"),
+    MIME::new("wav", "audio/wav"),
+    MIME::new("weba", "audio/webm"),
+    MIME::new("webm", "video/webm"),
+    MIME::new("webp", "image/webp"),
+    MIME::new("woff", "font/woff"),
+    MIME::new("woff2", "font/woff2"),
+    MIME::new("xhtml", "application/xhtml+xml"),
+    MIME::new("xml", "application/xml"),
+    MIME::new("zip", "application/zip"),
+];
+
+/// Struct to hold a file extension and its matching MIME type
+#[derive(Debug, Clone)]
+pub struct MIME {
+    extension: &'static str,
+    mime_type: &'static str,
+}
+
+impl MIME {
+    /// Create a new MIME type
+    pub const fn new(extension: &'static str, mime_type: &'static str) -> Self {
+        Self {
+            extension,
+            mime_type,
+        }
+    }
+}
+
+/// Gets the MIME type from the specified file extension using a slice of MIME types.
+/// If no type is found, None is returned.
+pub fn get_type(ext: &str, extensions: &[MIME]) -> Option<&'static str> {
+    extensions
+        .binary_search_by(|x| x.extension.cmp(ext))
+        .map(|x| extensions[x].mime_type)
+        .ok()
+}
+
+/// The MIME types used to infer the `Content-Type` of files from their extension.
+/// It starts with the builtin [`TYPES`], which can be overridden or added to.
+///
+/// Each server has its own registry, which is used by [`crate::Response::file`], [`crate::Download::file`] and the ServeStatic extension.
+/// Add types to it with [`crate::Server::mime`] and [`crate::Server::mimes`].
+/// ## Example
+/// ```rust
+/// # use afire::MimeTypes;
+/// let mut types = MimeTypes::new();
+/// types.insert("wasm", "application/wasm");
+/// types.insert("ts", "video/mp2t");
+///
+/// assert_eq!(types.get("wasm"), Some("application/wasm"));
+/// assert_eq!(types.get("TS"), Some("video/mp2t"));
+/// assert_eq!(types.get("png"), Some("image/png"));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct MimeTypes {
+    /// Types added to the registry, by lowercase extension.
+    custom: HashMap<String, String>,
+}
+
+impl MimeTypes {
+    /// Creates a new registry with only the builtin types.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a MIME type for a file extension, replacing any existing one.
+    /// The extension is case insensitive and can start with a `.`.
+    pub fn insert(&mut self, extension: impl AsRef<str>, mime_type: impl AsRef<str>) {
+        let extension = extension
+            .as_ref()
+            .trim_start_matches('.')
+            .to_ascii_lowercase();
+        self.custom.insert(extension, mime_type.as_ref().to_owned());
+    }
+
+    /// Adds many MIME types at once, like with [`MimeTypes::insert`].
+    pub fn extend(&mut self, types: impl IntoIterator<Item = (impl AsRef<str>, impl AsRef<str>)>) {
+        for (extension, mime_type) in types {
+            self.insert(extension, mime_type);
+        }
+    }
+
+    /// Gets the MIME type for a file extension, or None if it's unknown.
+    pub fn get(&self, extension: &str) -> Option<&str> {
+        let extension = extension.to_ascii_lowercase();
+        match self.custom.get(&extension) {
+            Some(i) => Some(i.as_str()),
+            None => get_type(&extension, &TYPES),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::Cursor,
+        sync::{Arc, Mutex},
+    };
+
+    use crate::{
+        internal::testing::{MemoryListener, MemoryStream},
+        Download, Method, Response, Server,
+    };
+
+    #[test]
+    fn test_mime_types() {
+        let dir = std::env::temp_dir().join(format!("afire-mime-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("app.wasm"), b"wasm").unwrap();
+        std::fs::write(dir.join("style.CSS"), b"css").unwrap();
+        std::fs::write(dir.join("clip.ts"), b"ts").unwrap();
+
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let requests = [
+            "GET /app.wasm HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            "GET /style.CSS HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            "GET /clip.ts HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        ];
+        let listener = MemoryListener(Mutex::new(vec![MemoryStream {
+            input: Cursor::new(requests.concat().into_bytes()),
+            output: output.clone(),
+        }]));
+
+        let mut server = Server::<()>::new("localhost", 0)
+            .mime("wasm", "application/wasm")
+            .mime("ts", "video/mp2t");
+        let files = dir.clone();
+        server.route(Method::GET, "/{file}", move |req| {
+            let path = files.join(req.param("file").unwrap());
+            match path.extension().unwrap() == "ts" {
+                true => Download::file(path).unwrap().response(req).unwrap(),
+                false => Response::file(path).unwrap(),
+            }
+        });
+        assert!(server.start_with_listener(listener).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        assert!(output.contains("\r\nContent-Type: application/wasm\r\n"));
+        assert!(output.contains("\r\nContent-Type: text/css\r\n"));
+        assert!(output.contains("\r\nContent-Type: video/mp2t\r\n"));
+    }
+}
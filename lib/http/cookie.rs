@@ -5,9 +5,15 @@
 use std::{
     fmt,
     ops::{Deref, DerefMut},
+    str::FromStr,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
 };
 
-use crate::encoding::url;
+use crate::internal::{
+    date::imp_date,
+    encoding::{hex, sha256, url},
+};
 
 /// Represents a Cookie
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
@@ -38,11 +44,81 @@ pub struct SetCookie {
 
     /// Cookie is secure
     pub secure: bool,
+
+    /// Cookie Expires.
+    /// The date the cookie expires, if both this and Max-Age are set, Max-Age is used by the client.
+    pub expires: Option<SystemTime>,
+
+    /// Cookie is HttpOnly, meaning it can not be accessed by JavaScript.
+    pub http_only: bool,
+
+    /// Cookie SameSite.
+    /// Controls if the cookie is sent with cross-site requests.
+    pub same_site: Option<SameSite>,
+
+    /// Cookie is partitioned, meaning it is stored separately for each top-level site ([CHIPS](https://developer.mozilla.org/en-US/docs/Web/Privacy/Partitioned_cookies)).
+    /// Partitioned cookies must also be Secure.
+    pub partitioned: bool,
+
+    /// If the server's [`CookiePolicy`] is applied to the cookie when it is added to a response with [`crate::Response::cookie`].
+    /// Defaults to true.
+    pub use_policy: bool,
+}
+
+/// Attributes added to every cookie set with [`crate::Response::cookie`], see [`crate::Server::cookie_policy`].
+///
+/// The policy only adds attributes: cookies that are already Secure or HttpOnly stay that way, and a cookie's own SameSite value is kept.
+/// To send a cookie without one of the policy's attributes, like a cookie that JavaScript needs to read, turn the policy off for it with [`SetCookie::use_policy`].
+/// ## Example
+/// ```
+/// # use afire::cookie::{CookiePolicy, SameSite};
+/// // Secure, HttpOnly and SameSite=Strict
+/// let policy = CookiePolicy::default().same_site(Some(SameSite::Strict));
+/// ```
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub struct CookiePolicy {
+    /// Makes cookies Secure, so they are only sent over HTTPS.
+    pub secure: bool,
+    /// Makes cookies HttpOnly, so they can't be read by JavaScript.
+    pub http_only: bool,
+    /// The SameSite value of cookies that don't have one.
+    pub same_site: Option<SameSite>,
+}
+
+/// The [cookie prefixes](https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Set-Cookie#cookie_prefixes) browsers give a special meaning.
+/// Browsers reject cookies with a prefix that don't have the attributes it requires, so a prefixed cookie can't have been set by an insecure page or another subdomain.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum CookiePrefix {
+    /// `__Secure-`, the cookie must be Secure.
+    Secure,
+    /// `__Host-`, the cookie must be Secure, have a Path of `/` and no Domain, which locks it to the host that set it.
+    Host,
+}
+
+/// Values for the SameSite attribute of a [`SetCookie`].
+/// See [MDN](https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Set-Cookie#samesitesamesite-value) for more information.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum SameSite {
+    /// The cookie is only sent with requests from the same site.
+    Strict,
+    /// The cookie is also sent when navigating to the site from another site.
+    /// This is the default in most browsers.
+    Lax,
+    /// The cookie is sent with all requests, including cross-site ones.
+    /// The cookie must also be Secure.
+    None,
 }
 
 /// A collection of Cookies.
-#[derive(Debug, Clone)]
-pub struct CookieJar(pub(crate) Vec<Cookie>);
+///
+/// If the server has a cookie key (see [`crate::Server::cookie_key`]), signed cookies can be made with [`CookieJar::sign`] and read back with [`CookieJar::get_signed`].
+#[derive(Clone)]
+pub struct CookieJar {
+    /// The cookies in the jar.
+    pub(crate) cookies: Vec<Cookie>,
+    /// The key used to sign and verify cookies.
+    pub(crate) key: Option<Arc<[u8]>>,
+}
 
 impl Cookie {
     /// Make a new Cookie from a name and a value.
@@ -91,6 +167,11 @@ impl SetCookie {
             domain: None,
             path: None,
             secure: false,
+            expires: None,
+            http_only: false,
+            same_site: None,
+            partitioned: false,
+            use_policy: true,
         }
     }
 
@@ -157,17 +238,208 @@ impl SetCookie {
         new.secure = secure;
         new
     }
+
+    /// Set the Expires field of a SetCookie.
+    /// This is the date the cookie should expire.
+    /// ## Example
+    /// ```
+    /// # use afire::SetCookie;
+    /// # use std::time::{Duration, UNIX_EPOCH};
+    /// let cookie = SetCookie::new("name", "value")
+    ///     .expires(UNIX_EPOCH + Duration::from_secs(1675899597));
+    ///
+    /// assert_eq!(cookie.to_string(), "name=value; Expires=Wed, 08 Feb 2023 23:39:57 GMT");
+    /// ```
+    pub fn expires(self, expires: SystemTime) -> SetCookie {
+        SetCookie {
+            expires: Some(expires),
+            ..self
+        }
+    }
+
+    /// Set the HttpOnly field of a SetCookie.
+    /// ## Example
+    /// ```
+    /// # use afire::SetCookie;
+    /// let cookie = SetCookie::new("name", "value")
+    ///     .http_only(true);
+    ///
+    /// assert_eq!(cookie.http_only, true);
+    /// ```
+    pub fn http_only(self, http_only: bool) -> SetCookie {
+        SetCookie { http_only, ..self }
+    }
+
+    /// Set the SameSite field of a SetCookie.
+    /// ## Example
+    /// ```
+    /// # use afire::{SetCookie, cookie::SameSite};
+    /// let cookie = SetCookie::new("name", "value")
+    ///     .same_site(SameSite::Strict);
+    ///
+    /// assert_eq!(cookie.to_string(), "name=value; SameSite=Strict");
+    /// ```
+    pub fn same_site(self, same_site: SameSite) -> SetCookie {
+        SetCookie {
+            same_site: Some(same_site),
+            ..self
+        }
+    }
+
+    /// Set the Partitioned field of a SetCookie.
+    /// Browsers will reject partitioned cookies that are not also Secure.
+    /// ## Example
+    /// ```
+    /// # use afire::SetCookie;
+    /// let cookie = SetCookie::new("name", "value")
+    ///     .secure(true)
+    ///     .partitioned(true);
+    ///
+    /// assert_eq!(cookie.to_string(), "name=value; Secure; Partitioned");
+    /// ```
+    pub fn partitioned(self, partitioned: bool) -> SetCookie {
+        SetCookie {
+            partitioned,
+            ..self
+        }
+    }
+
+    /// Sets if the server's [`CookiePolicy`] is applied to the cookie, see [`crate::Server::cookie_policy`].
+    /// ## Example
+    /// ```
+    /// # use afire::SetCookie;
+    /// // Readable by JavaScript, even if the server makes cookies HttpOnly
+    /// let cookie = SetCookie::new("theme", "dark")
+    ///     .use_policy(false);
+    /// ```
+    pub fn use_policy(self, use_policy: bool) -> SetCookie {
+        SetCookie { use_policy, ..self }
+    }
+
+    /// Adds a [`CookiePrefix`] to the name of the cookie, and sets the attributes it requires.
+    /// For [`CookiePrefix::Host`], this removes the Domain and sets the Path to `/`.
+    /// ## Example
+    /// ```
+    /// # use afire::{SetCookie, cookie::CookiePrefix};
+    /// let cookie = SetCookie::new("session", "1234")
+    ///     .with_prefix(CookiePrefix::Host);
+    ///
+    /// assert_eq!(cookie.to_string(), "__Host-session=1234; Path=/; Secure");
+    /// ```
+    pub fn with_prefix(self, prefix: CookiePrefix) -> SetCookie {
+        let mut cookie = SetCookie {
+            secure: true,
+            ..self
+        };
+        cookie.cookie.name = format!("{}{}", prefix.as_str(), cookie.cookie.name);
+        if prefix == CookiePrefix::Host {
+            cookie.domain = None;
+            cookie.path = Some("/".to_owned());
+        }
+        cookie
+    }
+
+    /// Gets the [`CookiePrefix`] of the cookie's name, if it has one.
+    /// Like in browsers, prefixes are matched case-insensitively.
+    pub fn prefix(&self) -> Option<CookiePrefix> {
+        [CookiePrefix::Secure, CookiePrefix::Host]
+            .iter()
+            .copied()
+            .find(|i| {
+                let name = self.cookie.name.as_bytes();
+                name.len() >= i.as_str().len()
+                    && name[..i.as_str().len()].eq_ignore_ascii_case(i.as_str().as_bytes())
+            })
+    }
+
+    /// Checks if the cookie has the attributes required by its [`CookiePrefix`].
+    /// Cookies without a prefix are always valid.
+    /// ## Example
+    /// ```
+    /// # use afire::SetCookie;
+    /// assert!(!SetCookie::new("__Host-id", "1").secure(true).prefix_valid());
+    /// assert!(SetCookie::new("__Host-id", "1").secure(true).path("/").prefix_valid());
+    /// ```
+    pub fn prefix_valid(&self) -> bool {
+        match self.prefix() {
+            None => true,
+            Some(CookiePrefix::Secure) => self.secure,
+            Some(CookiePrefix::Host) => {
+                self.secure && self.domain.is_none() && self.path.as_deref() == Some("/")
+            }
+        }
+    }
+
+    /// Adds the attributes of a [`CookiePolicy`] to the cookie, unless it opted out with [`SetCookie::use_policy`].
+    pub(crate) fn apply_policy(&mut self, policy: &CookiePolicy) {
+        if !self.use_policy {
+            return;
+        }
+
+        self.secure |= policy.secure;
+        self.http_only |= policy.http_only;
+        self.same_site = self.same_site.or(policy.same_site);
+    }
+}
+
+impl CookiePolicy {
+    /// Sets if cookies are made Secure.
+    pub fn secure(self, secure: bool) -> Self {
+        Self { secure, ..self }
+    }
+
+    /// Sets if cookies are made HttpOnly.
+    pub fn http_only(self, http_only: bool) -> Self {
+        Self { http_only, ..self }
+    }
+
+    /// Sets the SameSite value of cookies that don't have one.
+    pub fn same_site(self, same_site: Option<SameSite>) -> Self {
+        Self { same_site, ..self }
+    }
+}
+
+impl Default for CookiePolicy {
+    /// Secure, HttpOnly and SameSite=Lax, which protects cookies from being read by scripts or sent over HTTP, and from most cross-site request forgery.
+    fn default() -> Self {
+        Self {
+            secure: true,
+            http_only: true,
+            same_site: Some(SameSite::Lax),
+        }
+    }
+}
+
+impl CookiePrefix {
+    /// Gets the prefix, like `__Host-`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CookiePrefix::Secure => "__Secure-",
+            CookiePrefix::Host => "__Host-",
+        }
+    }
+}
+
+impl SameSite {
+    /// Gets the value of the SameSite attribute.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SameSite::Strict => "Strict",
+            SameSite::Lax => "Lax",
+            SameSite::None => "None",
+        }
+    }
 }
 
 impl CookieJar {
     /// Create a new empty CookieJar.
     pub fn new() -> CookieJar {
-        CookieJar(Vec::new())
+        CookieJar::from_vec(Vec::new())
     }
 
     /// Create a new CookieJar from a Vec of Cookies.
     pub fn from_vec(cookies: Vec<Cookie>) -> CookieJar {
-        CookieJar(cookies)
+        CookieJar { cookies, key: None }
     }
 
     /// Check if the cookie jar contains a cookie with the given name.
@@ -194,7 +466,7 @@ impl CookieJar {
     /// // Very secure session ID
     /// # }
     pub fn add(&mut self, name: impl AsRef<str>, value: impl AsRef<str>) {
-        self.0.push(Cookie::new(name, value));
+        self.cookies.push(Cookie::new(name, value));
     }
 
     /// Gets the value of a cookie with the given name.
@@ -239,7 +511,7 @@ impl CookieJar {
     /// jar.add_cookie(Cookie::new("Session", "1234"));
     /// # }
     pub fn add_cookie(&mut self, cookie: Cookie) {
-        self.0.push(cookie);
+        self.cookies.push(cookie);
     }
 
     /// Gets a reference to the Cookie struct of a cookie with the given name.
@@ -270,6 +542,72 @@ impl CookieJar {
     pub fn get_cookie_mut(&mut self, name: &str) -> Option<&mut Cookie> {
         self.iter_mut().find(|i| i.name == name)
     }
+
+    /// Gets the value of a cookie with the given name, parsed into any type that implements [`FromStr`].
+    /// If the cookie does not exist or can not be parsed, None is returned.
+    /// ## Example
+    /// ```rust
+    /// # use afire::cookie::CookieJar;
+    /// # fn test(jar: &CookieJar) {
+    /// let visits = jar.get_as::<u32>("Visits").unwrap_or(0);
+    /// # }
+    pub fn get_as<T: FromStr>(&self, name: &str) -> Option<T> {
+        self.get(name)?.parse().ok()
+    }
+
+    /// Gets the value of a signed cookie with the given name, verifying its signature with the server's cookie key.
+    /// If the cookie does not exist, was not signed, or was tampered with, None is returned.
+    /// Signed cookies are made with [`CookieJar::sign`].
+    /// ## Example
+    /// ```rust
+    /// # use afire::{Method, Server, Response};
+    /// # let mut server = Server::<()>::new("localhost", 8080);
+    /// server.route(Method::GET, "/", |req| {
+    ///     let user = req.cookies.get_signed("User").unwrap_or("Guest");
+    ///     Response::new().text(format!("Hello, {user}"))
+    /// });
+    /// ```
+    pub fn get_signed(&self, name: &str) -> Option<&str> {
+        let key = self.key.as_ref()?;
+        let (value, signature) = self.get(name)?.rsplit_once('.')?;
+
+        let expected = hex::encode(&sha256::hmac(key, format!("{name}={value}").as_bytes()));
+        let matches = expected.len() == signature.len()
+            && expected
+                .bytes()
+                .zip(signature.bytes())
+                .fold(0, |acc, (a, b)| acc | (a ^ b))
+                == 0;
+
+        matches.then_some(value)
+    }
+
+    /// Signs a cookie with the server's cookie key (HMAC-SHA256), so it can be read back with [`CookieJar::get_signed`].
+    /// The signature is added to the end of the cookie's value.
+    /// Signed cookies can still be read by the client, but not changed.
+    ///
+    /// ## Panics
+    /// Panics if the server has no cookie key, see [`crate::Server::cookie_key`].
+    /// ## Example
+    /// ```rust
+    /// # use afire::{Method, Server, Response, SetCookie};
+    /// # let mut server = Server::<()>::new("localhost", 8080);
+    /// server.route(Method::POST, "/login", |req| {
+    ///     let cookie = req.cookies.sign(SetCookie::new("User", "Darren").http_only(true));
+    ///     Response::new().cookie(cookie)
+    /// });
+    /// ```
+    pub fn sign(&self, mut cookie: SetCookie) -> SetCookie {
+        let key = self
+            .key
+            .as_ref()
+            .expect("No cookie key set, use Server::cookie_key to set one");
+
+        let message = format!("{}={}", cookie.cookie.name, cookie.cookie.value);
+        let signature = hex::encode(&sha256::hmac(key, message.as_bytes()));
+        cookie.cookie.value = format!("{}.{}", cookie.cookie.value, signature);
+        cookie
+    }
 }
 
 impl Default for CookieJar {
@@ -282,13 +620,20 @@ impl Deref for CookieJar {
     type Target = Vec<Cookie>;
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.cookies
     }
 }
 
 impl DerefMut for CookieJar {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
+        &mut self.cookies
+    }
+}
+
+// The key is left out so it doesn't end up in logs
+impl fmt::Debug for CookieJar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("CookieJar").field(&self.cookies).finish()
     }
 }
 
@@ -305,18 +650,24 @@ impl fmt::Display for SetCookie {
         let mut cookie_string = format!("{}={}; ", self.cookie.name, self.cookie.value);
 
         // Add max_age
-        if self.max_age.is_some() {
-            cookie_string.push_str(&format!("Max-Age={}; ", self.max_age.unwrap()));
+        if let Some(max_age) = self.max_age {
+            cookie_string.push_str(&format!("Max-Age={max_age}; "));
         }
 
         // Add domain
-        if self.domain.is_some() {
-            cookie_string.push_str(&format!("Domain={}; ", self.domain.as_ref().unwrap()));
+        if let Some(domain) = &self.domain {
+            cookie_string.push_str(&format!("Domain={domain}; "));
         }
 
         // Add path
-        if self.path.is_some() {
-            cookie_string.push_str(&format!("Path={}; ", self.path.as_ref().unwrap()));
+        if let Some(path) = &self.path {
+            cookie_string.push_str(&format!("Path={path}; "));
+        }
+
+        // Add expires
+        if let Some(expires) = self.expires {
+            let epoch = expires.duration_since(UNIX_EPOCH).unwrap_or_default();
+            cookie_string.push_str(&format!("Expires={}; ", imp_date(epoch.as_secs())));
         }
 
         // Add secure
@@ -324,13 +675,37 @@ impl fmt::Display for SetCookie {
             cookie_string.push_str("Secure; ");
         }
 
-        f.write_str(cookie_string.trim_end())
+        // Add http_only
+        if self.http_only {
+            cookie_string.push_str("HttpOnly; ");
+        }
+
+        // Add same_site
+        if let Some(same_site) = self.same_site {
+            cookie_string.push_str(&format!("SameSite={}; ", same_site.as_str()));
+        }
+
+        // Add partitioned
+        if self.partitioned {
+            cookie_string.push_str("Partitioned; ");
+        }
+
+        f.write_str(cookie_string.trim_end_matches("; "))
     }
 }
 
 #[cfg(test)]
 mod test {
-    use super::Cookie;
+    use std::{
+        io::Cursor,
+        sync::{Arc, Mutex},
+    };
+
+    use super::{Cookie, CookieJar, CookiePolicy, CookiePrefix, SameSite, SetCookie};
+    use crate::{
+        internal::testing::{MemoryListener, MemoryStream},
+        Method, Response, Server,
+    };
 
     #[test]
     fn test_cookie_parse() {
@@ -355,4 +730,96 @@ mod test {
         assert_eq!(cookies[1].name, "name3");
         assert_eq!(cookies[1].value, "value3");
     }
+
+    #[test]
+    fn test_set_cookie_display() {
+        let cookie = SetCookie::new("name", "value")
+            .max_age(60)
+            .path("/")
+            .secure(true)
+            .http_only(true)
+            .same_site(SameSite::Lax);
+        assert_eq!(
+            cookie.to_string(),
+            "name=value; Max-Age=60; Path=/; Secure; HttpOnly; SameSite=Lax"
+        );
+    }
+
+    #[test]
+    fn test_cookie_policy() {
+        let mut cookie = SetCookie::new("a", "1").same_site(SameSite::Strict);
+        cookie.apply_policy(&CookiePolicy::default());
+        assert_eq!(cookie.to_string(), "a=1; Secure; HttpOnly; SameSite=Strict");
+
+        let mut cookie = SetCookie::new("a", "1").use_policy(false);
+        cookie.apply_policy(&CookiePolicy::default());
+        assert_eq!(cookie.to_string(), "a=1");
+    }
+
+    #[test]
+    fn test_cookie_prefix() {
+        let cookie = SetCookie::new("id", "1")
+            .domain("example.com")
+            .with_prefix(CookiePrefix::Host);
+        assert_eq!(cookie.prefix(), Some(CookiePrefix::Host));
+        assert!(cookie.prefix_valid());
+        assert_eq!(cookie.to_string(), "__Host-id=1; Path=/; Secure");
+
+        assert_eq!(
+            SetCookie::new("__secure-id", "1").prefix(),
+            Some(CookiePrefix::Secure)
+        );
+        assert!(!SetCookie::new("__Secure-id", "1").prefix_valid());
+        assert!(SetCookie::new("__Secure-id", "1")
+            .secure(true)
+            .prefix_valid());
+        assert!(!SetCookie::new("__Host-id", "1")
+            .secure(true)
+            .path("/")
+            .domain("example.com")
+            .prefix_valid());
+        assert!(SetCookie::new("_Host-id", "1").prefix_valid());
+    }
+
+    #[test]
+    fn test_signed_cookie() {
+        let mut jar = CookieJar::new();
+        jar.key = Some(Arc::from(&b"secret"[..]));
+
+        let signed = jar.sign(SetCookie::new("user", "darren")).cookie;
+        jar.add_cookie(signed.clone());
+        jar.add(
+            "forged",
+            format!("admin.{}", signed.value.split_once('.').unwrap().1),
+        );
+        jar.add("unsigned", "darren");
+
+        assert_eq!(jar.get_signed("user"), Some("darren"));
+        assert_eq!(jar.get_signed("forged"), None);
+        assert_eq!(jar.get_signed("unsigned"), None);
+
+        jar.key = None;
+        assert_eq!(jar.get_signed("user"), None);
+    }
+
+    #[test]
+    fn test_server_cookie_policy() {
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let listener = MemoryListener(Mutex::new(vec![MemoryStream {
+            input: Cursor::new(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n".to_vec()),
+            output: output.clone(),
+        }]));
+
+        let mut server = Server::<()>::new("localhost", 0).cookie_policy(CookiePolicy::default());
+        server.route(Method::GET, "/", |_| {
+            Response::new()
+                .cookie(SetCookie::new("session", "1234"))
+                .cookie(SetCookie::new("theme", "dark").use_policy(false))
+        });
+        assert!(server.start_with_listener(listener).is_err());
+
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        assert!(output.contains("\r\nSet-Cookie: session=1234; Secure; HttpOnly; SameSite=Lax\r\n"));
+        assert!(output.contains("\r\nSet-Cookie: theme=dark\r\n"));
+    }
 }
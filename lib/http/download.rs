@@ -0,0 +1,290 @@
+//! Downloads that support range requests, so clients can resume them or seek through media.
+//!
+//! A [`Download`] wraps any `Read + Seek` source, like a file or a blob from a database, and makes a `200 OK`, `206 Partial Content` or `416 Range Not Satisfiable` response depending on the request's `Range` and `If-Range` headers.
+//!
+//! ## Example
+//! ```rust,no_run
+//! # use afire::{Method, Server, Response, Download};
+//! # let mut server = Server::<()>::new("localhost", 8080);
+//! server.route(Method::GET, "/video", |req| {
+//!     Download::file("data/video.mp4")
+//!         .and_then(|i| i.response(req))
+//!         .unwrap_or_else(|_| Response::not_found())
+//! });
+//! ```
+
+use std::{
+    any::Any,
+    fs::File,
+    io::{self, Cursor, Read, Seek, SeekFrom},
+    path::Path,
+    sync::Arc,
+    time::UNIX_EPOCH,
+};
+
+use crate::{
+    header::typed::{ByteRange, Range},
+    internal::date::{imp_date, parse_date},
+    response::ResponseBody,
+    HeaderType, Method, Request, Response, Status,
+};
+
+/// A download that supports range requests.
+/// See the [module docs](crate::download) for an example.
+///
+/// Only requests with a single range get a `206 Partial Content` response, the whole body is sent if multiple ranges are requested.
+/// If the request has an `If-Range` header, the range is only used if it matches the download's [`Download::etag`] or [`Download::last_modified`] date, so clients don't combine parts of different versions.
+pub struct Download<R> {
+    /// The source of the body.
+    source: R,
+    /// The `Content-Type` header.
+    content_type: Option<String>,
+    /// The extension of the file, used to infer the `Content-Type` if it isn't set.
+    extension: Option<String>,
+    /// The `ETag` header, with quotes.
+    etag: Option<String>,
+    /// When the source was last modified, in seconds since the unix epoch.
+    last_modified: Option<u64>,
+    /// The name browsers will save the download as, see [`Response::attachment`].
+    filename: Option<String>,
+}
+
+impl Download<File> {
+    /// Creates a download of the file at `path`.
+    /// The `Content-Type` is inferred from the file extension with the server's MIME types (see [`crate::Server::mime`]), and the `Last-Modified` date is taken from the file's metadata.
+    pub fn file(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path)?;
+        let last_modified = file
+            .metadata()?
+            .modified()
+            .ok()
+            .and_then(|i| i.duration_since(UNIX_EPOCH).ok())
+            .map(|i| i.as_secs());
+
+        let extension = path
+            .extension()
+            .map(|i| i.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        Ok(Self {
+            last_modified,
+            extension: Some(extension),
+            ..Self::new(file)
+        })
+    }
+}
+
+impl<R: Read + Seek + Send + 'static> Download<R> {
+    /// Creates a new download of the source, with a `Content-Type` of `application/octet-stream`.
+    pub fn new(source: R) -> Self {
+        Self {
+            source,
+            content_type: None,
+            extension: None,
+            etag: None,
+            last_modified: None,
+            filename: None,
+        }
+    }
+
+    /// Sets the `Content-Type` of the download.
+    pub fn content_type(self, content_type: impl AsRef<str>) -> Self {
+        Self {
+            content_type: Some(content_type.as_ref().to_owned()),
+            ..self
+        }
+    }
+
+    /// Sets the extension used to infer the `Content-Type` with the server's MIME types, if it isn't set with [`Download::content_type`].
+    #[cfg(feature = "extensions")]
+    pub(crate) fn extension(self, extension: impl AsRef<str>) -> Self {
+        Self {
+            extension: Some(extension.as_ref().to_owned()),
+            ..self
+        }
+    }
+
+    /// Sets the `ETag` of the download, used to check `If-Range` headers.
+    /// It is quoted if it isn't already.
+    pub fn etag(self, etag: impl AsRef<str>) -> Self {
+        let etag = etag.as_ref();
+        let etag = match etag.starts_with('"') || etag.starts_with("W/") {
+            true => etag.to_owned(),
+            false => format!("\"{etag}\""),
+        };
+
+        Self {
+            etag: Some(etag),
+            ..self
+        }
+    }
+
+    /// Sets when the download was last modified, in seconds since the unix epoch.
+    /// This is sent in the `Last-Modified` header and used to check `If-Range` headers.
+    pub fn last_modified(self, last_modified: u64) -> Self {
+        Self {
+            last_modified: Some(last_modified),
+            ..self
+        }
+    }
+
+    /// Has browsers save the download as `filename`, see [`Response::attachment`].
+    pub fn filename(self, filename: impl AsRef<str>) -> Self {
+        Self {
+            filename: Some(filename.as_ref().to_owned()),
+            ..self
+        }
+    }
+
+    /// Makes the response for a request.
+    /// This fails if the length of the source can't be found by seeking to its end, or it can't be seeked to the start of the range.
+    pub fn response(mut self, req: &Request) -> io::Result<Response> {
+        let len = self.source.seek(SeekFrom::End(0))?;
+
+        let mut res = Response::new().header(HeaderType::AcceptRanges, "bytes");
+        match (&self.content_type, self.extension.take()) {
+            (Some(content_type), _) => res.headers.add(HeaderType::ContentType, content_type),
+            (None, Some(extension)) => res.mime_extension = Some(extension.into()),
+            (None, None) => res
+                .headers
+                .add(HeaderType::ContentType, "application/octet-stream"),
+        }
+        if let Some(etag) = &self.etag {
+            res.headers.add(HeaderType::ETag, etag);
+        }
+        if let Some(last_modified) = self.last_modified {
+            res.headers
+                .add(HeaderType::LastModified, imp_date(last_modified));
+        }
+        if let Some(filename) = &self.filename {
+            res = res.attachment(filename);
+        }
+
+        let range = match self.range(req) {
+            Some(range) => range,
+            None => {
+                self.source.seek(SeekFrom::Start(0))?;
+                let res = res.header(HeaderType::ContentLength, len.to_string());
+                return Ok(Self::body(res, self.source, None));
+            }
+        };
+
+        let (start, end) = match range.resolve(len) {
+            Some(i) => i,
+            None => {
+                return Ok(res
+                    .status(Status::RangeNotSatisfiable)
+                    .header(HeaderType::ContentRange, format!("bytes */{len}"))
+                    .bytes(&[]))
+            }
+        };
+
+        self.source.seek(SeekFrom::Start(start))?;
+        let res = res
+            .status(Status::PartialContent)
+            .header(
+                HeaderType::ContentRange,
+                format!("bytes {start}-{end}/{len}"),
+            )
+            .header(HeaderType::ContentLength, (end - start + 1).to_string());
+        Ok(Self::body(res, self.source, Some(end - start + 1)))
+    }
+
+    /// Gets the range the request asks for, if it should be used.
+    fn range(&self, req: &Request) -> Option<ByteRange> {
+        if !matches!(req.method, Method::GET | Method::HEAD) {
+            return None;
+        }
+
+        let range = req.headers.get_typed::<Range>()?;
+        if !range.unit.eq_ignore_ascii_case("bytes") || range.ranges.len() != 1 {
+            return None;
+        }
+
+        // Weak ETags never match, and dates have to match exactly
+        if let Some(if_range) = req.headers.get(HeaderType::IfRange) {
+            let matches = match if_range.trim() {
+                i if i.starts_with('"') => self.etag.as_deref() == Some(i),
+                i if i.starts_with("W/") => false,
+                i => self.last_modified.is_some() && parse_date(i) == self.last_modified,
+            };
+            if !matches {
+                return None;
+            }
+        }
+
+        range.ranges.first().copied()
+    }
+
+    /// Sets the body of the response to `len` bytes of the source, or all of it.
+    /// Whole files are sent as files, so they can be sent with `sendfile`, and whole `Cursor<Arc<[u8]>>`s are sent without copying them.
+    fn body(mut res: Response, source: R, len: Option<u64>) -> Response {
+        if let Some(len) = len {
+            return res.stream(source.take(len));
+        }
+
+        let source = match (Box::new(source) as Box<dyn Any>).downcast::<File>() {
+            Ok(file) => {
+                res.data = ResponseBody::File(*file);
+                return res;
+            }
+            Err(source) => source,
+        };
+
+        match source.downcast::<Cursor<Arc<[u8]>>>() {
+            Ok(data) => res.shared(data.into_inner()),
+            Err(source) => res.stream(*source.downcast::<R>().expect("Source is an R")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::Cursor,
+        sync::{Arc, Mutex},
+    };
+
+    use super::Download;
+    use crate::{
+        internal::testing::{MemoryListener, MemoryStream},
+        Method, Server,
+    };
+
+    #[test]
+    fn test_download_ranges() {
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let requests = [
+            "GET / HTTP/1.1\r\nHost: localhost\r\nRange: bytes=2-4\r\n\r\n",
+            "GET / HTTP/1.1\r\nHost: localhost\r\nRange: bytes=-3\r\nIf-Range: \"v1\"\r\n\r\n",
+            "GET / HTTP/1.1\r\nHost: localhost\r\nRange: bytes=2-4\r\nIf-Range: \"v0\"\r\n\r\n",
+            "GET / HTTP/1.1\r\nHost: localhost\r\nRange: bytes=20-\r\n\r\n",
+        ];
+        let listener = MemoryListener(Mutex::new(vec![MemoryStream {
+            input: Cursor::new(requests.concat().into_bytes()),
+            output: output.clone(),
+        }]));
+
+        let mut server = Server::<()>::new("localhost", 0);
+        server.route(Method::GET, "/", |req| {
+            Download::new(Cursor::new(b"0123456789".to_vec()))
+                .etag("v1")
+                .response(req)
+                .unwrap()
+        });
+        assert!(server.start_with_listener(listener).is_err());
+
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        let responses = output.split("HTTP/1.1 ").skip(1).collect::<Vec<_>>();
+        assert!(responses[0].starts_with("206 Partial Content\r\n"));
+        assert!(responses[0].contains("\r\nContent-Range: bytes 2-4/10\r\n"));
+        assert!(responses[0].ends_with("\r\n\r\n234"));
+        assert!(responses[1].ends_with("\r\n\r\n789"));
+        assert!(responses[2].starts_with("200 OK\r\n"));
+        assert!(responses[2].contains("\r\nAccept-Ranges: bytes\r\n"));
+        assert!(responses[2].ends_with("\r\n\r\n0123456789"));
+        assert!(responses[3].starts_with("416 "));
+        assert!(responses[3].contains("\r\nContent-Range: bytes */10\r\n"));
+    }
+}
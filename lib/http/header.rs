@@ -2,11 +2,15 @@
 
 use std::{
     fmt::{self, Display},
+    hash::{Hash, Hasher},
+    mem,
     ops::{Deref, DerefMut},
 };
 
 use crate::error::{ParseError, Result};
 
+pub mod typed;
+
 /// Http header.
 /// Has a name and a value.
 #[derive(Debug, Hash, Clone, PartialEq, Eq)]
@@ -40,9 +44,49 @@ pub struct HeaderParams<'a> {
 
 /// Collection of headers.
 /// Used within [`Request`](crate::Request) and [`Response`](crate::Response).
+///
+/// Header names are matched case-insensitively, but the original casing of custom headers is kept when they are sent.
+/// A header can appear more than once (like `Set-Cookie` or `Vary`), so [`Headers::add`] appends a new value while [`Headers::insert`] replaces any existing ones.
+/// Use [`Headers::get_all`] to get every value of a repeated header.
 #[derive(Debug, Hash, Clone, PartialEq, Eq, Default)]
 pub struct Headers(pub(crate) Vec<Header>);
 
+/// How responses with more than one header of the same name are sent.
+/// Set for the whole server with [`crate::Server::header_policy`], or for a single response with [`crate::Response::header_policy`].
+///
+/// With either policy, the server's default headers are only sent if the response doesn't have a header with the same name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HeaderPolicy {
+    /// Headers that can only be sent once, like `Content-Type`, only have their last value sent, so setting one in a route or middleware replaces any earlier value.
+    /// Other headers, like `Set-Cookie`, `Vary` and custom headers, are sent as they were added.
+    ///
+    /// The headers that can only be sent once are `Accept-Ranges`, `Access-Control-Allow-Origin`, `Content-Disposition`, `Content-Length`, `Content-Range`, `Content-Type`, `Date`, `ETag`, `Last-Modified`, `Location`, `Referrer-Policy`, `Retry-After`, `Server`, `Strict-Transport-Security`, `X-Content-Type-Options` and `X-Frame-Options`.
+    #[default]
+    Replace,
+    /// Every header is sent as it was added.
+    Append,
+}
+
+/// Headers that can only be sent once, so [`HeaderPolicy::Replace`] only keeps their last value.
+const SINGLE: &[&str] = &[
+    "accept-ranges",
+    "access-control-allow-origin",
+    "content-disposition",
+    "content-length",
+    "content-range",
+    "content-type",
+    "date",
+    "etag",
+    "last-modified",
+    "location",
+    "referrer-policy",
+    "retry-after",
+    "server",
+    "strict-transport-security",
+    "x-content-type-options",
+    "x-frame-options",
+];
+
 impl Header {
     /// Make a new header from a name and a value.
     /// The name must implement `Into<HeaderType>`, so it can be a string or a [`HeaderType`].
@@ -92,7 +136,7 @@ impl Header {
     }
 
     /// Get the parameters of the header.
-    pub fn params(&self) -> HeaderParams {
+    pub fn params(&self) -> HeaderParams<'_> {
         HeaderParams::new(self.value.as_str())
     }
 }
@@ -133,7 +177,7 @@ impl<'a> HeaderParams<'a> {
 
     /// Gets the value of the specified parameter, returning `None` if it is not present.
     /// A parameter is a key-value pair that is separated by a semicolon and a space.
-    pub fn get(&self, name: impl AsRef<str>) -> Option<&str> {
+    pub fn get(&self, name: impl AsRef<str>) -> Option<&'a str> {
         let name = name.as_ref();
         self.params
             .iter()
@@ -187,6 +231,7 @@ impl Headers {
     }
 
     /// Adds a header to the collection, using the specified name and value.
+    /// Any existing headers with the same name are kept, see [`Headers::insert`] to replace them instead.
     /// See [`Headers::add_header`] for a version that takes a [`Header`] directly.
     /// ## Example
     /// ```rust
@@ -216,6 +261,74 @@ impl Headers {
             .map(|x| x.value.as_str())
     }
 
+    /// Gets the values of every header with the specified name, in the order they were added.
+    /// Useful for headers that can be sent more than once, like `Set-Cookie` or `Vary`.
+    /// ## Example
+    /// ```rust
+    /// # use afire::header::{Headers, HeaderType};
+    /// let mut headers = Headers::default();
+    /// headers.add(HeaderType::SetCookie, "a=1");
+    /// headers.add("set-cookie", "b=2");
+    ///
+    /// let cookies = headers.get_all(HeaderType::SetCookie).collect::<Vec<_>>();
+    /// assert_eq!(cookies, ["a=1", "b=2"]);
+    /// ```
+    pub fn get_all(&self, name: impl Into<HeaderType>) -> impl Iterator<Item = &str> {
+        let name = name.into();
+        self.iter()
+            .filter(move |x| x.name == name)
+            .map(|x| x.value.as_str())
+    }
+
+    /// Sets the value of the specified header, removing any existing headers with the same name.
+    /// If the header was already present, it keeps its position in the collection.
+    /// See [`Headers::add`] to add another value without removing the existing ones.
+    /// ## Example
+    /// ```rust
+    /// # use afire::header::{Headers, HeaderType};
+    /// let mut headers = Headers::default();
+    /// headers.add(HeaderType::ContentType, "text/plain");
+    /// headers.insert(HeaderType::ContentType, "text/html");
+    ///
+    /// assert_eq!(headers.get(HeaderType::ContentType), Some("text/html"));
+    /// assert_eq!(headers.len(), 1);
+    /// ```
+    pub fn insert(&mut self, name: impl Into<HeaderType>, value: impl AsRef<str>) {
+        let header = Header::new(name, value);
+        match self.iter().position(|x| x.name == header.name) {
+            Some(i) => {
+                let name = header.name.clone();
+                self.0[i] = header;
+                let mut idx = 0;
+                self.0.retain(|x| {
+                    idx += 1;
+                    idx - 1 == i || x.name != name
+                });
+            }
+            None => self.0.push(header),
+        }
+    }
+
+    /// Removes every header with the specified name, returning their values.
+    /// ## Example
+    /// ```rust
+    /// # use afire::header::{Headers, HeaderType};
+    /// let mut headers = Headers::default();
+    /// headers.add("X-Test", "a");
+    /// headers.add("x-test", "b");
+    ///
+    /// assert_eq!(headers.remove("X-TEST"), ["a", "b"]);
+    /// assert!(!headers.has("X-Test"));
+    /// ```
+    pub fn remove(&mut self, name: impl Into<HeaderType>) -> Vec<String> {
+        let name = name.into();
+        let (removed, kept) = mem::take(&mut self.0)
+            .into_iter()
+            .partition::<Vec<_>, _>(|x| x.name == name);
+        self.0 = kept;
+        removed.into_iter().map(|x| x.value).collect()
+    }
+
     /// Gets the value of the specified header as a mutable reference.
     /// If the header is not present, `None` is returned.
     /// See [`Headers::get`] for a non-mutable version.
@@ -252,6 +365,33 @@ impl Headers {
         let name = name.into();
         self.iter_mut().find(|x| x.name == name)
     }
+
+    /// Removes all but the last value of each header that can only be sent once, for [`HeaderPolicy::Replace`].
+    pub(crate) fn dedup(&mut self) {
+        let mut seen = Vec::new();
+        let keep = self
+            .0
+            .iter()
+            .rev()
+            .map(|x| {
+                let name = x.name.as_str();
+                if !SINGLE.iter().any(|i| i.eq_ignore_ascii_case(name)) {
+                    return true;
+                }
+                if seen.contains(&&x.name) {
+                    return false;
+                }
+                seen.push(&x.name);
+                true
+            })
+            .collect::<Vec<_>>();
+
+        let mut idx = self.0.len();
+        self.0.retain(|_| {
+            idx -= 1;
+            keep[idx]
+        });
+    }
 }
 
 impl fmt::Display for Header {
@@ -270,6 +410,7 @@ impl fmt::Display for Header {
 
 /// Stringify a Vec of headers.
 /// Each header is in the format `name: value` amd separated by a carriage return and newline (`\r\n`).
+#[cfg(feature = "client")]
 pub(crate) fn headers_to_string(headers: &[Header]) -> String {
     let out = headers
         .iter()
@@ -282,7 +423,10 @@ pub(crate) fn headers_to_string(headers: &[Header]) -> String {
 // https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers
 /// Common HTTP headers.
 /// Just the 'common' ones, which are ones that I use semi-frequently, or that are used internally.
-#[derive(Debug, Hash, Clone, PartialEq, Eq)]
+///
+/// Header names are case-insensitive, so `HeaderType::Custom("X-Test")` is equal to `HeaderType::Custom("x-test")`.
+/// The original casing of custom headers is kept when they are displayed.
+#[derive(Debug, Clone, Eq)]
 pub enum HeaderType {
     /// Indicates what content types (MIME types) are acceptable for the client.
     /// ([MDN](https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Accept))
@@ -296,9 +440,22 @@ pub enum HeaderType {
     /// Indicates what languages are acceptable for the client.
     /// ([MDN](https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Accept-Language))
     AcceptLanguage,
+    /// Advertises that the server supports range requests, with the unit `bytes`.
+    /// ([MDN](https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Accept-Ranges))
+    AcceptRanges,
+    /// Credentials used to authenticate the client with the server.
+    /// ([MDN](https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Authorization))
+    Authorization,
+    /// Directives for how the response should be cached by browsers and shared caches.
+    /// ([MDN](https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Cache-Control))
+    CacheControl,
     /// Allows re-using a socket for multiple requests with `keep-alive`, or closing the sockets with `close`.
     /// ([MDN](https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Connection))
     Connection,
+    /// Indicates if the content should be displayed inline in the browser or downloaded as an attachment, and the filename to use.
+    /// This can be set on a response with the [`crate::Response::attachment`] method.
+    /// ([MDN](https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Content-Disposition))
+    ContentDisposition,
     /// Lists the encodings that have been applied to the entity body.
     /// See [`HeaderType::AcceptEncoding`]
     /// ([MDN](https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Content-Encoding))
@@ -307,6 +464,10 @@ pub enum HeaderType {
     /// This is only required when the body is not chunked.
     /// ([MDN](https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Content-Length))
     ContentLength,
+    /// Indicates where a partial body belongs in the full resource, sent with `206 Partial Content` responses.
+    /// See [`HeaderType::Range`].
+    /// ([MDN](https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Content-Range))
+    ContentRange,
     /// Indicates the media type of the entity body.
     /// This can be set on a response with the [`crate::Response::content`] method.
     /// ([MDN](https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Content-Type))
@@ -317,21 +478,59 @@ pub enum HeaderType {
     /// The date and time at which the message was originated.
     /// ([MDN](https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Date))
     Date,
+    /// An identifier for a specific version of a resource, often a hash of the content.
+    /// Used with [`HeaderType::IfNoneMatch`] to avoid re-sending unchanged resources.
+    /// ([MDN](https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/ETag))
+    ETag,
+    /// Standard header added by reverse proxies to describe the original request, including the client's address (`for=`).
+    /// See [RFC 7239](https://www.rfc-editor.org/rfc/rfc7239).
+    /// ([MDN](https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Forwarded))
+    Forwarded,
     /// Sent with requests to indicate the host and port of the server to which the request is being sent.
     /// This allows for reverse proxies to forward requests to the correct server.
     /// ([MDN](https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Host))
     Host,
+    /// Makes a request conditional, the server only sends the resource if it has been modified after the given date, see [`HeaderType::LastModified`].
+    /// Otherwise a `304 Not Modified` response is sent.
+    /// ([MDN](https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/If-Modified-Since))
+    IfModifiedSince,
+    /// Makes a request conditional, the server only sends the resource if its [`HeaderType::ETag`] doesn't match any of the listed ones.
+    /// Otherwise a `304 Not Modified` response is sent.
+    /// ([MDN](https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/If-None-Match))
+    IfNoneMatch,
+    /// Makes a range request conditional, the range is only used if the resource still matches the given [`HeaderType::ETag`] or date.
+    /// Otherwise the whole resource is sent.
+    /// ([MDN](https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/If-Range))
+    IfRange,
+    /// Used with `Connection: keep-alive` to advertise how long an idle connection will be kept open and how many requests it can be used for.
+    /// ([MDN](https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Keep-Alive))
+    KeepAlive,
+    /// The date and time the resource was last modified.
+    /// Used with [`HeaderType::IfModifiedSince`] to avoid re-sending unchanged resources.
+    /// ([MDN](https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Last-Modified))
+    LastModified,
     /// Used with redirection status codes (301, 302, 303, 307, 308) to indicate the URL to redirect to.
     /// ([MDN](https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Location))
     Location,
+    /// Indicates the parts of a document that the server should return.
+    /// ([MDN](https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Range))
+    Range,
     /// Contains the address of the webpage that linked to the resource being requested.
     /// Note the misspelling of referrer as 'referer' in the HTTP spec.
     /// ([MDN](https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Referer))
     Referer,
+    /// Indicates how long the client should wait before making another request, in seconds or as a date.
+    /// Often sent with `429 Too Many Requests` or `503 Service Unavailable` responses.
+    /// ([MDN](https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Retry-After))
+    RetryAfter,
     /// An identifier for a specific name / version of the web server software.
     /// This is set to `afire/VERSION` by default.
     /// ([MDN](https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Server))
     Server,
+    /// Durations of the phases of handling a request, for debugging performance in browser developer tools.
+    /// See [`crate::Server::server_timing`].
+    /// ([MDN](https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Server-Timing))
+    ServerTiming,
     /// Used to send cookies from the server to the client.
     /// Its recommended to use the [`crate::SetCookie`] builder instead of this directly.
     /// ([MDN](https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Set-Cookie))
@@ -339,8 +538,7 @@ pub enum HeaderType {
     /// Specifies the transfer encoding of the message body.
     /// ([MDN](https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Transfer-Encoding))
     TransferEncoding,
-    /// Used to switch from HTTP to a different protocol on the same socket, often used for websockets.
-    /// Note that afire *currently* does not have built-in support for websockets.
+    /// Used to switch from HTTP to a different protocol on the same socket, often used for websockets (see [`crate::web_socket`]).
     /// ([MDN](https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Upgrade))
     Upgrade,
     /// Contains information about the client application, operating system, vendor, etc. that is making the request.
@@ -353,10 +551,31 @@ pub enum HeaderType {
     /// This is not an official HTTP header, but is still widely used.
     /// ([MDN](https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/X-Forwarded-For))
     XForwardedFor,
+    /// A header added by some reverse proxies (like nginx) containing the IP of the client.
+    /// This is not an official HTTP header.
+    XRealIp,
     /// Any other header that is not in this enum.
     Custom(String),
 }
 
+impl PartialEq for HeaderType {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (HeaderType::Custom(a), HeaderType::Custom(b)) => a.eq_ignore_ascii_case(b),
+            (a, b) => mem::discriminant(a) == mem::discriminant(b),
+        }
+    }
+}
+
+impl Hash for HeaderType {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        mem::discriminant(self).hash(state);
+        if let HeaderType::Custom(s) = self {
+            s.bytes().for_each(|x| x.to_ascii_lowercase().hash(state));
+        }
+    }
+}
+
 impl From<&HeaderType> for HeaderType {
     fn from(s: &HeaderType) -> Self {
         s.to_owned()
@@ -377,56 +596,193 @@ impl HeaderType {
             "accept-charset"    => HeaderType::AcceptCharset,
             "accept-encoding"   => HeaderType::AcceptEncoding,
             "accept-language"   => HeaderType::AcceptLanguage,
+            "accept-ranges"     => HeaderType::AcceptRanges,
+            "authorization"     => HeaderType::Authorization,
+            "cache-control"     => HeaderType::CacheControl,
             "connection"        => HeaderType::Connection,
+            "content-disposition"=> HeaderType::ContentDisposition,
             "content-encoding"  => HeaderType::ContentEncoding,
             "content-length"    => HeaderType::ContentLength,
+            "content-range"     => HeaderType::ContentRange,
             "content-type"      => HeaderType::ContentType,
             "cookie"            => HeaderType::Cookie,
             "date"              => HeaderType::Date,
+            "etag"              => HeaderType::ETag,
+            "forwarded"         => HeaderType::Forwarded,
             "host"              => HeaderType::Host,
+            "if-modified-since" => HeaderType::IfModifiedSince,
+            "if-none-match"     => HeaderType::IfNoneMatch,
+            "if-range"          => HeaderType::IfRange,
+            "keep-alive"        => HeaderType::KeepAlive,
+            "last-modified"     => HeaderType::LastModified,
             "location"          => HeaderType::Location,
+            "range"             => HeaderType::Range,
             "referer"           => HeaderType::Referer,
+            "retry-after"       => HeaderType::RetryAfter,
             "server"            => HeaderType::Server,
+            "server-timing"     => HeaderType::ServerTiming,
             "set-cookie"        => HeaderType::SetCookie,
             "transfer-encoding" => HeaderType::TransferEncoding,
             "upgrade"           => HeaderType::Upgrade,
             "user-agent"        => HeaderType::UserAgent,
             "via"               => HeaderType::Via,
             "x-forwarded-for"   => HeaderType::XForwardedFor,
+            "x-real-ip"         => HeaderType::XRealIp,
             _                   => HeaderType::Custom(s.to_string()),
         }
     }
+
+    /// Gets the name of the header, as it is sent.
+    #[rustfmt::skip]
+    pub(crate) fn as_str(&self) -> &str {
+        match self {
+            HeaderType::Accept           => "Accept",
+            HeaderType::AcceptCharset    => "Accept-Charset",
+            HeaderType::AcceptEncoding   => "Accept-Encoding",
+            HeaderType::AcceptLanguage   => "Accept-Language",
+            HeaderType::AcceptRanges     => "Accept-Ranges",
+            HeaderType::Authorization    => "Authorization",
+            HeaderType::CacheControl     => "Cache-Control",
+            HeaderType::Connection       => "Connection",
+            HeaderType::ContentDisposition=> "Content-Disposition",
+            HeaderType::ContentEncoding  => "Content-Encoding",
+            HeaderType::ContentLength    => "Content-Length",
+            HeaderType::ContentRange     => "Content-Range",
+            HeaderType::ContentType      => "Content-Type",
+            HeaderType::Cookie           => "Cookie",
+            HeaderType::Date             => "Date",
+            HeaderType::ETag             => "ETag",
+            HeaderType::Forwarded        => "Forwarded",
+            HeaderType::Host             => "Host",
+            HeaderType::IfModifiedSince  => "If-Modified-Since",
+            HeaderType::IfNoneMatch      => "If-None-Match",
+            HeaderType::IfRange          => "If-Range",
+            HeaderType::KeepAlive        => "Keep-Alive",
+            HeaderType::LastModified     => "Last-Modified",
+            HeaderType::Location         => "Location",
+            HeaderType::Range            => "Range",
+            HeaderType::Referer          => "Referer",
+            HeaderType::RetryAfter       => "Retry-After",
+            HeaderType::Server           => "Server",
+            HeaderType::ServerTiming     => "Server-Timing",
+            HeaderType::SetCookie        => "Set-Cookie",
+            HeaderType::TransferEncoding => "Transfer-Encoding",
+            HeaderType::Upgrade          => "Upgrade",
+            HeaderType::UserAgent        => "User-Agent",
+            HeaderType::Via              => "Via",
+            HeaderType::XForwardedFor    => "X-Forwarded-For",
+            HeaderType::XRealIp          => "X-Real-IP",
+            HeaderType::Custom(s)        => s,
+        }
+    }
 }
 
 impl Display for HeaderType {
-    #[rustfmt::skip]
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "{}",
-            match self {
-                HeaderType::Accept           => "Accept",
-                HeaderType::AcceptCharset    => "Accept-Charset",
-                HeaderType::AcceptEncoding   => "Accept-Encoding",
-                HeaderType::AcceptLanguage   => "Accept-Language",
-                HeaderType::Connection       => "Connection",
-                HeaderType::ContentEncoding  => "Content-Encoding",
-                HeaderType::ContentLength    => "Content-Length",
-                HeaderType::ContentType      => "Content-Type",
-                HeaderType::Cookie           => "Cookie",
-                HeaderType::Date             => "Date",
-                HeaderType::Host             => "Host",
-                HeaderType::Location         => "Location",
-                HeaderType::Referer          => "Referer",
-                HeaderType::Server           => "Server",
-                HeaderType::SetCookie        => "Set-Cookie",
-                HeaderType::TransferEncoding => "Transfer-Encoding",
-                HeaderType::Upgrade          => "Upgrade",
-                HeaderType::UserAgent        => "User-Agent",
-                HeaderType::Via              => "Via",
-                HeaderType::XForwardedFor    => "X-Forwarded-For",
-                HeaderType::Custom(s)        => s,
-            }
-        )
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::Cursor,
+        sync::{Arc, Mutex},
+    };
+
+    use super::{HeaderPolicy, HeaderType, Headers};
+    use crate::{
+        internal::testing::{MemoryListener, MemoryStream},
+        Method, Response, Server,
+    };
+
+    #[test]
+    fn test_header_type_case_insensitive() {
+        assert_eq!(HeaderType::from("content-TYPE"), HeaderType::ContentType);
+        assert_eq!(HeaderType::from("X-Test"), HeaderType::from("x-test"));
+        assert_ne!(HeaderType::from("X-Test"), HeaderType::from("X-Tests"));
+        assert_eq!(HeaderType::from("X-Test").to_string(), "X-Test");
+    }
+
+    #[test]
+    fn test_headers_insert() {
+        let mut headers = Headers::default();
+        headers.add("Vary", "Accept");
+        headers.add("X-Test", "1");
+        headers.add("vary", "Cookie");
+        assert_eq!(
+            headers.get_all("VARY").collect::<Vec<_>>(),
+            ["Accept", "Cookie"]
+        );
+
+        headers.insert("Vary", "*");
+        assert_eq!(headers.get_all("Vary").collect::<Vec<_>>(), ["*"]);
+        assert_eq!(headers[0].value, "*");
+        assert_eq!(headers.len(), 2);
+    }
+    #[test]
+    fn test_headers_dedup() {
+        let mut headers = Headers::default();
+        headers.add("Content-Type", "text/plain");
+        headers.add("Set-Cookie", "a=1");
+        headers.add("X-Test", "1");
+        headers.add("content-type", "application/json");
+        headers.add("Set-Cookie", "b=2");
+        headers.add("X-Test", "2");
+        headers.dedup();
+
+        let headers = headers
+            .iter()
+            .map(|i| format!("{}: {}", i.name, i.value))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            headers,
+            [
+                "Set-Cookie: a=1",
+                "X-Test: 1",
+                "Content-Type: application/json",
+                "Set-Cookie: b=2",
+                "X-Test: 2"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_header_policy() {
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let input = ["/", "/append"]
+            .map(|path| format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n"))
+            .concat();
+        let listener = MemoryListener(Mutex::new(vec![MemoryStream {
+            input: Cursor::new(input.into_bytes()),
+            output: output.clone(),
+        }]));
+
+        let mut server =
+            Server::<()>::new("localhost", 0).default_header("X-Frame-Options", "DENY");
+        server.route(Method::GET, "/", |_| {
+            Response::new()
+                .text("{}")
+                .header(HeaderType::ContentType, "application/json")
+                .header("X-Frame-Options", "SAMEORIGIN")
+                .header(HeaderType::SetCookie, "a=1")
+                .header(HeaderType::SetCookie, "b=2")
+        });
+        server.route(Method::GET, "/append", |_| {
+            Response::new()
+                .header(HeaderType::Location, "/a")
+                .header(HeaderType::Location, "/b")
+                .header_policy(HeaderPolicy::Append)
+        });
+        assert!(server.start_with_listener(listener).is_err());
+
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        let (first, second) = output.split_at(output.rfind("HTTP/1.1 ").unwrap());
+        assert_eq!(first.matches("Content-Type: ").count(), 1);
+        assert!(first.contains("\r\nContent-Type: application/json\r\n"));
+        assert_eq!(first.matches("X-Frame-Options: ").count(), 1);
+        assert!(first.contains("\r\nX-Frame-Options: SAMEORIGIN\r\n"));
+        assert_eq!(first.matches("Set-Cookie: ").count(), 2);
+        assert!(second.contains("\r\nLocation: /a\r\nLocation: /b\r\n"));
     }
 }
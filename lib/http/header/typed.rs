@@ -0,0 +1,879 @@
+//! Parsers and builders for common headers.
+//!
+//! Each type implements [`TypedHeader`], so it can be read from or written to a [`Headers`] collection with [`Headers::get_typed`] and [`Headers::insert_typed`].
+//! Headers are converted back into their wire format with [`Display`].
+//!
+//! | Type               | Header            |
+//! | ------------------ | ----------------- |
+//! | [`Accept`]         | `Accept`          |
+//! | [`AcceptLanguage`] | `Accept-Language` |
+//! | [`Authorization`]  | `Authorization`   |
+//! | [`CacheControl`]   | `Cache-Control`   |
+//! | [`ContentType`]    | `Content-Type`    |
+//! | [`Forwarded`]      | `Forwarded`       |
+//! | [`Range`]          | `Range`           |
+//!
+//! ## Example
+//! ```rust
+//! # use afire::{Method, Server, Response, header::typed::{Authorization, CacheControl}};
+//! # fn test(server: &mut Server) {
+//! server.route(Method::GET, "/", |req| {
+//!     let user = match req.headers.get_typed::<Authorization>() {
+//!         Some(Authorization::Basic { username, .. }) => username,
+//!         _ => return Response::new().status(401),
+//!     };
+//!
+//!     let mut res = Response::new().text(format!("Hello {user}"));
+//!     res.headers.insert_typed(CacheControl {
+//!         private: true,
+//!         max_age: Some(60),
+//!         ..Default::default()
+//!     });
+//!     res
+//! });
+//! # }
+//! ```
+
+use std::{
+    borrow::Cow,
+    fmt::{self, Display},
+};
+
+use super::{HeaderParams, HeaderType, Headers};
+use crate::internal::encoding::base64;
+
+/// A header that can be parsed from and converted back into a header value.
+pub trait TypedHeader: Sized + Display {
+    /// The name of the header.
+    fn name() -> HeaderType;
+
+    /// Parses a header value, returning `None` if it is invalid.
+    fn parse(value: &str) -> Option<Self>;
+}
+
+impl Headers {
+    /// Gets and parses the specified typed header.
+    /// If the header is present more than once, the values are joined with commas before parsing, as is allowed for list based headers like `Accept`.
+    /// Returns `None` if the header is not present or is invalid.
+    /// ## Example
+    /// ```rust
+    /// # use afire::header::{Headers, typed::Accept};
+    /// let mut headers = Headers::default();
+    /// headers.add("Accept", "text/html, application/json;q=0.5");
+    ///
+    /// let accept = headers.get_typed::<Accept>().unwrap();
+    /// assert_eq!(accept.quality("application/json"), 0.5);
+    /// ```
+    pub fn get_typed<T: TypedHeader>(&self) -> Option<T> {
+        let mut values = self.get_all(T::name()).peekable();
+        values.peek()?;
+        T::parse(&values.collect::<Vec<_>>().join(", "))
+    }
+
+    /// Adds a typed header to the collection, replacing any existing headers with the same name.
+    /// ## Example
+    /// ```rust
+    /// # use afire::header::{Headers, HeaderType, typed::ContentType};
+    /// let mut headers = Headers::default();
+    /// headers.insert_typed(ContentType::new("text/html").charset("utf-8"));
+    ///
+    /// assert_eq!(headers.get(HeaderType::ContentType), Some("text/html; charset=utf-8"));
+    /// ```
+    pub fn insert_typed<T: TypedHeader>(&mut self, header: T) {
+        self.insert(T::name(), header.to_string());
+    }
+}
+
+/// The `Accept` header, a list of media types the client can understand along with their quality values.
+/// Items are sorted by quality, highest first.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Accept(pub Vec<QualityItem>);
+
+/// A value with a [quality value](https://developer.mozilla.org/en-US/docs/Glossary/Quality_values), like `text/html;q=0.8`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QualityItem {
+    /// The value, like `text/html` or `*/*`.
+    pub value: String,
+    /// The quality, from 0 to 1.
+    /// Defaults to 1 if not specified.
+    pub quality: f32,
+}
+
+impl Accept {
+    /// Gets the quality the client assigned to a media type, taking wildcards (`text/*` and `*/*`) into account.
+    /// The most specific matching item is used, and 0 is returned if nothing matches.
+    /// ## Example
+    /// ```rust
+    /// # use afire::header::typed::{Accept, TypedHeader};
+    /// let accept = Accept::parse("text/*;q=0.5, text/html, */*;q=0.1").unwrap();
+    /// assert_eq!(accept.quality("text/html"), 1.0);
+    /// assert_eq!(accept.quality("text/plain"), 0.5);
+    /// assert_eq!(accept.quality("image/png"), 0.1);
+    /// ```
+    pub fn quality(&self, mime: &str) -> f32 {
+        let (kind, _) = mime.split_once('/').unwrap_or((mime, ""));
+        self.0
+            .iter()
+            .filter_map(|i| {
+                let specificity = match i.value.split_once('/') {
+                    _ if i.value.eq_ignore_ascii_case(mime) => 2,
+                    Some((i_kind, "*")) if i_kind.eq_ignore_ascii_case(kind) => 1,
+                    Some(("*", "*")) => 0,
+                    _ => return None,
+                };
+                Some((specificity, i.quality))
+            })
+            .max_by_key(|(specificity, _)| *specificity)
+            .map(|(_, quality)| quality)
+            .unwrap_or(0.0)
+    }
+}
+
+impl TypedHeader for Accept {
+    fn name() -> HeaderType {
+        HeaderType::Accept
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        parse_quality_list(value).map(Self)
+    }
+}
+
+impl Display for Accept {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_quality_list(f, &self.0)
+    }
+}
+
+/// The `Accept-Language` header, a list of [language ranges](https://www.rfc-editor.org/rfc/rfc4647#section-2.1) the client prefers along with their quality values.
+/// Items are sorted by quality, highest first.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AcceptLanguage(pub Vec<QualityItem>);
+
+impl AcceptLanguage {
+    /// Gets the quality the client assigned to a language tag.
+    /// Ranges match tags that start with them, so `en` matches `en-US`, and `*` matches everything.
+    /// If no range matches that way, a tag also matches the ranges that start with it, so `en` is still picked for a client that only asked for `en-GB`.
+    /// The most specific matching item is used, and 0 is returned if nothing matches.
+    /// ## Example
+    /// ```rust
+    /// # use afire::header::typed::{AcceptLanguage, TypedHeader};
+    /// let accept = AcceptLanguage::parse("en-US, en;q=0.8, fr-CA;q=0.5, *;q=0.1").unwrap();
+    /// assert_eq!(accept.quality("en-US"), 1.0);
+    /// assert_eq!(accept.quality("en-GB"), 0.8);
+    /// assert_eq!(accept.quality("fr"), 0.5);
+    /// assert_eq!(accept.quality("de"), 0.1);
+    /// ```
+    pub fn quality(&self, tag: &str) -> f32 {
+        // Checks if `range` is `tag` or a prefix of it that ends on a subtag
+        let prefix = |range: &str, tag: &str| {
+            tag.len() > range.len()
+                && tag.as_bytes()[range.len()] == b'-'
+                && tag[..range.len()].eq_ignore_ascii_case(range)
+        };
+
+        self.0
+            .iter()
+            .filter_map(|i| {
+                let specificity = match i.value.as_str() {
+                    range if range.eq_ignore_ascii_case(tag) => 3,
+                    range if prefix(range, tag) => 2,
+                    range if prefix(tag, range) => 1,
+                    "*" => 0,
+                    _ => return None,
+                };
+                Some((specificity, i.quality))
+            })
+            .max_by(|a, b| a.0.cmp(&b.0).then(a.1.total_cmp(&b.1)))
+            .map(|(_, quality)| quality)
+            .unwrap_or(0.0)
+    }
+}
+
+impl TypedHeader for AcceptLanguage {
+    fn name() -> HeaderType {
+        HeaderType::AcceptLanguage
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        parse_quality_list(value).map(Self)
+    }
+}
+
+impl Display for AcceptLanguage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_quality_list(f, &self.0)
+    }
+}
+
+/// Parses a comma separated list of values with quality values, sorted by quality with the highest first.
+fn parse_quality_list(value: &str) -> Option<Vec<QualityItem>> {
+    let mut items = value
+        .split(',')
+        .filter(|i| !i.trim().is_empty())
+        .map(|i| {
+            let params = HeaderParams::new(i);
+            let quality = match params.iter().find(|[k, _]| k.eq_ignore_ascii_case("q")) {
+                Some([_, q]) => q.parse().ok().filter(|q| (0.0..=1.0).contains(q))?,
+                None => 1.0,
+            };
+
+            Some(QualityItem {
+                value: params.value.trim().to_owned(),
+                quality,
+            })
+        })
+        .collect::<Option<Vec<_>>>()?;
+
+    items.sort_by(|a, b| b.quality.total_cmp(&a.quality));
+    Some(items)
+}
+
+/// Writes a list of values with quality values, leaving out qualities of 1.
+fn write_quality_list(f: &mut fmt::Formatter<'_>, items: &[QualityItem]) -> fmt::Result {
+    for (i, item) in items.iter().enumerate() {
+        if i != 0 {
+            f.write_str(", ")?;
+        }
+
+        f.write_str(&item.value)?;
+        if item.quality != 1.0 {
+            write!(f, ";q={}", item.quality)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The `Content-Type` header, a media type with optional `charset` and `boundary` parameters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContentType {
+    /// The media type, like `text/html`.
+    pub mime: String,
+    /// The character encoding, like `utf-8`.
+    pub charset: Option<String>,
+    /// The boundary used to separate the parts of a multipart body.
+    pub boundary: Option<String>,
+}
+
+impl ContentType {
+    /// Creates a new Content-Type from a media type, with no parameters.
+    pub fn new(mime: impl AsRef<str>) -> Self {
+        Self {
+            mime: mime.as_ref().to_owned(),
+            charset: None,
+            boundary: None,
+        }
+    }
+
+    /// Sets the `charset` parameter.
+    pub fn charset(self, charset: impl AsRef<str>) -> Self {
+        Self {
+            charset: Some(charset.as_ref().to_owned()),
+            ..self
+        }
+    }
+
+    /// Sets the `boundary` parameter.
+    pub fn boundary(self, boundary: impl AsRef<str>) -> Self {
+        Self {
+            boundary: Some(boundary.as_ref().to_owned()),
+            ..self
+        }
+    }
+}
+
+impl TypedHeader for ContentType {
+    fn name() -> HeaderType {
+        HeaderType::ContentType
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        let params = HeaderParams::new(value);
+        let mime = params.value.trim();
+        if !mime.contains('/') {
+            return None;
+        }
+
+        let param = |name: &str| {
+            params
+                .iter()
+                .find(|[k, _]| k.eq_ignore_ascii_case(name))
+                .map(|[_, v]| unquote(v).to_owned())
+        };
+
+        Some(Self {
+            mime: mime.to_owned(),
+            charset: param("charset"),
+            boundary: param("boundary"),
+        })
+    }
+}
+
+impl Display for ContentType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.mime)?;
+        if let Some(charset) = &self.charset {
+            write!(f, "; charset={}", quote(charset))?;
+        }
+        if let Some(boundary) = &self.boundary {
+            write!(f, "; boundary={}", quote(boundary))?;
+        }
+        Ok(())
+    }
+}
+
+/// The `Authorization` header, credentials sent by the client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Authorization {
+    /// [Basic](https://developer.mozilla.org/en-US/docs/Web/HTTP/Authentication#basic_authentication_scheme) authentication, a base64 encoded username and password.
+    Basic {
+        /// The username.
+        username: String,
+        /// The password.
+        password: String,
+    },
+    /// Bearer authentication, an opaque token.
+    Bearer(String),
+    /// Any other authentication scheme.
+    Other {
+        /// The name of the scheme.
+        scheme: String,
+        /// The credentials, as they were sent.
+        credentials: String,
+    },
+}
+
+impl TypedHeader for Authorization {
+    fn name() -> HeaderType {
+        HeaderType::Authorization
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        let (scheme, credentials) = value.trim().split_once(' ')?;
+        let credentials = credentials.trim();
+
+        Some(if scheme.eq_ignore_ascii_case("basic") {
+            let decoded = String::from_utf8(base64::decode(credentials)?).ok()?;
+            let (username, password) = decoded.split_once(':')?;
+            Self::Basic {
+                username: username.to_owned(),
+                password: password.to_owned(),
+            }
+        } else if scheme.eq_ignore_ascii_case("bearer") {
+            Self::Bearer(credentials.to_owned())
+        } else {
+            Self::Other {
+                scheme: scheme.to_owned(),
+                credentials: credentials.to_owned(),
+            }
+        })
+    }
+}
+
+impl Display for Authorization {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Basic { username, password } => write!(
+                f,
+                "Basic {}",
+                base64::encode(format!("{username}:{password}").as_bytes())
+            ),
+            Self::Bearer(token) => write!(f, "Bearer {token}"),
+            Self::Other {
+                scheme,
+                credentials,
+            } => write!(f, "{scheme} {credentials}"),
+        }
+    }
+}
+
+/// The `Range` header, the parts of a resource the client wants.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Range {
+    /// The unit of the ranges, almost always `bytes`.
+    pub unit: String,
+    /// The requested ranges.
+    pub ranges: Vec<ByteRange>,
+}
+
+/// A single range in a [`Range`] header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteRange {
+    /// From the first position to the second position, inclusive (`0-499`).
+    Bounded(u64, u64),
+    /// From a position to the end (`500-`).
+    From(u64),
+    /// The last n bytes (`-500`).
+    Suffix(u64),
+}
+
+impl ByteRange {
+    /// Resolves the range against a resource of the specified length, returning the inclusive start and end positions.
+    /// Returns `None` if the range can not be satisfied.
+    /// ## Example
+    /// ```rust
+    /// # use afire::header::typed::ByteRange;
+    /// assert_eq!(ByteRange::Bounded(0, 499).resolve(100), Some((0, 99)));
+    /// assert_eq!(ByteRange::Suffix(10).resolve(100), Some((90, 99)));
+    /// assert_eq!(ByteRange::From(100).resolve(100), None);
+    /// ```
+    pub fn resolve(&self, len: u64) -> Option<(u64, u64)> {
+        let (start, end) = match *self {
+            Self::Bounded(start, end) => (start, end.min(len.checked_sub(1)?)),
+            Self::From(start) => (start, len.checked_sub(1)?),
+            Self::Suffix(0) => return None,
+            Self::Suffix(n) => (len.saturating_sub(n), len.checked_sub(1)?),
+        };
+
+        (start <= end).then_some((start, end))
+    }
+}
+
+impl TypedHeader for Range {
+    fn name() -> HeaderType {
+        HeaderType::Range
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        let (unit, ranges) = value.trim().split_once('=')?;
+        let ranges = ranges
+            .split(',')
+            .map(|i| {
+                let (start, end) = i.trim().split_once('-')?;
+                Some(match (start, end) {
+                    ("", end) => ByteRange::Suffix(end.parse().ok()?),
+                    (start, "") => ByteRange::From(start.parse().ok()?),
+                    (start, end) => {
+                        let (start, end) = (start.parse().ok()?, end.parse().ok()?);
+                        if start > end {
+                            return None;
+                        }
+                        ByteRange::Bounded(start, end)
+                    }
+                })
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        Some(Self {
+            unit: unit.trim().to_owned(),
+            ranges,
+        })
+    }
+}
+
+impl Display for Range {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}=", self.unit)?;
+        for (i, range) in self.ranges.iter().enumerate() {
+            if i != 0 {
+                f.write_str(", ")?;
+            }
+
+            match range {
+                ByteRange::Bounded(start, end) => write!(f, "{start}-{end}")?,
+                ByteRange::From(start) => write!(f, "{start}-")?,
+                ByteRange::Suffix(n) => write!(f, "-{n}")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The `Cache-Control` header.
+/// Only the common directives are given their own fields, any others are kept in `other`.
+/// It can be built with the builder methods, and added to a response with [`crate::Response::cache`].
+/// ## Example
+/// ```rust
+/// # use afire::header::typed::CacheControl;
+/// let cache = CacheControl::new()
+///     .public()
+///     .max_age(3600)
+///     .stale_while_revalidate(60);
+/// assert_eq!(cache.to_string(), "public, max-age=3600, stale-while-revalidate=60");
+///
+/// // Or with the fields directly
+/// let cache = CacheControl {
+///     public: true,
+///     max_age: Some(3600),
+///     ..Default::default()
+/// };
+/// assert_eq!(cache.to_string(), "public, max-age=3600");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct CacheControl {
+    /// The response can be stored by any cache (`public`).
+    pub public: bool,
+    /// The response can only be stored by the client's cache (`private`).
+    pub private: bool,
+    /// The response must be validated with the server before being used (`no-cache`).
+    pub no_cache: bool,
+    /// The response must not be stored (`no-store`).
+    pub no_store: bool,
+    /// Stale responses must be validated before being used (`must-revalidate`).
+    pub must_revalidate: bool,
+    /// The response will not change while it is fresh (`immutable`).
+    pub immutable: bool,
+    /// How long the response stays fresh, in seconds (`max-age`).
+    pub max_age: Option<u64>,
+    /// How long the response stays fresh in shared caches, in seconds (`s-maxage`).
+    pub s_maxage: Option<u64>,
+    /// How long a stale response can be used while it is revalidated in the background, in seconds (`stale-while-revalidate`).
+    pub stale_while_revalidate: Option<u64>,
+    /// Any other directives, as they were sent.
+    pub other: Vec<String>,
+}
+
+impl CacheControl {
+    /// Creates a new Cache-Control header with no directives.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Lets any cache store the response (`public`).
+    pub fn public(self) -> Self {
+        Self {
+            public: true,
+            ..self
+        }
+    }
+
+    /// Only lets the client's cache store the response (`private`).
+    pub fn private(self) -> Self {
+        Self {
+            private: true,
+            ..self
+        }
+    }
+
+    /// Makes caches validate the response with the server before using it (`no-cache`).
+    pub fn no_cache(self) -> Self {
+        Self {
+            no_cache: true,
+            ..self
+        }
+    }
+
+    /// Stops caches from storing the response (`no-store`).
+    pub fn no_store(self) -> Self {
+        Self {
+            no_store: true,
+            ..self
+        }
+    }
+
+    /// Makes caches validate the response once it is stale (`must-revalidate`).
+    pub fn must_revalidate(self) -> Self {
+        Self {
+            must_revalidate: true,
+            ..self
+        }
+    }
+
+    /// Tells clients the response won't change while it is fresh, so they don't revalidate it on reload (`immutable`).
+    /// This is useful for assets with a hash in their name.
+    pub fn immutable(self) -> Self {
+        Self {
+            immutable: true,
+            ..self
+        }
+    }
+
+    /// Sets how long the response stays fresh, in seconds (`max-age`).
+    pub fn max_age(self, seconds: u64) -> Self {
+        Self {
+            max_age: Some(seconds),
+            ..self
+        }
+    }
+
+    /// Sets how long the response stays fresh in shared caches like CDNs, in seconds (`s-maxage`).
+    pub fn s_maxage(self, seconds: u64) -> Self {
+        Self {
+            s_maxage: Some(seconds),
+            ..self
+        }
+    }
+
+    /// Sets how long a stale response can be used while it is revalidated in the background, in seconds (`stale-while-revalidate`).
+    pub fn stale_while_revalidate(self, seconds: u64) -> Self {
+        Self {
+            stale_while_revalidate: Some(seconds),
+            ..self
+        }
+    }
+}
+
+impl TypedHeader for CacheControl {
+    fn name() -> HeaderType {
+        HeaderType::CacheControl
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        let mut out = Self::default();
+        for i in value.split(',').map(str::trim).filter(|i| !i.is_empty()) {
+            let (key, value) = match i.split_once('=') {
+                Some((key, value)) => (key.trim(), Some(unquote(value))),
+                None => (i, None),
+            };
+
+            match (key.to_ascii_lowercase().as_str(), value) {
+                ("public", None) => out.public = true,
+                ("private", None) => out.private = true,
+                ("no-cache", None) => out.no_cache = true,
+                ("no-store", None) => out.no_store = true,
+                ("must-revalidate", None) => out.must_revalidate = true,
+                ("immutable", None) => out.immutable = true,
+                ("max-age", Some(i)) => out.max_age = Some(i.parse().ok()?),
+                ("s-maxage", Some(i)) => out.s_maxage = Some(i.parse().ok()?),
+                ("stale-while-revalidate", Some(i)) => {
+                    out.stale_while_revalidate = Some(i.parse().ok()?)
+                }
+                _ => out.other.push(i.to_owned()),
+            }
+        }
+
+        Some(out)
+    }
+}
+
+impl Display for CacheControl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let flags = [
+            (self.public, "public"),
+            (self.private, "private"),
+            (self.no_cache, "no-cache"),
+            (self.no_store, "no-store"),
+            (self.must_revalidate, "must-revalidate"),
+            (self.immutable, "immutable"),
+        ];
+
+        let directives = flags
+            .iter()
+            .filter(|(set, _)| *set)
+            .map(|(_, name)| Cow::Borrowed(*name))
+            .chain(self.max_age.map(|i| format!("max-age={i}").into()))
+            .chain(self.s_maxage.map(|i| format!("s-maxage={i}").into()))
+            .chain(
+                self.stale_while_revalidate
+                    .map(|i| format!("stale-while-revalidate={i}").into()),
+            )
+            .chain(self.other.iter().map(|i| Cow::Borrowed(i.as_str())))
+            .collect::<Vec<_>>();
+
+        f.write_str(&directives.join(", "))
+    }
+}
+
+/// The `Forwarded` header ([RFC 7239](https://www.rfc-editor.org/rfc/rfc7239)), added by proxies to describe the hops a request took.
+/// Elements are in the order the proxies added them, so the first is the closest to the client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Forwarded(pub Vec<ForwardedElement>);
+
+/// A single hop in a [`Forwarded`] header.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ForwardedElement {
+    /// The interface where the request came in to the proxy (`by`).
+    pub by: Option<String>,
+    /// The client that made the request to the proxy (`for`).
+    pub forwarded_for: Option<String>,
+    /// The `Host` header the proxy received (`host`).
+    pub host: Option<String>,
+    /// The protocol used to make the request to the proxy, like `https` (`proto`).
+    pub proto: Option<String>,
+}
+
+impl TypedHeader for Forwarded {
+    fn name() -> HeaderType {
+        HeaderType::Forwarded
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        let elements = value
+            .split(',')
+            .map(|element| {
+                let mut out = ForwardedElement::default();
+                for (key, value) in element.split(';').filter_map(|i| i.split_once('=')) {
+                    let value = Some(unquote(value).to_owned());
+                    match key.trim().to_ascii_lowercase().as_str() {
+                        "by" => out.by = value,
+                        "for" => out.forwarded_for = value,
+                        "host" => out.host = value,
+                        "proto" => out.proto = value,
+                        _ => {}
+                    }
+                }
+                out
+            })
+            .collect();
+
+        Some(Self(elements))
+    }
+}
+
+impl Display for Forwarded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, element) in self.0.iter().enumerate() {
+            if i != 0 {
+                f.write_str(", ")?;
+            }
+
+            let pairs = [
+                ("by", &element.by),
+                ("for", &element.forwarded_for),
+                ("host", &element.host),
+                ("proto", &element.proto),
+            ];
+            let pairs = pairs
+                .iter()
+                .filter_map(|(key, value)| Some(format!("{key}={}", quote(value.as_ref()?))))
+                .collect::<Vec<_>>();
+            f.write_str(&pairs.join(";"))?;
+        }
+        Ok(())
+    }
+}
+
+/// Removes the quotes around a quoted string, if there are any.
+fn unquote(value: &str) -> &str {
+    let value = value.trim();
+    value
+        .strip_prefix('"')
+        .and_then(|i| i.strip_suffix('"'))
+        .unwrap_or(value)
+}
+
+/// Quotes a parameter value if it contains characters that are not allowed in a token.
+fn quote(value: &str) -> Cow<'_, str> {
+    let token = !value.is_empty()
+        && value
+            .bytes()
+            .all(|i| i.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&i));
+
+    match token {
+        true => Cow::Borrowed(value),
+        false => Cow::Owned(format!(
+            "\"{}\"",
+            value.replace('\\', "\\\\").replace('"', "\\\"")
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        Accept, AcceptLanguage, Authorization, ByteRange, CacheControl, ContentType, Forwarded,
+        Range, TypedHeader,
+    };
+
+    #[test]
+    fn test_accept() {
+        let accept = Accept::parse("text/html;q=0.5, application/json, */*;q=0").unwrap();
+        assert_eq!(accept.0[0].value, "application/json");
+        assert_eq!(accept.quality("text/html"), 0.5);
+        assert_eq!(accept.quality("image/png"), 0.0);
+        assert_eq!(
+            accept.to_string(),
+            "application/json, text/html;q=0.5, */*;q=0"
+        );
+        assert!(Accept::parse("text/html;q=2").is_none());
+    }
+
+    #[test]
+    fn test_accept_language() {
+        let accept = AcceptLanguage::parse("de;q=0.7, en-GB, en;q=0.8").unwrap();
+        assert_eq!(accept.0[0].value, "en-GB");
+        assert_eq!(accept.quality("EN-gb"), 1.0);
+        assert_eq!(accept.quality("en-US"), 0.8);
+        assert_eq!(accept.quality("de-AT"), 0.7);
+        assert_eq!(accept.quality("english"), 0.0);
+        assert_eq!(accept.quality("fr"), 0.0);
+        assert_eq!(accept.to_string(), "en-GB, en;q=0.8, de;q=0.7");
+
+        // Tags match more specific ranges if nothing else does
+        let accept = AcceptLanguage::parse("en-GB, en-US;q=0.5").unwrap();
+        assert_eq!(accept.quality("en"), 1.0);
+        assert_eq!(AcceptLanguage::parse("*;q=0.1").unwrap().quality("fr"), 0.1);
+    }
+
+    #[test]
+    fn test_content_type() {
+        let content = ContentType::parse("multipart/form-data; Boundary=\"a b\"").unwrap();
+        assert_eq!(content.mime, "multipart/form-data");
+        assert_eq!(content.boundary.as_deref(), Some("a b"));
+        assert_eq!(content.charset, None);
+        assert_eq!(content.to_string(), "multipart/form-data; boundary=\"a b\"");
+    }
+
+    #[test]
+    fn test_authorization() {
+        let auth = Authorization::parse("Basic dXNlcjpwYXNzOndvcmQ=").unwrap();
+        assert_eq!(
+            auth,
+            Authorization::Basic {
+                username: "user".to_owned(),
+                password: "pass:word".to_owned()
+            }
+        );
+        assert_eq!(auth.to_string(), "Basic dXNlcjpwYXNzOndvcmQ=");
+        assert_eq!(
+            Authorization::parse("bearer abc").unwrap(),
+            Authorization::Bearer("abc".to_owned())
+        );
+        assert!(Authorization::parse("Basic !!!").is_none());
+    }
+
+    #[test]
+    fn test_range() {
+        let range = Range::parse("bytes=0-499, 500-, -100").unwrap();
+        assert_eq!(range.unit, "bytes");
+        assert_eq!(
+            range.ranges,
+            [
+                ByteRange::Bounded(0, 499),
+                ByteRange::From(500),
+                ByteRange::Suffix(100)
+            ]
+        );
+        assert_eq!(range.to_string(), "bytes=0-499, 500-, -100");
+        assert!(Range::parse("bytes=10-5").is_none());
+        assert_eq!(ByteRange::Suffix(500).resolve(100), Some((0, 99)));
+    }
+
+    #[test]
+    fn test_cache_control() {
+        let cache = CacheControl::parse("no-cache, Max-Age=60, stale-if-error=10").unwrap();
+        assert!(cache.no_cache);
+        assert_eq!(cache.max_age, Some(60));
+        assert_eq!(cache.other, ["stale-if-error=10"]);
+        assert_eq!(cache.to_string(), "no-cache, max-age=60, stale-if-error=10");
+
+        let cache = CacheControl::new()
+            .private()
+            .no_store()
+            .immutable()
+            .s_maxage(10)
+            .stale_while_revalidate(5);
+        assert_eq!(
+            cache.to_string(),
+            "private, no-store, immutable, s-maxage=10, stale-while-revalidate=5"
+        );
+        assert_eq!(CacheControl::parse(&cache.to_string()), Some(cache));
+    }
+
+    #[test]
+    fn test_forwarded() {
+        let forwarded =
+            Forwarded::parse("for=192.0.2.43;proto=https, for=\"[2001:db8::1]\"").unwrap();
+        assert_eq!(forwarded.0[0].forwarded_for.as_deref(), Some("192.0.2.43"));
+        assert_eq!(forwarded.0[0].proto.as_deref(), Some("https"));
+        assert_eq!(
+            forwarded.0[1].forwarded_for.as_deref(),
+            Some("[2001:db8::1]")
+        );
+        assert_eq!(
+            forwarded.to_string(),
+            "for=192.0.2.43;proto=https, for=\"[2001:db8::1]\""
+        );
+    }
+}
@@ -3,8 +3,9 @@ use std::{fmt, str::FromStr};
 /// HTTP Methods.
 ///
 /// Also contains a special method (ANY) for routes that run on all methods, which will never be the method of a request.
+/// Methods outside of the standard set, like the WebDAV `PROPFIND` and `MKCOL`, are represented with [`Method::Custom`].
 /// From <https://developer.mozilla.org/en-US/docs/Web/HTTP/Methods>.
-#[derive(Debug, Hash, PartialEq, Eq, Clone, Copy)]
+#[derive(Debug, Hash, PartialEq, Eq, Clone)]
 pub enum Method {
     /// HTTP GET Method.
     /// [MDN](https://developer.mozilla.org/en-US/docs/Web/HTTP/Methods/GET)
@@ -54,6 +55,19 @@ pub enum Method {
     /// Used for tracing the route of a request
     TRACE,
 
+    /// Any other method, like the WebDAV `PROPFIND` method.
+    /// Unlike the standard methods, custom methods are case-sensitive.
+    ///
+    /// ## Example
+    /// ```rust
+    /// # use afire::{Method, Response, Server};
+    /// # let mut server = Server::<()>::new("localhost", 8080);
+    /// server.route(Method::Custom("PROPFIND".to_owned()), "/", |_req| {
+    ///     Response::new().text("Hello WebDAV")
+    /// });
+    /// ```
+    Custom(String),
+
     /// For routes that run on all methods
     ///
     /// Will not be use in a request
@@ -64,7 +78,8 @@ impl FromStr for Method {
     type Err = ();
 
     /// Convert a string to a method.
-    /// If the string is not a valid method or is ANY, an error will be returned.
+    /// Standard methods are matched case-insensitively, any other [token](https://www.rfc-editor.org/rfc/rfc9110#section-5.6.2) becomes a [`Method::Custom`].
+    /// If the string is not a valid token or is ANY, an error will be returned.
     /// ## Examples
     /// ```rust
     /// # use std::str::FromStr;
@@ -77,8 +92,9 @@ impl FromStr for Method {
     /// assert!(Method::from_str("HEAD").unwrap() == Method::HEAD);
     /// assert!(Method::from_str("PATCH").unwrap() == Method::PATCH);
     /// assert!(Method::from_str("TRACE").unwrap() == Method::TRACE);
+    /// assert!(Method::from_str("PROPFIND").unwrap() == Method::Custom("PROPFIND".to_owned()));
     /// assert!(Method::from_str("ANY") == Err(()));
-    /// assert!(Method::from_str("foo") == Err(()));
+    /// assert!(Method::from_str("fo o") == Err(()));
     /// ```
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         Ok(match s.to_uppercase().as_str() {
@@ -90,6 +106,8 @@ impl FromStr for Method {
             "HEAD" => Method::HEAD,
             "PATCH" => Method::PATCH,
             "TRACE" => Method::TRACE,
+            "ANY" => return Err(()),
+            _ if is_token(s) => Method::Custom(s.to_owned()),
             _ => return Err(()),
         })
     }
@@ -103,7 +121,7 @@ impl fmt::Display for Method {
     /// assert_eq!("GET", Method::GET.to_string());
     /// ```
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.write_str(match *self {
+        f.write_str(match self {
             Method::GET => "GET",
             Method::POST => "POST",
             Method::PUT => "PUT",
@@ -112,7 +130,15 @@ impl fmt::Display for Method {
             Method::HEAD => "HEAD",
             Method::PATCH => "PATCH",
             Method::TRACE => "TRACE",
+            Method::Custom(i) => i,
             Method::ANY => "ANY",
         })
     }
 }
+
+/// Checks if a string is a valid HTTP token, which methods must be.
+fn is_token(s: &str) -> bool {
+    !s.is_empty()
+        && s.bytes()
+            .all(|i| i.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&i))
+}
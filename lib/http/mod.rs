@@ -2,9 +2,11 @@
 
 pub mod content_type;
 pub mod cookie;
+pub mod download;
 pub mod header;
 pub mod method;
 pub mod multipart;
 pub mod query;
 pub mod server_sent_events;
 pub mod status;
+pub mod web_socket;
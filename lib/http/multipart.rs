@@ -1,12 +1,55 @@
-//! Multipart request parsing.
+//! Multipart request parsing and response building.
+//!
+//! [`MultipartData`] parses a whole multipart body at once, while [`MultipartReader`] parses parts lazily from any reader.
+//! The reader is useful for large uploads, as each part can be streamed to a file with [`MultipartPart::save_to`] without copying it into memory again.
+//! On routes with [`crate::Server::route_stream_body`], [`MultipartReader::from_stream`] reads the parts as they are received, so uploads don't have to fit in memory at all.
+//!
+//! ## Example
+//! ```rust
+//! # use afire::{Method, Server, Response, multipart::MultipartReader};
+//! # let mut server = Server::<()>::new("localhost", 8080);
+//! server.route(Method::POST, "/upload", |req| {
+//!     let mut reader = MultipartReader::from_request(req).unwrap();
+//!     while let Some(mut part) = reader.next_part().unwrap() {
+//!         if let Some(filename) = part.filename.clone() {
+//!             // Limit uploads to 10 MB
+//!             part.save_to(format!("uploads/{}.bin", part.name), 10 * 1024 * 1024).unwrap();
+//!             println!("Saved {filename}");
+//!         }
+//!     }
+//!     Response::new().text("Uploaded")
+//! });
+//! ```
+//!
+//! Multipart bodies can also be sent back with a [`Builder`].
+//! ```rust
+//! # use afire::{Method, Server, Header, multipart::Builder};
+//! # let mut server = Server::<()>::new("localhost", 8080);
+//! server.route(Method::GET, "/batch", |_req| {
+//!     Builder::mixed()
+//!         .part(&[Header::new("Content-Type", "application/json")], r#"{"id": 1}"#)
+//!         .part(&[Header::new("Content-Type", "application/json")], r#"{"id": 2}"#)
+//!         .response()
+//! });
+//! ```
 
 use std::{
     convert::TryFrom,
-    io::BufRead,
+    fs::{self, File},
+    io::{self, BufRead, Read, Write},
     ops::{Deref, DerefMut},
+    path::Path,
 };
 
-use crate::{header::Headers, Header, Request};
+use crate::{
+    header::Headers, internal::common::random_u64, request::BodyReader, Header, Request, Response,
+};
+
+/// The most bytes read from the underlying reader at once.
+const CHUNK_SIZE: usize = 16 * 1024;
+
+/// The max size of the headers of a single part, in bytes.
+const MAX_PART_HEADERS: usize = 16 * 1024;
 
 /// A multipart request.
 pub struct MultipartData<'a> {
@@ -39,20 +82,320 @@ pub enum MultipartError {
     InvalidData,
     /// An entry is invalid.
     InvalidEntry,
+    /// A part was larger than the limit passed to [`MultipartPart::read_to`] or [`MultipartPart::save_to`].
+    TooLarge,
+    /// An IO error occurred while reading the body or writing a part.
+    Io(io::Error),
+}
+
+/// Lazily parses the parts of a multipart body from a reader.
+/// Parts are returned one at a time by [`MultipartReader::next_part`], and any unread data of a part is skipped when the next part is requested.
+/// See the [module level docs](self) for an example.
+pub struct MultipartReader<R> {
+    /// The reader the body is read from.
+    reader: R,
+    /// The delimiter between parts (`\r\n--boundary`).
+    delimiter: Vec<u8>,
+    /// Data that has been read but not consumed yet, starting at `pos`.
+    buf: Vec<u8>,
+    /// The position of the first unconsumed byte in `buf`.
+    pos: usize,
+    /// If the underlying reader has no more data.
+    eof: bool,
+    /// What part of the body is currently being read.
+    state: ReaderState,
+}
+
+/// The current position of a [`MultipartReader`] in the body.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ReaderState {
+    /// Reading the data of a part (or the preamble before the first part).
+    Body,
+    /// Just after a delimiter, before the headers of the next part or the closing `--`.
+    Boundary,
+    /// The closing delimiter has been read.
+    Done,
+}
+
+/// A single part of a multipart body, returned by [`MultipartReader::next_part`].
+/// The data of the part can be read with the [`Read`] implementation, or with [`MultipartPart::read_to`] and [`MultipartPart::save_to`].
+pub struct MultipartPart<'a, R> {
+    /// The name of the part.
+    pub name: String,
+    /// The name of the uploaded file, if applicable.
+    pub filename: Option<String>,
+    /// The content type of the part, if specified.
+    pub content_type: Option<String>,
+    /// All the headers of the part.
+    pub headers: Headers,
+    /// The reader the part's data comes from.
+    reader: &'a mut MultipartReader<R>,
+}
+
+/// Builds a multipart body, like `multipart/mixed` for batch responses or `multipart/form-data`.
+/// See the [module level docs](self) for an example.
+#[derive(Debug, Clone)]
+pub struct Builder {
+    /// The content type of the body, without the boundary.
+    content_type: String,
+    /// The boundary between parts.
+    boundary: String,
+    /// The headers and content of each part.
+    parts: Vec<(Vec<Header>, Vec<u8>)>,
 }
 
 impl<'a> MultipartData<'a> {
     /// Get an entry by name, returns `None` if the entry does not exist.
-    pub fn get(&self, name: impl AsRef<str>) -> Option<&MultipartEntry> {
+    pub fn get(&self, name: impl AsRef<str>) -> Option<&MultipartEntry<'a>> {
         self.entries.iter().find(|x| x.name == name.as_ref())
     }
 
     /// Gets a mutable reference to an entry by name, returns `None` if the entry does not exist.
-    pub fn get_mut(&'a mut self, name: impl AsRef<str>) -> Option<&mut MultipartEntry> {
+    pub fn get_mut(&mut self, name: impl AsRef<str>) -> Option<&mut MultipartEntry<'a>> {
         self.entries.iter_mut().find(|x| x.name == name.as_ref())
     }
 }
 
+impl<R: Read> MultipartReader<R> {
+    /// Create a new MultipartReader from a reader and the boundary from the `Content-Type` header.
+    pub fn new(reader: R, boundary: impl AsRef<str>) -> Self {
+        Self {
+            reader,
+            delimiter: [b"\r\n--", boundary.as_ref().as_bytes()].concat(),
+            // The first delimiter is not preceded by a newline, so one is added to make it match
+            buf: b"\r\n".to_vec(),
+            pos: 0,
+            eof: false,
+            state: ReaderState::Body,
+        }
+    }
+
+    /// Gets the next part of the body, or `None` if there are no more parts.
+    /// Any unread data of the previous part is skipped.
+    pub fn next_part(&mut self) -> Result<Option<MultipartPart<'_, R>>, MultipartError> {
+        loop {
+            match self.state {
+                ReaderState::Body => {
+                    io::copy(&mut PartData(self), &mut io::sink())?;
+                }
+                ReaderState::Boundary => {
+                    self.fill(2)?;
+                    match self.buf.get(self.pos..self.pos + 2) {
+                        Some(b"--") => self.state = ReaderState::Done,
+                        Some(b"\r\n") => {
+                            self.pos += 2;
+                            break;
+                        }
+                        _ => return Err(MultipartError::InvalidData),
+                    }
+                }
+                ReaderState::Done => return Ok(None),
+            }
+        }
+
+        let headers = self.read_headers()?;
+        let (name, filename, headers) = parse_part_headers(&headers)?;
+        self.state = ReaderState::Body;
+
+        Ok(Some(MultipartPart {
+            name,
+            filename,
+            content_type: headers.get("Content-Type").map(str::to_owned),
+            headers,
+            reader: self,
+        }))
+    }
+
+    /// Calls `on_field` with each part of the body, in the order they arrive.
+    /// Any data of a part that `on_field` doesn't read is skipped.
+    /// Stops at the first error, from parsing the body or returned by `on_field`.
+    /// ## Example
+    /// ```rust
+    /// # use std::io;
+    /// # use afire::multipart::MultipartReader;
+    /// let body = "--XX\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\n1\r\n--XX--\r\n";
+    /// let mut names = Vec::new();
+    /// MultipartReader::new(body.as_bytes(), "XX")
+    ///     .for_each_field(|field| {
+    ///         names.push(field.name.clone());
+    ///         io::copy(field, &mut io::sink())?;
+    ///         Ok(())
+    ///     })
+    ///     .unwrap();
+    /// assert_eq!(names, ["a"]);
+    /// ```
+    pub fn for_each_field(
+        &mut self,
+        mut on_field: impl FnMut(&mut MultipartPart<'_, R>) -> Result<(), MultipartError>,
+    ) -> Result<(), MultipartError> {
+        while let Some(mut part) = self.next_part()? {
+            on_field(&mut part)?;
+        }
+
+        Ok(())
+    }
+
+    /// Reads the raw headers of a part, including the blank line after them.
+    fn read_headers(&mut self) -> Result<Vec<u8>, MultipartError> {
+        let end = loop {
+            let available = &self.buf[self.pos..];
+            if available.starts_with(b"\r\n") {
+                break 2;
+            }
+            if let Some(i) = find(available, b"\r\n\r\n") {
+                break i + 4;
+            }
+
+            if available.len() > MAX_PART_HEADERS || self.eof {
+                return Err(MultipartError::InvalidEntry);
+            }
+            self.fill(available.len() + 1)?;
+        };
+
+        let headers = self.buf[self.pos..self.pos + end].to_vec();
+        self.pos += end;
+        Ok(headers)
+    }
+
+    /// Reads from the underlying reader until at least `want` unconsumed bytes are buffered, or the reader runs out of data.
+    fn fill(&mut self, want: usize) -> io::Result<()> {
+        if self.pos > 0 && self.buf.len() - self.pos < want {
+            self.buf.drain(..self.pos);
+            self.pos = 0;
+        }
+
+        let mut chunk = [0; CHUNK_SIZE];
+        while self.buf.len() - self.pos < want && !self.eof {
+            match self.reader.read(&mut chunk) {
+                Ok(0) => self.eof = true,
+                Ok(n) => self.buf.extend_from_slice(&chunk[..n]),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads data of the current part into `out`, returning 0 once the delimiter after the part is reached.
+    fn read_data(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if self.state != ReaderState::Body {
+            return Ok(0);
+        }
+
+        self.fill(self.delimiter.len() + out.len().min(CHUNK_SIZE))?;
+        let available = &self.buf[self.pos..];
+        let end = match find(available, &self.delimiter) {
+            Some(0) => {
+                self.pos += self.delimiter.len();
+                self.state = ReaderState::Boundary;
+                return Ok(0);
+            }
+            Some(i) => i,
+            None if self.eof => return Err(io::ErrorKind::UnexpectedEof.into()),
+            // The end of the buffer could be the start of a delimiter, so it is kept until more data is read
+            None => available.len() + 1 - self.delimiter.len(),
+        };
+
+        let n = end.min(out.len());
+        out[..n].copy_from_slice(&available[..n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+impl<'a> MultipartReader<&'a [u8]> {
+    /// Create a MultipartReader for the body of a request.
+    /// Returns an error if the request does not have a `multipart/form-data` content type with a boundary.
+    pub fn from_request(req: &'a Request) -> Result<Self, MultipartError> {
+        let (_, boundary) = content_type(req)?;
+        Ok(Self::new(req.body.as_slice(), boundary))
+    }
+}
+
+impl<'a> MultipartReader<BodyReader<'a>> {
+    /// Create a MultipartReader that reads the body of a request with [`Request::body_reader`].
+    /// On routes with [`crate::Server::route_stream_body`], parts are parsed as the body is received, so memory use stays the same no matter how large the upload is.
+    /// Returns an error if the request does not have a `multipart/form-data` content type with a boundary.
+    /// ## Example
+    /// ```rust
+    /// # use std::fs::File;
+    /// # use afire::{Method, Server, Response, Status, multipart::MultipartReader};
+    /// # let mut server = Server::<()>::new("localhost", 8080);
+    /// server
+    ///     .route(Method::POST, "/upload", |req| {
+    ///         let result = MultipartReader::from_stream(req).and_then(|mut reader| {
+    ///             reader.for_each_field(|field| {
+    ///                 if field.filename.is_some() {
+    ///                     let mut file = File::create(format!("uploads/{}.bin", field.name))?;
+    ///                     field.read_to(&mut file, 1024 * 1024 * 1024)?;
+    ///                 }
+    ///                 Ok(())
+    ///             })
+    ///         });
+    ///
+    ///         match result {
+    ///             Ok(()) => Response::new().text("Uploaded"),
+    ///             Err(_) => Response::new().status(Status::BadRequest).text("Invalid upload"),
+    ///         }
+    ///     })
+    ///     .route_max_body_size(1024 * 1024 * 1024)
+    ///     .route_stream_body();
+    /// ```
+    pub fn from_stream(req: &'a Request) -> Result<Self, MultipartError> {
+        let (_, boundary) = content_type(req)?;
+        Ok(Self::new(req.body_reader(), boundary))
+    }
+}
+
+impl<R: Read> MultipartPart<'_, R> {
+    /// Copies the data of the part to a writer, returning the number of bytes copied.
+    /// If the part is larger than `limit` bytes, [`MultipartError::TooLarge`] is returned.
+    pub fn read_to(&mut self, writer: &mut impl Write, limit: u64) -> Result<u64, MultipartError> {
+        let copied = io::copy(&mut self.take(limit), writer)?;
+        if copied == limit && self.read(&mut [0])? != 0 {
+            return Err(MultipartError::TooLarge);
+        }
+
+        Ok(copied)
+    }
+
+    /// Saves the data of the part to a file, returning the number of bytes written.
+    /// If the part is larger than `limit` bytes, [`MultipartError::TooLarge`] is returned and the partially written file is removed.
+    pub fn save_to(&mut self, path: impl AsRef<Path>, limit: u64) -> Result<u64, MultipartError> {
+        let path = path.as_ref();
+        let mut file = File::create(path)?;
+        let out = self.read_to(&mut file, limit);
+
+        if out.is_err() {
+            drop(file);
+            let _ = fs::remove_file(path);
+        }
+        out
+    }
+}
+
+impl<R: Read> Read for MultipartPart<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read_data(buf)
+    }
+}
+
+/// Reads the data of the current part of a [`MultipartReader`], used to skip unread data.
+struct PartData<'a, R>(&'a mut MultipartReader<R>);
+
+impl<R: Read> Read for PartData<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read_data(buf)
+    }
+}
+
+impl From<io::Error> for MultipartError {
+    fn from(err: io::Error) -> Self {
+        MultipartError::Io(err)
+    }
+}
+
 impl<'a> Deref for MultipartData<'a> {
     type Target = Vec<MultipartEntry<'a>>;
 
@@ -71,21 +414,7 @@ impl<'a> TryFrom<&'a Request> for MultipartData<'a> {
     type Error = MultipartError;
 
     fn try_from(req: &'a Request) -> Result<Self, Self::Error> {
-        let content_type = req
-            .headers
-            .get_header("Content-Type")
-            .ok_or(MultipartError::InvalidContentType)?
-            .params();
-
-        let body_type = &content_type.value;
-        let boundary = content_type
-            .get("boundary")
-            .ok_or(MultipartError::InvalidBoundary)?;
-
-        if *body_type != "multipart/form-data" {
-            return Err(MultipartError::InvalidContentType);
-        }
-
+        let (body_type, boundary) = content_type(req)?;
         let boundary = [b"--", boundary.as_bytes()].concat();
         let data = split_boundary(&req.body, &boundary);
 
@@ -121,41 +450,190 @@ impl<'a> TryFrom<&'a [u8]> for MultipartEntry<'a> {
         }
 
         let (raw_headers, data) = value.split_at(index);
-
-        let mut headers = Vec::new();
-
-        for i in raw_headers
-            .lines()
-            .map(|x| x.unwrap())
-            .filter(|x| !x.is_empty())
-        {
-            let header = Header::from_string(i)
-                .ok()
-                .ok_or(MultipartError::InvalidEntry)?;
-            headers.push(header);
-        }
-
-        let headers = Headers(headers);
-        let content = headers
-            .get_header("Content-Disposition")
-            .ok_or(MultipartError::InvalidEntry)?;
-        let content_params = content.params();
+        let (name, filename, headers) = parse_part_headers(raw_headers)?;
 
         Ok(Self {
-            name: content_params
-                .get("name")
-                .ok_or(MultipartError::InvalidEntry)?
-                .strip_prefix('"')
-                .and_then(|x| x.strip_suffix('"'))
-                .ok_or(MultipartError::InvalidEntry)?
-                .to_string(),
-            filename: content_params.get("filename").map(|x| x.to_string()),
+            name,
+            filename,
             headers,
             data,
         })
     }
 }
 
+impl Builder {
+    /// Creates a builder for a `multipart/mixed` body, with a random boundary.
+    /// Parts are added with [`Builder::part`].
+    pub fn mixed() -> Self {
+        Self::new("multipart/mixed")
+    }
+
+    /// Creates a builder for a `multipart/form-data` body, with a random boundary.
+    /// Parts are added with [`Builder::field`] and [`Builder::file`].
+    pub fn form_data() -> Self {
+        Self::new("multipart/form-data")
+    }
+
+    /// Creates a builder for a multipart body with any `multipart/*` content type, with a random boundary.
+    pub fn new(content_type: impl AsRef<str>) -> Self {
+        Self {
+            content_type: content_type.as_ref().to_owned(),
+            boundary: format!("afire-{:016x}{:016x}", random_u64(), random_u64()),
+            parts: Vec::new(),
+        }
+    }
+
+    /// Sets the boundary between parts.
+    /// It is up to you to make sure it does not appear in the content of any part, which the random default practically guarantees.
+    pub fn boundary(self, boundary: impl AsRef<str>) -> Self {
+        Self {
+            boundary: boundary.as_ref().to_owned(),
+            ..self
+        }
+    }
+
+    /// Adds a part with the given headers and content.
+    pub fn part(mut self, headers: &[Header], content: impl AsRef<[u8]>) -> Self {
+        self.parts
+            .push((headers.to_vec(), content.as_ref().to_vec()));
+        self
+    }
+
+    /// Adds a form field, with a `Content-Disposition: form-data` header.
+    pub fn field(self, name: impl AsRef<str>, value: impl AsRef<[u8]>) -> Self {
+        let disposition = format!("form-data; name=\"{}\"", escape_quoted(name.as_ref()));
+        self.part(&[Header::new("Content-Disposition", disposition)], value)
+    }
+
+    /// Adds a file, with a `Content-Disposition: form-data` header containing its filename and the given content type.
+    pub fn file(
+        self,
+        name: impl AsRef<str>,
+        filename: impl AsRef<str>,
+        content_type: impl AsRef<str>,
+        content: impl AsRef<[u8]>,
+    ) -> Self {
+        let disposition = format!(
+            "form-data; name=\"{}\"; filename=\"{}\"",
+            escape_quoted(name.as_ref()),
+            escape_quoted(filename.as_ref())
+        );
+        let headers = [
+            Header::new("Content-Disposition", disposition),
+            Header::new("Content-Type", content_type),
+        ];
+        self.part(&headers, content)
+    }
+
+    /// Gets the value of the `Content-Type` header for the body, including the boundary.
+    pub fn content_type(&self) -> String {
+        format!("{}; boundary=\"{}\"", self.content_type, self.boundary)
+    }
+
+    /// Encodes all the parts into a multipart body.
+    pub fn build(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        for (headers, content) in &self.parts {
+            out.extend_from_slice(b"--");
+            out.extend_from_slice(self.boundary.as_bytes());
+            out.extend_from_slice(b"\r\n");
+            for header in headers {
+                out.extend_from_slice(header.to_string().as_bytes());
+                out.extend_from_slice(b"\r\n");
+            }
+            out.extend_from_slice(b"\r\n");
+            out.extend_from_slice(content);
+            out.extend_from_slice(b"\r\n");
+        }
+        out.extend_from_slice(b"--");
+        out.extend_from_slice(self.boundary.as_bytes());
+        out.extend_from_slice(b"--\r\n");
+        out
+    }
+
+    /// Creates a response with the encoded body and its `Content-Type` header.
+    pub fn response(&self) -> Response {
+        Response::new()
+            .bytes(&self.build())
+            .header("Content-Type", self.content_type())
+    }
+}
+
+/// Escapes a value to be put in a quoted `Content-Disposition` parameter.
+/// Like browsers do, quotes and newlines are percent encoded.
+fn escape_quoted(value: &str) -> String {
+    value
+        .replace('"', "%22")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+/// Gets the content type and boundary of a multipart request.
+fn content_type(req: &Request) -> Result<(&str, &str), MultipartError> {
+    let content_type = req
+        .headers
+        .get_header("Content-Type")
+        .ok_or(MultipartError::InvalidContentType)?
+        .params();
+
+    let body_type = content_type.value;
+    let boundary = content_type
+        .get("boundary")
+        .ok_or(MultipartError::InvalidBoundary)?;
+
+    if body_type != "multipart/form-data" {
+        return Err(MultipartError::InvalidContentType);
+    }
+
+    Ok((body_type, boundary.trim_matches('"')))
+}
+
+/// Parses the headers of a part, returning its name, filename and headers.
+fn parse_part_headers(
+    raw_headers: &[u8],
+) -> Result<(String, Option<String>, Headers), MultipartError> {
+    let mut headers = Vec::new();
+
+    for i in raw_headers
+        .lines()
+        .map(|x| x.map_err(|_| MultipartError::InvalidEntry))
+    {
+        let i = i?;
+        if i.is_empty() {
+            continue;
+        }
+
+        let header = Header::from_string(i)
+            .ok()
+            .ok_or(MultipartError::InvalidEntry)?;
+        headers.push(header);
+    }
+
+    let headers = Headers(headers);
+    let content = headers
+        .get_header("Content-Disposition")
+        .ok_or(MultipartError::InvalidEntry)?;
+    let content_params = content.params();
+
+    let name = content_params
+        .get("name")
+        .ok_or(MultipartError::InvalidEntry)?
+        .strip_prefix('"')
+        .and_then(|x| x.strip_suffix('"'))
+        .ok_or(MultipartError::InvalidEntry)?
+        .to_string();
+    let filename = content_params
+        .get("filename")
+        .map(|x| x.trim_matches('"').to_string());
+
+    Ok((name, filename, headers))
+}
+
+/// Finds the first position of `needle` in `haystack`.
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|x| x == needle)
+}
+
 fn split_boundary<'a>(data: &'a [u8], boundary: &[u8]) -> Vec<&'a [u8]> {
     let indexes = data
         .windows(boundary.len())
@@ -192,4 +670,109 @@ mod tests {
         assert_eq!(out[2], b"");
         assert_eq!(out[3], b"tomato");
     }
+
+    #[test]
+    fn test_multipart_reader() {
+        let body = b"preamble\r\n--XX\r\n\
+Content-Disposition: form-data; name=\"text\"\r\n\r\n\
+hello\r\n--XX\r\n\
+Content-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n\
+Content-Type: text/plain\r\n\r\n\
+line 1\r\n-- not the end\r\n--XX--\r\n";
+
+        // Read one byte at a time to make sure delimiters split across reads are found
+        let mut reader = MultipartReader::new(OneByte(body), "XX");
+
+        let part = reader.next_part().unwrap().unwrap();
+        assert_eq!(part.name, "text");
+        assert_eq!(part.filename, None);
+
+        let mut part = reader.next_part().unwrap().unwrap();
+        assert_eq!(part.filename.as_deref(), Some("a.txt"));
+        assert_eq!(part.content_type.as_deref(), Some("text/plain"));
+        assert!(matches!(
+            part.read_to(&mut Vec::new(), 5),
+            Err(MultipartError::TooLarge)
+        ));
+
+        let mut data = Vec::new();
+        let mut reader = MultipartReader::new(&body[..], "XX");
+        reader.next_part().unwrap();
+        let mut part = reader.next_part().unwrap().unwrap();
+        assert_eq!(part.read_to(&mut data, 1024).unwrap(), 22);
+        assert_eq!(data, b"line 1\r\n-- not the end");
+        assert!(reader.next_part().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_for_each_field() {
+        let body = b"--XX\r\n\
+Content-Disposition: form-data; name=\"a\"\r\n\r\n\
+first\r\n--XX\r\n\
+Content-Disposition: form-data; name=\"b\"\r\n\r\n\
+second\r\n--XX--\r\n";
+
+        let mut fields = Vec::new();
+        MultipartReader::new(OneByte(body), "XX")
+            .for_each_field(|field| {
+                // Only the start of each field is read, the rest should be skipped
+                let mut data = vec![0; 3];
+                field.read_exact(&mut data)?;
+                fields.push((field.name.clone(), data));
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(
+            fields,
+            [
+                ("a".to_owned(), b"fir".to_vec()),
+                ("b".to_owned(), b"sec".to_vec())
+            ]
+        );
+
+        let result =
+            MultipartReader::new(&body[..], "XX").for_each_field(|_| Err(MultipartError::TooLarge));
+        assert!(matches!(result, Err(MultipartError::TooLarge)));
+    }
+
+    #[test]
+    fn test_builder() {
+        let builder = Builder::form_data()
+            .boundary("XX")
+            .field("a\"b", "value")
+            .file("upload", "a.txt", "text/plain", "line 1\r\nline 2");
+        assert_eq!(
+            builder.content_type(),
+            "multipart/form-data; boundary=\"XX\""
+        );
+
+        let body = builder.build();
+        let mut reader = MultipartReader::new(&body[..], "XX");
+        let mut part = reader.next_part().unwrap().unwrap();
+        assert_eq!(part.name, "a%22b");
+        let mut data = Vec::new();
+        part.read_to(&mut data, 1024).unwrap();
+        assert_eq!(data, b"value");
+
+        let mut part = reader.next_part().unwrap().unwrap();
+        assert_eq!(part.filename.as_deref(), Some("a.txt"));
+        assert_eq!(part.content_type.as_deref(), Some("text/plain"));
+        let mut data = Vec::new();
+        part.read_to(&mut data, 1024).unwrap();
+        assert_eq!(data, b"line 1\r\nline 2");
+        assert!(reader.next_part().unwrap().is_none());
+
+        assert_ne!(Builder::mixed().boundary, Builder::mixed().boundary);
+    }
+
+    struct OneByte<'a>(&'a [u8]);
+
+    impl Read for OneByte<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.0.len().min(buf.len()).min(1);
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
 }
@@ -1,5 +1,5 @@
 use std::{
-    fmt,
+    fmt, mem,
     ops::{Deref, DerefMut},
 };
 
@@ -8,6 +8,17 @@ use crate::internal::encoding::url;
 /// Collection of query parameters.
 /// Can be made from the query string of a URL, or the body of a POST request.
 /// Similar to [`crate::header::Headers`].
+///
+/// The pairs are kept in the order they were received, and the query can be turned back into a percent encoded query string with [`ToString::to_string`].
+/// ## Example
+/// ```rust
+/// # use afire::Query;
+/// // Remove tracking params
+/// let mut query = Query::from_body("id=5&utm_source=news&q=a%20b");
+/// query.remove("utm_source");
+/// query.insert("page", "2");
+/// assert_eq!(query.to_string(), "?id=5&q=a%20b&page=2");
+/// ```
 #[derive(Debug, Hash, PartialEq, Eq, Clone)]
 pub struct Query(Vec<[String; 2]>);
 
@@ -55,6 +66,47 @@ impl Query {
         self.push([key.into(), value.into()]);
     }
 
+    /// Sets the value of a key, replacing any existing values.
+    /// The first existing pair with the key keeps its position, otherwise the pair is added to the end.
+    /// ## Example
+    /// ```rust
+    /// # use afire::Query;
+    /// let mut query = Query::from_body("a=1&b=2&a=3");
+    /// query.insert("a", "4");
+    /// assert_eq!(query.to_string(), "?a=4&b=2");
+    /// ```
+    pub fn insert(&mut self, key: impl Into<String>, value: impl Into<String>) {
+        let (key, value) = (key.into(), value.into());
+        match self.iter().position(|i| i[0] == key) {
+            Some(idx) => {
+                self.0[idx][1] = value;
+                let mut i = 0;
+                self.0.retain(|x| {
+                    i += 1;
+                    i - 1 == idx || x[0] != key
+                });
+            }
+            None => self.0.push([key, value]),
+        }
+    }
+
+    /// Removes all pairs with the specified key, returning their values.
+    /// ## Example
+    /// ```rust
+    /// # use afire::Query;
+    /// let mut query = Query::from_body("tag=a&id=5&tag=b");
+    /// assert_eq!(query.remove("tag"), ["a", "b"]);
+    /// assert_eq!(query.to_string(), "?id=5");
+    /// ```
+    pub fn remove(&mut self, key: impl AsRef<str>) -> Vec<String> {
+        let key = key.as_ref();
+        let (removed, kept) = mem::take(&mut self.0)
+            .into_iter()
+            .partition::<Vec<_>, _>(|i| i[0] == key);
+        self.0 = kept;
+        removed.into_iter().map(|[_, value]| value).collect()
+    }
+
     /// Get a value from a key.
     /// This will return None if the key does not exist.
     /// ## Example
@@ -137,18 +189,14 @@ impl Query {
 }
 
 // Implement fmt::Display for Query
+// Formats the query as a percent encoded query string, including the `?` if not empty.
 impl fmt::Display for Query {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        if self.is_empty() {
-            return f.write_str("");
+        for (i, [key, value]) in self.iter().enumerate() {
+            let start = if i == 0 { "?" } else { "&" };
+            write!(f, "{}{}={}", start, url::encode(key), url::encode(value))?;
         }
-
-        let mut output = String::from("?");
-        for i in &self.0 {
-            output.push_str(&format!("{}={}&", i[0], i[1]));
-        }
-        output.pop();
-        f.write_str(&output)
+        Ok(())
     }
 }
 
@@ -172,6 +220,18 @@ mod test {
         assert_eq!(query.get("bar"), None);
     }
 
+    #[test]
+    fn test_to_string() {
+        let mut query = Query::from_body("b=1&a=x%26y&c=%3D");
+        assert_eq!(query.to_string(), "?b=1&a=x%26y&c=%3D");
+
+        query.insert("b", "2");
+        query.remove("c");
+        assert_eq!(query.to_string(), "?b=2&a=x%26y");
+        query.clear();
+        assert_eq!(query.to_string(), "");
+    }
+
     #[test]
     fn test_get_mut() {
         let mut query = Query::from_body("foo=bar&nose=dog");
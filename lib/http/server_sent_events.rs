@@ -6,10 +6,14 @@
 //! # fn run(server: &mut Server) {
 //! server.route(Method::GET, "/sse", |req| {
 //!     let stream = req.sse().unwrap();
+//!     let shutdown = req.shutdown_token();
 //!
 //!     for i in 0..10 {
 //!         stream.send("update", i.to_string());
-//!         thread::sleep(Duration::from_secs(1));
+//!         // Stop early if the server is shutting down
+//!         if shutdown.wait_timeout(Duration::from_secs(1)) {
+//!             break;
+//!         }
 //!     }
 //!
 //!     Response::end()
@@ -17,6 +21,9 @@
 //! # }
 //! ```
 //!
+//! When the server shuts down, the events that were already sent are written, followed by the [shutdown event](ServerSentEventStream::shutdown_event) if there is one, then the connection is closed.
+//! Events sent after that are ignored, so handlers should check [`Request::shutdown_token`] and return instead of keeping the server from stopping.
+//!
 //! Then in the browser you can connect to the event stream with JavaScript using the [`EventSource`](https://developer.mozilla.org/en-US/docs/Web/API/EventSource) API:
 //! ```javascript
 //! const events = new EventSource("/sse");
@@ -25,16 +32,14 @@
 //! });
 //! ```
 use std::{
-    fmt::Display,
+    fmt::{self, Display},
     io::{self, Write},
-    sync::{
-        mpsc::{self, Sender},
-        Arc, Barrier,
-    },
+    net::Shutdown,
+    sync::mpsc::{self, Sender},
     thread,
 };
 
-use crate::{internal::common::ForceLock, Request};
+use crate::{internal::common::ForceLock, server::ShutdownSubscription, Request};
 
 /// A [server-sent event](https://developer.mozilla.org/en-US/docs/Web/API/Server-sent_events) stream.
 ///
@@ -45,6 +50,8 @@ pub struct ServerSentEventStream {
     /// If the EventSource connection gets reset, the client will send the last received event id in the `Last-Event-ID` header.
     /// This will be available here, if applicable.
     pub last_index: Option<u32>,
+    /// Tells the worker to stop when the server shuts down.
+    _shutdown: Option<ShutdownSubscription>,
 }
 
 /// An event that can be sent as a [server-sent event](https://developer.mozilla.org/en-US/docs/Web/API/Server-sent_events).
@@ -57,7 +64,10 @@ pub struct Event {
 enum EventType {
     Event(Event),
     SetRetry(u32),
-    Close(Arc<Barrier>),
+    SetShutdownEvent(Event),
+    /// Dropped by the worker once it stops.
+    Close(Sender<()>),
+    Shutdown,
 }
 
 impl ServerSentEventStream {
@@ -88,9 +98,15 @@ impl ServerSentEventStream {
     /// This will leave the socket open, so a new SSEStream could be created.
     /// Note: The client will likely try to reconnect automatically after a few seconds.
     pub fn close(&self) {
-        let barrier = Arc::new(Barrier::new(2));
-        let _ = self.stream.send(EventType::Close(barrier.clone()));
-        barrier.wait();
+        let (tx, rx) = mpsc::channel();
+        let _ = self.stream.send(EventType::Close(tx));
+        let _ = rx.recv();
+    }
+
+    /// Sets an event to send to the client when the server shuts down, right before the connection is closed.
+    /// This could tell the client when to reconnect, for example.
+    pub fn shutdown_event(&self, event: Event) {
+        let _ = self.stream.send(EventType::SetShutdownEvent(event));
     }
 
     /// Creates a new SSE stream from the given request.
@@ -101,25 +117,49 @@ impl ServerSentEventStream {
             .get("Last-Event-ID")
             .and_then(|id| id.parse::<u32>().ok());
 
+        this.consume_socket();
         let socket = this.socket.clone();
+        // Events are small and should be sent right away, instead of waiting to be combined with later ones
+        let _ = socket.force_lock().set_nodelay(true);
         socket.force_lock().write_all(b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\n\r\n")?;
 
         let (tx, rx) = mpsc::channel::<EventType>();
+        let shutdown_tx = tx.clone();
+        let shutdown = this.shutdown.subscribe(move || {
+            let _ = shutdown_tx.send(EventType::Shutdown);
+        });
+
         thread::Builder::new()
             .name("SSE worker".to_owned())
             .spawn(move || {
+                let mut shutdown_event = None;
                 for event in rx {
                     match event {
                         EventType::Event(e) => {
-                            let _ = socket.force_lock().write_all(e.to_string().as_bytes());
+                            let mut socket = socket.force_lock();
+                            let _ = socket
+                                .write_all(e.to_string().as_bytes())
+                                .and_then(|_| socket.flush());
                         }
                         EventType::SetRetry(retry) => {
+                            let mut socket = socket.force_lock();
                             let _ = socket
-                                .force_lock()
-                                .write_all(format!("retry: {retry}\n\n").as_bytes());
+                                .write_all(format!("retry: {retry}\n\n").as_bytes())
+                                .and_then(|_| socket.flush());
+                        }
+                        EventType::SetShutdownEvent(e) => shutdown_event = Some(e),
+                        EventType::Close(done) => {
+                            drop(done);
+                            break;
                         }
-                        EventType::Close(b) => {
-                            b.wait();
+                        EventType::Shutdown => {
+                            let mut socket = socket.force_lock();
+                            if let Some(e) = shutdown_event.take() {
+                                let _ = socket
+                                    .write_all(e.to_string().as_bytes())
+                                    .and_then(|_| socket.flush());
+                            }
+                            let _ = socket.shutdown(Shutdown::Both);
                             break;
                         }
                     }
@@ -130,6 +170,7 @@ impl ServerSentEventStream {
         Ok(Self {
             stream: tx,
             last_index,
+            _shutdown: shutdown,
         })
     }
 }
@@ -157,8 +198,8 @@ impl Event {
     }
 }
 
-impl ToString for Event {
-    fn to_string(&self) -> String {
+impl Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let mut out = String::new();
 
         if let Some(id) = self.id {
@@ -173,7 +214,7 @@ impl ToString for Event {
         }
 
         out.push('\n');
-        out
+        f.write_str(&out)
     }
 }
 
@@ -197,7 +238,17 @@ impl From<Event> for EventType {
 
 #[cfg(test)]
 mod test {
-    use super::Event;
+    use std::{
+        io::Cursor,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use super::{Event, ServerSentEventsExt};
+    use crate::{
+        internal::testing::{MemoryListener, MemoryStream},
+        Method, Response, Server,
+    };
 
     #[test]
     fn test_sse_event_format() {
@@ -207,4 +258,35 @@ mod test {
         let event = Event::new("update").id(1).data("Hello");
         assert_eq!(event.to_string(), "id: 1\nevent: update\ndata: Hello\n\n");
     }
+
+    #[test]
+    fn test_sse_shutdown() {
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let listener = MemoryListener(Mutex::new(vec![MemoryStream {
+            input: Cursor::new(b"GET /events HTTP/1.1\r\nHost: localhost\r\n\r\n".to_vec()),
+            output: output.clone(),
+        }]));
+
+        let mut server = Server::<()>::new("localhost", 0);
+        let shutdown = server.shutdown_handle();
+        server.route(Method::GET, "/events", move |req| {
+            let token = req.shutdown_token();
+            let stream = req.sse().unwrap();
+            stream.shutdown_event(Event::new("bye"));
+            stream.send("tick", 1);
+
+            assert!(!token.wait_timeout(Duration::from_millis(10)));
+            shutdown.shutdown();
+            assert!(token.wait_timeout(Duration::from_secs(5)));
+
+            // Waits for the worker, which stopped when the server shut down
+            stream.close();
+            stream.send("tick", 2);
+            Response::end()
+        });
+        assert!(server.start_with_listener(listener).is_ok());
+
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        assert!(output.ends_with("event: tick\ndata: 1\n\nevent: bye\ndata: \n\n"));
+    }
 }
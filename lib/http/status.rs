@@ -1,18 +1,14 @@
 //! HTTP status codes.
 //! Used in [`crate::Request`] and [`crate::Response`].
 
+use std::{borrow::Cow, sync::OnceLock};
+
 /// HTTP status codes.
 /// Used in [`crate::Request`] and [`crate::Response`].
 ///
 /// Used to indicate the status of an HTTP response.
-/// Note: Methods that accept a [`Status`] will also accept any [`u16`] value, converting it to a Status::Custom if it is not a valid status code.
-///
-///  Supports Status:
-/// - 100-101
-/// - 200-206
-/// - 300-307
-/// - 400-417
-/// - 500-505
+/// Note: Methods that accept a [`Status`] will also accept any [`u16`] value, converting it to a Status::Custom if it is not a known status code.
+/// Prefer using the named variants (`Status::NotFound` over `404`) so typos in status codes are caught at compile time.
 ///
 /// From <https://developer.mozilla.org/en-US/docs/Web/HTTP/Status>
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -51,6 +47,10 @@ pub enum Status {
     /// [MDN](https://developer.mozilla.org/en-US/docs/Web/HTTP/Status/206)
     PartialContent,
 
+    /// HTTP 207 Multi-Status.
+    /// [MDN](https://developer.mozilla.org/en-US/docs/Web/HTTP/Status/207)
+    MultiStatus,
+
     // == Redirection ==
     /// HTTP 300 Multiple Choices.
     /// [MDN](https://developer.mozilla.org/en-US/docs/Web/HTTP/Status/300)
@@ -206,6 +206,7 @@ impl Status {
             Status::NoContent => 204,
             Status::ResetContent => 205,
             Status::PartialContent => 206,
+            Status::MultiStatus => 207,
 
             Status::MultipleChoices => 300,
             Status::MovedPermanently => 301,
@@ -259,6 +260,7 @@ impl Status {
 
     /// Gets the default reason phrase for the status.
     /// For responses you can use the [`crate::Response::reason`] method to set a custom reason phrase.
+    /// Unknown status codes get a generic reason phrase for their class, for example `Client Error` for a `499`.
     pub fn reason_phrase(&self) -> &str {
         match self.code() {
             100 => "Continue",
@@ -272,6 +274,7 @@ impl Status {
             204 => "No Content",
             205 => "Reset Content",
             206 => "Partial Content",
+            207 => "Multi-Status",
 
             300 => "Multiple Choices",
             301 => "Moved Permanently",
@@ -318,9 +321,59 @@ impl Status {
             506 => "Variant Also Negotiates",
             510 => "Not Extended",
             511 => "Network Authentication Required",
-            _ => "OK",
+            x => match x / 100 {
+                1 => "Informational",
+                2 => "Success",
+                3 => "Redirection",
+                4 => "Client Error",
+                5 => "Server Error",
+                _ => "Unknown",
+            },
+        }
+    }
+
+    /// Gets the status line of a response with this status and its default reason phrase, like `HTTP/1.1 200 OK\r\n`.
+    /// The lines for codes 100 to 599 are only formatted once, the first time this is called.
+    pub(crate) fn status_line(&self) -> Cow<'static, str> {
+        static LINES: OnceLock<Vec<String>> = OnceLock::new();
+        let line = |code| {
+            let reason = Status::Custom(code).reason_phrase().to_owned();
+            format!("HTTP/1.1 {code} {reason}\r\n")
+        };
+
+        match self.code() {
+            code @ 100..=599 => {
+                let lines = LINES.get_or_init(|| (100..600).map(line).collect());
+                Cow::Borrowed(&lines[code as usize - 100])
+            }
+            code => Cow::Owned(line(code)),
         }
     }
+
+    /// Checks if the status is informational (1xx).
+    pub fn is_informational(&self) -> bool {
+        (100..200).contains(&self.code())
+    }
+
+    /// Checks if the status is successful (2xx).
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.code())
+    }
+
+    /// Checks if the status is a redirection (3xx).
+    pub fn is_redirection(&self) -> bool {
+        (300..400).contains(&self.code())
+    }
+
+    /// Checks if the status is a client error (4xx).
+    pub fn is_client_error(&self) -> bool {
+        (400..500).contains(&self.code())
+    }
+
+    /// Checks if the status is a server error (5xx).
+    pub fn is_server_error(&self) -> bool {
+        (500..600).contains(&self.code())
+    }
 }
 
 impl From<u16> for Status {
@@ -337,6 +390,7 @@ impl From<u16> for Status {
             204 => Status::NoContent,
             205 => Status::ResetContent,
             206 => Status::PartialContent,
+            207 => Status::MultiStatus,
 
             300 => Status::MultipleChoices,
             301 => Status::MovedPermanently,
@@ -388,3 +442,46 @@ impl From<u16> for Status {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::Status;
+
+    #[test]
+    fn test_status_from_code() {
+        assert_eq!(Status::from(404), Status::NotFound);
+        assert_eq!(Status::from(499), Status::Custom(499));
+        assert_eq!(Status::from(499).code(), 499);
+    }
+
+    #[test]
+    fn test_status_reason_phrase() {
+        assert_eq!(Status::ImaTeapot.reason_phrase(), "I'm a teapot");
+        assert_eq!(Status::Custom(299).reason_phrase(), "Success");
+        assert_eq!(Status::Custom(499).reason_phrase(), "Client Error");
+        assert_eq!(Status::Custom(999).reason_phrase(), "Unknown");
+    }
+
+    #[test]
+    fn test_status_line() {
+        assert_eq!(Status::Ok.status_line(), "HTTP/1.1 200 OK\r\n");
+        assert_eq!(
+            Status::Custom(499).status_line(),
+            "HTTP/1.1 499 Client Error\r\n"
+        );
+        assert_eq!(
+            Status::Custom(999).status_line(),
+            "HTTP/1.1 999 Unknown\r\n"
+        );
+    }
+
+    #[test]
+    fn test_status_class() {
+        assert!(Status::Continue.is_informational());
+        assert!(Status::NoContent.is_success());
+        assert!(Status::SeeOther.is_redirection());
+        assert!(Status::Custom(499).is_client_error());
+        assert!(Status::BadGateway.is_server_error());
+        assert!(!Status::Ok.is_client_error());
+    }
+}
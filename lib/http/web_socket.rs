@@ -1,148 +1,560 @@
+//! [WebSocket](https://developer.mozilla.org/en-US/docs/Web/API/WebSockets_API) support.
+//! ## Example
+//! ```rust
+//! # use afire::{Server, Response, Method, web_socket::{TxType, WebSocketExt}};
+//! # fn run(server: &mut Server) {
+//! server.route(Method::GET, "/ws", |req| {
+//!     let ws = req.ws().unwrap();
+//!
+//!     for message in &ws {
+//!         if let TxType::Text(text) = message {
+//!             let _ = ws.send(format!("You said: {text}"));
+//!         }
+//!     }
+//!
+//!     Response::end()
+//! });
+//! # }
+//! ```
+//!
+//! Once the connection is upgraded, a worker thread reads messages from the client and answers its pings.
+//! Messages are sent right away by the thread sending them, and a [`TxChannel`] from [`WebSocketStream::tx`] can be used to send them from other threads.
+//!
+//! Binary messages are received as the `Vec<u8>` their payload was read into, and sent without copying them into a frame first.
+//! With the `serde` feature, values can also be sent and received as JSON with `TxChannel::send_json` and `WebSocketStream::recv_json`.
+//!
+//! Connections are closed with [`WebSocketStream::close`], which waits for the client to answer, or when the [`WebSocketStream`] is dropped.
+//! When the client closes the connection, its [`CloseCode`] and reason are received in a [`TxType::Close`] message.
+//!
+//! When the server shuts down, clients are sent a Close frame with [`CloseCode::GoingAway`], and the connection is closed once they answer or [`WebSocketStream::close`] would have timed out.
+//! The [`TxType::Close`] message then ends the handler's loop over the messages, so the server can stop.
+//!
+//! The number of open WebSockets and the size and rate of the frames clients send can be limited with [`crate::Server::web_socket_limits`].
+//! Clients that go over a limit are sent a Close frame with the matching close code.
+//!
+//! Then in the browser you can connect to the socket with JavaScript using the [`WebSocket`](https://developer.mozilla.org/en-US/docs/Web/API/WebSocket) API:
+//! ```javascript
+//! const socket = new WebSocket("ws://localhost:8080/ws");
+//! socket.addEventListener("message", (event) => {
+//!   console.log(event.data);
+//! });
+//! ```
 use std::{
-    convert::TryInto,
     fmt::Display,
     io::{self, Read, Write},
-    net::TcpStream,
+    net::Shutdown,
     sync::{
-        mpsc::{self, Iter, Receiver, SyncSender},
-        Arc,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::{self, Iter, Receiver, RecvTimeoutError, Sender},
+        Arc, Mutex,
     },
     thread,
+    time::{Duration, Instant},
 };
 
+#[cfg(feature = "serde")]
+use serde::{de::DeserializeOwned, Serialize};
+
 use crate::{
+    clock::Clock,
     internal::{
         common::ForceLock,
         encoding::{base64, sha1},
     },
-    HeaderType, Request, Response, Status,
+    listener::Stream,
+    server::ShutdownSubscription,
+    HeaderType, Method, Request,
 };
 
 const WS_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
 
-/// A WebSocket stream.
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xA;
+
+/// How long [`WebSocketStream::close`] waits for the client to answer.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A WebSocket connection.
+/// The connection is closed when this is dropped.
+///
+/// For more information and usage examples, visit the [module level documentation](index.html).
 pub struct WebSocketStream {
-    rx: Arc<Receiver<TxType>>,
-    tx: Arc<SyncSender<TxType>>,
+    tx: TxChannel,
+    rx: Receiver<TxType>,
+    /// Closes the connection when the server shuts down.
+    _shutdown: Option<ShutdownSubscription>,
 }
 
-#[derive(Debug)]
-struct Frame {
-    fin: bool,
-    /// RSV1, RSV2, RSV3
-    /// BitPacked into one byte (0xRRR)
-    rsv: u8,
-    opcode: u8,
-    payload_len: u64,
-    mask: Option<[u8; 4]>,
-    payload: Vec<u8>,
+/// Sends messages to the client of a [`WebSocketStream`].
+/// It can be cloned and moved to other threads.
+#[derive(Clone)]
+pub struct TxChannel {
+    socket: Arc<Mutex<Box<dyn Stream>>>,
+    /// Set once a Close frame has been sent, after which no more frames can be.
+    closed: Arc<AtomicBool>,
 }
 
-/// Types of WebSocket frames
-#[derive(Debug)]
+/// Limits on the WebSockets of a server, set with [`crate::Server::web_socket_limits`].
+/// By default nothing is limited.
+/// ## Example
+/// ```
+/// # use afire::web_socket::WebSocketLimits;
+/// let limits = WebSocketLimits::default()
+///     .max_connections(1000)
+///     .max_message_size(64 * 1024)
+///     .max_frames_per_second(50);
+/// ```
+#[derive(Debug, Clone, Copy, Default, Hash, PartialEq, Eq)]
+pub struct WebSocketLimits {
+    /// The maximum number of WebSockets that can be open at once.
+    /// Connections over this limit are upgraded, then closed with code 1013 (Try Again Later).
+    pub max_connections: Option<usize>,
+    /// The maximum payload size of a single frame in bytes.
+    /// Clients that send larger frames are disconnected with code 1009 (Message Too Big).
+    pub max_frame_size: Option<u64>,
+    /// The maximum size of a message in bytes, after joining the frames it was split into.
+    /// Clients that send larger messages are disconnected with code 1009 (Message Too Big).
+    pub max_message_size: Option<usize>,
+    /// The maximum number of frames a client can send each second, including pings.
+    /// Clients that send more are disconnected with code 1008 (Policy Violation).
+    pub max_frames_per_second: Option<u32>,
+}
+
+/// The server's WebSocket limits and the number of WebSockets that are open.
+#[derive(Default)]
+pub(crate) struct WebSocketConfig {
+    pub(crate) limits: WebSocketLimits,
+    pub(crate) open: AtomicUsize,
+}
+
+/// Counts a WebSocket as open until it is dropped.
+struct OpenGuard(Arc<WebSocketConfig>);
+
+/// Checks the frames a client sends against the server's [`WebSocketLimits`].
+struct FrameLimiter {
+    limits: WebSocketLimits,
+    clock: Arc<dyn Clock>,
+    /// When the current one second window started.
+    window: Instant,
+    /// The number of frames received in the current window.
+    frames: u32,
+}
+
+/// The status codes a WebSocket can be closed with.
+/// - Reference: <https://www.rfc-editor.org/rfc/rfc6455#section-7.4.1>
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum CloseCode {
+    /// 1000, the connection did what it was opened for.
+    Normal,
+    /// 1001, the endpoint is going away, like a server shutting down or a browser leaving the page.
+    GoingAway,
+    /// 1002, the endpoint received a frame that breaks the protocol.
+    ProtocolError,
+    /// 1003, the endpoint received a type of message it can't accept.
+    Unsupported,
+    /// 1007, a message had invalid data, like a text message that isn't UTF-8.
+    InvalidData,
+    /// 1008, a message broke the endpoint's policy.
+    PolicyViolation,
+    /// 1009, a message was too big to process.
+    TooBig,
+    /// 1011, the server ran into an unexpected error.
+    InternalError,
+    /// 1013, the server is overloaded and the client should try again later.
+    TryAgainLater,
+    /// Any other code, like the ones from 4000 to 4999 that applications can use.
+    Other(u16),
+}
+
+/// The close code and reason of a Close frame.
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+pub struct CloseFrame {
+    /// Why the connection was closed.
+    pub code: CloseCode,
+    /// A description of why the connection was closed, which may be empty.
+    pub reason: String,
+}
+
+/// A message from the client.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum TxType {
-    /// Close the socket
-    Close,
-    /// Send / Receive a text message
+    /// The connection was closed.
+    /// Contains the close code and reason the client sent, or None if it didn't send any or the connection was lost.
+    /// This is always the last message.
+    Close(Option<CloseFrame>),
+    /// A text message.
     Text(String),
-    /// Send / Receive a binary message
+    /// A binary message.
     Binary(Vec<u8>),
 }
 
+/// Why the worker stopped reading messages.
+enum ReadError {
+    /// The connection was closed, or failed.
+    Closed,
+    /// The client sent a Close frame, which has been answered.
+    PeerClosed(Option<CloseFrame>),
+    /// The client broke the protocol, so the connection is closed with this code and reason.
+    Close(CloseCode, &'static str),
+}
+
+/// A frame sent by the client, with its payload unmasked.
+struct Frame {
+    fin: bool,
+    opcode: u8,
+    payload: Vec<u8>,
+}
+
 impl WebSocketStream {
-    /// Create a new WebSocket stream from a Request.
+    /// Upgrades the request's connection to a WebSocket.
+    /// This is called automatically if you use the [`WebSocketExt`] trait's .ws() method.
+    ///
+    /// Fails with [`io::ErrorKind::InvalidInput`] if the request isn't a WebSocket handshake, or [`io::ErrorKind::Unsupported`] if the connection's [`Stream`] can't be cloned.
+    /// If the server already has as many WebSockets open as [`WebSocketLimits::max_connections`] allows, the connection is closed and this fails with [`io::ErrorKind::ConnectionRefused`].
     pub fn from_request(req: &Request) -> io::Result<Self> {
-        dbg!(&req);
-        let ws_key = req.headers.get("Sec-WebSocket-Key").unwrap().to_owned();
-        trace!(Level::Debug, "WS Key: {}", ws_key);
-        let accept = base64::encode(&sha1::hash((ws_key + WS_GUID).as_bytes()));
-        trace!(Level::Debug, "WS Accept: {}", accept);
-
-        let mut upgrade = Response::new()
-            .status(Status::SwitchingProtocols)
-            .header(HeaderType::Upgrade, "websocket")
-            .header(HeaderType::Connection, "Upgrade")
-            .header("Sec-WebSocket-Accept", &accept)
-            .header("Sec-WebSocket-Version", "13");
-        upgrade.write(req.socket.clone(), &[]).unwrap();
-
-        let (s2c, rx) = mpsc::sync_channel::<TxType>(10);
-        let (_tx, c2s) = mpsc::sync_channel::<TxType>(10);
-        let (s2c, c2s) = (Arc::new(s2c), Arc::new(c2s));
-        let this_s2c = s2c.clone();
-
-        let socket = req.socket.force_lock();
-        let mut read_socket = socket.try_clone().unwrap();
-        let mut write_socket = socket.try_clone().unwrap();
+        let key = handshake_key(req).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "Not a WebSocket handshake")
+        })?;
+        let accept = base64::encode(&sha1::hash(format!("{key}{WS_GUID}").as_bytes()));
+
+        let mut socket = req.socket.force_lock();
+        let reader = socket.try_clone()?;
+        req.consume_socket();
+        // Messages can be far apart, so reading them shouldn't time out like waiting for a request does
+        socket.set_read_timeout(None)?;
+        let _ = socket.set_nodelay(true);
+        socket.write_all(format!("HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {accept}\r\n\r\n").as_bytes())?;
+        socket.flush()?;
         drop(socket);
-        thread::spawn(move || {
-            let mut buf = [0u8; 1024];
-            loop {
-                let len = read_socket.read(&mut buf).unwrap();
-                if len == 0 {
-                    break;
-                }
 
-                trace!(Level::Debug, "WS: Received: {:?}", &buf[..len]);
-                let frame = match Frame::from_slice(&buf[..len]) {
-                    Some(f) => f,
-                    None => continue,
-                };
+        let tx = TxChannel {
+            socket: req.socket.clone(),
+            closed: Arc::new(AtomicBool::new(false)),
+        };
+        let Some(guard) = OpenGuard::new(&req.web_socket_config) else {
+            trace!(Level::Debug, "Too many WebSockets, closing connection");
+            let _ = tx.write_frame(
+                OP_CLOSE,
+                &close_payload(CloseCode::TryAgainLater, "Too many connections"),
+            );
+            let _ = tx.socket.force_lock().shutdown(Shutdown::Both);
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                "Too many WebSocket connections",
+            ));
+        };
+        trace!(Level::Debug, "Upgraded connection to a WebSocket");
 
-                assert_eq!(&buf[..len], &frame.to_bytes()[..]);
+        // Dropped by the worker once it stops
+        let (running, stopped) = mpsc::channel::<()>();
+        let closer = tx.clone();
+        let shutdown = req.shutdown.subscribe(move || closer.going_away(stopped));
 
-                if !frame.fin {
-                    todo!("Handle fragmented frames");
-                }
+        let limiter = FrameLimiter::new(req.web_socket_config.limits, req.clock.clone());
+        let (messages, rx) = mpsc::channel();
+        let worker = tx.clone();
+        thread::Builder::new()
+            .name("WebSocket worker".to_owned())
+            .spawn(move || {
+                read_messages(reader, worker, messages, limiter, guard);
+                drop(running);
+            })?;
 
-                if frame.rsv != 0 {
-                    trace!(Level::Trace, "WS: Received frame with non-zero RSV bits");
-                }
+        Ok(Self {
+            tx,
+            rx,
+            _shutdown: shutdown,
+        })
+    }
 
-                // 0 = continuation
-                // 1 = text
-                // 2 = binary
-                // 8 = close
-                // 9 = ping
-                // 10 = pong
-                match frame.opcode {
-                    0 => {}
-                    1 => {}
-                    2 => {}
-                    8 => this_s2c.send(TxType::Close).unwrap(),
-                    9 => {}
-                    10 => {}
-                    _ => {}
-                }
-            }
-        });
+    /// Waits for the next message from the client.
+    /// Returns None once the connection has been closed and the [`TxType::Close`] message was received.
+    pub fn recv(&self) -> Option<TxType> {
+        self.rx.recv().ok()
+    }
 
-        thread::spawn(move || {
-            //todo
-            for i in rx {
-                trace!(Level::Debug, "WS: Sending {:?}", i);
-                match i {
-                    TxType::Close => Frame::close(),
-                    TxType::Text(s) => Frame::text(s),
-                    TxType::Binary(b) => Frame::binary(b),
-                }
-                .write(&mut write_socket)
-                .unwrap();
-                trace!(Level::Debug, "WS: Sent :p");
+    /// Waits for the next text or binary message from the client and deserializes it from JSON.
+    /// Returns None once the connection is closed.
+    #[cfg(feature = "serde")]
+    pub fn recv_json<T: DeserializeOwned>(&self) -> Option<io::Result<T>> {
+        let value = match self.recv()? {
+            TxType::Text(text) => serde_json::from_str(&text),
+            TxType::Binary(data) => serde_json::from_slice(&data),
+            TxType::Close(_) => return None,
+        };
+        Some(value.map_err(io::Error::from))
+    }
+
+    /// Closes the connection with a close code and a reason, which can be at most 123 bytes long.
+    /// Then waits up to five seconds for the client to answer with its own Close frame, ignoring any messages received in the meantime, and shuts down the connection.
+    /// Returns the close code and reason the client answered with, if any.
+    ///
+    /// Fails with [`io::ErrorKind::InvalidInput`] if the code can't be sent or the reason is too long, [`io::ErrorKind::NotConnected`] if the connection was already closed and [`io::ErrorKind::TimedOut`] if the client doesn't answer in time.
+    /// - Reference: <https://www.rfc-editor.org/rfc/rfc6455#section-7.1.2>
+    pub fn close(&self, code: CloseCode, reason: &str) -> io::Result<Option<CloseFrame>> {
+        if !code.is_valid() || reason.len() > 123 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Invalid close code or reason",
+            ));
+        }
+        self.tx
+            .write_frame(OP_CLOSE, &close_payload(code, reason))?;
+
+        let deadline = Instant::now() + CLOSE_TIMEOUT;
+        let answer = loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            match self.rx.recv_timeout(timeout) {
+                Ok(TxType::Close(frame)) => break Ok(frame),
+                Ok(_) => continue,
+                Err(RecvTimeoutError::Timeout) => break Err(io::ErrorKind::TimedOut.into()),
+                Err(RecvTimeoutError::Disconnected) => break Ok(None),
             }
-        });
+        };
+
+        let _ = self.tx.socket.force_lock().shutdown(Shutdown::Both);
+        answer
+    }
+
+    /// Gets a [`TxChannel`] for sending messages to the client from other threads.
+    /// Messages sent after this stream is dropped fail, as the connection is closed.
+    pub fn tx(&self) -> TxChannel {
+        self.tx.clone()
+    }
+
+    /// Sends a text message to the client.
+    pub fn send(&self, data: impl Display) -> io::Result<()> {
+        self.tx.send(data)
+    }
+
+    /// Sends a binary message to the client.
+    pub fn send_binary(&self, data: impl AsRef<[u8]>) -> io::Result<()> {
+        self.tx.send_binary(data)
+    }
+
+    /// Serializes `value` to JSON and sends it to the client as a text message.
+    #[cfg(feature = "serde")]
+    pub fn send_json(&self, value: &impl Serialize) -> io::Result<()> {
+        self.tx.send_json(value)
+    }
+}
 
-        Ok(Self { rx: c2s, tx: s2c })
+impl TxChannel {
+    /// Sends a text message to the client.
+    pub fn send(&self, data: impl Display) -> io::Result<()> {
+        self.write_frame(OP_TEXT, data.to_string().as_bytes())
     }
 
-    /// Sends 'text' data to the client.
-    pub fn send(&self, data: impl Display) {
-        self.tx.send(TxType::Text(data.to_string())).unwrap();
+    /// Sends a binary message to the client.
+    /// Frames sent by the server aren't masked, so the data is written to the socket as is.
+    pub fn send_binary(&self, data: impl AsRef<[u8]>) -> io::Result<()> {
+        self.write_frame(OP_BINARY, data.as_ref())
     }
 
-    /// Sends binary data to the client.
-    pub fn send_binary(&self, data: Vec<u8>) {
-        self.tx.send(TxType::Binary(data)).unwrap();
+    /// Serializes `value` to JSON and sends it to the client as a text message.
+    #[cfg(feature = "serde")]
+    pub fn send_json(&self, value: &impl Serialize) -> io::Result<()> {
+        self.write_frame(OP_TEXT, &serde_json::to_vec(value)?)
+    }
+
+    /// Closes the connection because the server is shutting down.
+    /// The client has until [`WebSocketStream::close`] would time out to answer, or the worker to stop for any other reason, before the connection is shut down.
+    fn going_away(self, stopped: Receiver<()>) {
+        let payload = close_payload(CloseCode::GoingAway, "Server shutting down");
+        if self.write_frame(OP_CLOSE, &payload).is_err() {
+            return;
+        }
+
+        let _ = thread::Builder::new()
+            .name("WebSocket closer".to_owned())
+            .spawn(move || {
+                let _ = stopped.recv_timeout(CLOSE_TIMEOUT);
+                let _ = self.socket.force_lock().shutdown(Shutdown::Both);
+            });
+    }
+
+    /// Writes a single, unfragmented frame.
+    /// Once a Close frame has been written, writing any others fails with [`io::ErrorKind::NotConnected`].
+    fn write_frame(&self, opcode: u8, payload: &[u8]) -> io::Result<()> {
+        let mut socket = self.socket.force_lock();
+        if self.closed.load(Ordering::Acquire) {
+            return Err(io::ErrorKind::NotConnected.into());
+        }
+        if opcode == OP_CLOSE {
+            self.closed.store(true, Ordering::Release);
+        }
+
+        socket.write_all(&frame_head(opcode, payload.len()))?;
+        socket.write_all(payload)?;
+        socket.flush()
+    }
+}
+
+impl CloseCode {
+    /// Gets the numeric close code.
+    pub fn code(&self) -> u16 {
+        match self {
+            CloseCode::Normal => 1000,
+            CloseCode::GoingAway => 1001,
+            CloseCode::ProtocolError => 1002,
+            CloseCode::Unsupported => 1003,
+            CloseCode::InvalidData => 1007,
+            CloseCode::PolicyViolation => 1008,
+            CloseCode::TooBig => 1009,
+            CloseCode::InternalError => 1011,
+            CloseCode::TryAgainLater => 1013,
+            CloseCode::Other(code) => *code,
+        }
+    }
+
+    /// Checks if the code can be sent in a Close frame.
+    /// Codes below 1000, the ones reserved to mean there was no Close frame (1005 and 1006) and unassigned ones can't be.
+    fn is_valid(&self) -> bool {
+        matches!(self.code(), 1000..=1003 | 1007..=1014 | 3000..=4999)
+    }
+}
+
+impl From<u16> for CloseCode {
+    fn from(code: u16) -> Self {
+        match code {
+            1000 => CloseCode::Normal,
+            1001 => CloseCode::GoingAway,
+            1002 => CloseCode::ProtocolError,
+            1003 => CloseCode::Unsupported,
+            1007 => CloseCode::InvalidData,
+            1008 => CloseCode::PolicyViolation,
+            1009 => CloseCode::TooBig,
+            1011 => CloseCode::InternalError,
+            1013 => CloseCode::TryAgainLater,
+            code => CloseCode::Other(code),
+        }
+    }
+}
+
+impl CloseFrame {
+    /// Parses the payload of a Close frame from the client.
+    /// - Reference: <https://www.rfc-editor.org/rfc/rfc6455#section-5.5.1>
+    fn parse(payload: &[u8]) -> Result<Option<Self>, ReadError> {
+        let Some((code, reason)) = payload.split_first_chunk() else {
+            return match payload.is_empty() {
+                true => Ok(None),
+                false => Err(ReadError::Close(
+                    CloseCode::ProtocolError,
+                    "Invalid close frame",
+                )),
+            };
+        };
+
+        let code = CloseCode::from(u16::from_be_bytes(*code));
+        if !code.is_valid() {
+            return Err(ReadError::Close(
+                CloseCode::ProtocolError,
+                "Invalid close code",
+            ));
+        }
+        let reason = String::from_utf8(reason.to_vec())
+            .map_err(|_| ReadError::Close(CloseCode::InvalidData, "Invalid UTF-8"))?;
+        Ok(Some(Self { code, reason }))
+    }
+}
+
+impl WebSocketLimits {
+    /// Sets the maximum number of WebSockets that can be open at once.
+    pub fn max_connections(self, max_connections: usize) -> Self {
+        Self {
+            max_connections: Some(max_connections),
+            ..self
+        }
+    }
+
+    /// Sets the maximum payload size of a single frame in bytes.
+    pub fn max_frame_size(self, max_frame_size: u64) -> Self {
+        Self {
+            max_frame_size: Some(max_frame_size),
+            ..self
+        }
+    }
+
+    /// Sets the maximum size of a message in bytes.
+    pub fn max_message_size(self, max_message_size: usize) -> Self {
+        Self {
+            max_message_size: Some(max_message_size),
+            ..self
+        }
+    }
+
+    /// Sets the maximum number of frames a client can send each second.
+    pub fn max_frames_per_second(self, max_frames_per_second: u32) -> Self {
+        Self {
+            max_frames_per_second: Some(max_frames_per_second),
+            ..self
+        }
+    }
+}
+
+impl WebSocketConfig {
+    pub(crate) fn new(limits: WebSocketLimits) -> Self {
+        Self {
+            limits,
+            open: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl OpenGuard {
+    /// Counts another open WebSocket, unless there are already as many as the limits allow.
+    fn new(config: &Arc<WebSocketConfig>) -> Option<Self> {
+        let max = config.limits.max_connections.unwrap_or(usize::MAX);
+        config
+            .open
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |i| {
+                (i < max).then_some(i + 1)
+            })
+            .ok()?;
+        Some(Self(config.clone()))
+    }
+}
+
+impl Drop for OpenGuard {
+    fn drop(&mut self) {
+        self.0.open.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+impl FrameLimiter {
+    fn new(limits: WebSocketLimits, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            limits,
+            window: clock.instant(),
+            clock,
+            frames: 0,
+        }
+    }
+
+    /// Counts a frame, failing if the client has sent too many this second.
+    fn count_frame(&mut self) -> Result<(), ReadError> {
+        let Some(max) = self.limits.max_frames_per_second else {
+            return Ok(());
+        };
+
+        let now = self.clock.instant();
+        if now.saturating_duration_since(self.window) >= Duration::from_secs(1) {
+            self.window = now;
+            self.frames = 0;
+        }
+
+        self.frames += 1;
+        if self.frames > max {
+            return Err(ReadError::Close(
+                CloseCode::PolicyViolation,
+                "Too many frames",
+            ));
+        }
+        Ok(())
     }
 }
 
@@ -155,181 +567,589 @@ impl<'a> IntoIterator for &'a WebSocketStream {
     }
 }
 
+impl Drop for WebSocketStream {
+    fn drop(&mut self) {
+        let _ = self
+            .tx
+            .write_frame(OP_CLOSE, &close_payload(CloseCode::Normal, ""));
+        let _ = self.tx.socket.force_lock().shutdown(Shutdown::Both);
+    }
+}
+
 impl Frame {
-    fn from_slice(buf: &[u8]) -> Option<Self> {
-        let fin = buf[0] & 0b1000_0000 != 0;
-        let rsv = (buf[0] & 0b0111_0000) >> 4;
-
-        let mask = buf[1] & 0b1000_0000 != 0;
-        let opcode = buf[0] & 0b0000_1111;
-        let (payload_len, offset) = match buf[1] as u64 & 0b0111_1111 {
-            126 => (u16::from_be_bytes([buf[2], buf[3]]) as u64, 4),
-            127 => (
-                u64::from_be_bytes([
-                    buf[2], buf[3], buf[4], buf[5], buf[6], buf[7], buf[8], buf[9],
-                ]),
-                10,
-            ),
-            i => (i, 2),
+    /// Reads a frame sent by a client, failing if its payload is longer than `max_size`.
+    /// - Reference: <https://www.rfc-editor.org/rfc/rfc6455#section-5.2>
+    fn read(socket: &mut impl Read, max_size: Option<u64>) -> Result<Self, ReadError> {
+        let mut head = [0; 2];
+        socket.read_exact(&mut head)?;
+        let (fin, opcode) = (head[0] & 0x80 != 0, head[0] & 0x0F);
+        let len = match head[1] & 0x7F {
+            126 => {
+                let mut len = [0; 2];
+                socket.read_exact(&mut len)?;
+                u16::from_be_bytes(len) as u64
+            }
+            127 => {
+                let mut len = [0; 8];
+                socket.read_exact(&mut len)?;
+                u64::from_be_bytes(len)
+            }
+            len => len as u64,
         };
-        trace!(
-            Level::Debug,
-            "WS: {{ fin: {fin}, rsv: {rsv}, opcode: {opcode}, payload_len: {payload_len}, mask: {mask} }}",
-        );
 
-        if payload_len == 0 {
-            trace!(Level::Debug, "WS: Empty payload");
-            return None;
+        // No extensions are negotiated so the RSV bits must be unset, clients must mask their frames and control frames can't be fragmented or longer than 125 bytes
+        let control = opcode & 0x8 != 0;
+        if head[0] & 0x70 != 0 || head[1] & 0x80 == 0 || (control && (!fin || len > 125)) {
+            return Err(ReadError::Close(CloseCode::ProtocolError, "Invalid frame"));
         }
-
-        if !mask {
-            trace!(Level::Debug, "WS: No mask");
-            return None;
+        if max_size.is_some_and(|max| len > max) {
+            return Err(ReadError::Close(CloseCode::TooBig, "Frame too large"));
         }
 
-        let mut decoded = Vec::with_capacity(payload_len as usize);
-        let mask = &buf[offset..offset + 4];
-        for i in 0..payload_len as usize {
-            decoded.push(buf[i + offset + 4] ^ mask[i % 4]);
+        let mut mask = [0; 4];
+        socket.read_exact(&mut mask)?;
+        // The buffer grows as the payload is read, so a frame can't make it allocate more than it sends
+        let mut payload = Vec::new();
+        socket.by_ref().take(len).read_to_end(&mut payload)?;
+        if (payload.len() as u64) < len {
+            return Err(ReadError::Closed);
         }
 
-        trace!(Level::Debug, "WS: Decoded: {:?}", decoded);
-        trace!(
-            Level::Debug,
-            "WS: Decoded: {:?}",
-            String::from_utf8_lossy(&decoded)
-        );
+        for (i, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[i % 4];
+        }
 
-        Some(Self {
+        Ok(Self {
             fin,
-            rsv,
             opcode,
-            payload_len,
-            mask: Some(mask.try_into().unwrap()),
-            payload: decoded,
+            payload,
         })
     }
+}
+
+impl From<io::Error> for ReadError {
+    fn from(_: io::Error) -> Self {
+        ReadError::Closed
+    }
+}
 
-    /*
-      0                   1                   2                   3
-      0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
-     +-+-+-+-+-------+-+-------------+-------------------------------+
-     |F|R|R|R| opcode|M| Payload len |    Extended payload length    |
-     |I|S|S|S|  (4)  |A|     (7)     |             (16/64)           |
-     |N|V|V|V|       |S|             |   (if payload len==126/127)   |
-     | |1|2|3|       |K|             |                               |
-     +-+-+-+-+-------+-+-------------+ - - - - - - - - - - - - - - - +
-     |     Extended payload length continued, if payload len == 127  |
-     + - - - - - - - - - - - - - - - +-------------------------------+
-     |                               |Masking-key, if MASK set to 1  |
-     +-------------------------------+-------------------------------+
-     | Masking-key (continued)       |          Payload Data         |
-     +-------------------------------- - - - - - - - - - - - - - - - +
-     :                     Payload Data continued ...                :
-     + - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - +
-     |                     Payload Data continued ...                |
-     +---------------------------------------------------------------+
-    */
-    fn to_bytes(&self) -> Vec<u8> {
-        let mut buf = Vec::new();
-        buf.push((self.fin as u8) << 7 | self.rsv << 4 | self.opcode);
-
-        match self.payload_len {
-            ..=125 => buf.push((self.mask.is_some() as u8) << 7 | self.payload_len as u8),
-            126..=65535 => {
-                buf.push((self.mask.is_some() as u8) << 7 | 126);
-                buf.extend_from_slice(&self.payload_len.to_be_bytes());
+/// A trait for upgrading a request to a WebSocket connection.
+pub trait WebSocketExt {
+    /// Upgrades the request's connection to a WebSocket.
+    /// See [`WebSocketStream::from_request`].
+    fn ws(&self) -> io::Result<WebSocketStream>;
+}
+
+impl WebSocketExt for Request {
+    fn ws(&self) -> io::Result<WebSocketStream> {
+        WebSocketStream::from_request(self)
+    }
+}
+
+/// Gets the client's `Sec-WebSocket-Key`, if the request is a valid WebSocket handshake.
+/// - Reference: <https://www.rfc-editor.org/rfc/rfc6455#section-4.2.1>
+fn handshake_key(req: &Request) -> Option<&str> {
+    let has_token = |header: HeaderType, token: &str| {
+        req.headers
+            .get_all(header)
+            .flat_map(|i| i.split(','))
+            .any(|i| i.trim().eq_ignore_ascii_case(token))
+    };
+
+    let valid = req.method == Method::GET
+        && has_token(HeaderType::Upgrade, "websocket")
+        && has_token(HeaderType::Connection, "upgrade")
+        && req.headers.get("Sec-WebSocket-Version") == Some("13");
+    valid
+        .then(|| req.headers.get("Sec-WebSocket-Key"))
+        .flatten()
+}
+
+/// Reads messages from the client until the connection is closed.
+fn read_messages(
+    mut socket: Box<dyn Stream>,
+    tx: TxChannel,
+    messages: Sender<TxType>,
+    mut limiter: FrameLimiter,
+    open: OpenGuard,
+) {
+    let error = loop {
+        match read_message(&mut socket, &tx, &mut limiter) {
+            Ok(message) => {
+                let _ = messages.send(message);
+            }
+            Err(e) => break e,
+        }
+    };
+
+    let peer = match error {
+        ReadError::Closed => None,
+        ReadError::PeerClosed(frame) => frame,
+        ReadError::Close(code, reason) => {
+            let _ = tx.write_frame(OP_CLOSE, &close_payload(code, reason));
+            None
+        }
+    };
+    // Stop counting the connection before the client sees it close, so it can reconnect right away
+    drop(open);
+    let _ = messages.send(TxType::Close(peer));
+    let _ = socket.shutdown(Shutdown::Both);
+}
+
+/// Reads frames until a whole message has been received, answering the control frames sent in between.
+fn read_message(
+    socket: &mut impl Read,
+    tx: &TxChannel,
+    limiter: &mut FrameLimiter,
+) -> Result<TxType, ReadError> {
+    let mut message: Option<(u8, Vec<u8>)> = None;
+    loop {
+        let frame = Frame::read(socket, limiter.limits.max_frame_size)?;
+        limiter.count_frame()?;
+        let fin = frame.fin;
+        match (frame.opcode, &mut message) {
+            (OP_PING, _) => {
+                let _ = tx.write_frame(OP_PONG, &frame.payload);
+                continue;
+            }
+            (OP_PONG, _) => continue,
+            (OP_CLOSE, _) => {
+                // Echo the client's close code, unless this answers a Close frame sent by the server
+                let close = CloseFrame::parse(&frame.payload)?;
+                let _ = tx.write_frame(OP_CLOSE, frame.payload.get(..2).unwrap_or_default());
+                return Err(ReadError::PeerClosed(close));
             }
+            (OP_TEXT | OP_BINARY, None) => message = Some((frame.opcode, frame.payload)),
+            (OP_CONTINUATION, Some((_, data))) => data.extend_from_slice(&frame.payload),
             _ => {
-                buf.push((self.mask.is_some() as u8) << 7 | 127);
-                buf.extend_from_slice(&self.payload_len.to_be_bytes());
+                return Err(ReadError::Close(
+                    CloseCode::ProtocolError,
+                    "Unexpected frame",
+                ))
             }
         }
 
-        match self.mask {
-            Some(mask) => {
-                buf.extend_from_slice(&mask);
-                buf.extend_from_slice(&xor_mask(&mask, &self.payload))
-            }
-            None => buf.extend_from_slice(&self.payload),
+        let size = message.as_ref().map_or(0, |(_, data)| data.len());
+        if limiter
+            .limits
+            .max_message_size
+            .is_some_and(|max| size > max)
+        {
+            return Err(ReadError::Close(CloseCode::TooBig, "Message too large"));
         }
 
-        buf
+        if fin {
+            break;
+        }
     }
 
-    fn write(&self, socket: &mut TcpStream) -> io::Result<()> {
-        let buf = self.to_bytes();
-        trace!(Level::Debug, "WS: Writing: {:?}", buf);
-
-        socket.write_all(&buf)?;
-        Ok(())
+    match message {
+        Some((OP_TEXT, data)) => String::from_utf8(data)
+            .map(TxType::Text)
+            .map_err(|_| ReadError::Close(CloseCode::InvalidData, "Invalid UTF-8")),
+        Some((_, data)) => Ok(TxType::Binary(data)),
+        None => unreachable!(),
     }
+}
 
-    fn close() -> Self {
-        Self {
-            fin: true,
-            rsv: 0,
-            opcode: 8,
-            payload_len: 0,
-            mask: None,
-            payload: Vec::new(),
+/// Encodes the start of an unmasked frame, before its payload.
+fn frame_head(opcode: u8, len: usize) -> Vec<u8> {
+    let mut head = vec![0x80 | opcode];
+    match len {
+        0..=125 => head.push(len as u8),
+        126..=0xFFFF => {
+            head.push(126);
+            head.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        _ => {
+            head.push(127);
+            head.extend_from_slice(&(len as u64).to_be_bytes());
         }
     }
+    head
+}
+
+/// Encodes the payload of a Close frame.
+fn close_payload(code: CloseCode, reason: &str) -> Vec<u8> {
+    let mut payload = code.code().to_be_bytes().to_vec();
+    payload.extend_from_slice(reason.as_bytes());
+    payload
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::{Cursor, Read, Write},
+        net::{SocketAddr, TcpListener, TcpStream},
+        sync::{mpsc, Arc, Mutex},
+        thread,
+        time::{Duration, UNIX_EPOCH},
+    };
+
+    use super::{
+        frame_head, read_message, CloseCode, CloseFrame, FrameLimiter, ReadError, TxChannel,
+        TxType, WebSocketExt, WebSocketLimits,
+    };
+    use crate::{
+        clock::{ManualClock, SystemClock},
+        internal::testing::MemoryStream,
+        Method, Response, Server,
+    };
+
+    /// Encodes a frame the way a client would, with a masked payload.
+    fn client_frame(head: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [0x12, 0x34, 0x56, 0x78];
+        let mut frame = frame_head(0, payload.len());
+        frame[0] = head;
+        frame[1] |= 0x80;
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        frame
+    }
 
-    fn text(text: String) -> Self {
-        Self {
-            fin: true,
-            rsv: 0,
-            opcode: 1,
-            payload_len: text.len() as u64,
-            mask: None,
-            payload: text.into_bytes(),
+    /// A [`TxChannel`] that writes to `output`.
+    fn memory_tx(output: &Arc<Mutex<Vec<u8>>>) -> TxChannel {
+        TxChannel {
+            socket: Arc::new(Mutex::new(Box::new(MemoryStream {
+                input: Cursor::new(Vec::new()),
+                output: output.clone(),
+            }))),
+            closed: Default::default(),
         }
     }
 
-    fn binary(binary: Vec<u8>) -> Self {
-        Self {
-            fin: true,
-            rsv: 0,
-            opcode: 2,
-            payload_len: binary.len() as u64,
-            mask: None,
-            payload: binary,
+    /// Sends a WebSocket handshake and reads the response head.
+    fn handshake(addr: SocketAddr) -> (TcpStream, String) {
+        let mut socket = TcpStream::connect(addr).unwrap();
+        socket.write_all(b"GET /ws HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n").unwrap();
+        let mut response = Vec::new();
+        while !response.ends_with(b"\r\n\r\n") {
+            let mut byte = [0];
+            socket.read_exact(&mut byte).unwrap();
+            response.push(byte[0]);
         }
+        (socket, String::from_utf8(response).unwrap())
     }
 
-    fn rsv1(&self) -> bool {
-        self.rsv & 0b100 != 0
+    fn read_server_frame(socket: &mut TcpStream) -> (u8, Vec<u8>) {
+        let mut head = [0; 2];
+        socket.read_exact(&mut head).unwrap();
+        assert_eq!(head[1] & 0x80, 0, "Server frames must not be masked");
+        let mut payload = vec![0; (head[1] & 0x7F) as usize];
+        socket.read_exact(&mut payload).unwrap();
+        (head[0], payload)
     }
 
-    fn rsv2(&self) -> bool {
-        self.rsv & 0b010 != 0
+    #[test]
+    fn test_frame_head() {
+        assert_eq!(frame_head(0x1, 5), [0x81, 5]);
+        assert_eq!(frame_head(0x2, 300), [0x82, 126, 0x01, 0x2C]);
+        assert_eq!(
+            frame_head(0x2, 70_000),
+            [0x82, 127, 0, 0, 0, 0, 0, 0x01, 0x11, 0x70]
+        );
     }
 
-    fn rsv3(&self) -> bool {
-        self.rsv & 0b001 != 0
+    #[test]
+    fn test_read_message() {
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let tx = memory_tx(&output);
+        let mut limiter = FrameLimiter::new(WebSocketLimits::default(), Arc::new(SystemClock));
+
+        // A text message split over two frames, with a ping in between
+        let mut input = client_frame(0x01, b"Hello, ");
+        input.extend(client_frame(0x89, b"ping"));
+        input.extend(client_frame(0x80, b"World"));
+        input.extend(client_frame(0x82, &[1, 2, 3]));
+        input.extend(client_frame(0x81, &[0xFF]));
+        let mut input = Cursor::new(input);
+
+        let message = read_message(&mut input, &tx, &mut limiter).ok();
+        assert_eq!(message, Some(TxType::Text("Hello, World".to_owned())));
+        assert_eq!(*output.lock().unwrap(), b"\x8A\x04ping");
+        let message = read_message(&mut input, &tx, &mut limiter).ok();
+        assert_eq!(message, Some(TxType::Binary(vec![1, 2, 3])));
+        assert!(matches!(
+            read_message(&mut input, &tx, &mut limiter),
+            Err(ReadError::Close(CloseCode::InvalidData, _))
+        ));
+
+        // Unmasked frames aren't allowed from clients
+        let mut input = Cursor::new(b"\x81\x02hi".to_vec());
+        assert!(matches!(
+            read_message(&mut input, &tx, &mut limiter),
+            Err(ReadError::Close(CloseCode::ProtocolError, _))
+        ));
+
+        // 1005 means there was no close code, so it can't be sent in a Close frame
+        let mut input = Cursor::new(client_frame(0x88, &1005_u16.to_be_bytes()));
+        assert!(matches!(
+            read_message(&mut input, &tx, &mut limiter),
+            Err(ReadError::Close(
+                CloseCode::ProtocolError,
+                "Invalid close code"
+            ))
+        ));
+
+        let mut payload = 4000_u16.to_be_bytes().to_vec();
+        payload.extend_from_slice(b"done");
+        let mut input = Cursor::new(client_frame(0x88, &payload));
+        assert!(matches!(
+            read_message(&mut input, &tx, &mut limiter),
+            Err(ReadError::PeerClosed(Some(CloseFrame { code: CloseCode::Other(4000), reason })))
+                if reason == "done"
+        ));
     }
-}
 
-/// A trait for initiating a WebSocket connection on a request.
-pub trait WebSocketExt {
-    /// Initiates a WebSocket connection on a request.
-    fn ws(&self) -> io::Result<WebSocketStream>;
-}
+    #[test]
+    fn test_web_socket() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (closed, close_frame) = mpsc::channel();
+        let closed = Mutex::new(closed);
+        let mut server = Server::<()>::new("localhost", 0);
+        server.route(Method::GET, "/ws", move |req| {
+            let ws = req.ws().unwrap();
+            for message in &ws {
+                match message {
+                    TxType::Text(text) => ws.send(text.to_uppercase()).unwrap(),
+                    TxType::Binary(data) => ws.send_binary(data).unwrap(),
+                    TxType::Close(frame) => closed.lock().unwrap().send(frame).unwrap(),
+                }
+            }
+            Response::end()
+        });
+        let shutdown = server.shutdown_handle();
+        let handle =
+            thread::spawn(move || server.start_threaded_with_listener(listener, 2).is_ok());
 
-impl WebSocketExt for Request {
-    fn ws(&self) -> io::Result<WebSocketStream> {
-        WebSocketStream::from_request(self)
+        let (mut socket, response) = handshake(addr);
+        assert!(response.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+        // The example from RFC 6455 section 1.3
+        assert!(response.contains("\r\nSec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+
+        socket.write_all(&client_frame(0x81, b"hello")).unwrap();
+        assert_eq!(read_server_frame(&mut socket), (0x81, b"HELLO".to_vec()));
+        socket.write_all(&client_frame(0x82, &[0, 1, 2])).unwrap();
+        assert_eq!(read_server_frame(&mut socket), (0x82, vec![0, 1, 2]));
+        socket.write_all(&client_frame(0x89, b"ping")).unwrap();
+        assert_eq!(read_server_frame(&mut socket), (0x8A, b"ping".to_vec()));
+
+        socket
+            .write_all(&client_frame(0x88, b"\x03\xE9leaving"))
+            .unwrap();
+        assert_eq!(read_server_frame(&mut socket), (0x88, vec![0x03, 0xE9]));
+        assert_eq!(socket.read(&mut [0; 16]).unwrap(), 0);
+        assert_eq!(
+            close_frame.recv().unwrap(),
+            Some(CloseFrame {
+                code: CloseCode::GoingAway,
+                reason: "leaving".to_owned()
+            })
+        );
+
+        shutdown.shutdown();
+        assert!(handle.join().unwrap());
     }
-}
 
-fn xor_mask(mask: &[u8], data: &[u8]) -> Vec<u8> {
-    debug_assert_eq!(mask.len(), 4);
+    #[test]
+    fn test_frame_limits() {
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let tx = memory_tx(&output);
+        let limits = WebSocketLimits::default()
+            .max_frame_size(4)
+            .max_message_size(6);
+        let mut limiter = FrameLimiter::new(limits, Arc::new(SystemClock));
+
+        let mut input = Cursor::new(client_frame(0x81, b"hello"));
+        assert!(matches!(
+            read_message(&mut input, &tx, &mut limiter),
+            Err(ReadError::Close(CloseCode::TooBig, "Frame too large"))
+        ));
+
+        // Each frame is small enough, but the message isn't
+        let mut input = client_frame(0x01, b"hell");
+        input.extend(client_frame(0x80, b"o!!"));
+        let mut input = Cursor::new(input);
+        assert!(matches!(
+            read_message(&mut input, &tx, &mut limiter),
+            Err(ReadError::Close(CloseCode::TooBig, "Message too large"))
+        ));
+    }
+
+    #[test]
+    fn test_frame_rate() {
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let tx = memory_tx(&output);
+        let clock = Arc::new(ManualClock::new(UNIX_EPOCH));
+        let limits = WebSocketLimits::default().max_frames_per_second(2);
+        let mut limiter = FrameLimiter::new(limits, Arc::new(clock.clone()));
+
+        let mut input = Vec::new();
+        for _ in 0..5 {
+            input.extend(client_frame(0x82, &[0]));
+        }
+        let mut input = Cursor::new(input);
+
+        assert!(read_message(&mut input, &tx, &mut limiter).is_ok());
+        assert!(read_message(&mut input, &tx, &mut limiter).is_ok());
+        clock.advance(Duration::from_secs(1));
+        assert!(read_message(&mut input, &tx, &mut limiter).is_ok());
+        assert!(read_message(&mut input, &tx, &mut limiter).is_ok());
+        assert!(matches!(
+            read_message(&mut input, &tx, &mut limiter),
+            Err(ReadError::Close(CloseCode::PolicyViolation, _))
+        ));
+    }
+
+    #[test]
+    fn test_close() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (answered, answer) = mpsc::channel();
+        let answered = Mutex::new(answered);
+        let mut server = Server::<()>::new("localhost", 0);
+        server.route(Method::GET, "/ws", move |req| {
+            let ws = req.ws().unwrap();
+            assert_eq!(ws.recv(), Some(TxType::Text("bye".to_owned())));
+            assert!(ws.close(CloseCode::Other(1005), "").is_err());
+            let frame = ws.close(CloseCode::Other(4000), "Goodbye").unwrap();
+            let sent = ws.send("too late").is_ok();
+            answered.lock().unwrap().send((frame, sent)).unwrap();
+            Response::end()
+        });
+        let shutdown = server.shutdown_handle();
+        let handle = thread::spawn(move || server.start_with_listener(listener).is_ok());
+
+        let (mut socket, _) = handshake(addr);
+        socket.write_all(&client_frame(0x81, b"bye")).unwrap();
+        assert_eq!(
+            read_server_frame(&mut socket),
+            (0x88, b"\x0F\xA0Goodbye".to_vec())
+        );
+
+        // Messages sent before the client answers are ignored
+        socket.write_all(&client_frame(0x81, b"wait")).unwrap();
+        socket
+            .write_all(&client_frame(0x88, b"\x0F\xA0ok"))
+            .unwrap();
+        assert_eq!(socket.read(&mut [0; 16]).unwrap(), 0);
+        assert_eq!(
+            answer.recv().unwrap(),
+            (
+                Some(CloseFrame {
+                    code: CloseCode::Other(4000),
+                    reason: "ok".to_owned()
+                }),
+                false
+            )
+        );
+
+        shutdown.shutdown();
+        assert!(handle.join().unwrap());
+    }
+
+    #[test]
+    fn test_max_connections() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut server = Server::<()>::new("localhost", 0)
+            .web_socket_limits(WebSocketLimits::default().max_connections(1));
+        server.route(Method::GET, "/ws", |req| {
+            if let Ok(ws) = req.ws() {
+                for _ in &ws {}
+            }
+            Response::end()
+        });
+        let shutdown = server.shutdown_handle();
+        let handle =
+            thread::spawn(move || server.start_threaded_with_listener(listener, 3).is_ok());
+
+        let (mut first, _) = handshake(addr);
+        first.write_all(&client_frame(0x89, b"")).unwrap();
+        assert_eq!(read_server_frame(&mut first), (0x8A, Vec::new()));
 
-    let mut decoded = Vec::with_capacity(data.len());
-    for (i, byte) in data.iter().enumerate() {
-        decoded.push(byte ^ mask[i % 4]);
+        // The second connection is upgraded, then closed right away
+        let (mut second, response) = handshake(addr);
+        assert!(response.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+        let (head, payload) = read_server_frame(&mut second);
+        assert_eq!(head, 0x88);
+        assert_eq!(payload[..2], 1013_u16.to_be_bytes());
+        assert_eq!(second.read(&mut [0; 16]).unwrap(), 0);
+
+        // Once the first is closed, there is room for another
+        first.write_all(&client_frame(0x88, &[0x03, 0xE8])).unwrap();
+        assert_eq!(read_server_frame(&mut first), (0x88, vec![0x03, 0xE8]));
+        assert_eq!(first.read(&mut [0; 16]).unwrap(), 0);
+        let (mut third, _) = handshake(addr);
+        third.write_all(&client_frame(0x89, b"")).unwrap();
+        assert_eq!(read_server_frame(&mut third), (0x8A, Vec::new()));
+        drop(third);
+
+        shutdown.shutdown();
+        assert!(handle.join().unwrap());
+    }
+
+    #[test]
+    fn test_shutdown() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (closed, close_frame) = mpsc::channel();
+        let closed = Mutex::new(closed);
+        let mut server = Server::<()>::new("localhost", 0);
+        server.route(Method::GET, "/ws", move |req| {
+            let ws = req.ws().unwrap();
+            for message in &ws {
+                if let TxType::Close(frame) = message {
+                    closed.lock().unwrap().send(frame).unwrap();
+                }
+            }
+            Response::end()
+        });
+        let shutdown = server.shutdown_handle();
+        let handle = thread::spawn(move || server.start_with_listener(listener).is_ok());
+
+        let (mut socket, _) = handshake(addr);
+        socket.write_all(&client_frame(0x89, b"")).unwrap();
+        assert_eq!(read_server_frame(&mut socket), (0x8A, Vec::new()));
+
+        shutdown.shutdown();
+        let (head, payload) = read_server_frame(&mut socket);
+        assert_eq!(head, 0x88);
+        assert_eq!(payload[..2], 1001_u16.to_be_bytes());
+        socket
+            .write_all(&client_frame(0x88, &payload[..2]))
+            .unwrap();
+        assert_eq!(socket.read(&mut [0; 16]).unwrap(), 0);
+
+        // The handler stops once the client answers, so the server can shut down
+        assert!(handle.join().unwrap());
+        let frame = close_frame.recv().unwrap().unwrap();
+        assert_eq!(frame.code, CloseCode::GoingAway);
     }
 
-    decoded
+    #[test]
+    fn test_not_a_handshake() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut server = Server::<()>::new("localhost", 0);
+        server.route(Method::GET, "/ws", |req| match req.ws() {
+            Ok(_) => Response::new().text("upgraded"),
+            Err(e) => Response::new().status(400).text(e),
+        });
+        let shutdown = server.shutdown_handle();
+        let handle = thread::spawn(move || server.start_with_listener(listener).is_ok());
+
+        let mut socket = TcpStream::connect(addr).unwrap();
+        socket
+            .write_all(b"GET /ws HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        socket.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+        assert!(response.ends_with("\r\n\r\nNot a WebSocket handshake"));
+
+        shutdown.shutdown();
+        assert!(handle.join().unwrap());
+    }
 }
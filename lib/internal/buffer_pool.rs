@@ -0,0 +1,117 @@
+//! A pool of byte buffers that are reused between requests.
+//!
+//! Reading a request and streaming a response each need a buffer, so without the pool every request makes a few allocations of several KiB.
+//! Buffers are returned to the pool when dropped, and the pool holds at most [`crate::Server::buffer_pool_size`] of them.
+
+use std::{
+    mem,
+    ops::{Deref, DerefMut},
+    sync::Mutex,
+};
+
+use crate::internal::common::ForceLock;
+
+/// A pool of reusable byte buffers.
+#[derive(Debug)]
+pub(crate) struct BufferPool {
+    buffers: Mutex<Vec<Vec<u8>>>,
+    max_buffers: usize,
+}
+
+/// A buffer taken from a [`BufferPool`].
+/// It is returned to the pool when dropped.
+pub(crate) struct PooledBuffer<'a> {
+    pool: &'a BufferPool,
+    buffer: Vec<u8>,
+}
+
+impl BufferPool {
+    /// Creates a pool that holds at most `max_buffers` unused buffers.
+    pub(crate) fn new(max_buffers: usize) -> Self {
+        Self {
+            buffers: Mutex::new(Vec::new()),
+            max_buffers,
+        }
+    }
+
+    /// Takes a buffer of `len` bytes from the pool, allocating a new one if the pool is empty.
+    /// Reused buffers are not cleared, so they may still contain data from the last time they were used.
+    pub(crate) fn take(&self, len: usize) -> PooledBuffer<'_> {
+        let mut buffer = self.buffers.force_lock().pop().unwrap_or_default();
+        buffer.resize(len, 0);
+        PooledBuffer { pool: self, buffer }
+    }
+
+    /// Takes an empty buffer from the pool, to be filled with [`PooledBuffer::as_vec`].
+    pub(crate) fn take_empty(&self) -> PooledBuffer<'_> {
+        self.take(0)
+    }
+
+    /// The number of unused buffers in the pool.
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.buffers.force_lock().len()
+    }
+}
+
+impl PooledBuffer<'_> {
+    /// Gets the underlying Vec, so the buffer can be grown.
+    pub(crate) fn as_vec(&mut self) -> &mut Vec<u8> {
+        &mut self.buffer
+    }
+}
+
+impl Deref for PooledBuffer<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        &self.buffer
+    }
+}
+
+impl DerefMut for PooledBuffer<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.buffer
+    }
+}
+
+impl Drop for PooledBuffer<'_> {
+    fn drop(&mut self) {
+        let mut buffers = self.pool.buffers.force_lock();
+        if buffers.len() < self.pool.max_buffers {
+            buffers.push(mem::take(&mut self.buffer));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::BufferPool;
+
+    #[test]
+    fn test_buffer_pool() {
+        let pool = BufferPool::new(1);
+
+        let mut a = pool.take(4);
+        a.copy_from_slice(b"afir");
+        let b = pool.take(8);
+        assert_eq!(&*b, &[0; 8]);
+
+        drop(a);
+        drop(b);
+        assert_eq!(pool.len(), 1);
+
+        // The buffer is reused, and resized to the new length
+        let a = pool.take(6);
+        assert_eq!(&a[..4], b"afir");
+        assert_eq!(a.len(), 6);
+        assert_eq!(pool.len(), 0);
+    }
+
+    #[test]
+    fn test_buffer_pool_disabled() {
+        let pool = BufferPool::new(0);
+        drop(pool.take(4));
+        assert_eq!(pool.len(), 0);
+    }
+}
@@ -1,5 +1,6 @@
 //! Some little functions used here and there
 
+use std::io;
 use std::net::{Ipv4Addr, Ipv6Addr};
 use std::sync::{Mutex, MutexGuard};
 use std::{borrow::Cow, net::IpAddr};
@@ -63,11 +64,11 @@ impl ToHostAddress for &str {
 
 /// Adds a force_lock method to Mutex, which will return the inner value even if its poisoned.
 pub(crate) trait ForceLock<T> {
-    fn force_lock(&self) -> MutexGuard<T>;
+    fn force_lock(&self) -> MutexGuard<'_, T>;
 }
 
 impl<T> ForceLock<T> for Mutex<T> {
-    fn force_lock(&self) -> MutexGuard<T> {
+    fn force_lock(&self) -> MutexGuard<'_, T> {
         match self.lock() {
             Ok(i) => i,
             Err(e) => e.into_inner(),
@@ -110,9 +111,20 @@ pub(crate) fn any_string(any: Box<dyn std::any::Any + Send>) -> Cow<'static, str
     Cow::Borrowed("")
 }
 
+/// Checks if an IO error means the client has disconnected.
+/// These are expected whenever a client goes away, so they shouldn't be logged as errors.
+pub(crate) fn is_disconnect(kind: io::ErrorKind) -> bool {
+    matches!(
+        kind,
+        io::ErrorKind::BrokenPipe
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::NotConnected
+    )
+}
+
 /// Get the current time since the Unix Epoch.
 /// Will panic if the system time is before the Unix Epoch.
-#[cfg(feature = "extensions")]
 pub(crate) fn epoch() -> std::time::Duration {
     use std::time::{SystemTime, UNIX_EPOCH};
 
@@ -121,6 +133,78 @@ pub(crate) fn epoch() -> std::time::Duration {
         .expect("System time is before the Unix Epoch. Make sure your date is set correctly.")
 }
 
+/// Generates a random u64.
+/// This uses the randomly seeded keys of the standard library's hasher, so it is not cryptographically secure.
+pub(crate) fn random_u64() -> u64 {
+    use std::{
+        collections::hash_map::RandomState,
+        hash::{BuildHasher, Hasher},
+        sync::atomic::{AtomicU64, Ordering},
+    };
+
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+    hasher.write_u128(epoch().as_nanos());
+    hasher.finish()
+}
+
+/// Encodes a string as a JSON string, with the surrounding quotes.
+#[cfg(feature = "extensions")]
+pub(crate) fn json_string(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Checks if `ip` is in the CIDR range `range/prefix`.
+pub(crate) fn in_range(ip: IpAddr, range: IpAddr, prefix: u8) -> bool {
+    match (ip, range) {
+        (IpAddr::V4(ip), IpAddr::V4(range)) => {
+            let mask = u32::MAX
+                .checked_shl(32 - prefix.min(32) as u32)
+                .unwrap_or(0);
+            u32::from(ip) & mask == u32::from(range) & mask
+        }
+        (IpAddr::V6(ip), IpAddr::V6(range)) => {
+            let mask = u128::MAX
+                .checked_shl(128 - prefix.min(128) as u32)
+                .unwrap_or(0);
+            u128::from(ip) & mask == u128::from(range) & mask
+        }
+        _ => false,
+    }
+}
+
+/// Escapes the characters that have a special meaning in XML and HTML.
+#[cfg(any(feature = "extensions", all(feature = "dev", debug_assertions)))]
+pub(crate) fn xml_escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for i in text.chars() {
+        match i {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            _ => out.push(i),
+        }
+    }
+    out
+}
+
 #[cfg(test)]
 mod test {
     use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
@@ -191,6 +275,7 @@ mod test {
     }
 
     #[test]
+    #[allow(clippy::needless_borrow)]
     fn test_from_ref_string_addr() {
         assert_eq!(
             (&"127.0.0.1".to_owned()).to_address().unwrap(),
@@ -205,4 +290,24 @@ mod test {
             IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1))
         );
     }
+
+    #[test]
+    #[cfg(feature = "extensions")]
+    fn test_json_string() {
+        use super::json_string;
+
+        assert_eq!(json_string("/index.html"), "\"/index.html\"");
+        assert_eq!(
+            json_string("a \"quote\"\\\n\u{1}"),
+            "\"a \\\"quote\\\"\\\\\\n\\u0001\""
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "extensions")]
+    fn test_xml_escape() {
+        use super::xml_escape;
+
+        assert_eq!(xml_escape("<a & 'b'>"), "&lt;a &amp; &apos;b&apos;&gt;");
+    }
 }
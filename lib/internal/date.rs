@@ -0,0 +1,257 @@
+//! Date formatting and parsing functions.
+//! Used for the `Date` header, conditional requests, cookie expiration dates and logging.
+
+const DAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Days between 0000-03-01 and 1970-01-01, used to shift dates so years start in March.
+/// This puts the leap day at the end of the year, which makes the math simpler.
+const EPOCH_SHIFT: u64 = 719_468;
+
+/// Days in a 400 year cycle of the Gregorian calendar.
+const DAYS_PER_ERA: u64 = 146_097;
+
+#[cfg(feature = "date-header")]
+thread_local! {
+    /// The last formatted `Date` header and the second it was formatted for.
+    static DATE_CACHE: std::cell::RefCell<(u64, String)> = const { std::cell::RefCell::new((u64::MAX, String::new())) };
+}
+
+/// Returns the number of days in a month.
+/// Month is 1-indexed.
+fn days_in_month(month: u8, year: u64) -> u8 {
+    match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400)) => {
+            29
+        }
+        2 => 28,
+        _ => unreachable!("Invalid month: {}", month),
+    }
+}
+
+/// Splits a unix timestamp into the year, month (1-indexed), day of the month (1-indexed) and weekday (0 is Sunday).
+/// Based on Howard Hinnant's [`civil_from_days`](https://howardhinnant.github.io/date_algorithms.html#civil_from_days).
+fn split_date(epoch: u64) -> (u64, u8, u8, u8) {
+    let days = epoch / 86400;
+    let weekday = ((days + 4) % 7) as u8;
+
+    let days = days + EPOCH_SHIFT;
+    let era = days / DAYS_PER_ERA;
+    let day_of_era = days % DAYS_PER_ERA;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u8;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    } as u8;
+    let year = year_of_era + era * 400 + (month <= 2) as u64;
+
+    (year, month, day, weekday)
+}
+
+/// Converts a date into the number of days since the unix epoch.
+/// Returns None if the date is before the epoch.
+/// Based on Howard Hinnant's [`days_from_civil`](https://howardhinnant.github.io/date_algorithms.html#days_from_civil).
+fn join_date(year: u64, month: u8, day: u8) -> Option<u64> {
+    let year = year.checked_sub((month <= 2) as u64)?;
+    let era = year / 400;
+    let year_of_era = year % 400;
+    let shifted_month = (month as u64 + 9) % 12;
+    let day_of_year = (153 * shifted_month + 2) / 5 + day as u64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    (era * DAYS_PER_ERA + day_of_era).checked_sub(EPOCH_SHIFT)
+}
+
+/// Returns the date in the IMF-fixdate format used by HTTP.
+/// Example: `Sun, 06 Nov 1994 08:49:37 GMT`
+pub fn imp_date(epoch: u64) -> String {
+    let seconds = epoch % 60;
+    let minutes = (epoch / 60) % 60;
+    let hours = (epoch / 3600) % 24;
+    let (year, month, day, weekday) = split_date(epoch);
+
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        DAYS[weekday as usize],
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        hours,
+        minutes,
+        seconds
+    )
+}
+
+/// Calls `f` with the date `now` (seconds since the unix epoch) in the IMF-fixdate format, for the `Date` header.
+/// The date is only formatted once a second on each thread.
+#[cfg(feature = "date-header")]
+pub(crate) fn with_imp_date<T>(now: u64, f: impl FnOnce(&str) -> T) -> T {
+    DATE_CACHE.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        if cache.0 != now {
+            *cache = (now, imp_date(now));
+        }
+        f(&cache.1)
+    })
+}
+
+/// Parses an HTTP date into a unix timestamp.
+/// As required by [RFC 9110, Section 5.6.7](https://www.rfc-editor.org/rfc/rfc9110.html#section-5.6.7), this accepts the IMF-fixdate format along with the obsolete RFC 850 and asctime formats.
+/// Two digit RFC 850 years before 70 are taken to be in the 2000s.
+///
+/// Returns None if the date is invalid or before the unix epoch.
+/// ## Example
+/// ```rust
+/// # use afire::internal::date::parse_date;
+/// assert_eq!(parse_date("Sun, 06 Nov 1994 08:49:37 GMT"), Some(784111777));
+/// assert_eq!(parse_date("Sunday, 06-Nov-94 08:49:37 GMT"), Some(784111777));
+/// assert_eq!(parse_date("Sun Nov  6 08:49:37 1994"), Some(784111777));
+/// ```
+pub fn parse_date(date: &str) -> Option<u64> {
+    let parts = date.split_ascii_whitespace().collect::<Vec<_>>();
+    let (day, month, year, time) = match parts[..] {
+        // IMF-fixdate: Sun, 06 Nov 1994 08:49:37 GMT
+        [_, day, month, year, time, "GMT"] if day.len() == 2 && year.len() == 4 => {
+            (day, month, year.parse().ok()?, time)
+        }
+        // RFC 850: Sunday, 06-Nov-94 08:49:37 GMT
+        [_, date, time, "GMT"] => {
+            let mut date = date.split('-');
+            let (day, month, year) = (date.next()?, date.next()?, date.next()?);
+            if date.next().is_some() || day.len() != 2 || year.len() != 2 {
+                return None;
+            }
+
+            let year = year.parse::<u64>().ok()?;
+            (day, month, year + if year < 70 { 2000 } else { 1900 }, time)
+        }
+        // asctime: Sun Nov  6 08:49:37 1994
+        [_, month, day, time, year] if year.len() == 4 => (day, month, year.parse().ok()?, time),
+        _ => return None,
+    };
+
+    let month = MONTHS.iter().position(|&i| i == month)? as u8 + 1;
+    let day = match day.parse::<u8>() {
+        Ok(day) if (1..=days_in_month(month, year)).contains(&day) => day,
+        _ => return None,
+    };
+
+    let mut time = time.split(':').map(|i| match i.len() {
+        2 => i.parse::<u64>().ok(),
+        _ => None,
+    });
+    let (hours, minutes, seconds) = (time.next()??, time.next()??, time.next()??);
+    // 60 is allowed for leap seconds
+    if time.next().is_some() || hours > 23 || minutes > 59 || seconds > 60 {
+        return None;
+    }
+
+    Some(join_date(year, month, day)? * 86400 + hours * 3600 + minutes * 60 + seconds)
+}
+
+/// Returns the date in the format used by the Common Log Format, in UTC.
+/// Example: `06/Nov/1994:08:49:37 +0000`
+pub fn clf_date(epoch: u64) -> String {
+    let seconds = epoch % 60;
+    let minutes = (epoch / 60) % 60;
+    let hours = (epoch / 3600) % 24;
+    let (year, month, day, _) = split_date(epoch);
+
+    format!(
+        "{:02}/{}/{}:{:02}:{:02}:{:02} +0000",
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        hours,
+        minutes,
+        seconds
+    )
+}
+
+/// Returns the date in the RFC 3339 format, in UTC.
+/// Example: `1994-11-06T08:49:37Z`
+pub fn rfc3339_date(epoch: u64) -> String {
+    let seconds = epoch % 60;
+    let minutes = (epoch / 60) % 60;
+    let hours = (epoch / 3600) % 24;
+    let (year, month, day, _) = split_date(epoch);
+
+    format!(
+        "{}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year, month, day, hours, minutes, seconds
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::{clf_date, imp_date, join_date, parse_date, rfc3339_date, split_date};
+
+    #[test]
+    fn test_epoch() {
+        assert_eq!(imp_date(0), "Thu, 01 Jan 1970 00:00:00 GMT");
+        assert_eq!(imp_date(123456), "Fri, 02 Jan 1970 10:17:36 GMT");
+        assert_eq!(imp_date(1675899597), "Wed, 08 Feb 2023 23:39:57 GMT");
+    }
+
+    #[test]
+    fn test_leap_years() {
+        assert_eq!(imp_date(951782400), "Tue, 29 Feb 2000 00:00:00 GMT");
+        assert_eq!(imp_date(4107542400), "Mon, 01 Mar 2100 00:00:00 GMT");
+        // Past where a u16 day count would overflow
+        assert_eq!(imp_date(5661792000), "Sun, 01 Jun 2149 00:00:00 GMT");
+
+        for days in (0..200_000).step_by(7) {
+            let (year, month, day, _) = split_date(days * 86400);
+            assert_eq!(join_date(year, month, day), Some(days));
+        }
+    }
+
+    #[test]
+    fn test_parse_date() {
+        for i in [0, 951782400, 1675899597, 4107542400] {
+            assert_eq!(parse_date(&imp_date(i)), Some(i));
+        }
+
+        assert_eq!(
+            parse_date("Wednesday, 08-Feb-23 23:39:57 GMT"),
+            Some(1675899597)
+        );
+        assert_eq!(parse_date("Wed Feb  8 23:39:57 2023"), Some(1675899597));
+
+        for i in [
+            "",
+            "Wed, 08 Feb 2023 23:39:57",
+            "Wed, 08 Feb 2023 23:39:57 UTC",
+            "Wed, 8 Feb 2023 23:39:57 GMT",
+            "Wed, 29 Feb 2023 23:39:57 GMT",
+            "Wed, 08 Fev 2023 23:39:57 GMT",
+            "Wed, 08 Feb 2023 24:00:00 GMT",
+            "Wed, 08 Feb 2023 23:39 GMT",
+            "Wed, 31 Dec 1969 23:59:59 GMT",
+        ] {
+            assert_eq!(parse_date(i), None, "{}", i);
+        }
+    }
+
+    #[test]
+    fn test_clf_date() {
+        assert_eq!(clf_date(0), "01/Jan/1970:00:00:00 +0000");
+        assert_eq!(clf_date(1675899597), "08/Feb/2023:23:39:57 +0000");
+    }
+
+    #[test]
+    fn test_rfc3339_date() {
+        assert_eq!(rfc3339_date(0), "1970-01-01T00:00:00Z");
+        assert_eq!(rfc3339_date(1675899597), "2023-02-08T23:39:57Z");
+    }
+}
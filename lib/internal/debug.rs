@@ -0,0 +1,154 @@
+//! The development error page, sent in place of the plain text panic response when the `dev` feature is enabled.
+//! It is only used in debug builds, so it can't leak the internals of a server in release builds even if the feature is left on.
+
+use std::{fmt::Write, rc::Rc};
+
+use crate::{
+    error::{PanicInfo, Result},
+    internal::common::xml_escape,
+    Content, Request, Response, Status, VERSION,
+};
+
+/// The most bytes of the request body shown on the page.
+const BODY_PREVIEW: usize = 4 * 1024;
+
+const STYLE: &str = "body{font-family:sans-serif;margin:0;background:#f6f6f6;color:#222}\
+header{background:#b3261e;color:#fff;padding:1em 2em}\
+header h1{margin:0;font-size:1.4em}header p{margin:.4em 0 0}\
+main{padding:1em 2em}\
+details{background:#fff;border:1px solid #ddd;border-radius:4px;margin-bottom:1em;padding:.5em 1em}\
+summary{cursor:pointer;font-weight:bold}\
+pre{white-space:pre-wrap;word-break:break-all;background:#f0f0f0;padding:.5em;overflow:auto}\
+table{border-collapse:collapse}td{border-bottom:1px solid #eee;padding:.2em 1em .2em 0;vertical-align:top}\
+td:first-child{font-weight:bold;white-space:nowrap}\
+footer{color:#777;font-size:.8em;padding:0 2em 1em}";
+
+/// Renders the error page for a panic.
+/// The request is an error if the panic happened before it could be parsed.
+pub(crate) fn error_page(req: &Result<Rc<Request>>, err: &PanicInfo) -> Response {
+    let location = match &err.route {
+        Some(route) => format!("route {} {}", route.method, route.path),
+        None => "middleware".to_owned(),
+    };
+
+    let mut html = String::new();
+    let _ = write!(
+        html,
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>Panic: {message}</title><style>{STYLE}</style></head><body>\
+        <header><h1>Panic in {location}</h1><p>{message}</p></header><main>",
+        message = xml_escape(&err.message),
+        location = xml_escape(&location),
+    );
+
+    html.push_str("<details open><summary>Request</summary>");
+    match req {
+        Ok(req) => request_details(&mut html, req),
+        Err(e) => {
+            let _ = write!(
+                html,
+                "<p>The request could not be parsed: {}</p>",
+                xml_escape(&e.to_string())
+            );
+        }
+    }
+    html.push_str("</details>");
+
+    html.push_str("<details open><summary>Backtrace</summary>");
+    match &err.backtrace {
+        Some(backtrace) => {
+            let _ = write!(html, "<pre>{}</pre>", xml_escape(&backtrace.to_string()));
+        }
+        None => html.push_str(
+            "<p>No backtrace was captured. Set the <code>RUST_BACKTRACE</code> environment variable or enable <code>Server::capture_backtraces</code> to see one.</p>",
+        ),
+    }
+    html.push_str("</details>");
+
+    let _ = write!(
+        html,
+        "</main><footer>afire/{VERSION} &mdash; this page is shown because the <code>dev</code> feature is enabled in a debug build. \
+        Release builds send the plain error response instead.</footer></body></html>"
+    );
+
+    Response::new()
+        .status(Status::InternalServerError)
+        .text(html)
+        .content(Content::HTML)
+        .header("Cache-Control", "no-store")
+}
+
+/// Writes the request line, client address, headers and a preview of the body.
+fn request_details(html: &mut String, req: &Request) {
+    let _ = write!(
+        html,
+        "<table><tr><td>Request</td><td><code>{} {}{} {}</code></td></tr><tr><td>Client</td><td>{}</td></tr>",
+        req.method,
+        xml_escape(&req.path),
+        xml_escape(&req.query.to_string()),
+        xml_escape(&req.version),
+        req.address
+    );
+
+    html.push_str("</table><h3>Headers</h3><table>");
+    for header in req.headers.iter() {
+        let _ = write!(
+            html,
+            "<tr><td>{}</td><td>{}</td></tr>",
+            xml_escape(&header.name.to_string()),
+            xml_escape(&header.value)
+        );
+    }
+    html.push_str("</table>");
+
+    if !req.body.is_empty() {
+        let end = req.body.len().min(BODY_PREVIEW);
+        let body = String::from_utf8_lossy(&req.body[..end]);
+        let _ = write!(
+            html,
+            "<h3>Body ({} bytes{})</h3><pre>{}</pre>",
+            req.body.len(),
+            if end < req.body.len() {
+                ", truncated"
+            } else {
+                ""
+            },
+            xml_escape(&body)
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::Cursor,
+        sync::{Arc, Mutex},
+    };
+
+    use crate::{
+        internal::testing::{MemoryListener, MemoryStream},
+        Method, Server,
+    };
+
+    #[test]
+    fn test_debug_error_page() {
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let listener = MemoryListener(Mutex::new(vec![MemoryStream {
+            input: Cursor::new(
+                b"POST /user/5?tab=<b> HTTP/1.1\r\nHost: localhost\r\nContent-Length: 4\r\n\r\nname".to_vec(),
+            ),
+            output: output.clone(),
+        }]));
+
+        let mut server = Server::<()>::new("localhost", 0);
+        server.route(Method::POST, "/user/{id}", |_| panic!("No <user>"));
+        assert!(server.start_with_listener(listener).is_err());
+
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        assert!(output.starts_with("HTTP/1.1 500 Internal Server Error\r\n"));
+        assert!(output.contains("Content-Type: text/html"));
+        assert!(output.contains("Panic in route POST /user/{id}"));
+        assert!(output.contains("No &lt;user&gt;"));
+        assert!(output.contains("POST /user/5?tab=%3Cb%3E HTTP/1.1"));
+        assert!(output.contains("<pre>name</pre>"));
+    }
+}
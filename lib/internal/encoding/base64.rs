@@ -7,8 +7,13 @@ const CHARS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ\
                        0123456789+/";
 
 /// Encodes a byte slice into a base64 string (with padding).
+/// ## Example
+/// ```rust
+/// # use afire::encoding::base64;
+/// assert_eq!(base64::encode(b"hello"), "aGVsbG8=");
+/// ```
 pub fn encode(inp: &[u8]) -> String {
-    let end_len = (inp.len() + 2) / 3 * 4;
+    let end_len = inp.len().div_ceil(3) * 4;
     let mut out = String::with_capacity(end_len);
 
     for i in (0..inp.len()).step_by(3) {
@@ -40,6 +45,12 @@ pub fn encode(inp: &[u8]) -> String {
 }
 
 /// Decodes a base64 string into a byte slice.
+/// Returns [`None`] if the string contains characters outside of the base64 alphabet.
+/// ## Example
+/// ```rust
+/// # use afire::encoding::base64;
+/// assert_eq!(base64::decode("aGVsbG8=").unwrap(), b"hello");
+/// ```
 pub fn decode(inp: &str) -> Option<Vec<u8>> {
     if inp.is_empty() {
         return Some(Vec::new());
@@ -56,8 +67,8 @@ pub fn decode(inp: &str) -> Option<Vec<u8>> {
                 'A'..='Z' => decode |= ((e - 65) as u32) << (6 * (3 - i)),
                 'a'..='z' => decode |= ((e - 71) as u32) << (6 * (3 - i)),
                 '0'..='9' => decode |= ((e + 4) as u32) << (6 * (3 - i)),
-                '+' => decode |= 62 << (6 * i),
-                '/' => decode |= 63 << (6 * i),
+                '+' => decode |= 62 << (6 * (3 - i)),
+                '/' => decode |= 63 << (6 * (3 - i)),
                 '=' => {
                     out.extend_from_slice(&decode.to_be_bytes()[1..i]);
                     continue 'o;
@@ -96,5 +107,7 @@ mod test {
         assert_eq!(decode("Zm9vYg==").unwrap(), b"foob");
         assert_eq!(decode("Zm9vYmE=").unwrap(), b"fooba");
         assert_eq!(decode("Zm9vYmFy").unwrap(), b"foobar");
+        assert_eq!(decode("+/+/").unwrap(), [0xfb, 0xff, 0xbf]);
+        assert_eq!(decode("Zm9v!"), None);
     }
 }
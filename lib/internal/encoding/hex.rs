@@ -0,0 +1,65 @@
+//! Hex encoding and decoding.
+
+/// Encodes a byte slice into a lowercase hex string.
+/// ## Example
+/// ```rust
+/// # use afire::encoding::hex;
+/// assert_eq!(hex::encode(&[0xde, 0xad, 0xbe, 0xef]), "deadbeef");
+/// ```
+pub fn encode(inp: &[u8]) -> String {
+    const CHARS: &[u8] = b"0123456789abcdef";
+
+    let mut out = String::with_capacity(inp.len() * 2);
+    for i in inp {
+        out.push(CHARS[(i >> 4) as usize] as char);
+        out.push(CHARS[(i & 0x0f) as usize] as char);
+    }
+
+    out
+}
+
+/// Decodes a hex string (upper or lower case) into bytes.
+/// If the string has an odd length or contains non hex characters, [`None`] is returned.
+/// ## Example
+/// ```rust
+/// # use afire::encoding::hex;
+/// assert_eq!(hex::decode("DEADbeef").unwrap(), [0xde, 0xad, 0xbe, 0xef]);
+/// ```
+pub fn decode(inp: &str) -> Option<Vec<u8>> {
+    if !inp.len().is_multiple_of(2) {
+        return None;
+    }
+
+    inp.as_bytes()
+        .chunks(2)
+        .map(|i| Some((digit(i[0])? << 4) | digit(i[1])?))
+        .collect()
+}
+
+fn digit(chr: u8) -> Option<u8> {
+    match chr {
+        b'0'..=b'9' => Some(chr - b'0'),
+        b'a'..=b'f' => Some(chr - b'a' + 10),
+        b'A'..=b'F' => Some(chr - b'A' + 10),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decode, encode};
+
+    #[test]
+    fn test_hex_encode() {
+        assert_eq!(encode(b""), "");
+        assert_eq!(encode(&[0x00, 0x0f, 0xf0, 0xff]), "000ff0ff");
+    }
+
+    #[test]
+    fn test_hex_decode() {
+        assert_eq!(decode("").unwrap(), b"");
+        assert_eq!(decode("000ff0FF").unwrap(), [0x00, 0x0f, 0xf0, 0xff]);
+        assert_eq!(decode("abc"), None);
+        assert_eq!(decode("zz"), None);
+    }
+}
@@ -1,5 +1,7 @@
 //! Functions having to do with encoding, decoding, and hashing data.
 
 pub mod base64;
+pub mod hex;
 pub mod sha1;
+pub mod sha256;
 pub mod url;
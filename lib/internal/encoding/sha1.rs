@@ -2,79 +2,143 @@
 //!
 //! NOTE: This is not a secure hash function, and should not be used unless necessary.
 
+use std::io::{self, Write};
+
+/// The size of the blocks the hash function works on, in bytes.
+const BLOCK_SIZE: usize = 64;
+
 /// SHA1 hash function.
 ///
 /// NOTE: This is not a secure hash function, and should not be used unless necessary.
+/// ## Example
+/// ```rust
+/// # use afire::encoding::{hex, sha1};
+/// let hash = sha1::hash(b"hello world");
+/// assert_eq!(hex::encode(&hash), "2aae6c35c94fcfb415dbe95f408b9ce91ee846ed");
+/// ```
 pub fn hash(message: &[u8]) -> [u8; 20] {
-    let mut h0 = 0x67452301u32;
-    let mut h1 = 0xefcdab89u32;
-    let mut h2 = 0x98badcfeu32;
-    let mut h3 = 0x10325476u32;
-    let mut h4 = 0xc3d2e1f0u32;
-
-    let msg_len = ((message.len() * 8 + 583) / 512) * 64;
-    let mut padded_message = vec![0u8; msg_len];
-    padded_message[..message.len()].copy_from_slice(message);
-    padded_message[message.len()] = 0x80;
-    padded_message[msg_len - 8..].copy_from_slice(&(8 * message.len() as u64).to_be_bytes());
-
-    for chunk in padded_message.chunks(64) {
-        let mut w = [0u32; 80];
-        for i in 0..16 {
-            w[i] = u32::from_be_bytes([
-                chunk[i * 4],
-                chunk[i * 4 + 1],
-                chunk[i * 4 + 2],
-                chunk[i * 4 + 3],
-            ]);
+    let mut hasher = Sha1::new();
+    hasher.update(message);
+    hasher.finalize()
+}
+
+/// A streaming SHA1 hasher, for hashing data that is not all in memory at once.
+/// It also implements [`Write`], so it can be used with [`std::io::copy`].
+///
+/// NOTE: This is not a secure hash function, and should not be used unless necessary.
+/// ## Example
+/// ```rust
+/// # use afire::encoding::sha1::{self, Sha1};
+/// let mut hasher = Sha1::new();
+/// hasher.update(b"hello ");
+/// hasher.update(b"world");
+/// assert_eq!(hasher.finalize(), sha1::hash(b"hello world"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Sha1 {
+    state: [u32; 5],
+    block: [u8; BLOCK_SIZE],
+    block_len: usize,
+    len: u64,
+}
+
+impl Sha1 {
+    /// Create a new hasher.
+    pub fn new() -> Self {
+        Self {
+            state: [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0],
+            block: [0; BLOCK_SIZE],
+            block_len: 0,
+            len: 0,
         }
-        for i in 16..80 {
-            w[i] = w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16];
-            w[i] = w[i].rotate_left(1);
+    }
+
+    /// Add data to the hash.
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.len += data.len() as u64;
+
+        while !data.is_empty() {
+            let take = (BLOCK_SIZE - self.block_len).min(data.len());
+            self.block[self.block_len..self.block_len + take].copy_from_slice(&data[..take]);
+            self.block_len += take;
+            data = &data[take..];
+
+            if self.block_len == BLOCK_SIZE {
+                compress(&mut self.state, &self.block);
+                self.block_len = 0;
+            }
         }
+    }
 
-        let mut a = h0;
-        let mut b = h1;
-        let mut c = h2;
-        let mut d = h3;
-        let mut e = h4;
-
-        for (i, ele) in w.iter().enumerate() {
-            let (f, k) = match i {
-                0..=19 => ((b & c) | ((!b) & d), 0x5a827999u32),
-                20..=39 => (b ^ c ^ d, 0x6ED9EBA1u32),
-                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdcu32),
-                60..=79 => (b ^ c ^ d, 0xca62c1d6u32),
-                _ => unreachable!(),
-            };
-            let temp = a
-                .rotate_left(5)
-                .wrapping_add(f)
-                .wrapping_add(e)
-                .wrapping_add(k)
-                .wrapping_add(*ele);
-            e = d;
-            d = c;
-            c = b.rotate_left(30);
-            b = a;
-            a = temp;
+    /// Pad the remaining data and return the hash.
+    pub fn finalize(mut self) -> [u8; 20] {
+        let bits = self.len * 8;
+        self.update(&[0x80]);
+        while self.block_len != BLOCK_SIZE - 8 {
+            self.update(&[0]);
         }
+        self.update(&bits.to_be_bytes());
 
-        h0 = h0.wrapping_add(a);
-        h1 = h1.wrapping_add(b);
-        h2 = h2.wrapping_add(c);
-        h3 = h3.wrapping_add(d);
-        h4 = h4.wrapping_add(e);
+        let mut out = [0u8; 20];
+        for (i, h) in self.state.iter().enumerate() {
+            out[i * 4..i * 4 + 4].copy_from_slice(&h.to_be_bytes());
+        }
+        out
     }
+}
 
-    let mut out = [0u8; 20];
-    out[0..4].copy_from_slice(&h0.to_be_bytes());
-    out[4..8].copy_from_slice(&h1.to_be_bytes());
-    out[8..12].copy_from_slice(&h2.to_be_bytes());
-    out[12..16].copy_from_slice(&h3.to_be_bytes());
-    out[16..20].copy_from_slice(&h4.to_be_bytes());
+impl Default for Sha1 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Write for Sha1 {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
 
-    out
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Process one block of the message.
+fn compress(state: &mut [u32; 5], chunk: &[u8; BLOCK_SIZE]) {
+    let mut w = [0u32; 80];
+    for (i, word) in chunk.chunks(4).enumerate() {
+        w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+    }
+    for i in 16..80 {
+        w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+    }
+
+    let [mut a, mut b, mut c, mut d, mut e] = *state;
+    for (i, ele) in w.iter().enumerate() {
+        let (f, k) = match i {
+            0..=19 => ((b & c) | ((!b) & d), 0x5a827999u32),
+            20..=39 => (b ^ c ^ d, 0x6ED9EBA1u32),
+            40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdcu32),
+            60..=79 => (b ^ c ^ d, 0xca62c1d6u32),
+            _ => unreachable!(),
+        };
+        let temp = a
+            .rotate_left(5)
+            .wrapping_add(f)
+            .wrapping_add(e)
+            .wrapping_add(k)
+            .wrapping_add(*ele);
+        e = d;
+        d = c;
+        c = b.rotate_left(30);
+        b = a;
+        a = temp;
+    }
+
+    for (h, x) in state.iter_mut().zip([a, b, c, d, e]) {
+        *h = h.wrapping_add(x);
+    }
 }
 
 #[cfg(test)]
@@ -103,6 +167,25 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_hash_streaming() {
+        let data = [0x61; 1000];
+        for split in [0, 1, 55, 56, 63, 64, 65, 999] {
+            let mut hasher = Sha1::new();
+            hasher.update(&data[..split]);
+            hasher.update(&data[split..]);
+            assert_eq!(hasher.finalize(), hash(&data));
+        }
+
+        assert_eq!(
+            hash(&data[..56]),
+            [
+                0xc2, 0xdb, 0x33, 0x0f, 0x60, 0x83, 0x85, 0x4c, 0x99, 0xd4, 0xb5, 0xbf, 0xb6, 0xe8,
+                0xf2, 0x9f, 0x20, 0x1b, 0xe6, 0x99
+            ]
+        );
+    }
+
     #[test]
     fn test_ws_has() {
         assert_eq!(
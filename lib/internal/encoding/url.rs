@@ -1,32 +1,91 @@
-//! Utilities for encoding and decoding strings.
-//! Currently only supports url encoding.
+//! Utilities for percent encoding and decoding strings.
+//! Also available as [`afire::encoding::url`](crate::encoding::url).
 
-/// Decode a url encoded string.
+/// Decode a url encoded string, like a query string or form body.
 /// Supports `+` and `%` encoding.
 /// If the decode fails for any reason, [`None`] is returned.
+/// ## Example
+/// ```rust
+/// # use afire::encoding::url;
+/// assert_eq!(url::decode("hello+world%21").unwrap(), "hello world!");
+/// ```
 pub fn decode(url: &str) -> Option<String> {
-    let mut chars = url.chars();
+    decode_inner(url, true)
+}
+
+/// Decode a percent encoded path segment.
+/// Unlike [`decode`], `+` is left as is, because it has no special meaning in paths.
+/// If the decode fails for any reason, [`None`] is returned.
+/// ## Example
+/// ```rust
+/// # use afire::encoding::url;
+/// assert_eq!(url::decode_path("my%20doc+1.txt").unwrap(), "my doc+1.txt");
+/// ```
+pub fn decode_path(path: &str) -> Option<String> {
+    decode_inner(path, false)
+}
+
+/// Normalizes the percent encoding of a url (RFC 3986, section 6.2.2).
+/// Encoded unreserved characters (`A-Z`, `a-z`, `0-9`, `-`, `.`, `_` and `~`) are decoded, and other escapes are made uppercase.
+/// Reserved characters like `%2F` stay encoded as decoding them would change the meaning of the url, and invalid escapes are left as is.
+/// ## Example
+/// ```rust
+/// # use afire::encoding::url;
+/// assert_eq!(url::normalize("/%7euser/a%2fb"), "/~user/a%2Fb");
+/// ```
+pub fn normalize(url: &str) -> String {
+    let bytes = url.as_bytes();
     let mut out = String::with_capacity(url.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .filter(|hex| bytes[i] == b'%' && hex.iter().all(u8::is_ascii_hexdigit));
+        let Some(byte) =
+            hex.and_then(|i| u8::from_str_radix(std::str::from_utf8(i).ok()?, 16).ok())
+        else {
+            let len = url[i..].chars().next().map_or(1, char::len_utf8);
+            out.push_str(&url[i..i + len]);
+            i += len;
+            continue;
+        };
 
-    while let Some(i) = chars.next() {
+        match byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            true => out.push(byte as char),
+            false => out.push_str(&format!("%{byte:02X}")),
+        }
+        i += 3;
+    }
+
+    out
+}
+
+fn decode_inner(url: &str, plus_space: bool) -> Option<String> {
+    let mut bytes = url.bytes();
+    let mut out = Vec::with_capacity(url.len());
+
+    while let Some(i) = bytes.next() {
         match i {
-            '+' => out.push(' '),
-            '%' => {
-                let mut hex = String::new();
-                hex.push(chars.next()?);
-                hex.push(chars.next()?);
-                out.push(u8::from_str_radix(&hex, 16).ok()? as char);
+            b'+' if plus_space => out.push(b' '),
+            b'%' => {
+                let hex = [bytes.next()?, bytes.next()?];
+                out.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
             }
             _ => out.push(i),
         }
     }
 
-    Some(out)
+    String::from_utf8(out).ok()
 }
 
 /// Encodes a string with url encoding.
 /// Uses `%20` for spaces not `+`.
 /// Allowed characters are `A-Z`, `a-z`, `0-9`, `-`, `.`, `_` and `~`.
+/// ## Example
+/// ```rust
+/// # use afire::encoding::url;
+/// assert_eq!(url::encode("my doc.txt"), "my%20doc.txt");
+/// ```
 pub fn encode(url: &str) -> String {
     const ALLOWED_CHARS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ\
                                    abcdefghijklmnopqrstuvwxyz\
@@ -34,12 +93,12 @@ pub fn encode(url: &str) -> String {
 
     let mut out = String::with_capacity(url.len());
 
-    for i in url.chars() {
-        if i.is_ascii() && ALLOWED_CHARS.contains(&(i as u8)) {
-            out.push(i);
+    for &i in url.as_bytes() {
+        if ALLOWED_CHARS.contains(&i) {
+            out.push(i as char);
             continue;
         }
-        out.push_str(&format!("%{:02X}", i as u8));
+        out.push_str(&format!("%{i:02X}"));
     }
 
     out
@@ -47,7 +106,7 @@ pub fn encode(url: &str) -> String {
 
 #[cfg(test)]
 mod test {
-    use super::{decode, encode};
+    use super::{decode, decode_path, encode, normalize};
 
     #[test]
     fn test_url_decode() {
@@ -57,6 +116,16 @@ mod test {
             decode("%3C%3E%22%23%25%7B%7D%7C%5C%5E~%5B%5D%60").unwrap(),
             "<>\"#%{}|\\^~[]`"
         );
+        assert_eq!(decode("caf%C3%A9").unwrap(), "café");
+        assert_eq!(decode("%FF"), None);
+    }
+
+    #[test]
+    fn test_url_decode_path() {
+        assert_eq!(decode_path("my%20doc.txt").unwrap(), "my doc.txt");
+        assert_eq!(decode_path("a+b").unwrap(), "a+b");
+        assert_eq!(decode_path("%2F"), Some("/".to_owned()));
+        assert_eq!(decode_path("%2"), None);
     }
 
     #[test]
@@ -66,6 +135,14 @@ mod test {
         assert_eq!(decode("hello%20world%2G"), None);
     }
 
+    #[test]
+    fn test_url_normalize() {
+        assert_eq!(normalize("/%41%62c/%2e%2E/%7E"), "/Abc/../~");
+        assert_eq!(normalize("/a%2fb%3F%20"), "/a%2Fb%3F%20");
+        assert_eq!(normalize("/caf%c3%a9/café"), "/caf%C3%A9/café");
+        assert_eq!(normalize("/%2G/%+1/%"), "/%2G/%+1/%");
+    }
+
     #[test]
     fn test_url_encode() {
         assert_eq!(encode("hello world"), "hello%20world");
@@ -74,5 +151,6 @@ mod test {
             encode("<>\"#%{}|\\^~[]`"),
             "%3C%3E%22%23%25%7B%7D%7C%5C%5E~%5B%5D%60"
         );
+        assert_eq!(encode("café"), "caf%C3%A9");
     }
 }
@@ -0,0 +1,303 @@
+//! A non-blocking event loop for managing connections.
+//!
+//! With the normal threaded model, every open connection pins a worker thread, even if it is just idling between keep-alive requests.
+//! Here, idle connections are instead parked in a [poll(2)](https://man7.org/linux/man-pages/man2/poll.2.html) set on the accept thread,
+//! and only connections that have data waiting to be read are passed to the thread pool.
+//! This lets thousands of mostly-idle connections be multiplexed over a handful of threads.
+
+use std::{
+    io::{self, Read, Write},
+    net::{Shutdown, TcpListener, TcpStream},
+    os::{
+        raw::{c_int, c_short},
+        unix::{
+            io::{AsRawFd, RawFd},
+            net::UnixStream,
+        },
+    },
+    sync::{mpsc, Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    error::Result,
+    internal::{
+        common::ForceLock,
+        handle::{
+            configure_socket, handle_request, log_socket_error, queue_timed_out, reject_connection,
+            reject_queued, reserve_connection, ConnectionGuard, ConnectionState,
+        },
+        scheduler::Scheduler,
+    },
+    listener::Stream,
+    thread_pool::ThreadPool,
+    trace, Server,
+};
+
+#[cfg(feature = "tracing")]
+use crate::trace::emoji;
+
+/// There is data to read.
+const POLLIN: c_short = 0x001;
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+type NfdsT = std::os::raw::c_ulong;
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+type NfdsT = std::os::raw::c_uint;
+
+/// The `pollfd` struct from `poll.h`.
+#[repr(C)]
+struct PollFd {
+    fd: c_int,
+    events: c_short,
+    revents: c_short,
+}
+
+extern "C" {
+    fn poll(fds: *mut PollFd, nfds: NfdsT, timeout: c_int) -> c_int;
+}
+
+/// A connection that is waiting for its next request.
+struct Connection {
+    /// The socket.
+    stream: Arc<Mutex<Box<dyn Stream>>>,
+    /// The raw file descriptor of the socket, so the mutex doesn't need to be locked to poll it.
+    fd: RawFd,
+    /// The requests handled on this connection and any pipelined data that has already been read.
+    state: ConnectionState,
+    /// When the last request on this connection finished (or when it was accepted).
+    last_active: Instant,
+    /// Releases the connection slot once the connection is dropped.
+    _guard: ConnectionGuard,
+}
+
+impl PollFd {
+    fn new(fd: RawFd) -> Self {
+        Self {
+            fd,
+            events: POLLIN,
+            revents: 0,
+        }
+    }
+
+    /// Checks if the socket is readable, or has been closed / errored (which will be found out by reading it).
+    fn ready(&self) -> bool {
+        self.revents != 0
+    }
+}
+
+impl Connection {
+    fn new(stream: TcpStream, guard: ConnectionGuard) -> Self {
+        Self {
+            fd: stream.as_raw_fd(),
+            state: ConnectionState::new(&stream),
+            stream: Arc::new(Mutex::new(Box::new(stream))),
+            last_active: Instant::now(),
+            _guard: guard,
+        }
+    }
+
+    /// Gets the time remaining before this connection will be closed for being idle.
+    fn remaining(&self, timeout: Duration) -> Duration {
+        timeout.saturating_sub(self.last_active.elapsed())
+    }
+
+    fn close(&self) {
+        if let Err(e) = self.stream.force_lock().shutdown(Shutdown::Both) {
+            log_socket_error("closing", &e);
+        }
+    }
+}
+
+/// Runs the event loop.
+/// Accepts new connections and waits for parked connections to become readable, sending them to the thread pool to be handled.
+/// Once a request has been handled, if the connection is still alive it will be sent back to be parked again.
+pub(crate) fn run<State>(
+    listener: TcpListener,
+    this: Arc<Server<State>>,
+    pool: Arc<ThreadPool>,
+    scheduler: Scheduler,
+) -> Result<()>
+where
+    State: 'static + Send + Sync,
+{
+    // Used to wake up the poll when workers return a connection.
+    let (mut waker_rx, waker_tx) = UnixStream::pair()?;
+    waker_rx.set_nonblocking(true)?;
+    let waker_tx = Arc::new(waker_tx);
+
+    let (tx, rx) = mpsc::channel::<Connection>();
+    let mut parked = Vec::<Connection>::new();
+
+    loop {
+        parked.extend(rx.try_iter());
+
+        // Close connections that have been idle for too long
+        if let Some(timeout) = this.keep_alive_timeout {
+            parked.retain(|i| {
+                let alive = i.remaining(timeout) > Duration::ZERO;
+                if !alive {
+                    trace!(Level::Debug, "Closing idle keep-alive socket");
+                    i.close();
+                }
+                alive
+            });
+        }
+
+        let mut fds = Vec::with_capacity(parked.len() + 2);
+        fds.push(PollFd::new(listener.as_raw_fd()));
+        fds.push(PollFd::new(waker_rx.as_raw_fd()));
+        fds.extend(parked.iter().map(|i| PollFd::new(i.fd)));
+
+        let timeout = match this.keep_alive_timeout {
+            Some(timeout) => parked
+                .iter()
+                .map(|i| i.remaining(timeout).as_millis().min(c_int::MAX as u128 - 1) as c_int + 1)
+                .min()
+                .unwrap_or(-1),
+            None => -1,
+        };
+
+        if let Err(e) = poll_fds(&mut fds, timeout) {
+            if e.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(e.into());
+        }
+
+        // Drain the waker
+        if fds[1].ready() {
+            while let Ok(1..) = waker_rx.read(&mut [0; 64]) {}
+        }
+
+        if this.shutdown.is_shutdown() {
+            parked.iter().for_each(Connection::close);
+            break;
+        }
+
+        // Send the connections with pending data to the thread pool.
+        // Going in reverse so swap_remove only moves connections that have already been checked.
+        for i in (0..parked.len()).rev() {
+            if !fds[i + 2].ready() {
+                continue;
+            }
+
+            let mut connection = parked.swap_remove(i);
+            let (this, tx, waker) = (this.clone(), tx.clone(), waker_tx.clone());
+            let queued = Instant::now();
+            pool.execute(move || {
+                if queue_timed_out(queued, &this) {
+                    reject_queued(connection.stream.clone(), &this);
+                    return;
+                }
+
+                // Pipelined requests that have already been read won't make the socket readable, so they are handled here
+                loop {
+                    if !handle_request(&connection.stream, &this, &mut connection.state) {
+                        return;
+                    }
+
+                    if connection.state.pending.is_empty() {
+                        break;
+                    }
+                }
+
+                connection.last_active = Instant::now();
+                if tx.send(connection).is_ok() {
+                    let _ = (&*waker).write(&[1]);
+                }
+            });
+        }
+
+        // New connections are parked until they have sent something
+        if fds[0].ready() {
+            match listener.accept() {
+                Ok((stream, _)) => {
+                    trace!(Level::Debug, "Opening socket {:?}", stream.peer_addr());
+                    let guard = match reserve_connection(&stream, &this) {
+                        Ok(i) => i,
+                        Err(limit) => {
+                            reject_connection(Box::new(stream), limit, &this);
+                            continue;
+                        }
+                    };
+
+                    match configure_socket(&stream, &this) {
+                        Ok(()) => parked.push(Connection::new(stream, guard)),
+                        Err(e) => log_socket_error("configuring", &e),
+                    }
+                }
+                Err(_e) => {
+                    trace!(Level::Error, "Error accepting socket: {:?}", _e);
+                }
+            }
+        }
+    }
+
+    // Stop the background tasks and wait for the requests that are being handled to finish
+    drop(scheduler);
+    drop(pool);
+    this.run_on_shutdown();
+    trace!("{}Server stopped", emoji("🛑"));
+    Ok(())
+}
+
+/// Safe wrapper around poll(2).
+fn poll_fds(fds: &mut [PollFd], timeout: c_int) -> io::Result<()> {
+    // SAFETY: `fds` is a valid slice of `pollfd` structs, and its length is passed along with it.
+    let res = unsafe { poll(fds.as_mut_ptr(), fds.len() as NfdsT, timeout) };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::{Read, Write},
+        thread,
+        time::Duration,
+    };
+
+    use crate::{Method, Response, Server};
+
+    #[test]
+    fn test_event_loop_pipelining_framing_error() {
+        use std::net::TcpStream;
+
+        let mut server = Server::<()>::new("127.0.0.1", 0).max_body_size(10);
+        server.route(Method::ANY, "**", |req| Response::new().text(&req.path));
+        let shutdown = server.shutdown_handle();
+        let handle = thread::spawn(move || server.start_event_loop(2).is_ok());
+        let addr = loop {
+            match shutdown.local_addr() {
+                Some(addr) => break addr,
+                None => thread::sleep(Duration::from_millis(10)),
+            }
+        };
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        stream
+            .write_all(b"GET /a HTTP/1.1\r\nHost: localhost\r\n\r\nPOST /b HTTP/1.1\r\nHost: localhost\r\nContent-Length: 20\r\n\r\nGET /c HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut output = String::new();
+        stream.read_to_string(&mut output).unwrap();
+
+        let statuses = output
+            .split("HTTP/1.1 ")
+            .skip(1)
+            .map(|i| &i[..3])
+            .collect::<Vec<_>>();
+        assert_eq!(statuses, ["200", "413"]);
+        assert!(!output.contains("/c"));
+
+        drop(stream);
+        shutdown.shutdown();
+        assert!(handle.join().unwrap());
+    }
+}
@@ -1,185 +1,1136 @@
 use std::{
-    cell::RefCell,
-    io::Read,
-    net::{Shutdown, TcpStream},
+    backtrace::{Backtrace, BacktraceStatus},
+    cell::{Cell, RefCell},
+    io::{self, Read},
+    net::{IpAddr, Shutdown},
     ops::Deref,
-    panic,
+    panic, process,
     rc::Rc,
-    sync::{Arc, Mutex},
+    result,
+    sync::{atomic::Ordering, Arc, Mutex, Once},
+    thread,
+    time::{Duration, Instant},
 };
 
 use crate::{
-    error::{HandleError, ParseError, Result, StreamError},
-    internal::common::any_string,
+    body_filter, consts,
+    error::{HandleError, PanicInfo, ParseError, Result, StreamError},
+    internal::{
+        common::{any_string, is_disconnect, ForceLock},
+        encoding::url,
+        parser::HeadLimits,
+        path::resolve_dots,
+        reaper,
+    },
+    listener::Stream,
     middleware::MiddleResult,
+    request::{BodyOptions, ConnectionInfo, RequestLimits},
     response::ResponseFlag,
-    route::RouteType,
-    trace, Content, Error, Request, Response, Server, Status,
+    route::{Route, RouteType, TrailingSlash},
+    server::{ConnectionCounters, ConnectionRejection},
+    timing::Phase,
+    trace, Content, Context, Error, HeaderType, Method, PanicPolicy, Request, Response, Server,
+    Status,
 };
 
+#[cfg(feature = "http2")]
+use crate::internal::http2;
+
 pub(crate) type Writeable = Box<RefCell<dyn Read + Send>>;
 
 // https://open.spotify.com/track/50txng2W8C9SycOXKIQP0D
 
-/// - Manages keep-alive sockets
-/// - Lets Request::from_socket read the request
-/// - Lets Response::write write the response to the socket
-/// - Runs End Middleware
-/// - Optionally closes the socket
-pub(crate) fn handle<State>(stream: TcpStream, this: &Server<State>)
+/// - Manages keep-alive sockets (idle timeout and request limit)
+/// - Handles each request with [`handle_request`]
+pub(crate) fn handle<State>(stream: Box<dyn Stream>, this: &Server<State>)
 where
     State: 'static + Send + Sync,
 {
     trace!(Level::Debug, "Opening socket {:?}", stream.peer_addr());
-    stream.set_read_timeout(this.socket_timeout).unwrap();
-    stream.set_write_timeout(this.socket_timeout).unwrap();
+    if let Err(e) = configure_socket(&*stream, this) {
+        log_socket_error("configuring", &e);
+        return;
+    }
+
+    let mut connection = ConnectionState::new(&*stream);
+    let tracked = reaper::track(&*stream, &connection.info, this);
     let stream = Arc::new(Mutex::new(stream));
     loop {
-        let mut keep_alive = false;
-        let req = Request::from_socket(stream.clone());
+        // Pipelined requests that have already been read don't need to be waited for
+        if connection.info.requests() > 0
+            && (this.shutdown.is_shutdown()
+                || (connection.pending.is_empty() && !wait_for_request(&stream, this)))
+        {
+            trace!(Level::Debug, "Closing idle keep-alive socket");
+            break;
+        }
+
+        if !handle_request(&stream, this, &mut connection) {
+            break;
+        }
+
+        if tracked.is_some() {
+            reaper::set_idle(&connection.info, Some(this.clock.instant()));
+        }
+    }
+}
+
+/// The state of a connection that is kept between its requests.
+#[derive(Debug)]
+pub(crate) struct ConnectionState {
+    /// Information about the connection, shared with its requests.
+    pub info: Arc<ConnectionInfo>,
+    /// Bytes that were received after the end of the last request.
+    /// When a client pipelines requests, this is the start of the next one.
+    pub pending: Vec<u8>,
+}
+
+impl ConnectionState {
+    pub(crate) fn new(stream: &dyn Stream) -> Self {
+        Self {
+            info: Arc::new(ConnectionInfo::new(stream.peer_credentials())),
+            pending: Vec::new(),
+        }
+    }
+}
+
+/// Keeps track of an open connection, used to enforce [`Server::max_connections`] and [`Server::max_connections_per_ip`].
+/// The connection is released when this is dropped.
+pub(crate) struct ConnectionGuard {
+    counters: Arc<ConnectionCounters>,
+    /// The address of the client, if connections per IP are being counted.
+    ip: Option<IpAddr>,
+}
+
+/// The connection limit that a new connection was over, see [`reserve_connection`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ConnectionLimit {
+    /// [`Server::max_connections`]
+    Global,
+    /// [`Server::max_connections_per_ip`]
+    PerIp,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.counters.open.fetch_sub(1, Ordering::AcqRel);
+        if let Some(ip) = self.ip {
+            let mut per_ip = self.counters.per_ip.force_lock();
+            if let Some(count) = per_ip.get_mut(&ip) {
+                *count -= 1;
+                if *count == 0 {
+                    per_ip.remove(&ip);
+                }
+            }
+        }
+    }
+}
+
+/// Tries to reserve a slot for a newly accepted connection from `stream`.
+/// Returns the limit that was reached if the server is already at its global or per IP connection limit, in which case the connection should be passed to [`reject_connection`].
+pub(crate) fn reserve_connection<State>(
+    stream: &dyn Stream,
+    this: &Server<State>,
+) -> result::Result<ConnectionGuard, ConnectionLimit>
+where
+    State: 'static + Send + Sync,
+{
+    let max = this.max_connections.unwrap_or(usize::MAX);
+    let counters = this.connection_stats.counters();
+    counters
+        .open
+        .fetch_update(Ordering::AcqRel, Ordering::Acquire, |i| {
+            (i < max).then(|| i + 1)
+        })
+        .map_err(|_| ConnectionLimit::Global)?;
+
+    // Connections without a peer address (like from some custom listeners) are only counted globally
+    let ip = match (this.max_connections_per_ip, stream.peer_addr()) {
+        (Some(max), Ok(addr)) => {
+            let mut per_ip = counters.per_ip.force_lock();
+            let count = per_ip.entry(addr.ip()).or_insert(0);
+            if *count >= max {
+                drop(per_ip);
+                counters.open.fetch_sub(1, Ordering::AcqRel);
+                return Err(ConnectionLimit::PerIp);
+            }
+
+            *count += 1;
+            Some(addr.ip())
+        }
+        _ => None,
+    };
+
+    Ok(ConnectionGuard {
+        counters: counters.clone(),
+        ip,
+    })
+}
+
+/// Handles an error from accepting a connection.
+/// Errors that only affect that connection or are temporary are logged, and the server keeps accepting connections after a short pause.
+/// Any other error means the listener is broken, so it is returned to stop the server.
+pub(crate) fn accept_error(error: io::Error) -> Result<()> {
+    if !is_transient_accept_error(&error) {
+        return Err(error.into());
+    }
+
+    trace!(Level::Error, "Error accepting socket: {:?}", error);
+    // Running out of file descriptors doesn't go away until other connections close, so don't spin on it
+    if error.kind() != io::ErrorKind::Interrupted {
+        thread::sleep(Duration::from_millis(10));
+    }
+    Ok(())
+}
+
+/// Checks if an error from accepting a connection only affects that connection, or is temporary.
+/// This includes connections that were reset before they were accepted, interrupted calls and running out of file descriptors (EMFILE and ENFILE), which goes away once other connections are closed.
+fn is_transient_accept_error(error: &io::Error) -> bool {
+    #[cfg(unix)]
+    const FD_LIMIT: [i32; 2] = [23, 24];
+    #[cfg(windows)]
+    const FD_LIMIT: [i32; 2] = [10024, 10024];
+    #[cfg(not(any(unix, windows)))]
+    const FD_LIMIT: [i32; 0] = [];
+
+    matches!(
+        error.kind(),
+        io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::Interrupted
+            | io::ErrorKind::WouldBlock
+            | io::ErrorKind::TimedOut
+    ) || error.raw_os_error().is_some_and(|i| FD_LIMIT.contains(&i))
+}
+
+/// Rejects a connection that is over one of the server's connection limits, see [`Server::connection_rejection`].
+/// Unless the server is set to close them right away, connections over the global limit are sent a `503 Service Unavailable` response and connections over the per IP limit are sent a `429 Too Many Requests`.
+///
+/// This runs on the thread accepting connections, so the connection is closed right after the response is written instead of waiting for the client with [`close_socket`].
+/// Otherwise clients that never close their side could stop new connections from being accepted.
+pub(crate) fn reject_connection<State>(
+    stream: Box<dyn Stream>,
+    limit: ConnectionLimit,
+    this: &Server<State>,
+) where
+    State: 'static + Send + Sync,
+{
+    trace!(
+        Level::Debug,
+        "Connection limit ({:?}) reached, rejecting {:?}",
+        limit,
+        stream.peer_addr()
+    );
+    let counters = this.connection_stats.counters();
+    counters.rejected.fetch_add(1, Ordering::Relaxed);
+
+    let stream = Arc::new(Mutex::new(stream));
+    match (this.connection_rejection, limit) {
+        (ConnectionRejection::Close, _) => {
+            if let Err(e) = stream.force_lock().shutdown(Shutdown::Both) {
+                log_socket_error("closing", &e);
+            }
+        }
+        (ConnectionRejection::Respond, ConnectionLimit::Global) => {
+            send_unavailable(stream, None, false, this)
+        }
+        (ConnectionRejection::Respond, ConnectionLimit::PerIp) => {
+            let res = Response::new()
+                .status(Status::TooManyRequests)
+                .text("Too Many Connections");
+            send_rejection(stream, res, false, this);
+        }
+    }
+}
+
+/// Checks if a connection has waited in the thread pool's queue for longer than [`Server::max_queue_wait`].
+pub(crate) fn queue_timed_out<State>(queued: Instant, this: &Server<State>) -> bool
+where
+    State: 'static + Send + Sync,
+{
+    this.max_queue_wait
+        .is_some_and(|max| queued.elapsed() > max)
+}
+
+/// Sends a `503 Service Unavailable` response to a connection that waited too long for a worker and closes it.
+pub(crate) fn reject_queued<State>(stream: Arc<Mutex<Box<dyn Stream>>>, this: &Server<State>)
+where
+    State: 'static + Send + Sync,
+{
+    trace!(
+        Level::Debug,
+        "Connection waited too long for a worker, rejecting {:?}",
+        stream.force_lock().peer_addr()
+    );
+
+    // Ask the client to retry once the queue has had time to drain
+    let retry_after = this
+        .max_queue_wait
+        .map(|i| i.as_secs() + u64::from(i.subsec_nanos() > 0))
+        .map(|i| i.max(1));
+    send_unavailable(stream, retry_after, true, this);
+}
+
+/// Sends a `503 Service Unavailable` response and closes the connection, see [`send_rejection`].
+fn send_unavailable<State>(
+    stream: Arc<Mutex<Box<dyn Stream>>>,
+    retry_after: Option<u64>,
+    linger: bool,
+    this: &Server<State>,
+) where
+    State: 'static + Send + Sync,
+{
+    let mut res = Response::new()
+        .status(Status::ServiceUnavailable)
+        .text("Service Unavailable");
+    if let Some(retry_after) = retry_after {
+        res.headers
+            .add(HeaderType::RetryAfter, retry_after.to_string());
+    }
+    send_rejection(stream, res, linger, this);
+}
+
+/// Sends a response to a connection that won't be handled, and closes it.
+/// If `linger` is false, the connection is shut down right away instead of with [`close_socket`], so the current thread isn't blocked waiting for the client.
+fn send_rejection<State>(
+    stream: Arc<Mutex<Box<dyn Stream>>>,
+    res: Response,
+    linger: bool,
+    this: &Server<State>,
+) where
+    State: 'static + Send + Sync,
+{
+    let _ = stream.force_lock().set_write_timeout(this.socket_timeout);
+    let mut res = res.content(Content::TXT).close();
+    if let Err(e) = res.write(stream.clone(), this) {
+        log_write_error(&e);
+    }
+
+    if linger {
+        close_socket(&stream);
+    } else if let Err(e) = stream.force_lock().shutdown(Shutdown::Both) {
+        log_socket_error("closing", &e);
+    }
+}
+
+thread_local! {
+    /// If this thread is the one accepting connections, in which case [`close_socket`] can't block waiting for clients.
+    static ACCEPTING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Marks the current thread as accepting connections until the returned guard is dropped.
+/// Used by [`Server::start_with_listener`], which handles connections on the same thread that accepts them.
+pub(crate) fn accepting() -> AcceptingGuard {
+    ACCEPTING.with(|i| i.set(true));
+    AcceptingGuard
+}
+
+/// Unmarks the current thread as accepting connections when dropped.
+pub(crate) struct AcceptingGuard;
+
+impl Drop for AcceptingGuard {
+    fn drop(&mut self) {
+        ACCEPTING.with(|i| i.set(false));
+    }
+}
+
+/// Closes a connection once its last response has been written.
+/// Only the write half is shut down at first, then anything the client is still sending (like a body the server didn't read) is discarded until it closes its side too.
+/// Otherwise the OS would reset the connection because of the unread data, and the client could lose the response before reading it.
+///
+/// This can wait on the client for up to [`consts::LINGER_TIMEOUT`], so on the thread accepting connections the waiting is moved to another thread.
+/// If the stream can't be cloned for that, it's closed right away instead.
+pub(crate) fn close_socket(stream: &Mutex<Box<dyn Stream>>) {
+    let mut stream = stream.force_lock();
+    if let Err(e) = stream.shutdown(Shutdown::Write) {
+        log_socket_error("closing", &e);
+        return;
+    }
 
-        if let Ok(req) = &req {
-            keep_alive = req.keep_alive();
+    if !ACCEPTING.with(Cell::get) {
+        return linger(&mut **stream);
+    }
+
+    let spawned = stream.try_clone().and_then(|mut stream| {
+        thread::Builder::new()
+            .name("afire linger".to_owned())
+            .spawn(move || linger(&mut *stream))
+    });
+
+    if spawned.is_err() {
+        if let Err(e) = stream.shutdown(Shutdown::Both) {
+            log_socket_error("closing", &e);
+        }
+    }
+}
+
+/// Discards anything the client sends until it closes its side of the connection, then closes ours.
+fn linger(stream: &mut dyn Stream) {
+    let deadline = Instant::now() + consts::LINGER_TIMEOUT;
+    let mut buf = [0; 1024];
+    let mut discarded = 0;
+    while discarded < consts::LINGER_LIMIT {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        if timeout.is_zero() || stream.set_read_timeout(Some(timeout)).is_err() {
+            break;
+        }
+
+        match stream.read(&mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => discarded += n,
+        }
+    }
+
+    if let Err(e) = stream.shutdown(Shutdown::Both) {
+        log_socket_error("closing", &e);
+    }
+}
+
+/// Logs an error from a socket operation.
+/// Errors from the client having disconnected are expected, so they are only logged at the trace level.
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+pub(crate) fn log_socket_error(action: &str, err: &io::Error) {
+    if is_disconnect(err.kind()) {
+        trace!(Level::Trace, "Client disconnected before {} socket", action);
+    } else {
+        trace!(Level::Error, "Error {} socket: {:?}", action, err);
+    }
+}
+
+/// Counts a connection that was closed before the client sent anything, like the speculative connections browsers open.
+fn empty_connection<State>(this: &Server<State>)
+where
+    State: 'static + Send + Sync,
+{
+    trace!(
+        Level::Debug,
+        "Client closed the connection without sending anything"
+    );
+    let counters = this.connection_stats.counters();
+    counters.empty.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Checks if an error from reading the start of a connection just means the client went away without sending anything.
+/// Along with disconnects, TLS streams fail with `UnexpectedEof` if the client closes the connection without ending the session.
+#[cfg(feature = "http2")]
+fn is_empty_close(kind: io::ErrorKind) -> bool {
+    is_disconnect(kind)
+        || matches!(
+            kind,
+            io::ErrorKind::UnexpectedEof | io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+        )
+}
+
+/// Logs an error from writing a response.
+fn log_write_error(err: &Error) {
+    match err {
+        Error::Io(e) if is_disconnect(e.kind()) => {
             trace!(
-                Level::Debug,
-                "{} {} {{ keep_alive: {} }}",
-                req.method,
-                req.path,
-                keep_alive
+                Level::Trace,
+                "Client disconnected before the response was written"
             );
         }
+        _ => {
+            trace!(Level::Debug, "Error writing to socket: {:?}", err);
+        }
+    }
+}
 
-        let (req, mut res) = get_response(req, this);
+/// Applies the server's socket options to a newly accepted connection.
+pub(crate) fn configure_socket<State>(stream: &dyn Stream, this: &Server<State>) -> io::Result<()>
+where
+    State: 'static + Send + Sync,
+{
+    stream.set_read_timeout(this.socket_timeout)?;
+    stream.set_write_timeout(this.socket_timeout)?;
+    if this.tcp_nodelay {
+        stream.set_nodelay(true)?;
+    }
 
-        if res.flag == ResponseFlag::End {
-            trace!(Level::Debug, "Ending socket");
-            break;
+    Ok(())
+}
+
+/// Handles a single request on a socket.
+/// - Lets Request::from_socket read the request
+/// - Lets Response::write write the response to the socket
+/// - Runs End Middleware
+/// - Optionally closes the socket
+///
+/// Returns true if the connection should be kept open for another request.
+pub(crate) fn handle_request<State>(
+    stream: &Arc<Mutex<Box<dyn Stream>>>,
+    this: &Server<State>,
+    connection: &mut ConnectionState,
+) -> bool
+where
+    State: 'static + Send + Sync,
+{
+    // HTTP/2 connections start with a preface instead of a request
+    #[cfg(feature = "http2")]
+    if connection.info.requests() == 0 {
+        match http2::read_preface(stream, connection, this) {
+            Ok(true) => {
+                // HTTP/2 connections manage their own idle streams
+                reaper::set_idle(&connection.info, None);
+                http2::handle(stream, this, connection, None);
+                return false;
+            }
+            Ok(false) => {}
+            Err(e) if connection.pending.is_empty() && is_empty_close(e.kind()) => {
+                empty_connection(this);
+                return false;
+            }
+            Err(e) => {
+                log_socket_error("reading", &e);
+                return false;
+            }
         }
+    }
 
-        if let Err(e) = res.write(stream.clone(), &this.default_headers) {
-            trace!(Level::Debug, "Error writing to socket: {:?}", e);
+    let mut info = ResponseInfo {
+        keep_alive: false,
+        http10: false,
+        http2: false,
+        requests: connection.info.next_request(),
+    };
+    let limits = RequestLimits {
+        head: HeadLimits {
+            request_line: this.max_request_line,
+            header_size: this.max_header_size,
+            headers: this.max_headers,
+            uri_policy: this.uri_policy,
+        },
+        request_line_timeout: this.request_line_timeout,
+        header_timeout: this.header_timeout,
+        socket_timeout: this.socket_timeout,
+    };
+    let buffer = this.buffer_pool.take(this.read_buffer_size);
+    let req = Request::from_socket(stream.clone(), limits, buffer, connection, |req| {
+        prepare_request(req, this)
+    });
+
+    match &req {
+        Err(Error::Stream(StreamError::ConnectionClosed)) if info.requests == 1 => {
+            empty_connection(this);
+            return false;
+        }
+        Err(Error::Stream(StreamError::ConnectionClosed)) => {
+            trace!(Level::Debug, "Client closed the connection");
+            return false;
+        }
+        // Like when the client disconnects before its address can be read
+        Err(Error::Io(e)) if is_disconnect(e.kind()) => {
+            trace!(Level::Debug, "Client disconnected");
+            return false;
         }
+        _ => {}
+    }
 
-        // End Middleware
-        if let Some(req) = req {
-            for i in this.middleware.iter().rev() {
-                if let Err(e) = panic::catch_unwind(panic::AssertUnwindSafe(|| i.end(&req, &res))) {
-                    trace!(Level::Error, "Error running end middleware: {:?}", e);
-                }
+    if let Ok(req) = &req {
+        info.keep_alive = req.keep_alive();
+        info.http10 = req.is_http10();
+        trace!(
+            Level::Debug,
+            "{} {} {{ keep_alive: {} }}",
+            req.method,
+            req.path,
+            info.keep_alive
+        );
+    }
+
+    // After a framing error, where the next request starts isn't known
+    let framing_error = req.is_err();
+
+    #[cfg(feature = "http2")]
+    let req = match req {
+        Ok(req) => match http2::upgrade_settings(&req, connection) {
+            Some(settings) => {
+                let upgrade = http2::Upgrade { req, settings };
+                http2::handle(stream, this, connection, Some(upgrade));
+                return false;
+            }
+            None => Ok(req),
+        },
+        Err(e) => Err(e),
+    };
+
+    let (req, mut res) = get_response(req, info, this);
+
+    if res.flag == ResponseFlag::End {
+        trace!(Level::Debug, "Ending socket");
+        return false;
+    }
+
+    // Anything written to or read from the socket now would get mixed up with what the handler is doing with it
+    if req.as_ref().is_some_and(|i| i.socket_consumed()) {
+        trace!(Level::Debug, "Socket was consumed by the handler");
+        return false;
+    }
+
+    // The rest of a streamed body the handler didn't read would be parsed as the next request
+    if req.as_ref().is_some_and(|i| !i.body_finished()) {
+        trace!(Level::Debug, "Streamed body wasn't read, closing socket");
+        res.flag = ResponseFlag::Close;
+    }
+
+    // Anything already read after a request that couldn't be framed can't be trusted to be the start of another request
+    if framing_error {
+        connection.pending.clear();
+        res.flag = ResponseFlag::Close;
+    }
+
+    if !res.sent {
+        if let Some(req) = req.as_ref().filter(|_| this.server_timing) {
+            res.headers
+                .add(HeaderType::ServerTiming, req.timing().server_timing());
+        }
+
+        finish_response(&mut res, info, this);
+        if let Err(e) = res.write(stream.clone(), this) {
+            log_write_error(&e);
+        }
+    }
+
+    if let Some(req) = &req {
+        req.mark(Phase::Write);
+    }
+
+    if let Some(req) = req {
+        end_middleware(&req, &res, this);
+    }
+
+    if res.flag == ResponseFlag::Close {
+        trace!(Level::Debug, "Closing socket");
+        close_socket(stream);
+        return false;
+    }
+
+    true
+}
+
+/// Prepares a request that has just been read for handling, setting its cookie key and normalizing its path if enabled.
+/// Returns how its body should be read.
+pub(crate) fn prepare_request<State>(req: &mut Request, this: &Server<State>) -> BodyOptions
+where
+    State: 'static + Send + Sync,
+{
+    req.cookies.key.clone_from(&this.cookie_key);
+    req.clock = this.clock.clone();
+    req.url_config = this.url_config.clone();
+    req.web_socket_config = this.web_socket_config.clone();
+    req.shutdown = this.shutdown.token();
+    if this.reap_idle_connections {
+        reaper::set_idle(&req.connection, None);
+    }
+    if this.uri_policy.normalize_percent_encoding {
+        req.path = url::normalize(&req.path);
+    }
+    if this.normalize_paths {
+        req.path = resolve_dots(&req.path);
+    }
+    body_options(req, this)
+}
+
+/// Runs the end middleware, once the response has been written.
+pub(crate) fn end_middleware<State>(req: &Request, res: &Response, this: &Server<State>)
+where
+    State: 'static + Send + Sync,
+{
+    for i in this.middleware.iter().rev() {
+        if let Err(_e) = catch_panic(this, || i.end(req, res)) {
+            trace!(Level::Error, "Error running end middleware: {}", _e);
+        }
+    }
+}
+
+/// Gets how the body of a request is read, using the options of the route that will handle it or the server's defaults.
+fn body_options<State>(req: &Request, this: &Server<State>) -> BodyOptions
+where
+    State: 'static + Send + Sync,
+{
+    let route = find_route(this, &req.method, &req.path);
+    let route = route.as_deref();
+    *req.route_meta.borrow_mut() = route.map(|i| i.meta.clone());
+
+    BodyOptions {
+        max_size: route.and_then(|i| i.max_body_size).or(this.max_body_size),
+        timeout: route.and_then(|i| i.body_timeout).or(this.body_timeout),
+        stream: route.is_some_and(|i| i.stream_body),
+    }
+}
+
+/// A route found by [`find_route`], either one of the server's routes or one from its [`crate::RouteTable`].
+enum FoundRoute<'a, State: 'static + Send + Sync> {
+    Server(&'a Route<State>),
+    Table(Arc<Route<State>>),
+}
+
+impl<State: 'static + Send + Sync> Deref for FoundRoute<'_, State> {
+    type Target = Route<State>;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            FoundRoute::Server(route) => route,
+            FoundRoute::Table(route) => route,
+        }
+    }
+}
+
+/// Finds the route that handles a method and path.
+/// Routes in the route table win over server routes with the same priority, as if they were added after them.
+fn find_route<'a, State>(
+    this: &'a Server<State>,
+    method: &Method,
+    path: &str,
+) -> Option<FoundRoute<'a, State>>
+where
+    State: 'static + Send + Sync,
+{
+    let server = this.router().find(method, path);
+    match (server, this.route_table.find(method, path)) {
+        (Some((priority, _)), Some((table, route))) if table >= priority => {
+            Some(FoundRoute::Table(route))
+        }
+        (None, Some((_, route))) => Some(FoundRoute::Table(route)),
+        (server, _) => server.map(|(_, idx)| FoundRoute::Server(&this.routes[idx])),
+    }
+}
+
+/// Information about the connection a request was received on, used to finish its response.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ResponseInfo {
+    /// If the client wants to keep the connection alive.
+    keep_alive: bool,
+    /// If the request was made with HTTP/1.0.
+    http10: bool,
+    /// If the request was received over HTTP/2, where responses are sent by the connection instead of [`Response::write`].
+    pub(crate) http2: bool,
+    /// The number of requests that have been handled on the connection, including this one.
+    requests: usize,
+}
+
+impl ResponseInfo {
+    /// Gets the info for a request received on an HTTP/2 connection, which is kept alive between requests.
+    #[cfg(feature = "http2")]
+    pub(crate) fn http2(requests: usize) -> Self {
+        Self {
+            keep_alive: true,
+            http10: false,
+            http2: true,
+            requests,
+        }
+    }
+}
+
+/// Decides if the connection will be kept alive after a response, and adds the headers to let the client know.
+pub(crate) fn finish_response<State>(res: &mut Response, info: ResponseInfo, this: &Server<State>)
+where
+    State: 'static + Send + Sync,
+{
+    res.http10 = info.http10;
+    let remaining = this
+        .max_keep_alive_requests
+        .map(|i| i.saturating_sub(info.requests));
+    if remaining == Some(0) {
+        trace!(Level::Debug, "Max keep-alive requests reached");
+    }
+
+    if !info.keep_alive || !this.keep_alive || remaining == Some(0) || this.shutdown.is_shutdown() {
+        // Let the client know the connection will be closed
+        res.flag = ResponseFlag::Close;
+    } else if res.flag != ResponseFlag::Close {
+        // HTTP/1.0 connections are only persistent if the server says so
+        if info.http10 && !res.headers.has(HeaderType::Connection) {
+            res.headers.add(HeaderType::Connection, "keep-alive");
+        }
+        add_keep_alive_header(res, this.keep_alive_timeout, remaining);
+    }
+}
+
+/// Waits for the client to start sending another request on a keep-alive connection.
+/// If no data is received before the keep-alive timeout (or socket timeout) runs out, or the server is shut down while waiting, false is returned and the socket should be closed.
+pub(crate) fn wait_for_request<State>(
+    stream: &Arc<Mutex<Box<dyn Stream>>>,
+    this: &Server<State>,
+) -> bool
+where
+    State: 'static + Send + Sync,
+{
+    let deadline = this
+        .keep_alive_timeout
+        .or(this.socket_timeout)
+        .map(|i| this.clock.instant() + i);
+    let stream = stream.force_lock();
+
+    let alive = loop {
+        // Wake up every so often to check if the server is shutting down
+        let mut timeout = consts::SHUTDOWN_POLL;
+        if let Some(deadline) = deadline {
+            timeout = timeout.min(deadline.saturating_duration_since(this.clock.instant()));
+            if timeout.is_zero() {
+                break false;
             }
         }
 
-        if !keep_alive || res.flag == ResponseFlag::Close || !this.keep_alive {
-            trace!(Level::Debug, "Closing socket");
-            if let Err(e) = stream.lock().unwrap().shutdown(Shutdown::Both) {
-                trace!(Level::Debug, "Error closing socket: {:?}", e);
+        if stream.set_read_timeout(Some(timeout)).is_err() {
+            break false;
+        }
+
+        match stream.peek(&mut [0]) {
+            Ok(n) => break n > 0,
+            Err(e) if e.kind() == io::ErrorKind::Unsupported => break true,
+            Err(e)
+                if !matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock
+                        | io::ErrorKind::TimedOut
+                        | io::ErrorKind::Interrupted
+                ) =>
+            {
+                break false
             }
-            break;
+            Err(_) => {}
         }
+
+        if this.shutdown.is_shutdown() {
+            break false;
+        }
+    };
+    alive && stream.set_read_timeout(this.socket_timeout).is_ok()
+}
+
+/// Adds the `Keep-Alive` header to a response to advertise the servers keep-alive policy.
+/// Nothing will be added if no timeout or request limit is set, or if the response already has the header.
+fn add_keep_alive_header(res: &mut Response, timeout: Option<Duration>, max: Option<usize>) {
+    if res.headers.has(HeaderType::KeepAlive) {
+        return;
+    }
+
+    let mut params = Vec::new();
+    if let Some(timeout) = timeout {
+        params.push(format!("timeout={}", timeout.as_secs().max(1)));
+    }
+
+    if let Some(max) = max {
+        params.push(format!("max={max}"));
+    }
+
+    if !params.is_empty() {
+        res.headers.add(HeaderType::KeepAlive, params.join(", "));
     }
 }
 
 /// Gets the response from a request.
 /// Will call middleware, route handlers and error handlers if needed.
-fn get_response<State>(
+pub(crate) fn get_response<State>(
     mut req: Result<Request>,
+    info: ResponseInfo,
     server: &Server<State>,
 ) -> (Option<Rc<Request>>, Response)
 where
     State: 'static + Send + Sync,
 {
-    let mut res = Err(Error::None);
-    let handle_error = |error, req: Result<_>, server| {
-        let err = HandleError::Panic(Box::new(req.clone()), any_string(error).into_owned()).into();
-        (req.ok(), error_response(&err, server))
+    let mut res = match &req {
+        Ok(req) => trailing_slash_redirect(req, server.trailing_slash).ok_or(Error::None),
+        Err(_) => Err(Error::None),
     };
 
     // Pre Middleware
-    for i in server.middleware.iter().rev() {
-        match panic::catch_unwind(panic::AssertUnwindSafe(|| i.pre_raw(&mut req))) {
-            Ok(MiddleResult::Send(this_res)) => {
-                res = Ok(this_res);
-                break;
+    if res.is_err() {
+        for i in server.middleware.iter().rev() {
+            match catch_panic(server, || i.pre_raw(&mut req)) {
+                Ok(MiddleResult::Send(this_res)) => {
+                    res = Ok(this_res);
+                    break;
+                }
+                Ok(MiddleResult::Abort) => break,
+                Ok(MiddleResult::Continue) => {}
+                Err(e) => return panic_response(e, req.map(Rc::new), server),
             }
-            Ok(MiddleResult::Abort) => break,
-            Ok(MiddleResult::Continue) => {}
-            Err(e) => return handle_error(e, req.map(Rc::new), server),
         }
     }
 
     let req = req.map(Rc::new);
+    if let Ok(req) = &req {
+        req.mark(Phase::Pre);
+    }
+
     if res.is_err() {
         if let Ok(req) = req.clone() {
-            res = handle_route(req, server);
+            res = handle_route(req.clone(), info, server);
+            req.mark(Phase::Route);
         }
     }
 
-    // Post Middleware
+    let (req, res) = post_middleware(req, res, server);
+    if let Some(req) = &req {
+        req.mark(Phase::Post);
+    }
+    (req, res)
+}
+
+/// Gets a redirect for a request if its path doesn't follow the trailing slash policy, see [`Server::trailing_slash`].
+fn trailing_slash_redirect(req: &Request, policy: TrailingSlash) -> Option<Response> {
+    let path = match policy {
+        _ if req.path == "/" => return None,
+        TrailingSlash::Trim if req.path.ends_with('/') => req.path.trim_end_matches('/').to_owned(),
+        TrailingSlash::Append if !req.path.ends_with('/') => format!("{}/", req.path),
+        _ => return None,
+    };
+
+    let location = format!("{}{}", path, req.query);
+
+    let status = match req.method {
+        Method::GET | Method::HEAD => Status::MovedPermanently,
+        _ => Status::PermanentRedirect,
+    };
+    Some(
+        Response::new()
+            .status(status)
+            .header(HeaderType::Location, location),
+    )
+}
+
+/// Runs the post middleware on a response, then turns any error into an error response.
+/// The server's response hooks are run on the final response, see [`Server::response_hook`].
+/// Responses that have already been sent are returned as is.
+pub(crate) fn post_middleware<State>(
+    req: Result<Rc<Request>>,
+    res: Result<Response>,
+    server: &Server<State>,
+) -> (Option<Rc<Request>>, Response)
+where
+    State: 'static + Send + Sync,
+{
+    if matches!(&res, Ok(i) if i.sent) {
+        return (req.ok(), res.unwrap());
+    }
+
+    let (req, mut res) = run_post_middleware(req, res, server);
+    if let Some(req) = &req {
+        for hook in &server.response_hooks {
+            if let Err(e) = catch_panic(server, || hook(req, &mut res)) {
+                return panic_response(e, Ok(req.clone()), server);
+            }
+        }
+
+        if let Err(e) = catch_panic(server, || {
+            body_filter::apply(&server.body_filters, req, &mut res)
+        }) {
+            return panic_response(e, Ok(req.clone()), server);
+        }
+    }
+    (req, res)
+}
+
+/// Runs the post middleware on a response that hasn't been sent, see [`post_middleware`].
+fn run_post_middleware<State>(
+    req: Result<Rc<Request>>,
+    mut res: Result<Response>,
+    server: &Server<State>,
+) -> (Option<Rc<Request>>, Response)
+where
+    State: 'static + Send + Sync,
+{
+    if let Ok(res) = &mut res {
+        apply_defaults(res, server);
+    }
+
     for i in server.middleware.iter().rev() {
-        match panic::catch_unwind(panic::AssertUnwindSafe(|| {
-            i.post_raw(req.clone(), &mut res)
-        })) {
-            Ok(MiddleResult::Send(res)) => return (req.ok(), res),
+        match catch_panic(server, || i.post_raw(req.clone(), &mut res)) {
+            Ok(MiddleResult::Send(mut res)) => {
+                apply_defaults(&mut res, server);
+                return (req.ok(), res);
+            }
             Ok(MiddleResult::Abort) => break,
             Ok(MiddleResult::Continue) => {}
-            Err(e) => return handle_error(e, req, server),
+            Err(e) => return panic_response(e, req, server),
         }
     }
 
+    // Post middleware (like ServeStatic) can also respond with files or add cookies
     let res = match res {
-        Ok(res) => res,
+        Ok(mut res) => {
+            apply_defaults(&mut res, server);
+            res
+        }
         Err(e) => {
-            let error = match req {
-                Err(ref err) => err,
-                Ok(_) => &e,
+            let res = match &req {
+                Err(err) => error_response(err, None, server),
+                Ok(req) => error_response(&e, Some(req), server),
             };
-
-            return (None, error_response(error, server));
+            return (req.ok(), res);
         }
     };
 
     (req.ok(), res)
 }
 
+/// Applies the server's defaults to a response before post middleware run.
+/// The `Content-Type` of file responses is set from the server's MIME types (see [`Server::mime`]), unless they already have one.
+/// Cookies are turned into headers with the server's cookie policy (see [`Server::cookie_policy`]).
+fn apply_defaults<State>(res: &mut Response, server: &Server<State>)
+where
+    State: 'static + Send + Sync,
+{
+    res.apply_cookies(server.cookie_policy.as_ref());
+    let Some(ext) = res.mime_extension.take() else {
+        return;
+    };
+
+    if !res.headers.has(HeaderType::ContentType) {
+        let content_type = server
+            .mime_types
+            .get(&ext)
+            .unwrap_or("application/octet-stream");
+        res.headers.add(HeaderType::ContentType, content_type);
+    }
+}
+
+/// Gets the error response for a panic in a middleware.
+fn panic_response<State>(
+    info: Box<PanicInfo>,
+    req: Result<Rc<Request>>,
+    server: &Server<State>,
+) -> (Option<Rc<Request>>, Response)
+where
+    State: 'static + Send + Sync,
+{
+    let err = HandleError::Panic(Box::new(req.clone()), info).into();
+    (req.ok(), error_response(&err, None, server))
+}
+
+thread_local! {
+    /// While a handler is running on this thread, if backtraces should be captured even if `RUST_BACKTRACE` isn't set.
+    static CAPTURE_BACKTRACE: Cell<Option<bool>> = const { Cell::new(None) };
+    /// The backtrace of the last panic in a handler on this thread.
+    static BACKTRACE: RefCell<Option<Backtrace>> = const { RefCell::new(None) };
+}
+
+/// Runs a route or middleware, catching any panic along with its backtrace.
+/// Backtraces are captured with a panic hook, as the stack has already been unwound once `catch_unwind` returns.
+/// The hook is installed the first time this is called, and calls the previous hook after capturing the backtrace.
+fn catch_panic<State, T>(
+    server: &Server<State>,
+    f: impl FnOnce() -> T,
+) -> std::result::Result<T, Box<PanicInfo>>
+where
+    State: 'static + Send + Sync,
+{
+    static HOOK: Once = Once::new();
+    HOOK.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if let Some(force) = CAPTURE_BACKTRACE.with(Cell::get) {
+                let backtrace = match force {
+                    true => Backtrace::force_capture(),
+                    false => Backtrace::capture(),
+                };
+                BACKTRACE.with(|i| *i.borrow_mut() = Some(backtrace));
+            }
+            previous(info);
+        }));
+    });
+
+    let outer = CAPTURE_BACKTRACE.with(|i| i.replace(Some(server.capture_backtraces)));
+    let result = panic::catch_unwind(panic::AssertUnwindSafe(f));
+    CAPTURE_BACKTRACE.with(|i| i.set(outer));
+
+    result.map_err(|e| {
+        if server.panic_policy == PanicPolicy::Abort {
+            trace!(Level::Error, "Handler panicked, aborting");
+            process::abort();
+        }
+
+        let backtrace = BACKTRACE
+            .with(|i| i.borrow_mut().take())
+            .filter(|i| i.status() == BacktraceStatus::Captured);
+        Box::new(PanicInfo::new(any_string(e), None, backtrace))
+    })
+}
+
 /// Tries to find a route that matches the request.
 /// If it finds one, it will call the handler and return the result (assuming it doesn't panic).
 /// If it doesn't find one, it will return an Error of HandleError::NotFound.
-fn handle_route<State>(req: Rc<Request>, this: &Server<State>) -> Result<Response>
+fn handle_route<State>(
+    req: Rc<Request>,
+    info: ResponseInfo,
+    this: &Server<State>,
+) -> Result<Response>
 where
     State: 'static + Send + Sync,
 {
     // Handle Route
     let path = req.path.to_owned();
-    for route in this.routes.iter().rev() {
-        if let Some(params) = route.matches(req.clone()) {
-            *req.path_params.borrow_mut() = params;
-            let result = panic::catch_unwind(panic::AssertUnwindSafe(|| match &route.handler {
-                RouteType::Stateless(i) => (i)(&req),
-                RouteType::Stateful(i) => {
-                    (i)(this.state.clone().expect("State not initialized"), &req)
-                }
-            }));
+    if let Some(route) = find_route(this, &req.method, &path) {
+        *req.path_params.borrow_mut() = route.matches(&req).unwrap_or_default();
+        *req.route_meta.borrow_mut() = Some(route.meta.clone());
+        let result = catch_panic(this, || match &route.handler {
+            RouteType::Stateless(i) => Ok((i)(&req)),
+            RouteType::Stateful(i) => Ok((i)(
+                this.state.clone().expect("State not initialized"),
+                &req,
+            )),
+            RouteType::Context(i) => {
+                let ctx = Context::new(req.clone(), info, this);
+                let res = (i)(&ctx);
+                ctx.finish(res)
+            }
+        });
 
-            let err = match result {
-                Ok(i) => return Ok(i),
-                Err(e) => any_string(e),
-            };
+        let mut info = match result {
+            Ok(i) => return i,
+            Err(e) => e,
+        };
 
-            return Err(Error::Handle(Box::new(HandleError::Panic(
-                Box::new(Ok(req)),
-                err.into_owned(),
-            ))));
-        }
+        info.route = Some(route.info());
+        return Err(Error::Handle(Box::new(HandleError::Panic(
+            Box::new(Ok(req)),
+            info,
+        ))));
     }
 
     Err(Error::Handle(Box::new(HandleError::NotFound(
-        req.method, path,
+        req.method.clone(),
+        path,
     ))))
 }
 
 /// Gets a response if there is an error.
 /// Can handle Parse, Handle and IO errors.
-pub fn error_response<State>(err: &Error, server: &Server<State>) -> Response
+/// Errors other than panics use the server's status page for the response's status, if one was set.
+pub fn error_response<State>(err: &Error, req: Option<&Request>, server: &Server<State>) -> Response
+where
+    State: 'static + Send + Sync,
+{
+    let res = default_error_response(err, server);
+    if matches!(err, Error::Handle(e) if matches!(**e, HandleError::Panic(..)))
+        || res.flag == ResponseFlag::End
+    {
+        return res;
+    }
+
+    match server.status_pages.get(&res.status) {
+        Some(page) => page(req, err).status(res.status),
+        None => res,
+    }
+}
+
+/// The default [`Server::error_handler`], a plain text 500 response with the panic message.
+#[cfg(not(all(feature = "dev", debug_assertions)))]
+pub(crate) fn default_error_handler(_req: &Result<Rc<Request>>, err: &PanicInfo) -> Response {
+    Response::new()
+        .status(Status::InternalServerError)
+        .text(format!("Internal Server Error :/\nError: {err}"))
+        .content(Content::TXT)
+}
+
+/// With the `dev` feature in debug builds, the default [`Server::error_handler`] is the HTML error page.
+#[cfg(all(feature = "dev", debug_assertions))]
+pub(crate) use crate::internal::debug::error_page as default_error_handler;
+
+/// Gets the default response for an error.
+fn default_error_response<State>(err: &Error, server: &Server<State>) -> Response
 where
     State: 'static + Send + Sync,
 {
@@ -188,9 +1139,33 @@ where
             unreachable!("None and Startup errors should not be here")
         }
         Error::Stream(e) => match e {
-            StreamError::UnexpectedEof => Response::new().status(400).text("Unexpected EOF"),
+            StreamError::UnexpectedEof => Response::new()
+                .status(Status::BadRequest)
+                .text("Unexpected EOF"),
+            StreamError::ConnectionClosed | StreamError::WriteTimeout => Response::end(),
+            StreamError::TimedOut => Response::new()
+                .status(Status::RequestTimeOut)
+                .text("Request Timeout"),
         },
-        Error::Parse(e) => Response::new().status(400).text(match e {
+        Error::Parse(ParseError::RequestLineTooLong) => Response::new()
+            .status(Status::URITooLarge)
+            .text("Request line too long"),
+        Error::Parse(ParseError::UriTooLong) => Response::new()
+            .status(Status::URITooLarge)
+            .text("URI too long"),
+        Error::Parse(ParseError::HeadersTooLarge) => Response::new()
+            .status(Status::RequestHeaderFieldsTooLarge)
+            .text("Request headers too large"),
+        Error::Parse(ParseError::BodyTooLarge) => Response::new()
+            .status(Status::PayloadTooLarge)
+            .text("Request body too large"),
+        Error::Parse(ParseError::UnsupportedTransferEncoding) => Response::new()
+            .status(Status::NotImplemented)
+            .text("Transfer encoding not supported"),
+        Error::Parse(ParseError::UnsupportedVersion) => Response::new()
+            .status(Status::HTTPVersionNotSupported)
+            .text("HTTP version not supported"),
+        Error::Parse(e) => Response::new().status(Status::BadRequest).text(match e {
             ParseError::NoSeparator => "No separator",
             ParseError::NoMethod => "No method",
             ParseError::NoPath => "No path",
@@ -199,16 +1174,316 @@ where
             ParseError::InvalidQuery => "Invalid query",
             ParseError::InvalidHeader => "Invalid header",
             ParseError::InvalidMethod => "Invalid method",
+            ParseError::NoHost => "No Host header",
+            ParseError::InvalidHost => "Invalid host",
+            ParseError::InvalidPath => "Invalid path",
+            ParseError::AmbiguousBody => "Ambiguous body length",
+            ParseError::RequestLineTooLong
+            | ParseError::UriTooLong
+            | ParseError::HeadersTooLarge
+            | ParseError::BodyTooLarge
+            | ParseError::UnsupportedTransferEncoding
+            | ParseError::UnsupportedVersion => unreachable!(),
         }),
         Error::Handle(e) => match e.deref() {
             HandleError::NotFound(method, path) => Response::new()
                 .status(Status::NotFound)
                 .text(format!("Cannot {method} {path}"))
                 .content(Content::TXT),
+            HandleError::Panic(..) if server.panic_policy == PanicPolicy::CatchAndClose => {
+                Response::end()
+            }
             HandleError::Panic(r, e) => {
-                (server.error_handler)(server.state.clone(), r, e.to_owned())
+                let mut res = (server.error_handler)(server.state.clone(), r, e);
+                res.panic = Some(e.clone());
+                res
             }
+            HandleError::ResponseAlreadySent | HandleError::DeferredDropped => Response::new()
+                .status(Status::InternalServerError)
+                .text(e)
+                .content(Content::TXT),
         },
-        Error::Io(e) => Response::new().status(500).text(e),
+        Error::Io(e) => Response::new().status(Status::InternalServerError).text(e),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::{Cursor, Read, Write},
+        net::Ipv4Addr,
+        sync::{Arc, Mutex},
+        thread,
+        time::Duration,
+    };
+
+    use crate::{
+        consts::LINGER_TIMEOUT,
+        internal::testing::{MemoryListener, MemoryStream},
+        Method, PanicPolicy, Response, Server,
+    };
+
+    #[test]
+    fn test_pipelining() {
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let listener = MemoryListener(Mutex::new(vec![MemoryStream {
+            input: Cursor::new(
+                b"GET /a HTTP/1.1\r\nHost: localhost\r\n\r\nPOST /b HTTP/1.1\r\nHost: localhost\r\nContent-Length: 4\r\n\r\nbodyGET /c HTTP/1.1\r\nHost: localhost\r\n\r\n"
+                    .to_vec(),
+            ),
+            output: output.clone(),
+        }]));
+
+        let mut server = Server::<()>::new("localhost", 0);
+        server.route(Method::ANY, "**", |req| {
+            let body = String::from_utf8_lossy(&req.body).into_owned();
+            Response::new().text(format!("{}{};", req.path, body))
+        });
+        assert!(server.start_with_listener(listener).is_err());
+
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        let bodies = output
+            .split("HTTP/1.1 ")
+            .skip(1)
+            .map(|i| i.split_once("\r\n\r\n").unwrap().1)
+            .collect::<String>();
+        assert_eq!(bodies, "/a;/bbody;/c;");
+    }
+
+    #[test]
+    fn test_pipelining_framing_error() {
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let listener = MemoryListener(Mutex::new(vec![MemoryStream {
+            input: Cursor::new(
+                b"GET /a HTTP/1.1\r\nHost: localhost\r\n\r\nPOST /b HTTP/1.1\r\nHost: localhost\r\nContent-Length: 20\r\n\r\nGET /c HTTP/1.1\r\nHost: localhost\r\n\r\n"
+                    .to_vec(),
+            ),
+            output: output.clone(),
+        }]));
+
+        let mut server = Server::<()>::new("localhost", 0).max_body_size(10);
+        server.route(Method::ANY, "**", |req| Response::new().text(&req.path));
+        assert!(server.start_with_listener(listener).is_err());
+
+        // The connection is closed after the error, even though the next request was already read
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        let statuses = output
+            .split("HTTP/1.1 ")
+            .skip(1)
+            .map(|i| &i[..3])
+            .collect::<Vec<_>>();
+        assert_eq!(statuses, ["200", "413"]);
+        assert!(!output.contains("/c"));
+    }
+
+    #[test]
+    fn test_panic_policy() {
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let listener = MemoryListener(Mutex::new(vec![MemoryStream {
+            input: Cursor::new(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n".to_vec()),
+            output: output.clone(),
+        }]));
+
+        let mut server = Server::<()>::new("localhost", 0).panic_policy(PanicPolicy::CatchAndClose);
+        server.route(Method::GET, "/", |_| panic!("Oh no"));
+        assert!(server.start_with_listener(listener).is_err());
+        assert!(output.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_max_queue_wait() {
+        let outputs = [(); 2].map(|_| Arc::new(Mutex::new(Vec::new())));
+        let streams = outputs
+            .iter()
+            .map(|output| MemoryStream {
+                input: Cursor::new(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n".to_vec()),
+                output: output.clone(),
+            })
+            .collect();
+        let listener = MemoryListener(Mutex::new(streams));
+
+        let mut server =
+            Server::<()>::new("localhost", 0).max_queue_wait(Duration::from_millis(100));
+        server.route(Method::GET, "/", |_| {
+            thread::sleep(Duration::from_millis(300));
+            Response::new()
+        });
+        assert!(server.start_threaded_with_listener(listener, 1).is_err());
+
+        // The second connection waits for the first to finish
+        let outputs = outputs.map(|i| String::from_utf8(i.lock().unwrap().clone()).unwrap());
+        assert!(outputs.iter().any(|i| i.starts_with("HTTP/1.1 200 OK\r\n")));
+        let rejected = outputs
+            .iter()
+            .find(|i| i.starts_with("HTTP/1.1 503 Service Unavailable\r\n"))
+            .unwrap();
+        assert!(rejected.contains("\r\nRetry-After: 1\r\n"));
+    }
+
+    #[test]
+    fn test_max_connections_per_ip() {
+        let outputs = [(); 3].map(|_| Arc::new(Mutex::new(Vec::new())));
+        let streams = outputs
+            .iter()
+            .map(|output| MemoryStream {
+                input: Cursor::new(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n".to_vec()),
+                output: output.clone(),
+            })
+            .collect();
+        let listener = MemoryListener(Mutex::new(streams));
+
+        let mut server = Server::<()>::new("localhost", 0).max_connections_per_ip(1);
+        let stats = server.connection_stats();
+        server.route(Method::GET, "/", |_| {
+            thread::sleep(Duration::from_millis(300));
+            Response::new()
+        });
+        assert!(server.start_threaded_with_listener(listener, 2).is_err());
+
+        // All the connections are from localhost, so only the first one accepted is handled
+        let outputs = outputs.map(|i| String::from_utf8(i.lock().unwrap().clone()).unwrap());
+        let count = |status: &str| outputs.iter().filter(|i| i.starts_with(status)).count();
+        assert_eq!(count("HTTP/1.1 200 OK\r\n"), 1, "{:?}", outputs);
+        assert_eq!(
+            count("HTTP/1.1 429 Too Many Requests\r\n"),
+            2,
+            "{:?}",
+            outputs
+        );
+        assert_eq!(stats.rejected(), 2);
+        assert_eq!(stats.open_from(Ipv4Addr::LOCALHOST.into()), 0);
+    }
+
+    #[test]
+    fn test_reject_without_linger() {
+        use std::{
+            net::{TcpListener, TcpStream},
+            time::Instant,
+        };
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut server = Server::<()>::new("localhost", 0).max_connections(1);
+        server.route(Method::GET, "/", |_| Response::new().text("Hello"));
+        let shutdown = server.shutdown_handle();
+        let handle =
+            thread::spawn(move || server.start_threaded_with_listener(listener, 2).is_ok());
+
+        // Takes the only connection slot without sending a request
+        let holding = TcpStream::connect(addr).unwrap();
+        thread::sleep(Duration::from_millis(100));
+
+        // Rejected clients that never close their side shouldn't hold up the accept thread
+        let start = Instant::now();
+        let mut rejected = Vec::new();
+        for _ in 0..3 {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            let mut output = String::new();
+            stream.read_to_string(&mut output).unwrap();
+            assert!(output.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+            rejected.push(stream);
+        }
+        assert!(start.elapsed() < LINGER_TIMEOUT, "{:?}", start.elapsed());
+
+        drop(holding);
+        thread::sleep(Duration::from_millis(100));
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let mut output = String::new();
+        stream.read_to_string(&mut output).unwrap();
+        assert!(output.ends_with("\r\n\r\nHello"), "{:?}", output);
+        drop(stream);
+
+        shutdown.shutdown();
+        assert!(handle.join().unwrap());
+    }
+
+    #[test]
+    fn test_close_without_linger_single_threaded() {
+        use std::{
+            net::{TcpListener, TcpStream},
+            time::Instant,
+        };
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut server = Server::<()>::new("localhost", 0);
+        server.route(Method::GET, "/", |_| Response::new().text("Hello").close());
+        let shutdown = server.shutdown_handle();
+        let handle = thread::spawn(move || server.start_with_listener(listener).is_ok());
+
+        // Clients that never close their side shouldn't hold up the next connection
+        let start = Instant::now();
+        let mut clients = Vec::new();
+        for _ in 0..3 {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            stream
+                .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+                .unwrap();
+            let mut output = String::new();
+            stream.read_to_string(&mut output).unwrap();
+            assert!(output.ends_with("\r\n\r\nHello"), "{:?}", output);
+            clients.push(stream);
+        }
+        assert!(start.elapsed() < LINGER_TIMEOUT, "{:?}", start.elapsed());
+
+        // The listener can't be woken, so it stops once the next connection is accepted
+        shutdown.shutdown();
+        drop(TcpStream::connect(addr).unwrap());
+        assert!(handle.join().unwrap());
+    }
+
+    #[test]
+    fn test_connection_close() {
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let listener = MemoryListener(Mutex::new(vec![MemoryStream {
+            input: Cursor::new(
+                b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: keep-alive, close\r\n\r\nGET / HTTP/1.1\r\nHost: localhost\r\n\r\n"
+                    .to_vec(),
+            ),
+            output: output.clone(),
+        }]));
+
+        let mut server = Server::<()>::new("localhost", 0);
+        server.route(Method::GET, "/", |_| Response::new().text("Hello"));
+        assert!(server.start_with_listener(listener).is_err());
+
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        assert_eq!(output.matches("HTTP/1.1 200 OK").count(), 1);
+        assert!(output.contains("\r\nConnection: close\r\n"));
+    }
+
+    #[test]
+    fn test_empty_connections() {
+        let requests = ["", "", "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n"];
+        let outputs = requests.map(|_| Arc::new(Mutex::new(Vec::new())));
+        let streams = requests
+            .iter()
+            .zip(&outputs)
+            .map(|(input, output)| MemoryStream {
+                input: Cursor::new(input.as_bytes().to_vec()),
+                output: output.clone(),
+            })
+            .collect();
+
+        let mut server = Server::<()>::new("localhost", 0);
+        server.route(Method::GET, "/", |_| Response::new().text("Hello"));
+        let stats = server.connection_stats();
+        assert!(server
+            .start_with_listener(MemoryListener(Mutex::new(streams)))
+            .is_err());
+
+        assert_eq!(stats.empty(), 2);
+        assert_eq!(stats.open(), 0);
+        assert!(outputs[0].lock().unwrap().is_empty());
+        assert!(!outputs[2].lock().unwrap().is_empty());
     }
 }
@@ -0,0 +1,314 @@
+//! Reading and writing HTTP/2 frames.
+//! - Reference: <https://www.rfc-editor.org/rfc/rfc9113#section-4>
+
+use std::io::{self, IoSlice, Read, Write};
+
+use crate::response::write_all_vectored;
+
+/// Sent by the client before anything else on an HTTP/2 connection.
+pub(crate) const PREFACE: &[u8; 24] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+/// The size of a frame header.
+const HEADER_SIZE: usize = 9;
+
+/// On DATA and HEADERS frames, the last frame the sender will send on the stream.
+pub(crate) const END_STREAM: u8 = 0x1;
+/// On SETTINGS and PING frames, that the frame is a reply.
+pub(crate) const ACK: u8 = 0x1;
+/// On HEADERS and CONTINUATION frames, the end of the header block.
+pub(crate) const END_HEADERS: u8 = 0x4;
+/// On DATA and HEADERS frames, that the payload is padded.
+pub(crate) const PADDED: u8 = 0x8;
+/// On HEADERS frames, that the payload starts with priority information.
+pub(crate) const PRIORITY: u8 = 0x20;
+
+/// The type of a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FrameType {
+    Data,
+    Headers,
+    Priority,
+    RstStream,
+    Settings,
+    PushPromise,
+    Ping,
+    GoAway,
+    WindowUpdate,
+    Continuation,
+    /// Frames of unknown types are ignored.
+    Unknown(u8),
+}
+
+/// The reason a stream or connection was closed, sent in RST_STREAM and GOAWAY frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ErrorCode {
+    NoError = 0x0,
+    ProtocolError = 0x1,
+    InternalError = 0x2,
+    FlowControlError = 0x3,
+    StreamClosed = 0x5,
+    FrameSizeError = 0x6,
+    RefusedStream = 0x7,
+    CompressionError = 0x9,
+    EnhanceYourCalm = 0xb,
+}
+
+/// An error that ends an HTTP/2 connection.
+#[derive(Debug)]
+pub(crate) enum ConnectionError {
+    /// Reading from or writing to the socket failed, so the connection is just closed.
+    Io(io::Error),
+    /// The client broke the protocol, so the connection is closed with a GOAWAY frame.
+    Protocol(ErrorCode),
+}
+
+/// A frame, with its payload.
+#[derive(Debug)]
+pub(crate) struct Frame {
+    pub kind: FrameType,
+    pub flags: u8,
+    /// The stream the frame is for, or zero for frames about the whole connection.
+    pub stream: u32,
+    pub payload: Vec<u8>,
+}
+
+/// The client's settings that change how the server sends frames.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Settings {
+    /// The flow control window of new streams.
+    pub initial_window_size: Option<u32>,
+    /// The largest frame payload the client accepts.
+    pub max_frame_size: Option<u32>,
+}
+
+impl FrameType {
+    fn from_u8(kind: u8) -> Self {
+        match kind {
+            0x0 => FrameType::Data,
+            0x1 => FrameType::Headers,
+            0x2 => FrameType::Priority,
+            0x3 => FrameType::RstStream,
+            0x4 => FrameType::Settings,
+            0x5 => FrameType::PushPromise,
+            0x6 => FrameType::Ping,
+            0x7 => FrameType::GoAway,
+            0x8 => FrameType::WindowUpdate,
+            0x9 => FrameType::Continuation,
+            i => FrameType::Unknown(i),
+        }
+    }
+
+    fn to_u8(self) -> u8 {
+        match self {
+            FrameType::Data => 0x0,
+            FrameType::Headers => 0x1,
+            FrameType::Priority => 0x2,
+            FrameType::RstStream => 0x3,
+            FrameType::Settings => 0x4,
+            FrameType::PushPromise => 0x5,
+            FrameType::Ping => 0x6,
+            FrameType::GoAway => 0x7,
+            FrameType::WindowUpdate => 0x8,
+            FrameType::Continuation => 0x9,
+            FrameType::Unknown(i) => i,
+        }
+    }
+}
+
+impl From<io::Error> for ConnectionError {
+    fn from(e: io::Error) -> Self {
+        ConnectionError::Io(e)
+    }
+}
+
+impl From<ErrorCode> for ConnectionError {
+    fn from(code: ErrorCode) -> Self {
+        ConnectionError::Protocol(code)
+    }
+}
+
+impl Frame {
+    pub(crate) fn new(kind: FrameType, flags: u8, stream: u32, payload: Vec<u8>) -> Self {
+        Self {
+            kind,
+            flags,
+            stream,
+            payload,
+        }
+    }
+
+    /// Makes a RST_STREAM frame, which closes a stream.
+    pub(crate) fn reset(stream: u32, code: ErrorCode) -> Self {
+        let payload = (code as u32).to_be_bytes().to_vec();
+        Self::new(FrameType::RstStream, 0, stream, payload)
+    }
+
+    /// Makes a WINDOW_UPDATE frame, which lets the client send `size` more bytes.
+    pub(crate) fn window_update(stream: u32, size: u32) -> Self {
+        let payload = size.to_be_bytes().to_vec();
+        Self::new(FrameType::WindowUpdate, 0, stream, payload)
+    }
+
+    /// Makes a GOAWAY frame, which closes the connection after `last_stream`.
+    pub(crate) fn go_away(last_stream: u32, code: ErrorCode) -> Self {
+        let mut payload = last_stream.to_be_bytes().to_vec();
+        payload.extend_from_slice(&(code as u32).to_be_bytes());
+        Self::new(FrameType::GoAway, 0, 0, payload)
+    }
+
+    /// Makes a SETTINGS frame from a list of setting ids and values.
+    pub(crate) fn settings(settings: &[(u16, u32)]) -> Self {
+        let mut payload = Vec::with_capacity(settings.len() * 6);
+        for (id, value) in settings {
+            payload.extend_from_slice(&id.to_be_bytes());
+            payload.extend_from_slice(&value.to_be_bytes());
+        }
+        Self::new(FrameType::Settings, 0, 0, payload)
+    }
+
+    /// Checks if the frame has a flag set.
+    pub(crate) fn has(&self, flag: u8) -> bool {
+        self.flags & flag != 0
+    }
+
+    /// Reads a frame, failing with a `FRAME_SIZE_ERROR` if its payload is bigger than `max_size`.
+    pub(crate) fn read(
+        stream: &mut (impl Read + ?Sized),
+        max_size: usize,
+    ) -> Result<Self, ConnectionError> {
+        let mut header = [0; HEADER_SIZE];
+        stream.read_exact(&mut header)?;
+
+        let len = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+        if len > max_size {
+            return Err(ErrorCode::FrameSizeError.into());
+        }
+
+        let mut payload = vec![0; len];
+        stream.read_exact(&mut payload)?;
+        let stream = u32::from_be_bytes([header[5], header[6], header[7], header[8]]) & 0x7fff_ffff;
+        Ok(Self::new(
+            FrameType::from_u8(header[3]),
+            header[4],
+            stream,
+            payload,
+        ))
+    }
+
+    /// Writes the frame, returning the number of bytes written.
+    pub(crate) fn write(&self, mut stream: &mut (impl Write + ?Sized)) -> io::Result<usize> {
+        let len = (self.payload.len() as u32).to_be_bytes();
+        let mut header = [0; HEADER_SIZE];
+        header[..3].copy_from_slice(&len[1..]);
+        header[3] = self.kind.to_u8();
+        header[4] = self.flags;
+        header[5..].copy_from_slice(&self.stream.to_be_bytes());
+
+        write_all_vectored(
+            &mut stream,
+            &mut [IoSlice::new(&header), IoSlice::new(&self.payload)],
+        )?;
+        Ok(HEADER_SIZE + self.payload.len())
+    }
+
+    /// Gets the part of a DATA or HEADERS frame's payload after the padding length and before the padding.
+    pub(crate) fn unpadded(&self) -> Result<&[u8], ErrorCode> {
+        if !self.has(PADDED) {
+            return Ok(&self.payload);
+        }
+
+        let (&padding, rest) = self
+            .payload
+            .split_first()
+            .ok_or(ErrorCode::FrameSizeError)?;
+        rest.len()
+            .checked_sub(padding as usize)
+            .map(|i| &rest[..i])
+            .ok_or(ErrorCode::ProtocolError)
+    }
+}
+
+impl Settings {
+    /// Parses the payload of a SETTINGS frame.
+    pub(crate) fn parse(payload: &[u8]) -> Result<Self, ErrorCode> {
+        if !payload.len().is_multiple_of(6) {
+            return Err(ErrorCode::FrameSizeError);
+        }
+
+        let mut settings = Self {
+            initial_window_size: None,
+            max_frame_size: None,
+        };
+        for i in payload.chunks_exact(6) {
+            let id = u16::from_be_bytes([i[0], i[1]]);
+            let value = u32::from_be_bytes([i[2], i[3], i[4], i[5]]);
+            match id {
+                // SETTINGS_ENABLE_PUSH
+                0x2 if value > 1 => return Err(ErrorCode::ProtocolError),
+                // SETTINGS_INITIAL_WINDOW_SIZE
+                0x4 if value > 0x7fff_ffff => return Err(ErrorCode::FlowControlError),
+                0x4 => settings.initial_window_size = Some(value),
+                // SETTINGS_MAX_FRAME_SIZE
+                0x5 if !(0x4000..=0xff_ffff).contains(&value) => {
+                    return Err(ErrorCode::ProtocolError)
+                }
+                0x5 => settings.max_frame_size = Some(value),
+                // Unknown settings and ones that don't affect the server are ignored
+                _ => {}
+            }
+        }
+
+        Ok(settings)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ConnectionError, ErrorCode, Frame, FrameType, Settings, END_HEADERS, PADDED};
+
+    #[test]
+    fn test_frame() {
+        let frame = Frame::new(FrameType::Headers, END_HEADERS, 3, b"abc".to_vec());
+        let mut out = Vec::new();
+        assert_eq!(frame.write(&mut out).unwrap(), 12);
+        assert_eq!(out, b"\x00\x00\x03\x01\x04\x00\x00\x00\x03abc");
+
+        let frame = Frame::read(&mut &out[..], 16_384).unwrap();
+        assert_eq!(frame.kind, FrameType::Headers);
+        assert!(frame.has(END_HEADERS));
+        assert_eq!(frame.stream, 3);
+        assert_eq!(frame.payload, b"abc");
+
+        assert!(matches!(
+            Frame::read(&mut &out[..], 2),
+            Err(ConnectionError::Protocol(ErrorCode::FrameSizeError))
+        ));
+    }
+
+    #[test]
+    fn test_unpadded() {
+        let frame = Frame::new(FrameType::Data, PADDED, 1, b"\x02abcxx".to_vec());
+        assert_eq!(frame.unpadded().unwrap(), b"abc");
+
+        let frame = Frame::new(FrameType::Data, PADDED, 1, b"\x05abc".to_vec());
+        assert_eq!(frame.unpadded(), Err(ErrorCode::ProtocolError));
+    }
+
+    #[test]
+    fn test_settings() {
+        let frame = Frame::settings(&[(0x3, 100), (0x4, 1 << 20), (0x5, 1 << 15)]);
+        let settings = Settings::parse(&frame.payload).unwrap();
+        assert_eq!(settings.initial_window_size, Some(1 << 20));
+        assert_eq!(settings.max_frame_size, Some(1 << 15));
+
+        let frame = Frame::settings(&[(0x5, 100)]);
+        assert_eq!(
+            Settings::parse(&frame.payload).unwrap_err(),
+            ErrorCode::ProtocolError
+        );
+        assert_eq!(
+            Settings::parse(b"\x00\x04").unwrap_err(),
+            ErrorCode::FrameSizeError
+        );
+    }
+}
@@ -0,0 +1,385 @@
+//! HPACK, the header compression used by HTTP/2.
+//! - Reference: <https://www.rfc-editor.org/rfc/rfc7541>
+
+use std::{collections::VecDeque, sync::OnceLock};
+
+use super::huffman;
+
+/// Headers that are common enough to be referenced by index in every connection.
+const STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+/// The size each entry takes up in the dynamic table, on top of its name and value.
+const ENTRY_OVERHEAD: usize = 32;
+
+/// A header name and value, which don't have to be valid UTF-8.
+pub(crate) type Field = (Vec<u8>, Vec<u8>);
+
+/// Decodes header blocks from the client.
+/// The dynamic table is kept between blocks, so every block on a connection has to be decoded in order.
+pub(crate) struct Decoder {
+    /// The headers the client has added to the dynamic table, newest first.
+    table: VecDeque<Field>,
+    /// The size of the entries in the table.
+    size: usize,
+    /// The max size of the table, which the client can change up to `limit`.
+    max_size: usize,
+    /// The largest table the client is allowed to use, from the server's `SETTINGS_HEADER_TABLE_SIZE`.
+    limit: usize,
+}
+
+impl Decoder {
+    /// Creates a new decoder with an empty dynamic table of up to `limit` bytes.
+    pub(crate) fn new(limit: usize) -> Self {
+        Self {
+            table: VecDeque::new(),
+            size: 0,
+            max_size: limit,
+            limit,
+        }
+    }
+
+    /// Decodes a header block into its headers.
+    /// Returns None if the block is invalid, which breaks the connection as the tables can't be kept in sync anymore.
+    pub(crate) fn decode(&mut self, mut block: &[u8]) -> Option<Vec<Field>> {
+        let mut headers = Vec::new();
+        while let Some(&first) = block.first() {
+            if first & 0x80 != 0 {
+                // Indexed header field
+                let index = decode_integer(&mut block, 7)?;
+                headers.push(self.get(index)?.clone());
+            } else if first & 0x40 != 0 {
+                // Literal header field with incremental indexing
+                let field = self.literal(&mut block, 6)?;
+                self.insert(field.clone());
+                headers.push(field);
+            } else if first & 0x20 != 0 {
+                // Dynamic table size update
+                let size = decode_integer(&mut block, 5)?;
+                if size > self.limit {
+                    return None;
+                }
+                self.max_size = size;
+                self.evict(0);
+            } else {
+                // Literal header field without indexing or never indexed
+                headers.push(self.literal(&mut block, 4)?);
+            }
+        }
+
+        Some(headers)
+    }
+
+    /// Gets an entry by its index, with the static table first.
+    fn get(&self, index: usize) -> Option<&Field> {
+        static FIELDS: OnceLock<Vec<Field>> = OnceLock::new();
+        let fields = FIELDS.get_or_init(|| {
+            STATIC_TABLE
+                .iter()
+                .map(|(name, value)| (name.as_bytes().to_vec(), value.as_bytes().to_vec()))
+                .collect()
+        });
+
+        match index {
+            0 => None,
+            i if i <= fields.len() => fields.get(i - 1),
+            i => self.table.get(i - fields.len() - 1),
+        }
+    }
+
+    /// Decodes a literal header field, whose name is an index if the prefix isn't zero.
+    fn literal(&self, block: &mut &[u8], prefix: u8) -> Option<Field> {
+        let name = match decode_integer(block, prefix)? {
+            0 => decode_string(block)?,
+            index => self.get(index)?.0.clone(),
+        };
+        Some((name, decode_string(block)?))
+    }
+
+    /// Adds an entry to the dynamic table, evicting the oldest entries to make room.
+    /// Entries bigger than the whole table just empty it.
+    fn insert(&mut self, field: Field) {
+        let size = field.0.len() + field.1.len() + ENTRY_OVERHEAD;
+        self.evict(size);
+        if size <= self.max_size {
+            self.size += size;
+            self.table.push_front(field);
+        }
+    }
+
+    /// Evicts entries until there is room for `size` more bytes.
+    fn evict(&mut self, size: usize) {
+        while self.size + size > self.max_size {
+            match self.table.pop_back() {
+                Some((name, value)) => self.size -= name.len() + value.len() + ENTRY_OVERHEAD,
+                None => break,
+            }
+        }
+    }
+}
+
+/// Encodes a header, adding it to a header block.
+/// Headers are sent as literals without indexing (or as an index into the static table if it has the whole header), so no dynamic table has to be kept.
+/// The name has to be lowercase.
+pub(crate) fn encode(name: &str, value: &str, out: &mut Vec<u8>) {
+    let mut name_index = None;
+    for (i, (static_name, static_value)) in STATIC_TABLE.iter().enumerate() {
+        if *static_name != name {
+            continue;
+        }
+
+        if *static_value == value {
+            encode_integer(i + 1, 7, 0x80, out);
+            return;
+        }
+        name_index = name_index.or(Some(i + 1));
+    }
+
+    match name_index {
+        Some(i) => encode_integer(i, 4, 0, out),
+        None => {
+            out.push(0);
+            encode_string(name.as_bytes(), out);
+        }
+    }
+    encode_string(value.as_bytes(), out);
+}
+
+/// Decodes an integer whose first byte has `prefix` bits for it.
+/// Values that don't fit in the prefix are continued in the next bytes, 7 bits at a time.
+fn decode_integer(block: &mut &[u8], prefix: u8) -> Option<usize> {
+    let (&first, rest) = block.split_first()?;
+    *block = rest;
+
+    let max = (1 << prefix) - 1;
+    let mut value = usize::from(first) & max;
+    if value < max {
+        return Some(value);
+    }
+
+    let mut shift = 0;
+    loop {
+        let (&byte, rest) = block.split_first()?;
+        *block = rest;
+
+        // Values that would overflow aren't valid anyway
+        if shift > 28 {
+            return None;
+        }
+        value = value.checked_add(usize::from(byte & 0x7f) << shift)?;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+        shift += 7;
+    }
+}
+
+/// Encodes an integer with `prefix` bits in the first byte, whose other bits are set to `flags`.
+fn encode_integer(mut value: usize, prefix: u8, flags: u8, out: &mut Vec<u8>) {
+    let max = (1 << prefix) - 1;
+    if value < max {
+        out.push(flags | value as u8);
+        return;
+    }
+
+    out.push(flags | max as u8);
+    value -= max;
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// Decodes a string, which may be Huffman encoded.
+fn decode_string(block: &mut &[u8]) -> Option<Vec<u8>> {
+    let huffman = block.first()? & 0x80 != 0;
+    let len = decode_integer(block, 7)?;
+    if len > block.len() {
+        return None;
+    }
+
+    let (data, rest) = block.split_at(len);
+    *block = rest;
+    match huffman {
+        true => huffman::decode(data),
+        false => Some(data.to_vec()),
+    }
+}
+
+/// Encodes a string, with Huffman encoding if it makes it shorter.
+fn encode_string(data: &[u8], out: &mut Vec<u8>) {
+    let encoded = huffman::encode(data);
+    match encoded.len() < data.len() {
+        true => {
+            encode_integer(encoded.len(), 7, 0x80, out);
+            out.extend_from_slice(&encoded);
+        }
+        false => {
+            encode_integer(data.len(), 7, 0, out);
+            out.extend_from_slice(data);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decode_integer, encode, encode_integer, Decoder};
+
+    fn fields(headers: &[(&str, &str)]) -> Vec<(Vec<u8>, Vec<u8>)> {
+        headers
+            .iter()
+            .map(|(name, value)| (name.as_bytes().to_vec(), value.as_bytes().to_vec()))
+            .collect()
+    }
+
+    #[test]
+    fn test_integer() {
+        // From RFC 7541, appendix C.1
+        let mut out = Vec::new();
+        encode_integer(10, 5, 0, &mut out);
+        encode_integer(1337, 5, 0, &mut out);
+        encode_integer(42, 8, 0, &mut out);
+        assert_eq!(out, [0x0a, 0x1f, 0x9a, 0x0a, 0x2a]);
+
+        let mut block = &out[..];
+        assert_eq!(decode_integer(&mut block, 5), Some(10));
+        assert_eq!(decode_integer(&mut block, 5), Some(1337));
+        assert_eq!(decode_integer(&mut block, 8), Some(42));
+        assert!(block.is_empty());
+
+        assert_eq!(decode_integer(&mut &[0x1f, 0xff, 0xff][..], 5), None);
+    }
+
+    #[test]
+    fn test_decode() {
+        // From RFC 7541, appendix C.3 and C.4
+        let mut decoder = Decoder::new(4096);
+        let block = b"\x82\x86\x84\x41\x0fwww.example.com";
+        assert_eq!(
+            decoder.decode(block).unwrap(),
+            fields(&[
+                (":method", "GET"),
+                (":scheme", "http"),
+                (":path", "/"),
+                (":authority", "www.example.com"),
+            ])
+        );
+
+        // Uses the authority added to the dynamic table by the last block
+        let block = b"\x82\x86\x84\xbe\x58\x86\xa8\xeb\x10\x64\x9c\xbf";
+        assert_eq!(
+            decoder.decode(block).unwrap(),
+            fields(&[
+                (":method", "GET"),
+                (":scheme", "http"),
+                (":path", "/"),
+                (":authority", "www.example.com"),
+                ("cache-control", "no-cache"),
+            ])
+        );
+        assert_eq!(decoder.table.len(), 2);
+        assert_eq!(decoder.size, 110);
+
+        // Index out of range
+        assert_eq!(decoder.decode(b"\xc1"), None);
+        // Table size update larger than allowed
+        assert_eq!(decoder.decode(b"\x3f\xe2\x1f"), None);
+    }
+
+    #[test]
+    fn test_eviction() {
+        let mut decoder = Decoder::new(100);
+        let mut block = b"\x40\x01a\x28".to_vec();
+        block.extend_from_slice(&[b'a'; 40]);
+        decoder.decode(&block).unwrap();
+        decoder.decode(b"\x40\x01b\x01b").unwrap();
+        assert_eq!(decoder.table.len(), 1);
+        assert_eq!(decoder.get(62).unwrap().0, b"b");
+
+        // Shrinking the table evicts everything
+        decoder.decode(b"\x20").unwrap();
+        assert_eq!(decoder.table.len(), 0);
+    }
+
+    #[test]
+    fn test_encode() {
+        let mut block = Vec::new();
+        encode(":status", "200", &mut block);
+        encode(":status", "201", &mut block);
+        encode("content-type", "text/plain", &mut block);
+        encode("x-custom", "Hello", &mut block);
+        assert_eq!(block[0], 0x88);
+
+        assert_eq!(
+            Decoder::new(4096).decode(&block).unwrap(),
+            fields(&[
+                (":status", "200"),
+                (":status", "201"),
+                ("content-type", "text/plain"),
+                ("x-custom", "Hello"),
+            ])
+        );
+    }
+}
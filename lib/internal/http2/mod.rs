@@ -0,0 +1,912 @@
+//! Experimental HTTP/2 support, enabled with the `http2` feature.
+//!
+//! Connections that start with the HTTP/2 connection preface are handled here instead of as HTTP/1.
+//! Clients send it right away after negotiating `h2` with ALPN on a TLS connection (see [`crate::listener::TlsInfo::alpn`]), or on cleartext connections if they already know the server supports HTTP/2.
+//! Cleartext connections can also switch from HTTP/1.1 with an `Upgrade: h2c` request, see [`upgrade_settings`].
+//! Requests go through the same middleware and routes as HTTP/1 requests.
+//!
+//! Streams are handled one at a time, and clients are told to only open one at a time with `SETTINGS_MAX_CONCURRENT_STREAMS`.
+//! Request bodies are always read before the request is handled, and server push isn't supported.
+//! - Reference: <https://www.rfc-editor.org/rfc/rfc9113>
+
+use std::{
+    cell::{Cell, RefCell},
+    io::{self, Read, Write},
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use crate::{
+    cookie::CookieJar,
+    error::{self, ParseError},
+    header::Headers,
+    internal::{
+        common::ForceLock,
+        encoding::base64,
+        handle::{
+            close_socket, end_middleware, finish_response, get_response, log_socket_error,
+            prepare_request, wait_for_request, ConnectionState, ResponseInfo,
+        },
+        parser::parse_request_line,
+    },
+    listener::Stream,
+    request::{ConnectionInfo, Extensions},
+    response::{read_retry, ResponseBody, ResponseFlag, WriteStats},
+    timing::{Phase, Timing},
+    trace, Cookie, Header, HeaderType, Method, Request, Response, Server, Status,
+};
+
+use self::{
+    frame::{
+        ConnectionError, ErrorCode, Frame, FrameType, Settings, ACK, END_HEADERS, END_STREAM,
+        PRIORITY,
+    },
+    hpack::{Decoder, Field},
+};
+
+mod frame;
+mod hpack;
+mod huffman;
+
+pub(crate) use self::frame::PREFACE;
+
+/// The flow control window of the connection and new streams before the client changes it.
+const DEFAULT_WINDOW: i64 = 65_535;
+
+/// The largest frame payload either side can send before the other changes it.
+const DEFAULT_FRAME_SIZE: usize = 16_384;
+
+/// The largest flow control window allowed.
+const MAX_WINDOW: i64 = 0x7fff_ffff;
+
+/// The size of the table the client can use to compress headers.
+const HEADER_TABLE_SIZE: usize = 4096;
+
+/// Headers that only apply to a single HTTP/1 connection, which aren't allowed in HTTP/2.
+const CONNECTION_HEADERS: [&str; 5] = [
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Checks if a new connection starts with the HTTP/2 preface, only reading as much as it takes to tell.
+/// If it doesn't, the bytes that were read are left in [`ConnectionState::pending`] to be parsed as the start of an HTTP/1 request.
+/// The client has [`Server::request_line_timeout`] to send the preface, like it would for a request line.
+pub(crate) fn read_preface<State>(
+    stream: &Mutex<Box<dyn Stream>>,
+    connection: &mut ConnectionState,
+    this: &Server<State>,
+) -> io::Result<bool>
+where
+    State: 'static + Send + Sync,
+{
+    let mut stream = stream.force_lock();
+    if let Some(timeout) = this.request_line_timeout {
+        let timeout = this.socket_timeout.map_or(timeout, |i| i.min(timeout));
+        stream.set_read_timeout(Some(timeout))?;
+    }
+
+    let pending = &mut connection.pending;
+    while pending.len() < PREFACE.len() && PREFACE.starts_with(pending) {
+        let start = pending.len();
+        pending.resize(PREFACE.len(), 0);
+        let read = stream.read(&mut pending[start..]);
+        pending.truncate(start + *read.as_ref().unwrap_or(&0));
+        if read? == 0 {
+            break;
+        }
+    }
+
+    stream.set_read_timeout(this.socket_timeout)?;
+    if pending[..] != PREFACE[..] {
+        return Ok(false);
+    }
+
+    pending.clear();
+    Ok(true)
+}
+
+/// Checks if an HTTP/1.1 request asks to switch to HTTP/2 with `Upgrade: h2c`, returning the client's settings from its `HTTP2-Settings` header if it does.
+/// Upgrades are only done on cleartext connections, and not for requests whose streamed body hasn't been read, as the rest of it would be sent as HTTP/1.
+/// Requests that can't be upgraded are just handled as HTTP/1.1, which is how clients expect servers without HTTP/2 support to respond.
+/// - Reference: <https://www.rfc-editor.org/rfc/rfc7540#section-3.2>
+pub(crate) fn upgrade_settings(req: &Request, connection: &ConnectionState) -> Option<Settings> {
+    let tokens = |header| {
+        req.headers
+            .get_all(header)
+            .flat_map(|i| i.split(','))
+            .map(|i| i.trim().to_ascii_lowercase())
+            .collect::<Vec<_>>()
+    };
+    let connection_tokens = tokens(HeaderType::Connection);
+    let wanted = req.version == "HTTP/1.1"
+        && tokens(HeaderType::Upgrade).iter().any(|i| i == "h2c")
+        && connection_tokens.iter().any(|i| i == "upgrade")
+        && connection_tokens.iter().any(|i| i == "http2-settings")
+        && matches!(connection.info.tls.get(), Some(None))
+        && req.body_finished();
+    let mut headers = req.headers.get_all("HTTP2-Settings");
+    match (headers.next(), headers.next()) {
+        (Some(header), None) if wanted => decode_settings(header),
+        _ => None,
+    }
+}
+
+/// Decodes the `HTTP2-Settings` header of an upgrade request, which is a SETTINGS frame payload encoded with unpadded base64url.
+fn decode_settings(header: &str) -> Option<Settings> {
+    let header = header.trim();
+    if header.contains(['+', '/', '=']) {
+        return None;
+    }
+
+    let mut header = header.replace('-', "+").replace('_', "/");
+    while !header.len().is_multiple_of(4) {
+        header.push('=');
+    }
+
+    Settings::parse(&base64::decode(&header)?).ok()
+}
+
+/// Handles an HTTP/2 connection, once its preface has been read or its upgrade request has been received.
+/// Returns after the connection has been closed.
+pub(crate) fn handle<State>(
+    stream: &Arc<Mutex<Box<dyn Stream>>>,
+    this: &Server<State>,
+    connection: &mut ConnectionState,
+    upgrade: Option<Upgrade>,
+) where
+    State: 'static + Send + Sync,
+{
+    trace!(Level::Debug, "Handling HTTP/2 connection");
+    connection
+        .info
+        .tls
+        .get_or_init(|| stream.force_lock().tls_info());
+
+    let mut h2 = Connection::new(stream.clone(), connection.info.clone(), this);
+    let code = match h2.run(connection, upgrade) {
+        Ok(()) => ErrorCode::NoError,
+        Err(ConnectionError::Protocol(code)) => {
+            trace!(Level::Debug, "HTTP/2 connection error: {:?}", code);
+            code
+        }
+        Err(ConnectionError::Io(e)) => {
+            match e.kind() {
+                io::ErrorKind::UnexpectedEof => {
+                    trace!(Level::Debug, "Client closed the connection");
+                }
+                _ => log_socket_error("using", &e),
+            }
+            close_socket(stream);
+            return;
+        }
+    };
+
+    if let Err(e) = h2.write_frame(&Frame::go_away(h2.last_stream, code)) {
+        log_socket_error("closing", &e);
+    }
+    close_socket(stream);
+}
+
+/// A request that asked to switch its connection to HTTP/2, which is answered on stream 1 once it has switched.
+pub(crate) struct Upgrade {
+    pub req: Request,
+    /// The client's settings, from [`upgrade_settings`].
+    pub settings: Settings,
+}
+
+/// The state of an HTTP/2 connection.
+struct Connection<'a, State: 'static + Send + Sync> {
+    stream: Arc<Mutex<Box<dyn Stream>>>,
+    info: Arc<ConnectionInfo>,
+    server: &'a Server<State>,
+    /// Decodes the headers of requests, which are compressed with a table shared by the whole connection.
+    decoder: Decoder,
+    /// The id of the last stream the client opened.
+    last_stream: u32,
+    /// The stream that is being handled.
+    active: Option<u32>,
+    /// If the client reset the active stream.
+    reset: bool,
+    /// If the client sent a GOAWAY frame, so it won't open any more streams.
+    closing: bool,
+    /// How much the server can send on the connection, from the client's flow control window.
+    window: i64,
+    /// How much the server can send on the active stream.
+    stream_window: i64,
+    /// The flow control window of new streams, from the client's settings.
+    initial_window: i64,
+    /// The largest frame payload the client accepts.
+    max_frame_size: usize,
+    /// The number of bytes written to the socket.
+    written: u64,
+}
+
+impl<'a, State: 'static + Send + Sync> Connection<'a, State> {
+    fn new(
+        stream: Arc<Mutex<Box<dyn Stream>>>,
+        info: Arc<ConnectionInfo>,
+        server: &'a Server<State>,
+    ) -> Self {
+        Self {
+            stream,
+            info,
+            server,
+            decoder: Decoder::new(HEADER_TABLE_SIZE),
+            last_stream: 0,
+            active: None,
+            reset: false,
+            closing: false,
+            window: DEFAULT_WINDOW,
+            stream_window: DEFAULT_WINDOW,
+            initial_window: DEFAULT_WINDOW,
+            max_frame_size: DEFAULT_FRAME_SIZE,
+            written: 0,
+        }
+    }
+
+    /// Handles streams until the connection should be closed.
+    /// For upgraded connections, the 101 response is sent first and the upgrade request is answered on stream 1.
+    fn run(
+        &mut self,
+        connection: &mut ConnectionState,
+        upgrade: Option<Upgrade>,
+    ) -> Result<(), ConnectionError> {
+        if upgrade.is_some() {
+            let mut stream = self.stream.force_lock();
+            stream.write_all(
+                b"HTTP/1.1 101 Switching Protocols\r\nConnection: Upgrade\r\nUpgrade: h2c\r\n\r\n",
+            )?;
+            stream.flush()?;
+        }
+
+        self.write_frame(&Frame::settings(&[
+            // SETTINGS_HEADER_TABLE_SIZE
+            (0x1, HEADER_TABLE_SIZE as u32),
+            // SETTINGS_MAX_CONCURRENT_STREAMS
+            (0x3, 1),
+            // SETTINGS_MAX_HEADER_LIST_SIZE
+            (
+                0x6,
+                self.server.max_header_size.min(u32::MAX as usize) as u32,
+            ),
+        ]))?;
+
+        if let Some(Upgrade { req, settings }) = upgrade {
+            self.apply_settings(settings);
+            // The client sends the preface once it gets the 101 response
+            if !read_preface(&self.stream, connection, self.server)? {
+                return Err(ErrorCode::ProtocolError.into());
+            }
+
+            self.open_stream(1)?;
+            // The request was already counted when it was read as HTTP/1
+            let info = ResponseInfo::http2(self.info.requests());
+            if !self.respond_to(1, Ok(req), info, true)? {
+                return Ok(());
+            }
+        }
+
+        // Idle connections are closed like keep-alive connections that don't send another request
+        while !self.closing && wait_for_request(&self.stream, self.server) {
+            let frame = self.read_frame()?;
+            if frame.kind != FrameType::Headers {
+                self.control(frame)?;
+                continue;
+            }
+
+            if !self.handle_stream(frame)? {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Handles a request, from the HEADERS frame that opens its stream.
+    /// Returns false if the connection should be closed.
+    fn handle_stream(&mut self, frame: Frame) -> Result<bool, ConnectionError> {
+        let start = Instant::now();
+        let id = frame.stream;
+        let end_stream = frame.has(END_STREAM);
+        self.open_stream(id)?;
+        let fields = self.read_headers(frame)?;
+
+        let req = match self.request(fields, start) {
+            Ok(mut req) => {
+                let options = prepare_request(&mut req, self.server);
+                match end_stream {
+                    true => Ok(req),
+                    false => self.read_body(id, options.max_size)?.map(|body| {
+                        req.body = Arc::new(body);
+                        req
+                    }),
+                }
+            }
+            Err(e) => Err(e),
+        };
+
+        // The client doesn't want the response anymore
+        if self.reset {
+            self.active = None;
+            return Ok(true);
+        }
+
+        if let Ok(req) = &req {
+            req.mark(Phase::Parse);
+        }
+        let finished = end_stream || req.is_ok();
+        let info = ResponseInfo::http2(self.info.next_request());
+        self.respond_to(id, req, info, finished)
+    }
+
+    /// Runs the request of a stream through the server and sends the response.
+    /// `finished` is if the client has sent the whole stream, otherwise it is reset once the response is sent.
+    /// Returns false if the connection should be closed.
+    fn respond_to(
+        &mut self,
+        id: u32,
+        req: error::Result<Request>,
+        info: ResponseInfo,
+        finished: bool,
+    ) -> Result<bool, ConnectionError> {
+        if let Ok(req) = &req {
+            trace!(
+                Level::Debug,
+                "{} {} {{ stream: {} }}",
+                req.method,
+                req.path,
+                id
+            );
+        }
+
+        let (req, mut res) = get_response(req, info, self.server);
+        if res.flag == ResponseFlag::End || req.as_ref().is_some_and(|i| i.socket_consumed()) {
+            trace!(Level::Debug, "Ending HTTP/2 connection");
+            return Ok(false);
+        }
+
+        if let Some(req) = req.as_ref().filter(|_| self.server.server_timing) {
+            res.headers
+                .add(HeaderType::ServerTiming, req.timing().server_timing());
+        }
+        finish_response(&mut res, info, self.server);
+
+        let head = req.as_ref().is_some_and(|i| i.method == Method::HEAD);
+        let result = self.respond(id, &mut res, head);
+
+        // Ask the client to stop sending a body that won't be read
+        let result = match (result, finished || self.reset) {
+            (Ok(()), false) => self
+                .write_frame(&Frame::reset(id, ErrorCode::NoError))
+                .map(|_| ())
+                .map_err(ConnectionError::Io),
+            (result, _) => result,
+        };
+
+        if let Some(req) = req {
+            req.mark(Phase::Write);
+            end_middleware(&req, &res, self.server);
+        }
+
+        result?;
+        self.active = None;
+        Ok(res.flag != ResponseFlag::Close)
+    }
+
+    /// Checks that the client can open a stream, and makes it the active stream.
+    fn open_stream(&mut self, id: u32) -> Result<(), ConnectionError> {
+        // Stream ids have to be odd and increasing
+        if id.is_multiple_of(2) || id <= self.last_stream {
+            return Err(ErrorCode::ProtocolError.into());
+        }
+
+        self.last_stream = id;
+        self.active = Some(id);
+        self.reset = false;
+        self.stream_window = self.initial_window;
+        Ok(())
+    }
+
+    /// Reads the header block started by a HEADERS frame, along with any CONTINUATION frames, and decodes it.
+    fn read_headers(&mut self, frame: Frame) -> Result<Vec<Field>, ConnectionError> {
+        let mut block = frame.unpadded()?;
+        if frame.has(PRIORITY) {
+            block = block.get(5..).ok_or(ErrorCode::FrameSizeError)?;
+        }
+
+        // Compressed headers are smaller than the decoded ones, which are limited by the server's max header size
+        let max_size = self.server.max_header_size.max(DEFAULT_FRAME_SIZE);
+        let mut block = block.to_vec();
+        let mut end = frame.has(END_HEADERS);
+        while !end {
+            let next = self.read_frame()?;
+            if next.kind != FrameType::Continuation || next.stream != frame.stream {
+                return Err(ErrorCode::ProtocolError.into());
+            }
+
+            if block.len() + next.payload.len() > max_size {
+                return Err(ErrorCode::EnhanceYourCalm.into());
+            }
+            block.extend_from_slice(&next.payload);
+            end = next.has(END_HEADERS);
+        }
+
+        Ok(self
+            .decoder
+            .decode(&block)
+            .ok_or(ErrorCode::CompressionError)?)
+    }
+
+    /// Makes a request from the headers of a stream.
+    fn request(&self, fields: Vec<Field>, start: Instant) -> error::Result<Request> {
+        let (mut method, mut path, mut authority) = (None, None, None);
+        let mut headers = Vec::new();
+        let mut cookies = Vec::new();
+        let mut lines = Vec::new();
+        let mut size = 0;
+        for (name, value) in fields {
+            let (Ok(name), Ok(value)) = (String::from_utf8(name), String::from_utf8(value)) else {
+                return Err(ParseError::InvalidHeader.into());
+            };
+
+            // Each header counts for an extra 32 bytes, like in SETTINGS_MAX_HEADER_LIST_SIZE
+            size += name.len() + value.len() + 32;
+            if size > self.server.max_header_size
+                || headers.len() + cookies.len() >= self.server.max_headers
+            {
+                return Err(ParseError::HeadersTooLarge.into());
+            }
+
+            if name.bytes().any(|i| i.is_ascii_uppercase())
+                || value.contains(['\r', '\n', '\0'])
+                || CONNECTION_HEADERS.contains(&name.as_str())
+                || (name == "te" && value != "trailers")
+            {
+                return Err(ParseError::InvalidHeader.into());
+            }
+
+            match name.as_str() {
+                ":method" => method = Some(value),
+                ":path" => path = Some(value),
+                ":authority" => authority = Some(value),
+                ":scheme" => {}
+                i if i.starts_with(':') => return Err(ParseError::InvalidHeader.into()),
+                _ => {
+                    lines.extend_from_slice(format!("{name}: {value}\r\n").as_bytes());
+                    match name.as_str() {
+                        "cookie" => cookies.extend(Cookie::from_string(&value)),
+                        _ => headers.push(Header::new(name, value)),
+                    }
+                }
+            }
+        }
+
+        let method = method.ok_or(ParseError::NoMethod)?;
+        let path = path
+            .filter(|i| !i.is_empty() && !i.contains(char::is_whitespace))
+            .ok_or(ParseError::NoPath)?;
+        self.server.uri_policy.check(path.as_bytes())?;
+        let mut raw_head = format!("{method} {path} HTTP/2\r\n").into_bytes();
+        let (method, path, query, version, _) = parse_request_line(&raw_head)?;
+        raw_head.extend_from_slice(&lines);
+        raw_head.extend_from_slice(b"\r\n");
+
+        // The authority replaces the Host header (RFC 9113, section 8.3.1)
+        if let Some(authority) = authority {
+            headers.retain(|i| i.name != HeaderType::Host);
+            headers.push(Header::new(HeaderType::Host, authority));
+        }
+
+        let socket = self.stream.force_lock();
+        let (address, local_address) = (socket.peer_addr()?, socket.local_addr().ok());
+        drop(socket);
+
+        Ok(Request {
+            method,
+            path,
+            version,
+            path_params: RefCell::new(Vec::new()),
+            route_meta: RefCell::new(None),
+            query,
+            headers: Headers(headers),
+            cookies: CookieJar::from_vec(cookies),
+            body: Arc::new(Vec::new()),
+            address,
+            local_address,
+            socket: self.stream.clone(),
+            timing: Cell::new(Timing::new(start)),
+            extensions: Extensions::default(),
+            body_stream: RefCell::new(None),
+            connection: self.info.clone(),
+            raw_head,
+            socket_consumed: Cell::new(false),
+            clock: self.server.clock.clone(),
+            url_config: self.server.url_config.clone(),
+            web_socket_config: self.server.web_socket_config.clone(),
+            shutdown: self.server.shutdown.token(),
+        })
+    }
+
+    /// Reads the body of a stream from its DATA frames, handling any other frames received along the way.
+    /// Stops early if the client resets the stream, see [`Connection::reset`].
+    fn read_body(
+        &mut self,
+        id: u32,
+        max_size: Option<usize>,
+    ) -> Result<error::Result<Vec<u8>>, ConnectionError> {
+        let mut body = Vec::new();
+        while !self.reset {
+            let frame = self.read_frame()?;
+            let end = frame.has(END_STREAM);
+            match frame.kind {
+                FrameType::Data if frame.stream == id => {}
+                // Trailers, which are ignored
+                FrameType::Headers if frame.stream == id => {
+                    self.read_headers(frame)?;
+                    match end {
+                        true => break,
+                        false => return Err(ErrorCode::ProtocolError.into()),
+                    }
+                }
+                _ => {
+                    self.control(frame)?;
+                    continue;
+                }
+            }
+
+            // Let the client send more
+            let len = frame.payload.len() as u32;
+            if len > 0 {
+                self.write_frame(&Frame::window_update(0, len))?;
+                if !end {
+                    self.write_frame(&Frame::window_update(id, len))?;
+                }
+            }
+
+            let data = frame.unpadded()?;
+            if max_size.is_some_and(|i| body.len() + data.len() > i) {
+                return Ok(Err(ParseError::BodyTooLarge.into()));
+            }
+
+            body.extend_from_slice(data);
+            if end {
+                break;
+            }
+        }
+
+        Ok(Ok(body))
+    }
+
+    /// Applies the client's settings.
+    fn apply_settings(&mut self, settings: Settings) {
+        if let Some(size) = settings.initial_window_size {
+            // This also changes the window of the active stream
+            let size = i64::from(size);
+            self.stream_window += size - self.initial_window;
+            self.initial_window = size;
+        }
+        if let Some(size) = settings.max_frame_size {
+            self.max_frame_size = size as usize;
+        }
+    }
+
+    /// Handles a frame that doesn't open a stream or carry the body of the active one.
+    fn control(&mut self, frame: Frame) -> Result<(), ConnectionError> {
+        let connection_frame = matches!(
+            frame.kind,
+            FrameType::Settings | FrameType::Ping | FrameType::GoAway
+        );
+        let any_stream = matches!(frame.kind, FrameType::WindowUpdate | FrameType::Unknown(_));
+        if connection_frame != (frame.stream == 0) && !any_stream {
+            return Err(ErrorCode::ProtocolError.into());
+        }
+
+        match frame.kind {
+            FrameType::Settings if frame.has(ACK) => {}
+            FrameType::Settings => {
+                self.apply_settings(Settings::parse(&frame.payload)?);
+                self.write_frame(&Frame::new(FrameType::Settings, ACK, 0, Vec::new()))?;
+            }
+            FrameType::Ping if frame.payload.len() != 8 => {
+                return Err(ErrorCode::FrameSizeError.into())
+            }
+            FrameType::Ping if !frame.has(ACK) => {
+                self.write_frame(&Frame::new(FrameType::Ping, ACK, 0, frame.payload))?;
+            }
+            FrameType::WindowUpdate => {
+                let [a, b, c, d] = frame.payload[..] else {
+                    return Err(ErrorCode::FrameSizeError.into());
+                };
+                let size = i64::from(u32::from_be_bytes([a, b, c, d]) & 0x7fff_ffff);
+                if size == 0 {
+                    return Err(ErrorCode::ProtocolError.into());
+                }
+
+                let window = match frame.stream {
+                    0 => &mut self.window,
+                    i if Some(i) == self.active => &mut self.stream_window,
+                    _ => return Ok(()),
+                };
+                *window += size;
+                if *window > MAX_WINDOW {
+                    return Err(ErrorCode::FlowControlError.into());
+                }
+            }
+            FrameType::RstStream if frame.payload.len() != 4 => {
+                return Err(ErrorCode::FrameSizeError.into())
+            }
+            FrameType::RstStream if frame.stream > self.last_stream => {
+                return Err(ErrorCode::ProtocolError.into())
+            }
+            FrameType::RstStream => self.reset |= Some(frame.stream) == self.active,
+            FrameType::GoAway => self.closing = true,
+            // Only one stream can be open at a time, but the header block still has to be decoded to keep the table in sync
+            FrameType::Headers => {
+                let id = frame.stream;
+                if id.is_multiple_of(2) || id <= self.last_stream {
+                    return Err(ErrorCode::ProtocolError.into());
+                }
+                self.last_stream = id;
+                self.read_headers(frame)?;
+                self.write_frame(&Frame::reset(id, ErrorCode::RefusedStream))?;
+            }
+            // The rest of bodies of streams that have been closed, which still count against the connection's window
+            FrameType::Data if frame.stream <= self.last_stream => {
+                let len = frame.payload.len() as u32;
+                if len > 0 {
+                    self.write_frame(&Frame::window_update(0, len))?;
+                }
+            }
+            FrameType::Data => return Err(ErrorCode::StreamClosed.into()),
+            FrameType::Continuation | FrameType::PushPromise => {
+                return Err(ErrorCode::ProtocolError.into())
+            }
+            FrameType::Priority | FrameType::Ping | FrameType::Unknown(_) => {}
+        }
+
+        Ok(())
+    }
+
+    /// Sends a response on a stream, recording how long it took in its [`WriteStats`].
+    fn respond(&mut self, id: u32, res: &mut Response, head: bool) -> Result<(), ConnectionError> {
+        let (start, written) = (Instant::now(), self.written);
+        let result = self.write_response(id, res, head);
+        res.write_stats = Some(WriteStats {
+            bytes: self.written - written,
+            duration: start.elapsed(),
+        });
+        result
+    }
+
+    fn write_response(
+        &mut self,
+        id: u32,
+        res: &mut Response,
+        head: bool,
+    ) -> Result<(), ConnectionError> {
+        res.apply_cookies(self.server.cookie_policy.as_ref());
+        res.apply_header_policy(self.server.header_policy);
+        let bodiless = matches!(res.status, Status::NoContent | Status::NotModified);
+        if let (ResponseBody::Static(data), false) = (&res.data, bodiless) {
+            if !res.headers.has(HeaderType::ContentLength) {
+                res.headers
+                    .add(HeaderType::ContentLength, data.len().to_string());
+            }
+        }
+
+        let mut block = Vec::new();
+        hpack::encode(":status", &res.status.code().to_string(), &mut block);
+        let defaults = self.server.default_headers.iter();
+        let defaults = defaults.filter(|i| !res.headers.has(&i.name));
+        for header in res.headers.iter().chain(defaults) {
+            let name = header.name.as_str().to_ascii_lowercase();
+            if !CONNECTION_HEADERS.contains(&name.as_str()) {
+                hpack::encode(&name, &header.value, &mut block);
+            }
+        }
+
+        // HTTP requires a Date header on responses from servers with a clock
+        #[cfg(feature = "date-header")]
+        if !res.headers.has(HeaderType::Date) && !self.server.default_headers.has(HeaderType::Date)
+        {
+            let now = self.server.clock.epoch().as_secs();
+            crate::internal::date::with_imp_date(now, |date| {
+                hpack::encode("date", date, &mut block)
+            });
+        }
+
+        let empty = matches!(&res.data, ResponseBody::Static(i) if i.is_empty());
+        let end_stream = empty || bodiless || head;
+        let mut chunks = block.chunks(self.max_frame_size).peekable();
+        let mut kind = FrameType::Headers;
+        loop {
+            let chunk = chunks.next().unwrap_or_default();
+            let mut flags = 0;
+            if chunks.peek().is_none() {
+                flags |= END_HEADERS;
+            }
+            if end_stream && kind == FrameType::Headers {
+                flags |= END_STREAM;
+            }
+
+            self.write_frame(&Frame::new(kind, flags, id, chunk.to_vec()))?;
+            kind = FrameType::Continuation;
+            if flags & END_HEADERS != 0 {
+                break;
+            }
+        }
+
+        if end_stream {
+            return Ok(());
+        }
+
+        let mut data;
+        let body: &mut dyn Read = match &mut res.data {
+            ResponseBody::Static(i) => {
+                data = &i[..];
+                &mut data
+            }
+            ResponseBody::Stream(i) => i.get_mut(),
+            ResponseBody::File(i) => i,
+        };
+
+        let mut buffer = self
+            .server
+            .buffer_pool
+            .take(self.server.chunk_size.clamp(1, DEFAULT_FRAME_SIZE));
+        while let Some(window) = self.wait_for_window()? {
+            let len = window.min(buffer.len());
+            let read = match read_retry(body, &mut buffer[..len]) {
+                Ok(i) => i,
+                Err(e) => {
+                    trace!(Level::Error, "Error reading response body: {:?}", e);
+                    self.write_frame(&Frame::reset(id, ErrorCode::InternalError))?;
+                    break;
+                }
+            };
+
+            let flags = if read == 0 { END_STREAM } else { 0 };
+            self.write_frame(&Frame::new(
+                FrameType::Data,
+                flags,
+                id,
+                buffer[..read].to_vec(),
+            ))?;
+            if read == 0 {
+                break;
+            }
+            if res.flush {
+                self.stream.force_lock().flush()?;
+            }
+
+            self.window -= read as i64;
+            self.stream_window -= read as i64;
+        }
+
+        Ok(())
+    }
+
+    /// Waits until the client's flow control windows let the server send more of the active stream's body, handling frames as they arrive.
+    /// Returns the number of bytes that can be sent in the next DATA frame, or None if the client reset the stream.
+    fn wait_for_window(&mut self) -> Result<Option<usize>, ConnectionError> {
+        loop {
+            if self.reset {
+                return Ok(None);
+            }
+
+            let window = self.window.min(self.stream_window);
+            if window > 0 {
+                return Ok(Some((window as usize).min(self.max_frame_size)));
+            }
+
+            let frame = self.read_frame()?;
+            self.control(frame)?;
+        }
+    }
+
+    fn read_frame(&self) -> Result<Frame, ConnectionError> {
+        Frame::read(&mut **self.stream.force_lock(), DEFAULT_FRAME_SIZE)
+    }
+
+    fn write_frame(&mut self, frame: &Frame) -> io::Result<()> {
+        self.written += frame.write(&mut **self.stream.force_lock())? as u64;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::Cursor,
+        sync::{Arc, Mutex},
+    };
+
+    use super::PREFACE;
+    use crate::{
+        internal::testing::{CleartextStream, MemoryListener, MemoryStream},
+        HeaderType, Method, Response, Server,
+    };
+
+    #[test]
+    fn test_http2() {
+        let mut input = PREFACE.to_vec();
+        // Empty SETTINGS frame
+        input.extend_from_slice(b"\x00\x00\x00\x04\x00\x00\x00\x00\x00");
+        // HEADERS frame for `GET /` on stream 1, from RFC 7541 appendix C.3.1
+        let block = b"\x82\x86\x84\x41\x0fwww.example.com";
+        input.extend_from_slice(&[0, 0, block.len() as u8, 0x1, 0x5, 0, 0, 0, 1]);
+        input.extend_from_slice(block);
+
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let listener = MemoryListener(Mutex::new(vec![MemoryStream {
+            input: Cursor::new(input),
+            output: output.clone(),
+        }]));
+
+        let mut server = Server::<()>::new("localhost", 0);
+        server.route(Method::GET, "/", |req| {
+            Response::new().text(req.headers.get(HeaderType::Host).unwrap_or_default())
+        });
+        assert!(server.start_with_listener(listener).is_err());
+
+        // Split the output into (type, flags, stream, payload)
+        let output = output.lock().unwrap().clone();
+        let mut frames = Vec::new();
+        let mut rest = &output[..];
+        while rest.len() >= 9 {
+            let len = u32::from_be_bytes([0, rest[0], rest[1], rest[2]]) as usize;
+            let stream = u32::from_be_bytes([rest[5], rest[6], rest[7], rest[8]]);
+            frames.push((rest[3], rest[4], stream, &rest[9..9 + len]));
+            rest = &rest[9 + len..];
+        }
+        assert!(rest.is_empty());
+
+        // SETTINGS, SETTINGS ACK, HEADERS with `:status 200`, DATA, empty DATA with END_STREAM
+        let kinds = frames.iter().map(|i| (i.0, i.1, i.2)).collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            [
+                (0x4, 0x0, 0),
+                (0x4, 0x1, 0),
+                (0x1, 0x4, 1),
+                (0x0, 0x0, 1),
+                (0x0, 0x1, 1)
+            ]
+        );
+        assert_eq!(frames[2].3[0], 0x88);
+        assert_eq!(frames[3].3, b"www.example.com");
+    }
+
+    #[test]
+    fn test_http2_upgrade() {
+        let mut input = b"GET /a HTTP/1.1\r\nHost: localhost\r\nConnection: Upgrade, HTTP2-Settings\r\nUpgrade: h2c\r\nHTTP2-Settings: AAMAAABkAAQAoAAAAAIAAAAA\r\n\r\n".to_vec();
+        input.extend_from_slice(PREFACE);
+
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let listener = MemoryListener(Mutex::new(vec![CleartextStream(MemoryStream {
+            input: Cursor::new(input),
+            output: output.clone(),
+        })]));
+
+        let mut server = Server::<()>::new("localhost", 0);
+        server.route(Method::GET, "/a", |_| Response::new().text("Hello"));
+        assert!(server.start_with_listener(listener).is_err());
+
+        let output = output.lock().unwrap().clone();
+        let head =
+            b"HTTP/1.1 101 Switching Protocols\r\nConnection: Upgrade\r\nUpgrade: h2c\r\n\r\n";
+        assert!(output.starts_with(head));
+
+        // SETTINGS, then the response to the upgrade request on stream 1
+        let frames = &output[head.len()..];
+        let len = frames[2] as usize;
+        assert_eq!(frames[3], 0x4);
+        let frames = &frames[9 + len..];
+        assert_eq!(&frames[3..9], [0x1, 0x4, 0, 0, 0, 1]);
+        assert_eq!(frames[9], 0x88);
+        assert!(frames.windows(5).any(|i| i == b"Hello"));
+    }
+}
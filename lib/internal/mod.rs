@@ -3,7 +3,29 @@
 //! These may be helpful but may not remain compatible between updates.
 //! No guarantees are made about their stability, they are likely to be changed or removed.
 
+pub(crate) mod buffer_pool;
 pub mod common;
+pub mod date;
+#[cfg(all(feature = "dev", debug_assertions))]
+pub(crate) mod debug;
 pub mod encoding;
+#[cfg(unix)]
+pub(crate) mod event_loop;
 pub(crate) mod handle;
+#[cfg(feature = "http2")]
+pub(crate) mod http2;
+pub mod parser;
 pub mod path;
+#[cfg(unix)]
+pub(crate) mod peer_cred;
+pub(crate) mod reaper;
+pub(crate) mod regex;
+pub(crate) mod router;
+pub(crate) mod scheduler;
+#[cfg(all(feature = "sendfile", any(target_os = "linux", target_os = "android")))]
+pub(crate) mod sendfile;
+#[cfg(feature = "signals")]
+pub(crate) mod signal;
+pub(crate) mod socket;
+#[cfg(test)]
+pub(crate) mod testing;
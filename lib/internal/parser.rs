@@ -0,0 +1,567 @@
+//! An incremental parser for the head (request line and headers) of HTTP/1.x requests.
+//!
+//! Data is given to the parser as it is received with [`HeadParser::feed`], in chunks of any size.
+//! Each byte is only scanned once, so a head split across many reads (like large headers arriving in multiple TCP segments) is never parsed from the start again.
+//! The parser doesn't do any IO itself, which also lets it be fuzz tested directly.
+//!
+//! ## Example
+//! ```rust
+//! # use afire::internal::parser::{HeadLimits, HeadParser};
+//! let mut parser = HeadParser::new(HeadLimits::default());
+//! assert_eq!(parser.feed(b"GET /hello HT").unwrap(), 13);
+//! assert!(!parser.is_done());
+//!
+//! // Bytes after the end of the head (the start of the body) are not used
+//! let data = b"TP/1.1\r\nHost: localhost\r\n\r\nbody";
+//! assert_eq!(parser.feed(data).unwrap(), data.len() - 4);
+//!
+//! let head = parser.finish().unwrap();
+//! assert_eq!(head.path, "/hello");
+//! assert_eq!(head.headers[0].value, "localhost");
+//! ```
+
+use std::str::FromStr;
+
+use crate::{
+    error::{Error, ParseError, Result},
+    Cookie, Header, HeaderType, Method, Query, UriPolicy,
+};
+
+/// Limits on the size of a request head, see [`crate::Server::max_request_line`], [`crate::Server::max_header_size`] and [`crate::Server::max_headers`].
+#[derive(Debug, Clone, Copy)]
+pub struct HeadLimits {
+    /// The maximum length of the request line in bytes, including the line ending.
+    pub request_line: usize,
+    /// The maximum total size of the headers in bytes, including line endings and the empty line at the end.
+    pub header_size: usize,
+    /// The maximum number of headers.
+    pub headers: usize,
+    /// How the request target is checked.
+    pub uri_policy: UriPolicy,
+}
+
+/// The part of the head a [`HeadParser`] is waiting for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseState {
+    /// The request line hasn't been fully received.
+    RequestLine,
+    /// The request line was parsed, and headers are being received.
+    Headers,
+    /// The whole head has been parsed.
+    Done,
+}
+
+/// A request head parsed by a [`HeadParser`].
+#[derive(Debug)]
+pub struct RequestHead {
+    /// The request method.
+    pub method: Method,
+    /// The request path, without the query.
+    pub path: String,
+    /// The request query.
+    pub query: Query,
+    /// The HTTP version string, like `HTTP/1.1`.
+    pub version: String,
+    /// The authority (host) of the target if it was in absolute-form, like `GET http://example.com/ HTTP/1.1`.
+    pub authority: Option<String>,
+    /// The headers, not including `Cookie` headers.
+    pub headers: Vec<Header>,
+    /// The cookies from all `Cookie` headers.
+    pub cookies: Vec<Cookie>,
+    /// The request line and headers as they were received.
+    pub raw: Vec<u8>,
+}
+
+/// An incremental request head parser, see the [module level docs](self).
+#[derive(Debug)]
+pub struct HeadParser {
+    limits: HeadLimits,
+    state: ParseState,
+    /// The bytes of the head received so far.
+    raw: Vec<u8>,
+    /// The index in `raw` of the line that is being received.
+    line_start: usize,
+    /// The index in `raw` of the end of the request line.
+    headers_start: usize,
+    /// The parsed request line: method, path, query, version and authority.
+    request_line: Option<(Method, String, Query, String, Option<String>)>,
+    headers: Vec<Header>,
+    cookies: Vec<Cookie>,
+}
+
+impl Default for HeadLimits {
+    fn default() -> Self {
+        Self {
+            request_line: 8 * 1024,
+            header_size: 32 * 1024,
+            headers: 100,
+            uri_policy: UriPolicy::default(),
+        }
+    }
+}
+
+impl HeadParser {
+    /// Creates a new parser with the given limits.
+    pub fn new(limits: HeadLimits) -> Self {
+        Self {
+            limits,
+            state: ParseState::RequestLine,
+            raw: Vec::new(),
+            line_start: 0,
+            headers_start: 0,
+            request_line: None,
+            headers: Vec::new(),
+            cookies: Vec::new(),
+        }
+    }
+
+    /// Gets the part of the head the parser is waiting for.
+    pub fn state(&self) -> ParseState {
+        self.state
+    }
+
+    /// Checks if the whole head has been parsed.
+    pub fn is_done(&self) -> bool {
+        self.state == ParseState::Done
+    }
+
+    /// Checks if no data has been given to the parser yet.
+    pub fn is_empty(&self) -> bool {
+        self.raw.is_empty()
+    }
+
+    /// Parses the next chunk of a request, returning how many bytes of it were part of the head.
+    /// Once the end of the head is reached the rest of the data (like the start of the body) is not used, and further calls return `Ok(0)`.
+    ///
+    /// Errors are returned as soon as a line is invalid or a limit is exceeded, even if the line hasn't been fully received.
+    /// After an error, the parser should not be used again.
+    pub fn feed(&mut self, data: &[u8]) -> Result<usize> {
+        let mut used = 0;
+        while self.state != ParseState::Done && used < data.len() {
+            let rest = &data[used..];
+            let (chunk, complete) = match rest.iter().position(|&i| i == b'\n') {
+                Some(end) => (&rest[..=end], true),
+                None => (rest, false),
+            };
+
+            self.check_limit(chunk.len(), complete)?;
+            self.raw.extend_from_slice(chunk);
+            used += chunk.len();
+
+            if complete {
+                self.parse_line()?;
+                self.line_start = self.raw.len();
+            }
+        }
+
+        Ok(used)
+    }
+
+    /// Gets the parsed head, or None if it hasn't been fully received.
+    pub fn finish(self) -> Option<RequestHead> {
+        let (method, path, query, version, authority) = match self.state {
+            ParseState::Done => self.request_line?,
+            _ => return None,
+        };
+
+        Some(RequestHead {
+            method,
+            path,
+            query,
+            version,
+            authority,
+            headers: self.headers,
+            cookies: self.cookies,
+            raw: self.raw,
+        })
+    }
+
+    /// Checks that adding `len` bytes to the current line won't go over the limit of the current part of the head.
+    /// A line can be exactly as long as the limit only if it ends there.
+    fn check_limit(&self, len: usize, complete: bool) -> Result<()> {
+        let (size, limit, error) = match self.state {
+            ParseState::RequestLine => (
+                self.raw.len() + len,
+                self.limits.request_line,
+                ParseError::RequestLineTooLong,
+            ),
+            _ => (
+                self.raw.len() + len - self.headers_start,
+                self.limits.header_size,
+                ParseError::HeadersTooLarge,
+            ),
+        };
+
+        match size > limit || (size == limit && !complete) {
+            true => Err(error.into()),
+            false => Ok(()),
+        }
+    }
+
+    /// Parses the line that was just completed.
+    fn parse_line(&mut self) -> Result<()> {
+        let line = &self.raw[self.line_start..];
+        if self.state == ParseState::RequestLine {
+            // Checked before parsing so the HTTP/2 preface (`PRI * HTTP/2.0`) doesn't fail as an invalid method
+            let version = line.split(u8::is_ascii_whitespace).rfind(|i| !i.is_empty());
+            if version.is_some_and(|i| i.starts_with(b"HTTP/") && !i.starts_with(b"HTTP/1.")) {
+                return Err(ParseError::UnsupportedVersion.into());
+            }
+
+            let mut parts = line
+                .split(u8::is_ascii_whitespace)
+                .filter(|i| !i.is_empty());
+            if let Some(target) = parts.nth(1) {
+                self.limits.uri_policy.check(target)?;
+            }
+
+            self.request_line = Some(parse_request_line(line)?);
+            self.headers_start = self.raw.len();
+            self.state = ParseState::Headers;
+            return Ok(());
+        }
+
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        if line.is_empty() {
+            self.check_host()?;
+            self.state = ParseState::Done;
+            return Ok(());
+        }
+
+        // Obsolete line folding, which proxies could read as a separate header (RFC 9112, section 5.2)
+        if line.starts_with(b" ") || line.starts_with(b"\t") {
+            return Err(ParseError::InvalidHeader.into());
+        }
+
+        if self.headers.len() + self.cookies.len() >= self.limits.headers {
+            return Err(ParseError::HeadersTooLarge.into());
+        }
+
+        let header = Header::from_string(String::from_utf8_lossy(line))?;
+        match header.name {
+            HeaderType::Cookie => self.cookies.extend(Cookie::from_string(&header.value)),
+            _ => self.headers.push(header),
+        }
+        Ok(())
+    }
+
+    /// Checks the Host header once all headers have been received.
+    /// The host of an absolute-form target replaces the Host header (RFC 9112, section 3.2.2).
+    fn check_host(&mut self) -> Result<()> {
+        let Some((.., version, authority)) = &self.request_line else {
+            return Err(ParseError::NoRequestLine.into());
+        };
+
+        let hosts = self.headers.iter().filter(|i| i.name == HeaderType::Host);
+        if let Some(authority) = authority {
+            self.headers.retain(|i| i.name != HeaderType::Host);
+            self.headers.push(Header::new(HeaderType::Host, authority));
+        } else if hosts.clone().count() > 1 || hosts.clone().any(|i| !valid_host(&i.value)) {
+            return Err(ParseError::InvalidHost.into());
+        } else if hosts.count() == 0 && !version.eq_ignore_ascii_case("HTTP/1.0") {
+            return Err(ParseError::NoHost.into());
+        }
+
+        Ok(())
+    }
+}
+
+/// Parse a request line into a method, path, query, version and the authority (host) if the target is in absolute-form.
+pub(crate) fn parse_request_line(
+    bytes: &[u8],
+) -> Result<(Method, String, Query, String, Option<String>)> {
+    let request_line = String::from_utf8_lossy(bytes);
+    let mut parts = request_line.split_whitespace();
+
+    let raw_method = match parts.next() {
+        Some(i) => i,
+        None => return Err(Error::Parse(ParseError::NoMethod)),
+    };
+    let method =
+        Method::from_str(raw_method).map_err(|_| Error::Parse(ParseError::InvalidMethod))?;
+    let (authority, mut raw_path) = match parts.next() {
+        Some(i) => match split_absolute_form(i)? {
+            Some((authority, path)) => (Some(authority.to_owned()), path.chars()),
+            None => (None, i.chars()),
+        },
+        None => return Err(Error::Parse(ParseError::NoVersion)),
+    };
+
+    let mut final_path = String::new();
+    let mut final_query = String::new();
+    let mut last_is_slash = false;
+    while let Some(i) = raw_path.next() {
+        match i {
+            '/' | '\\' => {
+                if last_is_slash {
+                    continue;
+                }
+
+                last_is_slash = true;
+                final_path.push('/');
+            }
+            '?' => {
+                final_query.extend(raw_path);
+                break;
+            }
+            _ => {
+                last_is_slash = false;
+                final_path.push(i);
+            }
+        }
+    }
+
+    // Absolute-form targets can leave out the path, like `http://example.com`
+    if authority.is_some() && final_path.is_empty() {
+        final_path.push('/');
+    }
+
+    let query = Query::from_body(&final_query);
+    let version = match parts.next() {
+        Some(i) => i.to_owned(),
+        None => return Err(Error::Parse(ParseError::NoVersion)),
+    };
+
+    Ok((method, final_path, query, version, authority))
+}
+
+/// Splits an absolute-form request target (like `http://example.com/path?query`) into its authority and the rest of the target.
+/// Returns None if the target is in the normal origin-form (`/path?query`).
+fn split_absolute_form(target: &str) -> Result<Option<(&str, &str)>> {
+    let rest = match target.split_once("://") {
+        Some((_scheme, rest)) if !target.starts_with(['/', '\\']) => rest,
+        _ => return Ok(None),
+    };
+
+    let end = rest.find(['/', '?']).unwrap_or(rest.len());
+    let (authority, path) = rest.split_at(end);
+    if authority.is_empty() || !valid_host(authority) {
+        return Err(Error::Parse(ParseError::InvalidHost));
+    }
+
+    Ok(Some((authority, path)))
+}
+
+/// Checks if a host (from the Host header or an absolute-form target) is valid.
+/// It can't contain user info (`user@`), or any characters that would end the authority part of a URL.
+pub(crate) fn valid_host(host: &str) -> bool {
+    !host.contains(|c: char| {
+        c.is_ascii_whitespace() || c.is_ascii_control() || matches!(c, '/' | '\\' | '?' | '#' | '@')
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::Cursor,
+        sync::{Arc, Mutex},
+    };
+
+    use super::{parse_request_line, HeadLimits, HeadParser, ParseState};
+    use crate::{
+        error::{Error, ParseError},
+        internal::testing::{MemoryListener, MemoryStream},
+        Method, Response, Server, UriPolicy,
+    };
+
+    const REQUEST: &[u8] = b"POST /a?b=c HTTP/1.1\r\nHost: localhost\r\nCookie: a=1; b=2\r\nContent-Length: 4\r\n\r\nbody";
+
+    #[test]
+    fn test_split_reads() {
+        // Every way of splitting the request into two reads gives the same head
+        for split in 0..REQUEST.len() {
+            let mut parser = HeadParser::new(HeadLimits::default());
+            let used = parser.feed(&REQUEST[..split]).unwrap();
+            assert_eq!(used, split.min(REQUEST.len() - 4));
+            parser.feed(&REQUEST[used..]).unwrap();
+
+            let head = parser.finish().unwrap();
+            assert_eq!(head.path, "/a");
+            assert_eq!(head.query.get("b"), Some("c"));
+            assert_eq!(head.headers.len(), 2);
+            assert_eq!(head.cookies.len(), 2);
+            assert_eq!(head.raw, &REQUEST[..REQUEST.len() - 4]);
+        }
+
+        // And one byte at a time
+        let mut parser = HeadParser::new(HeadLimits::default());
+        let used = REQUEST
+            .chunks(1)
+            .map(|i| parser.feed(i).unwrap())
+            .sum::<usize>();
+        assert_eq!(used, REQUEST.len() - 4);
+        assert!(parser.is_done());
+    }
+
+    #[test]
+    fn test_limits() {
+        let limits = HeadLimits {
+            request_line: 16,
+            header_size: 21,
+            headers: 1,
+            ..HeadLimits::default()
+        };
+
+        // Lines can be exactly as long as the limit
+        let mut parser = HeadParser::new(limits);
+        parser.feed(b"GET / HTTP/1.1\r").unwrap();
+        parser.feed(b"\nHost: localhost\r\n\r\n").unwrap();
+        assert!(parser.is_done());
+
+        // But an incomplete line at the limit is rejected before the rest arrives
+        let mut parser = HeadParser::new(limits);
+        assert_eq!(
+            parser.feed(b"GET /aa HTTP/1.1"),
+            Err(ParseError::RequestLineTooLong.into())
+        );
+
+        let mut parser = HeadParser::new(limits);
+        parser.feed(b"GET / HTTP/1.1\r\n").unwrap();
+        assert_eq!(parser.state(), ParseState::Headers);
+        assert_eq!(
+            parser.feed(b"Host: localhost\r\nA: b\r\n"),
+            Err(ParseError::HeadersTooLarge.into())
+        );
+
+        let mut parser = HeadParser::new(HeadLimits {
+            header_size: 1024,
+            ..limits
+        });
+        assert_eq!(
+            parser.feed(b"GET / HTTP/1.1\r\nHost: localhost\r\nA: b\r\n"),
+            Err(ParseError::HeadersTooLarge.into())
+        );
+    }
+
+    #[test]
+    fn test_invalid_heads() {
+        for (head, error) in [
+            (&b"GET / HTTP/2.0\r\n"[..], ParseError::UnsupportedVersion),
+            (b"\r\n", ParseError::NoMethod),
+            (b"GET / HTTP/1.1\r\n\r\n", ParseError::NoHost),
+            (
+                b"GET / HTTP/1.1\r\nHost: a\r\n folded\r\n",
+                ParseError::InvalidHeader,
+            ),
+            (
+                b"GET / HTTP/1.1\r\nHost: a\r\nHost: b\r\n\r\n",
+                ParseError::InvalidHost,
+            ),
+            (b"GET / HTTP/1.1\r\nNo colon\r\n", ParseError::InvalidHeader),
+        ] {
+            let mut parser = HeadParser::new(HeadLimits::default());
+            assert_eq!(parser.feed(head), Err(error.into()), "{:?}", head);
+        }
+
+        let mut parser = HeadParser::new(HeadLimits::default());
+        parser.feed(b"GET / HTTP/1.0\n\n").unwrap();
+        assert!(parser.finish().is_some());
+
+        let mut parser = HeadParser::new(HeadLimits::default());
+        parser.feed(b"GET / HTTP/1.1\r\nHost: a\r\n").unwrap();
+        assert!(parser.finish().is_none());
+    }
+
+    #[test]
+    fn test_absolute_form() {
+        let (_, path, query, _, authority) =
+            parse_request_line(b"GET http://example.com:8080/a//b?c=d HTTP/1.1\r\n").unwrap();
+        assert_eq!(path, "/a/b");
+        assert_eq!(query.get("c"), Some("d"));
+        assert_eq!(authority.as_deref(), Some("example.com:8080"));
+
+        let (_, path, _, _, authority) =
+            parse_request_line(b"GET https://[::1] HTTP/1.1\r\n").unwrap();
+        assert_eq!(path, "/");
+        assert_eq!(authority.as_deref(), Some("[::1]"));
+
+        let (_, path, _, _, authority) =
+            parse_request_line(b"GET /redirect?to=http://example.com HTTP/1.1\r\n").unwrap();
+        assert_eq!(path, "/redirect");
+        assert_eq!(authority, None);
+
+        for target in ["http:///path", "http://user@example.com/"] {
+            let line = format!("GET {target} HTTP/1.1\r\n");
+            assert_eq!(
+                parse_request_line(line.as_bytes()).unwrap_err(),
+                Error::Parse(ParseError::InvalidHost)
+            );
+        }
+    }
+
+    #[test]
+    fn test_missing_host() {
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let listener = MemoryListener(Mutex::new(vec![MemoryStream {
+            input: Cursor::new(b"GET / HTTP/1.1\r\n\r\n".to_vec()),
+            output: output.clone(),
+        }]));
+
+        let mut server = Server::<()>::new("localhost", 0);
+        server.route(Method::GET, "/", |_| Response::new().text("Hello"));
+        assert!(server.start_with_listener(listener).is_err());
+
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        assert!(output.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+        assert!(output.ends_with("\r\n\r\nNo Host header"));
+    }
+
+    #[test]
+    fn test_unsupported_version() {
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let listener = MemoryListener(Mutex::new(vec![MemoryStream {
+            input: Cursor::new(b"GET / HTTP/3.0\r\nHost: localhost\r\n\r\n".to_vec()),
+            output: output.clone(),
+        }]));
+
+        let mut server = Server::<()>::new("localhost", 0);
+        server.route(Method::GET, "/", |_| Response::new().text("Hello"));
+        assert!(server.start_with_listener(listener).is_err());
+
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        assert!(output.starts_with("HTTP/1.1 505 "));
+        assert!(output.contains("Connection: close\r\n"));
+    }
+
+    #[test]
+    fn test_uri_policy() {
+        let long = format!(
+            "GET /{} HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            "a".repeat(64)
+        );
+        let requests = [
+            long.as_str(),
+            "GET /caf%FF HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            "GET /%7euser/a%2fb HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        ];
+        let outputs = requests.map(|_| Arc::new(Mutex::new(Vec::new())));
+        let streams = requests
+            .iter()
+            .zip(&outputs)
+            .map(|(input, output)| MemoryStream {
+                input: Cursor::new(input.as_bytes().to_vec()),
+                output: output.clone(),
+            })
+            .collect();
+
+        let mut server = Server::<()>::new("localhost", 0).uri_policy(UriPolicy {
+            max_length: 32,
+            reject_invalid_utf8: true,
+            normalize_percent_encoding: true,
+        });
+        server.route(Method::ANY, "**", |req| Response::new().text(&req.path));
+        assert!(server
+            .start_with_listener(MemoryListener(Mutex::new(streams)))
+            .is_err());
+
+        let outputs = outputs
+            .iter()
+            .map(|i| String::from_utf8(i.lock().unwrap().clone()).unwrap())
+            .collect::<Vec<_>>();
+        assert!(outputs[0].starts_with("HTTP/1.1 414 "));
+        assert!(outputs[1].starts_with("HTTP/1.1 400 "));
+        assert!(outputs[2].starts_with("HTTP/1.1 200 "));
+        assert!(outputs[2].ends_with("\r\n\r\n/~user/a%2Fb"));
+    }
+}
@@ -1,6 +1,6 @@
 //! HTTP Path stuff
 
-use super::encoding::url;
+use super::{encoding::url, regex::Regex};
 
 /// Http Path
 #[derive(Debug, PartialEq, Eq)]
@@ -23,6 +23,9 @@ pub enum PathPart {
     /// Path param (/{name})
     Param(String),
 
+    /// Path param that only matches if the constraint holds (/{id:u32} or /{name:[a-z]+})
+    ConstrainedParam(String, Constraint),
+
     /// Match anything for self and after
     AnyAfter,
 
@@ -37,7 +40,7 @@ impl Path {
         let mut out = Vec::new();
 
         // Split off into Path Parts
-        for i in path.split('/') {
+        for i in split_segments(&path) {
             out.push(PathPart::from_segment(i));
         }
 
@@ -47,7 +50,8 @@ impl Path {
         }
     }
 
-    /// Match Path, returns None if it doesn't match and the path params if it does
+    /// Match Path, returns None if it doesn't match and the path params if it does.
+    /// Segments of the path are percent decoded before being matched, so path params arrive decoded.
     pub fn match_path(&self, path: String) -> Option<Vec<(String, String)>> {
         if self.parts == [PathPart::AnyAfter] {
             return Some(Vec::new());
@@ -58,14 +62,19 @@ impl Path {
 
         let path = path.split('/');
         for (i, j) in self.parts.iter().zip(path.clone()) {
+            let j = url::decode_path(j).unwrap_or_else(|| j.to_owned());
             match i {
                 PathPart::Normal(x) => {
-                    if x != j {
+                    if *x != j {
                         return None;
                     }
                 }
-                PathPart::Param(x) => {
-                    out.push((x.to_owned(), url::decode(j).unwrap_or_else(|| j.to_owned())))
+                PathPart::Param(x) => out.push((x.to_owned(), j)),
+                PathPart::ConstrainedParam(x, constraint) => {
+                    if !constraint.matches(&j) {
+                        return None;
+                    }
+                    out.push((x.to_owned(), j))
                 }
                 PathPart::AnyAfter => return Some(out),
                 PathPart::Any => {}
@@ -78,6 +87,83 @@ impl Path {
 
         Some(out)
     }
+
+    /// Gets the error message for the first path param with an invalid constraint, if there is one.
+    pub fn constraint_error(&self) -> Option<&str> {
+        self.parts.iter().find_map(|i| match i {
+            PathPart::ConstrainedParam(_, Constraint(ConstraintKind::Invalid(e))) => {
+                Some(e.as_str())
+            }
+            _ => None,
+        })
+    }
+}
+
+/// A constraint on a path param.
+///
+/// If the constraint is the name of a number type (like `u32` or `f64`) or `bool`, the param has to parse as that type.
+/// Otherwise it is a regex that the param has to match completely, see [`crate::Server::route`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Constraint(ConstraintKind);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ConstraintKind {
+    Type(String),
+    Regex(Regex),
+    /// An invalid regex, with the error message.
+    Invalid(String),
+}
+
+impl Constraint {
+    /// The types that can be used as constraints.
+    const TYPES: &'static [&'static str] = &[
+        "u8", "u16", "u32", "u64", "u128", "usize", "i8", "i16", "i32", "i64", "i128", "isize",
+        "f32", "f64", "bool",
+    ];
+
+    /// Parses a constraint, either a type name or a regex.
+    pub fn new(constraint: &str) -> Self {
+        if Self::TYPES.contains(&constraint) {
+            return Self(ConstraintKind::Type(constraint.to_owned()));
+        }
+
+        Self(match Regex::new(constraint) {
+            Ok(regex) => ConstraintKind::Regex(regex),
+            Err(e) => {
+                ConstraintKind::Invalid(format!("Invalid constraint `{}`: {}", constraint, e))
+            }
+        })
+    }
+
+    /// Checks if a param value satisfies the constraint.
+    pub fn matches(&self, value: &str) -> bool {
+        fn parses<T: std::str::FromStr>(value: &str) -> bool {
+            value.parse::<T>().is_ok()
+        }
+
+        match &self.0 {
+            ConstraintKind::Type(i) => match i.as_str() {
+                "u8" => parses::<u8>(value),
+                "u16" => parses::<u16>(value),
+                "u32" => parses::<u32>(value),
+                "u64" => parses::<u64>(value),
+                "u128" => parses::<u128>(value),
+                "usize" => parses::<usize>(value),
+                "i8" => parses::<i8>(value),
+                "i16" => parses::<i16>(value),
+                "i32" => parses::<i32>(value),
+                "i64" => parses::<i64>(value),
+                "i128" => parses::<i128>(value),
+                "isize" => parses::<isize>(value),
+                "f32" => parses::<f32>(value),
+                "f64" => parses::<f64>(value),
+                "bool" => parses::<bool>(value),
+                _ => unreachable!(),
+            },
+            ConstraintKind::Regex(regex) => regex.is_match(value),
+            ConstraintKind::Invalid(_) => false,
+        }
+    }
 }
 
 impl PathPart {
@@ -86,18 +172,46 @@ impl PathPart {
         match seg {
             "*" => PathPart::Any,
             "**" => PathPart::AnyAfter,
-            x if x.starts_with('{') && x.ends_with('}') => PathPart::Param(
-                x.strip_prefix('{')
-                    .unwrap()
-                    .strip_suffix('}')
-                    .unwrap()
-                    .to_owned(),
-            ),
+            x if x.starts_with('{') && x.ends_with('}') => {
+                let param = x.strip_prefix('{').unwrap().strip_suffix('}').unwrap();
+                match param.split_once(':') {
+                    Some((name, constraint)) => {
+                        PathPart::ConstrainedParam(name.to_owned(), Constraint::new(constraint))
+                    }
+                    None => PathPart::Param(param.to_owned()),
+                }
+            }
             _ => PathPart::Normal(seg.to_owned()),
         }
     }
 }
 
+/// Splits a route path into its segments.
+/// Slashes inside of `{…}` are part of a param's constraint, so they don't split the path.
+///
+/// Ex: `a/{b:[^/]+}/c` => [`a`, `{b:[^/]+}`, `c`]
+fn split_segments(path: &str) -> Vec<&str> {
+    let mut out = Vec::new();
+    let (mut depth, mut escaped, mut start) = (0_usize, false, 0);
+
+    for (i, c) in path.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '{' => depth += 1,
+            '}' => depth = depth.saturating_sub(1),
+            '/' if depth == 0 => {
+                out.push(&path[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+
+    out.push(&path[start..]);
+    out
+}
+
 /// Normalize a Path
 ///
 /// Removes loading and trailing slashes
@@ -113,9 +227,33 @@ pub fn normalize_path(mut path: String) -> String {
     path
 }
 
+/// Resolves the `.` and `..` segments of a request path and removes empty segments.
+/// `..` segments can't go above the root, and a trailing slash is kept.
+///
+/// Ex: `/a/./b/../c/` => `/a/c/`
+pub fn resolve_dots(path: &str) -> String {
+    let mut segments = Vec::new();
+    for i in path.split('/') {
+        match i {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            _ => segments.push(i),
+        }
+    }
+
+    let trailing = matches!(path.rsplit('/').next(), Some("" | "." | ".."));
+    let mut out = format!("/{}", segments.join("/"));
+    if trailing && !segments.is_empty() {
+        out.push('/');
+    }
+    out
+}
+
 #[cfg(test)]
 mod test {
-    use super::{normalize_path, Path, PathPart};
+    use super::{normalize_path, resolve_dots, Path, PathPart};
 
     #[test]
     fn test_path_new() {
@@ -173,6 +311,65 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_match_path_decode() {
+        assert_eq!(
+            Path::new("/files/{name}".to_owned()).match_path("/files/my%20doc+1.txt".to_owned()),
+            Some(vec![("name".to_owned(), "my doc+1.txt".to_owned())])
+        );
+
+        assert_eq!(
+            Path::new("/hello world".to_owned()).match_path("/hello%20world".to_owned()),
+            Some(vec![])
+        );
+    }
+
+    #[test]
+    fn test_match_path_constrained() {
+        let path = Path::new("/user/{id:u32}".to_owned());
+        assert_eq!(
+            path.match_path("/user/12".to_owned()),
+            Some(vec![("id".to_owned(), "12".to_owned())])
+        );
+        assert_eq!(path.match_path("/user/new".to_owned()), None);
+        assert_eq!(path.constraint_error(), None);
+
+        let path = Path::new("/file/{name:[a-z]+\\.txt}".to_owned());
+        assert!(path.match_path("/file/notes.txt".to_owned()).is_some());
+        assert!(path.match_path("/file/notes.md".to_owned()).is_none());
+
+        let path = Path::new("/file/{name:[a-z}".to_owned());
+        assert!(path.constraint_error().is_some());
+        assert!(path.match_path("/file/a".to_owned()).is_none());
+    }
+
+    #[test]
+    fn test_match_path_constraint_slash() {
+        let path = Path::new("/file/{name:[^/]+}/raw".to_owned());
+        assert_eq!(path.parts.len(), 3);
+        assert_eq!(path.constraint_error(), None);
+        assert_eq!(
+            path.match_path("/file/notes.txt/raw".to_owned()),
+            Some(vec![("name".to_owned(), "notes.txt".to_owned())])
+        );
+        assert!(path.match_path("/file/a/b/raw".to_owned()).is_none());
+
+        let path = Path::new("/id/{id:[0-9]{2,4}}/{rest:\\{[a/]*}".to_owned());
+        assert_eq!(path.parts.len(), 3);
+        assert!(path.match_path("/id/123/{a".to_owned()).is_some());
+    }
+
+    #[test]
+    fn test_resolve_dots() {
+        assert_eq!(resolve_dots("/a/./b/../c"), "/a/c");
+        assert_eq!(resolve_dots("/a/b/.."), "/a/");
+        assert_eq!(resolve_dots("/a/b/"), "/a/b/");
+        assert_eq!(resolve_dots("/../../etc/passwd"), "/etc/passwd");
+        assert_eq!(resolve_dots("/.."), "/");
+        assert_eq!(resolve_dots("/"), "/");
+        assert_eq!(resolve_dots("/a//b"), "/a/b");
+    }
+
     #[test]
     fn test_match_path_any() {
         assert_eq!(
@@ -0,0 +1,120 @@
+//! Reading the credentials of the process on the other end of a Unix socket.
+//!
+//! Linux and Android report the process, user and group ids with the `SO_PEERCRED` socket option.
+//! The BSDs and macOS only report the user and group ids, with `getpeereid`.
+//! On other platforms the credentials aren't available.
+
+use std::os::unix::io::RawFd;
+
+use crate::listener::PeerCredentials;
+
+/// Gets the credentials of the peer of the Unix socket `fd`, or None if they can't be read.
+#[cfg(all(
+    any(target_os = "linux", target_os = "android"),
+    not(any(
+        target_arch = "mips",
+        target_arch = "mips64",
+        target_arch = "powerpc",
+        target_arch = "powerpc64",
+        target_arch = "sparc64"
+    ))
+))]
+pub(crate) fn peer_credentials(fd: RawFd) -> Option<PeerCredentials> {
+    use std::{
+        mem,
+        os::raw::{c_int, c_void},
+    };
+
+    const SOL_SOCKET: c_int = 1;
+    const SO_PEERCRED: c_int = 17;
+
+    #[repr(C)]
+    struct Ucred {
+        pid: i32,
+        uid: u32,
+        gid: u32,
+    }
+
+    extern "C" {
+        fn getsockopt(
+            socket: c_int,
+            level: c_int,
+            name: c_int,
+            value: *mut c_void,
+            len: *mut u32,
+        ) -> c_int;
+    }
+
+    let mut cred = Ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut len = mem::size_of::<Ucred>() as u32;
+    // Safety: `cred` and `len` are valid for writes, and `len` is the size of `cred`
+    let result = unsafe {
+        getsockopt(
+            fd,
+            SOL_SOCKET,
+            SO_PEERCRED,
+            &mut cred as *mut Ucred as *mut c_void,
+            &mut len,
+        )
+    };
+
+    // The pid is zero if the peer isn't in this process's pid namespace
+    (result == 0).then(|| PeerCredentials {
+        pid: (cred.pid > 0).then_some(cred.pid as u32),
+        uid: cred.uid,
+        gid: cred.gid,
+    })
+}
+
+/// Gets the credentials of the peer of the Unix socket `fd`, or None if they can't be read.
+#[cfg(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "netbsd",
+    target_os = "dragonfly"
+))]
+pub(crate) fn peer_credentials(fd: RawFd) -> Option<PeerCredentials> {
+    use std::os::raw::c_int;
+
+    extern "C" {
+        fn getpeereid(socket: c_int, euid: *mut u32, egid: *mut u32) -> c_int;
+    }
+
+    let (mut uid, mut gid) = (0, 0);
+    // Safety: `uid` and `gid` are valid for writes
+    let result = unsafe { getpeereid(fd, &mut uid, &mut gid) };
+    (result == 0).then_some(PeerCredentials {
+        pid: None,
+        uid,
+        gid,
+    })
+}
+
+/// Peer credentials aren't supported on this platform.
+#[cfg(not(any(
+    all(
+        any(target_os = "linux", target_os = "android"),
+        not(any(
+            target_arch = "mips",
+            target_arch = "mips64",
+            target_arch = "powerpc",
+            target_arch = "powerpc64",
+            target_arch = "sparc64"
+        ))
+    ),
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "netbsd",
+    target_os = "dragonfly"
+)))]
+pub(crate) fn peer_credentials(_fd: RawFd) -> Option<PeerCredentials> {
+    None
+}
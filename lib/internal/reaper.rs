@@ -0,0 +1,214 @@
+//! Closes connections that have been idle for too long, see [`crate::Server::reap_idle_connections`].
+//!
+//! Keep-alive connections are normally closed by the thread handling them once the idle timeout passes, but a thread that is blocked reading from a connection can't notice.
+//! This happens with streams that don't support [`Stream::peek`] (like most TLS streams) and with clients that open a connection without sending a request.
+//! The reaper thread keeps another handle to each connection and shuts it down, which makes the blocked read return.
+
+use std::{
+    collections::HashMap,
+    fmt::{self, Debug},
+    net::Shutdown,
+    sync::{atomic::Ordering, Arc, Condvar, Mutex},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    clock::Clock, internal::common::ForceLock, listener::Stream, request::ConnectionInfo,
+    server::ConnectionCounters, trace, Server,
+};
+
+/// The longest the reaper sleeps between checks.
+const MAX_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A connection watched by the reaper.
+pub(crate) struct TrackedConnection {
+    info: Arc<ConnectionInfo>,
+    /// Another handle to the connection, used to shut it down.
+    stream: Box<dyn Stream>,
+}
+
+/// Removes a connection from the reaper when it is closed.
+pub(crate) struct TrackGuard {
+    counters: Arc<ConnectionCounters>,
+    id: u64,
+}
+
+/// The running reaper thread.
+/// It is stopped when this is dropped.
+pub(crate) struct Reaper {
+    stop: Arc<(Mutex<bool>, Condvar)>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Reaper {
+    /// Starts the reaper thread, if it is enabled and the server has an idle timeout.
+    pub(crate) fn start<State>(this: &Server<State>) -> Option<Self>
+    where
+        State: 'static + Send + Sync,
+    {
+        let timeout = idle_timeout(this)?;
+        let counters = this.connection_stats.counters().clone();
+        let clock = this.clock.clone();
+        let interval = (timeout / 4).min(MAX_INTERVAL);
+
+        let stop = Arc::new((Mutex::new(false), Condvar::new()));
+        let thread_stop = stop.clone();
+        let handle = thread::Builder::new()
+            .name("afire-reaper".to_owned())
+            .spawn(move || {
+                let (stopped, wake) = &*thread_stop;
+                let mut stopped = stopped.force_lock();
+                while !*stopped {
+                    reap(&counters, &*clock, timeout);
+                    stopped = wake
+                        .wait_timeout(stopped, interval)
+                        .unwrap_or_else(|e| e.into_inner())
+                        .0;
+                }
+            })
+            .expect("Failed to spawn reaper thread");
+
+        Some(Self {
+            stop,
+            handle: Some(handle),
+        })
+    }
+}
+
+impl Drop for Reaper {
+    fn drop(&mut self) {
+        *self.stop.0.force_lock() = true;
+        self.stop.1.notify_all();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for TrackGuard {
+    fn drop(&mut self) {
+        self.counters.tracked.force_lock().remove(&self.id);
+    }
+}
+
+impl Debug for TrackedConnection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TrackedConnection")
+            .field("info", &self.info)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Gets how long connections can be idle before they are reaped, or None if the reaper is disabled.
+fn idle_timeout<State>(this: &Server<State>) -> Option<Duration>
+where
+    State: 'static + Send + Sync,
+{
+    if !this.reap_idle_connections {
+        return None;
+    }
+
+    this.keep_alive_timeout.or(this.socket_timeout)
+}
+
+/// Starts watching a new connection, which is idle until its first request is received.
+/// Returns None if the reaper is disabled or the stream can't be cloned.
+pub(crate) fn track<State>(
+    stream: &dyn Stream,
+    info: &Arc<ConnectionInfo>,
+    this: &Server<State>,
+) -> Option<TrackGuard>
+where
+    State: 'static + Send + Sync,
+{
+    idle_timeout(this)?;
+    let clone = match stream.try_clone() {
+        Ok(i) => i,
+        Err(_e) => {
+            trace!(Level::Debug, "Can't watch connection for idleness: {}", _e);
+            return None;
+        }
+    };
+
+    set_idle(info, Some(this.clock.instant()));
+    let counters = this.connection_stats.counters().clone();
+    counters.tracked.force_lock().insert(
+        info.id(),
+        TrackedConnection {
+            info: info.clone(),
+            stream: clone,
+        },
+    );
+
+    Some(TrackGuard {
+        counters,
+        id: info.id(),
+    })
+}
+
+/// Marks a connection as idle since the given time, or as handling a request if it is None.
+pub(crate) fn set_idle(info: &ConnectionInfo, since: Option<Instant>) {
+    *info.idle_since.force_lock() = since;
+}
+
+/// Shuts down the connections that have been idle for longer than `timeout`.
+fn reap(counters: &ConnectionCounters, clock: &dyn Clock, timeout: Duration) {
+    let now = clock.instant();
+    counters.tracked.force_lock().retain(|_, conn| {
+        let idle = *conn.info.idle_since.force_lock();
+        let expired = idle.is_some_and(|i| now.saturating_duration_since(i) >= timeout);
+        if !expired {
+            return true;
+        }
+
+        trace!(
+            Level::Debug,
+            "Reaping idle connection {:?}",
+            conn.stream.peer_addr()
+        );
+        counters.reaped.fetch_add(1, Ordering::Relaxed);
+        let _ = conn.stream.shutdown(Shutdown::Both);
+        false
+    });
+}
+
+/// Counts the tracked connections that are waiting for a request.
+pub(crate) fn idle_count(tracked: &HashMap<u64, TrackedConnection>) -> usize {
+    tracked
+        .values()
+        .filter(|i| i.info.idle_since.force_lock().is_some())
+        .count()
+}
+
+#[cfg(test)]
+mod test {
+    use std::{io::Read, thread, time::Duration};
+
+    use crate::Server;
+
+    #[test]
+    fn test_reap_idle_connections() {
+        use std::net::{TcpListener, TcpStream};
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = Server::<()>::new("localhost", 0)
+            .keep_alive_timeout(Duration::from_millis(200))
+            .reap_idle_connections(true);
+        let stats = server.connection_stats();
+        let shutdown = server.shutdown_handle();
+        let handle =
+            thread::spawn(move || server.start_threaded_with_listener(listener, 2).is_ok());
+
+        // Never sends a request, so its worker is blocked reading one until the connection is reaped
+        let mut idle = TcpStream::connect(addr).unwrap();
+        idle.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        assert_eq!(idle.read(&mut [0; 16]).unwrap(), 0);
+        assert_eq!(stats.reaped(), 1);
+        assert_eq!(stats.idle(), 0);
+
+        shutdown.shutdown();
+        assert!(handle.join().unwrap());
+    }
+}
@@ -0,0 +1,445 @@
+//! A small backtracking regex engine, for the regex constraints on path params (`/file/{name:[a-z]+}`).
+//!
+//! Supports literals, `.`, character classes (`[a-z]`, `[^0-9]`), the `\d`, `\w` and `\s` classes and their negations,
+//! groups (`(...)`, `(?:...)`), alternation (`|`) and the `*`, `+`, `?`, `{n}`, `{n,}` and `{n,m}` quantifiers.
+//! Patterns always have to match the whole input, so `^` and `$` are not needed.
+//!
+//! Patterns are compiled to a list of instructions, which are run on every possible path through the pattern at once (a Pike VM).
+//! This doesn't recurse or backtrack, so a match takes at most the length of the input times the length of the program steps.
+
+use std::{iter::Peekable, mem, str::Chars};
+
+/// The most instructions a compiled pattern can have, as quantifiers like `{n}` repeat the instructions of what they quantify.
+const MAX_PROGRAM: usize = 10_000;
+
+/// The most steps a match can take before it gives up and fails, so long inputs on large patterns can't be used to tie up the server.
+const MAX_STEPS: usize = 1_000_000;
+
+/// A compiled regex.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Regex {
+    /// The instructions of the pattern, starting at the first.
+    program: Vec<Inst>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Inst {
+    /// Matches a character.
+    Char(char),
+    /// Matches any character.
+    Any,
+    /// Matches a character in a class.
+    Class(Class),
+    /// Continues at both instructions.
+    Split(usize, usize),
+    /// Continues at another instruction.
+    Jump(usize),
+    /// The pattern has matched, if this is reached at the end of the input.
+    Match,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Node {
+    Char(char),
+    Any,
+    Class(Class),
+    Group(Vec<Vec<Node>>),
+    Repeat(Box<Node>, usize, Option<usize>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Class {
+    negated: bool,
+    ranges: Vec<(char, char)>,
+}
+
+impl Regex {
+    /// Compiles a pattern, returning an error message if it is invalid.
+    pub(crate) fn new(pattern: &str) -> Result<Self, String> {
+        let pattern = pattern.strip_prefix('^').unwrap_or(pattern);
+        let pattern = match pattern.strip_suffix('$') {
+            Some(i) if !i.ends_with('\\') => i,
+            _ => pattern,
+        };
+
+        let mut chars = pattern.chars().peekable();
+        let alts = parse_alts(&mut chars)?;
+        if let Some(c) = chars.next() {
+            return Err(format!("Unexpected `{}`", c));
+        }
+
+        let mut program = Vec::new();
+        compile_alts(&alts, &mut program)?;
+        push(&mut program, Inst::Match)?;
+        Ok(Self { program })
+    }
+
+    /// Checks if the regex matches the whole input.
+    pub(crate) fn is_match(&self, input: &str) -> bool {
+        let mut threads = Threads {
+            program: &self.program,
+            added: vec![usize::MAX; self.program.len()],
+            stack: Vec::new(),
+            steps: 0,
+        };
+        let mut current = Vec::new();
+        let mut next = Vec::new();
+        threads.add(&mut current, 0, 0);
+
+        for (i, c) in input.chars().enumerate() {
+            for &pc in &current {
+                let matches = match &self.program[pc] {
+                    Inst::Char(i) => c == *i,
+                    Inst::Any => true,
+                    Inst::Class(class) => class.matches(c),
+                    _ => false,
+                };
+                if matches {
+                    threads.add(&mut next, pc + 1, i + 1);
+                }
+            }
+
+            if next.is_empty() || threads.steps > MAX_STEPS {
+                return false;
+            }
+            current = mem::take(&mut next);
+        }
+
+        current.iter().any(|&pc| self.program[pc] == Inst::Match)
+    }
+}
+
+fn parse_alts(chars: &mut Peekable<Chars>) -> Result<Vec<Vec<Node>>, String> {
+    let mut alts = vec![Vec::new()];
+    while let Some(&c) = chars.peek() {
+        let node = match c {
+            ')' => break,
+            '|' => {
+                chars.next();
+                alts.push(Vec::new());
+                continue;
+            }
+            '*' | '+' | '?' | '{' => {
+                chars.next();
+                let last = alts.last_mut().unwrap();
+                let node = match last.pop() {
+                    Some(Node::Repeat(..)) | None => {
+                        return Err(format!("Nothing to repeat before `{}`", c))
+                    }
+                    Some(i) => i,
+                };
+                let (min, max) = parse_quantifier(c, chars)?;
+                // Lazy quantifiers match the same things, as the whole input always has to match
+                if chars.peek() == Some(&'?') {
+                    chars.next();
+                }
+                last.push(Node::Repeat(Box::new(node), min, max));
+                continue;
+            }
+            _ => parse_atom(chars)?,
+        };
+        alts.last_mut().unwrap().push(node);
+    }
+
+    Ok(alts)
+}
+
+fn parse_atom(chars: &mut Peekable<Chars>) -> Result<Node, String> {
+    Ok(match chars.next().unwrap() {
+        '.' => Node::Any,
+        '(' => {
+            if chars.peek() == Some(&'?') {
+                chars.next();
+                if chars.next() != Some(':') {
+                    return Err("Only non-capturing groups (`(?:...)`) are supported".to_owned());
+                }
+            }
+            let alts = parse_alts(chars)?;
+            if chars.next() != Some(')') {
+                return Err("Unclosed group".to_owned());
+            }
+            Node::Group(alts)
+        }
+        '[' => Node::Class(parse_class(chars)?),
+        '\\' => match chars.next() {
+            Some(c) => match escape_class(c) {
+                Some(class) => Node::Class(class),
+                None => Node::Char(c),
+            },
+            None => return Err("Trailing `\\`".to_owned()),
+        },
+        c => Node::Char(c),
+    })
+}
+
+fn parse_class(chars: &mut Peekable<Chars>) -> Result<Class, String> {
+    let negated = chars.peek() == Some(&'^');
+    if negated {
+        chars.next();
+    }
+
+    let mut ranges = Vec::new();
+    let mut first = true;
+    loop {
+        let start = match chars.next() {
+            Some(']') if !first => break,
+            Some('\\') => match chars.next() {
+                Some(c) => match escape_class(c) {
+                    Some(class) if !class.negated => {
+                        ranges.extend(class.ranges);
+                        first = false;
+                        continue;
+                    }
+                    Some(_) => return Err(format!("`\\{}` is not supported in a class", c)),
+                    None => c,
+                },
+                None => return Err("Trailing `\\`".to_owned()),
+            },
+            Some(c) => c,
+            None => return Err("Unclosed character class".to_owned()),
+        };
+        first = false;
+
+        let mut lookahead = chars.clone();
+        if lookahead.next() == Some('-') && !matches!(lookahead.peek(), Some(']') | None) {
+            chars.next();
+            let end = match chars.next() {
+                Some('\\') => chars.next().ok_or("Trailing `\\`")?,
+                Some(c) => c,
+                None => unreachable!(),
+            };
+            if end < start {
+                return Err(format!("Invalid range `{}-{}`", start, end));
+            }
+            ranges.push((start, end));
+        } else {
+            ranges.push((start, start));
+        }
+    }
+
+    Ok(Class { negated, ranges })
+}
+
+fn parse_quantifier(
+    c: char,
+    chars: &mut Peekable<Chars>,
+) -> Result<(usize, Option<usize>), String> {
+    Ok(match c {
+        '*' => (0, None),
+        '+' => (1, None),
+        '?' => (0, Some(1)),
+        _ => {
+            let mut inner = String::new();
+            loop {
+                match chars.next() {
+                    Some('}') => break,
+                    Some(c) => inner.push(c),
+                    None => return Err("Unclosed `{`".to_owned()),
+                }
+            }
+
+            let number = |i: &str| {
+                i.trim()
+                    .parse::<usize>()
+                    .map_err(|_| format!("Invalid quantifier `{{{}}}`", inner))
+            };
+            let (min, max) = match inner.split_once(',') {
+                Some((min, max)) if max.trim().is_empty() => (number(min)?, None),
+                Some((min, max)) => (number(min)?, Some(number(max)?)),
+                None => (number(&inner)?, Some(number(&inner)?)),
+            };
+            if max.is_some_and(|max| max < min) {
+                return Err(format!("Invalid quantifier `{{{}}}`", inner));
+            }
+            (min, max)
+        }
+    })
+}
+
+/// Gets the class for an escape like `\d`, or None if it is just an escaped character.
+fn escape_class(c: char) -> Option<Class> {
+    let ranges = match c.to_ascii_lowercase() {
+        'd' => vec![('0', '9')],
+        'w' => vec![('a', 'z'), ('A', 'Z'), ('0', '9'), ('_', '_')],
+        's' => vec![(' ', ' '), ('\t', '\r')],
+        _ => return None,
+    };
+
+    Some(Class {
+        negated: c.is_ascii_uppercase(),
+        ranges,
+    })
+}
+
+/// Adds an instruction to a program, failing if it gets too large.
+fn push(program: &mut Vec<Inst>, inst: Inst) -> Result<usize, String> {
+    if program.len() >= MAX_PROGRAM {
+        return Err("Pattern is too large".to_owned());
+    }
+
+    program.push(inst);
+    Ok(program.len() - 1)
+}
+
+fn compile_alts(alts: &[Vec<Node>], program: &mut Vec<Inst>) -> Result<(), String> {
+    let mut jumps = Vec::new();
+    for (i, alt) in alts.iter().enumerate() {
+        if i + 1 == alts.len() {
+            compile_seq(alt, program)?;
+            break;
+        }
+
+        // Either this alternative or the ones after it
+        let split = push(program, Inst::Split(program.len() + 1, 0))?;
+        compile_seq(alt, program)?;
+        jumps.push(push(program, Inst::Jump(0))?);
+        program[split] = Inst::Split(split + 1, program.len());
+    }
+
+    let end = program.len();
+    for i in jumps {
+        program[i] = Inst::Jump(end);
+    }
+    Ok(())
+}
+
+fn compile_seq(nodes: &[Node], program: &mut Vec<Inst>) -> Result<(), String> {
+    nodes.iter().try_for_each(|i| compile_node(i, program))
+}
+
+fn compile_node(node: &Node, program: &mut Vec<Inst>) -> Result<(), String> {
+    match node {
+        Node::Char(c) => push(program, Inst::Char(*c)).map(drop),
+        Node::Any => push(program, Inst::Any).map(drop),
+        Node::Class(class) => push(program, Inst::Class(class.clone())).map(drop),
+        Node::Group(alts) => compile_alts(alts, program),
+        Node::Repeat(node, min, max) => {
+            for _ in 0..*min {
+                compile_node(node, program)?;
+            }
+
+            match max {
+                // Either another match of the node followed by this split again, or the rest of the pattern
+                None => {
+                    let split = push(program, Inst::Split(program.len() + 1, 0))?;
+                    compile_node(node, program)?;
+                    push(program, Inst::Jump(split))?;
+                    program[split] = Inst::Split(split + 1, program.len());
+                }
+                // Each optional match can skip to the end
+                Some(max) => {
+                    let mut splits = Vec::new();
+                    for _ in *min..*max {
+                        splits.push(push(program, Inst::Split(program.len() + 1, 0))?);
+                        compile_node(node, program)?;
+                    }
+
+                    let end = program.len();
+                    for i in splits {
+                        program[i] = Inst::Split(i + 1, end);
+                    }
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+/// The state shared by all the threads of a match.
+/// A thread is a position in the program that the input so far could have reached, and there is at most one per instruction.
+struct Threads<'a> {
+    program: &'a [Inst],
+    /// For each instruction, the input position it was last added at.
+    added: Vec<usize>,
+    /// Instructions that still need to be followed while adding a thread.
+    stack: Vec<usize>,
+    /// How many instructions have been visited, to give up after [`MAX_STEPS`].
+    steps: usize,
+}
+
+impl Threads<'_> {
+    /// Adds a thread at `pc` to `list`, following any jumps and splits to the instructions that match characters (or the end).
+    /// `pos` is the position in the input the thread is at, so each instruction is only added once per position.
+    fn add(&mut self, list: &mut Vec<usize>, pc: usize, pos: usize) {
+        self.stack.push(pc);
+        while let Some(pc) = self.stack.pop() {
+            self.steps += 1;
+            if self.added[pc] == pos {
+                continue;
+            }
+            self.added[pc] = pos;
+
+            match self.program[pc] {
+                Inst::Jump(i) => self.stack.push(i),
+                Inst::Split(a, b) => self.stack.extend([b, a]),
+                _ => list.push(pc),
+            }
+        }
+    }
+}
+
+impl Class {
+    fn matches(&self, c: char) -> bool {
+        self.ranges
+            .iter()
+            .any(|(start, end)| (*start..=*end).contains(&c))
+            != self.negated
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Regex;
+
+    fn matches(pattern: &str, input: &str) -> bool {
+        Regex::new(pattern).unwrap().is_match(input)
+    }
+
+    #[test]
+    fn test_regex_match() {
+        assert!(matches("[a-z]+", "hello"));
+        assert!(!matches("[a-z]+", "Hello"));
+        assert!(!matches("[a-z]+", ""));
+        assert!(matches("\\d{2,4}", "123"));
+        assert!(!matches("\\d{2,4}", "12345"));
+        assert!(matches("v\\d+(\\.\\d+)*", "v1.2.30"));
+        assert!(matches("(cat|dog)s?", "dogs"));
+        assert!(!matches("(cat|dog)s?", "cow"));
+        assert!(matches("[^/.]+\\.txt", "notes.txt"));
+        assert!(matches("a.*b.*c", "axxbyyc"));
+        assert!(matches("^[\\w-]+$", "my_file-1"));
+        assert!(matches("(a*)*b", "aaab"));
+        assert!(!matches("(a*)*b", "aaaa"));
+        assert!(!matches("(a*)*b", &"a".repeat(100)));
+        assert!(!matches("a{2}(b|c){1,3}", "abbc"));
+        assert!(matches("a{2}(b|c){1,3}", "aabc"));
+        assert!(matches("(a|)+b", "aab"));
+    }
+
+    #[test]
+    fn test_regex_long_input() {
+        let input = "a".repeat(100_000);
+        assert!(matches("[a-z]+", &input));
+        assert!(!matches("[a-z]+", &format!("{}A", input)));
+        assert!(matches("((a|b))+", &"ab".repeat(5_000)));
+        assert!(matches("(?:a*)*", &input));
+    }
+
+    #[test]
+    fn test_regex_invalid() {
+        for i in [
+            "[a-z",
+            "(abc",
+            "abc)",
+            "+a",
+            "a{3,1}",
+            "a{x}",
+            "[z-a]",
+            "\\",
+            "a{100000}",
+            "(a{100}){100}",
+        ] {
+            assert!(Regex::new(i).is_err(), "{}", i);
+        }
+    }
+}
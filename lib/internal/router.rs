@@ -0,0 +1,245 @@
+//! Finds the route that matches a request.
+//!
+//! The routes are compiled into a tree of path segments the first time a request is handled,
+//! so matching a request only has to walk the segments of its path instead of trying every route.
+//! When more than one route matches, the one with the highest priority wins, and then the one that was added last.
+
+use std::{borrow::Borrow, collections::HashMap};
+
+use super::{
+    encoding::url,
+    path::{normalize_path, Constraint, PathPart},
+};
+use crate::{Method, Route};
+
+/// A tree of the server's routes.
+#[derive(Debug, Default)]
+pub(crate) struct Router {
+    root: Node,
+    /// The method and priority of each route, by index.
+    routes: Vec<(Method, i32)>,
+}
+
+/// A node in the route tree, for one segment of a path.
+#[derive(Debug, Default)]
+struct Node {
+    /// Routes that end at this node.
+    routes: Vec<usize>,
+    /// Routes that have a `**` at this node, matching one or more segments after it.
+    any_after: Vec<usize>,
+    /// Children for normal segments.
+    children: HashMap<String, Node>,
+    /// Child for a param (`{name}`) or wildcard (`*`) segment, which match any one segment.
+    wildcard: Option<Box<Node>>,
+    /// Children for constrained params (`{id:u32}`), which only match segments that satisfy the constraint.
+    constrained: Vec<(Constraint, Node)>,
+}
+
+impl Router {
+    /// Builds the tree from a list of routes.
+    /// The routes can be owned or shared (like the `Arc`s in a [`crate::RouteTable`]).
+    pub(crate) fn new<State, R>(routes: &[R]) -> Self
+    where
+        State: 'static + Send + Sync,
+        R: Borrow<Route<State>>,
+    {
+        let mut root = Node::default();
+        for (idx, route) in routes.iter().enumerate() {
+            root.insert(&route.borrow().path.parts, idx);
+        }
+
+        Self {
+            root,
+            routes: routes
+                .iter()
+                .map(|i| (i.borrow().method.clone(), i.borrow().priority))
+                .collect(),
+        }
+    }
+
+    /// Finds the route that matches the method and path with the highest priority, or the last added one if there is a tie.
+    /// Returns the priority and index of the route.
+    pub(crate) fn find(&self, method: &Method, path: &str) -> Option<(i32, usize)> {
+        let path = normalize_path(path.to_owned());
+        let segments = path
+            .split('/')
+            .map(|i| url::decode_path(i).unwrap_or_else(|| i.to_owned()))
+            .collect::<Vec<_>>();
+
+        let mut best = None;
+        self.root.find(&segments, &mut |idx| {
+            let (route_method, priority) = &self.routes[idx];
+            let key = (*priority, idx);
+            if (*route_method == Method::ANY || route_method == method) && Some(key) > best {
+                best = Some(key);
+            }
+        });
+        best
+    }
+}
+
+impl Node {
+    fn insert(&mut self, parts: &[PathPart], idx: usize) {
+        let (first, rest) = match parts.split_first() {
+            Some(i) => i,
+            None => return self.routes.push(idx),
+        };
+
+        let child = match first {
+            PathPart::Normal(i) => self.children.entry(i.to_owned()).or_default(),
+            PathPart::Param(_) | PathPart::Any => {
+                self.wildcard.get_or_insert_with(Default::default)
+            }
+            PathPart::ConstrainedParam(_, constraint) => {
+                match self.constrained.iter().position(|(i, _)| i == constraint) {
+                    Some(i) => &mut self.constrained[i].1,
+                    None => {
+                        self.constrained.push((constraint.clone(), Node::default()));
+                        &mut self.constrained.last_mut().unwrap().1
+                    }
+                }
+            }
+            PathPart::AnyAfter => return self.any_after.push(idx),
+        };
+        child.insert(rest, idx);
+    }
+
+    /// Calls `found` with every route that matches the segments.
+    fn find(&self, segments: &[String], found: &mut impl FnMut(usize)) {
+        let (first, rest) = match segments.split_first() {
+            Some(i) => i,
+            None => return self.routes.iter().for_each(|&i| found(i)),
+        };
+
+        self.any_after.iter().for_each(|&i| found(i));
+        if let Some(child) = self.children.get(first) {
+            child.find(rest, found);
+        }
+
+        if let Some(child) = &self.wildcard {
+            child.find(rest, found);
+        }
+
+        for (constraint, child) in &self.constrained {
+            if constraint.matches(first) {
+                child.find(rest, found);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Router;
+    use crate::{Method, Response, Route};
+
+    fn route(method: Method, path: &str) -> Route<()> {
+        Route::new(method, path.to_owned(), Box::new(|_| Response::new()))
+    }
+
+    fn router(routes: &[(Method, &str)]) -> Router {
+        let routes = routes
+            .iter()
+            .map(|(method, path)| route(method.clone(), path))
+            .collect::<Vec<_>>();
+        Router::new(&routes)
+    }
+
+    fn find(router: &Router, method: &Method, path: &str) -> Option<usize> {
+        router.find(method, path).map(|(_, idx)| idx)
+    }
+
+    #[test]
+    fn test_router_find() {
+        let router = router(&[
+            (Method::GET, "/"),
+            (Method::GET, "/user/{id}"),
+            (Method::POST, "/user/new"),
+            (Method::ANY, "/files/**"),
+            (Method::GET, "/a/*/c"),
+        ]);
+
+        assert_eq!(find(&router, &Method::GET, "/"), Some(0));
+        assert_eq!(find(&router, &Method::GET, "/user/12"), Some(1));
+        assert_eq!(find(&router, &Method::GET, "/user/new"), Some(1));
+        assert_eq!(find(&router, &Method::POST, "/user/new"), Some(2));
+        assert_eq!(find(&router, &Method::POST, "/user/12"), None);
+        assert_eq!(find(&router, &Method::DELETE, "/files/a/b"), Some(3));
+        assert_eq!(find(&router, &Method::GET, "/files"), None);
+        assert_eq!(find(&router, &Method::GET, "/a/b/c"), Some(4));
+        assert_eq!(find(&router, &Method::GET, "/a/b/c/d"), None);
+        assert_eq!(find(&router, &Method::GET, "/nothing"), None);
+    }
+
+    #[test]
+    fn test_router_priority() {
+        // Later routes take priority, even if they are less specific
+        let router = router(&[
+            (Method::GET, "/user/new"),
+            (Method::GET, "/user/{id}"),
+            (Method::ANY, "**"),
+            (Method::GET, "/about"),
+        ]);
+
+        assert_eq!(find(&router, &Method::GET, "/user/new"), Some(2));
+        assert_eq!(find(&router, &Method::GET, "/about"), Some(3));
+        assert_eq!(find(&router, &Method::GET, "/"), Some(2));
+    }
+
+    #[test]
+    fn test_router_decode() {
+        let router = router(&[(Method::GET, "/hello world")]);
+        assert_eq!(find(&router, &Method::GET, "/hello%20world"), Some(0));
+    }
+
+    #[test]
+    fn test_router_explicit_priority() {
+        let mut routes = vec![
+            route(Method::GET, "/user/new"),
+            route(Method::GET, "/user/{id}"),
+        ];
+        routes[0].priority = 1;
+
+        let router = Router::new(&routes);
+        assert_eq!(find(&router, &Method::GET, "/user/new"), Some(0));
+        assert_eq!(find(&router, &Method::GET, "/user/12"), Some(1));
+    }
+
+    #[test]
+    fn test_router_constraints() {
+        let router = router(&[
+            (Method::GET, "/user/new"),
+            (Method::GET, "/user/{id:u32}"),
+            (Method::GET, "/file/{name:[a-z]+\\.txt}"),
+            (Method::GET, "/file/{name}"),
+        ]);
+
+        assert_eq!(find(&router, &Method::GET, "/user/new"), Some(0));
+        assert_eq!(find(&router, &Method::GET, "/user/12"), Some(1));
+        assert_eq!(find(&router, &Method::GET, "/user/-12"), None);
+        assert_eq!(find(&router, &Method::GET, "/file/notes.txt"), Some(3));
+        assert_eq!(find(&router, &Method::GET, "/file/notes.md"), Some(3));
+
+        let router = super::Router::new(&[
+            route(Method::GET, "/file/{name}"),
+            route(Method::GET, "/file/{name:[a-z]+\\.txt}"),
+        ]);
+        assert_eq!(find(&router, &Method::GET, "/file/notes.txt"), Some(1));
+        assert_eq!(find(&router, &Method::GET, "/file/Notes.txt"), Some(0));
+    }
+
+    #[test]
+    fn test_route_conflicts() {
+        let conflicts = |a: &str, b: &str| route(Method::GET, a).conflicts(&route(Method::GET, b));
+
+        assert!(conflicts("/user/{id}", "/user/{name}"));
+        assert!(conflicts("/user/{id}", "/user/*"));
+        assert!(conflicts("/files/**", "files/**/"));
+        assert!(!conflicts("/user/{id}", "/user/new"));
+        assert!(!conflicts("/user/{id}", "/user/{id}/posts"));
+        assert!(!conflicts("/user/{id}", "/user/{id:u32}"));
+        assert!(conflicts("/user/{id:u32}", "/user/{name:u32}"));
+        assert!(!conflicts("/user/{id:u32}", "/user/{id:u64}"));
+        assert!(!route(Method::GET, "/").conflicts(&route(Method::POST, "/")));
+    }
+}
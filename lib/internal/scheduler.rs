@@ -0,0 +1,179 @@
+//! Runs the background tasks added with [`crate::Server::schedule`] and [`crate::Server::spawn_task`].
+//!
+//! A single scheduler thread waits until a task is due and then hands it off to the thread pool (or runs it itself when there is no pool).
+//! Periodic tasks are skipped if the previous run hasn't finished yet, so slow tasks don't pile up.
+
+use std::{
+    panic,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::{consts, internal::common::ForceLock, thread_pool::ThreadPool, trace};
+
+#[cfg(feature = "tracing")]
+use crate::internal::common::any_string;
+
+type Job = Arc<dyn Fn() + Send + Sync>;
+
+/// A background task.
+#[derive(Clone)]
+pub(crate) struct Task {
+    /// How often the task runs, or None if it only runs once when the server starts.
+    pub(crate) interval: Option<Duration>,
+    /// The function to run.
+    job: Job,
+    /// Set while the task is running.
+    running: Arc<AtomicBool>,
+}
+
+/// The running scheduler thread.
+/// It is stopped when this is dropped, tasks that are already running are not interrupted.
+pub(crate) struct Scheduler {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Task {
+    /// Creates a task that runs every `interval`, starting one interval after the server starts.
+    pub(crate) fn periodic(interval: Duration, job: impl Fn() + Send + Sync + 'static) -> Self {
+        Self {
+            interval: Some(interval),
+            job: Arc::new(job),
+            running: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Creates a task that runs once when the server starts.
+    pub(crate) fn once(job: impl FnOnce() + Send + 'static) -> Self {
+        let job = Mutex::new(Some(job));
+        Self {
+            interval: None,
+            job: Arc::new(move || {
+                if let Some(job) = job.force_lock().take() {
+                    job();
+                }
+            }),
+            running: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Runs the task, catching any panics.
+    fn run(&self) {
+        if let Err(_e) = panic::catch_unwind(panic::AssertUnwindSafe(|| (self.job)())) {
+            trace!(Level::Error, "Background task panicked: {}", any_string(_e));
+        }
+        self.running.store(false, Ordering::Release);
+    }
+}
+
+impl Scheduler {
+    /// Starts the scheduler thread, if there are any tasks.
+    /// Tasks are run on `pool` if it is given, otherwise they are run on the scheduler thread.
+    pub(crate) fn start(tasks: &[Task], pool: Option<Arc<ThreadPool>>) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        if tasks.is_empty() {
+            return Self { stop, handle: None };
+        }
+
+        let start = Instant::now();
+        let mut tasks = tasks
+            .iter()
+            .map(|i| (start + i.interval.unwrap_or_default(), i.clone()))
+            .collect::<Vec<_>>();
+
+        let thread_stop = stop.clone();
+        let handle = thread::Builder::new()
+            .name("Scheduler".to_owned())
+            .spawn(move || {
+                while !thread_stop.load(Ordering::Acquire) && !tasks.is_empty() {
+                    let now = Instant::now();
+                    for (next, task) in tasks.iter_mut().filter(|(next, _)| *next <= now) {
+                        // Move the next run forward, skipping runs that were missed
+                        if let Some(interval) = task.interval {
+                            while *next <= now {
+                                *next += interval;
+                            }
+                        }
+
+                        if task.running.swap(true, Ordering::AcqRel) {
+                            trace!(Level::Debug, "Background task still running, skipping");
+                            continue;
+                        }
+
+                        let task = task.clone();
+                        match &pool {
+                            Some(pool) => pool.execute(move || task.run()),
+                            None => task.run(),
+                        }
+                    }
+
+                    tasks.retain(|(next, task)| task.interval.is_some() || *next > now);
+                    let next = tasks.iter().map(|(next, _)| *next).min();
+                    let wait = next.map_or(Duration::ZERO, |i| i - Instant::now().min(i));
+                    thread::sleep(wait.min(consts::SHUTDOWN_POLL));
+                }
+            })
+            .expect("Error creating scheduler thread");
+
+        Self {
+            stop,
+            handle: Some(handle),
+        }
+    }
+}
+
+impl Drop for Scheduler {
+    /// Stops the scheduler and waits for its thread to exit.
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        thread,
+        time::Duration,
+    };
+
+    use super::{Scheduler, Task};
+
+    #[test]
+    fn test_scheduler() {
+        let periodic = Arc::new(AtomicUsize::new(0));
+        let once = Arc::new(AtomicUsize::new(0));
+
+        let (periodic_count, once_count) = (periodic.clone(), once.clone());
+        let tasks = [
+            Task::periodic(Duration::from_millis(20), move || {
+                periodic_count.fetch_add(1, Ordering::AcqRel);
+            }),
+            Task::once(move || {
+                once_count.fetch_add(1, Ordering::AcqRel);
+            }),
+        ];
+
+        let scheduler = Scheduler::start(&tasks, None);
+        thread::sleep(Duration::from_millis(110));
+        drop(scheduler);
+
+        let runs = periodic.load(Ordering::Acquire);
+        assert!((3..=6).contains(&runs), "{} runs", runs);
+        assert_eq!(once.load(Ordering::Acquire), 1);
+
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(periodic.load(Ordering::Acquire), runs);
+    }
+}
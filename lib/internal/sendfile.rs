@@ -0,0 +1,50 @@
+//! Zero-copy file responses with `sendfile(2)`.
+//!
+//! When a file is sent over a plain TCP socket, the kernel can copy it directly to the socket instead of reading it into userspace and writing it back out.
+//! Enabled with the `sendfile` feature, only supported on Linux and Android.
+
+use std::{
+    fs::File,
+    io,
+    os::unix::io::{AsRawFd, RawFd},
+};
+
+use crate::response::WriteDeadline;
+
+/// The most bytes Linux will transfer in a single `sendfile` call.
+const MAX_CHUNK: usize = 0x7fff_f000;
+
+extern "C" {
+    fn sendfile(out_fd: i32, in_fd: i32, offset: *mut i64, count: usize) -> isize;
+}
+
+/// Copies the rest of `file` (from its current position) to the socket `socket` with `sendfile`.
+/// The number of bytes sent is added to `written` as they are sent, so it is correct even if sending fails partway through.
+/// Calls that fail because the socket isn't ready are retried until the `deadline` passes.
+pub(crate) fn send_file(
+    file: &File,
+    socket: RawFd,
+    written: &mut u64,
+    deadline: &mut WriteDeadline,
+) -> io::Result<()> {
+    let len = file.metadata()?.len();
+    let mut sent = 0;
+
+    while sent < len {
+        let count = ((len - sent) as usize).min(MAX_CHUNK);
+        // Safety: both file descriptors are valid for the duration of the call, and a null offset makes sendfile use (and advance) the file's position
+        let n = unsafe { sendfile(socket, file.as_raw_fd(), std::ptr::null_mut(), count) };
+
+        match n {
+            0 => break,
+            n if n < 0 => deadline.retry(io::Error::last_os_error())?,
+            n => {
+                sent += n as u64;
+                *written += n as u64;
+                deadline.progress();
+            }
+        }
+    }
+
+    Ok(())
+}
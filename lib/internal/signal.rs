@@ -0,0 +1,117 @@
+//! Gracefully shuts down servers on SIGINT and SIGTERM, see [`crate::Server::handle_signals`].
+//!
+//! The signal handler itself only writes a byte to a pipe, as that is one of the few things that is safe to do in a signal handler.
+//! A watcher thread reads from the other end of the pipe and shuts down all the servers that have registered.
+//! If a second signal is received, the process exits immediately without waiting for requests to finish.
+
+use crate::server::ShutdownHandle;
+
+/// Registers a server to be shut down when the process receives SIGINT or SIGTERM.
+/// The signal handlers are installed the first time this is called.
+#[cfg(unix)]
+pub(crate) fn register(handle: ShutdownHandle) {
+    use std::sync::Once;
+
+    use crate::internal::common::ForceLock;
+
+    static INSTALL: Once = Once::new();
+    unix::HANDLES.force_lock().push(handle);
+    INSTALL.call_once(unix::install);
+}
+
+/// Signal handling is only supported on unix platforms.
+#[cfg(not(unix))]
+pub(crate) fn register(_handle: ShutdownHandle) {
+    use crate::trace;
+    trace!(
+        Level::Error,
+        "Signal handling is not supported on this platform"
+    );
+}
+
+#[cfg(unix)]
+mod unix {
+    use std::{
+        io::{ErrorKind, Read},
+        os::{
+            raw::{c_int, c_void},
+            unix::{io::IntoRawFd, net::UnixStream},
+        },
+        sync::{
+            atomic::{AtomicBool, AtomicI32, Ordering},
+            Mutex,
+        },
+        thread,
+    };
+
+    use crate::{internal::common::ForceLock, server::ShutdownHandle, trace, trace::emoji};
+
+    const SIGINT: c_int = 2;
+    const SIGTERM: c_int = 15;
+
+    extern "C" {
+        fn signal(signum: c_int, handler: extern "C" fn(c_int)) -> usize;
+        fn write(fd: c_int, buf: *const c_void, count: usize) -> isize;
+        fn _exit(status: c_int) -> !;
+    }
+
+    /// The servers to shut down when a signal is received.
+    pub(super) static HANDLES: Mutex<Vec<ShutdownHandle>> = Mutex::new(Vec::new());
+    /// The write end of the pipe to the watcher thread.
+    static PIPE: AtomicI32 = AtomicI32::new(-1);
+    /// Set once the first signal has been received.
+    static RECEIVED: AtomicBool = AtomicBool::new(false);
+
+    /// Creates the pipe, starts the watcher thread and installs the signal handlers.
+    pub(super) fn install() {
+        let (mut rx, tx) = match UnixStream::pair() {
+            Ok(i) => i,
+            Err(e) => {
+                trace!(Level::Error, "Error creating signal pipe: {:?}", e);
+                return;
+            }
+        };
+        PIPE.store(tx.into_raw_fd(), Ordering::Release);
+
+        let watcher = thread::Builder::new()
+            .name("Signal Handler".to_owned())
+            .spawn(move || loop {
+                match rx.read(&mut [0]) {
+                    Ok(0) => return,
+                    Ok(_) => {}
+                    Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                    Err(e) => {
+                        trace!(Level::Error, "Error reading signal pipe: {:?}", e);
+                        return;
+                    }
+                }
+
+                trace!("{}Received shutdown signal", emoji("🛑"));
+                for handle in HANDLES.force_lock().drain(..) {
+                    handle.shutdown();
+                }
+            });
+
+        if let Err(e) = watcher {
+            trace!(Level::Error, "Error creating signal thread: {:?}", e);
+            return;
+        }
+
+        // SAFETY: `on_signal` only uses atomics and async-signal-safe functions.
+        unsafe {
+            signal(SIGINT, on_signal);
+            signal(SIGTERM, on_signal);
+        }
+    }
+
+    extern "C" fn on_signal(_signum: c_int) {
+        // SAFETY: `_exit` is async-signal-safe, unlike `std::process::exit`.
+        if RECEIVED.swap(true, Ordering::AcqRel) {
+            unsafe { _exit(130) };
+        }
+
+        // SAFETY: The buffer is valid for one byte and `write` is async-signal-safe.
+        let fd = PIPE.load(Ordering::Acquire);
+        unsafe { write(fd, [1u8].as_ptr() as *const c_void, 1) };
+    }
+}
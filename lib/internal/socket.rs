@@ -0,0 +1,220 @@
+//! Creating the listening socket.
+//!
+//! [`TcpListener::bind`] doesn't let you configure the socket before it starts listening,
+//! so when any listener options are changed from their defaults the socket is set up manually (on Linux).
+
+use std::{
+    io::{self, ErrorKind},
+    net::{SocketAddr, TcpListener},
+};
+
+use crate::{
+    error::{Result, StartupError},
+    trace, Server,
+};
+
+/// Binds a listener to the server's address, applying the server's listener options.
+/// On platforms other than Linux and Android, [`Server::reuse_address`], [`Server::reuse_port`] and [`Server::backlog`] are ignored.
+///
+/// Returns [`StartupError::InvalidIp`] if the server's IP could not be parsed and [`StartupError::AddressInUse`] if something is already listening on the address.
+pub(crate) fn bind<State>(this: &Server<State>) -> Result<TcpListener>
+where
+    State: 'static + Send + Sync,
+{
+    if this.invalid_ip {
+        return Err(StartupError::InvalidIp.into());
+    }
+
+    bind_listener(this).map_err(|e| match e.kind() {
+        ErrorKind::AddrInUse => StartupError::AddressInUse.into(),
+        _ => e.into(),
+    })
+}
+
+fn bind_listener<State>(this: &Server<State>) -> io::Result<TcpListener>
+where
+    State: 'static + Send + Sync,
+{
+    let addr = SocketAddr::new(this.ip, this.port);
+    let default = this.reuse_address && !this.reuse_port && this.backlog.is_none();
+    if default {
+        return TcpListener::bind(addr);
+    }
+
+    trace!(
+        Level::Debug,
+        "Binding with listener options {{ reuse_address: {}, reuse_port: {}, backlog: {:?} }}",
+        this.reuse_address,
+        this.reuse_port,
+        this.backlog
+    );
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    return sys::bind_with_options(addr, this);
+
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    {
+        trace!(
+            Level::Error,
+            "Listener options are not supported on this platform, ignoring them"
+        );
+        TcpListener::bind(addr)
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod sys {
+    use std::{
+        io,
+        mem::size_of,
+        net::{SocketAddr, TcpListener},
+        os::{
+            raw::{c_int, c_void},
+            unix::io::{AsRawFd, FromRawFd},
+        },
+    };
+
+    use crate::Server;
+
+    const AF_INET: c_int = 2;
+    const AF_INET6: c_int = 10;
+    const SOCK_STREAM: c_int = 1;
+    const SOCK_CLOEXEC: c_int = 0o2000000;
+    const SOL_SOCKET: c_int = 1;
+    const SO_REUSEADDR: c_int = 2;
+    const SO_REUSEPORT: c_int = 15;
+
+    /// The `sockaddr_in` struct from `netinet/in.h`.
+    #[repr(C)]
+    struct SockAddrIn {
+        family: u16,
+        port: u16,
+        addr: [u8; 4],
+        zero: [u8; 8],
+    }
+
+    /// The `sockaddr_in6` struct from `netinet/in.h`.
+    #[repr(C)]
+    struct SockAddrIn6 {
+        family: u16,
+        port: u16,
+        flow_info: u32,
+        addr: [u8; 16],
+        scope_id: u32,
+    }
+
+    extern "C" {
+        fn socket(domain: c_int, kind: c_int, protocol: c_int) -> c_int;
+        fn setsockopt(
+            fd: c_int,
+            level: c_int,
+            name: c_int,
+            value: *const c_void,
+            len: u32,
+        ) -> c_int;
+        fn bind(fd: c_int, addr: *const c_void, len: u32) -> c_int;
+        fn listen(fd: c_int, backlog: c_int) -> c_int;
+    }
+
+    /// Turns the return value of a libc function into a Result.
+    fn check(res: c_int) -> io::Result<c_int> {
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(res)
+    }
+
+    pub(super) fn bind_with_options<State>(
+        addr: SocketAddr,
+        this: &Server<State>,
+    ) -> io::Result<TcpListener>
+    where
+        State: 'static + Send + Sync,
+    {
+        let family = match addr {
+            SocketAddr::V4(_) => AF_INET,
+            SocketAddr::V6(_) => AF_INET6,
+        };
+
+        // SAFETY: All pointers passed to libc point to live values, with their sizes passed along with them.
+        // The new file descriptor is immediately owned by the TcpListener, so it will be closed if anything fails.
+        unsafe {
+            let fd = check(socket(family, SOCK_STREAM | SOCK_CLOEXEC, 0))?;
+            let listener = TcpListener::from_raw_fd(fd);
+
+            let set_option = |name, value: bool| {
+                let value = value as c_int;
+                check(setsockopt(
+                    fd,
+                    SOL_SOCKET,
+                    name,
+                    &value as *const c_int as *const c_void,
+                    size_of::<c_int>() as u32,
+                ))
+            };
+            set_option(SO_REUSEADDR, this.reuse_address)?;
+            set_option(SO_REUSEPORT, this.reuse_port)?;
+
+            match addr {
+                SocketAddr::V4(addr) => {
+                    let raw = SockAddrIn {
+                        family: AF_INET as u16,
+                        port: addr.port().to_be(),
+                        addr: addr.ip().octets(),
+                        zero: [0; 8],
+                    };
+                    let ptr = &raw as *const SockAddrIn as *const c_void;
+                    check(bind(fd, ptr, size_of::<SockAddrIn>() as u32))?;
+                }
+                SocketAddr::V6(addr) => {
+                    let raw = SockAddrIn6 {
+                        family: AF_INET6 as u16,
+                        port: addr.port().to_be(),
+                        flow_info: addr.flowinfo(),
+                        addr: addr.ip().octets(),
+                        scope_id: addr.scope_id(),
+                    };
+                    let ptr = &raw as *const SockAddrIn6 as *const c_void;
+                    check(bind(fd, ptr, size_of::<SockAddrIn6>() as u32))?;
+                }
+            }
+
+            let backlog = this.backlog.unwrap_or(128).min(c_int::MAX as u32) as c_int;
+            check(listen(listener.as_raw_fd(), backlog))?;
+
+            Ok(listener)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::TcpListener;
+
+    use crate::{
+        error::{Error, StartupError},
+        Server,
+    };
+
+    #[test]
+    fn test_invalid_ip() {
+        let server = Server::<()>::new("not an ip", 0);
+        assert_eq!(
+            server.start().unwrap_err(),
+            Error::Startup(StartupError::InvalidIp)
+        );
+    }
+
+    #[test]
+    fn test_address_in_use() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let server = Server::<()>::new("localhost", port);
+        assert_eq!(
+            server.start().unwrap_err(),
+            Error::Startup(StartupError::AddressInUse)
+        );
+    }
+}
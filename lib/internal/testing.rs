@@ -0,0 +1,99 @@
+//! In-memory streams and listeners for testing the server without opening sockets.
+//!
+//! Each [`MemoryStream`] reads its input from a buffer and writes everything the server sends to a shared output, which can be checked once the server stops.
+//! [`MemoryListener`] hands out its streams from last to first, then fails, which stops the server.
+
+use std::{
+    io::{self, Cursor, Read, Write},
+    net::{Ipv4Addr, Shutdown, SocketAddr},
+    sync::{Arc, Mutex},
+};
+
+use crate::listener::{Listener, Stream, TlsInfo};
+
+/// A connection from localhost that looks like it uses TLS 1.3.
+pub(crate) struct MemoryStream {
+    pub input: Cursor<Vec<u8>>,
+    pub output: Arc<Mutex<Vec<u8>>>,
+}
+
+/// A listener that accepts each of its streams once.
+pub(crate) struct MemoryListener<S = MemoryStream>(pub Mutex<Vec<S>>);
+
+/// A [`MemoryStream`] without TLS.
+pub(crate) struct CleartextStream(pub MemoryStream);
+
+impl Read for MemoryStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.input.read(buf)
+    }
+}
+
+impl Write for MemoryStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.output.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Stream for MemoryStream {
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        Ok((Ipv4Addr::LOCALHOST, 0).into())
+    }
+
+    fn shutdown(&self, _how: Shutdown) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn tls_info(&self) -> Option<TlsInfo> {
+        Some(TlsInfo {
+            version: Some("TLSv1.3".to_owned()),
+            ..TlsInfo::default()
+        })
+    }
+}
+
+impl Read for CleartextStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Write for CleartextStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Stream for CleartextStream {
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.0.peer_addr()
+    }
+
+    fn shutdown(&self, _how: Shutdown) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<S: Stream + 'static> Listener for MemoryListener<S> {
+    type Stream = S;
+
+    fn accept(&self) -> io::Result<S> {
+        self.0
+            .lock()
+            .unwrap()
+            .pop()
+            .ok_or_else(|| io::Error::other("No more streams"))
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok((Ipv4Addr::LOCALHOST, 0).into())
+    }
+}
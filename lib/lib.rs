@@ -8,44 +8,83 @@ pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 /// Contains all the constants used in afire.
 /// These may be in the future moved into the [`Server`] struct.
 mod consts {
+    use std::time::Duration;
+
     /// The initial buffer allocation for the request.
+    #[cfg(feature = "client")]
     pub const BUFF_SIZE: usize = 256;
 
-    /// Max chunk size for chunked transfer encoding.
+    /// Default max chunk size for chunked transfer encoding.
     pub const CHUNK_SIZE: usize = 16 * 1024;
+
+    /// Default number of unused buffers kept for reuse between requests.
+    pub const BUFFER_POOL_SIZE: usize = 64;
+
+    /// How long a streaming response body can keep returning `WouldBlock` before the response is aborted.
+    pub const STREAM_RETRY_TIMEOUT: Duration = Duration::from_secs(30);
+
+    /// How long to wait before reading from a streaming response body again after it returns `WouldBlock`.
+    pub const STREAM_RETRY_DELAY: Duration = Duration::from_millis(1);
+
+    /// How often idle keep-alive connections check if the server is shutting down.
+    pub const SHUTDOWN_POLL: Duration = Duration::from_millis(100);
+
+    /// How long to wait for the client to close its side of a connection after the server has closed its side.
+    pub const LINGER_TIMEOUT: Duration = Duration::from_secs(2);
+
+    /// The most data that will be read and discarded while waiting for the client to close its side of a connection.
+    pub const LINGER_LIMIT: usize = 64 * 1024;
 }
 
 // Export Internal Functions
 pub mod internal;
 
 // Import Internal Functions
-mod thread_pool;
+pub mod thread_pool;
 use http::*;
-use internal::{encoding, handle, path};
+use internal::{handle, path};
 
 #[macro_use]
 pub mod trace;
+pub mod app;
+pub mod body_filter;
+pub mod clock;
+pub mod config;
+pub mod context;
 pub mod error;
 mod http;
+pub mod listener;
 pub mod middleware;
+pub mod render;
 mod request;
 mod response;
 mod route;
 mod server;
+pub mod timing;
 pub use self::{
-    content_type::Content,
+    app::App,
+    body_filter::BodyFilter,
+    clock::Clock,
+    config::ServerConfig,
+    content_type::{Content, MimeTypes},
+    context::Context,
     cookie::{Cookie, SetCookie},
+    download::Download,
     error::Error,
     header::{Header, HeaderType},
-    http::{cookie, header, multipart, server_sent_events},
+    http::{cookie, download, header, multipart, server_sent_events, web_socket},
     method::Method,
     middleware::Middleware,
     query::Query,
-    request::Request,
-    response::Response,
-    route::Route,
-    server::Server,
+    request::{BodyReader, ConnectionInfo, Extensions, Request},
+    response::{Response, WriteStats},
+    route::{Route, RouteId, RouteInfo, RouteMeta, RouteTable, TrailingSlash},
+    server::{
+        ConnectionRejection, ConnectionStats, PanicPolicy, Server, ShutdownHandle, ShutdownToken,
+        UriPolicy,
+    },
     status::Status,
+    timing::Timing,
 };
 
 /// The Prelude is a collection of very commonly used *things* in afire.
@@ -55,12 +94,31 @@ pub mod prelude {
         error::{self, Error},
         middleware::{MiddleResult, Middleware},
         server_sent_events::ServerSentEventsExt,
-        Content, Cookie, Header, HeaderType, Method, Query, Request, Response, Server, SetCookie,
-        Status,
+        web_socket::WebSocketExt,
+        Content, Context, Cookie, Header, HeaderType, Method, Query, Request, Response, Server,
+        SetCookie, Status,
     };
 }
 
+pub mod encoding {
+    //! Encoding, decoding and hashing utilities.
+    //! These are used by afire internally, and are exposed so extensions and middleware can use them without extra dependencies.
+    //!
+    //! Unlike the functions in [`crate::internal`], these are part of the public API and will remain stable between updates.
+    //!
+    //! | Module     | Description                                          |
+    //! | ---------- | ---------------------------------------------------- |
+    //! | [`base64`] | Base64 encoding and decoding.                        |
+    //! | [`hex`]    | Hex encoding and decoding.                           |
+    //! | [`sha1`]   | SHA-1 hash function, only use it if you have to.     |
+    //! | [`sha256`] | SHA-256 hash function and HMAC-SHA256.               |
+    //! | [`url`]    | Percent encoding and decoding for urls and paths.    |
+    pub use crate::internal::encoding::{base64, hex, sha1, sha256, url};
+}
+
 // Extra Features
+#[cfg(feature = "client")]
+pub mod client;
 #[cfg(feature = "extensions")]
 mod extensions;
 #[cfg(feature = "extensions")]
@@ -69,24 +127,62 @@ pub mod extension {
     //! Includes helpful middleware like Serve Static, Rate Limit and Logger.
     //!
     //! ## All Feature
-    //! | Name            | Description                                           |
-    //! | --------------- | ----------------------------------------------------- |
-    //! | [`Date`]        | Add the Date header to responses. Required by HTTP.   |
-    //! | [`Head`]        | Add support for HTTP `HEAD` requests.                 |
-    //! | [`Logger`]      | Log incoming requests to the console / file.          |
-    //! | [`RateLimiter`] | Limit how many requests can be handled from a source. |
-    //! | [`RealIp`]      | Get the real IP of a client through a reverse proxy   |
-    //! | [`RequestId`]   | Add a Request-Id header to all requests.              |
-    //! | [`ServeStatic`] | Serve static files from a dir.                        |
-    //! | [`Trace`]       | Add support for the HTTP `TRACE` method.              |
+    //! | Name               | Description                                           |
+    //! | ------------------ | ----------------------------------------------------- |
+    //! | [`AcmeChallenge`]  | Answer ACME `http-01` challenges for certificates.    |
+    //! | [`Canonicalize`]   | Redirect requests to the canonical form of their URL. |
+    //! | [`Date`]           | Add the Date header (already on by default).\*        |
+    //! | [`ETag`]           | Add ETags to responses and handle `If-None-Match`.    |
+    //! | [`Favicon`]        | Serve the site's icon on `/favicon.ico`.              |
+    //! | [`ForceHttps`]     | Redirect HTTP requests to HTTPS and enable HSTS.      |
+    //! | [`Head`]           | Add support for HTTP `HEAD` requests.                 |
+    //! | [`IpFilter`]       | Allow or deny requests by IP address (CIDR ranges).   |
+    //! | [`Logger`]         | Log incoming requests to the console / file.          |
+    //! | [`MethodOverride`] | Let HTML forms use methods other than GET and POST.   |
+    //! | [`OpenApi`]        | Serve an OpenAPI spec generated from the routes.      |
+    //! | [`PathRewrite`]    | Rewrite or strip prefixes from paths before routing.  |
+    //! | [`RateLimiter`]    | Limit how many requests can be handled from a source. |
+    //! | [`RealIp`]         | Get the real IP of a client through a reverse proxy   |
+    //! | [`RealIpResolver`] | Replace request addresses with the real client IP     |
+    //! | [`Recorder`]       | Record requests and responses to disk for replaying.  |
+    //! | [`RequestId`]      | Add a Request-Id header to all requests.              |
+    //! | [`RobotsTxt`]      | Serve crawler rules on `/robots.txt`.                 |
+    //! | [`RouteDebug`]     | List the server's routes, for debugging.              |
+    //! | [`ServeStatic`]    | Serve static files from a dir.                        |
+    //! | [`Throttle`]       | Limit the bandwidth used by responses.                |
+    //! | [`Timeout`]        | Respond with a 504 when handlers take too long.       |
+    //! | [`Trace`]          | Add support for the HTTP `TRACE` method.              |
+    //! | [`TraceContext`]   | Propagate W3C Trace Context headers.                  |
+    //! | [`Upload`]         | Save files uploaded with PUT or POST to a dir.        |
+    //! | [`WebDav`]         | Serve a directory over WebDAV.                        |
+    //!
+    //! \* The Date header is added to every response by default with the `date-header` feature.
+    //! The [`Date`] extension only exists for compatibility, or to add the header when that feature is disabled.
     pub use crate::extensions::{
+        acme_challenge::AcmeChallenge,
+        canonicalize::{self, Canonicalize},
         date::{self, Date},
+        etag::ETag,
+        favicon::Favicon,
+        force_https::ForceHttps,
         head::Head,
+        ip_filter::IpFilter,
         logger::{self, Logger},
-        ratelimit::RateLimiter,
-        real_ip::RealIp,
-        request_id::RequestId,
+        method_override::MethodOverride,
+        openapi::{self, OpenApi},
+        path_rewrite::PathRewrite,
+        ratelimit::{self, RateLimiter},
+        real_ip::{RealIp, RealIpResolver},
+        recorder::{self, Recorder},
+        request_id::{self, RequestId},
+        robots_txt::RobotsTxt,
+        route_debug::RouteDebug,
         serve_static::{self, ServeStatic},
+        throttle::{self, Throttle},
+        timeout::{self, Timeout},
         trace::Trace,
+        trace_context::{self, TraceContext},
+        upload::{self, Upload},
+        webdav::WebDav,
     };
 }
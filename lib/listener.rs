@@ -0,0 +1,363 @@
+//! Abstractions over the transport the server accepts connections from.
+//!
+//! By default afire listens on a [`TcpListener`], but any type implementing [`Listener`] can be used with [`crate::Server::start_with_listener`] or [`crate::Server::start_threaded_with_listener`].
+//! This lets you supply things like TLS acceptors, in-memory streams for tests or proxied transports.
+//!
+//! ## Example
+//! A listener that wraps a [`TcpListener`] and logs every connection.
+//! ```rust,no_run
+//! # use std::{io, net::{SocketAddr, TcpListener, TcpStream}};
+//! # use afire::{listener::Listener, Server};
+//! struct LoggingListener(TcpListener);
+//!
+//! impl Listener for LoggingListener {
+//!     type Stream = TcpStream;
+//!
+//!     fn accept(&self) -> io::Result<TcpStream> {
+//!         let (stream, addr) = self.0.accept()?;
+//!         println!("New connection from {}", addr);
+//!         Ok(stream)
+//!     }
+//!
+//!     fn local_addr(&self) -> io::Result<SocketAddr> {
+//!         self.0.local_addr()
+//!     }
+//! }
+//!
+//! let server = Server::<()>::new("localhost", 8080);
+//! let listener = LoggingListener(TcpListener::bind("localhost:8080").unwrap());
+//! server.start_with_listener(listener).unwrap();
+//! ```
+
+#[cfg(unix)]
+use std::os::unix::{
+    io::{AsRawFd, RawFd},
+    net::{UnixListener, UnixStream},
+};
+use std::{
+    io::{self, Read, Write},
+    net::{Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpListener, TcpStream},
+    time::Duration,
+};
+
+/// A source of incoming connections.
+pub trait Listener {
+    /// The type of connection this listener accepts.
+    type Stream: Stream + 'static;
+
+    /// Waits for and accepts a new connection.
+    fn accept(&self) -> io::Result<Self::Stream>;
+
+    /// Gets the address this listener is bound to.
+    /// Listeners without an IP address, like Unix sockets, should return [`io::ErrorKind::Unsupported`].
+    fn local_addr(&self) -> io::Result<SocketAddr>;
+
+    /// Gets a function that wakes up a thread blocked in [`Listener::accept`], which is called when the server shuts down so it can stop accepting connections.
+    /// By default it connects to [`Listener::local_addr`] over TCP, so listeners that can't be reached that way should override this.
+    /// If this fails, the server stops once the next connection is accepted.
+    fn waker(&self) -> io::Result<Box<dyn Fn() + Send + Sync>> {
+        let mut addr = self.local_addr()?;
+        if addr.ip().is_unspecified() {
+            addr.set_ip(match addr {
+                SocketAddr::V4(_) => Ipv4Addr::LOCALHOST.into(),
+                SocketAddr::V6(_) => Ipv6Addr::LOCALHOST.into(),
+            });
+        }
+
+        Ok(Box::new(move || {
+            let _ = TcpStream::connect_timeout(&addr, Duration::from_secs(1));
+        }))
+    }
+}
+
+/// A connection that a request can be read from and a response written to.
+/// Only [`Stream::peer_addr`] and [`Stream::shutdown`] are required, the socket options are ignored by default.
+pub trait Stream: Read + Write + Send {
+    /// Gets the address of the client on the other end of the connection.
+    fn peer_addr(&self) -> io::Result<SocketAddr>;
+
+    /// Gets the local address of the connection, that the client connected to.
+    /// Used for [`crate::Request::local_address`], by default this is unsupported.
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    /// Shuts down the read half, write half or both halves of the connection.
+    fn shutdown(&self, how: Shutdown) -> io::Result<()>;
+
+    /// Sets the read timeout.
+    /// Used for [`crate::Server::socket_timeout`] and [`crate::Server::keep_alive_timeout`].
+    fn set_read_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Sets the write timeout.
+    /// Used for [`crate::Server::socket_timeout`].
+    fn set_write_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Sets the `TCP_NODELAY` option.
+    /// Used for [`crate::Server::tcp_nodelay`].
+    fn set_nodelay(&self, _nodelay: bool) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Reads data into `buf` without removing it from the stream.
+    /// Used to wait for the next request on keep-alive connections, if this is unsupported (the default) the connection is assumed to be alive.
+    fn peek(&self, _buf: &mut [u8]) -> io::Result<usize> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    /// Creates another handle to the same connection.
+    /// Used by [`crate::web_socket`] to read messages from the client while others are being sent, and by [`crate::Server::reap_idle_connections`] to shut down idle connections while a read on them is blocked.
+    /// By default this is unsupported, so WebSockets can't be opened on such connections and they aren't reaped.
+    fn try_clone(&self) -> io::Result<Box<dyn Stream>> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    /// Gets the raw file descriptor of the underlying socket, if data written to it is sent to the client as is.
+    /// Used to send files with `sendfile` when the `sendfile` feature is enabled.
+    /// Streams that transform the data (like TLS) must return None, which is the default.
+    #[cfg(unix)]
+    fn raw_fd(&self) -> Option<RawFd> {
+        None
+    }
+
+    /// Gets information about the TLS session, for streams that handle TLS.
+    /// This is read once the first request on the connection has been received (so the handshake is done), and is available from [`crate::ConnectionInfo::tls`].
+    /// By default this is None.
+    fn tls_info(&self) -> Option<TlsInfo> {
+        None
+    }
+
+    /// Gets the identity of the process on the other end of the connection, for local sockets that support it.
+    /// This is read when the connection is accepted, and is available from [`crate::ConnectionInfo::peer_credentials`].
+    /// By default this is None.
+    fn peer_credentials(&self) -> Option<PeerCredentials> {
+        None
+    }
+}
+
+/// Information about the TLS session of a connection, from [`Stream::tls_info`].
+/// ## Example
+/// ```rust
+/// # use afire::listener::TlsInfo;
+/// let mut info = TlsInfo::default();
+/// info.version = Some("TLSv1.3".to_owned());
+/// info.alpn = Some("http/1.1".to_owned());
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct TlsInfo {
+    /// The protocol version, like `TLSv1.3`.
+    pub version: Option<String>,
+    /// The negotiated cipher suite, like `TLS13_AES_128_GCM_SHA256`.
+    pub cipher: Option<String>,
+    /// The protocol negotiated with ALPN, like `http/1.1`.
+    pub alpn: Option<String>,
+    /// The server name the client asked for with SNI.
+    pub server_name: Option<String>,
+}
+
+/// The identity of the process on the other end of a local connection, from [`Stream::peer_credentials`].
+/// These are the credentials the peer had when it connected, not necessarily its current ones.
+/// ## Example
+/// ```rust
+/// # use afire::{Method, Response, Server, Status};
+/// # let mut server = Server::<()>::new("localhost", 8080);
+/// // Only let processes running as root use the admin API
+/// server.route(Method::POST, "/admin/reload", |req| {
+///     match req.connection().peer_credentials() {
+///         Some(peer) if peer.uid == 0 => Response::new().text("Reloading"),
+///         _ => Response::new().status(Status::Forbidden),
+///     }
+/// });
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct PeerCredentials {
+    /// The id of the process, if the platform reports it (only Linux and Android do).
+    pub pid: Option<u32>,
+    /// The effective user id of the process.
+    pub uid: u32,
+    /// The effective group id of the process.
+    pub gid: u32,
+}
+
+impl Listener for TcpListener {
+    type Stream = TcpStream;
+
+    fn accept(&self) -> io::Result<TcpStream> {
+        TcpListener::accept(self).map(|(stream, _)| stream)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        TcpListener::local_addr(self)
+    }
+}
+
+impl Stream for TcpStream {
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        TcpStream::peer_addr(self)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        TcpStream::local_addr(self)
+    }
+
+    fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        TcpStream::shutdown(self, how)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_write_timeout(self, timeout)
+    }
+
+    fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+        TcpStream::set_nodelay(self, nodelay)
+    }
+
+    fn peek(&self, buf: &mut [u8]) -> io::Result<usize> {
+        TcpStream::peek(self, buf)
+    }
+
+    fn try_clone(&self) -> io::Result<Box<dyn Stream>> {
+        TcpStream::try_clone(self).map(|i| Box::new(i) as Box<dyn Stream>)
+    }
+
+    #[cfg(unix)]
+    fn raw_fd(&self) -> Option<RawFd> {
+        Some(self.as_raw_fd())
+    }
+}
+
+/// Unix sockets don't have an IP address, so [`Listener::local_addr`] is unsupported and [`crate::Server::on_start`] isn't run.
+/// The server is woken up to shut down by connecting to the socket's path.
+#[cfg(unix)]
+impl Listener for UnixListener {
+    type Stream = UnixStream;
+
+    fn accept(&self) -> io::Result<UnixStream> {
+        UnixListener::accept(self).map(|(stream, _)| stream)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    fn waker(&self) -> io::Result<Box<dyn Fn() + Send + Sync>> {
+        let addr = UnixListener::local_addr(self)?;
+        let path = addr
+            .as_pathname()
+            .ok_or(io::ErrorKind::Unsupported)?
+            .to_owned();
+
+        Ok(Box::new(move || {
+            let _ = UnixStream::connect(&path);
+        }))
+    }
+}
+
+/// Clients on Unix sockets are always on the same machine, so their [`crate::Request::address`] is `127.0.0.1:0`.
+/// Use [`crate::ConnectionInfo::peer_credentials`] to tell them apart.
+#[cfg(unix)]
+impl Stream for UnixStream {
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        Ok((Ipv4Addr::LOCALHOST, 0).into())
+    }
+
+    fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        UnixStream::shutdown(self, how)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_write_timeout(self, timeout)
+    }
+
+    fn try_clone(&self) -> io::Result<Box<dyn Stream>> {
+        UnixStream::try_clone(self).map(|i| Box::new(i) as Box<dyn Stream>)
+    }
+
+    fn raw_fd(&self) -> Option<RawFd> {
+        Some(self.as_raw_fd())
+    }
+
+    fn peer_credentials(&self) -> Option<PeerCredentials> {
+        crate::internal::peer_cred::peer_credentials(self.as_raw_fd())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::Cursor,
+        sync::{Arc, Mutex},
+    };
+
+    use crate::{
+        internal::testing::{MemoryListener, MemoryStream},
+        Method, Response, Server,
+    };
+
+    #[test]
+    fn test_memory_listener() {
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let listener = MemoryListener(Mutex::new(vec![MemoryStream {
+            input: Cursor::new(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n".to_vec()),
+            output: output.clone(),
+        }]));
+
+        let mut server = Server::<()>::new("localhost", 0);
+        server.route(Method::GET, "/", |_| Response::new().text("Hello"));
+        assert!(server.start_with_listener(listener).is_err());
+
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        assert!(output.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(output.ends_with("\r\n\r\nHello"));
+    }
+
+    #[test]
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn test_peer_credentials() {
+        use std::{
+            io::{Read, Write},
+            os::unix::net::{UnixListener, UnixStream},
+            thread,
+        };
+
+        let path = std::env::temp_dir().join(format!("afire-peer-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+
+        let mut server = Server::<()>::new("localhost", 0);
+        server.route(Method::GET, "/", |req| {
+            let peer = req.connection().peer_credentials().unwrap();
+            Response::new().text(peer.pid.unwrap()).close()
+        });
+        let shutdown = server.shutdown_handle();
+        let handle = thread::spawn(move || server.start_with_listener(listener).is_ok());
+
+        let mut stream = UnixStream::connect(&path).unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut output = String::new();
+        stream.read_to_string(&mut output).unwrap();
+        assert!(output.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(output.ends_with(&format!("\r\n\r\n{}", std::process::id())));
+        drop(stream);
+
+        shutdown.shutdown();
+        assert!(handle.join().unwrap());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -2,9 +2,14 @@
 //! They can be used to Log Requests, Ratelimit Requests, add Analytics, etc.
 //! For more information, see the [Middleware Example](https://github.com/Basicprogrammer10/afire/blob/main/examples/basic/middleware.rs).
 
-use std::{any::type_name, rc::Rc};
+#[cfg(feature = "tracing")]
+use std::any::type_name;
+use std::rc::Rc;
 
-use crate::{error::Result, trace::emoji, Request, Response, Server};
+use crate::{error::Result, Request, Response, Server};
+
+#[cfg(feature = "tracing")]
+use crate::trace::emoji;
 
 /// A response from a middleware handler
 pub enum MiddleResult {
@@ -0,0 +1,122 @@
+//! Rendering templates into responses.
+//!
+//! Anything that implements [`Render`] can be sent with [`crate::Response::render`], which sets the body and the content type.
+//! Adapters for [tinytemplate](https://docs.rs/tinytemplate) and [minijinja](https://docs.rs/minijinja) are available with the `tinytemplate` and `minijinja` features.
+//! ## Example
+//! ```rust
+//! # use afire::{Method, Response, Server, render::{Render, RenderError}};
+//! struct Greeting<'a> {
+//!     name: &'a str,
+//! }
+//!
+//! impl Render for Greeting<'_> {
+//!     fn render(&self) -> Result<String, RenderError> {
+//!         Ok(format!("<h1>Hello, {}!</h1>", self.name))
+//!     }
+//! }
+//!
+//! # fn test(server: &mut Server) {
+//! server.route(Method::GET, "/hello/{name}", |req| {
+//!     let name = req.param("name").unwrap();
+//!     Response::new().render(Greeting { name: &name })
+//! });
+//! # }
+//! ```
+
+use std::error::Error;
+
+use crate::Content;
+
+/// An error from rendering a template.
+pub type RenderError = Box<dyn Error + Send + Sync>;
+
+/// Something that can be rendered into a response body, like a template with its data.
+pub trait Render {
+    /// Renders the body.
+    fn render(&self) -> Result<String, RenderError>;
+
+    /// The content type of the rendered body, HTML by default.
+    fn content_type(&self) -> Content<'_> {
+        Content::HTML
+    }
+}
+
+/// Renders a template from a [`tinytemplate::TinyTemplate`] with some data.
+/// TinyTemplate can't be shared between threads, but parsing templates is cheap, so they can be made when handling the request.
+/// ## Example
+/// ```rust
+/// # use afire::{Method, Response, Server, render::TinyTemplate};
+/// # use std::collections::HashMap;
+/// # fn test(server: &mut Server) {
+/// server.route(Method::GET, "/hello/{name}", |req| {
+///     let mut templates = tinytemplate::TinyTemplate::new();
+///     templates.add_template("hello", "<h1>Hello, {name}!</h1>").unwrap();
+///
+///     let data = HashMap::from([("name", req.param("name").unwrap())]);
+///     Response::new().render(TinyTemplate::new(&templates, "hello", &data))
+/// });
+/// # }
+/// ```
+#[cfg(feature = "tinytemplate")]
+pub struct TinyTemplate<'a, T> {
+    templates: &'a tinytemplate::TinyTemplate<'a>,
+    name: &'a str,
+    data: &'a T,
+}
+
+#[cfg(feature = "tinytemplate")]
+impl<'a, T: serde::Serialize> TinyTemplate<'a, T> {
+    /// Creates a new TinyTemplate renderer for the template with the given name.
+    pub fn new(templates: &'a tinytemplate::TinyTemplate<'a>, name: &'a str, data: &'a T) -> Self {
+        Self {
+            templates,
+            name,
+            data,
+        }
+    }
+}
+
+#[cfg(feature = "tinytemplate")]
+impl<T: serde::Serialize> Render for TinyTemplate<'_, T> {
+    fn render(&self) -> Result<String, RenderError> {
+        Ok(self.templates.render(self.name, self.data)?)
+    }
+}
+
+/// Renders a [`minijinja::Template`] with some data.
+/// ## Example
+/// ```rust
+/// # use afire::{Method, Response, Server, render::MiniJinja};
+/// # use std::{collections::HashMap, sync::Arc};
+/// # fn test(server: &mut Server) {
+/// let mut env = minijinja::Environment::new();
+/// env.add_template("hello", "<h1>Hello, {{ name }}!</h1>").unwrap();
+/// let env = Arc::new(env);
+///
+/// server.route(Method::GET, "/hello/{name}", move |req| {
+///     let template = env.get_template("hello").unwrap();
+///     let data = HashMap::from([("name", req.param("name").unwrap())]);
+///     Response::new().render(MiniJinja::new(template, data))
+/// });
+/// # }
+/// ```
+#[cfg(feature = "minijinja")]
+pub struct MiniJinja<'a, T> {
+    template: minijinja::Template<'a, 'a>,
+    data: T,
+}
+
+#[cfg(feature = "minijinja")]
+impl<'a, T: serde::Serialize> MiniJinja<'a, T> {
+    /// Creates a new MiniJinja renderer for a template.
+    pub fn new(template: minijinja::Template<'a, 'a>, data: T) -> Self {
+        Self { template, data }
+    }
+}
+
+#[cfg(feature = "minijinja")]
+impl<T: serde::Serialize> Render for MiniJinja<'_, T> {
+    fn render(&self) -> Result<String, RenderError> {
+        Ok(self.template.render(&self.data)?)
+    }
+}
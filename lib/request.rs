@@ -1,20 +1,37 @@
 use std::{
+    any::{Any, TypeId},
     borrow::Cow,
-    cell::RefCell,
+    cell::{Cell, RefCell},
+    collections::HashMap,
     fmt::Debug,
-    io::{BufRead, BufReader, Read},
-    net::{SocketAddr, TcpStream},
-    str::FromStr,
-    sync::{Arc, Mutex},
+    io::{self, BufRead, Read, Write},
+    mem,
+    net::{IpAddr, SocketAddr},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard, OnceLock,
+    },
+    time::{Duration, Instant},
 };
 
 use crate::{
-    consts::BUFF_SIZE,
+    clock::{Clock, SystemClock},
     cookie::CookieJar,
     error::{ParseError, Result, StreamError},
-    header::{HeaderType, Headers},
-    internal::common::ForceLock,
-    Cookie, Error, Header, Method, Query,
+    header::{
+        typed::{Accept, AcceptLanguage, Forwarded},
+        HeaderType, Headers,
+    },
+    internal::{
+        buffer_pool::PooledBuffer,
+        common::{in_range, is_disconnect, ForceLock},
+        handle::ConnectionState,
+        parser::{valid_host, HeadLimits, HeadParser, ParseState},
+    },
+    listener::{PeerCredentials, Stream, TlsInfo},
+    timing::{Phase, Timing},
+    web_socket::WebSocketConfig,
+    Content, Header, Method, Query, RouteMeta, ShutdownToken, Status,
 };
 
 /// Http Request
@@ -33,6 +50,9 @@ pub struct Request {
     /// Path Params, filled by the router
     pub(crate) path_params: RefCell<Vec<(String, String)>>,
 
+    /// The metadata of the route that handles the request, see [`Request::route_meta`].
+    pub(crate) route_meta: RefCell<Option<Arc<RouteMeta>>>,
+
     /// Request Query.
     pub query: Query,
 
@@ -50,16 +70,362 @@ pub struct Request {
     /// If you are using a reverse proxy, this will be the address of the proxy (often localhost).
     pub address: SocketAddr,
 
-    /// The raw tcp socket
-    pub socket: Arc<Mutex<TcpStream>>,
+    /// Server socket address that the request was received on.
+    /// This is useful when the server is listening on multiple interfaces (like `0.0.0.0`).
+    /// Will be None if the [`Stream`] doesn't support [`Stream::local_addr`].
+    pub local_address: Option<SocketAddr>,
+
+    /// The raw socket.
+    /// This is a [`std::net::TcpStream`] unless the server was started with a custom [`crate::listener::Listener`].
+    pub socket: Arc<Mutex<Box<dyn Stream>>>,
+
+    /// When each phase of handling the request finished, see [`Request::timing`].
+    pub(crate) timing: Cell<Timing>,
+
+    /// Values attached to the request by middleware, see [`Extensions`].
+    pub extensions: Extensions,
+
+    /// The part of a streamed body that is still to be read, see [`crate::Server::route_stream_body`].
+    pub(crate) body_stream: RefCell<Option<BodyStream>>,
+
+    /// The connection the request was received on, see [`Request::connection`].
+    pub(crate) connection: Arc<ConnectionInfo>,
+
+    /// The request line and headers as they were received, see [`Request::raw_head`].
+    pub(crate) raw_head: Vec<u8>,
+
+    /// If the handler took over the socket, see [`Request::consume_socket`].
+    pub(crate) socket_consumed: Cell<bool>,
+
+    /// The clock of the server, see [`Request::clock`].
+    pub(crate) clock: Arc<dyn Clock>,
+
+    /// How the URL the client used is found, see [`Request::base_url`].
+    pub(crate) url_config: Arc<UrlConfig>,
+
+    /// The server's WebSocket limits, see [`crate::Server::web_socket_limits`].
+    pub(crate) web_socket_config: Arc<WebSocketConfig>,
+
+    /// Tells when the server is shutting down, see [`Request::shutdown_token`].
+    pub(crate) shutdown: ShutdownToken,
+}
+
+/// The server's settings for [`Request::base_url`], set with [`crate::Server::public_url`] and [`crate::Server::trust_proxy`].
+#[derive(Debug, Clone, Default)]
+pub(crate) struct UrlConfig {
+    /// The URL clients use to reach the server, without a trailing slash.
+    pub(crate) public_url: Option<String>,
+    /// Proxies trusted to set the forwarded headers, as an address and prefix length.
+    /// Proxies on localhost are always trusted.
+    pub(crate) trusted_proxies: Vec<(IpAddr, u8)>,
+}
+
+/// A map of values attached to a request, with one value of each type.
+/// Middleware can add values in `pre`, which routes and later middleware can then read.
+/// It is best to use types defined in your own crate as the keys, to avoid clashing with other middleware.
+/// ## Example
+/// ```rust
+/// # use afire::{Server, Response, Method, Middleware, Request, middleware::MiddleResult};
+/// struct User(String);
+///
+/// struct Auth;
+///
+/// impl Middleware for Auth {
+///     fn pre(&self, req: &mut Request) -> MiddleResult {
+///         if let Some(user) = req.headers.get("X-User") {
+///             let user = User(user.to_owned());
+///             req.extensions.insert(user);
+///         }
+///         MiddleResult::Continue
+///     }
+/// }
+///
+/// # let mut server = Server::<()>::new("localhost", 8080);
+/// Auth.attach(&mut server);
+/// server.route(Method::GET, "/", |req| {
+///     let user = req.extensions.get::<User>().map_or("nobody", |i| &i.0);
+///     Response::new().text(format!("Hello, {}", user))
+/// });
+/// ```
+#[derive(Default)]
+pub struct Extensions(HashMap<TypeId, Box<dyn Any + Send + Sync>>);
+
+impl Extensions {
+    /// Adds a value, returning the previous value of the same type if there was one.
+    pub fn insert<T: Any + Send + Sync>(&mut self, value: T) -> Option<T> {
+        self.0
+            .insert(TypeId::of::<T>(), Box::new(value))
+            .and_then(|i| i.downcast().ok())
+            .map(|i| *i)
+    }
+
+    /// Gets a reference to the value of a type.
+    pub fn get<T: Any + Send + Sync>(&self) -> Option<&T> {
+        self.0.get(&TypeId::of::<T>())?.downcast_ref()
+    }
+
+    /// Gets a mutable reference to the value of a type.
+    pub fn get_mut<T: Any + Send + Sync>(&mut self) -> Option<&mut T> {
+        self.0.get_mut(&TypeId::of::<T>())?.downcast_mut()
+    }
+
+    /// Removes the value of a type, returning it.
+    pub fn remove<T: Any + Send + Sync>(&mut self) -> Option<T> {
+        self.0
+            .remove(&TypeId::of::<T>())
+            .and_then(|i| i.downcast().ok())
+            .map(|i| *i)
+    }
+
+    /// Checks if there is a value of a type.
+    pub fn contains<T: Any + Send + Sync>(&self) -> bool {
+        self.0.contains_key(&TypeId::of::<T>())
+    }
+
+    /// The number of values.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Checks if there are no values.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl Debug for Extensions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Extensions")
+            .field("len", &self.0.len())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Information about the connection a request was received on, from [`Request::connection`].
+/// It is shared by all the requests on a keep-alive connection, so it can also hold values for the whole connection, like a cached login.
+/// ## Example
+/// ```rust
+/// # use afire::{Server, Response, Method};
+/// struct Visits(usize);
+///
+/// # let mut server = Server::<()>::new("localhost", 8080);
+/// server.route(Method::GET, "/", |req| {
+///     let connection = req.connection();
+///     let mut extensions = connection.extensions();
+///     if !extensions.contains::<Visits>() {
+///         extensions.insert(Visits(0));
+///     }
+///
+///     let visits = extensions.get_mut::<Visits>().unwrap();
+///     visits.0 += 1;
+///     Response::new().text(format!(
+///         "Connection #{} has sent {} requests",
+///         connection.id(),
+///         visits.0
+///     ))
+/// });
+/// ```
+pub struct ConnectionInfo {
+    /// The unique id of the connection.
+    id: u64,
+    /// The number of requests received on the connection.
+    requests: AtomicUsize,
+    /// When the connection was accepted.
+    opened: Instant,
+    /// Information about the TLS session, read along with the first request.
+    pub(crate) tls: OnceLock<Option<TlsInfo>>,
+    /// The identity of the process on the other end of the connection, read when it was accepted.
+    peer_credentials: Option<PeerCredentials>,
+    /// Values stored for the whole connection.
+    extensions: Mutex<Extensions>,
+    /// When the connection started waiting for its next request, or None while a request is being handled.
+    /// Used by [`crate::Server::reap_idle_connections`].
+    pub(crate) idle_since: Mutex<Option<Instant>>,
+}
+
+impl ConnectionInfo {
+    pub(crate) fn new(peer_credentials: Option<PeerCredentials>) -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+        Self {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            requests: AtomicUsize::new(0),
+            opened: Instant::now(),
+            tls: OnceLock::new(),
+            peer_credentials,
+            extensions: Mutex::new(Extensions::default()),
+            idle_since: Mutex::new(None),
+        }
+    }
+
+    /// Gets the id of the connection.
+    /// Ids are unique for the life of the process, counting up from 1 in the order connections are accepted.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Gets the number of requests that have been received on the connection, including the current one.
+    pub fn requests(&self) -> usize {
+        self.requests.load(Ordering::Relaxed)
+    }
+
+    /// Counts a new request on the connection, returning the number of requests including it.
+    pub(crate) fn next_request(&self) -> usize {
+        self.requests.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// Gets when the connection was accepted.
+    pub fn opened(&self) -> Instant {
+        self.opened
+    }
+
+    /// Gets information about the TLS session of the connection.
+    /// This is only available if the server was started with a [`crate::listener::Listener`] whose streams implement [`Stream::tls_info`].
+    pub fn tls(&self) -> Option<&TlsInfo> {
+        self.tls.get()?.as_ref()
+    }
+
+    /// Gets the user, group and process ids of the client, for connections on local sockets.
+    /// This is only available if the server was started with a [`crate::listener::Listener`] whose streams implement [`Stream::peer_credentials`], like [`std::os::unix::net::UnixListener`].
+    pub fn peer_credentials(&self) -> Option<PeerCredentials> {
+        self.peer_credentials
+    }
+
+    /// Gets the values stored for the connection.
+    /// Values are kept until the connection is closed, and are shared by all the requests on it.
+    pub fn extensions(&self) -> MutexGuard<'_, Extensions> {
+        self.extensions.force_lock()
+    }
+}
+
+impl Debug for ConnectionInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ConnectionInfo")
+            .field("id", &self.id)
+            .field("requests", &self.requests())
+            .field("opened", &self.opened)
+            .field("tls", &self.tls())
+            .field("peer_credentials", &self.peer_credentials)
+            .finish_non_exhaustive()
+    }
 }
 
 impl Request {
+    /// Gets the request line and headers exactly as they were received, including the empty line that ends them.
+    /// Changes made to the request (like by [`Request::set_path`]) aren't included, use [`Request::to_bytes`] for that.
+    ///
+    /// This is useful for things like request signing or audit logs, which need the exact bytes the client sent.
+    /// ## Example
+    /// ```rust
+    /// # use afire::{Server, Response, Method};
+    /// # let mut server = Server::<()>::new("localhost", 8080);
+    /// server.route(Method::GET, "/echo", |req| {
+    ///     Response::new().bytes(req.raw_head())
+    /// });
+    /// ```
+    pub fn raw_head(&self) -> &[u8] {
+        &self.raw_head
+    }
+
+    /// Gets the query string exactly as it was received, without the `?`.
+    /// Unlike [`Request::query`], it isn't decoded and keeps params without a value.
+    /// ## Example
+    /// ```rust
+    /// # use afire::{Server, Response, Method};
+    /// # let mut server = Server::<()>::new("localhost", 8080);
+    /// // GET /search?q=a+b&debug
+    /// server.route(Method::GET, "/search", |req| {
+    ///     assert_eq!(req.raw_query(), "q=a+b&debug");
+    ///     Response::new()
+    /// });
+    /// ```
+    pub fn raw_query(&self) -> &str {
+        let line = self
+            .raw_head
+            .split(|i| *i == b'\n')
+            .next()
+            .unwrap_or_default();
+        let target = str::from_utf8(line)
+            .ok()
+            .and_then(|i| i.split_whitespace().nth(1))
+            .unwrap_or_default();
+        target.split_once('?').map(|i| i.1).unwrap_or_default()
+    }
+
+    /// Serializes the request back into an HTTP/1.1 message, with its current method, path, query, headers, cookies and body.
+    /// The query is percent encoded again, so it may not be byte for byte the same as the one that was received, see [`Request::raw_head`].
+    ///
+    /// The body is only included if it has been read, so it is missing for routes that stream the body (see [`crate::Server::route_stream_body`]).
+    /// ## Example
+    /// ```rust
+    /// # use afire::{Server, Response, Method};
+    /// # let mut server = Server::<()>::new("localhost", 8080);
+    /// // Forward requests to another server
+    /// server.route(Method::ANY, "/api/**", |req| {
+    ///     let bytes = req.to_bytes();
+    ///     // ... send `bytes` to the upstream server
+    ///     Response::new()
+    /// });
+    /// ```
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.raw_head.len() + self.body.len());
+        let _ = write!(
+            out,
+            "{} {}{} {}\r\n",
+            self.method, self.path, self.query, self.version
+        );
+        for header in self.headers.iter() {
+            let _ = write!(out, "{}\r\n", header);
+        }
+        if !self.cookies.is_empty() {
+            let cookies = self
+                .cookies
+                .iter()
+                .map(|i| i.to_string())
+                .collect::<Vec<_>>();
+            let _ = write!(out, "Cookie: {}\r\n", cookies.join("; "));
+        }
+        out.extend_from_slice(b"\r\n");
+        out.extend_from_slice(&self.body);
+        out
+    }
+
+    /// Gets information about the connection the request was received on, and values stored for the whole connection.
+    /// See [`ConnectionInfo`] for an example.
+    pub fn connection(&self) -> &ConnectionInfo {
+        &self.connection
+    }
+
+    /// Checks if the client wants to keep the connection open after this request.
+    /// HTTP/1.1 connections are kept open unless the client sends `Connection: close`, while HTTP/1.0 connections are closed unless the client sends `Connection: keep-alive`.
+    /// If the client sends both, `close` wins.
     pub(crate) fn keep_alive(&self) -> bool {
-        self.headers
-            .get(HeaderType::Connection)
-            .map(|i| i.to_lowercase() == "keep-alive")
-            .unwrap_or(false)
+        let connection = self
+            .headers
+            .get_all(HeaderType::Connection)
+            .flat_map(|i| i.split(','))
+            .map(str::trim);
+
+        let (mut close, mut keep_alive) = (false, false);
+        for i in connection {
+            close |= i.eq_ignore_ascii_case("close");
+            keep_alive |= i.eq_ignore_ascii_case("keep-alive");
+        }
+
+        !close && (keep_alive || !self.is_http10())
+    }
+
+    /// Checks if the request was made with HTTP/1.0.
+    /// Responses to HTTP/1.0 requests are not sent with chunked transfer encoding, and the connection is closed after the response unless the client asked for keep-alive.
+    /// ## Example
+    /// ```rust
+    /// # use afire::{Response, Method, Server};
+    /// # let mut server = Server::<()>::new("localhost", 8080);
+    /// server.route(Method::GET, "/", |req| {
+    ///     Response::new().text(if req.is_http10() { "Hello, old client" } else { "Hello" })
+    /// });
+    /// ```
+    pub fn is_http10(&self) -> bool {
+        self.version.eq_ignore_ascii_case("HTTP/1.0")
     }
 
     /// Get a path parameter by its name.
@@ -91,6 +457,240 @@ impl Request {
             .map(|i| i.1.to_owned())
     }
 
+    /// Gets the metadata of the route that handles the request, see [`crate::Server::route_meta`].
+    /// In pre middleware this is the route that matches the request as it was received, before other middleware could change its path.
+    /// Returns None if no route matches the request.
+    pub fn route_meta(&self) -> Option<Arc<RouteMeta>> {
+        self.route_meta.borrow().clone()
+    }
+
+    /// Gets the clock of the server that received the request, see [`crate::Server::clock`].
+    /// Middleware and routes that depend on the time should use this instead of the system time, so they can be tested with a [`crate::clock::ManualClock`].
+    /// ## Example
+    /// ```rust
+    /// # use afire::{Clock, Response, Method, Server};
+    /// # let mut server = Server::<()>::new("localhost", 8080);
+    /// server.route(Method::GET, "/time", |req| {
+    ///     Response::new().text(req.clock().epoch().as_secs())
+    /// });
+    /// ```
+    pub fn clock(&self) -> &dyn Clock {
+        &*self.clock
+    }
+
+    /// Gets a token for finding out when the server that received the request is shutting down, see [`ShutdownToken`].
+    /// Handlers that run for a long time (like ones streaming events) should check it and return once the server is shutting down, as the server waits for them before stopping.
+    pub fn shutdown_token(&self) -> ShutdownToken {
+        self.shutdown.clone()
+    }
+
+    /// Gets the host the request was sent to, from the `Host` header.
+    /// For requests with an absolute-form target (like `GET http://example.com/ HTTP/1.1`, which is sent to proxies), the host from the target replaces the `Host` header.
+    /// HTTP/1.1 requests without a host are rejected with a `400 Bad Request`, so this is only None for HTTP/1.0 requests.
+    /// ## Example
+    /// ```rust
+    /// # use afire::{Response, Method, Server};
+    /// # let mut server = Server::<()>::new("localhost", 8080);
+    /// server.route(Method::GET, "/", |req| {
+    ///     Response::new().text(format!("Welcome to {}", req.host().unwrap_or("the site")))
+    /// });
+    /// ```
+    pub fn host(&self) -> Option<&str> {
+        self.headers.get(HeaderType::Host)
+    }
+
+    /// Gets the scheme, host and port clients used to reach the server, like `https://example.com`, for building absolute URLs in redirects and links.
+    ///
+    /// If the server has a [`crate::Server::public_url`], it is always returned.
+    /// Otherwise, when the request came from a trusted proxy (see [`crate::Server::trust_proxy`]) the `Forwarded` or `X-Forwarded-Proto`, `X-Forwarded-Host` and `X-Forwarded-Port` headers are used.
+    /// Anything not set by a proxy comes from the request itself: the scheme is `https` if the connection uses TLS, and the host is from the `Host` header (or the local address of the socket for HTTP/1.0 requests without one).
+    ///
+    /// The proxy is checked with the address of the socket, so this still works after [`crate::extension::RealIpResolver`] has replaced [`Request::address`].
+    /// ## Example
+    /// ```rust
+    /// # use afire::{Response, Method, Server};
+    /// # let mut server = Server::<()>::new("localhost", 8080);
+    /// server.route(Method::POST, "/login", |req| {
+    ///     // Redirect to the same site the client used, even behind a reverse proxy
+    ///     Response::see_other(format!("{}/dashboard", req.base_url()))
+    /// });
+    /// ```
+    pub fn base_url(&self) -> String {
+        let config = &self.url_config;
+        if let Some(url) = &config.public_url {
+            return url.clone();
+        }
+
+        let peer = self
+            .socket
+            .force_lock()
+            .peer_addr()
+            .map_or(self.address.ip(), |i| i.ip());
+        let trusted = config
+            .trusted_proxies
+            .iter()
+            .any(|(range, prefix)| in_range(peer, *range, *prefix));
+
+        // Only what the first proxy saw matters, so the first value of each header is used
+        let first = |name: &str| {
+            let value = self.headers.get(name)?.split(',').next()?.trim();
+            (!value.is_empty()).then(|| value.to_owned())
+        };
+        let (mut scheme, mut host, mut port) = (None, None, None);
+        if trusted {
+            match self.headers.get_typed::<Forwarded>() {
+                Some(i) => {
+                    let element = i.0.into_iter().next().unwrap_or_default();
+                    (scheme, host) = (element.proto, element.host);
+                }
+                None => {
+                    scheme = first("X-Forwarded-Proto");
+                    host = first("X-Forwarded-Host");
+                    port = first("X-Forwarded-Port").filter(|i| i.parse::<u16>().is_ok());
+                }
+            }
+        }
+
+        let scheme = scheme
+            .filter(|i| {
+                i.bytes()
+                    .all(|i| i.is_ascii_alphanumeric() || b"+-.".contains(&i))
+            })
+            .unwrap_or_else(|| {
+                let tls = self.connection.tls().is_some();
+                if tls { "https" } else { "http" }.to_owned()
+            })
+            .to_ascii_lowercase();
+        let host = host.filter(|i| valid_host(i));
+        let mut host = match host.or_else(|| self.host().map(str::to_owned)) {
+            Some(host) => host,
+            None => self
+                .local_address
+                .map_or_else(|| "localhost".to_owned(), |i| i.to_string()),
+        };
+
+        // Add the forwarded port, unless the host has one or it's the default for the scheme
+        let has_port = host.rsplit_once(':').is_some_and(|(_, i)| !i.contains(']'));
+        if let Some(port) = port {
+            let default = matches!(
+                (scheme.as_str(), port.as_str()),
+                ("http", "80") | ("https", "443")
+            );
+            if !has_port && !default {
+                host = format!("{host}:{port}");
+            }
+        }
+
+        format!("{scheme}://{host}")
+    }
+
+    /// Replaces the path of the request.
+    /// If the new path has a query string (like `/search?q=afire`), it replaces the request's query too.
+    /// When done in a pre middleware, the request is routed with the new path.
+    /// ## Example
+    /// ```rust
+    /// # use afire::{Request, Middleware, middleware::MiddleResult};
+    /// // Serve the old API from the new routes
+    /// struct Rewrite;
+    ///
+    /// impl Middleware for Rewrite {
+    ///     fn pre(&self, req: &mut Request) -> MiddleResult {
+    ///         if req.path == "/api/users" {
+    ///             req.set_path("/api/v2/users?legacy=true");
+    ///         }
+    ///         MiddleResult::Continue
+    ///     }
+    /// }
+    /// ```
+    pub fn set_path(&mut self, path: impl AsRef<str>) {
+        let (path, query) = match path.as_ref().split_once('?') {
+            Some((path, query)) => (path, Some(query)),
+            None => (path.as_ref(), None),
+        };
+
+        self.path = match path.starts_with('/') {
+            true => path.to_owned(),
+            false => format!("/{path}"),
+        };
+        if let Some(query) = query {
+            self.query = Query::from_body(query);
+        }
+    }
+
+    /// Removes a prefix from the path of the request, returning false if the path isn't under it.
+    /// The prefix has to match whole segments, so `/api` is stripped from `/api/users` and `/api` but not `/apis`.
+    /// This is useful for mounting an app that expects to be at the root under a sub path.
+    /// ## Example
+    /// ```rust
+    /// # use afire::{Request, Middleware, middleware::MiddleResult};
+    /// struct Mount;
+    ///
+    /// impl Middleware for Mount {
+    ///     fn pre(&self, req: &mut Request) -> MiddleResult {
+    ///         // `/app/users` is handled by the `/users` route
+    ///         req.strip_prefix("/app");
+    ///         MiddleResult::Continue
+    ///     }
+    /// }
+    /// ```
+    pub fn strip_prefix(&mut self, prefix: impl AsRef<str>) -> bool {
+        let prefix = prefix.as_ref().trim_end_matches('/');
+        let rest = match self.path.strip_prefix(prefix) {
+            Some(i) if i.is_empty() || i.starts_with('/') => i,
+            _ => return false,
+        };
+
+        self.path = match rest {
+            "" => "/".to_owned(),
+            _ => rest.to_owned(),
+        };
+        true
+    }
+
+    /// Sets a header on the request, replacing any existing values.
+    /// ## Example
+    /// ```rust
+    /// # use afire::{Request, Middleware, middleware::MiddleResult};
+    /// struct Tenant;
+    ///
+    /// impl Middleware for Tenant {
+    ///     fn pre(&self, req: &mut Request) -> MiddleResult {
+    ///         let tenant = req.host().and_then(|i| i.split('.').next()).unwrap_or("default").to_owned();
+    ///         req.insert_header("X-Tenant", tenant);
+    ///         MiddleResult::Continue
+    ///     }
+    /// }
+    /// ```
+    pub fn insert_header(&mut self, name: impl Into<HeaderType>, value: impl AsRef<str>) {
+        self.headers.insert(name, value);
+    }
+
+    /// Replaces the body of the request with the result of a function, which gets the current body.
+    /// The `Content-Length` header is updated to the new length, if the request has one.
+    /// This only changes [`Request::body`], so it doesn't affect bodies streamed with [`crate::Server::route_stream_body`].
+    /// ## Example
+    /// ```rust
+    /// # use afire::{Request, Middleware, middleware::MiddleResult};
+    /// struct TrimBody;
+    ///
+    /// impl Middleware for TrimBody {
+    ///     fn pre(&self, req: &mut Request) -> MiddleResult {
+    ///         req.map_body(|body| body.trim_ascii().to_vec());
+    ///         MiddleResult::Continue
+    ///     }
+    /// }
+    /// ```
+    pub fn map_body(&mut self, map: impl FnOnce(Vec<u8>) -> Vec<u8>) {
+        let body = mem::take(&mut self.body);
+        let body = map(Arc::try_unwrap(body).unwrap_or_else(|i| (*i).clone()));
+
+        if self.headers.has(HeaderType::ContentLength) {
+            self.headers
+                .insert(HeaderType::ContentLength, body.len().to_string());
+        }
+        self.body = Arc::new(body);
+    }
+
     /// Gets the body of the request as a string.
     /// This uses the [`String::from_utf8_lossy`] method, so it will replace invalid UTF-8 characters with the unicode replacement character (�).
     /// If you want to use a different encoding or handle invalid characters, use a string method on the body field.
@@ -98,67 +698,357 @@ impl Request {
         String::from_utf8_lossy(&self.body)
     }
 
-    /// Read a request from a TcpStream.
-    pub(crate) fn from_socket(raw_stream: Arc<Mutex<TcpStream>>) -> Result<Self> {
-        let stream = raw_stream.force_lock();
+    /// Gets a reader for the body of the request.
+    /// On routes with [`crate::Server::route_stream_body`], this reads the body from the socket as it is received, otherwise it just reads [`Request::body`].
+    /// Each streamed byte can only be read once, so creating another reader continues where the last one stopped.
+    /// ## Example
+    /// ```rust
+    /// # use std::io::{BufRead, BufReader};
+    /// # use afire::{Response, Method, Server};
+    /// # let mut server = Server::<()>::new("localhost", 8080);
+    /// server
+    ///     .route(Method::POST, "/lines", |req| {
+    ///         let lines = BufReader::new(req.body_reader()).lines().count();
+    ///         Response::new().text(format!("{} lines", lines))
+    ///     })
+    ///     .route_stream_body();
+    /// ```
+    pub fn body_reader(&self) -> BodyReader<'_> {
+        BodyReader { req: self, pos: 0 }
+    }
 
-        trace!(Level::Debug, "Reading header");
-        let peer_addr = stream.peer_addr()?;
-        let mut reader = BufReader::new(&*stream);
-        let mut request_line = Vec::with_capacity(BUFF_SIZE);
-        reader
-            .read_until(10, &mut request_line)
-            .map_err(|_| StreamError::UnexpectedEof)?;
-
-        let (method, path, query, version) = parse_request_line(&request_line)?;
-
-        let mut headers = Vec::new();
-        let mut cookies = Vec::new();
-        loop {
-            let mut buff = Vec::with_capacity(BUFF_SIZE);
-            reader
-                .read_until(10, &mut buff)
-                .map_err(|_| StreamError::UnexpectedEof)?;
-            let line = String::from_utf8_lossy(&buff);
-            if line.len() <= 2 {
-                break;
+    /// Checks if the whole body has been read.
+    /// This is only false for streamed bodies the handler didn't finish reading, which are still on the socket.
+    pub(crate) fn body_finished(&self) -> bool {
+        self.body_stream
+            .borrow()
+            .as_ref()
+            .is_none_or(BodyStream::finished)
+    }
+
+    /// Picks the content type the client prefers out of the ones the route can send, using the quality values and wildcards in the `Accept` header.
+    /// If the client likes multiple options equally, the one earliest in `options` is used.
+    /// If the request has no (or an invalid) `Accept` header, the first option is returned, and if the client accepts none of the options, `None` is returned.
+    ///
+    /// ## Example
+    /// ```rust
+    /// # use afire::{Response, Method, Server, Content};
+    /// # let mut server = Server::<()>::new("localhost", 8080);
+    /// server.route(Method::GET, "/", |req| {
+    ///     match req.accepts(&[Content::JSON, Content::HTML]) {
+    ///         Some(Content::HTML) => Response::new().text("<h1>Hello</h1>").content(Content::HTML),
+    ///         Some(_) => Response::new().text(r#"{"message": "Hello"}"#).content(Content::JSON),
+    ///         None => Response::new().status(406),
+    ///     }
+    /// });
+    /// ```
+    pub fn accepts<'a>(&self, options: &[Content<'a>]) -> Option<Content<'a>> {
+        let accept = match self.headers.get_typed::<Accept>() {
+            Some(i) => i,
+            None => return options.first().copied(),
+        };
+
+        let mut best = None;
+        for option in options {
+            let quality = accept.quality(option.as_type());
+            if quality > 0.0 && best.is_none_or(|(_, i)| quality > i) {
+                best = Some((*option, quality));
             }
+        }
+
+        best.map(|(i, _)| i)
+    }
+
+    /// Gets the languages the client prefers from the `Accept-Language` header, highest quality first.
+    /// Languages the client marked as unacceptable (with a quality of 0) are left out, and an empty list is returned if the header is missing or invalid.
+    /// Use [`crate::header::typed::AcceptLanguage`] to get the quality values too.
+    /// ## Example
+    /// ```rust
+    /// # use afire::{Response, Method, Server};
+    /// # let mut server = Server::<()>::new("localhost", 8080);
+    /// server.route(Method::GET, "/", |req| {
+    ///     // With `Accept-Language: fr-CH, fr;q=0.9, en;q=0.8` this is `fr-CH, fr, en`
+    ///     Response::new().text(req.languages().join(", "))
+    /// });
+    /// ```
+    pub fn languages(&self) -> Vec<String> {
+        let Some(accept) = self.headers.get_typed::<AcceptLanguage>() else {
+            return Vec::new();
+        };
+
+        accept
+            .0
+            .into_iter()
+            .filter(|i| i.quality > 0.0)
+            .map(|i| i.value)
+            .collect()
+    }
+
+    /// Picks the language the client prefers out of the ones the site is translated into, using the `Accept-Language` header.
+    /// Ranges match the tags that start with them, so a client asking for `en` is given `en-US`, see [`AcceptLanguage::quality`] for the details.
+    /// If the client likes multiple languages equally, the one earliest in `supported` is used.
+    /// If the request has no (or an invalid) `Accept-Language` header, the first language is returned, and if the client accepts none of them, `None` is returned.
+    /// ## Example
+    /// ```rust
+    /// # use afire::{Response, Method, Server};
+    /// # let mut server = Server::<()>::new("localhost", 8080);
+    /// server.route(Method::GET, "/", |req| {
+    ///     // Fall back to English when the client doesn't accept any of the translations
+    ///     let text = match req.negotiate_language(&["en", "de", "fr"]).unwrap_or("en") {
+    ///         "de" => "Hallo Welt",
+    ///         "fr" => "Bonjour le monde",
+    ///         _ => "Hello World",
+    ///     };
+    ///     Response::new().text(text)
+    /// });
+    /// ```
+    pub fn negotiate_language<'a>(&self, supported: &[&'a str]) -> Option<&'a str> {
+        let accept = match self.headers.get_typed::<AcceptLanguage>() {
+            Some(i) => i,
+            None => return supported.first().copied(),
+        };
 
-            let header = Header::from_string(&line[..line.len() - 2])?;
-            if header.name != HeaderType::Cookie {
-                headers.push(header);
-                continue;
+        let mut best = None;
+        for language in supported {
+            let quality = accept.quality(language);
+            if quality > 0.0 && best.is_none_or(|(_, i)| quality > i) {
+                best = Some((*language, quality));
             }
+        }
+
+        best.map(|(i, _)| i)
+    }
+
+    /// Gets when each phase of handling the request finished so far.
+    /// In `end` middleware all the phases have finished, so this shows where the time handling the request went.
+    /// ## Example
+    /// ```rust
+    /// # use afire::{Server, Response, Method};
+    /// # let mut server = Server::<()>::new("localhost", 8080);
+    /// server.route(Method::GET, "/", |req| {
+    ///     Response::new().text(format!("Parsed in {:?}", req.timing().total()))
+    /// });
+    /// ```
+    pub fn timing(&self) -> Timing {
+        self.timing.get()
+    }
+
+    /// Marks the socket as taken over by the handler, like for a WebSocket or an event stream that keeps writing to it from another thread.
+    /// Once the handler returns, the server won't send the response it returned or try to read another request from the connection, and leaves closing the socket to whatever took it over.
+    /// This is done automatically by [`crate::server_sent_events::ServerSentEventsExt::sse`].
+    /// HTTP/2 connections (with the experimental `http2` feature) can't be taken over, as all of their streams share the socket, so they are just closed.
+    /// ## Example
+    /// ```rust
+    /// # use std::{io::Write, thread};
+    /// # use afire::{Server, Response, Method};
+    /// # let mut server = Server::<()>::new("localhost", 8080);
+    /// server.route(Method::GET, "/raw", |req| {
+    ///     req.consume_socket();
+    ///     let socket = req.socket.clone();
+    ///     thread::spawn(move || {
+    ///         let _ = socket.lock().unwrap().write_all(b"HTTP/1.1 200 OK\r\n\r\n");
+    ///     });
+    ///
+    ///     // Not sent, as the socket was consumed
+    ///     Response::new()
+    /// });
+    /// ```
+    pub fn consume_socket(&self) {
+        self.socket_consumed.set(true);
+    }
+
+    /// Checks if the handler took over the socket with [`Request::consume_socket`].
+    pub fn socket_consumed(&self) -> bool {
+        self.socket_consumed.get()
+    }
+
+    /// Sends an informational (1xx) response to the client before the final response, like [`Request::early_hints`].
+    /// Any number of them can be sent while the handler is still working, and the response the handler returns is sent after them as usual.
+    ///
+    /// Returns false without sending anything if the client can't receive them.
+    /// HTTP/1.0 clients don't understand interim responses, they aren't supported on HTTP/2 connections (with the experimental `http2` feature) yet, and they can't be sent once the socket was taken over with [`Request::consume_socket`].
+    ///
+    /// Panics if the status isn't informational, or is `101 Switching Protocols`, as that has to be sent as the final response.
+    /// ## Example
+    /// ```rust
+    /// # use afire::{Server, Response, Method, Header, Status};
+    /// # let mut server = Server::<()>::new("localhost", 8080);
+    /// server.route(Method::GET, "/", |req| {
+    ///     req.send_interim(Status::EarlyHints, [Header::new("Link", "</app.js>; rel=preload; as=script")])
+    ///         .unwrap();
+    ///     Response::new().text("Done")
+    /// });
+    /// ```
+    pub fn send_interim(
+        &self,
+        status: impl Into<Status>,
+        headers: impl IntoIterator<Item = Header>,
+    ) -> Result<bool> {
+        let status = status.into();
+        assert!(
+            status.is_informational() && status != Status::SwitchingProtocols,
+            "{:?} can't be sent as an interim response",
+            status
+        );
 
-            cookies.extend(Cookie::from_string(&header.value));
+        if self.version != "HTTP/1.1" || self.socket_consumed() {
+            return Ok(false);
         }
 
-        let content_len = headers
-            .iter()
-            .find(|i| i.name == HeaderType::ContentLength)
-            .map(|i| i.value.parse::<usize>().unwrap_or(0))
-            .unwrap_or(0);
-        let mut body = vec![0; content_len];
+        let mut out = format!("HTTP/1.1 {} {}\r\n", status.code(), status.reason_phrase());
+        for header in headers {
+            out.push_str(&format!("{header}\r\n"));
+        }
+        out.push_str("\r\n");
+
+        let mut socket = self.socket.force_lock();
+        socket.write_all(out.as_bytes())?;
+        socket.flush()?;
+        Ok(true)
+    }
+
+    /// Sends a `103 Early Hints` response with a `Link` header for each of the links, so the client can start preloading resources while the handler is still working on the final response.
+    /// See [`Request::send_interim`] for when they can't be sent.
+    /// ## Example
+    /// ```rust
+    /// # use std::{thread, time::Duration};
+    /// # use afire::{Server, Response, Method};
+    /// # let mut server = Server::<()>::new("localhost", 8080);
+    /// server.route(Method::GET, "/", |req| {
+    ///     let _ = req.early_hints(["</style.css>; rel=preload; as=style"]);
+    ///
+    ///     // Slow work, like querying a database
+    ///     thread::sleep(Duration::from_millis(10));
+    ///     Response::new().text("<link rel=stylesheet href=/style.css>")
+    /// });
+    /// ```
+    pub fn early_hints(&self, links: impl IntoIterator<Item = impl AsRef<str>>) -> Result<bool> {
+        let headers = links.into_iter().map(|i| Header::new("Link", i.as_ref()));
+        self.send_interim(Status::EarlyHints, headers)
+    }
+
+    /// Records that a phase of handling the request has finished.
+    pub(crate) fn mark(&self, phase: Phase) {
+        let mut timing = self.timing.get();
+        timing.mark(phase);
+        self.timing.set(timing);
+    }
+
+    /// Read a request from a Stream, using `buffer` as the read buffer.
+    ///
+    /// [`ConnectionState::pending`] holds bytes that were already read from the connection but not yet parsed.
+    /// They are read before the stream, and once the request has been read any bytes received after it (the start of the next pipelined request) are put back.
+    ///
+    /// Once the headers have been read, `body_options` is called with the request to decide how its body is read.
+    /// It can also modify the request before that, like normalizing its path.
+    pub(crate) fn from_socket(
+        raw_stream: Arc<Mutex<Box<dyn Stream>>>,
+        limits: RequestLimits,
+        buffer: PooledBuffer<'_>,
+        connection: &mut ConnectionState,
+        body_options: impl FnOnce(&mut Request) -> BodyOptions,
+    ) -> Result<Self> {
+        let start = Instant::now();
+        let mut stream = raw_stream.force_lock();
+
+        trace!(Level::Debug, "Reading header");
+        let peer_addr = stream.peer_addr()?;
+        let local_addr = stream.local_addr().ok();
+        let stream_reader = DeadlineReader::new(&mut **stream, limits.socket_timeout);
+        let pending = mem::take(&mut connection.pending);
+        let mut reader = PooledReader::new(stream_reader, buffer, &pending);
+        reader.get_mut().deadline(limits.request_line_timeout);
+
+        let mut parser = HeadParser::new(limits.head);
+        while !parser.is_done() {
+            let data = match reader.fill_buf() {
+                Ok([]) => Err(StreamError::UnexpectedEof),
+                Ok(data) => Ok(data),
+                Err(e) => Err(stream_error(e)),
+            };
 
-        if content_len > 0 {
-            reader
-                .read_exact(&mut body)
-                .map_err(|_| StreamError::UnexpectedEof)?;
+            let data = match data {
+                // Clients that close the connection (or let it time out) without sending anything don't get a response
+                Err(StreamError::UnexpectedEof | StreamError::TimedOut) if parser.is_empty() => {
+                    return Err(StreamError::ConnectionClosed.into())
+                }
+                Err(e) => return Err(e.into()),
+                Ok(data) => data,
+            };
+
+            let state = parser.state();
+            let used = parser.feed(data)?;
+            reader.consume(used);
+
+            if state == ParseState::RequestLine && parser.state() != state {
+                connection
+                    .info
+                    .tls
+                    .get_or_init(|| reader.get_mut().stream.tls_info());
+                reader.get_mut().deadline(limits.header_timeout);
+            }
         }
 
-        drop(stream);
-        Ok(Self {
-            method,
-            path,
-            version,
+        let head = parser.finish().expect("The parser is done");
+        let headers = head.headers;
+        let content_len = content_length(&headers)?;
+
+        let mut req = Self {
+            method: head.method,
+            path: head.path,
+            version: head.version,
             path_params: RefCell::new(Vec::new()),
-            query,
+            route_meta: RefCell::new(None),
+            query: head.query,
             headers: Headers(headers),
-            cookies: CookieJar(cookies),
-            body: Arc::new(body),
+            cookies: CookieJar::from_vec(head.cookies),
+            body: Arc::new(Vec::new()),
             address: peer_addr,
-            socket: raw_stream,
-        })
+            local_address: local_addr,
+            socket: raw_stream.clone(),
+            timing: Cell::new(Timing::new(start)),
+            extensions: Extensions::default(),
+            body_stream: RefCell::new(None),
+            connection: connection.info.clone(),
+            raw_head: head.raw,
+            socket_consumed: Cell::new(false),
+            clock: Arc::new(SystemClock),
+            url_config: Arc::default(),
+            web_socket_config: Arc::default(),
+            shutdown: ShutdownToken::default(),
+        };
+
+        let options = body_options(&mut req);
+        if options.max_size.is_some_and(|i| content_len > i) {
+            return Err(ParseError::BodyTooLarge.into());
+        }
+
+        let deadline = options.timeout.map(|i| Instant::now() + i);
+        if options.stream {
+            // Only the part of the body that has already been read is kept, the rest is left on the socket for the handler
+            let buffered = reader.buffered();
+            let buffered = buffered[..content_len.min(buffered.len())].to_vec();
+            reader.consume(buffered.len());
+            *req.body_stream.get_mut() = Some(BodyStream {
+                remaining: content_len - buffered.len(),
+                buffered,
+                pos: 0,
+                deadline,
+                socket_timeout: limits.socket_timeout,
+            });
+        } else if content_len > 0 {
+            let mut body = vec![0; content_len];
+            reader.get_mut().deadline = deadline;
+            reader.read_exact(&mut body).map_err(stream_error)?;
+            req.body = Arc::new(body);
+        }
+
+        connection.pending.extend_from_slice(reader.buffered());
+        reader.into_inner().finish()?;
+        drop(stream);
+
+        req.mark(Phase::Parse);
+        Ok(req)
     }
 }
 
@@ -174,55 +1064,684 @@ impl Debug for Request {
             .field("cookies", &*self.cookies)
             .field("body", &self.body)
             .field("address", &self.address)
+            .field("local_address", &self.local_address)
+            .field("timing", &self.timing.get())
+            .field("extensions", &self.extensions)
+            .field("connection", &self.connection)
             .finish()
     }
 }
 
-/// Parse a request line into a method, path, query, and version
-pub(crate) fn parse_request_line(bytes: &[u8]) -> Result<(Method, String, Query, String)> {
-    let request_line = String::from_utf8_lossy(bytes);
-    let mut parts = request_line.split_whitespace();
+/// Limits on the size of a request, to stop clients from using up all the server's memory.
+/// See [`crate::Server::max_request_line`], [`crate::Server::max_header_size`] and [`crate::Server::max_headers`].
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct RequestLimits {
+    /// Limits on the size of the request line and headers.
+    pub head: HeadLimits,
+    /// How long the client has to send the request line.
+    pub request_line_timeout: Option<Duration>,
+    /// How long the client has to send the headers.
+    pub header_timeout: Option<Duration>,
+    /// The timeout for each read, restored once the request has been read.
+    pub socket_timeout: Option<Duration>,
+}
+
+/// How the body of a request is read, from the server's limits and the options of the route that will handle it.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct BodyOptions {
+    /// The maximum size of the body in bytes.
+    pub max_size: Option<usize>,
+    /// How long the client has to send the body.
+    pub timeout: Option<Duration>,
+    /// If the body is left on the socket to be read by the handler.
+    pub stream: bool,
+}
+
+/// A body that is read from the socket by the route handler, see [`crate::Server::route_stream_body`].
+pub(crate) struct BodyStream {
+    /// The start of the body, which was read along with the headers.
+    buffered: Vec<u8>,
+    /// The position of the next byte to read in `buffered`.
+    pos: usize,
+    /// The number of bytes of the body that are still on the socket.
+    remaining: usize,
+    /// When the client has to have sent the whole body by.
+    deadline: Option<Instant>,
+    /// The socket timeout, used for reads when it is shorter than the time left.
+    socket_timeout: Option<Duration>,
+}
+
+impl BodyStream {
+    /// Checks if the whole body has been read.
+    fn finished(&self) -> bool {
+        self.pos >= self.buffered.len() && self.remaining == 0
+    }
+
+    fn read(&mut self, socket: &Mutex<Box<dyn Stream>>, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos < self.buffered.len() {
+            let read = (&self.buffered[self.pos..]).read(buf)?;
+            self.pos += read;
+            return Ok(read);
+        }
+
+        let len = buf.len().min(self.remaining);
+        if len == 0 {
+            return Ok(0);
+        }
+
+        let mut stream = socket.force_lock();
+        let mut reader = DeadlineReader::new(&mut **stream, self.socket_timeout);
+        reader.deadline = self.deadline;
+        let read = reader.read(&mut buf[..len]);
+        reader.finish()?;
+
+        match read? {
+            0 => Err(io::ErrorKind::UnexpectedEof.into()),
+            read => {
+                self.remaining -= read;
+                Ok(read)
+            }
+        }
+    }
+}
+
+/// Reads the body of a request, from [`Request::body_reader`].
+pub struct BodyReader<'a> {
+    req: &'a Request,
+    /// The position in [`Request::body`], if the body isn't streamed.
+    pos: usize,
+}
+
+impl Read for BodyReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(stream) = self.req.body_stream.borrow_mut().as_mut() {
+            return stream.read(&self.req.socket, buf);
+        }
+
+        let read = self
+            .req
+            .body
+            .get(self.pos..)
+            .unwrap_or_default()
+            .read(buf)?;
+        self.pos += read;
+        Ok(read)
+    }
+}
+
+/// A reader that fails with a timeout once a deadline has passed.
+/// Used to limit the total time a client can take to send each part of a request, not just the time of each read.
+struct DeadlineReader<'a> {
+    /// The stream being read from.
+    stream: &'a mut dyn Stream,
+    /// When the current phase has to be done by.
+    deadline: Option<Instant>,
+    /// The socket timeout, used for reads when it is shorter than the time left.
+    socket_timeout: Option<Duration>,
+    /// If the read timeout of the stream has been changed and needs to be restored.
+    changed: bool,
+}
+
+impl<'a> DeadlineReader<'a> {
+    fn new(stream: &'a mut dyn Stream, socket_timeout: Option<Duration>) -> Self {
+        Self {
+            stream,
+            deadline: None,
+            socket_timeout,
+            changed: false,
+        }
+    }
+
+    /// Starts a new phase that has to be done within `timeout`.
+    fn deadline(&mut self, timeout: Option<Duration>) {
+        self.deadline = timeout.map(|i| Instant::now() + i);
+    }
+
+    /// Restores the stream's read timeout to the socket timeout.
+    fn finish(self) -> io::Result<()> {
+        if self.changed {
+            self.stream.set_read_timeout(self.socket_timeout)?;
+        }
+        Ok(())
+    }
+}
+
+impl Read for DeadlineReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(deadline) = self.deadline {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+
+            let timeout = self.socket_timeout.map_or(remaining, |i| i.min(remaining));
+            self.stream.set_read_timeout(Some(timeout))?;
+            self.changed = true;
+        }
+
+        self.stream.read(buf)
+    }
+}
+
+/// A buffered reader like [`std::io::BufReader`], but using a buffer from the server's buffer pool.
+struct PooledReader<'a, R> {
+    inner: R,
+    buffer: PooledBuffer<'a>,
+    /// The position of the next byte to read in the buffer.
+    pos: usize,
+    /// The number of bytes in the buffer that have been filled.
+    filled: usize,
+}
+
+impl<'a, R: Read> PooledReader<'a, R> {
+    /// Creates a new reader, with `pending` already in the buffer so it is read before `inner`.
+    fn new(inner: R, mut buffer: PooledBuffer<'a>, pending: &[u8]) -> Self {
+        if pending.len() > buffer.len() {
+            buffer.as_vec().resize(pending.len(), 0);
+        }
+        buffer[..pending.len()].copy_from_slice(pending);
+
+        Self {
+            inner,
+            buffer,
+            pos: 0,
+            filled: pending.len(),
+        }
+    }
+
+    fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Gets the bytes that have been read from `inner` but not consumed yet.
+    fn buffered(&self) -> &[u8] {
+        &self.buffer[self.pos..self.filled]
+    }
+}
+
+impl<R: Read> Read for PooledReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // Skip the buffer for large reads (like the body) when it is empty
+        if self.pos >= self.filled && buf.len() >= self.buffer.len() {
+            return self.inner.read(buf);
+        }
+
+        let read = self.fill_buf()?.read(buf)?;
+        self.consume(read);
+        Ok(read)
+    }
+}
+
+impl<R: Read> BufRead for PooledReader<'_, R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos >= self.filled {
+            self.filled = self.inner.read(&mut self.buffer)?;
+            self.pos = 0;
+        }
+
+        Ok(&self.buffer[self.pos..self.filled])
+    }
 
-    let raw_method = match parts.next() {
-        Some(i) => i,
-        None => return Err(Error::Parse(ParseError::NoMethod)),
+    fn consume(&mut self, amt: usize) {
+        self.pos = (self.pos + amt).min(self.filled);
+    }
+}
+
+/// Converts an error from reading a request into a [`StreamError`].
+fn stream_error(err: io::Error) -> StreamError {
+    match err.kind() {
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => StreamError::TimedOut,
+        kind if is_disconnect(kind) => StreamError::ConnectionClosed,
+        _ => StreamError::UnexpectedEof,
+    }
+}
+
+/// Gets the length of a request's body from its `Content-Length` headers.
+/// Requests that could be framed differently by a proxy are rejected (RFC 9112, section 6.3), as what the server reads as the body could be read as another request by the proxy or the other way around.
+/// Transfer codings aren't supported, so requests with a `Transfer-Encoding` header are always rejected, as their body length can't be known.
+fn content_length(headers: &[Header]) -> Result<usize> {
+    let chunked = headers
+        .iter()
+        .any(|i| i.name == HeaderType::TransferEncoding);
+    let mut lengths = headers
+        .iter()
+        .filter(|i| i.name == HeaderType::ContentLength)
+        .flat_map(|i| i.value.split(','))
+        .map(str::trim);
+
+    let Some(first) = lengths.next() else {
+        return match chunked {
+            true => Err(ParseError::UnsupportedTransferEncoding.into()),
+            false => Ok(0),
+        };
     };
-    let method =
-        Method::from_str(raw_method).map_err(|_| Error::Parse(ParseError::InvalidMethod))?;
-    let mut raw_path = match parts.next() {
-        Some(i) => i.chars(),
-        None => return Err(Error::Parse(ParseError::NoVersion)),
+    if chunked || lengths.any(|i| i != first) {
+        return Err(ParseError::AmbiguousBody.into());
+    }
+
+    // `usize::from_str` also allows a leading `+`
+    match first.bytes().all(|i| i.is_ascii_digit()) {
+        true => first.parse().map_err(|_| ParseError::InvalidHeader.into()),
+        false => Err(ParseError::InvalidHeader.into()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::{Cursor, Read, Write},
+        sync::{Arc, Mutex},
     };
 
-    let mut final_path = String::new();
-    let mut final_query = String::new();
-    let mut last_is_slash = false;
-    while let Some(i) = raw_path.next() {
-        match i {
-            '/' | '\\' => {
-                if last_is_slash {
-                    continue;
-                }
+    use super::{content_length, Extensions, Request};
+    use crate::{
+        error::{Error, ParseError},
+        internal::testing::{CleartextStream, MemoryListener, MemoryStream},
+        middleware::MiddleResult,
+        Header, Method, Middleware, Response, Server,
+    };
 
-                last_is_slash = true;
-                final_path.push('/');
-            }
-            '?' => {
-                final_query.extend(raw_path);
-                break;
+    #[test]
+    fn test_content_length() {
+        let length = |headers: &[(&str, &str)]| {
+            let headers = headers
+                .iter()
+                .map(|(name, value)| Header::new(*name, *value))
+                .collect::<Vec<_>>();
+            content_length(&headers)
+        };
+
+        assert_eq!(length(&[]), Ok(0));
+        assert_eq!(length(&[("Content-Length", "10")]), Ok(10));
+        assert_eq!(
+            length(&[("Content-Length", "10"), ("Content-Length", "10, 10")]),
+            Ok(10)
+        );
+
+        for headers in [
+            &[("Content-Length", "10"), ("Content-Length", "11")][..],
+            &[("Content-Length", "10, 11")],
+            &[("Content-Length", "10"), ("Transfer-Encoding", "chunked")],
+        ] {
+            assert_eq!(
+                length(headers),
+                Err(Error::Parse(ParseError::AmbiguousBody))
+            );
+        }
+
+        for value in ["chunked", "gzip, chunked"] {
+            assert_eq!(
+                length(&[("Transfer-Encoding", value)]),
+                Err(Error::Parse(ParseError::UnsupportedTransferEncoding))
+            );
+        }
+
+        for value in ["+10", "-1", "", "0x10"] {
+            assert_eq!(
+                length(&[("Content-Length", value)]),
+                Err(Error::Parse(ParseError::InvalidHeader))
+            );
+        }
+    }
+
+    #[test]
+    fn test_extensions() {
+        #[derive(Debug, PartialEq)]
+        struct User(&'static str);
+
+        let mut extensions = Extensions::default();
+        assert_eq!(extensions.insert(User("ferris")), None);
+        assert_eq!(extensions.insert(5_u32), None);
+        assert_eq!(extensions.get::<User>(), Some(&User("ferris")));
+
+        assert_eq!(extensions.insert(User("corro")), Some(User("ferris")));
+        *extensions.get_mut::<u32>().unwrap() += 1;
+        assert_eq!(extensions.remove::<u32>(), Some(6));
+        assert!(!extensions.contains::<u32>());
+        assert_eq!(extensions.len(), 1);
+    }
+
+    #[test]
+    fn test_connection_info() {
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let listener = MemoryListener(Mutex::new(vec![MemoryStream {
+            input: Cursor::new(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n".repeat(2)),
+            output: output.clone(),
+        }]));
+
+        struct Visits(usize);
+
+        let mut server = Server::<()>::new("localhost", 0);
+        server.route(Method::GET, "/", |req| {
+            let connection = req.connection();
+            let mut extensions = connection.extensions();
+            if !extensions.contains::<Visits>() {
+                extensions.insert(Visits(0));
             }
-            _ => {
-                last_is_slash = false;
-                final_path.push(i);
+
+            let visits = extensions.get_mut::<Visits>().unwrap();
+            visits.0 += 1;
+            Response::new().text(format!(
+                "{}/{}/{};",
+                connection.requests(),
+                visits.0,
+                connection.tls().and_then(|i| i.version.as_deref()).unwrap()
+            ))
+        });
+        assert!(server.start_with_listener(listener).is_err());
+
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        let bodies = output
+            .split("HTTP/1.1 ")
+            .skip(1)
+            .map(|i| i.split_once("\r\n\r\n").unwrap().1)
+            .collect::<String>();
+        assert_eq!(bodies, "1/1/TLSv1.3;2/2/TLSv1.3;");
+    }
+
+    #[test]
+    fn test_early_hints() {
+        let outputs = [(); 2].map(|_| Arc::new(Mutex::new(Vec::new())));
+        let listener = MemoryListener(Mutex::new(vec![
+            MemoryStream {
+                input: Cursor::new(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n".to_vec()),
+                output: outputs[0].clone(),
+            },
+            MemoryStream {
+                input: Cursor::new(b"GET / HTTP/1.0\r\n\r\n".to_vec()),
+                output: outputs[1].clone(),
+            },
+        ]));
+
+        let mut server = Server::<()>::new("localhost", 0);
+        server.route(Method::GET, "/", |req| {
+            let sent = req.early_hints(["</a.css>; rel=preload", "</b.js>; rel=preload"]);
+            Response::new().text(sent.unwrap())
+        });
+        assert!(server.start_with_listener(listener).is_err());
+
+        let output = String::from_utf8(outputs[0].lock().unwrap().clone()).unwrap();
+        assert!(output.starts_with(
+            "HTTP/1.1 103 Early Hints\r\nLink: </a.css>; rel=preload\r\nLink: </b.js>; rel=preload\r\n\r\nHTTP/1.1 200 OK\r\n"
+        ));
+        assert!(output.ends_with("\r\n\r\ntrue"));
+
+        let output = String::from_utf8(outputs[1].lock().unwrap().clone()).unwrap();
+        assert!(output.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(output.ends_with("\r\n\r\nfalse"));
+    }
+
+    #[test]
+    fn test_request_rewrite() {
+        struct Rewrite;
+        impl Middleware for Rewrite {
+            fn pre(&self, req: &mut Request) -> MiddleResult {
+                if !req.strip_prefix("/app") {
+                    req.set_path("/fallback?from=rewrite");
+                }
+                req.insert_header("X-Rewritten", "true");
+                req.map_body(|body| body.to_ascii_uppercase());
+                MiddleResult::Continue
             }
         }
+
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let listener = MemoryListener(Mutex::new(vec![MemoryStream {
+            input: Cursor::new(
+                b"POST /app/echo HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nhelloGET /other HTTP/1.1\r\nHost: localhost\r\nX-Rewritten: false\r\n\r\n"
+                    .to_vec(),
+            ),
+            output: output.clone(),
+        }]));
+
+        let mut server = Server::<()>::new("localhost", 0);
+        server.route(Method::POST, "/echo", |req| {
+            Response::new().text(format!(
+                "{} {}",
+                req.body_str(),
+                req.headers.get("Content-Length").unwrap()
+            ))
+        });
+        server.route(Method::GET, "/fallback", |req| {
+            Response::new().text(format!(
+                "{} {}",
+                req.query.get("from").unwrap(),
+                req.headers.get("X-Rewritten").unwrap()
+            ))
+        });
+        Rewrite.attach(&mut server);
+        assert!(server.start_with_listener(listener).is_err());
+
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        assert!(output.contains("\r\n\r\nHELLO 5"));
+        assert!(output.ends_with("\r\n\r\nrewrite true"));
     }
 
-    let query = Query::from_body(&final_query);
-    let version = match parts.next() {
-        Some(i) => i.to_owned(),
-        None => return Err(Error::Parse(ParseError::NoVersion)),
-    };
+    #[test]
+    fn test_request_bytes() {
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let listener = MemoryListener(Mutex::new(vec![MemoryStream {
+            input: Cursor::new(
+                b"POST /a//b?x=1 HTTP/1.1\r\nHost: localhost\r\nCookie: id=5\r\nContent-Length: 2\r\n\r\nhi"
+                    .to_vec(),
+            ),
+            output: output.clone(),
+        }]));
+
+        let mut server = Server::<()>::new("localhost", 0);
+        server.route(Method::POST, "/a/b", |req| {
+            assert_eq!(
+                req.raw_head(),
+                b"POST /a//b?x=1 HTTP/1.1\r\nHost: localhost\r\nCookie: id=5\r\nContent-Length: 2\r\n\r\n"
+            );
+            assert_eq!(req.raw_query(), "x=1");
+            Response::new().bytes(&req.to_bytes())
+        });
+        assert!(server.start_with_listener(listener).is_err());
+
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        assert!(output.ends_with(
+            "\r\n\r\nPOST /a/b?x=1 HTTP/1.1\r\nHost: localhost\r\nContent-Length: 2\r\nCookie: id=5\r\n\r\nhi"
+        ));
+    }
 
-    Ok((method, final_path, query, version))
+    #[test]
+    fn test_route_body_options() {
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let listener = MemoryListener(Mutex::new(vec![MemoryStream {
+            input: Cursor::new(
+                [
+                    "POST /upload HTTP/1.1\r\nHost: localhost\r\nContent-Length: 10\r\n\r\n0123456789",
+                    "POST /stream HTTP/1.1\r\nHost: localhost\r\nContent-Length: 6\r\n\r\nstream",
+                    "POST /api HTTP/1.1\r\nHost: localhost\r\nContent-Length: 10\r\n\r\n0123456789",
+                ]
+                .concat()
+                .into_bytes(),
+            ),
+            output: output.clone(),
+        }]));
+
+        let mut server = Server::<()>::new("localhost", 0).max_body_size(4);
+        server.route(Method::POST, "/api", |req| {
+            Response::new().text(req.body_str())
+        });
+        server
+            .route(Method::POST, "/upload", |req| {
+                Response::new().text(req.body_str())
+            })
+            .route_max_body_size(16);
+        server
+            .route(Method::POST, "/stream", |req| {
+                let mut body = String::new();
+                req.body_reader().read_to_string(&mut body).unwrap();
+                Response::new().text(format!("{}{}", req.body.len(), body))
+            })
+            .route_max_body_size(16)
+            .route_stream_body();
+        assert!(server.start_with_listener(listener).is_err());
+
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        let responses = output
+            .split("HTTP/1.1 ")
+            .skip(1)
+            .map(|i| (&i[..3], i.split_once("\r\n\r\n").unwrap().1))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            responses,
+            [
+                ("200", "0123456789"),
+                ("200", "0stream"),
+                ("413", "Request body too large")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ambiguous_requests() {
+        let requests = [
+            "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 4\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\nGET /smuggled HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 0\r\nContent-Length: 44\r\n\r\nGET /smuggled HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            "GET / HTTP/1.1\r\nHost: localhost\r\nX-Folded: a\r\n b\r\n\r\n",
+        ];
+        let outputs = requests.map(|_| Arc::new(Mutex::new(Vec::new())));
+        let streams = requests
+            .iter()
+            .zip(&outputs)
+            .map(|(input, output)| MemoryStream {
+                input: Cursor::new(input.as_bytes().to_vec()),
+                output: output.clone(),
+            })
+            .collect();
+
+        let mut server = Server::<()>::new("localhost", 0);
+        server.route(Method::ANY, "**", |req| Response::new().text(&req.path));
+        assert!(server
+            .start_with_listener(MemoryListener(Mutex::new(streams)))
+            .is_err());
+
+        for output in outputs {
+            let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+            assert!(output.starts_with("HTTP/1.1 400 "), "{:?}", output);
+            assert!(output.contains("Connection: close\r\n"));
+            assert!(!output.contains("smuggled"));
+        }
+    }
+
+    #[test]
+    fn test_transfer_encoding_rejected() {
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let listener = MemoryListener(Mutex::new(vec![MemoryStream {
+            input: Cursor::new(
+                b"POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n\
+                  26\r\nGET /smuggled HTTP/1.1\r\nHost: localhost\r\n\r\n\r\n0\r\n\r\n"
+                    .to_vec(),
+            ),
+            output: output.clone(),
+        }]));
+
+        let mut server = Server::<()>::new("localhost", 0);
+        server.route(Method::ANY, "**", |req| Response::new().text(&req.path));
+        assert!(server.start_with_listener(listener).is_err());
+
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        assert!(output.starts_with("HTTP/1.1 501 "), "{:?}", output);
+        assert!(output.contains("Connection: close\r\n"));
+        assert!(!output.contains("smuggled"));
+        assert_eq!(output.matches("HTTP/1.1").count(), 1);
+    }
+
+    #[test]
+    fn test_consume_socket() {
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let listener = MemoryListener(Mutex::new(vec![MemoryStream {
+            input: Cursor::new(
+                b"GET /take HTTP/1.1\r\nHost: localhost\r\n\r\nGET /next HTTP/1.1\r\nHost: localhost\r\n\r\n".to_vec(),
+            ),
+            output: output.clone(),
+        }]));
+
+        let mut server = Server::<()>::new("localhost", 0);
+        server.route(Method::GET, "/take", |req| {
+            req.consume_socket();
+            req.socket.lock().unwrap().write_all(b"taken").unwrap();
+            Response::new().text("not sent")
+        });
+        server.route(Method::GET, "/next", |_| Response::new().text("next"));
+        assert!(server.start_with_listener(listener).is_err());
+
+        assert_eq!(*output.lock().unwrap(), b"taken");
+    }
+
+    #[test]
+    fn test_base_url() {
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let input = [
+            "Host: localhost:8080\r\n",
+            "Host: localhost\r\nX-Forwarded-Proto: HTTPS, http\r\nX-Forwarded-Host: example.com\r\nX-Forwarded-Port: 8443\r\n",
+            "Host: localhost\r\nX-Forwarded-Proto: https\r\nX-Forwarded-Port: 443\r\n",
+            "Host: localhost\r\nForwarded: proto=https;host=a.com, proto=http\r\nX-Forwarded-Host: b.com\r\n",
+            "Host: localhost\r\nX-Forwarded-Host: evil.com/path\r\n",
+        ]
+        .map(|headers| format!("GET / HTTP/1.1\r\n{headers}\r\n"))
+        .concat();
+        let listener = MemoryListener(Mutex::new(vec![CleartextStream(MemoryStream {
+            input: Cursor::new(input.into_bytes()),
+            output: output.clone(),
+        })]));
+
+        let mut server = Server::<()>::new("localhost", 0).trust_proxy([127, 0, 0, 1], 32);
+        server.route(Method::GET, "/", |req| Response::new().text(req.base_url()));
+        assert!(server.start_with_listener(listener).is_err());
+
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        let bodies = output
+            .split("HTTP/1.1 ")
+            .skip(1)
+            .map(|i| i.split("\r\n\r\n").nth(1).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            bodies,
+            [
+                "http://localhost:8080",
+                "https://example.com:8443",
+                "https://localhost",
+                "https://a.com",
+                "http://localhost"
+            ]
+        );
+
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let listener = MemoryListener(Mutex::new(vec![MemoryStream {
+            input: Cursor::new(
+                b"GET / HTTP/1.1\r\nHost: localhost\r\nX-Forwarded-Host: b.com\r\n\r\n".to_vec(),
+            ),
+            output: output.clone(),
+        }]));
+        let mut server = Server::<()>::new("localhost", 0).public_url("https://example.com/");
+        server.route(Method::GET, "/", |req| Response::new().text(req.base_url()));
+        assert!(server.start_with_listener(listener).is_err());
+
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        assert!(output.ends_with("\r\n\r\nhttps://example.com"));
+
+        // Proxies on localhost aren't trusted unless configured
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let listener = MemoryListener(Mutex::new(vec![CleartextStream(MemoryStream {
+            input: Cursor::new(
+                b"GET / HTTP/1.1\r\nHost: localhost\r\nX-Forwarded-Proto: https\r\nX-Forwarded-Host: evil.com\r\n\r\n"
+                    .to_vec(),
+            ),
+            output: output.clone(),
+        })]));
+        let mut server = Server::<()>::new("localhost", 0).trust_proxy([10, 0, 0, 0], 8);
+        server.route(Method::GET, "/", |req| Response::new().text(req.base_url()));
+        assert!(server.start_with_listener(listener).is_err());
+
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        assert!(output.ends_with("\r\n\r\nhttp://localhost"), "{:?}", output);
+    }
 }
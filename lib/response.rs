@@ -1,15 +1,26 @@
 use std::cell::RefCell;
 use std::fmt::{self, Debug, Display, Formatter};
-use std::io::{ErrorKind, Read, Write};
-use std::net::TcpStream;
-use std::sync::{Arc, Mutex};
+use std::fs::File;
+use std::io::{self, ErrorKind, IoSlice, Read, Write};
+use std::mem;
+use std::path::Path;
+use std::sync::{
+    mpsc::{self, Sender},
+    Arc, Mutex,
+};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use crate::consts;
-use crate::header::{HeaderType, Headers};
+use crate::header::{typed::CacheControl, HeaderPolicy, HeaderType, Headers};
 use crate::http::status::Status;
 use crate::{
-    error::Result, header::headers_to_string, internal::handle::Writeable, Content, Header,
-    SetCookie,
+    cookie::CookiePolicy,
+    error::{PanicInfo, Result, StreamError},
+    internal::{buffer_pool::BufferPool, encoding::url, handle::Writeable},
+    listener::Stream,
+    render::Render,
+    Content, Error, Header, Server, SetCookie,
 };
 
 /// Http Response
@@ -34,6 +45,42 @@ pub struct Response {
     /// - Close: Set the Connection header to close and will close the connection after the response is sent.
     /// - End: End the connection without sending a response
     pub flag: ResponseFlag,
+
+    /// Stats about writing the response to the client.
+    /// Only set after the response has been written.
+    pub(crate) write_stats: Option<WriteStats>,
+
+    /// If the response is being sent to an HTTP/1.0 client, which doesn't support chunked transfer encoding.
+    pub(crate) http10: bool,
+
+    /// If the response has already been written, by [`crate::Context::send`].
+    pub(crate) sent: bool,
+
+    /// The panic this response was made for, see [`Response::panic`].
+    pub(crate) panic: Option<Box<PanicInfo>>,
+
+    /// The extension of the file this response is for, used to set its `Content-Type` from the server's MIME types before post middleware run.
+    pub(crate) mime_extension: Option<Box<str>>,
+
+    /// Cookies added with [`Response::cookie`], which are turned into `Set-Cookie` headers with the server's [`CookiePolicy`] before post middleware run.
+    pub(crate) cookies: Vec<SetCookie>,
+
+    /// If streamed bodies are flushed to the client after every chunk, see [`Response::flush_chunks`].
+    pub(crate) flush: bool,
+
+    /// How repeated headers are sent, overriding the server's [`Server::header_policy`].
+    pub(crate) header_policy: Option<HeaderPolicy>,
+}
+
+/// Stats about writing a [`Response`] to the client.
+/// Available to `end` middleware through [`Response::write_stats`], for things like bandwidth accounting or detecting slow clients.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WriteStats {
+    /// The number of bytes written to the socket, including the status line and headers.
+    /// If writing failed partway through, this is how much was written before the error.
+    pub bytes: u64,
+    /// How long it took to write the response.
+    pub duration: Duration,
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -47,11 +94,14 @@ pub enum ResponseFlag {
 }
 
 /// Response Data.
-/// Can be either a Static Vec<u8> or a Stream (impl [`Read`]).
+/// Can be either a Static `Arc<[u8]>`, a Stream (impl [`Read`]) or a File.
 /// Static responses are sent in one go, while streams are sent in chunks (chunked transfer encoding).
+/// Static bodies are reference counted, so the same body can be sent many times without copying it (see [`Response::shared`]).
+/// Files are sent as is, with `sendfile` if the `sendfile` feature is enabled.
 pub enum ResponseBody {
-    Static(Vec<u8>),
+    Static(Arc<[u8]>),
     Stream(Writeable),
+    File(File),
 }
 
 impl Response {
@@ -73,6 +123,14 @@ impl Response {
             headers: Default::default(),
             reason: None,
             flag: ResponseFlag::None,
+            write_stats: None,
+            http10: false,
+            sent: false,
+            panic: None,
+            mime_extension: None,
+            cookies: Vec::new(),
+            flush: false,
+            header_policy: None,
         }
     }
 
@@ -84,6 +142,78 @@ impl Response {
         }
     }
 
+    /// Creates a `302 Found` response that redirects the client to `url`.
+    /// The body is empty and the `Location` header is set.
+    /// ## Example
+    /// ```rust
+    /// # use afire::{Response, Method, Server};
+    /// # let mut server = Server::<()>::new("localhost", 8080);
+    /// server.route(Method::GET, "/old", |_| Response::redirect("/new"));
+    /// ```
+    pub fn redirect(url: impl AsRef<str>) -> Self {
+        Self::redirect_status(Status::Found, url)
+    }
+
+    /// Creates a `308 Permanent Redirect` response that redirects the client to `url`.
+    /// Unlike `301 Moved Permanently`, clients must keep the same method and body when following the redirect.
+    /// ## Example
+    /// ```rust
+    /// # use afire::Response;
+    /// let response = Response::redirect_permanent("https://example.com");
+    /// ```
+    pub fn redirect_permanent(url: impl AsRef<str>) -> Self {
+        Self::redirect_status(Status::PermanentRedirect, url)
+    }
+
+    /// Creates a `303 See Other` response that redirects the client to `url`.
+    /// The client will always follow it with a GET request, so this is commonly used after handling a form submission.
+    /// ## Example
+    /// ```rust
+    /// # use afire::{Response, Method, Server};
+    /// # let mut server = Server::<()>::new("localhost", 8080);
+    /// server.route(Method::POST, "/login", |_| Response::see_other("/dashboard"));
+    /// ```
+    pub fn see_other(url: impl AsRef<str>) -> Self {
+        Self::redirect_status(Status::SeeOther, url)
+    }
+
+    /// Creates a `404 Not Found` response with a plain text body.
+    /// ## Example
+    /// ```rust
+    /// # use afire::Response;
+    /// let response = Response::not_found();
+    /// ```
+    pub fn not_found() -> Self {
+        Self::new()
+            .status(Status::NotFound)
+            .text("Not Found")
+            .content(Content::TXT)
+    }
+
+    /// Creates a `204 No Content` response with an empty body.
+    /// ## Example
+    /// ```rust
+    /// # use afire::Response;
+    /// let response = Response::no_content();
+    /// ```
+    pub fn no_content() -> Self {
+        Self {
+            status: Status::NoContent,
+            data: ResponseBody::empty(),
+            ..Default::default()
+        }
+    }
+
+    /// Creates a redirect response with the given status code.
+    fn redirect_status(status: Status, url: impl AsRef<str>) -> Self {
+        Self {
+            status,
+            data: ResponseBody::empty(),
+            ..Default::default()
+        }
+        .header(HeaderType::Location, url)
+    }
+
     /// Add a status code to a Response.
     /// This accepts [`Status`] as well as a [`u16`].
     /// ## Example
@@ -128,11 +258,39 @@ impl Response {
     /// ```
     pub fn text(self, text: impl Display) -> Self {
         Self {
-            data: text.to_string().as_bytes().to_vec().into(),
+            data: text.to_string().into_bytes().into(),
             ..self
         }
     }
 
+    /// Renders a template as the body of a Response, and sets the content type (HTML by default).
+    /// If rendering fails, the error is logged and a `500 Internal Server Error` is sent instead.
+    /// See the [`crate::render`] module for more info.
+    /// ## Example
+    /// ```rust
+    /// # use afire::{Response, render::{Render, RenderError}};
+    /// struct Page;
+    ///
+    /// impl Render for Page {
+    ///     fn render(&self) -> Result<String, RenderError> {
+    ///         Ok("<h1>Hello from afire!</h1>".to_owned())
+    ///     }
+    /// }
+    ///
+    /// let response = Response::new().render(Page);
+    /// ```
+    pub fn render(self, template: impl Render) -> Self {
+        match template.render() {
+            Ok(body) => self.text(body).content(template.content_type()),
+            Err(_e) => {
+                trace!(Level::Error, "Error rendering template: {}", _e);
+                self.status(Status::InternalServerError)
+                    .text("Internal Server Error")
+                    .content(Content::TXT)
+            }
+        }
+    }
+
     /// Add raw bytes as data to a Response.
     /// This response type is considered static and will be sent in one go, not chunked.
     /// ## Example
@@ -144,13 +302,35 @@ impl Response {
     /// ```
     pub fn bytes(self, bytes: &[u8]) -> Self {
         Self {
-            data: bytes.to_vec().into(),
+            data: ResponseBody::Static(bytes.into()),
+            ..self
+        }
+    }
+
+    /// Add a shared body to a Response, without copying it.
+    /// Cloning an `Arc<[u8]>` is cheap, so this is useful when the same large body (like a cached page) is sent many times.
+    /// This response type is considered static and will be sent in one go, not chunked.
+    /// ## Example
+    /// ```rust
+    /// # use std::sync::Arc;
+    /// # use afire::{Method, Response, Server};
+    /// let mut server = Server::<()>::new("localhost", 8080);
+    ///
+    /// let page: Arc<[u8]> = Arc::from(&b"<h1>Hello World</h1>"[..]);
+    /// server.route(Method::GET, "/", move |_| Response::new().shared(page.clone()));
+    /// ```
+    pub fn shared(self, body: impl Into<Arc<[u8]>>) -> Self {
+        Self {
+            data: ResponseBody::Static(body.into()),
             ..self
         }
     }
 
     /// Add a stream as data to a Response.
     /// This response type is considered dynamic and will be streamed to the client in chunks using `Transfer-Encoding: chunked`.
+    ///
+    /// The body ends when the stream returns `Ok(0)`, which can be after any number of bytes.
+    /// If it returns an `Interrupted` or `WouldBlock` error the read is retried (`WouldBlock` only for up to 30 seconds), any other error aborts the response.
     /// ## Example
     /// ```rust,no_run
     /// # use afire::{Response, Method, Server};
@@ -170,6 +350,131 @@ impl Response {
         }
     }
 
+    /// Creates a response that streams the file at `path`.
+    /// The `Content-Length` is set from the file size, so the file is sent as is without being loaded into memory or using chunked transfer encoding.
+    /// The `Content-Type` is inferred from the file extension with the server's MIME types (see [`Server::mime`]) when the response is sent, falling back to `application/octet-stream`.
+    ///
+    /// Use [`Response::attachment`] to have browsers download the file instead of displaying it.
+    /// ## Example
+    /// ```rust,no_run
+    /// # use afire::{Response, Method, Server};
+    /// # let mut server = Server::<()>::new("localhost", 8080);
+    /// server.route(Method::GET, "/report", |_| {
+    ///     Response::file("data/report.pdf")
+    ///         .map(|res| res.attachment("report.pdf"))
+    ///         .unwrap_or_else(|_| Response::not_found())
+    /// });
+    /// ```
+    pub fn file(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path)?;
+        let len = file.metadata()?.len();
+
+        let ext = path
+            .extension()
+            .map(|i| i.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        Ok(Self {
+            data: ResponseBody::File(file),
+            mime_extension: Some(ext.into()),
+            ..Self::new()
+        }
+        .header(HeaderType::ContentLength, len.to_string()))
+    }
+
+    /// Sets the `Content-Disposition` header so browsers download the response as a file named `filename`, instead of displaying it.
+    /// ## Example
+    /// ```rust
+    /// # use afire::Response;
+    /// let response = Response::new()
+    ///     .text("a,b,c")
+    ///     .attachment("data.csv");
+    /// ```
+    pub fn attachment(self, filename: impl AsRef<str>) -> Self {
+        let filename = filename.as_ref();
+        let fallback = filename
+            .chars()
+            .map(|i| match i {
+                '"' | '\\' => '_',
+                i if i.is_ascii() && !i.is_ascii_control() => i,
+                _ => '_',
+            })
+            .collect::<String>();
+
+        // Non-ASCII names are sent with the RFC 6266 `filename*` parameter, with an ASCII fallback for old clients
+        let mut value = format!("attachment; filename=\"{fallback}\"");
+        if !filename.is_ascii() {
+            value.push_str(&format!("; filename*=UTF-8''{}", url::encode(filename)));
+        }
+
+        self.header(HeaderType::ContentDisposition, value)
+    }
+
+    /// Add an iterator of chunks as data to a Response.
+    /// Each chunk is sent to the client as soon as the iterator yields it, using `Transfer-Encoding: chunked`.
+    /// Chunks are flushed as they are sent (see [`Response::flush_chunks`]), so they reach the client without delay.
+    /// ## Example
+    /// ```rust
+    /// # use afire::{Response, Method, Server};
+    /// # let mut server = Server::<()>::new("localhost", 8080);
+    /// server.route(Method::GET, "/count", |_| {
+    ///     Response::new().chunks((0..10).map(|i| format!("{i}\n").into_bytes()))
+    /// });
+    /// ```
+    pub fn chunks(self, chunks: impl Iterator<Item = Vec<u8>> + Send + 'static) -> Self {
+        self.stream(ChunkReader::new(chunks)).flush_chunks(true)
+    }
+
+    /// Sets if a streamed body is flushed to the client after every chunk, for realtime output like progress updates or logs.
+    /// While the body is sent, Nagle's algorithm is also disabled on the socket (like [`Server::tcp_nodelay`] does for the whole server), so small chunks aren't held back waiting for more data.
+    ///
+    /// Each read from the stream is sent as one chunk, so a [`Read`] source controls when data is flushed by returning from `read`.
+    /// This is enabled by default for [`Response::chunks`] and [`Response::channel`], and has no effect on static bodies.
+    /// ## Example
+    /// ```rust,no_run
+    /// # use std::process::{Command, Stdio};
+    /// # use afire::{Response, Method, Server};
+    /// # let mut server = Server::<()>::new("localhost", 8080);
+    /// server.route(Method::GET, "/build", |_| {
+    ///     // Send the build output as it's printed
+    ///     let child = Command::new("make")
+    ///         .stdout(Stdio::piped())
+    ///         .spawn()
+    ///         .unwrap();
+    ///     Response::new()
+    ///         .stream(child.stdout.unwrap())
+    ///         .flush_chunks(true)
+    /// });
+    /// ```
+    pub fn flush_chunks(self, flush: bool) -> Self {
+        Self { flush, ..self }
+    }
+
+    /// Creates a Response with a body that is sent as data is sent into the returned channel.
+    /// The response ends once every [`Sender`] is dropped.
+    /// Because the body is only sent after the handler returns, data must be sent from another thread.
+    /// ## Example
+    /// ```rust
+    /// # use std::{thread, time::Duration};
+    /// # use afire::{Response, Method, Server};
+    /// # let mut server = Server::<()>::new("localhost", 8080);
+    /// server.route(Method::GET, "/progress", |_| {
+    ///     let (tx, res) = Response::channel();
+    ///     thread::spawn(move || {
+    ///         for i in 0..=100 {
+    ///             let _ = tx.send(format!("{i}%\n").into_bytes());
+    ///             thread::sleep(Duration::from_millis(10));
+    ///         }
+    ///     });
+    ///     res
+    /// });
+    /// ```
+    pub fn channel() -> (Sender<Vec<u8>>, Self) {
+        let (tx, rx) = mpsc::channel();
+        (tx, Self::new().chunks(rx.into_iter()))
+    }
+
     /// Add a Header to a Response.
     /// Will accept any type that implements `AsRef<str>`, so [`String`], [`str`], [`&str`], etc.
     /// ## Example
@@ -184,6 +489,44 @@ impl Response {
         self
     }
 
+    /// Sets how headers added more than once are sent, overriding the server's [`Server::header_policy`] for this response.
+    /// ## Example
+    /// ```rust
+    /// # use afire::{Response, header::HeaderPolicy};
+    /// // Send both `Location` headers, for testing how clients handle it
+    /// let response = Response::new()
+    ///     .header("Location", "/a")
+    ///     .header("Location", "/b")
+    ///     .header_policy(HeaderPolicy::Append);
+    /// ```
+    pub fn header_policy(self, header_policy: HeaderPolicy) -> Self {
+        Self {
+            header_policy: Some(header_policy),
+            ..self
+        }
+    }
+
+    /// Removes repeated headers if the [`HeaderPolicy`] of this response, or the server's, is [`HeaderPolicy::Replace`].
+    pub(crate) fn apply_header_policy(&mut self, server: HeaderPolicy) {
+        if self.header_policy.unwrap_or(server) == HeaderPolicy::Replace {
+            self.headers.dedup();
+        }
+    }
+
+    /// Sets the `Cache-Control` header of a Response, replacing any existing one.
+    /// ## Example
+    /// ```rust
+    /// # use afire::{Response, header::typed::CacheControl};
+    /// // Cache a hashed asset forever
+    /// let response = Response::new()
+    ///     .text("console.log('Hello')")
+    ///     .cache(CacheControl::new().public().max_age(31536000).immutable());
+    /// ```
+    pub fn cache(mut self, cache: CacheControl) -> Self {
+        self.headers.insert_typed(cache);
+        self
+    }
+
     /// Add a list of Headers to a Response.
     /// Only accepts a slice of [`Header`]s.
     /// ## Example
@@ -219,7 +562,8 @@ impl Response {
     }
 
     /// Add a cookie to a response.
-    /// The [`SetCookie`] will be converted to a [`Header`] and added to the Response.
+    /// The [`SetCookie`] will be converted to a `Set-Cookie` [`Header`] before post middleware run, with the attributes of the server's [`CookiePolicy`] (see [`Server::cookie_policy`]) added.
+    /// Cookies with a [`crate::cookie::CookiePrefix`] that don't have the attributes it requires are still sent, but an error is logged as browsers will reject them.
     /// ## Example
     /// ```
     /// # use afire::{Response, SetCookie};
@@ -229,8 +573,7 @@ impl Response {
     ///     .cookie(SetCookie::new("name2", "value2"));
     /// ```
     pub fn cookie(mut self, cookie: SetCookie) -> Self {
-        self.headers
-            .push(Header::new("Set-Cookie", cookie.to_string()));
+        self.cookies.push(cookie);
         self
     }
 
@@ -245,18 +588,31 @@ impl Response {
     ///         SetCookie::new("name2", "value2")
     ///     ]);
     /// ```
-    pub fn cookies(self, cookie: &[SetCookie]) -> Self {
-        let mut new = Vec::new();
+    pub fn cookies(mut self, cookie: &[SetCookie]) -> Self {
+        self.cookies.extend_from_slice(cookie);
+        self
+    }
 
-        for c in cookie {
-            new.push(Header::new("Set-Cookie", c.to_string()));
-        }
+    /// Turns the cookies added with [`Response::cookie`] into `Set-Cookie` headers, applying the cookie policy.
+    pub(crate) fn apply_cookies(&mut self, policy: Option<&CookiePolicy>) {
+        for mut cookie in mem::take(&mut self.cookies) {
+            if let Some(policy) = policy {
+                cookie.apply_policy(policy);
+            }
 
-        self.headers(&new)
+            if !cookie.prefix_valid() {
+                trace!(
+                    Level::Error,
+                    "Cookie `{}` doesn't have the attributes its prefix requires, so browsers will reject it",
+                    cookie.cookie.name
+                );
+            }
+            self.headers.add(HeaderType::SetCookie, cookie.to_string());
+        }
     }
 
     /// Set a Content Type on a Response with a [`Content`] enum.
-    /// This will add a `Content-Type` header to the Response.
+    /// This will add a `Content-Type` header to the Response, replacing any existing one.
     /// ## Example
     /// ```
     /// # use afire::{Response, Content};
@@ -265,7 +621,8 @@ impl Response {
     ///     .content(Content::HTML);
     /// ```
     pub fn content(mut self, content_type: Content) -> Self {
-        self.headers.push(content_type.into());
+        let header = Header::from(content_type);
+        self.headers.insert(header.name, header.value);
         self
     }
 
@@ -275,54 +632,173 @@ impl Response {
         modifier(self)
     }
 
-    // TODO: Make crate local
-    /// Writes a Response to a TcpStream.
-    /// Will take care of adding default headers and closing the connection if needed.
-    pub fn write(
-        &mut self,
-        stream: Arc<Mutex<TcpStream>>,
-        default_headers: &[Header],
-    ) -> Result<()> {
-        // Add default headers to response
-        // Only the ones that aren't already in the response
-        for i in default_headers {
-            if !self.headers.has(&i.name) {
-                self.headers.push(i.clone());
-            }
-        }
+    /// Gets stats about writing this response to the client, like how many bytes were sent and how long it took.
+    /// This is only available after the response has been written, so in `end` middleware.
+    /// ## Example
+    /// ```rust
+    /// # use afire::{Middleware, Request, Response};
+    /// struct Bandwidth;
+    ///
+    /// impl Middleware for Bandwidth {
+    ///     fn end(&self, req: &Request, res: &Response) {
+    ///         if let Some(stats) = res.write_stats() {
+    ///             println!("{} {}: sent {}B in {:?}", req.method, req.path, stats.bytes, stats.duration);
+    ///         }
+    ///     }
+    /// }
+    /// ```
+    pub fn write_stats(&self) -> Option<WriteStats> {
+        self.write_stats
+    }
 
+    /// Gets information about the panic this response was made for, if the route or a middleware panicked.
+    /// This is set on the response from the [`crate::Server::error_handler`], so it can be reported in `end` middleware.
+    /// ## Example
+    /// ```rust
+    /// # use afire::{Middleware, Request, Response};
+    /// struct PanicReporter;
+    ///
+    /// impl Middleware for PanicReporter {
+    ///     fn end(&self, req: &Request, res: &Response) {
+    ///         if let Some(panic) = res.panic() {
+    ///             eprintln!("{} {} panicked: {}", req.method, req.path, panic);
+    ///         }
+    ///     }
+    /// }
+    /// ```
+    pub fn panic(&self) -> Option<&PanicInfo> {
+        self.panic.as_deref()
+    }
+
+    /// Writes a Response to a Stream.
+    /// Will take care of adding the server's default headers and closing the connection if needed.
+    pub(crate) fn write<State>(
+        &mut self,
+        stream: Arc<Mutex<Box<dyn Stream>>>,
+        server: &Server<State>,
+    ) -> Result<()>
+    where
+        State: 'static + Send + Sync,
+    {
+        // Cookies added by post middleware or response hooks
+        self.apply_cookies(server.cookie_policy.as_ref());
+        self.apply_header_policy(server.header_policy);
         let static_body = self.data.is_static();
 
         // Add content-length header to response if we are sending a static body
-        if static_body && !self.headers.has(HeaderType::ContentLength) {
+        // 204 No Content and 304 Not Modified responses never have a body, so they don't get one
+        let bodiless = matches!(self.status, Status::NoContent | Status::NotModified);
+        if static_body && !bodiless && !self.headers.has(HeaderType::ContentLength) {
             self.headers.push(self.data.content_len());
         }
 
+        // Streams are sent with chunked transfer encoding, unless their length is already known
+        // HTTP/1.0 clients don't support chunked encoding, so the body is sent as is and the end is marked by closing the connection
+        let unknown_length = !static_body && !self.headers.has(HeaderType::ContentLength);
+        let chunked = unknown_length && !self.http10;
+        if unknown_length && self.http10 {
+            self.flag = ResponseFlag::Close;
+        }
+
         // Add Connection: close if response is set to close
         if self.flag == ResponseFlag::Close && !self.headers.has(HeaderType::Connection) {
             self.headers.push(Header::new("Connection", "close"));
         }
 
-        if !static_body && !self.headers.has(HeaderType::TransferEncoding) {
+        if chunked && !self.headers.has(HeaderType::TransferEncoding) {
             self.headers
                 .push(Header::new("Transfer-Encoding", "chunked"));
         }
 
-        // Convert the response to a string
-        let response = format!(
-            "HTTP/1.1 {} {}\r\n{}\r\n\r\n",
-            self.status.code(),
-            self.reason
-                .to_owned()
-                .unwrap_or_else(|| self.status.reason_phrase().to_owned()),
-            headers_to_string(&self.headers)
-        );
+        // Serialize the status line and headers into a pooled buffer
+        // The status lines and the server's default headers are already formatted, default headers are added if they aren't already in the response
+        let mut head = server.buffer_pool.take_empty();
+        let buf = head.as_vec();
+        match &self.reason {
+            Some(reason) => {
+                let _ = write!(buf, "HTTP/1.1 {} {}\r\n", self.status.code(), reason);
+            }
+            None => buf.extend_from_slice(self.status.status_line().as_bytes()),
+        }
+        for i in self.headers.iter() {
+            for part in [i.name.as_str(), ": ", &i.value, "\r\n"] {
+                buf.extend_from_slice(part.as_bytes());
+            }
+        }
+        for (name, line) in server.default_header_lines() {
+            if !self.headers.has(name) {
+                buf.extend_from_slice(line.as_bytes());
+            }
+        }
 
+        // HTTP requires a Date header on responses from servers with a clock
+        #[cfg(feature = "date-header")]
+        if !self.headers.has(HeaderType::Date) && !server.default_headers.has(HeaderType::Date) {
+            crate::internal::date::with_imp_date(server.clock.epoch().as_secs(), |date| {
+                for part in ["Date: ", date, "\r\n"] {
+                    buf.extend_from_slice(part.as_bytes());
+                }
+            });
+        }
+        buf.extend_from_slice(b"\r\n");
+
+        let start = Instant::now();
         let mut stream = stream.lock().unwrap();
-        stream.write_all(response.as_bytes())?;
-        self.data.write(&mut stream)?;
 
-        Ok(())
+        // Small chunks would otherwise wait for the previous ones to be acknowledged before being sent
+        let nodelay = self.flush && !static_body && !server.tcp_nodelay;
+        if nodelay {
+            let _ = stream.set_nodelay(true);
+        }
+        #[cfg(all(feature = "sendfile", any(target_os = "linux", target_os = "android")))]
+        let fd = stream.raw_fd();
+        let timeout = server
+            .socket_timeout
+            .unwrap_or(consts::STREAM_RETRY_TIMEOUT);
+        let mut out = CountingWriter {
+            inner: &mut **stream,
+            bytes: 0,
+            deadline: WriteDeadline::new(timeout),
+        };
+
+        let (data, flush) = (&mut self.data, self.flush);
+        let result = (|| {
+            // Static bodies are sent along with the head in a single write
+            if let ResponseBody::Static(body) = &*data {
+                write_all_vectored(&mut out, &mut [IoSlice::new(&head), IoSlice::new(body)])?;
+                return Ok(());
+            }
+
+            out.write_all(&head)?;
+
+            #[cfg(all(feature = "sendfile", any(target_os = "linux", target_os = "android")))]
+            if let (ResponseBody::File(file), false, Some(fd)) = (&*data, chunked, fd) {
+                crate::internal::sendfile::send_file(file, fd, &mut out.bytes, &mut out.deadline)?;
+                return Ok(());
+            }
+
+            data.write(
+                &mut out,
+                chunked,
+                flush,
+                &server.buffer_pool,
+                server.chunk_size,
+            )
+        })();
+
+        self.write_stats = Some(WriteStats {
+            bytes: out.bytes,
+            duration: start.elapsed(),
+        });
+        let expired = out.deadline.expired;
+        if nodelay {
+            let _ = stream.set_nodelay(false);
+        }
+
+        match result {
+            Err(_) if expired => Err(Error::Stream(StreamError::WriteTimeout)),
+            result => result,
+        }
     }
 }
 
@@ -334,7 +810,7 @@ impl Default for Response {
 
 impl ResponseBody {
     pub fn empty() -> Self {
-        ResponseBody::Static(Vec::new())
+        ResponseBody::Static(Arc::new([]))
     }
 
     /// Checks if the ResponseBody is static.
@@ -352,39 +828,228 @@ impl ResponseBody {
         Header::new("Content-Length", len.to_string())
     }
 
-    /// Writes a ResponseBody to a TcpStream.
+    /// Writes a ResponseBody to a Stream.
     /// Either in one go if it is static or in chunks if it is a stream.
-    fn write(&mut self, stream: &mut TcpStream) -> Result<()> {
-        match self {
-            ResponseBody::Static(data) => stream.write_all(data)?,
-            ResponseBody::Stream(data) => {
-                let data = data.get_mut();
-                loop {
-                    let mut chunk = vec![0; consts::CHUNK_SIZE];
-                    let read = match data.read(&mut chunk) {
-                        Ok(0) => break,
-                        Ok(n) => n,
-                        Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
-                        Err(e) => return Err(e.into()),
-                    };
-
-                    let mut section = format!("{read:X}\r\n").as_bytes().to_vec();
-                    section.extend(&chunk[..read]);
-                    section.extend(b"\r\n");
-
-                    stream.write_all(&section)?;
-                }
-
-                stream.write_all(b"0\r\n\r\n")?;
+    /// If `chunked` is false, streams are copied as is.
+    /// If `flush` is true, the stream is flushed after every chunk.
+    fn write(
+        &mut self,
+        stream: &mut impl Write,
+        chunked: bool,
+        flush: bool,
+        buffers: &BufferPool,
+        chunk_size: usize,
+    ) -> Result<()> {
+        let data: &mut dyn Read = match self {
+            ResponseBody::Static(data) => {
+                stream.write_all(data)?;
+                return Ok(());
             }
+            ResponseBody::Stream(data) => data.get_mut(),
+            ResponseBody::File(file) => file,
         };
 
+        // Leave room for the chunk size line before the data and the CRLF after it,
+        // so each chunk can be sent in a single write without copying it into another buffer.
+        const HEAD: usize = 2 * mem::size_of::<usize>() + 2;
+        let mut buffer = buffers.take(HEAD + chunk_size + 2);
+        loop {
+            let read = read_retry(data, &mut buffer[HEAD..HEAD + chunk_size])?;
+            if read == 0 {
+                break;
+            }
+
+            if !chunked {
+                stream.write_all(&buffer[HEAD..HEAD + read])?;
+            } else {
+                let size = format!("{read:X}\r\n");
+                let start = HEAD - size.len();
+                buffer[start..HEAD].copy_from_slice(size.as_bytes());
+                buffer[HEAD + read..HEAD + read + 2].copy_from_slice(b"\r\n");
+                stream.write_all(&buffer[start..HEAD + read + 2])?;
+            }
+
+            if flush {
+                stream.flush()?;
+            }
+        }
+
+        if chunked {
+            stream.write_all(b"0\r\n\r\n")?;
+        }
+
         Ok(())
     }
 }
 
+/// Reads from a streaming response body, retrying like [`Read::read_to_end`] does instead of aborting the response.
+/// `Interrupted` errors are retried right away, and `WouldBlock` errors are retried until the reader has gone [`consts::STREAM_RETRY_TIMEOUT`] without becoming ready.
+/// Returning `Ok(0)` at any point ends the body.
+pub(crate) fn read_retry(data: &mut dyn Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut deadline = None;
+    loop {
+        match data.read(buf) {
+            Ok(n) => return Ok(n),
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                let deadline =
+                    *deadline.get_or_insert_with(|| Instant::now() + consts::STREAM_RETRY_TIMEOUT);
+                if Instant::now() >= deadline {
+                    return Err(ErrorKind::TimedOut.into());
+                }
+                thread::sleep(consts::STREAM_RETRY_DELAY);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Counts the bytes written to the inner writer.
+/// Writes that fail because the socket isn't ready (`WouldBlock`) are retried until the [`WriteDeadline`] passes.
+struct CountingWriter<'a> {
+    inner: &'a mut dyn Write,
+    bytes: u64,
+    deadline: WriteDeadline,
+}
+
+/// Decides how long to keep retrying writes to a socket that isn't accepting data.
+pub(crate) struct WriteDeadline {
+    /// How long writes can go without making progress.
+    timeout: Duration,
+    /// When a write last made progress.
+    last_progress: Instant,
+    /// If writing was given up on because the timeout passed.
+    expired: bool,
+}
+
+impl WriteDeadline {
+    fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            last_progress: Instant::now(),
+            expired: false,
+        }
+    }
+
+    /// Records that a write made progress.
+    pub(crate) fn progress(&mut self) {
+        self.last_progress = Instant::now();
+    }
+
+    /// Handles an error from a write.
+    /// Returns Ok if the write should be retried, after waiting a bit if the socket wasn't ready, otherwise the error is given back.
+    pub(crate) fn retry(&mut self, err: io::Error) -> io::Result<()> {
+        match err.kind() {
+            ErrorKind::Interrupted => Ok(()),
+            ErrorKind::WouldBlock | ErrorKind::TimedOut => {
+                if self.last_progress.elapsed() >= self.timeout {
+                    self.expired = true;
+                    return Err(err);
+                }
+                thread::sleep(consts::STREAM_RETRY_DELAY);
+                Ok(())
+            }
+            _ => Err(err),
+        }
+    }
+}
+
+impl Write for CountingWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        loop {
+            match self.inner.write(buf) {
+                Ok(n) => {
+                    self.bytes += n as u64;
+                    self.deadline.progress();
+                    return Ok(n);
+                }
+                Err(e) => self.deadline.retry(e)?,
+            }
+        }
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        loop {
+            match self.inner.write_vectored(bufs) {
+                Ok(n) => {
+                    self.bytes += n as u64;
+                    self.deadline.progress();
+                    return Ok(n);
+                }
+                Err(e) => self.deadline.retry(e)?,
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Writes all of the buffers to a writer, using vectored writes so they can be sent with a single system call.
+/// Like [`Write::write_all`], this retries when the write is interrupted.
+pub(crate) fn write_all_vectored(
+    out: &mut impl Write,
+    mut bufs: &mut [IoSlice<'_>],
+) -> io::Result<()> {
+    IoSlice::advance_slices(&mut bufs, 0);
+    while !bufs.is_empty() {
+        match out.write_vectored(bufs) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(n) => IoSlice::advance_slices(&mut bufs, n),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(())
+}
+
+/// Adapts an iterator of chunks into a [`Read`].
+struct ChunkReader<I> {
+    chunks: I,
+    current: Vec<u8>,
+    pos: usize,
+}
+
+impl<I> ChunkReader<I> {
+    fn new(chunks: I) -> Self {
+        Self {
+            chunks,
+            current: Vec::new(),
+            pos: 0,
+        }
+    }
+}
+
+impl<I: Iterator<Item = Vec<u8>>> Read for ChunkReader<I> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // Skip empty chunks, as reading 0 bytes would end the response
+        while self.pos >= self.current.len() {
+            match self.chunks.next() {
+                Some(i) => {
+                    self.current = i;
+                    self.pos = 0;
+                }
+                None => return Ok(0),
+            }
+        }
+
+        let len = buf.len().min(self.current.len() - self.pos);
+        buf[..len].copy_from_slice(&self.current[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
 impl From<Vec<u8>> for ResponseBody {
     fn from(x: Vec<u8>) -> Self {
+        ResponseBody::Static(x.into())
+    }
+}
+
+impl From<Arc<[u8]>> for ResponseBody {
+    fn from(x: Arc<[u8]>) -> Self {
         ResponseBody::Static(x)
     }
 }
@@ -400,6 +1065,192 @@ impl Debug for ResponseBody {
         match self {
             Self::Static(arg) => f.debug_tuple("Static").field(arg).finish(),
             Self::Stream(_arg) => f.debug_tuple("Stream").finish(),
+            Self::File(arg) => f.debug_tuple("File").field(arg).finish(),
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::{
+        cell::RefCell,
+        io::{self, ErrorKind, IoSlice, Read, Write},
+        time::Duration,
+    };
+
+    use super::{write_all_vectored, ChunkReader, CountingWriter, ResponseBody, WriteDeadline};
+    use crate::internal::buffer_pool::BufferPool;
+
+    /// Returns an `Interrupted` or `WouldBlock` error before every read.
+    struct FlakyReader {
+        data: &'static [u8],
+        reads: usize,
+    }
+
+    impl Read for FlakyReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.reads += 1;
+            match self.reads % 3 {
+                1 => return Err(ErrorKind::Interrupted.into()),
+                2 => return Err(ErrorKind::WouldBlock.into()),
+                _ => {}
+            }
+
+            let n = buf.len().min(self.data.len()).min(4);
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data = &self.data[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn test_stream_retry() {
+        let reader = FlakyReader {
+            data: b"Hello World",
+            reads: 0,
+        };
+        let mut body = ResponseBody::Stream(Box::new(RefCell::new(reader)));
+
+        let mut out = Vec::new();
+        body.write(&mut out, true, false, &BufferPool::new(0), 16)
+            .unwrap();
+        assert_eq!(out, b"4\r\nHell\r\n4\r\no Wo\r\n3\r\nrld\r\n0\r\n\r\n");
+    }
+
+    /// Records where in the output it was flushed.
+    #[derive(Default)]
+    struct FlushWriter {
+        out: Vec<u8>,
+        flushes: Vec<usize>,
+    }
+
+    impl Write for FlushWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.out.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.flushes.push(self.out.len());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_stream_flush() {
+        let chunks = vec![b"Hello".to_vec(), b" World".to_vec()];
+        let mut body = ResponseBody::Stream(Box::new(RefCell::new(ChunkReader::new(
+            chunks.clone().into_iter(),
+        ))));
+        let mut out = FlushWriter::default();
+        body.write(&mut out, true, true, &BufferPool::new(0), 16)
+            .unwrap();
+        assert_eq!(out.out, b"5\r\nHello\r\n6\r\n World\r\n0\r\n\r\n");
+        assert_eq!(out.flushes, [10, 21]);
+
+        let mut body =
+            ResponseBody::Stream(Box::new(RefCell::new(ChunkReader::new(chunks.into_iter()))));
+        let mut out = FlushWriter::default();
+        body.write(&mut out, false, false, &BufferPool::new(0), 16)
+            .unwrap();
+        assert_eq!(out.out, b"Hello World");
+        assert!(out.flushes.is_empty());
+    }
+
+    /// Writes at most 3 bytes at a time.
+    struct SlowWriter(Vec<u8>);
+
+    impl Write for SlowWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let n = buf.len().min(3);
+            self.0.extend_from_slice(&buf[..n]);
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_write_all_vectored() {
+        let mut out = SlowWriter(Vec::new());
+        let mut bufs = [
+            IoSlice::new(b"HTTP/1.1 200 OK\r\n\r\n"),
+            IoSlice::new(b""),
+            IoSlice::new(b"Hello"),
+        ];
+        write_all_vectored(&mut out, &mut bufs).unwrap();
+        assert_eq!(out.0, b"HTTP/1.1 200 OK\r\n\r\nHello");
+    }
+
+    /// Writes 3 bytes after every `WouldBlock` error, until `limit` bytes have been written.
+    struct BlockingWriter {
+        out: Vec<u8>,
+        blocked: bool,
+        limit: usize,
+    }
+
+    impl Write for BlockingWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.blocked = !self.blocked;
+            if self.blocked || self.out.len() >= self.limit {
+                return Err(ErrorKind::WouldBlock.into());
+            }
+
+            let n = buf.len().min(3);
+            self.out.extend_from_slice(&buf[..n]);
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_write_retry() {
+        let mut inner = BlockingWriter {
+            out: Vec::new(),
+            blocked: false,
+            limit: usize::MAX,
+        };
+        let mut out = CountingWriter {
+            inner: &mut inner,
+            bytes: 0,
+            deadline: WriteDeadline::new(Duration::from_secs(1)),
+        };
+        out.write_all(b"Hello World").unwrap();
+        assert_eq!(out.bytes, 11);
+        assert_eq!(inner.out, b"Hello World");
+
+        // The client stops reading partway through
+        let mut inner = BlockingWriter {
+            out: Vec::new(),
+            blocked: false,
+            limit: 6,
+        };
+        let mut out = CountingWriter {
+            inner: &mut inner,
+            bytes: 0,
+            deadline: WriteDeadline::new(Duration::from_millis(20)),
+        };
+        assert!(out.write_all(b"Hello World").is_err());
+        assert!(out.deadline.expired);
+        assert_eq!(out.bytes, 6);
+    }
+
+    #[test]
+    fn test_chunk_reader() {
+        let chunks = vec![b"Hello".to_vec(), Vec::new(), b", World".to_vec()];
+        let mut reader = ChunkReader::new(chunks.into_iter());
+
+        let mut buf = [0; 3];
+        assert_eq!(reader.read(&mut buf).unwrap(), 3);
+        assert_eq!(&buf, b"Hel");
+
+        let mut rest = String::new();
+        reader.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "lo, World");
+    }
+}
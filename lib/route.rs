@@ -1,40 +1,226 @@
 use std::fmt::{self, Debug};
-use std::rc::Rc;
-use std::sync::Arc;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, PoisonError, RwLock,
+};
+use std::time::Duration;
 
-use crate::{path::Path, Method, Request, Response};
+use crate::{
+    internal::router::Router,
+    path::{Path, PathPart},
+    Context, Method, Request, Response,
+};
+
+#[cfg(feature = "tracing")]
+use crate::trace::emoji;
+
+#[cfg(feature = "extensions")]
+use crate::extensions::openapi::RouteDocs;
 
 type StatelessRoute = Box<dyn Fn(&Request) -> Response + Send + Sync>;
 type StatefulRoute<State> = Box<dyn Fn(Arc<State>, &Request) -> Response + Send + Sync>;
+type ContextRoute<State> = Box<dyn Fn(&Context<State>) -> Response + Send + Sync>;
 
-pub enum RouteType<State> {
+pub enum RouteType<State: 'static + Send + Sync> {
     Stateless(StatelessRoute),
     Stateful(StatefulRoute<State>),
+    Context(ContextRoute<State>),
 }
 
 /// Defines a route.
 ///
 /// You should not use this directly.
-/// It will be created automatically when using [`crate::Server::route`] or [`crate::Server::stateful_route`].
+/// It will be created automatically when using [`crate::Server::route`], [`crate::Server::stateful_route`] or [`crate::Server::context_route`].
 #[derive(Debug)]
-pub struct Route<State> {
+pub struct Route<State: 'static + Send + Sync> {
     /// Route Method (GET, POST, ANY, etc.)
-    method: Method,
+    pub(crate) method: Method,
 
     /// Route path, in its tokenized form.
-    path: Path,
+    pub(crate) path: Path,
 
-    /// Route Handler, either stateless or stateful.
+    /// Route Handler, either stateless, stateful or taking a context.
     pub(crate) handler: RouteType<State>,
+
+    /// Routes with a higher priority are matched first, see [`crate::Server::route_priority`].
+    pub(crate) priority: i32,
+
+    /// A name for the route, see [`crate::Server::route_name`].
+    pub(crate) name: Option<String>,
+
+    /// Overrides [`crate::Server::max_body_size`] for this route, see [`crate::Server::route_max_body_size`].
+    pub(crate) max_body_size: Option<usize>,
+
+    /// Overrides [`crate::Server::body_timeout`] for this route, see [`crate::Server::route_body_timeout`].
+    pub(crate) body_timeout: Option<Duration>,
+
+    /// If the body is left on the socket for the handler to read, see [`crate::Server::route_stream_body`].
+    pub(crate) stream_body: bool,
+
+    /// Metadata attached to the route, see [`crate::Server::route_meta`].
+    pub(crate) meta: Arc<RouteMeta>,
+
+    /// Documentation for the route, see [`crate::Server::route_docs`].
+    #[cfg(feature = "extensions")]
+    pub(crate) docs: Option<RouteDocs>,
+}
+
+/// Information about a route, from [`crate::Server::routes`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RouteInfo {
+    /// The method the route handles.
+    pub method: Method,
+    /// The path the route handles, like `/user/{id}`.
+    pub path: String,
+    /// The name of the route, if it was given one with [`crate::Server::route_name`].
+    pub name: Option<String>,
+    /// The priority of the route, see [`crate::Server::route_priority`].
+    pub priority: i32,
+    /// Metadata attached to the route with [`crate::Server::route_meta`].
+    pub meta: RouteMeta,
+    /// Documentation for the route, if it was given any with [`crate::Server::route_docs`].
+    #[cfg(feature = "extensions")]
+    pub docs: Option<RouteDocs>,
 }
 
-impl<State> Route<State> {
+/// Metadata attached to a route with [`crate::Server::route_meta`], like tags, descriptions or auth requirements.
+/// Each key can have more than one value, which are kept in the order they were added.
+///
+/// The metadata of the route that handles a request can be read at runtime with [`Request::route_meta`],
+/// so middleware can make decisions for each route without keeping its own list of paths.
+/// The `OpenApi` extension uses the `summary`, `description` and `tag` keys, and `RouteDebug` lists all of it.
+/// ## Example
+/// ```rust
+/// # use afire::{Server, Response, Method, Request, Status, middleware::{Middleware, MiddleResult}};
+/// struct Auth;
+///
+/// impl Middleware for Auth {
+///     fn pre(&self, req: &mut Request) -> MiddleResult {
+///         let meta = req.route_meta();
+///         let required = meta.as_ref().and_then(|x| x.get("auth"));
+///         if required == Some("admin") && !req.headers.has("Authorization") {
+///             return MiddleResult::Send(Response::new().status(Status::Unauthorized));
+///         }
+///         MiddleResult::Continue
+///     }
+/// }
+///
+/// # let mut server = Server::<()>::new("localhost", 8080);
+/// server
+///     .route(Method::DELETE, "/user/{id}", |_| Response::new().text("Deleted"))
+///     .route_meta("auth", "admin")
+///     .route_meta("tag", "users");
+/// Auth.attach(&mut server);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RouteMeta(Vec<(String, String)>);
+
+impl RouteMeta {
+    /// Adds a value for a key, after any values the key already has.
+    pub fn add(&mut self, key: impl AsRef<str>, value: impl AsRef<str>) {
+        self.0
+            .push((key.as_ref().to_owned(), value.as_ref().to_owned()));
+    }
+
+    /// Gets the first value of a key.
+    pub fn get(&self, key: impl AsRef<str>) -> Option<&str> {
+        self.get_all(key).next()
+    }
+
+    /// Gets all the values of a key, in the order they were added.
+    pub fn get_all(&self, key: impl AsRef<str>) -> impl Iterator<Item = &str> {
+        let key = key.as_ref().to_owned();
+        self.0
+            .iter()
+            .filter(move |(k, _)| *k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Checks if a key has any values.
+    pub fn has(&self, key: impl AsRef<str>) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Iterates over all the keys and values, in the order they were added.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    /// Checks if there is no metadata.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// Routes that can be added and removed while the server is running, for things like plugins or feature flags.
+/// You can get one with [`crate::Server::route_table`], before or after starting the server.
+/// It is cheap to clone, and changes apply to the next request that is routed.
+///
+/// Routes in the table are matched along with the ones added with [`crate::Server::route`] and friends, using the same priorities.
+/// When a route in the table and a server route have the same priority, the one in the table wins, as if it had been added last.
+/// Unlike server routes, routes in the table are not checked for conflicts, and are not listed in [`crate::Server::routes`].
+/// ## Example
+/// ```rust,no_run
+/// # use afire::{Server, Response, Method};
+/// let server = Server::<()>::new("localhost", 8080);
+/// let table = server.route_table();
+///
+/// let id = table.add(Method::GET, "/beta", |_| Response::new().text("New feature!"));
+/// std::thread::spawn(move || {
+///     std::thread::sleep(std::time::Duration::from_secs(60));
+///     // Turn the feature off again
+///     table.remove(id);
+/// });
+///
+/// server.start_threaded(4).unwrap();
+/// ```
+pub struct RouteTable<State: 'static + Send + Sync>(Arc<RouteTableInner<State>>);
+
+struct RouteTableInner<State: 'static + Send + Sync> {
+    /// The id of the next route to be added.
+    next_id: AtomicU64,
+    /// The current routes, replaced as a whole on every change so requests never wait for a change to finish.
+    routes: RwLock<Arc<RouteSnapshot<State>>>,
+}
+
+/// The routes in a [`RouteTable`] at one point in time.
+struct RouteSnapshot<State: 'static + Send + Sync> {
+    ids: Vec<RouteId>,
+    routes: Vec<Arc<Route<State>>>,
+    router: Router,
+}
+
+/// Identifies a route added to a [`RouteTable`], so it can be removed later.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RouteId(u64);
+
+/// How requests with or without a trailing slash are handled, see [`crate::Server::trailing_slash`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TrailingSlash {
+    /// Paths with and without a trailing slash are the same, so `/foo/` is handled by the `/foo` route.
+    #[default]
+    Ignore,
+    /// Requests to paths with a trailing slash are redirected to the path without it (`/foo/` to `/foo`).
+    Trim,
+    /// Requests to paths without a trailing slash are redirected to the path with it (`/foo` to `/foo/`).
+    Append,
+}
+
+impl<State: 'static + Send + Sync> Route<State> {
     /// Creates a new route.
     pub(crate) fn new(method: Method, path: String, handler: StatelessRoute) -> Self {
         Self {
             method,
             path: Path::new(path),
             handler: RouteType::Stateless(handler),
+            priority: 0,
+            name: None,
+            max_body_size: None,
+            body_timeout: None,
+            stream_body: false,
+            meta: Arc::default(),
+            #[cfg(feature = "extensions")]
+            docs: None,
         }
     }
 
@@ -48,6 +234,31 @@ impl<State> Route<State> {
             method,
             path: Path::new(path),
             handler: RouteType::Stateful(handler),
+            priority: 0,
+            name: None,
+            max_body_size: None,
+            body_timeout: None,
+            stream_body: false,
+            meta: Arc::default(),
+            #[cfg(feature = "extensions")]
+            docs: None,
+        }
+    }
+
+    /// Create a new context route
+    pub(crate) fn new_context(method: Method, path: String, handler: ContextRoute<State>) -> Self {
+        Self {
+            method,
+            path: Path::new(path),
+            handler: RouteType::Context(handler),
+            priority: 0,
+            name: None,
+            max_body_size: None,
+            body_timeout: None,
+            stream_body: false,
+            meta: Arc::default(),
+            #[cfg(feature = "extensions")]
+            docs: None,
         }
     }
 
@@ -56,9 +267,44 @@ impl<State> Route<State> {
         matches!(self.handler, RouteType::Stateful(_))
     }
 
+    /// Gets the path of the route, as it was defined.
+    pub(crate) fn path(&self) -> String {
+        format!("/{}", self.path.raw)
+    }
+
+    /// Gets information about the route.
+    pub(crate) fn info(&self) -> RouteInfo {
+        RouteInfo {
+            method: self.method.clone(),
+            path: self.path(),
+            name: self.name.clone(),
+            priority: self.priority,
+            meta: (*self.meta).clone(),
+            #[cfg(feature = "extensions")]
+            docs: self.docs.clone(),
+        }
+    }
+
+    /// Checks if two routes match exactly the same requests, so neither would take priority without relying on the order they were added in.
+    /// Param and wildcard segments match the same things, so `/user/{id}` conflicts with `/user/{name}` and `/user/*`.
+    pub(crate) fn conflicts(&self, other: &Self) -> bool {
+        let same_part = |(a, b): (&PathPart, &PathPart)| match (a, b) {
+            (PathPart::Normal(a), PathPart::Normal(b)) => a == b,
+            (PathPart::Param(_) | PathPart::Any, PathPart::Param(_) | PathPart::Any) => true,
+            (PathPart::ConstrainedParam(_, a), PathPart::ConstrainedParam(_, b)) => a == b,
+            (PathPart::AnyAfter, PathPart::AnyAfter) => true,
+            _ => false,
+        };
+
+        self.method == other.method
+            && self.priority == other.priority
+            && self.path.parts.len() == other.path.parts.len()
+            && self.path.parts.iter().zip(&other.path.parts).all(same_part)
+    }
+
     /// Checks if a Request matches the route.
     /// Returns the path parameters if it does.
-    pub(crate) fn matches(&self, req: Rc<Request>) -> Option<Vec<(String, String)>> {
+    pub(crate) fn matches(&self, req: &Request) -> Option<Vec<(String, String)>> {
         if self.method != Method::ANY && self.method != req.method {
             return None;
         }
@@ -66,11 +312,249 @@ impl<State> Route<State> {
     }
 }
 
-impl<State> Debug for RouteType<State> {
+impl<State: 'static + Send + Sync> RouteTable<State> {
+    /// Adds a stateless route, see [`crate::Server::route`].
+    pub fn add(
+        &self,
+        method: Method,
+        path: impl AsRef<str>,
+        handler: impl Fn(&Request) -> Response + Send + Sync + 'static,
+    ) -> RouteId {
+        let path = path.as_ref().to_owned();
+        self.insert(Route::new(method, path, Box::new(handler)))
+    }
+
+    /// Adds a context route, see [`crate::Server::context_route`].
+    pub fn add_context(
+        &self,
+        method: Method,
+        path: impl AsRef<str>,
+        handler: impl Fn(&Context<State>) -> Response + Send + Sync + 'static,
+    ) -> RouteId {
+        let path = path.as_ref().to_owned();
+        self.insert(Route::new_context(method, path, Box::new(handler)))
+    }
+
+    /// Adds a route with all of its options, like its priority or body limits.
+    /// Returns the id of the route, to remove it with [`RouteTable::remove`].
+    pub(crate) fn insert(&self, route: Route<State>) -> RouteId {
+        let id = RouteId(self.0.next_id.fetch_add(1, Ordering::Relaxed));
+        trace!(
+            "{}Adding Runtime Route {} {}",
+            emoji("🚗"),
+            route.method,
+            route.path()
+        );
+
+        self.update(|ids, routes| {
+            ids.push(id);
+            routes.push(Arc::new(route));
+        });
+        id
+    }
+
+    /// Removes a route from the table.
+    /// Returns false if the route was already removed.
+    /// Requests that are already being handled by the route are not affected.
+    pub fn remove(&self, id: RouteId) -> bool {
+        let mut found = false;
+        self.update(|ids, routes| {
+            if let Some(idx) = ids.iter().position(|x| *x == id) {
+                trace!(
+                    "{}Removing Runtime Route {}",
+                    emoji("🚗"),
+                    routes[idx].path()
+                );
+                ids.remove(idx);
+                routes.remove(idx);
+                found = true;
+            }
+        });
+        found
+    }
+
+    /// Removes every route from the table.
+    pub fn clear(&self) {
+        self.update(|ids, routes| {
+            ids.clear();
+            routes.clear();
+        });
+    }
+
+    /// Gets information about the routes currently in the table, in the order they were added.
+    pub fn routes(&self) -> Vec<(RouteId, RouteInfo)> {
+        let snapshot = self.snapshot();
+        let routes = snapshot.routes.iter().map(|x| x.info());
+        snapshot.ids.iter().copied().zip(routes).collect()
+    }
+
+    /// Finds the route in the table that matches the method and path, along with its priority.
+    pub(crate) fn find(&self, method: &Method, path: &str) -> Option<(i32, Arc<Route<State>>)> {
+        let snapshot = self.snapshot();
+        let (priority, idx) = snapshot.router.find(method, path)?;
+        Some((priority, snapshot.routes[idx].clone()))
+    }
+
+    fn snapshot(&self) -> Arc<RouteSnapshot<State>> {
+        self.0
+            .routes
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Changes the routes and rebuilds the router.
+    /// Writers are serialized by the lock, while readers keep using the old snapshot until the new one is swapped in.
+    fn update(&self, change: impl FnOnce(&mut Vec<RouteId>, &mut Vec<Arc<Route<State>>>)) {
+        let mut current = self
+            .0
+            .routes
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        let mut ids = current.ids.clone();
+        let mut routes = current.routes.clone();
+        change(&mut ids, &mut routes);
+
+        let router = Router::new(&routes);
+        *current = Arc::new(RouteSnapshot {
+            ids,
+            routes,
+            router,
+        });
+    }
+}
+
+impl<State: 'static + Send + Sync> Default for RouteTable<State> {
+    fn default() -> Self {
+        Self(Arc::new(RouteTableInner {
+            next_id: AtomicU64::new(0),
+            routes: RwLock::new(Arc::new(RouteSnapshot {
+                ids: Vec::new(),
+                routes: Vec::new(),
+                router: Router::default(),
+            })),
+        }))
+    }
+}
+
+impl<State: 'static + Send + Sync> Clone for RouteTable<State> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<State: 'static + Send + Sync> Debug for RouteTable<State> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list()
+            .entries(self.snapshot().routes.iter().map(|x| x.info()))
+            .finish()
+    }
+}
+
+impl<State: 'static + Send + Sync> Debug for RouteType<State> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             RouteType::Stateless(_) => f.write_str("stateless"),
             RouteType::Stateful(_) => f.write_str("stateful"),
+            RouteType::Context(_) => f.write_str("context"),
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::Cursor,
+        sync::{Arc, Mutex},
+    };
+
+    use crate::{
+        internal::testing::{MemoryListener, MemoryStream},
+        middleware::MiddleResult,
+        Method, Middleware, Request, Response, Server, Status,
+    };
+
+    #[test]
+    fn test_route_meta() {
+        struct Auth;
+
+        impl Middleware for Auth {
+            fn pre(&self, req: &mut Request) -> MiddleResult {
+                match req.route_meta() {
+                    Some(meta) if meta.has("admin") => {
+                        MiddleResult::Send(Response::new().status(Status::Unauthorized))
+                    }
+                    _ => MiddleResult::Continue,
+                }
+            }
+        }
+
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let input = ["/admin", "/public", "/missing"]
+            .map(|path| format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n"))
+            .concat();
+        let listener = MemoryListener(Mutex::new(vec![MemoryStream {
+            input: Cursor::new(input.into_bytes()),
+            output: output.clone(),
+        }]));
+
+        let mut server = Server::<()>::new("localhost", 0);
+        server
+            .route(Method::GET, "/admin", |_| Response::new().text("secret"))
+            .route_meta("admin", "true");
+        server
+            .route(Method::GET, "/public", |req| {
+                let meta = req.route_meta().unwrap();
+                Response::new().text(meta.get_all("tag").collect::<Vec<_>>().join(","))
+            })
+            .route_meta("tag", "a")
+            .route_meta("tag", "b");
+        Auth.attach(&mut server);
+        assert_eq!(server.routes()[1].meta.get("tag"), Some("a"));
+        assert!(server.start_with_listener(listener).is_err());
+
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        let responses = output.split("HTTP/1.1 ").skip(1).collect::<Vec<_>>();
+        assert!(responses[0].starts_with("401 "), "{:?}", responses[0]);
+        assert!(responses[1].ends_with("\r\n\r\na,b"), "{:?}", responses[1]);
+        assert!(responses[2].starts_with("404 "), "{:?}", responses[2]);
+    }
+
+    #[test]
+    fn test_route_table() {
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let input = ["/flag", "/remove", "/flag", "/add", "/beta"]
+            .map(|path| format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n"))
+            .concat();
+        let listener = MemoryListener(Mutex::new(vec![MemoryStream {
+            input: Cursor::new(input.into_bytes()),
+            output: output.clone(),
+        }]));
+
+        let mut server = Server::<()>::new("localhost", 0);
+        let table = server.route_table();
+        let id = table.add(Method::GET, "/flag", |_| Response::new().text("on"));
+        server.route(Method::GET, "/flag", |_| Response::new().text("off"));
+
+        let remove = table.clone();
+        server.route(Method::GET, "/remove", move |_| {
+            Response::new().text(remove.remove(id))
+        });
+        let add = table.clone();
+        server.route(Method::GET, "/add", move |_| {
+            add.add(Method::GET, "/beta", |_| Response::new().text("beta"));
+            Response::new().text("added")
+        });
+        assert!(server.start_with_listener(listener).is_err());
+
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        let bodies = output
+            .split("HTTP/1.1 ")
+            .skip(1)
+            .map(|x| x.split("\r\n\r\n").nth(1).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(bodies, ["on", "true", "off", "added", "beta"]);
+        assert_eq!(table.routes().len(), 1);
+        assert!(!table.remove(id));
+    }
+}
@@ -1,20 +1,61 @@
 // Import STD libraries
-use std::any::type_name;
-use std::net::{IpAddr, SocketAddr, TcpListener};
+#[cfg(feature = "tracing")]
+use std::any::{type_name, type_name_of_val};
+use std::collections::HashMap;
+use std::fmt::{self, Debug};
+use std::mem;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::rc::Rc;
 use std::str;
-use std::sync::Arc;
-use std::time::Duration;
+use std::sync::{
+    atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    Arc, Condvar, Mutex, OnceLock,
+};
+use std::time::{Duration, Instant};
 
 // Import local files
+#[cfg(feature = "extensions")]
+use crate::extensions::openapi::RouteDocs;
+#[cfg(unix)]
+use crate::internal::event_loop;
+#[cfg(feature = "tracing")]
+use crate::trace::emoji;
 use crate::{
-    error::Result, error::StartupError, handle::handle, header::Headers,
-    internal::common::ToHostAddress, thread_pool::ThreadPool, trace::emoji, Content, Header,
-    HeaderType, Method, Middleware, Request, Response, Route, Status, VERSION,
+    body_filter::BodyFilter,
+    clock::{Clock, SystemClock},
+    consts,
+    cookie::CookiePolicy,
+    error::{Error, PanicInfo, ParseError, Result, StartupError},
+    handle::{
+        accept_error, accepting, default_error_handler, handle, queue_timed_out, reject_connection,
+        reject_queued, reserve_connection,
+    },
+    header::{HeaderPolicy, Headers},
+    internal::{
+        buffer_pool::BufferPool,
+        common::{ForceLock, ToHostAddress},
+        encoding::url,
+        reaper::{idle_count, Reaper, TrackedConnection},
+        router::Router,
+        scheduler::{Scheduler, Task},
+        socket,
+    },
+    listener::Listener,
+    request::UrlConfig,
+    route::{RouteInfo, RouteTable, TrailingSlash},
+    thread_pool::{ThreadPool, ThreadPoolHandle},
+    web_socket::{WebSocketConfig, WebSocketLimits},
+    Context, Header, HeaderType, Method, Middleware, MimeTypes, Request, Response, Route, Status,
+    VERSION,
 };
 
-type ErrorHandler<State> =
-    Box<dyn Fn(Option<Arc<State>>, &Box<Result<Rc<Request>>>, String) -> Response + Send + Sync>;
+type StartHandler = Box<dyn Fn(SocketAddr) + Send + Sync>;
+type ShutdownHook = Box<dyn Fn() + Send + Sync>;
+type ResponseHook = Box<dyn Fn(&Request, &mut Response) + Send + Sync>;
+type ErrorHandler<State> = Box<
+    dyn Fn(Option<Arc<State>>, &Box<Result<Rc<Request>>>, &PanicInfo) -> Response + Send + Sync,
+>;
+type StatusPage = Box<dyn Fn(Option<&Request>, &Error) -> Response + Send + Sync>;
 
 /// Defines a server.
 pub struct Server<State: 'static + Send + Sync = ()> {
@@ -24,9 +65,19 @@ pub struct Server<State: 'static + Send + Sync = ()> {
     /// Ip address to listen on.
     pub ip: IpAddr,
 
+    /// Set if the IP passed to [`Server::new`] could not be parsed.
+    /// The error is returned when the server is started, as [`StartupError::InvalidIp`].
+    pub(crate) invalid_ip: bool,
+
     /// Routes to handle.
     pub routes: Vec<Route<State>>,
 
+    /// The routes compiled into a tree for matching requests, see [`Server::router`].
+    pub(crate) router: OnceLock<Router>,
+
+    /// Routes that can be changed while the server is running, see [`Server::route_table`].
+    pub(crate) route_table: RouteTable<State>,
+
     // Other stuff
     /// Middleware
     pub middleware: Vec<Box<dyn Middleware + Send + Sync>>,
@@ -37,22 +88,182 @@ pub struct Server<State: 'static + Send + Sync = ()> {
     /// Default response for internal server errors
     pub error_handler: ErrorHandler<State>,
 
+    /// What to do when a route or middleware panics, see [`Server::panic_policy`].
+    pub panic_policy: PanicPolicy,
+
+    /// If backtraces of panics in routes and middleware are always captured, see [`Server::capture_backtraces`].
+    pub capture_backtraces: bool,
+
+    /// Custom responses for the errors generated by the server, see [`Server::status_page`].
+    pub(crate) status_pages: HashMap<Status, StatusPage>,
+
+    /// Called with the address the server is listening on, once it has been bound.
+    pub on_start: Option<StartHandler>,
+
+    /// Hooks run after the server has been gracefully shut down, see [`Server::on_shutdown`].
+    pub(crate) on_shutdown: Vec<ShutdownHook>,
+
+    /// Hooks run on every response before it is written, see [`Server::response_hook`].
+    pub(crate) response_hooks: Vec<ResponseHook>,
+
+    /// Transforms applied to response bodies, see [`Server::body_filter`].
+    pub(crate) body_filters: Vec<Box<dyn BodyFilter>>,
+
     /// Headers automatically added to every response.
     pub default_headers: Headers,
 
+    /// How responses with repeated headers are sent, see [`Server::header_policy`].
+    pub header_policy: HeaderPolicy,
+
+    /// The default headers formatted as header lines, see [`Server::default_header_lines`].
+    pub(crate) default_header_lines: OnceLock<Vec<(HeaderType, String)>>,
+
+    /// The MIME types used to infer the `Content-Type` of files, see [`Server::mime`].
+    pub(crate) mime_types: MimeTypes,
+
     /// Weather to allow keep-alive connections.
     /// If this is set to false, the server will close the connection after every request.
     /// This is enabled by default.
     pub keep_alive: bool,
 
+    /// How long an idle keep-alive connection will be held open while waiting for the next request.
+    /// If this is None, idle connections will be held open until the client closes them (or the socket timeout is reached).
+    pub keep_alive_timeout: Option<Duration>,
+
+    /// The maximum number of requests that will be handled on a single keep-alive connection.
+    /// Once this limit is reached, the connection will be closed after the response is sent.
+    pub max_keep_alive_requests: Option<usize>,
+
     /// Socket Timeout
     pub socket_timeout: Option<Duration>,
+
+    /// How long a client has to send the request line, from when the server starts reading the request.
+    /// Connections that take longer are sent a `408 Request Timeout` response and closed.
+    pub request_line_timeout: Option<Duration>,
+
+    /// How long a client has to send all the headers after the request line.
+    /// Connections that take longer are sent a `408 Request Timeout` response and closed.
+    pub header_timeout: Option<Duration>,
+
+    /// How long a client has to send the body after the headers.
+    /// Connections that take longer are sent a `408 Request Timeout` response and closed.
+    pub body_timeout: Option<Duration>,
+
+    /// Weather to set `TCP_NODELAY` on accepted sockets, disabling Nagle's algorithm.
+    pub tcp_nodelay: bool,
+
+    /// Weather to set `SO_REUSEADDR` on the listener.
+    /// This is enabled by default.
+    pub reuse_address: bool,
+
+    /// Weather to set `SO_REUSEPORT` on the listener.
+    pub reuse_port: bool,
+
+    /// The maximum number of pending connections the listener will queue before refusing new ones.
+    /// If this is None, the default of 128 is used.
+    pub backlog: Option<u32>,
+
+    /// The maximum number of connections that can be open at once.
+    /// Any connections over this limit will immediately be sent a `503 Service Unavailable` response and closed.
+    pub max_connections: Option<usize>,
+
+    /// The maximum number of connections that can be open at once from a single IP address.
+    /// Any connections over this limit will immediately be sent a `429 Too Many Requests` response and closed.
+    pub max_connections_per_ip: Option<usize>,
+
+    /// What is done with connections over [`Server::max_connections`] or [`Server::max_connections_per_ip`].
+    pub connection_rejection: ConnectionRejection,
+
+    /// Weather to close idle connections from a background thread, even while a read on them is blocked.
+    /// See [`Server::reap_idle_connections`].
+    pub reap_idle_connections: bool,
+
+    /// The longest a connection can wait in the thread pool's queue before a worker starts handling it.
+    /// Connections that wait longer are sent a `503 Service Unavailable` response with a `Retry-After` header and closed.
+    pub max_queue_wait: Option<Duration>,
+
+    /// Counters for the server's connections, see [`Server::connection_stats`].
+    pub(crate) connection_stats: ConnectionStats,
+
+    /// The maximum number of worker threads the thread pool can grow to under load.
+    /// If this is None, the pool will stay at the number of threads passed to [`Server::start_threaded`].
+    pub max_threads: Option<usize>,
+
+    /// How long extra worker threads (over the initial amount) can be idle before they are stopped.
+    pub thread_idle_timeout: Duration,
+
+    /// Counters for monitoring the thread pool.
+    pub(crate) thread_pool: ThreadPoolHandle,
+
+    /// Used to stop the server, see [`Server::shutdown_handle`].
+    pub(crate) shutdown: ShutdownHandle,
+
+    /// If the server should shut down on SIGINT and SIGTERM, see [`Server::handle_signals`].
+    #[cfg(feature = "signals")]
+    pub(crate) handle_signals: bool,
+
+    /// Background tasks, see [`Server::schedule`] and [`Server::spawn_task`].
+    pub(crate) tasks: Vec<Task>,
+
+    /// The key used to sign cookies, see [`Server::cookie_key`].
+    pub(crate) cookie_key: Option<Arc<[u8]>>,
+
+    /// The attributes added to cookies set with [`Response::cookie`], see [`Server::cookie_policy`].
+    pub(crate) cookie_policy: Option<CookiePolicy>,
+
+    /// The source of the current time, see [`Server::clock`].
+    pub(crate) clock: Arc<dyn Clock>,
+
+    /// The public URL and trusted proxies used by [`Request::base_url`], see [`Server::public_url`] and [`Server::trust_proxy`].
+    pub(crate) url_config: Arc<UrlConfig>,
+
+    /// The limits on WebSockets and the number that are open, see [`Server::web_socket_limits`].
+    pub(crate) web_socket_config: Arc<WebSocketConfig>,
+
+    /// The maximum length of a request line (method, path and version) in bytes.
+    /// Requests with a longer request line are sent a `414 URI Too Long` response.
+    pub max_request_line: usize,
+
+    /// The maximum total size of a request's headers in bytes.
+    /// Requests with larger headers are sent a `431 Request Header Fields Too Large` response.
+    pub max_header_size: usize,
+
+    /// The maximum number of headers a request can have.
+    /// Requests with more headers are sent a `431 Request Header Fields Too Large` response.
+    pub max_headers: usize,
+
+    /// The maximum size of a request's body in bytes.
+    /// Requests with a larger body are sent a `413 Payload Too Large` response.
+    /// This can be changed for each route with [`Server::route_max_body_size`].
+    pub max_body_size: Option<usize>,
+
+    /// The size of the buffer used to read requests.
+    pub read_buffer_size: usize,
+
+    /// The maximum size of each chunk when streaming a response body.
+    pub chunk_size: usize,
+
+    /// Buffers that are reused between requests, see [`Server::buffer_pool_size`].
+    pub(crate) buffer_pool: Arc<BufferPool>,
+
+    /// How requests with or without a trailing slash are handled, see [`Server::trailing_slash`].
+    pub trailing_slash: TrailingSlash,
+
+    /// If `.` and `..` segments are resolved before requests are routed, see [`Server::normalize_paths`].
+    pub normalize_paths: bool,
+
+    /// How the targets of requests are checked and normalized, see [`Server::uri_policy`].
+    pub uri_policy: UriPolicy,
+
+    /// If the timings of each request are sent in a `Server-Timing` header, see [`Server::server_timing`].
+    pub server_timing: bool,
 }
 
 /// Implementations for Server
 impl<State: Send + Sync> Server<State> {
     /// Creates a new server on the specified address and port.
     /// `raw_ip` can be either an IP address or 'localhost', which expands to 127.0.0.1.
+    /// If the IP is invalid, starting the server will return [`StartupError::InvalidIp`].
     ///
     /// ## Example
     /// ```rust
@@ -63,22 +274,70 @@ impl<State: Send + Sync> Server<State> {
     /// ```
     pub fn new(raw_ip: impl ToHostAddress, port: u16) -> Self {
         trace!("{}Initializing Server v{}", emoji("🐍"), VERSION);
+        let ip = raw_ip.to_address();
+
         Server {
             port,
-            ip: raw_ip.to_address().unwrap(),
+            invalid_ip: ip.is_err(),
+            ip: ip.unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED)),
             routes: Vec::new(),
+            router: OnceLock::new(),
+            route_table: RouteTable::default(),
             middleware: Vec::new(),
 
-            error_handler: Box::new(|_state, _req, err| {
-                Response::new()
-                    .status(Status::InternalServerError)
-                    .text(format!("Internal Server Error :/\nError: {err}"))
-                    .content(Content::TXT)
-            }),
+            error_handler: Box::new(|_state, req, err| default_error_handler(req, err)),
+            panic_policy: PanicPolicy::default(),
+            capture_backtraces: false,
+            status_pages: HashMap::new(),
 
+            on_start: None,
+            on_shutdown: Vec::new(),
+            response_hooks: Vec::new(),
+            body_filters: Vec::new(),
             default_headers: Headers(vec![Header::new("Server", format!("afire/{VERSION}"))]),
+            header_policy: HeaderPolicy::default(),
+            default_header_lines: OnceLock::new(),
+            mime_types: MimeTypes::new(),
             keep_alive: true,
+            keep_alive_timeout: None,
+            max_keep_alive_requests: None,
             socket_timeout: None,
+            request_line_timeout: None,
+            header_timeout: None,
+            body_timeout: None,
+            tcp_nodelay: false,
+            reuse_address: true,
+            reuse_port: false,
+            backlog: None,
+            max_connections: None,
+            max_connections_per_ip: None,
+            connection_rejection: ConnectionRejection::default(),
+            reap_idle_connections: false,
+            max_queue_wait: None,
+            connection_stats: ConnectionStats::default(),
+            max_threads: None,
+            thread_idle_timeout: Duration::from_secs(60),
+            thread_pool: ThreadPoolHandle::default(),
+            shutdown: ShutdownHandle::default(),
+            #[cfg(feature = "signals")]
+            handle_signals: false,
+            tasks: Vec::new(),
+            cookie_key: None,
+            cookie_policy: None,
+            clock: Arc::new(SystemClock),
+            url_config: Arc::default(),
+            web_socket_config: Arc::default(),
+            max_request_line: 8 * 1024,
+            max_header_size: 32 * 1024,
+            max_headers: 100,
+            max_body_size: None,
+            read_buffer_size: 8 * 1024,
+            chunk_size: consts::CHUNK_SIZE,
+            buffer_pool: Arc::new(BufferPool::new(consts::BUFFER_POOL_SIZE)),
+            trailing_slash: TrailingSlash::Ignore,
+            normalize_paths: false,
+            uri_policy: UriPolicy::default(),
+            server_timing: false,
             state: None,
         }
     }
@@ -100,17 +359,59 @@ impl<State: Send + Sync> Server<State> {
     /// server.start().unwrap();
     /// ```
     pub fn start(&self) -> Result<()> {
-        trace!("{}Starting Server [{}:{}]", emoji("✨"), self.ip, self.port);
+        self.start_with_listener(socket::bind(self)?)
+    }
+
+    /// Starts the server without a threadpool, accepting connections from a custom [`Listener`].
+    /// This is blocking.
+    /// The server's ip, port and listener options are ignored, as the listener is already bound.
+    /// See the [`crate::listener`] module for more information.
+    ///
+    /// ## Example
+    /// ```rust,no_run
+    /// # use std::net::TcpListener;
+    /// # use afire::Server;
+    /// let server = Server::<()>::new("localhost", 8080);
+    ///
+    /// /* Define Routes, Attach Middleware, etc. */
+    ///
+    /// // Any type implementing Listener can be used here
+    /// let listener = TcpListener::bind("localhost:8080").unwrap();
+    /// server.start_with_listener(listener).unwrap();
+    /// ```
+    pub fn start_with_listener(&self, listener: impl Listener) -> Result<()> {
+        trace!("{}Starting Server", emoji("✨"));
         self.check()?;
+        self.run_on_start(&listener);
+        let scheduler = Scheduler::start(&self.tasks, None);
+        let reaper = Reaper::start(self);
+        let accepting = accepting();
 
-        let listener = TcpListener::bind(SocketAddr::new(self.ip, self.port))?;
+        loop {
+            let stream = listener.accept();
+            if self.shutdown.is_shutdown() {
+                break;
+            }
 
-        for event in listener.incoming() {
-            handle(event?, self);
+            let stream = match stream {
+                Ok(i) => Box::new(i),
+                Err(e) => {
+                    accept_error(e)?;
+                    continue;
+                }
+            };
+            match reserve_connection(&*stream, self) {
+                Ok(_guard) => handle(stream, self),
+                Err(limit) => reject_connection(stream, limit, self),
+            }
         }
 
-        // We should never get Here
-        unreachable!()
+        drop(accepting);
+        drop(scheduler);
+        drop(reaper);
+        self.run_on_shutdown();
+        trace!("{}Server stopped", emoji("🛑"));
+        Ok(())
     }
 
     /// Start the server with a threadpool of `threads` threads.
@@ -132,8 +433,108 @@ impl<State: Send + Sync> Server<State> {
     /// server.start_threaded(4).unwrap();
     /// ```
     pub fn start_threaded(self, threads: usize) -> Result<()> {
+        let listener = socket::bind(&self)?;
+        self.start_threaded_with_listener(listener, threads)
+    }
+
+    /// Start the server with a threadpool of `threads` threads, accepting connections from a custom [`Listener`].
+    /// Just like [`Server::start_with_listener`], this is blocking and the server's ip, port and listener options are ignored.
+    ///
+    /// ## Example
+    /// ```rust,no_run
+    /// # use std::net::TcpListener;
+    /// # use afire::Server;
+    /// let server = Server::<()>::new("localhost", 8080);
+    ///
+    /// /* Define Routes, Attach Middleware, etc. */
+    ///
+    /// let listener = TcpListener::bind("localhost:8080").unwrap();
+    /// server.start_threaded_with_listener(listener, 4).unwrap();
+    /// ```
+    pub fn start_threaded_with_listener(
+        self,
+        listener: impl Listener,
+        threads: usize,
+    ) -> Result<()> {
+        trace!("{}Starting Server ({} threads)", emoji("✨"), threads);
+        self.check()?;
+        self.run_on_start(&listener);
+
+        let pool = Arc::new(self.new_thread_pool(threads));
+        let scheduler = Scheduler::start(&self.tasks, Some(pool.clone()));
+        let reaper = Reaper::start(&self);
+        let this = Arc::new(self);
+
+        loop {
+            let stream = listener.accept();
+            if this.shutdown.is_shutdown() {
+                break;
+            }
+
+            let stream = match stream {
+                Ok(i) => Box::new(i),
+                Err(e) => {
+                    accept_error(e)?;
+                    continue;
+                }
+            };
+            let guard = match reserve_connection(&*stream, &this) {
+                Ok(i) => i,
+                Err(limit) => {
+                    reject_connection(stream, limit, &this);
+                    continue;
+                }
+            };
+
+            let this = this.clone();
+            let queued = Instant::now();
+            pool.execute(move || {
+                match queue_timed_out(queued, &this) {
+                    true => reject_queued(Arc::new(Mutex::new(stream)), &this),
+                    false => handle(stream, &this),
+                }
+                drop(guard);
+            });
+        }
+
+        // Stop the background tasks and wait for the requests that are being handled to finish
+        // The reaper is stopped last, so connections blocked waiting for a request are still closed while waiting
+        drop(scheduler);
+        drop(pool);
+        drop(reaper);
+        this.run_on_shutdown();
+        trace!("{}Server stopped", emoji("🛑"));
+        Ok(())
+    }
+
+    /// Start the server with a threadpool of `threads` threads, using a non-blocking event loop to manage connections.
+    /// Just like [`Server::start_threaded`], this is blocking.
+    ///
+    /// With [`Server::start_threaded`], each open connection occupies a worker thread, even when its just waiting for its next keep-alive request.
+    /// In event loop mode, idle connections are instead watched with [poll(2)](https://man7.org/linux/man-pages/man2/poll.2.html) and are only given to a worker once they have data to read,
+    /// letting many mostly-idle connections share a small number of threads.
+    /// Note: Streaming connections like server-sent events will still take up a worker thread.
+    ///
+    /// This is currently only available on unix platforms.
+    ///
+    /// ## Example
+    /// ```rust,no_run
+    /// // Import Library
+    /// use afire::{Server, Response, Header, Method};
+    ///
+    /// // Creates a server on localhost (127.0.0.1) port 8080
+    /// let mut server = Server::<()>::new("localhost", 8080);
+    ///
+    /// /* Define Routes, Attach Middleware, etc. */
+    ///
+    /// // Starts the server with 4 worker threads
+    /// // This is blocking
+    /// server.start_event_loop(4).unwrap();
+    /// ```
+    #[cfg(unix)]
+    pub fn start_event_loop(self, threads: usize) -> Result<()> {
         trace!(
-            "{}Starting Server [{}:{}] ({} threads)",
+            "{}Starting Server [{}:{}] ({} threads, event loop)",
             emoji("✨"),
             self.ip,
             self.port,
@@ -141,23 +542,64 @@ impl<State: Send + Sync> Server<State> {
         );
         self.check()?;
 
-        let listener = TcpListener::bind(SocketAddr::new(self.ip, self.port))?;
-        let pool = ThreadPool::new(threads);
-        let this = Arc::new(self);
+        let listener = socket::bind(&self)?;
+        self.run_on_start(&listener);
+        let pool = Arc::new(self.new_thread_pool(threads));
+        let scheduler = Scheduler::start(&self.tasks, Some(pool.clone()));
+        event_loop::run(listener, Arc::new(self), pool, scheduler)
+    }
 
-        for event in listener.incoming() {
-            let this = this.clone();
-            pool.execute(move || handle(event.unwrap(), &this));
+    /// Runs the [`Server::on_start`] callback, once the listener has been bound.
+    /// Also keeps the listener's address and waker, so [`ShutdownHandle::shutdown`] can wake up the accept loop.
+    fn run_on_start(&self, listener: &impl Listener) {
+        let addr = listener.local_addr().ok();
+        #[cfg(feature = "tracing")]
+        if let Some(addr) = addr {
+            trace!("{}Listening on {}", emoji("👂"), addr);
+        }
+        *self.shutdown.0.addr.force_lock() = addr;
+
+        match listener.waker() {
+            Ok(waker) => *self.shutdown.0.waker.force_lock() = Some(waker),
+            Err(_e) => {
+                trace!(
+                    Level::Error,
+                    "Listener can't be woken up, the server will stop after the next connection once it is shut down: {}",
+                    _e
+                );
+            }
+        }
+
+        #[cfg(feature = "signals")]
+        if self.handle_signals {
+            crate::internal::signal::register(self.shutdown.clone());
+        }
+
+        if let (Some(on_start), Some(addr)) = (&self.on_start, addr) {
+            on_start(addr);
+        }
+    }
+
+    /// Runs the [`Server::on_shutdown`] hooks, once the server has stopped and the requests in progress have finished.
+    pub(crate) fn run_on_shutdown(&self) {
+        for hook in &self.on_shutdown {
+            hook();
         }
+    }
 
-        // We should never get Here
-        unreachable!()
+    /// Creates the thread pool used by [`Server::start_threaded`] and [`Server::start_event_loop`].
+    fn new_thread_pool(&self, threads: usize) -> ThreadPool {
+        ThreadPool::new(
+            threads,
+            self.max_threads.unwrap_or(threads),
+            self.thread_idle_timeout,
+            self.thread_pool.clone(),
+        )
     }
 
     /// Add a new default header to the server.
     /// This will be added to every response if it is not already present.
-    ///
-    /// This will be added to every response
+    /// Any existing default header with the same name (like the default `Server` header) is replaced.
     /// ## Example
     /// ```rust
     /// # use afire::{Server, Header};
@@ -170,14 +612,97 @@ impl<State: Send + Sync> Server<State> {
         let mut headers = self.default_headers;
         let header = Header::new(key, value);
         trace!("{}Adding Server Header ({})", emoji("😀"), header);
-        headers.push(header);
+        headers.insert(header.name, header.value);
 
         Server {
             default_headers: headers,
+            default_header_lines: OnceLock::new(),
+            ..self
+        }
+    }
+
+    /// Sets how responses with more than one header of the same name are sent.
+    /// By default ([`HeaderPolicy::Replace`]) only the last value of headers that can only be sent once, like `Content-Type`, is sent.
+    /// This can be overridden for a single response with [`Response::header_policy`].
+    /// ## Example
+    /// ```rust
+    /// # use afire::{Server, header::HeaderPolicy};
+    /// // Send every header as it was added
+    /// let server = Server::<()>::new("localhost", 8080)
+    ///     .header_policy(HeaderPolicy::Append);
+    /// ```
+    pub fn header_policy(self, header_policy: HeaderPolicy) -> Self {
+        trace!(
+            "{}Setting Header policy to {:?}",
+            emoji("😀"),
+            header_policy
+        );
+
+        Server {
+            header_policy,
             ..self
         }
     }
 
+    /// Sets the MIME type used for files with the given extension, overriding the builtin type if there is one.
+    /// This is used by [`Response::file`], [`crate::Download::file`] and the ServeStatic extension to set the `Content-Type` of files.
+    /// ## Example
+    /// ```rust
+    /// # use afire::Server;
+    /// let mut server = Server::<()>::new("localhost", 8080)
+    ///     .mime("wasm", "application/wasm")
+    ///     // Override the builtin `text/x-typescript`
+    ///     .mime("ts", "video/mp2t");
+    /// ```
+    pub fn mime(mut self, extension: impl AsRef<str>, mime_type: impl AsRef<str>) -> Self {
+        trace!(
+            "{}Adding MIME type ({} => {})",
+            emoji("📄"),
+            extension.as_ref(),
+            mime_type.as_ref()
+        );
+        self.mime_types.insert(extension, mime_type);
+        self
+    }
+
+    /// Sets many MIME types at once, like with [`Server::mime`].
+    /// ## Example
+    /// ```rust
+    /// # use afire::Server;
+    /// let mut server = Server::<()>::new("localhost", 8080)
+    ///     .mimes([("wasm", "application/wasm"), ("webmanifest", "application/manifest+json")]);
+    /// ```
+    pub fn mimes(
+        mut self,
+        types: impl IntoIterator<Item = (impl AsRef<str>, impl AsRef<str>)>,
+    ) -> Self {
+        trace!("{}Adding MIME types", emoji("📄"));
+        self.mime_types.extend(types);
+        self
+    }
+
+    /// Gets the MIME types used by the server, with the builtin types and any added with [`Server::mime`].
+    pub fn mime_types(&self) -> &MimeTypes {
+        &self.mime_types
+    }
+
+    /// Gets the routes compiled into a tree, for finding the route that matches a request.
+    /// The tree is built the first time this is called, and again after routes are added with [`Server::route`] and friends.
+    pub(crate) fn router(&self) -> &Router {
+        self.router.get_or_init(|| Router::new(&self.routes))
+    }
+
+    /// Gets the default headers formatted as `name: value\r\n` lines, so they don't have to be formatted again for every response.
+    /// They are formatted the first time this is called, so changes to [`Server::default_headers`] after the server has started are ignored.
+    pub(crate) fn default_header_lines(&self) -> &[(HeaderType, String)] {
+        self.default_header_lines.get_or_init(|| {
+            self.default_headers
+                .iter()
+                .map(|i| (i.name.clone(), format!("{i}\r\n")))
+                .collect()
+        })
+    }
+
     /// Set the timeout for the socket.
     /// This will ensure that the server will not hang on a request for too long.
     /// By default there is no timeout.
@@ -204,126 +729,1110 @@ impl<State: Send + Sync> Server<State> {
         }
     }
 
-    /// Set the keep alive state of the server.
-    /// This will determine if the server will keep the connection alive after a request.
-    /// By default this is true.
-    /// If you aren't using a threadpool, you may want to set this to false.
+    /// Set `TCP_NODELAY` on accepted sockets.
+    /// This disables Nagle's algorithm, so small writes are sent right away instead of being buffered, which lowers latency.
+    /// By default this is false.
     /// ## Example
     /// ```rust
     /// # use afire::Server;
     /// // Create a server for localhost on port 8080
     /// let mut server = Server::<()>::new("localhost", 8080)
-    ///     // Disable Keep Alive
-    ///     .keep_alive(false);
+    ///     // Disable Nagle's algorithm
+    ///     .tcp_nodelay(true);
     /// ```
-    pub fn keep_alive(self, keep_alive: bool) -> Self {
-        trace!("{}Setting Keep Alive to {}", emoji("🔁"), keep_alive);
+    pub fn tcp_nodelay(self, tcp_nodelay: bool) -> Self {
+        trace!("{}Setting TCP_NODELAY to {}", emoji("🔌"), tcp_nodelay);
 
-        Server { keep_alive, ..self }
+        Server {
+            tcp_nodelay,
+            ..self
+        }
     }
 
-    /// Set the state of a server.
-    /// The state will be available to stateful routes ([`Server::stateful_route`]) and middleware.
-    /// It is not mutable, so you will need to use an atomic or sync type to mutate it.
+    /// Set `SO_REUSEADDR` on the listener.
+    /// This lets the server bind to its address again right after restarting, even if old connections are still in `TIME_WAIT`.
+    /// By default this is true.
     ///
+    /// Note: Listener options are currently only supported on Linux and Android, they are ignored on other platforms.
     /// ## Example
-    /// ```rust,no_run
-    /// # use afire::{Server, Response, Method};
-    /// # use std::sync::atomic::{AtomicU32, Ordering};
+    /// ```rust
+    /// # use afire::Server;
     /// // Create a server for localhost on port 8080
-    /// // Note: We can omit the type parameter here because we are setting the state
-    /// let mut server = Server::new("localhost", 8080)
-    ///     // Set server wide state
-    ///     .state(AtomicU32::new(0));
-    ///
-    /// // Add a stateful route to increment the state
-    /// server.stateful_route(Method::GET, "/", |state, _req| {
-    ///     Response::new().text(state.fetch_add(1, Ordering::Relaxed))
-    /// });
+    /// let mut server = Server::<()>::new("localhost", 8080)
+    ///     .reuse_address(false);
     /// ```
-    pub fn state(self, state: State) -> Self {
-        trace!(
-            "{}Setting Server State [{}]",
-            emoji("📦️"),
-            type_name::<State>()
-        );
+    pub fn reuse_address(self, reuse_address: bool) -> Self {
+        trace!("{}Setting SO_REUSEADDR to {}", emoji("🔌"), reuse_address);
 
-        Self {
-            state: Some(Arc::new(state)),
+        Server {
+            reuse_address,
             ..self
         }
     }
 
-    /// Set the panic handler, which is called if a route or middleware panics.
-    /// This is only available if the `panic_handler` feature is enabled.
-    /// If you don't set it, the default response is 500 "Internal Server Error :/".
-    /// Be sure that your panic handler wont panic, because that will just panic the whole application.
+    /// Set `SO_REUSEPORT` on the listener.
+    /// This lets multiple processes bind to the same address, with the kernel balancing connections between them.
+    /// By default this is false.
+    ///
+    /// Note: Listener options are currently only supported on Linux and Android, they are ignored on other platforms.
     /// ## Example
     /// ```rust
-    /// # use afire::{Server, Response, Status};
-    /// # let mut server = Server::<()>::new("localhost", 8080);
-    /// // Set the panic handler response
-    /// server.error_handler(|_state, _req, err| {
-    ///     Response::new()
-    ///         .status(Status::InternalServerError)
-    ///         .text(format!("Internal Server Error: {}", err))
-    /// });
+    /// # use afire::Server;
+    /// // Create a server for localhost on port 8080
+    /// let mut server = Server::<()>::new("localhost", 8080)
+    ///     .reuse_port(true);
     /// ```
-    pub fn error_handler(
-        &mut self,
-        res: impl Fn(Option<Arc<State>>, &Box<Result<Rc<Request>>>, String) -> Response
-            + Send
-            + Sync
-            + 'static,
-    ) {
-        trace!("{}Setting Error Handler", emoji("✌"));
+    pub fn reuse_port(self, reuse_port: bool) -> Self {
+        trace!("{}Setting SO_REUSEPORT to {}", emoji("🔌"), reuse_port);
 
-        self.error_handler = Box::new(res);
+        Server { reuse_port, ..self }
     }
 
-    /// Create a new route.
-    /// The path can contain parameters, which are defined with `{...}`, as well as wildcards, which are defined with `*`.
-    /// (`**` lets you math anything after the wildcard, including `/`)
+    /// Set the size of the listener's accept backlog.
+    /// This is the number of pending connections that the OS will queue before refusing new ones.
+    /// By default this is 128.
+    ///
+    /// Note: Listener options are currently only supported on Linux and Android, they are ignored on other platforms.
     /// ## Example
     /// ```rust
-    /// # use afire::{Server, Response, Header, Method, Content};
-    /// # let mut server = Server::<()>::new("localhost", 8080);
-    /// // Define a route
-    /// server.route(Method::GET, "/greet/{name}", |req| {
-    ///     let name = req.param("name").unwrap();
-    ///
-    ///     Response::new()
-    ///         .text(format!("Hello, {}!", name))
-    ///         .content(Content::TXT)
-    /// });
+    /// # use afire::Server;
+    /// // Create a server for localhost on port 8080
+    /// let mut server = Server::<()>::new("localhost", 8080)
+    ///     // Queue up to 1024 pending connections
+    ///     .backlog(1024);
     /// ```
-    pub fn route(
-        &mut self,
-        method: Method,
-        path: impl AsRef<str>,
-        handler: impl Fn(&Request) -> Response + Send + Sync + 'static,
-    ) -> &mut Self {
-        let path = path.as_ref().to_owned();
-        trace!("{}Adding Route {} {}", emoji("🚗"), method, path);
+    pub fn backlog(self, backlog: u32) -> Self {
+        trace!("{}Setting Backlog to {}", emoji("🔌"), backlog);
 
-        self.routes
-            .push(Route::new(method, path, Box::new(handler)));
-        self
+        Server {
+            backlog: Some(backlog),
+            ..self
+        }
     }
 
-    /// Create a new stateful route.
-    /// Is the same as [`Server::route`], but the state is passed as the first parameter.
-    /// (See [`Server::state`])
-    ///
-    /// Note: If you add a stateful route, you must also set the state or starting the sever will return an error.
+    /// Set the maximum number of connections that can be open at once.
+    /// Once this limit is reached, new connections will immediately be sent a `503 Service Unavailable` response and closed,
+    /// instead of waiting in the thread pool's queue.
+    /// This keeps latency down for the connections that are being handled when the server is overloaded.
+    /// By default there is no limit.
     /// ## Example
     /// ```rust
-    /// # use afire::{Server, Response, Header, Method};
+    /// # use afire::Server;
     /// // Create a server for localhost on port 8080
-    /// let mut server = Server::<u32>::new("localhost", 8080)
-    ///    .state(101);
-    ///
-    /// // Define a route
+    /// let mut server = Server::<()>::new("localhost", 8080)
+    ///     // Allow at most 1000 open connections
+    ///     .max_connections(1000);
+    /// ```
+    pub fn max_connections(self, max_connections: usize) -> Self {
+        trace!(
+            "{}Setting Max connections to {}",
+            emoji("🚦"),
+            max_connections
+        );
+
+        Server {
+            max_connections: Some(max_connections),
+            ..self
+        }
+    }
+
+    /// Set the maximum number of connections that can be open at once from a single IP address.
+    /// Once a client has this many connections open, its new connections are sent a `429 Too Many Requests` response and closed before any of their requests are read.
+    /// Unlike the [`crate::extension::RateLimiter`], which counts requests, this stops a single client from using up all the server's connections (or threads) with slow or idle connections.
+    ///
+    /// Behind a reverse proxy all connections come from the proxy's address, so this should only be used on servers that clients connect to directly.
+    /// By default there is no limit.
+    /// ## Example
+    /// ```rust
+    /// # use afire::Server;
+    /// // Create a server for localhost on port 8080
+    /// let mut server = Server::<()>::new("localhost", 8080)
+    ///     // Allow at most 20 open connections from each client
+    ///     .max_connections_per_ip(20);
+    /// ```
+    pub fn max_connections_per_ip(self, max_connections_per_ip: usize) -> Self {
+        trace!(
+            "{}Setting Max connections per IP to {}",
+            emoji("🚦"),
+            max_connections_per_ip
+        );
+
+        Server {
+            max_connections_per_ip: Some(max_connections_per_ip),
+            ..self
+        }
+    }
+
+    /// Set what is done with connections over [`Server::max_connections`] or [`Server::max_connections_per_ip`].
+    /// By default they are sent an error response, but they can also be closed without one, which uses less resources when the server is being flooded.
+    /// ## Example
+    /// ```rust
+    /// # use afire::{Server, ConnectionRejection};
+    /// // Create a server for localhost on port 8080
+    /// let mut server = Server::<()>::new("localhost", 8080)
+    ///     .max_connections_per_ip(20)
+    ///     // Close extra connections right away
+    ///     .connection_rejection(ConnectionRejection::Close);
+    /// ```
+    pub fn connection_rejection(self, connection_rejection: ConnectionRejection) -> Self {
+        trace!(
+            "{}Setting Connection rejection to {:?}",
+            emoji("🚦"),
+            connection_rejection
+        );
+
+        Server {
+            connection_rejection,
+            ..self
+        }
+    }
+
+    /// Sets weather idle connections are closed by a background thread, even while the thread handling them is blocked reading from the socket.
+    /// Connections are idle from when they are opened or their last response is sent until the head of their next request is received, and are closed once they have been idle for longer than the [`Server::keep_alive_timeout`] (or [`Server::socket_timeout`] if it isn't set).
+    ///
+    /// This catches clients that open connections without sending anything and keep-alive connections on streams that don't support [`crate::listener::Stream::peek`], like most TLS streams.
+    /// Only streams that implement [`crate::listener::Stream::try_clone`] can be reaped, and the reaper doesn't run if neither timeout is set.
+    /// Connections handled by [`Server::start_event_loop`] are already watched by the event loop while they are idle, so they aren't tracked.
+    ///
+    /// The number of idle and reaped connections can be read from [`Server::connection_stats`].
+    /// ## Example
+    /// ```rust
+    /// # use std::time::Duration;
+    /// # use afire::Server;
+    /// let mut server = Server::<()>::new("localhost", 8080)
+    ///     .keep_alive_timeout(Duration::from_secs(30))
+    ///     .reap_idle_connections(true);
+    /// ```
+    pub fn reap_idle_connections(self, reap_idle_connections: bool) -> Self {
+        trace!(
+            "{}Setting idle connection reaping to {}",
+            emoji("🧹"),
+            reap_idle_connections
+        );
+
+        Server {
+            reap_idle_connections,
+            ..self
+        }
+    }
+
+    /// Set the longest a connection can wait for a worker when all of the thread pool's workers are busy.
+    /// Connections that wait longer are sent a `503 Service Unavailable` response and closed, instead of being handled late when the client may have already given up.
+    /// The response has a `Retry-After` header with the wait time, rounded up to a whole second.
+    /// By default connections wait as long as it takes.
+    ///
+    /// This only applies to servers started with [`Server::start_threaded`] or [`Server::start_event_loop`].
+    /// ## Example
+    /// ```rust
+    /// # use afire::Server;
+    /// # use std::time::Duration;
+    /// // Create a server for localhost on port 8080
+    /// let mut server = Server::<()>::new("localhost", 8080)
+    ///     // Reject connections that wait more than 2 seconds for a worker
+    ///     .max_queue_wait(Duration::from_secs(2));
+    /// ```
+    pub fn max_queue_wait(self, max_queue_wait: Duration) -> Self {
+        trace!(
+            "{}Setting Max queue wait to {:?}",
+            emoji("🚦"),
+            max_queue_wait
+        );
+
+        Server {
+            max_queue_wait: Some(max_queue_wait),
+            ..self
+        }
+    }
+
+    /// Set the keep alive state of the server.
+    /// This will determine if the server will keep the connection alive after a request.
+    /// By default this is true.
+    /// If you aren't using a threadpool, you may want to set this to false.
+    /// ## Example
+    /// ```rust
+    /// # use afire::Server;
+    /// // Create a server for localhost on port 8080
+    /// let mut server = Server::<()>::new("localhost", 8080)
+    ///     // Disable Keep Alive
+    ///     .keep_alive(false);
+    /// ```
+    pub fn keep_alive(self, keep_alive: bool) -> Self {
+        trace!("{}Setting Keep Alive to {}", emoji("🔁"), keep_alive);
+
+        Server { keep_alive, ..self }
+    }
+
+    /// Set how long an idle keep-alive connection will be held open while waiting for another request.
+    /// Once the timeout is reached without receiving any data, the connection will be closed.
+    /// The policy is advertised to clients with the `Keep-Alive` header.
+    /// By default there is no timeout.
+    /// ## Example
+    /// ```rust
+    /// # use std::time::Duration;
+    /// # use afire::Server;
+    /// // Create a server for localhost on port 8080
+    /// let mut server = Server::<()>::new("localhost", 8080)
+    ///     // Close idle connections after 5 seconds
+    ///     .keep_alive_timeout(Duration::from_secs(5));
+    /// ```
+    pub fn keep_alive_timeout(self, keep_alive_timeout: Duration) -> Self {
+        trace!(
+            "{}Setting Keep Alive timeout to {:?}",
+            emoji("⏳"),
+            keep_alive_timeout
+        );
+
+        Server {
+            keep_alive_timeout: Some(keep_alive_timeout),
+            ..self
+        }
+    }
+
+    /// Set the maximum number of requests that can be handled on a single keep-alive connection.
+    /// After this many requests, the connection will be closed once the response has been sent.
+    /// The policy is advertised to clients with the `Keep-Alive` header.
+    /// By default there is no limit.
+    /// ## Example
+    /// ```rust
+    /// # use afire::Server;
+    /// // Create a server for localhost on port 8080
+    /// let mut server = Server::<()>::new("localhost", 8080)
+    ///     // Only handle 100 requests per connection
+    ///     .max_keep_alive_requests(100);
+    /// ```
+    pub fn max_keep_alive_requests(self, max_keep_alive_requests: usize) -> Self {
+        trace!(
+            "{}Setting Max Keep Alive requests to {}",
+            emoji("🔁"),
+            max_keep_alive_requests
+        );
+
+        Server {
+            max_keep_alive_requests: Some(max_keep_alive_requests),
+            ..self
+        }
+    }
+
+    /// Set how long a client has to send the request line.
+    /// Unlike [`Server::socket_timeout`], which limits how long a single read can take, this limits the total time, so clients can't hold a worker thread by sending one byte at a time ([Slowloris](https://en.wikipedia.org/wiki/Slowloris_(computer_security))).
+    /// On keep-alive connections without a [`Server::keep_alive_timeout`], this also includes the time spent waiting for the next request.
+    /// Connections that take longer are sent a `408 Request Timeout` response and closed.
+    /// By default there is no timeout.
+    /// ## Example
+    /// ```rust
+    /// # use std::time::Duration;
+    /// # use afire::Server;
+    /// // Create a server for localhost on port 8080
+    /// let mut server = Server::<()>::new("localhost", 8080)
+    ///     .request_line_timeout(Duration::from_secs(10))
+    ///     .header_timeout(Duration::from_secs(10))
+    ///     .body_timeout(Duration::from_secs(30));
+    /// ```
+    pub fn request_line_timeout(self, request_line_timeout: Duration) -> Self {
+        trace!(
+            "{}Setting Request line timeout to {:?}",
+            emoji("⏳"),
+            request_line_timeout
+        );
+
+        Server {
+            request_line_timeout: Some(request_line_timeout),
+            ..self
+        }
+    }
+
+    /// Set how long a client has to send all the headers, after the request line.
+    /// Connections that take longer are sent a `408 Request Timeout` response and closed.
+    /// See [`Server::request_line_timeout`] for more information.
+    /// By default there is no timeout.
+    pub fn header_timeout(self, header_timeout: Duration) -> Self {
+        trace!(
+            "{}Setting Header timeout to {:?}",
+            emoji("⏳"),
+            header_timeout
+        );
+
+        Server {
+            header_timeout: Some(header_timeout),
+            ..self
+        }
+    }
+
+    /// Set how long a client has to send the body, after the headers.
+    /// Connections that take longer are sent a `408 Request Timeout` response and closed.
+    /// See [`Server::request_line_timeout`] for more information.
+    /// By default there is no timeout.
+    pub fn body_timeout(self, body_timeout: Duration) -> Self {
+        trace!("{}Setting Body timeout to {:?}", emoji("⏳"), body_timeout);
+
+        Server {
+            body_timeout: Some(body_timeout),
+            ..self
+        }
+    }
+
+    /// Set the maximum length of a request line (method, path and version) in bytes.
+    /// Requests with a longer request line are sent a `414 URI Too Long` response and the connection is closed.
+    /// By default this is 8 KiB.
+    /// ## Example
+    /// ```rust
+    /// # use afire::Server;
+    /// // Create a server for localhost on port 8080
+    /// let mut server = Server::<()>::new("localhost", 8080)
+    ///     .max_request_line(4 * 1024);
+    /// ```
+    pub fn max_request_line(self, max_request_line: usize) -> Self {
+        trace!(
+            "{}Setting Max request line to {}",
+            emoji("📏"),
+            max_request_line
+        );
+
+        Server {
+            max_request_line,
+            ..self
+        }
+    }
+
+    /// Set the maximum total size of a request's headers in bytes.
+    /// Requests with larger headers are sent a `431 Request Header Fields Too Large` response and the connection is closed.
+    /// By default this is 32 KiB.
+    /// ## Example
+    /// ```rust
+    /// # use afire::Server;
+    /// // Create a server for localhost on port 8080
+    /// let mut server = Server::<()>::new("localhost", 8080)
+    ///     .max_header_size(16 * 1024);
+    /// ```
+    pub fn max_header_size(self, max_header_size: usize) -> Self {
+        trace!(
+            "{}Setting Max header size to {}",
+            emoji("📏"),
+            max_header_size
+        );
+
+        Server {
+            max_header_size,
+            ..self
+        }
+    }
+
+    /// Set the maximum number of headers a request can have.
+    /// Requests with more headers are sent a `431 Request Header Fields Too Large` response and the connection is closed.
+    /// By default this is 100.
+    /// ## Example
+    /// ```rust
+    /// # use afire::Server;
+    /// // Create a server for localhost on port 8080
+    /// let mut server = Server::<()>::new("localhost", 8080)
+    ///     .max_headers(50);
+    /// ```
+    pub fn max_headers(self, max_headers: usize) -> Self {
+        trace!("{}Setting Max headers to {}", emoji("📏"), max_headers);
+
+        Server {
+            max_headers,
+            ..self
+        }
+    }
+
+    /// Set the maximum size of a request's body in bytes.
+    /// Requests with a larger `Content-Length` are sent a `413 Payload Too Large` response and the connection is closed, without reading the body.
+    /// Routes that need a different limit (like file uploads) can override it with [`Server::route_max_body_size`].
+    /// By default there is no limit.
+    /// ## Example
+    /// ```rust
+    /// # use afire::Server;
+    /// // Create a server for localhost on port 8080
+    /// let mut server = Server::<()>::new("localhost", 8080)
+    ///     .max_body_size(1024 * 1024);
+    /// ```
+    pub fn max_body_size(self, max_body_size: usize) -> Self {
+        trace!("{}Setting Max body size to {}", emoji("📏"), max_body_size);
+
+        Server {
+            max_body_size: Some(max_body_size),
+            ..self
+        }
+    }
+
+    /// Set the size of the buffer used to read requests.
+    /// Larger buffers mean fewer reads for requests with large headers, at the cost of more memory per connection.
+    /// By default this is 8 KiB.
+    /// ## Example
+    /// ```rust
+    /// # use afire::Server;
+    /// // Create a server for localhost on port 8080
+    /// let mut server = Server::<()>::new("localhost", 8080)
+    ///     .read_buffer_size(16 * 1024);
+    /// ```
+    pub fn read_buffer_size(self, read_buffer_size: usize) -> Self {
+        trace!(
+            "{}Setting Read buffer size to {}",
+            emoji("📏"),
+            read_buffer_size
+        );
+
+        Server {
+            read_buffer_size,
+            ..self
+        }
+    }
+
+    /// Set the maximum size of each chunk when streaming a response body (like with [`Response::stream`] or [`Response::file`]).
+    /// By default this is 16 KiB.
+    /// ## Example
+    /// ```rust
+    /// # use afire::Server;
+    /// // Create a server for localhost on port 8080
+    /// let mut server = Server::<()>::new("localhost", 8080)
+    ///     .chunk_size(64 * 1024);
+    /// ```
+    pub fn chunk_size(self, chunk_size: usize) -> Self {
+        trace!("{}Setting Chunk size to {}", emoji("📏"), chunk_size);
+
+        Server { chunk_size, ..self }
+    }
+
+    /// Set the number of unused read and chunk buffers that are kept to be reused by later requests, instead of allocating new ones for every request.
+    /// Set this to 0 to disable the pool.
+    /// By default this is 64.
+    /// ## Example
+    /// ```rust
+    /// # use afire::Server;
+    /// // Create a server for localhost on port 8080
+    /// let mut server = Server::<()>::new("localhost", 8080)
+    ///     .buffer_pool_size(256);
+    /// ```
+    pub fn buffer_pool_size(self, buffer_pool_size: usize) -> Self {
+        trace!(
+            "{}Setting Buffer pool size to {}",
+            emoji("📏"),
+            buffer_pool_size
+        );
+
+        Server {
+            buffer_pool: Arc::new(BufferPool::new(buffer_pool_size)),
+            ..self
+        }
+    }
+
+    /// Set how requests with or without a trailing slash are handled.
+    /// By default ([`TrailingSlash::Ignore`]) `/foo` and `/foo/` are treated as the same path.
+    /// With [`TrailingSlash::Trim`] or [`TrailingSlash::Append`], requests are redirected to the path without or with the slash before they reach any middleware or routes.
+    /// `GET` and `HEAD` requests are redirected with a `301 Moved Permanently`, other methods with a `308 Permanent Redirect` so clients resend the body.
+    /// ## Example
+    /// ```rust
+    /// # use afire::{Server, TrailingSlash};
+    /// // Redirect `/foo/` to `/foo`
+    /// let mut server = Server::<()>::new("localhost", 8080)
+    ///     .trailing_slash(TrailingSlash::Trim);
+    /// ```
+    pub fn trailing_slash(self, trailing_slash: TrailingSlash) -> Self {
+        trace!(
+            "{}Setting Trailing slash to {:?}",
+            emoji("🔗"),
+            trailing_slash
+        );
+
+        Server {
+            trailing_slash,
+            ..self
+        }
+    }
+
+    /// Set if `.` and `..` segments are resolved before requests are handled, so `/a/./b/../c` is routed as `/a/c`.
+    /// `..` segments can't go above the root, and the request's path is replaced with the resolved path, so middleware sees it too.
+    /// Duplicate slashes are always collapsed.
+    /// By default this is disabled.
+    /// ## Example
+    /// ```rust
+    /// # use afire::Server;
+    /// let mut server = Server::<()>::new("localhost", 8080)
+    ///     .normalize_paths(true);
+    /// ```
+    pub fn normalize_paths(self, normalize_paths: bool) -> Self {
+        trace!(
+            "{}Setting Normalize paths to {}",
+            emoji("🔗"),
+            normalize_paths
+        );
+
+        Server {
+            normalize_paths,
+            ..self
+        }
+    }
+
+    /// Sets how the targets (path and query) of requests are checked and normalized.
+    /// By default targets can be up to 8 KiB, invalid UTF-8 in paths is handled lossily and percent encoding isn't normalized, see [`UriPolicy`] for the options.
+    /// ## Example
+    /// ```rust
+    /// # use afire::{Server, UriPolicy};
+    /// let mut server = Server::<()>::new("localhost", 8080).uri_policy(UriPolicy {
+    ///     max_length: 2 * 1024,
+    ///     reject_invalid_utf8: true,
+    ///     ..UriPolicy::default()
+    /// });
+    /// ```
+    pub fn uri_policy(self, uri_policy: UriPolicy) -> Self {
+        trace!("{}Setting URI policy to {:?}", emoji("🔗"), uri_policy);
+
+        Server { uri_policy, ..self }
+    }
+
+    /// Set if the timings of each request (see [`crate::Request::timing`]) are sent to the client in a `Server-Timing` header.
+    /// Browser developer tools show these next to the request, which helps with finding where a slow request spent its time.
+    /// The header includes every phase up to the `post` middleware, as the response is written after it is sent.
+    /// By default this is disabled, as it tells clients how long requests take to handle.
+    /// ## Example
+    /// ```rust
+    /// # use afire::Server;
+    /// let mut server = Server::<()>::new("localhost", 8080)
+    ///     .server_timing(cfg!(debug_assertions));
+    /// ```
+    pub fn server_timing(self, server_timing: bool) -> Self {
+        trace!("{}Setting Server timing to {}", emoji("⏱"), server_timing);
+
+        Server {
+            server_timing,
+            ..self
+        }
+    }
+
+    /// Set the maximum number of worker threads the thread pool can grow to.
+    /// When all workers are busy, extra workers will be spawned (up to this limit) instead of leaving requests waiting in the queue.
+    /// Extra workers are stopped again once they have been idle for the [`Server::thread_idle_timeout`].
+    /// By default the pool does not grow.
+    /// ## Example
+    /// ```rust
+    /// # use afire::Server;
+    /// // Create a server for localhost on port 8080
+    /// let mut server = Server::<()>::new("localhost", 8080)
+    ///     // Let the pool grow up to 32 threads
+    ///     .max_threads(32);
+    /// ```
+    pub fn max_threads(self, max_threads: usize) -> Self {
+        trace!("{}Setting Max threads to {}", emoji("🧵"), max_threads);
+
+        Server {
+            max_threads: Some(max_threads),
+            ..self
+        }
+    }
+
+    /// Set how long extra worker threads can be idle before they are stopped.
+    /// This only applies to workers spawned because of [`Server::max_threads`], the initial workers are always kept alive.
+    /// By default this is 60 seconds.
+    /// ## Example
+    /// ```rust
+    /// # use std::time::Duration;
+    /// # use afire::Server;
+    /// // Create a server for localhost on port 8080
+    /// let mut server = Server::<()>::new("localhost", 8080)
+    ///     .max_threads(32)
+    ///     // Stop extra threads after 10 seconds of inactivity
+    ///     .thread_idle_timeout(Duration::from_secs(10));
+    /// ```
+    pub fn thread_idle_timeout(self, thread_idle_timeout: Duration) -> Self {
+        trace!(
+            "{}Setting Thread idle timeout to {:?}",
+            emoji("⏳"),
+            thread_idle_timeout
+        );
+
+        Server {
+            thread_idle_timeout,
+            ..self
+        }
+    }
+
+    /// Gets a handle to monitor the server's thread pool, with its queue depth, busy workers and the number of jobs handled.
+    /// This can be called before the server is started, and the handle will be updated once it is running.
+    /// See [`ThreadPoolHandle`] for an example.
+    pub fn thread_pool(&self) -> ThreadPoolHandle {
+        self.thread_pool.clone()
+    }
+
+    /// Gets a handle to the thread pool's counters.
+    #[deprecated(note = "Renamed to Server::thread_pool")]
+    pub fn thread_pool_stats(&self) -> ThreadPoolHandle {
+        self.thread_pool()
+    }
+
+    /// Gets a handle to the server's connection counters, with the number of open connections and the ones closed before sending anything.
+    /// Like [`Server::thread_pool`], this can be called before the server is started.
+    /// See [`ConnectionStats`] for an example.
+    pub fn connection_stats(&self) -> ConnectionStats {
+        self.connection_stats.clone()
+    }
+
+    /// Gets a handle to a table of routes that can be added and removed while the server is running.
+    /// See [`RouteTable`] for an example.
+    pub fn route_table(&self) -> RouteTable<State> {
+        self.route_table.clone()
+    }
+
+    /// Gets a handle that can be used to shut down the server once it is running, from any thread.
+    /// See [`ShutdownHandle`] for an example.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Gets a token that can be used to find out when the server is shutting down, without being able to shut it down.
+    /// See [`ShutdownToken`] for an example.
+    pub fn shutdown_token(&self) -> ShutdownToken {
+        self.shutdown.token()
+    }
+
+    /// Gracefully shut down the server when the process receives SIGINT (Ctrl-C) or SIGTERM (like from `docker stop`), instead of being killed in the middle of requests.
+    /// Just like [`ShutdownHandle::shutdown`], the server stops accepting connections, finishes the requests in progress and runs the [`Server::on_shutdown`] hooks before `start` returns.
+    /// A second signal exits the process immediately.
+    ///
+    /// This requires the `signals` feature and is currently only supported on unix platforms.
+    /// ## Example
+    /// ```rust
+    /// # use afire::Server;
+    /// let mut server = Server::<()>::new("localhost", 8080).handle_signals(true);
+    /// server.on_shutdown(|| println!("Shutting down"));
+    /// ```
+    #[cfg(feature = "signals")]
+    pub fn handle_signals(self, handle_signals: bool) -> Self {
+        trace!(
+            "{}Setting Signal Handling to {}",
+            emoji("🛑"),
+            handle_signals
+        );
+
+        Server {
+            handle_signals,
+            ..self
+        }
+    }
+
+    /// Set the key used to sign cookies.
+    /// Cookies signed with [`crate::cookie::CookieJar::sign`] can be read back with [`crate::cookie::CookieJar::get_signed`], which checks that the client has not changed them.
+    /// The key should be long (at least 32 bytes), random and kept secret, if it is changed all existing signed cookies become invalid.
+    /// ## Example
+    /// ```rust
+    /// # use afire::Server;
+    /// // Create a server for localhost on port 8080
+    /// let mut server = Server::<()>::new("localhost", 8080)
+    ///     .cookie_key(b"a very secret key that is at least 32 bytes long");
+    /// ```
+    pub fn cookie_key(self, key: impl AsRef<[u8]>) -> Self {
+        trace!("{}Setting Cookie Key", emoji("🍪"));
+
+        Server {
+            cookie_key: Some(Arc::from(key.as_ref())),
+            ..self
+        }
+    }
+
+    /// Sets the attributes added to every cookie set with [`Response::cookie`].
+    /// [`CookiePolicy::default`] makes cookies Secure and HttpOnly, and SameSite=Lax unless they have their own SameSite value.
+    /// Cookies can opt out with [`crate::SetCookie::use_policy`].
+    /// By default no attributes are added.
+    /// ## Example
+    /// ```rust
+    /// # use afire::{Server, cookie::CookiePolicy};
+    /// let server = Server::<()>::new("localhost", 8080)
+    ///     .cookie_policy(CookiePolicy::default());
+    /// ```
+    pub fn cookie_policy(self, cookie_policy: CookiePolicy) -> Self {
+        trace!("{}Setting Cookie Policy", emoji("🍪"));
+
+        Server {
+            cookie_policy: Some(cookie_policy),
+            ..self
+        }
+    }
+
+    /// Set the clock the server gets the current time from.
+    /// It is used for the `Date` header and keep-alive timeouts, and is available to middleware and routes with [`Request::clock`].
+    /// This defaults to the [`SystemClock`], see the [`crate::clock`] module for more info.
+    /// ## Example
+    /// ```rust
+    /// # use std::{sync::Arc, time::UNIX_EPOCH};
+    /// # use afire::{clock::ManualClock, Server};
+    /// let clock = Arc::new(ManualClock::new(UNIX_EPOCH));
+    /// let server = Server::<()>::new("localhost", 8080).clock(clock.clone());
+    /// ```
+    pub fn clock(self, clock: impl Clock + 'static) -> Self {
+        trace!("{}Setting Clock", emoji("🕐"));
+
+        Server {
+            clock: Arc::new(clock),
+            ..self
+        }
+    }
+
+    /// Sets the URL clients use to reach the server, like `https://example.com`, which [`Request::base_url`] returns for every request.
+    /// This is the most reliable way to build absolute URLs, as it doesn't depend on any headers.
+    /// Without it, the URL is worked out from each request and the headers set by trusted proxies, see [`Server::trust_proxy`].
+    /// ## Example
+    /// ```rust
+    /// # use afire::Server;
+    /// let server = Server::<()>::new("localhost", 8080).public_url("https://example.com");
+    /// ```
+    pub fn public_url(self, url: impl AsRef<str>) -> Self {
+        let url = url.as_ref().trim_end_matches('/');
+        trace!("{}Setting public URL to {}", emoji("🌐"), url);
+
+        let mut url_config = (*self.url_config).clone();
+        url_config.public_url = Some(url.to_owned());
+        Server {
+            url_config: Arc::new(url_config),
+            ..self
+        }
+    }
+
+    /// Trusts the reverse proxies in a CIDR range to tell the server which scheme, host and port clients used with the `Forwarded` or `X-Forwarded-*` headers, for [`Request::base_url`].
+    /// For example `trust_proxy([10, 0, 0, 0], 8)` trusts `10.0.0.0/8`.
+    /// No proxies are trusted by default, not even ones on localhost, as other local processes could connect to the server directly.
+    ///
+    /// **Warning**: Make sure your reverse proxy is overwriting these headers on incoming requests, so clients can't make the server link to other sites.
+    /// ## Example
+    /// ```rust
+    /// # use afire::Server;
+    /// // Trust the load balancer
+    /// let server = Server::<()>::new("localhost", 8080).trust_proxy([10, 0, 0, 5], 32);
+    /// ```
+    pub fn trust_proxy(self, addr: impl Into<IpAddr>, prefix: u8) -> Self {
+        let addr = addr.into();
+        trace!("{}Trusting proxies in {}/{}", emoji("🌐"), addr, prefix);
+
+        let mut url_config = (*self.url_config).clone();
+        url_config.trusted_proxies.push((addr, prefix));
+        Server {
+            url_config: Arc::new(url_config),
+            ..self
+        }
+    }
+
+    /// Sets limits on the [WebSockets](crate::web_socket) of the server, to protect public realtime endpoints from being overwhelmed.
+    /// Clients that go over a limit are sent a Close frame with the matching close code and disconnected.
+    /// By default nothing is limited.
+    /// ## Example
+    /// ```rust
+    /// # use afire::{Server, web_socket::WebSocketLimits};
+    /// let server = Server::<()>::new("localhost", 8080).web_socket_limits(
+    ///     WebSocketLimits::default()
+    ///         .max_connections(1000)
+    ///         .max_message_size(64 * 1024)
+    ///         .max_frames_per_second(50),
+    /// );
+    /// ```
+    pub fn web_socket_limits(self, limits: WebSocketLimits) -> Self {
+        trace!("{}Setting WebSocket limits", emoji("🔌"));
+
+        Server {
+            web_socket_config: Arc::new(WebSocketConfig::new(limits)),
+            ..self
+        }
+    }
+
+    /// Set the state of a server.
+    /// The state will be available to stateful routes ([`Server::stateful_route`]) and middleware.
+    /// It is not mutable, so you will need to use an atomic or sync type to mutate it.
+    ///
+    /// ## Example
+    /// ```rust,no_run
+    /// # use afire::{Server, Response, Method};
+    /// # use std::sync::atomic::{AtomicU32, Ordering};
+    /// // Create a server for localhost on port 8080
+    /// // Note: We can omit the type parameter here because we are setting the state
+    /// let mut server = Server::new("localhost", 8080)
+    ///     // Set server wide state
+    ///     .state(AtomicU32::new(0));
+    ///
+    /// // Add a stateful route to increment the state
+    /// server.stateful_route(Method::GET, "/", |state, _req| {
+    ///     Response::new().text(state.fetch_add(1, Ordering::Relaxed))
+    /// });
+    /// ```
+    pub fn state(self, state: State) -> Self {
+        trace!(
+            "{}Setting Server State [{}]",
+            emoji("📦️"),
+            type_name::<State>()
+        );
+
+        Self {
+            state: Some(Arc::new(state)),
+            ..self
+        }
+    }
+
+    /// Set the panic handler, which is called if a route or middleware panics.
+    /// This is only available if the `panic_handler` feature is enabled.
+    /// If you don't set it, the default response is 500 "Internal Server Error :/".
+    /// With the `dev` feature enabled in a debug build, the default is instead an HTML page with the panic message, route, request and backtrace (if captured).
+    /// Release builds always use the plain text response, so the page can't expose a server's internals in production.
+    /// Be sure that your panic handler wont panic, because that will just panic the whole application.
+    /// It is only used with the default [`PanicPolicy::CatchAndRespond`], see [`Server::panic_policy`].
+    ///
+    /// The handler gets the server's state (if it has one), the request (if it could be parsed) and a [`PanicInfo`] with the panic message and the route that panicked.
+    /// This is enough context to render an error page or report the panic to an error tracking service.
+    /// ## Example
+    /// ```rust
+    /// # use afire::{Server, Response, Status};
+    /// # let mut server = Server::<()>::new("localhost", 8080);
+    /// // Set the panic handler response
+    /// server.error_handler(|_state, _req, err| {
+    ///     let route = err.route.as_ref().map_or("middleware", |i| &i.path);
+    ///     Response::new()
+    ///         .status(Status::InternalServerError)
+    ///         .text(format!("Internal Server Error in {}: {}", route, err))
+    /// });
+    /// ```
+    pub fn error_handler(
+        &mut self,
+        res: impl Fn(Option<Arc<State>>, &Box<Result<Rc<Request>>>, &PanicInfo) -> Response
+            + Send
+            + Sync
+            + 'static,
+    ) {
+        trace!("{}Setting Error Handler", emoji("✌"));
+
+        self.error_handler = Box::new(res);
+    }
+
+    /// Sets what the server does when a route or middleware panics.
+    /// By default the panic is caught and the response from the [`Server::error_handler`] is sent, see [`PanicPolicy`] for the other options.
+    /// ## Example
+    /// ```rust
+    /// # use afire::{Server, PanicPolicy};
+    /// // Crash instead of continuing after a panic
+    /// let mut server = Server::<()>::new("localhost", 8080)
+    ///     .panic_policy(PanicPolicy::Abort);
+    /// ```
+    pub fn panic_policy(self, panic_policy: PanicPolicy) -> Self {
+        trace!("{}Setting panic policy to {:?}", emoji("💥"), panic_policy);
+
+        Server {
+            panic_policy,
+            ..self
+        }
+    }
+
+    /// Always capture a backtrace when a route or middleware panics, even if the `RUST_BACKTRACE` environment variable isn't set.
+    /// The backtrace is given to the [`Server::error_handler`] in the [`PanicInfo`], and is logged by the [`crate::extension::Logger`].
+    /// Capturing backtraces is slow, but it only happens when there is a panic.
+    /// By default this is false.
+    /// ## Example
+    /// ```rust
+    /// # use afire::Server;
+    /// let mut server = Server::<()>::new("localhost", 8080)
+    ///     .capture_backtraces(true);
+    /// ```
+    pub fn capture_backtraces(self, capture_backtraces: bool) -> Self {
+        trace!(
+            "{}Setting capture backtraces to {}",
+            emoji("🔍"),
+            capture_backtraces
+        );
+
+        Server {
+            capture_backtraces,
+            ..self
+        }
+    }
+
+    /// Sets the response for requests that don't match any route.
+    /// By default this is a 404 with the text `Cannot <METHOD> <PATH>`.
+    /// The status of the returned response is always set to `404 Not Found`.
+    /// ## Example
+    /// ```rust
+    /// # use afire::{Server, Response};
+    /// # let mut server = Server::<()>::new("localhost", 8080);
+    /// server.not_found(|req| {
+    ///     Response::new()
+    ///         .text(format!(r#"{{"error": "No route for {}"}}"#, req.path))
+    ///         .content(afire::Content::JSON)
+    /// });
+    /// ```
+    pub fn not_found(&mut self, res: impl Fn(&Request) -> Response + Send + Sync + 'static) {
+        self.status_page(Status::NotFound, move |req, err| match req {
+            Some(req) => res(req),
+            None => Response::new().text(err),
+        });
+    }
+
+    /// Sets the response for errors the server generates with the given status, instead of the default plain text one.
+    /// This is used for requests that can't be parsed (`400 Bad Request`), are too large (`413 Payload Too Large`, `414 URI Too Long` or `431 Request Header Fields Too Large`), time out (`408 Request Timeout`) or don't match a route (`404 Not Found`).
+    /// Responses returned by routes and middleware are never replaced, and panics are still handled by [`Server::error_handler`].
+    ///
+    /// The handler gets the request, if it could be parsed, and the error.
+    /// The status of the returned response is always set to the given status.
+    /// ## Example
+    /// ```rust
+    /// # use afire::{Server, Response, Status, Content};
+    /// # let mut server = Server::<()>::new("localhost", 8080);
+    /// server.status_page(Status::BadRequest, |_req, err| {
+    ///     Response::new()
+    ///         .text(format!(r#"{{"error": "{}"}}"#, err))
+    ///         .content(Content::JSON)
+    /// });
+    /// ```
+    pub fn status_page(
+        &mut self,
+        status: Status,
+        res: impl Fn(Option<&Request>, &Error) -> Response + Send + Sync + 'static,
+    ) {
+        trace!("{}Setting {} page", emoji("✌"), status.code());
+        self.status_pages.insert(status, Box::new(res));
+    }
+
+    /// Set a callback that is run once the server has bound its listener, with the address it is listening on.
+    /// This is useful if the server was created with port 0, in which case the OS will pick a free port.
+    /// It isn't run for listeners without an IP address, like Unix sockets.
+    /// ## Example
+    /// ```rust,no_run
+    /// # use std::sync::mpsc;
+    /// # use afire::Server;
+    /// let (tx, rx) = mpsc::sync_channel(1);
+    /// // Create a server on localhost with an OS assigned port
+    /// let mut server = Server::<()>::new("localhost", 0);
+    /// server.on_start(move |addr| tx.send(addr).unwrap());
+    ///
+    /// std::thread::spawn(move || server.start().unwrap());
+    /// println!("Listening on {}", rx.recv().unwrap());
+    /// ```
+    pub fn on_start(&mut self, on_start: impl Fn(SocketAddr) + Send + Sync + 'static) {
+        trace!("{}Setting Start Handler", emoji("✌"));
+
+        self.on_start = Some(Box::new(on_start));
+    }
+
+    /// Add a hook that is run after the server has been gracefully shut down (see [`Server::shutdown_handle`]).
+    /// It runs once the server has stopped accepting connections and the requests in progress have finished, right before `start` returns.
+    /// This is a good place to flush logs or close database connections.
+    /// ## Example
+    /// ```rust
+    /// # use afire::Server;
+    /// let mut server = Server::<()>::new("localhost", 8080);
+    /// server.on_shutdown(|| println!("Goodbye!"));
+    /// ```
+    pub fn on_shutdown(&mut self, hook: impl Fn() + Send + Sync + 'static) {
+        trace!("{}Adding Shutdown Hook", emoji("✌"));
+
+        self.on_shutdown.push(Box::new(hook));
+    }
+
+    /// Add a hook that can change every response before it is written.
+    /// Hooks run after the route and all post middleware (including on error responses), in the order they were added, so they are a simple way to enforce policies across the whole server without writing a [`Middleware`].
+    /// Responses to requests that couldn't be parsed and responses already sent with [`crate::Context::send`] aren't passed to hooks.
+    /// ## Example
+    /// ```rust
+    /// # use afire::{Server, HeaderType};
+    /// let mut server = Server::<()>::new("localhost", 8080);
+    /// server.response_hook(|_req, res| {
+    ///     // Don't let the site be embedded in frames
+    ///     if !res.headers.has("X-Frame-Options") {
+    ///         res.headers.add("X-Frame-Options", "DENY");
+    ///     }
+    ///
+    ///     // Don't leak internal headers
+    ///     res.headers.remove("X-Internal-Token");
+    ///
+    ///     // Make sure text is always sent with a charset
+    ///     if let Some(content_type) = res.headers.get_mut(HeaderType::ContentType) {
+    ///         if content_type.starts_with("text/") && !content_type.contains("charset") {
+    ///             content_type.push_str("; charset=utf-8");
+    ///         }
+    ///     }
+    /// });
+    /// ```
+    pub fn response_hook(
+        &mut self,
+        hook: impl Fn(&Request, &mut Response) + Send + Sync + 'static,
+    ) {
+        trace!("{}Adding Response Hook", emoji("✌"));
+
+        self.response_hooks.push(Box::new(hook));
+    }
+
+    /// Add a filter that transforms the bodies of responses as they are sent, like for compression or minification.
+    /// Filters are applied in the order they were added, after post middleware and response hooks have run.
+    /// See the [`crate::body_filter`] module for more information.
+    /// ## Example
+    /// ```rust
+    /// # use std::io::{Cursor, Read};
+    /// # use afire::{body_filter::Body, header::Headers, Request, Server};
+    /// let mut server = Server::<()>::new("localhost", 8080);
+    /// // Add a trailing newline to every response
+    /// server.body_filter(|_req: &Request, _headers: &mut Headers, body: Body| {
+    ///     Box::new(body.chain(Cursor::new(b"\n"))) as Body
+    /// });
+    /// ```
+    pub fn body_filter(&mut self, filter: impl BodyFilter + 'static) {
+        trace!(
+            "{}Adding Body Filter {}",
+            emoji("🧪"),
+            type_name_of_val(&filter)
+        );
+
+        self.body_filters.push(Box::new(filter));
+    }
+
+    /// Create a new route.
+    /// The path can contain parameters, which are defined with `{...}`, as well as wildcards, which are defined with `*`.
+    /// (`**` lets you math anything after the wildcard, including `/`)
+    ///
+    /// Parameters can be constrained, so the route only matches if the constraint holds.
+    /// `{id:u32}` only matches segments that parse as a `u32` (any of the number types or `bool` can be used),
+    /// and any other constraint is a regex that has to match the whole segment, like `{name:[a-z]+}`.
+    /// The regex can contain `/`, but as segments never do it only ever matches within one segment.
+    /// An invalid regex will make starting the server fail with [`StartupError::InvalidRoute`].
+    /// ## Example
+    /// ```rust
+    /// # use afire::{Server, Response, Header, Method, Content};
+    /// # let mut server = Server::<()>::new("localhost", 8080);
+    /// // Define a route
+    /// server.route(Method::GET, "/greet/{name}", |req| {
+    ///     let name = req.param("name").unwrap();
+    ///
+    ///     Response::new()
+    ///         .text(format!("Hello, {}!", name))
+    ///         .content(Content::TXT)
+    /// });
+    ///
+    /// // `/user/new` isn't handled by this route, as `new` isn't a u32
+    /// server.route(Method::GET, "/user/{id:u32}", |req| {
+    ///     let id = req.param("id").unwrap().parse::<u32>().unwrap();
+    ///     Response::new().text(format!("User #{}", id))
+    /// });
+    /// server.route(Method::GET, "/user/new", |_| Response::new().text("New user"));
+    /// ```
+    pub fn route(
+        &mut self,
+        method: Method,
+        path: impl AsRef<str>,
+        handler: impl Fn(&Request) -> Response + Send + Sync + 'static,
+    ) -> &mut Self {
+        let path = path.as_ref().to_owned();
+        trace!("{}Adding Route {} {}", emoji("🚗"), method, path);
+
+        self.router.take();
+        self.routes
+            .push(Route::new(method, path, Box::new(handler)));
+        self
+    }
+
+    /// Create a new stateful route.
+    /// Is the same as [`Server::route`], but the state is passed as the first parameter.
+    /// (See [`Server::state`])
+    ///
+    /// Note: If you add a stateful route, you must also set the state or starting the sever will return an error.
+    /// ## Example
+    /// ```rust
+    /// # use afire::{Server, Response, Header, Method};
+    /// // Create a server for localhost on port 8080
+    /// let mut server = Server::<u32>::new("localhost", 8080)
+    ///    .state(101);
+    ///
+    /// // Define a route
     /// server.stateful_route(Method::GET, "/nose", |sta, req| {
     ///     Response::new().text(sta.to_string())
     /// });
@@ -337,11 +1846,263 @@ impl<State: Send + Sync> Server<State> {
         let path = path.as_ref().to_owned();
         trace!("{}Adding Route {} {}", emoji("🚗"), method, path);
 
+        self.router.take();
         self.routes
             .push(Route::new_stateful(method, path, Box::new(handler)));
         self
     }
 
+    /// Create a new context route.
+    /// Is the same as [`Server::route`], but the handler is passed a [`Context`] instead of the request.
+    /// The context gives access to the request, the app state and the server, and lets the handler send its response early.
+    ///
+    /// Note: Unlike stateful routes, context routes don't require the state to be set, [`Context::app`] will panic if it is not.
+    /// ## Example
+    /// ```rust
+    /// # use afire::{Server, Response, Method};
+    /// # let mut server = Server::<()>::new("localhost", 8080);
+    /// server.context_route(Method::GET, "/greet/{name}", |ctx| {
+    ///     let name = ctx.req.param("name").unwrap();
+    ///     let busy = ctx.thread_pool().busy();
+    ///     Response::new().text(format!("Hello, {name}! ({busy} busy threads)"))
+    /// });
+    /// ```
+    pub fn context_route(
+        &mut self,
+        method: Method,
+        path: impl AsRef<str>,
+        handler: impl Fn(&Context<State>) -> Response + Send + Sync + 'static,
+    ) -> &mut Self {
+        let path = path.as_ref().to_owned();
+        trace!("{}Adding Route {} {}", emoji("🚗"), method, path);
+
+        self.router.take();
+        self.routes
+            .push(Route::new_context(method, path, Box::new(handler)));
+        self
+    }
+
+    /// Sets the priority of the route that was added last.
+    /// When more than one route matches a request, the one with the highest priority handles it.
+    /// If they have the same priority the one added last is used, but two routes with the same method and priority that match exactly the same paths
+    /// (like `/user/{id}` and `/user/{name}`) will make starting the server fail with [`StartupError::RouteConflict`].
+    /// By default routes have a priority of 0.
+    /// ## Example
+    /// ```rust
+    /// # use afire::{Server, Response, Method};
+    /// # let mut server = Server::<()>::new("localhost", 8080);
+    /// // Without the priority, `/user/new` would be handled by the `/user/{id}` route as it was added later
+    /// server
+    ///     .route(Method::GET, "/user/new", |_| Response::new().text("New user"))
+    ///     .route_priority(1);
+    /// server.route(Method::GET, "/user/{id}", |_| Response::new().text("User"));
+    /// ```
+    pub fn route_priority(&mut self, priority: i32) -> &mut Self {
+        if let Some(route) = self.routes.last_mut() {
+            trace!("{}Setting Route Priority to {}", emoji("🚗"), priority);
+            route.priority = priority;
+        }
+
+        self.router.take();
+        self
+    }
+
+    /// Sets the name of the route that was added last.
+    /// Names are only used to identify routes in [`Server::routes`], like in the `RouteDebug` extension.
+    /// ## Example
+    /// ```rust
+    /// # use afire::{Server, Response, Method};
+    /// # let mut server = Server::<()>::new("localhost", 8080);
+    /// server
+    ///     .route(Method::GET, "/user/{id}", |_| Response::new().text("User"))
+    ///     .route_name("get_user");
+    /// ```
+    pub fn route_name(&mut self, name: impl AsRef<str>) -> &mut Self {
+        if let Some(route) = self.routes.last_mut() {
+            route.name = Some(name.as_ref().to_owned());
+        }
+
+        self
+    }
+
+    /// Sets the maximum body size of the route that was added last, overriding [`Server::max_body_size`].
+    /// The limit is checked against the `Content-Length` before the body is read.
+    /// ## Example
+    /// ```rust
+    /// # use afire::{Server, Response, Method};
+    /// // Keep the API at 1 MiB, but allow uploads of up to 1 GiB
+    /// let mut server = Server::<()>::new("localhost", 8080).max_body_size(1024 * 1024);
+    /// server
+    ///     .route(Method::POST, "/upload", |req| Response::new().text(req.body.len()))
+    ///     .route_max_body_size(1024 * 1024 * 1024);
+    /// ```
+    pub fn route_max_body_size(&mut self, max_body_size: usize) -> &mut Self {
+        if let Some(route) = self.routes.last_mut() {
+            trace!(
+                "{}Setting Route max body size to {}",
+                emoji("🚗"),
+                max_body_size
+            );
+            route.max_body_size = Some(max_body_size);
+        }
+
+        self
+    }
+
+    /// Sets how long a client has to send the body of a request to the route that was added last, overriding [`Server::body_timeout`].
+    /// ## Example
+    /// ```rust
+    /// # use std::time::Duration;
+    /// # use afire::{Server, Response, Method};
+    /// # let mut server = Server::<()>::new("localhost", 8080);
+    /// server
+    ///     .route(Method::POST, "/upload", |req| Response::new().text(req.body.len()))
+    ///     .route_body_timeout(Duration::from_secs(300));
+    /// ```
+    pub fn route_body_timeout(&mut self, body_timeout: Duration) -> &mut Self {
+        if let Some(route) = self.routes.last_mut() {
+            trace!(
+                "{}Setting Route body timeout to {:?}",
+                emoji("🚗"),
+                body_timeout
+            );
+            route.body_timeout = Some(body_timeout);
+        }
+
+        self
+    }
+
+    /// Makes the route that was added last read request bodies as they are received, instead of buffering them first.
+    /// [`Request::body`] will be empty, and the handler reads the body with [`Request::body_reader`], so large uploads don't have to fit in memory.
+    /// Middleware also only sees the empty body.
+    ///
+    /// If the handler doesn't read the whole body, the connection is closed after the response.
+    /// ## Example
+    /// ```rust
+    /// # use std::{fs::File, io};
+    /// # use afire::{Server, Response, Method, Status};
+    /// # let mut server = Server::<()>::new("localhost", 8080);
+    /// server
+    ///     .route(Method::POST, "/upload", |req| {
+    ///         let mut file = File::create("upload.bin").unwrap();
+    ///         match io::copy(&mut req.body_reader(), &mut file) {
+    ///             Ok(len) => Response::new().text(format!("Received {} bytes", len)),
+    ///             Err(_) => Response::new().status(Status::BadRequest),
+    ///         }
+    ///     })
+    ///     .route_stream_body();
+    /// ```
+    pub fn route_stream_body(&mut self) -> &mut Self {
+        if let Some(route) = self.routes.last_mut() {
+            route.stream_body = true;
+        }
+
+        self
+    }
+
+    /// Attaches a key and value to the route that was added last.
+    /// Keys can be added more than once to give them multiple values.
+    /// The metadata can be read at runtime with [`Request::route_meta`] and is included in [`Server::routes`], see [`crate::RouteMeta`] for an example.
+    /// ## Example
+    /// ```rust
+    /// # use afire::{Server, Response, Method};
+    /// # let mut server = Server::<()>::new("localhost", 8080);
+    /// server
+    ///     .route(Method::GET, "/user/{id}", |_| Response::new().text("User"))
+    ///     .route_meta("tag", "users")
+    ///     .route_meta("description", "Gets a user by their ID");
+    /// ```
+    pub fn route_meta(&mut self, key: impl AsRef<str>, value: impl AsRef<str>) -> &mut Self {
+        if let Some(route) = self.routes.last_mut() {
+            Arc::make_mut(&mut route.meta).add(key, value);
+        }
+
+        self
+    }
+
+    /// Attaches documentation to the route that was added last, for the `OpenApi` extension.
+    /// ## Example
+    /// ```rust
+    /// # use afire::{Server, Response, Method};
+    /// use afire::extension::openapi::RouteDocs;
+    /// # let mut server = Server::<()>::new("localhost", 8080);
+    /// server
+    ///     .route(Method::GET, "/user/{id}", |_| Response::new().text("User"))
+    ///     .route_docs(RouteDocs::new().summary("Get a user by their ID"));
+    /// ```
+    #[cfg(feature = "extensions")]
+    pub fn route_docs(&mut self, docs: RouteDocs) -> &mut Self {
+        if let Some(route) = self.routes.last_mut() {
+            route.docs = Some(docs);
+        }
+
+        self
+    }
+
+    /// Gets information about the routes that have been added, in the order they were added.
+    /// This is useful for checking what routes an app made up of many modules actually has.
+    /// ## Example
+    /// ```rust
+    /// # use afire::{Server, Response, Method};
+    /// # let mut server = Server::<()>::new("localhost", 8080);
+    /// server
+    ///     .route(Method::GET, "/user/{id}", |_| Response::new().text("User"))
+    ///     .route_name("get_user");
+    ///
+    /// for route in server.routes() {
+    ///     println!("{} {} ({:?})", route.method, route.path, route.name);
+    /// }
+    /// # assert_eq!(server.routes()[0].path, "/user/{id}");
+    /// ```
+    pub fn routes(&self) -> Vec<RouteInfo> {
+        self.routes.iter().map(Route::info).collect()
+    }
+
+    /// Runs a task in the background every `interval` while the server is running, starting one interval after the server starts.
+    /// This is useful for things like clearing expired sessions or flushing metrics.
+    ///
+    /// Tasks are run on the server's thread pool, or on a dedicated scheduler thread when using [`Server::start`].
+    /// If a run is still going when the task is next due, that run is skipped.
+    /// Once the server is shut down no new runs are started, but a run that is in progress is allowed to finish.
+    /// ## Example
+    /// ```rust
+    /// # use std::{sync::atomic::{AtomicUsize, Ordering}, time::Duration};
+    /// # use afire::Server;
+    /// let mut server = Server::new("localhost", 8080).state(AtomicUsize::new(0));
+    ///
+    /// // Reset the counter every minute
+    /// let app = server.app();
+    /// server.schedule(Duration::from_secs(60), move || {
+    ///     app.store(0, Ordering::Relaxed);
+    /// });
+    /// ```
+    pub fn schedule(
+        &mut self,
+        interval: Duration,
+        task: impl Fn() + Send + Sync + 'static,
+    ) -> &mut Self {
+        trace!("{}Scheduling task every {:?}", emoji("⏰"), interval);
+        self.tasks.push(Task::periodic(interval, task));
+        self
+    }
+
+    /// Runs a task once in the background when the server starts.
+    /// Like [`Server::schedule`], it runs on the server's thread pool.
+    /// ## Example
+    /// ```rust
+    /// # use afire::Server;
+    /// let mut server = Server::<()>::new("localhost", 8080);
+    ///
+    /// server.spawn_task(|| {
+    ///     println!("Warming up caches...");
+    /// });
+    /// ```
+    pub fn spawn_task(&mut self, task: impl FnOnce() + Send + 'static) -> &mut Self {
+        trace!("{}Adding background task", emoji("⏰"));
+        self.tasks.push(Task::once(task));
+        self
+    }
+
     /// Gets a reference to the current server state set outside of stateful routes.
     /// Will <u>panic</u> if the server has no state.
     /// ## Example
@@ -366,6 +2127,561 @@ impl<State: Send + Sync> Server<State> {
             return Err(StartupError::InvalidSocketTimeout.into());
         }
 
+        if self.keep_alive_timeout == Some(Duration::ZERO) {
+            return Err(StartupError::InvalidKeepAliveTimeout.into());
+        }
+
+        if self.max_keep_alive_requests == Some(0) {
+            return Err(StartupError::InvalidMaxKeepAliveRequests.into());
+        }
+
+        if self.max_connections == Some(0) {
+            return Err(StartupError::InvalidMaxConnections.into());
+        }
+
+        for route in &self.routes {
+            if let Some(error) = route.path.constraint_error() {
+                return Err(StartupError::InvalidRoute(route.path(), error.to_owned()).into());
+            }
+        }
+
+        for (i, route) in self.routes.iter().enumerate() {
+            if let Some(other) = self.routes[i + 1..].iter().find(|x| route.conflicts(x)) {
+                return Err(StartupError::RouteConflict(
+                    route.method.clone(),
+                    route.path(),
+                    other.path(),
+                )
+                .into());
+            }
+        }
+
+        if self.read_buffer_size == 0 || self.chunk_size == 0 {
+            return Err(StartupError::InvalidBufferSize.into());
+        }
+
+        if self
+            .tasks
+            .iter()
+            .any(|x| x.interval == Some(Duration::ZERO))
+        {
+            return Err(StartupError::InvalidTaskInterval.into());
+        }
+
+        Ok(())
+    }
+}
+
+/// Counters for the connections of a server, for monitoring.
+/// You can get one with [`Server::connection_stats`] before starting the server.
+/// It is cheap to clone and will stay updated while the server is running.
+///
+/// Browsers often open connections speculatively and close them without sending a request.
+/// These are closed quietly instead of being logged as errors, and counted in [`ConnectionStats::empty`].
+/// ## Example
+/// ```rust,no_run
+/// # use std::{thread, time::Duration};
+/// # use afire::Server;
+/// let server = Server::<()>::new("localhost", 8080);
+/// let stats = server.connection_stats();
+///
+/// thread::spawn(move || loop {
+///     println!("{} open, {} empty", stats.open(), stats.empty());
+///     thread::sleep(Duration::from_secs(1));
+/// });
+///
+/// server.start_threaded(4).unwrap();
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionStats(Arc<ConnectionCounters>);
+
+#[derive(Debug, Default)]
+pub(crate) struct ConnectionCounters {
+    /// The number of connections currently open.
+    pub(crate) open: AtomicUsize,
+    /// The number of connections currently open from each IP address, only counted if [`Server::max_connections_per_ip`] is set.
+    pub(crate) per_ip: Mutex<HashMap<IpAddr, usize>>,
+    /// The number of connections that were closed before the client sent anything.
+    pub(crate) empty: AtomicU64,
+    /// The number of connections that were rejected for being over a connection limit.
+    pub(crate) rejected: AtomicU64,
+    /// The connections watched by the idle connection reaper, by id.
+    pub(crate) tracked: Mutex<HashMap<u64, TrackedConnection>>,
+    /// The number of connections closed by the idle connection reaper.
+    pub(crate) reaped: AtomicU64,
+}
+
+/// What is done with connections that are over a connection limit, see [`Server::connection_rejection`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConnectionRejection {
+    /// Send a `503 Service Unavailable` response (for [`Server::max_connections`]) or a `429 Too Many Requests` response (for [`Server::max_connections_per_ip`]) and close the connection.
+    #[default]
+    Respond,
+    /// Close the connection without sending anything.
+    Close,
+}
+
+impl ConnectionStats {
+    /// Gets the number of connections that are currently open.
+    pub fn open(&self) -> usize {
+        self.0.open.load(Ordering::Acquire)
+    }
+
+    /// Gets the number of connections that are currently open from an IP address.
+    /// Connections are only counted per IP if [`Server::max_connections_per_ip`] is set, otherwise this is always zero.
+    pub fn open_from(&self, ip: IpAddr) -> usize {
+        self.0.per_ip.force_lock().get(&ip).copied().unwrap_or(0)
+    }
+
+    /// Gets the number of connections that were rejected for being over [`Server::max_connections`] or [`Server::max_connections_per_ip`].
+    pub fn rejected(&self) -> u64 {
+        self.0.rejected.load(Ordering::Relaxed)
+    }
+
+    /// Gets the number of connections that were closed (or timed out) before the client sent any bytes.
+    pub fn empty(&self) -> u64 {
+        self.0.empty.load(Ordering::Relaxed)
+    }
+
+    /// Gets the number of open connections that are waiting for their next request.
+    /// Connections are only tracked if [`Server::reap_idle_connections`] is enabled, otherwise this is always zero.
+    pub fn idle(&self) -> usize {
+        idle_count(&self.0.tracked.force_lock())
+    }
+
+    /// Gets the number of connections that were closed by the idle connection reaper, see [`Server::reap_idle_connections`].
+    pub fn reaped(&self) -> u64 {
+        self.0.reaped.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn counters(&self) -> &Arc<ConnectionCounters> {
+        &self.0
+    }
+}
+
+/// A handle used to gracefully shut down a running server from another thread, or from a route with [`crate::Context::shutdown`].
+/// You can get one with [`Server::shutdown_handle`] before starting the server.
+///
+/// Once shut down, the server stops accepting connections, finishes the requests it is currently handling and returns from `start`.
+/// Keep-alive connections are closed after their current request.
+/// Long running handlers can find out the server is shutting down with a [`ShutdownToken`].
+///
+/// ## Example
+/// ```rust,no_run
+/// # use std::{thread, time::Duration};
+/// # use afire::Server;
+/// let server = Server::<()>::new("localhost", 8080);
+/// let handle = server.shutdown_handle();
+///
+/// thread::spawn(move || {
+///     thread::sleep(Duration::from_secs(60));
+///     handle.shutdown();
+/// });
+///
+/// // Returns once the server has been shut down
+/// server.start_threaded(4).unwrap();
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ShutdownHandle(Arc<ShutdownState>);
+
+/// Lets long running handlers (like ones streaming server-sent events) find out when the server is shutting down, so they can finish up and return.
+/// Requests in progress are waited for when the server shuts down, so a handler that never returns stops `start` from returning.
+///
+/// Get one from [`crate::Request::shutdown_token`], [`crate::Context::shutdown_token`] or [`Server::shutdown_token`].
+/// Unlike a [`ShutdownHandle`], it can't shut down the server.
+/// ## Example
+/// ```rust
+/// # use std::time::Duration;
+/// # use afire::{Server, Response, Method};
+/// # let mut server = Server::<()>::new("localhost", 8080);
+/// server.route(Method::GET, "/work", |req| {
+///     let token = req.shutdown_token();
+///     let mut done = 0;
+///     // Does a step of work every second, until the server starts shutting down
+///     while done < 10 && !token.wait_timeout(Duration::from_secs(1)) {
+///         done += 1;
+///     }
+///     Response::new().text(format!("Finished {done} steps"))
+/// });
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ShutdownToken(Arc<ShutdownState>);
+
+#[derive(Default)]
+struct ShutdownState {
+    /// Set once the server should stop.
+    shutdown: AtomicBool,
+    /// The address the server is listening on, see [`ShutdownHandle::local_addr`].
+    addr: Mutex<Option<SocketAddr>>,
+    /// Wakes up the accept loop, see [`Listener::waker`].
+    waker: Mutex<Option<Box<dyn Fn() + Send + Sync>>>,
+    /// Wakes up threads waiting with [`ShutdownToken::wait`].
+    wake: Condvar,
+    /// Held while notifying `wake`, so waiters can't miss the shutdown.
+    waiting: Mutex<()>,
+    /// Functions run on shutdown, like telling server-sent event workers to stop.
+    subscribers: Mutex<HashMap<u64, Box<dyn FnOnce() + Send>>>,
+    /// The id of the next subscriber.
+    next_subscriber: AtomicU64,
+}
+
+/// Removes a function added with [`ShutdownToken::subscribe`] when dropped.
+pub(crate) struct ShutdownSubscription {
+    state: Arc<ShutdownState>,
+    id: u64,
+}
+
+impl ShutdownHandle {
+    /// Tells the server to shut down.
+    /// This returns right away, without waiting for the server to stop.
+    pub fn shutdown(&self) {
+        if self.0.shutdown.swap(true, Ordering::AcqRel) {
+            return;
+        }
+
+        trace!("{}Shutting down server", emoji("🛑"));
+
+        drop(self.0.waiting.force_lock());
+        self.0.wake.notify_all();
+        let subscribers = mem::take(&mut *self.0.subscribers.force_lock());
+        for subscriber in subscribers.into_values() {
+            subscriber();
+        }
+
+        // Wake up the accept loop, so it sees the server is shutting down
+        if let Some(waker) = &*self.0.waker.force_lock() {
+            waker();
+        }
+    }
+
+    /// Checks if the server has been told to shut down.
+    pub fn is_shutdown(&self) -> bool {
+        self.0.shutdown.load(Ordering::Acquire)
+    }
+
+    /// Gets the address the server is listening on, or None if it hasn't been started or its listener doesn't have an IP address.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        *self.0.addr.force_lock()
+    }
+
+    /// Gets a [`ShutdownToken`] for the server, which can't be used to shut it down.
+    pub fn token(&self) -> ShutdownToken {
+        ShutdownToken(self.0.clone())
+    }
+}
+
+impl ShutdownToken {
+    /// Checks if the server has been told to shut down.
+    pub fn is_shutdown(&self) -> bool {
+        self.0.shutdown.load(Ordering::Acquire)
+    }
+
+    /// Blocks until the server is told to shut down.
+    pub fn wait(&self) {
+        let mut waiting = self.0.waiting.force_lock();
+        while !self.is_shutdown() {
+            waiting = self.0.wake.wait(waiting).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    /// Blocks until the server is told to shut down or `timeout` passes, returning true if it is shutting down.
+    /// This can be used in place of [`std::thread::sleep`] in handlers that do something periodically.
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut waiting = self.0.waiting.force_lock();
+        while !self.is_shutdown() {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                break;
+            }
+
+            waiting = self
+                .0
+                .wake
+                .wait_timeout(waiting, remaining)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+
+        self.is_shutdown()
+    }
+
+    /// Runs `subscriber` once the server is told to shut down, or right away if it already has been.
+    /// It is removed if the returned [`ShutdownSubscription`] is dropped first.
+    pub(crate) fn subscribe(
+        &self,
+        subscriber: impl FnOnce() + Send + 'static,
+    ) -> Option<ShutdownSubscription> {
+        let id = self.0.next_subscriber.fetch_add(1, Ordering::Relaxed);
+        self.0
+            .subscribers
+            .force_lock()
+            .insert(id, Box::new(subscriber));
+
+        // The subscribers may have already been run, if the server was shut down while this one was being added
+        if self.is_shutdown() {
+            let subscriber = self.0.subscribers.force_lock().remove(&id);
+            subscriber.into_iter().for_each(|i| i());
+            return None;
+        }
+
+        Some(ShutdownSubscription {
+            state: self.0.clone(),
+            id,
+        })
+    }
+}
+
+impl Drop for ShutdownSubscription {
+    fn drop(&mut self) {
+        self.state.subscribers.force_lock().remove(&self.id);
+    }
+}
+
+impl Debug for ShutdownState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ShutdownState")
+            .field("shutdown", &self.shutdown)
+            .field("addr", &self.addr)
+            .finish_non_exhaustive()
+    }
+}
+
+/// How the targets of requests are checked and normalized, see [`Server::uri_policy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UriPolicy {
+    /// The maximum length of the request target (the path and query) in bytes.
+    /// Requests with a longer target are sent a `414 URI Too Long` response.
+    /// The whole request line is also limited by [`Server::max_request_line`].
+    pub max_length: usize,
+    /// If requests whose path isn't valid UTF-8 (as raw bytes or once percent decoded) or has invalid percent encoding are sent a `400 Bad Request` response.
+    /// Otherwise invalid raw bytes are replaced with `U+FFFD`, and paths that can't be percent decoded are routed as they are.
+    pub reject_invalid_utf8: bool,
+    /// If the percent encoding of paths is normalized with [`crate::encoding::url::normalize`] before requests are routed, so `/%7Euser` is the same as `/~user`.
+    /// Escaped reserved characters like `%2F` stay escaped, but are made uppercase.
+    pub normalize_percent_encoding: bool,
+}
+
+impl Default for UriPolicy {
+    fn default() -> Self {
+        Self {
+            max_length: 8 * 1024,
+            reject_invalid_utf8: false,
+            normalize_percent_encoding: false,
+        }
+    }
+}
+
+impl UriPolicy {
+    /// Checks a request target, before it is parsed.
+    pub(crate) fn check(&self, target: &[u8]) -> Result<()> {
+        if target.len() > self.max_length {
+            return Err(ParseError::UriTooLong.into());
+        }
+
+        if self.reject_invalid_utf8 {
+            let path = target.split(|&i| i == b'?').next().unwrap_or_default();
+            let valid = str::from_utf8(path).is_ok_and(|i| url::decode_path(i).is_some());
+            if !valid {
+                return Err(ParseError::InvalidPath.into());
+            }
+        }
+
         Ok(())
     }
 }
+
+/// What the server does when a route or middleware panics, see [`Server::panic_policy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PanicPolicy {
+    /// Catch the panic and send the response from the [`Server::error_handler`], a `500 Internal Server Error` by default.
+    #[default]
+    CatchAndRespond,
+    /// Catch the panic and close the connection without sending a response.
+    CatchAndClose,
+    /// Abort the process, after the panic message (and backtrace if enabled) has been printed.
+    /// Use this if you would rather crash (and be restarted by a supervisor) than keep running in a possibly corrupted state.
+    Abort,
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::{self, Cursor},
+        net::SocketAddr,
+        sync::{Arc, Mutex},
+    };
+
+    use super::Server;
+    use crate::{
+        error::PanicInfo,
+        internal::testing::{MemoryListener, MemoryStream},
+        listener::Listener,
+        Method, Middleware, Request, Response, Status,
+    };
+
+    /// Fails with each of its errors before accepting any streams.
+    struct FlakyListener(Mutex<Vec<io::Error>>, MemoryListener);
+
+    impl Listener for FlakyListener {
+        type Stream = MemoryStream;
+
+        fn accept(&self) -> io::Result<MemoryStream> {
+            match self.0.lock().unwrap().pop() {
+                Some(e) => Err(e),
+                None => self.1.accept(),
+            }
+        }
+
+        fn local_addr(&self) -> io::Result<SocketAddr> {
+            self.1.local_addr()
+        }
+    }
+
+    #[test]
+    fn test_transient_accept_errors() {
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let listener = FlakyListener(
+            Mutex::new(vec![
+                io::ErrorKind::ConnectionAborted.into(),
+                // EMFILE, too many open files
+                io::Error::from_raw_os_error(24),
+                io::ErrorKind::Interrupted.into(),
+            ]),
+            MemoryListener(Mutex::new(vec![MemoryStream {
+                input: Cursor::new(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n".to_vec()),
+                output: output.clone(),
+            }])),
+        );
+
+        let mut server = Server::<()>::new("localhost", 0);
+        server.route(Method::GET, "/", |_| Response::new().text("Hello"));
+        // Stops on the listener's fatal error once it runs out of streams
+        let error = server.start_with_listener(listener).unwrap_err();
+        assert!(error.to_string().contains("No more streams"));
+
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        assert!(output.starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+
+    #[test]
+    fn test_status_pages() {
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let listener = MemoryListener(Mutex::new(vec![
+            MemoryStream {
+                input: Cursor::new(b"GET /missing HTTP/1.1\r\nHost: localhost\r\n\r\n".to_vec()),
+                output: output.clone(),
+            },
+            MemoryStream {
+                input: Cursor::new(b"GET / HTTP/1.1\r\n\r\n".to_vec()),
+                output: output.clone(),
+            },
+        ]));
+
+        let mut server = Server::<()>::new("localhost", 0);
+        server.not_found(|req| Response::new().text(format!("Nothing at {}", req.path)));
+        server.status_page(Status::BadRequest, |req, err| {
+            assert!(req.is_none());
+            Response::new().text(format!("Bad: {err}"))
+        });
+        assert!(server.start_with_listener(listener).is_err());
+
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        assert!(output.contains("HTTP/1.1 404 Not Found\r\n"));
+        assert!(output.contains("\r\n\r\nNothing at /missing"));
+        assert!(output.contains("HTTP/1.1 400 Bad Request\r\n"));
+        assert!(output.contains("\r\n\r\nBad: No Host header in HTTP/1.1 request"));
+    }
+
+    #[test]
+    fn test_error_handler() {
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let listener = MemoryListener(Mutex::new(vec![MemoryStream {
+            input: Cursor::new(b"GET /user/5 HTTP/1.1\r\nHost: localhost\r\n\r\n".to_vec()),
+            output: output.clone(),
+        }]));
+
+        let mut server = Server::<()>::new("localhost", 0);
+        server.route(Method::GET, "/user/{id}", |_| panic!("No user"));
+        server.route_name("user");
+        server.error_handler(|_state, req, err| {
+            let route = err.route.as_ref().unwrap();
+            let path = &req.as_ref().as_ref().unwrap().path;
+            Response::new()
+                .status(Status::InternalServerError)
+                .text(format!(
+                    "{} {} ({}): {}",
+                    path,
+                    route.path,
+                    route.name.as_deref().unwrap(),
+                    err
+                ))
+        });
+        assert!(server.start_with_listener(listener).is_err());
+
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        assert!(output.starts_with("HTTP/1.1 500 Internal Server Error\r\n"));
+        assert!(output.ends_with("\r\n\r\n/user/5 /user/{id} (user): No user"));
+    }
+
+    #[test]
+    fn test_panic_backtrace() {
+        struct Report(Arc<Mutex<Option<PanicInfo>>>);
+        impl Middleware for Report {
+            fn end(&self, _req: &Request, res: &Response) {
+                *self.0.lock().unwrap() = res.panic().cloned();
+            }
+        }
+
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let listener = MemoryListener(Mutex::new(vec![MemoryStream {
+            input: Cursor::new(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n".to_vec()),
+            output: output.clone(),
+        }]));
+
+        let panic = Arc::new(Mutex::new(None));
+        let mut server = Server::<()>::new("localhost", 0).capture_backtraces(true);
+        server.route(Method::GET, "/", |_| panic!("Oh no"));
+        Report(panic.clone()).attach(&mut server);
+        assert!(server.start_with_listener(listener).is_err());
+
+        let panic = panic.lock().unwrap().take().unwrap();
+        assert_eq!(panic.message, "Oh no");
+        assert_eq!(panic.route.unwrap().path, "/");
+        assert!(panic.backtrace.is_some());
+    }
+
+    #[test]
+    fn test_response_hook() {
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let input = ["/", "/missing"]
+            .map(|path| format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n"))
+            .concat();
+        let listener = MemoryListener(Mutex::new(vec![MemoryStream {
+            input: Cursor::new(input.into_bytes()),
+            output: output.clone(),
+        }]));
+
+        let mut server = Server::<()>::new("localhost", 0);
+        server.route(Method::GET, "/", |_| {
+            Response::new().text("Hello").header("X-Internal-Id", "1")
+        });
+        server.response_hook(|_, res| {
+            res.headers.remove("X-Internal-Id");
+            res.headers.add("X-Frame-Options", "DENY");
+        });
+        server.response_hook(|req, res| res.headers.add("X-Path", &req.path));
+        assert!(server.start_with_listener(listener).is_err());
+
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        let responses = output.split("HTTP/1.1 ").skip(1).collect::<Vec<_>>();
+        assert!(responses[0].contains("\r\nX-Frame-Options: DENY\r\nX-Path: /\r\n"));
+        assert!(
+            !responses[0].contains("X-Internal-Id"),
+            "{:?}",
+            responses[0]
+        );
+        assert!(responses[1].starts_with("404 "), "{:?}", responses[1]);
+        assert!(responses[1].contains("\r\nX-Path: /missing\r\n"));
+    }
+}
@@ -1,96 +1,393 @@
 //! A thread pool implementation.
 //! Used for handling multiple connections at once.
+//!
+//! The pool starts with a fixed number of workers, but if all of them are busy it can spawn extra workers (up to a maximum) to handle the load.
+//! Extra workers are retired after being idle for a while, and workers that die from a panic are replaced.
 
-use std::sync::{mpsc, Arc, Mutex};
+use std::collections::VecDeque;
+use std::sync::{
+    atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    Arc, Condvar, Mutex,
+};
 use std::thread::{self, JoinHandle};
+use std::time::Duration;
 
-use crate::internal::common::ForceLock;
+use crate::{internal::common::ForceLock, trace};
 
-/// Messages that can be handled by the pool's workers.
-enum Message {
-    /// Stops the worker.
-    Kill,
-    /// A job to be executed by the worker.
-    Job(Box<dyn FnOnce() + 'static + Send>),
-}
+type Job = Box<dyn FnOnce() + 'static + Send>;
 
 /// A thread pool.
 pub(crate) struct ThreadPool {
-    /// The number of threads in the pool.
-    threads: usize,
-    /// Handle to each worker thread.
-    workers: Vec<Worker>,
-    /// The channel used to send messages to the workers.
-    sender: mpsc::Sender<Message>,
+    /// State shared with the workers.
+    shared: Arc<Shared>,
+}
+
+/// State shared between the pool and its workers.
+struct Shared {
+    /// Jobs waiting to be picked up by a worker.
+    queue: Mutex<VecDeque<Job>>,
+    /// Used to wake up workers when a job is added or the pool is shutting down.
+    condvar: Condvar,
+    /// Handle to each worker thread that may still be running.
+    handles: Mutex<Vec<JoinHandle<()>>>,
+    /// Set when the pool is dropped.
+    shutdown: AtomicBool,
+    /// The number of workers that are always kept alive.
+    min_threads: usize,
+    /// The maximum number of workers that can be running at once.
+    max_threads: usize,
+    /// How long extra workers can be idle before being retired.
+    idle_timeout: Duration,
+    /// Counters for monitoring.
+    stats: ThreadPoolHandle,
+}
+
+/// A handle for monitoring a server's thread pool.
+/// You can get one with [`crate::Server::thread_pool`] before starting the server.
+/// It is cheap to clone and will stay updated while the server is running.
+///
+/// Besides exporting the numbers for monitoring, they can be used for backpressure, like rejecting expensive requests when the queue is getting long.
+/// ## Example
+/// ```rust,no_run
+/// # use std::{thread, time::Duration};
+/// # use afire::Server;
+/// let server = Server::<()>::new("localhost", 8080);
+/// let pool = server.thread_pool();
+///
+/// thread::spawn(move || loop {
+///     println!(
+///         "{} busy / {} threads, {} queued, {} handled",
+///         pool.busy(),
+///         pool.threads(),
+///         pool.queued(),
+///         pool.completed()
+///     );
+///     thread::sleep(Duration::from_secs(1));
+/// });
+///
+/// server.start_threaded(4).unwrap();
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ThreadPoolHandle(Arc<Counters>);
+
+/// The old name of [`ThreadPoolHandle`].
+#[deprecated(note = "Renamed to ThreadPoolHandle")]
+pub type ThreadPoolStats = ThreadPoolHandle;
+
+#[derive(Debug, Default)]
+struct Counters {
+    threads: AtomicUsize,
+    max_threads: AtomicUsize,
+    busy: AtomicUsize,
+    queued: AtomicUsize,
+    completed: AtomicU64,
+    panics: AtomicUsize,
+    draining: AtomicBool,
 }
 
-/// A worker thread.
-/// Contains a handle to the thread, and an id.
-struct Worker {
-    _id: usize,
-    handle: Option<JoinHandle<()>>,
+/// Marks a worker as alive.
+/// If the worker's thread unwinds from a panic, this will be dropped and the worker will be replaced.
+struct Sentinel {
+    shared: Arc<Shared>,
 }
 
 impl ThreadPool {
-    /// Creates a new thread pool with the specified number of threads.
+    /// Creates a new thread pool with `size` workers.
+    /// Under load it can grow up to `max_threads` workers, extra workers will be stopped after `idle_timeout`.
     /// Panics if `size` is 0.
-    pub(crate) fn new(size: usize) -> Self {
+    pub(crate) fn new(
+        size: usize,
+        max_threads: usize,
+        idle_timeout: Duration,
+        stats: ThreadPoolHandle,
+    ) -> Self {
         assert!(size > 0);
+        let max_threads = max_threads.max(size);
+        stats.0.max_threads.store(max_threads, Ordering::Release);
+        stats.0.draining.store(false, Ordering::Release);
 
-        let (tx, rx) = mpsc::channel();
-        let mut workers = Vec::with_capacity(size);
+        let shared = Arc::new(Shared {
+            queue: Mutex::new(VecDeque::new()),
+            condvar: Condvar::new(),
+            handles: Mutex::new(Vec::with_capacity(size)),
+            shutdown: AtomicBool::new(false),
+            min_threads: size,
+            max_threads,
+            idle_timeout,
+            stats,
+        });
 
-        let receiver = Arc::new(Mutex::new(rx));
-        for i in 0..size {
-            workers.push(Worker::new(i, Arc::clone(&receiver)));
+        for _ in 0..size {
+            spawn_worker(&shared);
         }
 
-        Self {
-            threads: size,
-            sender: tx,
-            workers,
-        }
+        Self { shared }
     }
 
     /// Executes a job on the thread pool.
+    /// If all the workers are busy, another one will be spawned (as long as the pool is not at its max size).
     pub(crate) fn execute(&self, f: impl FnOnce() + 'static + Send) {
-        let job = Message::Job(Box::new(f));
-        self.sender.send(job).unwrap();
+        let shared = &self.shared;
+        let stats = &shared.stats.0;
+
+        let mut queue = shared.queue.force_lock();
+        queue.push_back(Box::new(f));
+        let queued = stats.queued.fetch_add(1, Ordering::AcqRel) + 1;
+        drop(queue);
+
+        let (threads, busy) = (shared.stats.threads(), shared.stats.busy());
+        if busy + queued > threads && threads < shared.max_threads {
+            trace!(
+                Level::Debug,
+                "Spawning extra worker ({} threads)",
+                threads + 1
+            );
+            spawn_worker(shared);
+        }
+
+        shared.condvar.notify_one();
     }
 }
 
-impl Worker {
-    /// Creates a new worker thread.
-    fn new(id: usize, rx: Arc<Mutex<mpsc::Receiver<Message>>>) -> Self {
-        let handle = thread::Builder::new()
-            .name(format!("Worker {id}"))
-            .spawn(move || loop {
-                let job = rx.force_lock().recv().unwrap();
-                match job {
-                    Message::Job(job) => job(),
-                    Message::Kill => break,
-                }
-            })
-            .expect("Error creating worker thread");
+impl ThreadPoolHandle {
+    /// The number of worker threads currently running.
+    pub fn threads(&self) -> usize {
+        self.0.threads.load(Ordering::Acquire)
+    }
+
+    /// The number of workers currently handling a job.
+    pub fn busy(&self) -> usize {
+        self.0.busy.load(Ordering::Acquire)
+    }
+
+    /// The number of jobs waiting for a free worker.
+    pub fn queued(&self) -> usize {
+        self.0.queued.load(Ordering::Acquire)
+    }
+
+    /// The number of workers that have died from a panic (and been replaced).
+    pub fn panics(&self) -> usize {
+        self.0.panics.load(Ordering::Acquire)
+    }
+
+    /// The most worker threads the pool can grow to, see [`crate::Server::max_threads`].
+    /// This is 0 until the server is started.
+    pub fn max_threads(&self) -> usize {
+        self.0.max_threads.load(Ordering::Acquire)
+    }
+
+    /// The total number of jobs (connections, or requests with [`crate::Server::start_event_loop`]) that have been handled.
+    /// Jobs that panicked are counted in [`ThreadPoolHandle::panics`] instead.
+    pub fn completed(&self) -> u64 {
+        self.0.completed.load(Ordering::Acquire)
+    }
+
+    /// Checks if the pool is shutting down.
+    /// While draining, no new jobs are accepted and the workers finish the ones that are [`ThreadPoolHandle::busy`] or [`ThreadPoolHandle::queued`] before stopping.
+    pub fn is_draining(&self) -> bool {
+        self.0.draining.load(Ordering::Acquire)
+    }
+
+    /// Checks if every worker is busy and the pool can't grow any more, so new jobs will have to wait in the queue.
+    pub fn is_saturated(&self) -> bool {
+        self.busy() + self.queued() >= self.max_threads()
+    }
+}
+
+/// Starts a new worker thread.
+/// It will run jobs from the queue until the pool is shut down, or it is an extra worker and has been idle for too long.
+fn spawn_worker(shared: &Arc<Shared>) {
+    let stats = &shared.stats.0;
+    let id = stats.threads.fetch_add(1, Ordering::AcqRel);
+    let sentinel = Sentinel {
+        shared: shared.clone(),
+    };
+
+    let handle = thread::Builder::new()
+        .name(format!("Worker {id}"))
+        .spawn(move || {
+            let shared = sentinel.shared.clone();
+            let stats = &shared.stats.0;
+
+            while let Some(job) = next_job(&shared) {
+                stats.busy.fetch_add(1, Ordering::AcqRel);
+                job();
+                stats.busy.fetch_sub(1, Ordering::AcqRel);
+                stats.completed.fetch_add(1, Ordering::AcqRel);
+            }
+
+            drop(sentinel);
+        })
+        .expect("Error creating worker thread");
+
+    let mut handles = shared.handles.force_lock();
+    handles.retain(|i| !i.is_finished());
+    handles.push(handle);
+}
+
+/// Waits for the next job.
+/// Returns None if the worker should stop, which happens when the pool is shutting down and the queue is empty,
+/// or when the worker has been idle for too long and there are more than `min_threads` workers.
+/// The worker is removed from the thread count before returning None.
+fn next_job(shared: &Shared) -> Option<Job> {
+    let stats = &shared.stats.0;
+    let mut queue = shared.queue.force_lock();
+
+    loop {
+        if let Some(job) = queue.pop_front() {
+            stats.queued.fetch_sub(1, Ordering::AcqRel);
+            return Some(job);
+        }
+
+        if shared.shutdown.load(Ordering::Acquire) {
+            stats.threads.fetch_sub(1, Ordering::AcqRel);
+            return None;
+        }
+
+        let (new_queue, timeout) = match shared.condvar.wait_timeout(queue, shared.idle_timeout) {
+            Ok(i) => i,
+            Err(e) => e.into_inner(),
+        };
+        queue = new_queue;
+
+        if timeout.timed_out() && queue.is_empty() {
+            // Retire this worker if there are more than the minimum number running
+            let retire = stats
+                .threads
+                .fetch_update(Ordering::AcqRel, Ordering::Acquire, |i| {
+                    (i > shared.min_threads).then(|| i - 1)
+                })
+                .is_ok();
+
+            if retire {
+                trace!(Level::Debug, "Retiring idle worker");
+                return None;
+            }
+        }
+    }
+}
 
-        Self {
-            _id: id,
-            handle: Some(handle),
+impl Drop for Sentinel {
+    /// Replaces the worker if it is being dropped because of a panic.
+    fn drop(&mut self) {
+        if !thread::panicking() {
+            return;
+        }
+
+        let stats = &self.shared.stats.0;
+        stats.busy.fetch_sub(1, Ordering::AcqRel);
+        stats.threads.fetch_sub(1, Ordering::AcqRel);
+        stats.panics.fetch_add(1, Ordering::AcqRel);
+        trace!(Level::Error, "Worker panicked, spawning a replacement");
+
+        if !self.shared.shutdown.load(Ordering::Acquire) {
+            spawn_worker(&self.shared);
         }
     }
 }
 
 impl Drop for ThreadPool {
-    /// Stops all workers with a [`Message::Kill`] message, and waits for them to finish.
+    /// Signals all workers to stop once the queue is empty, and waits for them to finish.
     fn drop(&mut self) {
-        for _ in 0..self.threads {
-            self.sender.send(Message::Kill).unwrap();
+        self.shared.stats.0.draining.store(true, Ordering::Release);
+        self.shared.shutdown.store(true, Ordering::Release);
+        self.shared.condvar.notify_all();
+
+        loop {
+            let handle = self.shared.handles.force_lock().pop();
+            match handle {
+                Some(i) => {
+                    let _ = i.join();
+                }
+                None => break,
+            }
         }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{mpsc, Arc, Barrier};
+    use std::time::Duration;
 
-        for worker in &mut self.workers {
-            if let Some(thread) = worker.handle.take() {
-                thread.join().unwrap();
+    use super::{ThreadPool, ThreadPoolHandle};
+
+    #[test]
+    fn test_pool_scales_up() {
+        let stats = ThreadPoolHandle::default();
+        let pool = ThreadPool::new(1, 4, Duration::from_secs(60), stats.clone());
+        let barrier = Arc::new(Barrier::new(5));
+
+        for _ in 0..4 {
+            let barrier = barrier.clone();
+            pool.execute(move || {
+                barrier.wait();
+            });
+        }
+
+        barrier.wait();
+        assert_eq!(stats.threads(), 4);
+    }
+
+    #[test]
+    fn test_pool_retires_idle_workers() {
+        let stats = ThreadPoolHandle::default();
+        let pool = ThreadPool::new(1, 2, Duration::from_millis(10), stats.clone());
+        let barrier = Arc::new(Barrier::new(3));
+
+        for _ in 0..2 {
+            let barrier = barrier.clone();
+            pool.execute(move || {
+                barrier.wait();
+            });
+        }
+
+        barrier.wait();
+        std::thread::sleep(Duration::from_millis(200));
+        assert_eq!(stats.threads(), 1);
+        drop(pool);
+    }
+
+    #[test]
+    fn test_pool_replaces_panicked_workers() {
+        let stats = ThreadPoolHandle::default();
+        let pool = ThreadPool::new(1, 1, Duration::from_secs(60), stats.clone());
+        let (tx, rx) = mpsc::channel();
+
+        pool.execute(|| panic!("Worker panic"));
+        pool.execute(move || tx.send(()).unwrap());
+
+        rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(stats.panics(), 1);
+        assert_eq!(stats.threads(), 1);
+    }
+    #[test]
+    fn test_pool_handle() {
+        let stats = ThreadPoolHandle::default();
+        let pool = ThreadPool::new(1, 2, Duration::from_secs(60), stats.clone());
+        let barrier = Arc::new(Barrier::new(3));
+        assert_eq!(stats.max_threads(), 2);
+
+        for _ in 0..2 {
+            let barrier = barrier.clone();
+            pool.execute(move || {
+                barrier.wait();
+            });
+        }
+
+        barrier.wait();
+        for _ in 0..100 {
+            if stats.completed() == 2 {
+                break;
             }
+            std::thread::sleep(Duration::from_millis(10));
         }
+        assert_eq!(stats.completed(), 2);
+        assert!(!stats.is_saturated());
+        assert!(!stats.is_draining());
+
+        drop(pool);
+        assert!(stats.is_draining());
+        assert_eq!(stats.threads(), 0);
     }
 }
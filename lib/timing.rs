@@ -0,0 +1,157 @@
+//! Timings for the phases of handling a request.
+//!
+//! Every request records when each phase of handling it finished, so `end` middleware can see where the time went without wrapping everything manually.
+//! The timings can also be sent to the client in a [`Server-Timing`](https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Server-Timing) header with [`crate::Server::server_timing`].
+//! ## Example
+//! ```rust
+//! # use afire::{Middleware, Request, Response, timing::Phase};
+//! struct SlowRequests;
+//!
+//! impl Middleware for SlowRequests {
+//!     fn end(&self, req: &Request, _res: &Response) {
+//!         let timing = req.timing();
+//!         if timing.total().as_millis() > 100 {
+//!             println!("{} took {:?} ({:?} in the route)", req.path, timing.total(), timing.phase(Phase::Route));
+//!         }
+//!     }
+//! }
+//! ```
+
+use std::time::{Duration, Instant};
+
+/// A phase of handling a request, in the order they happen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// Reading and parsing the request.
+    Parse,
+    /// Running the `pre` middleware.
+    Pre,
+    /// Running the route handler.
+    /// This is skipped if a middleware sent a response in `pre`.
+    Route,
+    /// Running the `post` middleware.
+    Post,
+    /// Writing the response to the client.
+    Write,
+}
+
+/// When each [`Phase`] of handling a request finished, see [`crate::Request::timing`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timing {
+    /// When reading the request started.
+    start: Instant,
+    /// When each phase finished, indexed by the phase.
+    marks: [Option<Instant>; 5],
+}
+
+impl Phase {
+    /// All the phases, in order.
+    pub const ALL: [Phase; 5] = [
+        Phase::Parse,
+        Phase::Pre,
+        Phase::Route,
+        Phase::Post,
+        Phase::Write,
+    ];
+
+    /// The name of the phase, as used in the `Server-Timing` header.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Phase::Parse => "parse",
+            Phase::Pre => "pre",
+            Phase::Route => "route",
+            Phase::Post => "post",
+            Phase::Write => "write",
+        }
+    }
+}
+
+impl Timing {
+    /// Starts timing a request.
+    pub(crate) fn new(start: Instant) -> Self {
+        Self {
+            start,
+            marks: [None; 5],
+        }
+    }
+
+    /// Records that a phase has finished.
+    pub(crate) fn mark(&mut self, phase: Phase) {
+        self.marks[phase as usize] = Some(Instant::now());
+    }
+
+    /// When reading the request started.
+    pub fn start(&self) -> Instant {
+        self.start
+    }
+
+    /// How long a phase took, or None if it hasn't finished (or was skipped).
+    /// A phase starts when the last phase before it that ran finished.
+    pub fn phase(&self, phase: Phase) -> Option<Duration> {
+        let end = self.marks[phase as usize]?;
+        let start = self.marks[..phase as usize]
+            .iter()
+            .rev()
+            .find_map(|i| *i)
+            .unwrap_or(self.start);
+        Some(end - start)
+    }
+
+    /// How long each phase that has finished took, in order.
+    pub fn phases(&self) -> Vec<(Phase, Duration)> {
+        Phase::ALL
+            .iter()
+            .filter_map(|&i| Some((i, self.phase(i)?)))
+            .collect()
+    }
+
+    /// The time from when reading the request started to when the last phase finished.
+    pub fn total(&self) -> Duration {
+        self.marks
+            .iter()
+            .rev()
+            .find_map(|i| *i)
+            .map_or(Duration::ZERO, |i| i - self.start)
+    }
+
+    /// Formats the finished phases as a `Server-Timing` header value, like `parse;dur=0.052, route;dur=1.204`.
+    pub fn server_timing(&self) -> String {
+        self.phases()
+            .iter()
+            .map(|(phase, duration)| {
+                format!(
+                    "{};dur={:.3}",
+                    phase.name(),
+                    duration.as_secs_f64() * 1000.0
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use super::{Phase, Timing};
+
+    #[test]
+    fn test_timing_phases() {
+        let start = Instant::now();
+        let mut timing = Timing::new(start);
+        timing.marks[Phase::Parse as usize] = Some(start + Duration::from_millis(2));
+        timing.marks[Phase::Pre as usize] = Some(start + Duration::from_millis(3));
+        // The route is skipped, so post starts when pre finished
+        timing.marks[Phase::Post as usize] = Some(start + Duration::from_millis(7));
+
+        assert_eq!(timing.phase(Phase::Parse), Some(Duration::from_millis(2)));
+        assert_eq!(timing.phase(Phase::Route), None);
+        assert_eq!(timing.phase(Phase::Post), Some(Duration::from_millis(4)));
+        assert_eq!(timing.total(), Duration::from_millis(7));
+        assert_eq!(
+            timing.server_timing(),
+            "parse;dur=2.000, pre;dur=1.000, post;dur=4.000"
+        );
+    }
+}
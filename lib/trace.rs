@@ -1,4 +1,8 @@
 //! Basic built-in logging system
+//!
+//! By default afire's internal logs are printed to stdout with the [`DefaultFormatter`].
+//! To send them to your application's existing logging pipeline, set a backend with [`set_backend`].
+//! Adapters for the [`log`](https://docs.rs/log) and [`tracing`](https://docs.rs/tracing) crates are available with the `log-backend` and `tracing-backend` features.
 
 use std::{
     fmt::{self, Arguments, Display},
@@ -12,10 +16,10 @@ use std::{
 static LEVEL: AtomicU8 = AtomicU8::new(1);
 /// Whether or not to colorize the log output.
 static COLOR: AtomicBool = AtomicBool::new(true);
-/// The global log formatter.
+/// The global log backend.
 /// Will use [`DefaultFormatter`] if none is set.
-static FORMATTER: RwLock<Option<Box<dyn Formatter + Send + Sync + 'static>>> = RwLock::new(None);
-/// Whether or not a formatter has been set.
+static BACKEND: RwLock<Option<Box<dyn TraceSink>>> = RwLock::new(None);
+/// Whether or not a backend has been set.
 /// Used because loading a bool is faster than a RwLock.
 /// This is always loaded before the RwLock to improve performance when using the default formatter.
 static FORMATTER_PRESENT: AtomicBool = AtomicBool::new(false);
@@ -67,10 +71,21 @@ impl Display for Level {
 
 /// Sets the global afire log level.
 /// Setting to [`Level::Off`] will disable all logging.
+/// This can be changed at any time, even while the server is running.
 pub fn set_log_level(level: Level) {
     LEVEL.store(level as u8, Ordering::Relaxed);
 }
 
+/// Gets the current global afire log level.
+pub fn log_level() -> Level {
+    match LEVEL.load(Ordering::Relaxed) {
+        0 => Level::Off,
+        1 => Level::Error,
+        2 => Level::Trace,
+        _ => Level::Debug,
+    }
+}
+
 /// Globally enables or disables colorized log output.
 /// Enabled by default.
 pub fn set_log_color(color: bool) {
@@ -81,8 +96,28 @@ pub fn set_log_color(color: bool) {
 /// This can be used to redirect afire's log output to a file, or to another logging system.
 /// By default, afire will use a simple formatter that prints to stdout.
 pub fn set_log_formatter(formatter: impl Formatter + Send + Sync + 'static) {
+    set_backend(formatter);
+}
+
+/// Sets the global log backend, which all of afire's internal log messages are sent to.
+/// Only messages at or below the global log level (see [`set_log_level`]) are sent to the backend.
+/// ## Example
+/// ```rust
+/// # use std::fmt::Arguments;
+/// # use afire::trace::{self, Level, TraceSink};
+/// struct Stderr;
+///
+/// impl TraceSink for Stderr {
+///     fn trace(&self, level: Level, msg: &Arguments) {
+///         eprintln!("afire {level}: {msg}");
+///     }
+/// }
+///
+/// trace::set_backend(Stderr);
+/// ```
+pub fn set_backend(backend: impl TraceSink + 'static) {
     FORMATTER_PRESENT.store(true, Ordering::Relaxed);
-    *FORMATTER.write().unwrap() = Some(Box::new(formatter));
+    *BACKEND.write().unwrap() = Some(Box::new(backend));
 }
 
 /// Logs a message at the specified log level.
@@ -94,24 +129,25 @@ pub fn _trace(level: Level, fmt: Arguments) {
         return;
     }
 
-    let msg = fmt.to_string();
     if FORMATTER_PRESENT.load(Ordering::Relaxed) {
-        let formatter = FORMATTER.read().unwrap();
-        if let Some(formatter) = &*formatter {
-            formatter.format(level, COLOR.load(Ordering::Relaxed), msg);
+        let backend = BACKEND.read().unwrap();
+        if let Some(backend) = &*backend {
+            backend.trace(level, &fmt);
             return;
         }
     }
 
-    DefaultFormatter.format(level, COLOR.load(Ordering::Relaxed), msg);
+    DefaultFormatter.format(level, COLOR.load(Ordering::Relaxed), fmt.to_string());
 }
 
 // this is a totally normal and necessary function
+// Only used in `trace!` calls, which are compiled out without the `tracing` feature
+#[cfg_attr(not(feature = "tracing"), allow(dead_code))]
 pub(crate) fn emoji(emoji: &str) -> String {
-    #[cfg(feature = "emoji-logging")]
-    return emoji.to_owned() + " ";
-    #[cfg(not(feature = "emoji-logging"))]
-    String::new()
+    match cfg!(feature = "emoji-logging") {
+        true => emoji.to_owned() + " ",
+        false => String::new(),
+    }
 }
 
 /// Simple logging system.
@@ -130,6 +166,79 @@ macro_rules! trace {
     };
 }
 
+/// A destination for afire's internal log messages.
+/// Set with [`set_backend`].
+///
+/// Any [`Formatter`] is also a TraceSink.
+pub trait TraceSink: Send + Sync {
+    /// Processes a log message.
+    ///
+    /// Note: Only log messages with a level equal to or higher than the global log level will be passed to the backend.
+    fn trace(&self, level: Level, msg: &Arguments);
+}
+
+impl<T: Formatter + Send + Sync> TraceSink for T {
+    fn trace(&self, level: Level, msg: &Arguments) {
+        self.format(level, COLOR.load(Ordering::Relaxed), msg.to_string());
+    }
+}
+
+/// A [`TraceSink`] that sends afire's log messages to the [`log`](https://docs.rs/log) crate, with the target `afire`.
+/// [`Level::Trace`] messages are logged at the `Info` level.
+///
+/// Enabled with the `log-backend` feature.
+/// ## Example
+/// ```rust
+/// use afire::trace::{self, Level, LogBackend};
+///
+/// trace::set_log_level(Level::Debug);
+/// trace::set_backend(LogBackend);
+/// ```
+#[cfg(feature = "log-backend")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LogBackend;
+
+#[cfg(feature = "log-backend")]
+impl TraceSink for LogBackend {
+    fn trace(&self, level: Level, msg: &Arguments) {
+        let level = match level {
+            Level::Off => return,
+            Level::Error => log::Level::Error,
+            Level::Trace => log::Level::Info,
+            Level::Debug => log::Level::Debug,
+        };
+
+        log::log!(target: "afire", level, "{}", msg);
+    }
+}
+
+/// A [`TraceSink`] that sends afire's log messages to the [`tracing`](https://docs.rs/tracing) crate as events, with the target `afire`.
+/// [`Level::Trace`] messages are logged at the `INFO` level.
+///
+/// Enabled with the `tracing-backend` feature.
+/// ## Example
+/// ```rust
+/// use afire::trace::{self, Level, TracingBackend};
+///
+/// trace::set_log_level(Level::Debug);
+/// trace::set_backend(TracingBackend);
+/// ```
+#[cfg(feature = "tracing-backend")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TracingBackend;
+
+#[cfg(feature = "tracing-backend")]
+impl TraceSink for TracingBackend {
+    fn trace(&self, level: Level, msg: &Arguments) {
+        match level {
+            Level::Off => {}
+            Level::Error => tracing_crate::error!(target: "afire", "{}", msg),
+            Level::Trace => tracing_crate::info!(target: "afire", "{}", msg),
+            Level::Debug => tracing_crate::debug!(target: "afire", "{}", msg),
+        }
+    }
+}
+
 /// A trait for custom log formatters.
 pub trait Formatter {
     /// Processes a log message.